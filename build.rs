@@ -0,0 +1,88 @@
+//! Embeds the W3C resource pack when the `w3c-resources` feature is enabled.
+//!
+//! Every file found beneath `resources/w3c` is listed, under its path relative to that
+//! directory, in a table included by `src/w3c.rs`. The build fails if any of the files named in
+//! `resources/w3c/README.md`, or the W3C license notice, is missing from the pack.
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// The files the pack must hold, by path relative to `resources/w3c`.
+const REQUIRED: &[&str] = &[
+    "LICENSE",
+    "xhtml1/xhtml1-strict.dtd",
+    "xhtml1/xhtml1-transitional.dtd",
+    "xhtml1/xhtml1-frameset.dtd",
+    "xhtml1/xhtml-lat1.ent",
+    "xhtml1/xhtml-symbol.ent",
+    "xhtml1/xhtml-special.ent",
+    "xhtml11/xhtml11.dtd",
+    "xhtml11/xhtml11-model-1.mod",
+    "svg11/svg11.dtd",
+    "svg11/svg11-basic.dtd",
+    "svg11/svg11-tiny.dtd",
+    "xsd/xml.xsd",
+    "xsd/XMLSchema.xsd",
+    "xsd/XMLSchema.dtd",
+    "xsd/datatypes.dtd",
+    "xlink/xlink.xsd",
+];
+
+fn collect(directory: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(&path, files)?;
+        } else if path.extension().is_some_and(|extension| extension != "md") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if env::var_os("CARGO_FEATURE_W3C_RESOURCES").is_none() {
+        return;
+    }
+    let manifest = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").expect("set by cargo"));
+    let root = manifest.join("resources").join("w3c");
+    println!("cargo:rerun-if-changed={}", root.display());
+    let missing: Vec<&str> = REQUIRED
+        .iter()
+        .copied()
+        .filter(|name| !root.join(name).is_file())
+        .collect();
+    if !missing.is_empty() {
+        panic!(
+            "the `w3c-resources` feature needs the W3C files listed in {}, but these are \
+             missing: {}",
+            root.join("README.md").display(),
+            missing.join(", ")
+        );
+    }
+    let mut files = Vec::new();
+    collect(&root, &mut files).expect("cannot list resources/w3c");
+    files.sort();
+    let mut table = String::from("&[\n");
+    for file in &files {
+        println!("cargo:rerun-if-changed={}", file.display());
+        let name = file
+            .strip_prefix(&root)
+            .expect("beneath the root")
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join("/");
+        table.push_str(&format!(
+            "    ({:?}, include_bytes!({:?})),\n",
+            name,
+            file.display().to_string()
+        ));
+    }
+    table.push_str("]\n");
+    let out = PathBuf::from(env::var_os("OUT_DIR").expect("set by cargo"));
+    fs::write(out.join("w3c_resources.rs"), table).expect("cannot write the resource table");
+}
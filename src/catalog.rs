@@ -0,0 +1,1115 @@
+//! An `EntityResolver` driven by OASIS XML Catalogs.
+//!
+//! `CatalogResolver` maps public and system identifiers, and URI references, to other URIs
+//! (normally local files) as directed by a list of catalog entry files, following the resolution
+//! rules of OASIS XML Catalogs 1.1. The `public`, `system`, `rewriteSystem`, `systemSuffix`,
+//! `delegatePublic`, `delegateSystem`, `uri`, `rewriteURI`, `uriSuffix`, `delegateURI` and
+//! `nextCatalog` entries are supported, inside `group` elements or not, along with the `prefer`
+//! attribute and `xml:base`.
+//!
+//! Catalog entry files are XML documents. They are read with `CatalogBuilder`, a content handler
+//! that collects their entries, and loaded only when resolution reaches them. A catalog entry
+//! file that cannot be read or parsed is ignored, as the specification requires.
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Read;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use common;
+use common::EntityResolver;
+use common::Locator;
+use ext::EntityResolver2;
+use helpers::ErrorKind;
+use helpers::ParseErrorImpl;
+use helpers::XMLNS;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::Result;
+use uri;
+use xinclude::ResourceParser;
+
+/// The OASIS XML Catalogs Namespace URI.
+pub const CATALOG_NS: &str = "urn:oasis:names:tc:entity:xmlns:xml:catalog";
+
+/// Whether public identifiers are used when a system identifier is available as well.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Prefer {
+    /// Public entries and `delegatePublic` entries apply even if a system identifier is given.
+    Public,
+    /// Public entries and `delegatePublic` entries apply only if no system identifier is given.
+    System,
+}
+
+/// An entry of a catalog.
+///
+/// Identifiers are kept normalized, and URI references absolute (resolved against the base URI
+/// in force for the entry).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Entry {
+    /// `<public publicId="..." uri="..."/>`. `prefer` is `None` if neither the entry nor any
+    /// enclosing element specified it, in which case the resolver's default applies.
+    Public {
+        public_id: String,
+        uri: String,
+        prefer: Option<Prefer>,
+    },
+    /// `<system systemId="..." uri="..."/>`
+    System { system_id: String, uri: String },
+    /// `<rewriteSystem systemIdStartString="..." rewritePrefix="..."/>`
+    RewriteSystem { start: String, prefix: String },
+    /// `<systemSuffix systemIdSuffix="..." uri="..."/>`
+    SystemSuffix { suffix: String, uri: String },
+    /// `<delegatePublic publicIdStartString="..." catalog="..."/>`
+    DelegatePublic {
+        start: String,
+        catalog: String,
+        prefer: Option<Prefer>,
+    },
+    /// `<delegateSystem systemIdStartString="..." catalog="..."/>`
+    DelegateSystem { start: String, catalog: String },
+    /// `<uri name="..." uri="..."/>`
+    Uri { name: String, uri: String },
+    /// `<rewriteURI uriStartString="..." rewritePrefix="..."/>`
+    RewriteUri { start: String, prefix: String },
+    /// `<uriSuffix uriSuffix="..." uri="..."/>`
+    UriSuffix { suffix: String, uri: String },
+    /// `<delegateURI uriStartString="..." catalog="..."/>`
+    DelegateUri { start: String, catalog: String },
+    /// `<nextCatalog catalog="..."/>`
+    NextCatalog { catalog: String },
+}
+
+/// The entries of a catalog entry file, in document order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Catalog {
+    entries: Vec<Entry>,
+}
+
+impl Catalog {
+    /// Construct a catalog from its entries.
+    pub fn new(entries: Vec<Entry>) -> Self {
+        Catalog { entries }
+    }
+
+    /// Return the entries of the catalog.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+}
+
+/// Normalize a public identifier: collapse white space into single spaces and trim it.
+pub fn normalize_public_id(public_id: &str) -> String {
+    public_id.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Normalize a system identifier or URI: percent-encode the characters that are not allowed
+/// in URIs.
+pub fn normalize_system_id(system_id: &str) -> String {
+    let mut normalized = String::with_capacity(system_id.len());
+    for c in system_id.chars() {
+        if c.is_ascii_graphic() && !"\"<>\\^`{|}".contains(c) {
+            normalized.push(c);
+        } else {
+            let mut buffer = [0; 4];
+            for byte in c.encode_utf8(&mut buffer).bytes() {
+                normalized.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+    normalized
+}
+
+/// Return the public identifier represented by a `urn:publicid:` URN, or `None` if `urn` is
+/// not such a URN.
+pub fn unwrap_urn(urn: &str) -> Option<String> {
+    let prefix = "urn:publicid:";
+    if urn.len() < prefix.len() || !urn[..prefix.len()].eq_ignore_ascii_case(prefix) {
+        return None;
+    }
+    let mut public_id = String::new();
+    let mut rest = &urn[prefix.len()..];
+    while let Some(c) = rest.chars().next() {
+        let (replacement, length) = match c {
+            '+' => (" ", 1),
+            ':' => ("//", 1),
+            ';' => ("::", 1),
+            '%' => match rest.get(1..3).map(str::to_ascii_uppercase).as_deref() {
+                Some("2B") => ("+", 3),
+                Some("3A") => (":", 3),
+                Some("2F") => ("/", 3),
+                Some("3B") => (";", 3),
+                Some("27") => ("'", 3),
+                Some("3F") => ("?", 3),
+                Some("23") => ("#", 3),
+                Some("25") => ("%", 3),
+                _ => ("%", 1),
+            },
+            _ => {
+                public_id.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+        };
+        public_id.push_str(replacement);
+        rest = &rest[length..];
+    }
+    Some(public_id)
+}
+
+/// An open element of a catalog entry file.
+struct Scope {
+    base: String,
+    prefer: Option<Prefer>,
+    /// Whether the element is outside the catalog vocabulary, so its content is ignored.
+    ignored: bool,
+}
+
+/// A content handler that collects the entries of a catalog entry file.
+///
+/// Elements outside the catalog Namespace are ignored along with their content, as are entries
+/// that lack a required attribute.
+pub struct CatalogBuilder<L: Locator> {
+    base_uri: String,
+    scopes: RefCell<Vec<Scope>>,
+    entries: RefCell<Vec<Entry>>,
+    _locator: PhantomData<L>,
+}
+
+impl<L: Locator> CatalogBuilder<L> {
+    /// Construct a builder for the catalog entry file with the given (absolute) URI, against
+    /// which the relative URI references of the file are resolved.
+    pub fn new(base_uri: &str) -> Self {
+        CatalogBuilder {
+            base_uri: base_uri.to_owned(),
+            scopes: RefCell::new(Vec::new()),
+            entries: RefCell::new(Vec::new()),
+            _locator: PhantomData,
+        }
+    }
+
+    /// Return the catalog built from the events received so far, and reset the builder.
+    pub fn take_catalog(&self) -> Catalog {
+        self.scopes.borrow_mut().clear();
+        Catalog::new(std::mem::take(&mut *self.entries.borrow_mut()))
+    }
+}
+
+fn parse_prefer(value: Option<String>) -> Option<Prefer> {
+    match value.as_deref().map(str::trim) {
+        Some("public") => Some(Prefer::Public),
+        Some("system") => Some(Prefer::System),
+        _ => None,
+    }
+}
+
+impl<L: Locator, A: Attributes> ContentHandler<L, A> for CatalogBuilder<L> {
+    fn end_element(&self, _uri: &str, _local_name: &str, _q_name: &str) -> Result<()> {
+        self.scopes.borrow_mut().pop();
+        Ok(())
+    }
+    fn start_element(
+        &self,
+        uri: &str,
+        local_name: &str,
+        _q_name: &str,
+        attributes: A,
+    ) -> Result<()> {
+        let mut scopes = self.scopes.borrow_mut();
+        let (parent_base, parent_prefer, parent_ignored) = match scopes.last() {
+            Some(scope) => (scope.base.clone(), scope.prefer, scope.ignored),
+            None => (self.base_uri.clone(), None, false),
+        };
+        let base = match attributes.get_ns_name_value(XMLNS, "base") {
+            Some(base) => uri::resolve(&parent_base, &base),
+            None => parent_base,
+        };
+        let prefer = parse_prefer(attributes.get_ns_name_value("", "prefer")).or(parent_prefer);
+        let ignored = parent_ignored || uri != CATALOG_NS;
+        let attribute = |name: &str| attributes.get_ns_name_value("", name);
+        let absolute = |name: &str| {
+            attribute(name).map(|reference| uri::resolve(&base, &normalize_system_id(&reference)))
+        };
+        let entry = if ignored {
+            None
+        } else {
+            match local_name {
+                "public" => attribute("publicId").and_then(|public_id| {
+                    Some(Entry::Public {
+                        public_id: normalize_public_id(&public_id),
+                        uri: absolute("uri")?,
+                        prefer,
+                    })
+                }),
+                "system" => attribute("systemId").and_then(|system_id| {
+                    Some(Entry::System {
+                        system_id: normalize_system_id(&system_id),
+                        uri: absolute("uri")?,
+                    })
+                }),
+                "rewriteSystem" => attribute("systemIdStartString").and_then(|start| {
+                    Some(Entry::RewriteSystem {
+                        start: normalize_system_id(&start),
+                        prefix: absolute("rewritePrefix")?,
+                    })
+                }),
+                "systemSuffix" => attribute("systemIdSuffix").and_then(|suffix| {
+                    Some(Entry::SystemSuffix {
+                        suffix: normalize_system_id(&suffix),
+                        uri: absolute("uri")?,
+                    })
+                }),
+                "delegatePublic" => attribute("publicIdStartString").and_then(|start| {
+                    Some(Entry::DelegatePublic {
+                        start: normalize_public_id(&start),
+                        catalog: absolute("catalog")?,
+                        prefer,
+                    })
+                }),
+                "delegateSystem" => attribute("systemIdStartString").and_then(|start| {
+                    Some(Entry::DelegateSystem {
+                        start: normalize_system_id(&start),
+                        catalog: absolute("catalog")?,
+                    })
+                }),
+                "uri" => attribute("name").and_then(|name| {
+                    Some(Entry::Uri {
+                        name: normalize_system_id(&name),
+                        uri: absolute("uri")?,
+                    })
+                }),
+                "rewriteURI" => attribute("uriStartString").and_then(|start| {
+                    Some(Entry::RewriteUri {
+                        start: normalize_system_id(&start),
+                        prefix: absolute("rewritePrefix")?,
+                    })
+                }),
+                "uriSuffix" => attribute("uriSuffix").and_then(|suffix| {
+                    Some(Entry::UriSuffix {
+                        suffix: normalize_system_id(&suffix),
+                        uri: absolute("uri")?,
+                    })
+                }),
+                "delegateURI" => attribute("uriStartString").and_then(|start| {
+                    Some(Entry::DelegateUri {
+                        start: normalize_system_id(&start),
+                        catalog: absolute("catalog")?,
+                    })
+                }),
+                "nextCatalog" => absolute("catalog").map(|catalog| Entry::NextCatalog { catalog }),
+                _ => None,
+            }
+        };
+        if let Some(entry) = entry {
+            self.entries.borrow_mut().push(entry);
+        }
+        scopes.push(Scope {
+            base,
+            prefer,
+            ignored,
+        });
+        Ok(())
+    }
+}
+
+/// Return the value of the candidate with the longest matching start string or suffix, and
+/// the length of the match.
+fn longest<'e, T, F>(
+    candidates: impl Iterator<Item = (&'e str, T)>,
+    matches: F,
+) -> Option<(usize, T)>
+where
+    F: Fn(&str) -> bool,
+{
+    candidates.filter(|&(key, _)| matches(key)).fold(
+        None,
+        |best: Option<(usize, T)>, (key, value)| match best {
+            Some((length, _)) if length >= key.len() => best,
+            _ => Some((key.len(), value)),
+        },
+    )
+}
+
+/// Return the catalogs of the matching delegation entries, longest match first.
+fn delegates<'e>(
+    candidates: impl Iterator<Item = (&'e str, &'e str)>,
+    identifier: &str,
+) -> Vec<String> {
+    let mut matching: Vec<(&str, &str)> = candidates
+        .filter(|&(start, _)| identifier.starts_with(start))
+        .collect();
+    matching.sort_by_key(|&(start, _)| std::cmp::Reverse(start.len()));
+    let mut catalogs: Vec<String> = Vec::new();
+    for (_, catalog) in matching {
+        if !catalogs.iter().any(|c| c == catalog) {
+            catalogs.push(catalog.to_owned());
+        }
+    }
+    catalogs
+}
+
+/// Resolves with the catalogs of delegation entries, tracking the catalogs being searched.
+type Delegation<'d> = dyn Fn(Vec<String>, &mut Vec<String>) -> Option<String> + 'd;
+
+/// What resolution in a single catalog entry file came to.
+enum Outcome {
+    /// The identifier was mapped to this URI.
+    Mapped(String),
+    /// Resolution continues with only these catalogs.
+    Delegate(Vec<String>),
+    /// Resolution continues with these `nextCatalog` entries.
+    Next(Vec<String>),
+}
+
+/// An `EntityResolver` that maps identifiers to local resources through OASIS XML Catalogs.
+///
+/// `resolve_entity` maps the public and system identifiers of an external entity with
+/// `resolve_external_id`, and, if no public identifier is given and the system identifier has
+/// no mapping, maps the system identifier as a URI reference with `resolve_uri`, which covers
+/// XInclude `href`s and schema locations. The resource a mapping leads to is opened if it is a
+/// `file:` URI; any other URI is an error. Identifiers without a mapping are declined.
+///
+/// Catalog entry files are parsed with a `ResourceParser` the first time resolution reaches
+/// them, and kept for the lifetime of the resolver.
+pub struct CatalogResolver<L: Locator, P: ResourceParser<L>> {
+    catalogs: Vec<String>,
+    parser: P,
+    prefer: Cell<Prefer>,
+    loaded: RefCell<HashMap<String, Rc<Catalog>>>,
+    _locator: PhantomData<L>,
+}
+
+impl<L: Locator, P: ResourceParser<L>> CatalogResolver<L, P> {
+    /// Construct a resolver using the catalog entry files with the given absolute URIs, in
+    /// order, which are parsed with `parser`.
+    pub fn new(catalogs: &[&str], parser: P) -> Self {
+        CatalogResolver {
+            catalogs: catalogs.iter().map(|&catalog| catalog.to_owned()).collect(),
+            parser,
+            prefer: Cell::new(Prefer::Public),
+            loaded: RefCell::new(HashMap::new()),
+            _locator: PhantomData,
+        }
+    }
+
+    /// Return the `prefer` setting for entries that don't specify it.
+    pub fn get_prefer(&self) -> Prefer {
+        self.prefer.get()
+    }
+
+    /// Set the `prefer` setting for entries that don't specify it. The default is
+    /// `Prefer::Public`.
+    pub fn set_prefer(&self, prefer: Prefer) {
+        self.prefer.set(prefer);
+    }
+
+    /// Supply the catalog for a catalog entry file URI, instead of loading it when it is
+    /// reached.
+    pub fn preload(&self, uri: &str, catalog: Catalog) {
+        self.loaded
+            .borrow_mut()
+            .insert(uri.to_owned(), Rc::new(catalog));
+    }
+
+    /// Return the URI that an external identifier is mapped to, if any.
+    ///
+    /// A `urn:publicid:` URN given as either identifier is taken as the public identifier it
+    /// represents.
+    pub fn resolve_external_id(
+        &self,
+        public_id: Option<&str>,
+        system_id: Option<&str>,
+    ) -> Option<String> {
+        let mut public_id = public_id.map(|public_id| match unwrap_urn(public_id) {
+            Some(unwrapped) => normalize_public_id(&unwrapped),
+            None => normalize_public_id(public_id),
+        });
+        let mut system_id = system_id.map(normalize_system_id);
+        if let Some(unwrapped) = system_id.as_deref().and_then(unwrap_urn) {
+            if public_id.is_none() {
+                public_id = Some(normalize_public_id(&unwrapped));
+            }
+            system_id = None;
+        }
+        if public_id.is_none() && system_id.is_none() {
+            return None;
+        }
+        self.resolve_in(
+            &self.catalogs,
+            &mut Vec::new(),
+            &|catalog: &Catalog| {
+                self.external_id_outcome(catalog, public_id.as_deref(), system_id.as_deref())
+            },
+            &|catalogs: Vec<String>, visited: &mut Vec<String>| {
+                // Delegation is a new resolution, with only the identifier that matched.
+                let by_system = system_id
+                    .as_deref()
+                    .map(|system_id| (None, Some(system_id.to_owned())));
+                let (public_id, system_id) = by_system.unwrap_or((public_id.clone(), None));
+                self.resolve_in(
+                    &catalogs,
+                    visited,
+                    &|catalog: &Catalog| {
+                        self.external_id_outcome(
+                            catalog,
+                            public_id.as_deref(),
+                            system_id.as_deref(),
+                        )
+                    },
+                    &|_, _| None,
+                )
+            },
+        )
+    }
+
+    /// Return the URI that a URI reference is mapped to, if any.
+    pub fn resolve_uri(&self, reference: &str) -> Option<String> {
+        let reference = match unwrap_urn(reference) {
+            Some(public_id) => return self.resolve_external_id(Some(&public_id), None),
+            None => normalize_system_id(reference),
+        };
+        self.resolve_in(
+            &self.catalogs,
+            &mut Vec::new(),
+            &|catalog: &Catalog| self.uri_outcome(catalog, &reference),
+            &|catalogs: Vec<String>, visited: &mut Vec<String>| {
+                self.resolve_in(
+                    &catalogs,
+                    visited,
+                    &|catalog: &Catalog| self.uri_outcome(catalog, &reference),
+                    &|_, _| None,
+                )
+            },
+        )
+    }
+
+    /// Resolve in each of the catalogs in turn, following `nextCatalog` entries depth first.
+    /// `visited` holds the catalogs being searched, so that circular references are skipped.
+    fn resolve_in(
+        &self,
+        catalogs: &[String],
+        visited: &mut Vec<String>,
+        outcome: &dyn Fn(&Catalog) -> Option<Outcome>,
+        delegate: &Delegation,
+    ) -> Option<String> {
+        for catalog_uri in catalogs {
+            if visited.contains(catalog_uri) {
+                continue;
+            }
+            let catalog = self.load(catalog_uri);
+            visited.push(catalog_uri.clone());
+            let result = match outcome(&catalog) {
+                Some(Outcome::Mapped(uri)) => Some(uri),
+                Some(Outcome::Delegate(catalogs)) => {
+                    let result = delegate(catalogs, visited);
+                    visited.pop();
+                    return result;
+                }
+                Some(Outcome::Next(next)) => self.resolve_in(&next, visited, outcome, delegate),
+                None => None,
+            };
+            visited.pop();
+            if result.is_some() {
+                return result;
+            }
+        }
+        None
+    }
+
+    fn external_id_outcome(
+        &self,
+        catalog: &Catalog,
+        public_id: Option<&str>,
+        system_id: Option<&str>,
+    ) -> Option<Outcome> {
+        let entries = catalog.entries();
+        if let Some(system_id) = system_id {
+            for entry in entries {
+                if let Entry::System {
+                    system_id: ref s,
+                    ref uri,
+                } = *entry
+                {
+                    if s == system_id {
+                        return Some(Outcome::Mapped(uri.clone()));
+                    }
+                }
+            }
+            let rewrite = longest(
+                entries.iter().filter_map(|entry| match *entry {
+                    Entry::RewriteSystem {
+                        ref start,
+                        ref prefix,
+                    } => Some((start.as_str(), prefix)),
+                    _ => None,
+                }),
+                |start| system_id.starts_with(start),
+            );
+            if let Some((start, prefix)) = rewrite {
+                return Some(Outcome::Mapped(format!(
+                    "{}{}",
+                    prefix,
+                    &system_id[start..]
+                )));
+            }
+            let suffix = longest(
+                entries.iter().filter_map(|entry| match *entry {
+                    Entry::SystemSuffix {
+                        ref suffix,
+                        ref uri,
+                    } => Some((suffix.as_str(), uri)),
+                    _ => None,
+                }),
+                |suffix| system_id.ends_with(suffix),
+            );
+            if let Some((_, uri)) = suffix {
+                return Some(Outcome::Mapped(uri.clone()));
+            }
+            let catalogs = delegates(
+                entries.iter().filter_map(|entry| match *entry {
+                    Entry::DelegateSystem {
+                        ref start,
+                        ref catalog,
+                    } => Some((start.as_str(), catalog.as_str())),
+                    _ => None,
+                }),
+                system_id,
+            );
+            if !catalogs.is_empty() {
+                return Some(Outcome::Delegate(catalogs));
+            }
+        }
+        if let Some(public_id) = public_id {
+            let applies = |prefer: Option<Prefer>| {
+                system_id.is_none() || prefer.unwrap_or_else(|| self.prefer.get()) == Prefer::Public
+            };
+            for entry in entries {
+                if let Entry::Public {
+                    public_id: ref p,
+                    ref uri,
+                    prefer,
+                } = *entry
+                {
+                    if p == public_id && applies(prefer) {
+                        return Some(Outcome::Mapped(uri.clone()));
+                    }
+                }
+            }
+            let catalogs = delegates(
+                entries.iter().filter_map(|entry| match *entry {
+                    Entry::DelegatePublic {
+                        ref start,
+                        ref catalog,
+                        prefer,
+                    } if applies(prefer) => Some((start.as_str(), catalog.as_str())),
+                    _ => None,
+                }),
+                public_id,
+            );
+            if !catalogs.is_empty() {
+                return Some(Outcome::Delegate(catalogs));
+            }
+        }
+        next_catalogs(entries)
+    }
+
+    fn uri_outcome(&self, catalog: &Catalog, reference: &str) -> Option<Outcome> {
+        let entries = catalog.entries();
+        for entry in entries {
+            if let Entry::Uri { ref name, ref uri } = *entry {
+                if name == reference {
+                    return Some(Outcome::Mapped(uri.clone()));
+                }
+            }
+        }
+        let rewrite = longest(
+            entries.iter().filter_map(|entry| match *entry {
+                Entry::RewriteUri {
+                    ref start,
+                    ref prefix,
+                } => Some((start.as_str(), prefix)),
+                _ => None,
+            }),
+            |start| reference.starts_with(start),
+        );
+        if let Some((start, prefix)) = rewrite {
+            return Some(Outcome::Mapped(format!(
+                "{}{}",
+                prefix,
+                &reference[start..]
+            )));
+        }
+        let suffix = longest(
+            entries.iter().filter_map(|entry| match *entry {
+                Entry::UriSuffix {
+                    ref suffix,
+                    ref uri,
+                } => Some((suffix.as_str(), uri)),
+                _ => None,
+            }),
+            |suffix| reference.ends_with(suffix),
+        );
+        if let Some((_, uri)) = suffix {
+            return Some(Outcome::Mapped(uri.clone()));
+        }
+        let catalogs = delegates(
+            entries.iter().filter_map(|entry| match *entry {
+                Entry::DelegateUri {
+                    ref start,
+                    ref catalog,
+                } => Some((start.as_str(), catalog.as_str())),
+                _ => None,
+            }),
+            reference,
+        );
+        if !catalogs.is_empty() {
+            return Some(Outcome::Delegate(catalogs));
+        }
+        next_catalogs(entries)
+    }
+
+    /// Return the catalog entry file with the given URI, loading it if needed. A file that
+    /// cannot be loaded is taken as an empty catalog.
+    fn load(&self, catalog_uri: &str) -> Rc<Catalog> {
+        if let Some(catalog) = self.loaded.borrow().get(catalog_uri) {
+            return catalog.clone();
+        }
+        let builder = CatalogBuilder::<L>::new(catalog_uri);
+        let parsed = uri::to_file_path(catalog_uri)
+            .and_then(|path| std::fs::File::open(path).ok())
+            .is_some_and(|mut file| {
+                self.parser
+                    .parse_resource(&mut file, catalog_uri, &builder)
+                    .is_ok()
+            });
+        let catalog = Rc::new(if parsed {
+            builder.take_catalog()
+        } else {
+            Catalog::default()
+        });
+        self.loaded
+            .borrow_mut()
+            .insert(catalog_uri.to_owned(), catalog.clone());
+        catalog
+    }
+}
+
+fn next_catalogs(entries: &[Entry]) -> Option<Outcome> {
+    let next: Vec<String> = entries
+        .iter()
+        .filter_map(|entry| match *entry {
+            Entry::NextCatalog { ref catalog } => Some(catalog.clone()),
+            _ => None,
+        })
+        .collect();
+    if next.is_empty() {
+        None
+    } else {
+        Some(Outcome::Next(next))
+    }
+}
+
+impl<L: Locator, P: ResourceParser<L>> EntityResolver for CatalogResolver<L, P> {
+    fn resolve_entity(
+        &self,
+        public_id: Option<&str>,
+        system_id: &str,
+    ) -> Result<Option<Box<dyn Read>>> {
+        let system = if system_id.is_empty() {
+            None
+        } else {
+            Some(system_id)
+        };
+        let mapped = self
+            .resolve_external_id(public_id, system)
+            .or_else(|| match public_id {
+                None => self.resolve_uri(system_id),
+                Some(_) => None,
+            });
+        let mapped = match mapped {
+            Some(mapped) => mapped,
+            None => return Ok(None),
+        };
+        let path = uri::to_file_path(&mapped).ok_or_else(|| {
+            Box::new(ParseErrorImpl::of_kind(
+                ErrorKind::Resolution,
+                format!(
+                    "{} is mapped to {}, which is not a file: URI",
+                    system_id, mapped
+                ),
+            )) as Box<dyn common::Error>
+        })?;
+        match std::fs::File::open(&path) {
+            Ok(file) => Ok(Some(Box::new(file))),
+            Err(e) => Err(Box::new(ParseErrorImpl::of_kind(
+                ErrorKind::Resolution,
+                format!(
+                    "{} is mapped to {}, which cannot be opened: {}",
+                    system_id, mapped, e
+                ),
+            ))),
+        }
+    }
+}
+
+/// Catalog entries match system identifiers as written in the document, so the base URI and
+/// entity name are not needed, and external subsets are not supplied.
+impl<L: Locator, P: ResourceParser<L>> EntityResolver2 for CatalogResolver<L, P> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event::Event;
+    use helpers::AttributesImpl;
+    use helpers::LocatorImpl;
+    use std::io::Write;
+
+    fn start(local_name: &str, pairs: &[(&str, &str)]) -> Event {
+        let mut attributes = AttributesImpl::new();
+        for &(name, value) in pairs {
+            match name.strip_prefix("xml:") {
+                Some(local) => attributes.add_attribute(XMLNS, local, name, "CDATA", value),
+                None => attributes.add_attribute("", name, name, "CDATA", value),
+            }
+        }
+        Event::start_element(CATALOG_NS, local_name, local_name, &attributes)
+    }
+
+    fn end(local_name: &str) -> Event {
+        Event::end_element(CATALOG_NS, local_name, local_name)
+    }
+
+    /// An entry element without content.
+    fn entry(local_name: &str, pairs: &[(&str, &str)]) -> Vec<Event> {
+        vec![start(local_name, pairs), end(local_name)]
+    }
+
+    /// Build the catalog of a `catalog` element with the given content.
+    fn build(base_uri: &str, content: Vec<Vec<Event>>) -> Catalog {
+        let builder = CatalogBuilder::<LocatorImpl>::new(base_uri);
+        let mut events = vec![start("catalog", &[])];
+        events.extend(content.into_iter().flatten());
+        events.push(end("catalog"));
+        for event in events {
+            event.dispatch(&builder).unwrap();
+        }
+        builder.take_catalog()
+    }
+
+    /// A parser for resolvers whose catalogs are all preloaded.
+    fn no_parser(
+        _input: &mut dyn Read,
+        system_id: &str,
+        _handler: &dyn ContentHandler<LocatorImpl, AttributesImpl>,
+    ) -> Result<()> {
+        panic!("unexpected load of {}", system_id)
+    }
+
+    type Parser =
+        fn(&mut dyn Read, &str, &dyn ContentHandler<LocatorImpl, AttributesImpl>) -> Result<()>;
+
+    fn resolver(catalogs: Vec<(&str, Catalog)>) -> CatalogResolver<LocatorImpl, Parser> {
+        let uris: Vec<&str> = catalogs.iter().map(|c| c.0).collect();
+        let resolver = CatalogResolver::new(&uris[..1], no_parser as Parser);
+        for (uri, catalog) in catalogs {
+            resolver.preload(uri, catalog);
+        }
+        resolver
+    }
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(
+            normalize_public_id("  -//OASIS//DTD  DocBook\tXML//EN "),
+            "-//OASIS//DTD DocBook XML//EN"
+        );
+        assert_eq!(
+            normalize_system_id("a b/\u{e9}{x}.dtd"),
+            "a%20b/%C3%A9%7Bx%7D.dtd"
+        );
+        assert_eq!(
+            unwrap_urn("URN:publicid:-:OASIS:DTD+DocBook+XML+V4.1.2:EN"),
+            Some("-//OASIS//DTD DocBook XML V4.1.2//EN".to_owned())
+        );
+        assert_eq!(
+            unwrap_urn("urn:publicid:a%2Bb;c%3F"),
+            Some("a+b::c?".to_owned())
+        );
+        assert_eq!(unwrap_urn("urn:isbn:1"), None);
+    }
+
+    #[test]
+    fn builder() {
+        let base = "file:///etc/xml/catalog";
+        let mut content = vec![
+            entry("public", &[("publicId", " -//A//EN "), ("uri", "a.dtd")]),
+            vec![
+                start("group", &[("prefer", "system"), ("xml:base", "dtds/")]),
+                Event::start_element("urn:other", "other", "other", &AttributesImpl::new()),
+            ],
+            entry("system", &[("systemId", "ignored"), ("uri", "x")]),
+            vec![Event::end_element("urn:other", "other", "other")],
+            entry(
+                "system",
+                &[("systemId", "http://b/b d.dtd"), ("uri", "b.dtd")],
+            ),
+            entry(
+                "delegatePublic",
+                &[("publicIdStartString", "-//C"), ("catalog", "c.xml")],
+            ),
+            entry("uri", &[("name", "missing-uri")]),
+            vec![end("group")],
+            entry("nextCatalog", &[("catalog", "/next.xml")]),
+        ];
+        let mut foreign = AttributesImpl::new();
+        foreign.add_attribute("", "systemId", "systemId", "CDATA", "x");
+        foreign.add_attribute("", "uri", "uri", "CDATA", "x");
+        content.push(vec![
+            Event::start_element("urn:other", "system", "system", &foreign),
+            Event::end_element("urn:other", "system", "system"),
+        ]);
+        assert_eq!(
+            build(base, content).entries(),
+            &[
+                Entry::Public {
+                    public_id: "-//A//EN".to_owned(),
+                    uri: "file:///etc/xml/a.dtd".to_owned(),
+                    prefer: None,
+                },
+                Entry::System {
+                    system_id: "http://b/b%20d.dtd".to_owned(),
+                    uri: "file:///etc/xml/dtds/b.dtd".to_owned(),
+                },
+                Entry::DelegatePublic {
+                    start: "-//C".to_owned(),
+                    catalog: "file:///etc/xml/dtds/c.xml".to_owned(),
+                    prefer: Some(Prefer::System),
+                },
+                Entry::NextCatalog {
+                    catalog: "file:///next.xml".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn external_identifiers() {
+        let catalog = Catalog::new(vec![
+            Entry::System {
+                system_id: "http://x/a.dtd".to_owned(),
+                uri: "file:///a.dtd".to_owned(),
+            },
+            Entry::RewriteSystem {
+                start: "http://x/".to_owned(),
+                prefix: "file:///x/".to_owned(),
+            },
+            Entry::RewriteSystem {
+                start: "http://x/deep/".to_owned(),
+                prefix: "file:///deep/".to_owned(),
+            },
+            Entry::SystemSuffix {
+                suffix: "/b.dtd".to_owned(),
+                uri: "file:///b.dtd".to_owned(),
+            },
+            Entry::Public {
+                public_id: "-//P//EN".to_owned(),
+                uri: "file:///p.dtd".to_owned(),
+                prefer: None,
+            },
+            Entry::Public {
+                public_id: "-//S//EN".to_owned(),
+                uri: "file:///s.dtd".to_owned(),
+                prefer: Some(Prefer::System),
+            },
+        ]);
+        let resolver = resolver(vec![("file:///catalog", catalog)]);
+        let resolve = |public_id, system_id| resolver.resolve_external_id(public_id, system_id);
+        assert_eq!(
+            resolve(None, Some("http://x/a.dtd")).unwrap(),
+            "file:///a.dtd"
+        );
+        assert_eq!(
+            resolve(None, Some("http://x/deep/c.dtd")).unwrap(),
+            "file:///deep/c.dtd"
+        );
+        assert_eq!(
+            resolve(None, Some("http://y/b.dtd")).unwrap(),
+            "file:///b.dtd"
+        );
+        assert_eq!(
+            resolve(Some("-//P//EN"), Some("http://y/p.dtd")).unwrap(),
+            "file:///p.dtd"
+        );
+        assert_eq!(resolve(Some("-//S//EN"), Some("http://y/s.dtd")), None);
+        assert_eq!(resolve(Some("-//S//EN"), None).unwrap(), "file:///s.dtd");
+        assert_eq!(
+            resolve(None, Some("urn:publicid:-:P:EN")).unwrap(),
+            "file:///p.dtd"
+        );
+        resolver.set_prefer(Prefer::System);
+        assert_eq!(resolver.get_prefer(), Prefer::System);
+        assert_eq!(resolve(Some("-//P//EN"), Some("http://y/p.dtd")), None);
+        assert_eq!(resolve(None, None), None);
+    }
+
+    #[test]
+    fn uris() {
+        let catalog = Catalog::new(vec![
+            Entry::Uri {
+                name: "http://x/s.xsd".to_owned(),
+                uri: "file:///s.xsd".to_owned(),
+            },
+            Entry::RewriteUri {
+                start: "http://x/".to_owned(),
+                prefix: "file:///x/".to_owned(),
+            },
+            Entry::UriSuffix {
+                suffix: ".rng".to_owned(),
+                uri: "file:///all.rng".to_owned(),
+            },
+        ]);
+        let resolver = resolver(vec![("file:///catalog", catalog)]);
+        assert_eq!(
+            resolver.resolve_uri("http://x/s.xsd").unwrap(),
+            "file:///s.xsd"
+        );
+        assert_eq!(
+            resolver.resolve_uri("http://x/t.xsd").unwrap(),
+            "file:///x/t.xsd"
+        );
+        assert_eq!(
+            resolver.resolve_uri("http://y/a.rng").unwrap(),
+            "file:///all.rng"
+        );
+        assert_eq!(resolver.resolve_uri("http://y/a.xsd"), None);
+        assert_eq!(
+            resolver.resolve_external_id(None, Some("http://x/s.xsd")),
+            None
+        );
+    }
+
+    #[test]
+    fn delegation_and_next_catalogs() {
+        let first = Catalog::new(vec![
+            Entry::DelegateSystem {
+                start: "http://d/".to_owned(),
+                catalog: "file:///delegate".to_owned(),
+            },
+            Entry::NextCatalog {
+                catalog: "file:///next".to_owned(),
+            },
+            Entry::NextCatalog {
+                catalog: "file:///catalog".to_owned(),
+            },
+        ]);
+        let delegate = Catalog::new(vec![
+            Entry::System {
+                system_id: "http://d/a.dtd".to_owned(),
+                uri: "file:///delegated.dtd".to_owned(),
+            },
+            Entry::System {
+                system_id: "http://n/a.dtd".to_owned(),
+                uri: "file:///wrong.dtd".to_owned(),
+            },
+        ]);
+        let next = Catalog::new(vec![
+            Entry::System {
+                system_id: "http://n/a.dtd".to_owned(),
+                uri: "file:///next.dtd".to_owned(),
+            },
+            Entry::System {
+                system_id: "http://d/b.dtd".to_owned(),
+                uri: "file:///not-delegated.dtd".to_owned(),
+            },
+        ]);
+        let resolver = resolver(vec![
+            ("file:///catalog", first),
+            ("file:///delegate", delegate),
+            ("file:///next", next),
+        ]);
+        let resolve = |system_id| resolver.resolve_external_id(None, Some(system_id));
+        assert_eq!(resolve("http://d/a.dtd").unwrap(), "file:///delegated.dtd");
+        assert_eq!(resolve("http://d/b.dtd"), None);
+        assert_eq!(resolve("http://n/a.dtd").unwrap(), "file:///next.dtd");
+        assert_eq!(resolve("http://z/a.dtd"), None);
+    }
+
+    #[test]
+    fn resolves_entities_to_files() {
+        let dir = std::env::temp_dir().join(format!("catalog-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::File::create(dir.join("catalog.xml")).unwrap();
+        std::fs::File::create(dir.join("a.dtd"))
+            .unwrap()
+            .write_all(b"<!ELEMENT a EMPTY>")
+            .unwrap();
+        let base = format!("file://{}/", dir.display());
+        let catalog_uri = format!("{}catalog.xml", base);
+        let loads = Cell::new(0);
+        let parser = |_: &mut dyn Read,
+                      system_id: &str,
+                      handler: &dyn ContentHandler<LocatorImpl, AttributesImpl>|
+         -> Result<()> {
+            assert_eq!(system_id, catalog_uri);
+            loads.set(loads.get() + 1);
+            let mut events = vec![start("catalog", &[])];
+            events.extend(entry(
+                "system",
+                &[("systemId", "http://x/a.dtd"), ("uri", "a.dtd")],
+            ));
+            events.extend(entry(
+                "system",
+                &[("systemId", "http://x/b.dtd"), ("uri", "b.dtd")],
+            ));
+            events.extend(entry(
+                "uri",
+                &[("name", "http://x/c"), ("uri", "http://y/c")],
+            ));
+            events.push(end("catalog"));
+            for event in events {
+                event.dispatch(handler)?;
+            }
+            Ok(())
+        };
+        let resolver = CatalogResolver::new(&[&catalog_uri], parser);
+
+        let mut content = String::new();
+        resolver
+            .resolve_entity(None, "http://x/a.dtd")
+            .unwrap()
+            .expect("mapped")
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "<!ELEMENT a EMPTY>");
+        assert!(resolver
+            .resolve_entity(None, "http://x/z.dtd")
+            .unwrap()
+            .is_none());
+        let error = resolver
+            .resolve_entity(None, "http://x/b.dtd")
+            .err()
+            .expect("missing file");
+        assert!(error.to_string().starts_with(&format!(
+            "http://x/b.dtd is mapped to {}b.dtd, which cannot be opened",
+            base
+        )));
+        let error = resolver
+            .resolve_entity(None, "http://x/c")
+            .err()
+            .expect("not a file");
+        assert_eq!(
+            error.to_string(),
+            "http://x/c is mapped to http://y/c, which is not a file: URI"
+        );
+        assert_eq!(loads.get(), 1);
+
+        let missing = CatalogResolver::new(&[&format!("{}missing.xml", base)], parser);
+        assert_eq!(
+            missing.resolve_external_id(None, Some("http://x/a.dtd")),
+            None
+        );
+        assert_eq!(loads.get(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
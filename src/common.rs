@@ -1,13 +1,13 @@
-/// The SAX2 API definitions from which some of these interfaces were derived comes with
-/// the following notice:
-///
-/// > This module, both source code and documentation, is in the Public Domain,
-/// > and comes with NO WARRANTY. See http://www.saxproject.org for further
-/// > information.
-///
-/// See also http://www.saxproject.org/copying.html
+//! The SAX2 API definitions from which some of these interfaces were derived comes with
+//! the following notice:
+//!
+//! > This module, both source code and documentation, is in the Public Domain,
+//! > and comes with NO WARRANTY. See http://www.saxproject.org for further
+//! > information.
+//!
+//! See also http://www.saxproject.org/copying.html
 
-pub type Result<T> = std::result::Result<T, Box<Error>>;
+pub type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
 pub trait Error: std::error::Error {}
 
@@ -159,23 +159,26 @@ pub trait Locator {
 /// The following resolver would provide the application with a special character stream for the
 /// entity with the system identifier "http://www.myhost.com/today":
 ///
-/// use EntityResolver;
-/// use InputSource;
-///
 /// ```
-/// impl EntityResolve for MyResolve {
-///   pub fn resolve_entity(&self, public_id: Option<&str>, system_id: &str) ->
-///   Result<Option<Box<std::io::Read>>>
-///   {
-///     if systemd_id == "http://www.myhost.com/today" {
-///       // return a special input source
-///       let reader = MyReader::new();
-///       return Ok(Some(Box::new(reader)));
-///     } else {
-///       // use the default behaviour
-///       return None;
+/// use xml_parser_traits::common::{EntityResolver, Result};
+///
+/// struct MyResolver;
+///
+/// impl EntityResolver for MyResolver {
+///     fn resolve_entity(
+///         &self,
+///         _public_id: Option<&str>,
+///         system_id: &str,
+///     ) -> Result<Option<Box<dyn std::io::Read>>> {
+///         if system_id == "http://www.myhost.com/today" {
+///             // return a special input source
+///             let reader = std::io::Cursor::new(b"<today/>".to_vec());
+///             Ok(Some(Box::new(reader)))
+///         } else {
+///             // use the default behaviour
+///             Ok(None)
+///         }
 ///     }
-///   }
 /// }
 /// ```
 ///
@@ -195,7 +198,7 @@ pub trait EntityResolver {
         &self,
         public_id: Option<&str>,
         system_id: &str,
-    ) -> Result<Option<Box<std::io::Read>>> {
+    ) -> Result<Option<Box<dyn std::io::Read>>> {
         Ok(None)
     }
 }
@@ -0,0 +1,923 @@
+//! CSS selectors, matched against SAX streams.
+//!
+//! A `Selector` is parsed from a CSS selector list and translated into the streamable subset of
+//! XPath accepted by `xpath::streaming`. A `SelectorMatcher` then recognizes the matching
+//! elements from the stack of open elements as `start_element` events arrive, and routes their
+//! subtrees to callbacks or to other content handlers.
+//!
+//! Supported are type and universal selectors, with an optional Namespace prefix (`svg|rect`,
+//! `*|a`, `|a`), `#id`, `.class`, attribute selectors with the `=`, `~=`, `|=`, `^=`, `$=` and
+//! `*=` operators, the descendant and child combinators, and the `:root`, `:first-child`,
+//! `:nth-child()` and `:not()` pseudo-classes. Sibling combinators, and pseudo-classes that
+//! depend on following siblings or on content, such as `:last-child` or `:empty`, cannot be
+//! evaluated on a stream and are rejected.
+//!
+//! As in CSS, an unprefixed type selector matches elements in the default Namespace if one is
+//! declared in the `NamespaceSupport` given to `Selector::parse`, and in any Namespace
+//! otherwise, while unprefixed attribute names match attributes without a Namespace. Names are
+//! case-sensitive, as in XML.
+use std::rc::Rc;
+
+use common::Locator;
+use helpers::AttributesImpl;
+use helpers::ErrorKind;
+use helpers::LocatorImpl;
+use helpers::NamespaceSupport;
+use helpers::ParseErrorImpl;
+use names;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::Result;
+use xpath::streaming::Match;
+use xpath::streaming::MatchedNode;
+use xpath::streaming::StreamMatcher;
+use xpath::streaming::Subtree;
+use xpath::XPath;
+
+type SResult<T> = std::result::Result<T, ParseErrorImpl>;
+
+/// A parsed CSS selector list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Selector {
+    source: String,
+    xpath: String,
+}
+
+impl Selector {
+    /// Parse a selector list, resolving the Namespace prefixes it uses against `namespaces`.
+    pub fn parse(source: &str, namespaces: &NamespaceSupport) -> SResult<Selector> {
+        let mut parser = Parser {
+            chars: source.chars().collect(),
+            position: 0,
+            namespaces,
+        };
+        let mut alternatives = Vec::new();
+        loop {
+            parser.skip_whitespace();
+            alternatives.push(parser.complex()?);
+            match parser.peek() {
+                Some(',') => parser.position += 1,
+                None => break,
+                Some(c) => return Err(parser.error(&format!("unexpected '{}'", c))),
+            }
+        }
+        let xpath = alternatives.join(" | ");
+        XPath::compile_pattern(&xpath, &NamespaceSupport::new()).map_err(|e| {
+            ParseErrorImpl::of_kind(
+                ErrorKind::Expression,
+                format!("cannot translate selector \"{}\": {}", source, e),
+            )
+        })?;
+        Ok(Selector {
+            source: source.to_owned(),
+            xpath,
+        })
+    }
+
+    /// Return the source text of the selector.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Return the XPath match pattern equivalent to the selector.
+    pub fn to_xpath(&self) -> &str {
+        &self.xpath
+    }
+}
+
+/// Return an XPath literal for a string.
+fn literal(s: &str) -> String {
+    if !s.contains('\'') {
+        format!("'{}'", s)
+    } else if !s.contains('"') {
+        format!("\"{}\"", s)
+    } else {
+        let parts: Vec<String> = s.split('\'').map(|part| format!("'{}'", part)).collect();
+        format!("concat({})", parts.join(", \"'\", "))
+    }
+}
+
+/// The Namespace part of a qualified name in a selector.
+enum NamespaceSpec {
+    /// No prefix was given.
+    Default,
+    /// `*|name`
+    Any,
+    /// `|name`
+    Empty,
+    /// `prefix|name`
+    Uri(String),
+}
+
+/// Return an XPath expression selecting the attributes of the context element with the given
+/// name.
+fn attribute_path(namespace: &NamespaceSpec, local_name: &str) -> String {
+    let local_test = format!("local-name() = {}", literal(local_name));
+    match namespace {
+        NamespaceSpec::Default | NamespaceSpec::Empty if names::is_ncname(local_name) => {
+            format!("@{}", local_name)
+        }
+        NamespaceSpec::Default | NamespaceSpec::Empty => {
+            format!("@*[{} and namespace-uri() = '']", local_test)
+        }
+        NamespaceSpec::Any => format!("@*[{}]", local_test),
+        NamespaceSpec::Uri(uri) => {
+            format!("@*[{} and namespace-uri() = {}]", local_test, literal(uri))
+        }
+    }
+}
+
+/// Return an XPath condition that holds if a whitespace-separated list contains a word.
+fn contains_word(list: &str, word: &str) -> String {
+    if word.is_empty() || word.contains(names::is_whitespace) {
+        "false()".to_owned()
+    } else {
+        format!(
+            "contains(concat(' ', normalize-space({}), ' '), {})",
+            list,
+            literal(&format!(" {} ", word))
+        )
+    }
+}
+
+/// Return an XPath condition on `position()` equivalent to `:nth-child(an+b)`.
+fn nth(a: i64, b: i64) -> String {
+    if a == 0 {
+        format!("position() = {}", b)
+    } else {
+        format!(
+            "(position() - ({b})) mod {a} = 0 and (position() - ({b})) div {a} >= 0",
+            a = a,
+            b = b
+        )
+    }
+}
+
+fn parse_nth(text: &str) -> Option<(i64, i64)> {
+    let text: String = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+    match text.as_str() {
+        "odd" => return Some((2, 1)),
+        "even" => return Some((2, 0)),
+        _ => {}
+    }
+    let integer = |s: &str| -> Option<i64> {
+        let digits = s.trim_start_matches(['+', '-']);
+        if digits.is_empty()
+            || !digits.chars().all(|c| c.is_ascii_digit())
+            || s.len() - digits.len() > 1
+        {
+            return None;
+        }
+        s.parse().ok()
+    };
+    match text.find('n') {
+        Some(index) => {
+            let a = match &text[..index] {
+                "" | "+" => 1,
+                "-" => -1,
+                a => integer(a)?,
+            };
+            let b = match &text[index + 1..] {
+                "" => 0,
+                b if b.starts_with(['+', '-']) => integer(b)?,
+                _ => return None,
+            };
+            Some((a, b))
+        }
+        None => Some((0, integer(&text)?)),
+    }
+}
+
+struct Parser<'a> {
+    chars: Vec<char>,
+    position: usize,
+    namespaces: &'a NamespaceSupport,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).cloned()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).cloned()
+    }
+
+    fn error(&self, message: &str) -> ParseErrorImpl {
+        let source: String = self.chars.iter().collect();
+        ParseErrorImpl::of_kind(
+            ErrorKind::Expression,
+            format!(
+                "{} at offset {} in selector \"{}\"",
+                message, self.position, source
+            ),
+        )
+    }
+
+    fn expect(&mut self, c: char) -> SResult<()> {
+        if self.peek() == Some(c) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c)))
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.position += 1;
+        }
+        self.position > start
+    }
+
+    fn is_name_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '_' || c == '-' || !c.is_ascii()
+    }
+
+    fn at_ident(&self) -> bool {
+        let is_start = |c: Option<char>| {
+            c.is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || !c.is_ascii() || c == '\\')
+        };
+        match self.peek() {
+            Some('-') => is_start(self.peek_at(1)) || self.peek_at(1) == Some('-'),
+            c => is_start(c),
+        }
+    }
+
+    fn escape(&mut self) -> SResult<char> {
+        self.expect('\\')?;
+        let start = self.position;
+        while self.position - start < 6 && self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+            self.position += 1;
+        }
+        if self.position > start {
+            let hex: String = self.chars[start..self.position].iter().collect();
+            if self.peek().is_some_and(|c| c.is_whitespace()) {
+                self.position += 1;
+            }
+            let code = u32::from_str_radix(&hex, 16).unwrap_or(0);
+            return Ok(std::char::from_u32(code)
+                .filter(|c| *c != '\0')
+                .unwrap_or('\u{FFFD}'));
+        }
+        match self.peek() {
+            Some(c) if c != '\n' => {
+                self.position += 1;
+                Ok(c)
+            }
+            _ => Err(self.error("invalid escape")),
+        }
+    }
+
+    /// Read a name, as used by identifiers and by `#id`.
+    fn name(&mut self) -> SResult<String> {
+        let mut name = String::new();
+        loop {
+            match self.peek() {
+                Some('\\') => name.push(self.escape()?),
+                Some(c) if Parser::is_name_char(c) => {
+                    name.push(c);
+                    self.position += 1;
+                }
+                _ => break,
+            }
+        }
+        if name.is_empty() {
+            return Err(self.error("expected a name"));
+        }
+        Ok(name)
+    }
+
+    fn ident(&mut self) -> SResult<String> {
+        if !self.at_ident() {
+            return Err(self.error("expected an identifier"));
+        }
+        self.name()
+    }
+
+    fn string(&mut self) -> SResult<String> {
+        let quote = self.peek().expect("called at a quote");
+        self.position += 1;
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                None | Some('\n') => return Err(self.error("unterminated string")),
+                Some(c) if c == quote => {
+                    self.position += 1;
+                    return Ok(value);
+                }
+                Some('\\') if self.peek_at(1) == Some('\n') => self.position += 2,
+                Some('\\') => value.push(self.escape()?),
+                Some(c) => {
+                    value.push(c);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    /// Read an optionally prefixed name; `None` stands for `*` as the local name. Returns
+    /// `Ok(None)` if there is no name at the current position.
+    fn qualified_name(&mut self) -> SResult<Option<(NamespaceSpec, Option<String>)>> {
+        // A '|' followed by '=' is the |= attribute operator, not a Namespace separator.
+        let bar_follows = |parser: &Parser, offset: usize| {
+            parser.peek_at(offset) == Some('|') && parser.peek_at(offset + 1) != Some('=')
+        };
+        let namespace = if bar_follows(self, 0) {
+            self.position += 1;
+            NamespaceSpec::Empty
+        } else if self.peek() == Some('*') {
+            self.position += 1;
+            if !bar_follows(self, 0) {
+                return Ok(Some((NamespaceSpec::Default, None)));
+            }
+            self.position += 1;
+            NamespaceSpec::Any
+        } else if self.at_ident() {
+            let name = self.ident()?;
+            if !bar_follows(self, 0) {
+                return Ok(Some((NamespaceSpec::Default, Some(name))));
+            }
+            self.position += 1;
+            match self.namespaces.get_uri(&name) {
+                Some(uri) => NamespaceSpec::Uri(uri.to_owned()),
+                None => return Err(self.error(&format!("undeclared prefix \"{}\"", name))),
+            }
+        } else {
+            return Ok(None);
+        };
+        if self.peek() == Some('*') {
+            self.position += 1;
+            Ok(Some((namespace, None)))
+        } else {
+            Ok(Some((namespace, Some(self.ident()?))))
+        }
+    }
+
+    /// Parse a complex selector into a relative or absolute location path.
+    fn complex(&mut self) -> SResult<String> {
+        let (first, root) = self.compound(true)?;
+        let mut path = if root { format!("/{}", first) } else { first };
+        loop {
+            let whitespace = self.skip_whitespace();
+            let separator = match self.peek() {
+                None | Some(',') => break,
+                Some('>') => {
+                    self.position += 1;
+                    self.skip_whitespace();
+                    "/"
+                }
+                Some('+') | Some('~') => {
+                    return Err(self.error("sibling combinators cannot be matched on a stream"))
+                }
+                Some(_) if whitespace => "//",
+                Some(c) => return Err(self.error(&format!("unexpected '{}'", c))),
+            };
+            let (step, _) = self.compound(false)?;
+            path.push_str(separator);
+            path.push_str(&step);
+        }
+        Ok(path)
+    }
+
+    /// Parse a compound selector into a location step, and whether it uses `:root`.
+    fn compound(&mut self, allow_root: bool) -> SResult<(String, bool)> {
+        let (conditions, root) = self.compound_conditions(allow_root)?;
+        let step = if conditions.is_empty() {
+            "*".to_owned()
+        } else {
+            format!("*[{}]", conditions.join(" and "))
+        };
+        Ok((step, root))
+    }
+
+    fn compound_conditions(&mut self, allow_root: bool) -> SResult<(Vec<String>, bool)> {
+        let mut conditions = Vec::new();
+        let mut root = false;
+        let start = self.position;
+        if let Some((namespace, local_name)) = self.qualified_name()? {
+            let namespace = match namespace {
+                NamespaceSpec::Default => match self.namespaces.get_uri("") {
+                    Some(uri) if !uri.is_empty() => NamespaceSpec::Uri(uri.to_owned()),
+                    _ => NamespaceSpec::Any,
+                },
+                namespace => namespace,
+            };
+            if let Some(local_name) = local_name {
+                conditions.push(format!("local-name() = {}", literal(&local_name)));
+            }
+            match namespace {
+                NamespaceSpec::Uri(uri) => {
+                    conditions.push(format!("namespace-uri() = {}", literal(&uri)))
+                }
+                NamespaceSpec::Empty => conditions.push("namespace-uri() = ''".to_owned()),
+                _ => {}
+            }
+        }
+        loop {
+            match self.peek() {
+                Some('#') => {
+                    self.position += 1;
+                    let id = literal(&self.name()?);
+                    conditions.push(format!("(@id = {} or @xml:id = {})", id, id));
+                }
+                Some('.') => {
+                    self.position += 1;
+                    let class = self.ident()?;
+                    conditions.push(contains_word("@class", &class));
+                }
+                Some('[') => {
+                    self.position += 1;
+                    conditions.push(self.attribute_selector()?);
+                }
+                Some(':') if self.peek_at(1) == Some(':') => {
+                    return Err(self.error("pseudo-elements cannot be matched"))
+                }
+                Some(':') => {
+                    self.position += 1;
+                    match self.pseudo_class()? {
+                        Some(condition) => conditions.push(condition),
+                        None if allow_root => root = true,
+                        None => return Err(self.error(":root must start the selector")),
+                    }
+                }
+                _ => break,
+            }
+        }
+        if self.position == start {
+            return Err(self.error("expected a selector"));
+        }
+        Ok((conditions, root))
+    }
+
+    fn attribute_selector(&mut self) -> SResult<String> {
+        self.skip_whitespace();
+        let (namespace, local_name) = match self.qualified_name()? {
+            Some((namespace, Some(local_name))) => (namespace, local_name),
+            _ => return Err(self.error("expected an attribute name")),
+        };
+        let attribute = attribute_path(&namespace, &local_name);
+        self.skip_whitespace();
+        let operator = match (self.peek(), self.peek_at(1)) {
+            (Some(']'), _) => {
+                self.position += 1;
+                return Ok(attribute);
+            }
+            (Some('='), _) => {
+                self.position += 1;
+                '='
+            }
+            (Some(c), Some('=')) if "~|^$*".contains(c) => {
+                self.position += 2;
+                c
+            }
+            _ => return Err(self.error("expected an attribute operator or ']'")),
+        };
+        self.skip_whitespace();
+        let value = match self.peek() {
+            Some('"') | Some('\'') => self.string()?,
+            _ => self.ident()?,
+        };
+        self.skip_whitespace();
+        if self.at_ident() {
+            let flag = self.ident()?;
+            if flag != "s" {
+                return Err(self.error(&format!("unsupported attribute flag \"{}\"", flag)));
+            }
+            self.skip_whitespace();
+        }
+        self.expect(']')?;
+        let value_literal = literal(&value);
+        Ok(match operator {
+            '=' => format!("{} = {}", attribute, value_literal),
+            '~' => contains_word(&attribute, &value),
+            '|' => format!(
+                "({a} = {v} or starts-with({a}, {p}))",
+                a = attribute,
+                v = value_literal,
+                p = literal(&format!("{}-", value))
+            ),
+            _ if value.is_empty() => "false()".to_owned(),
+            '^' => format!("starts-with({}, {})", attribute, value_literal),
+            '$' => format!(
+                "substring({a}, string-length({a}) - {n} + 1) = {v}",
+                a = attribute,
+                n = value.chars().count(),
+                v = value_literal
+            ),
+            _ => format!("contains({}, {})", attribute, value_literal),
+        })
+    }
+
+    /// Parse a pseudo-class into a condition, or `None` for `:root`.
+    fn pseudo_class(&mut self) -> SResult<Option<String>> {
+        let name = self.ident()?;
+        let has_arguments = self.peek() == Some('(');
+        if has_arguments {
+            self.position += 1;
+            self.skip_whitespace();
+        }
+        let condition = match (name.as_str(), has_arguments) {
+            ("root", false) => return Ok(None),
+            ("first-child", false) => "position() = 1".to_owned(),
+            ("nth-child", true) => {
+                let start = self.position;
+                while self.peek().is_some_and(|c| c != ')') {
+                    self.position += 1;
+                }
+                let argument: String = self.chars[start..self.position].iter().collect();
+                let (a, b) = parse_nth(&argument).ok_or_else(|| {
+                    self.error(&format!("invalid :nth-child argument \"{}\"", argument))
+                })?;
+                nth(a, b)
+            }
+            ("not", true) => {
+                let mut alternatives = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    let (conditions, _) = self.compound_conditions(false)?;
+                    alternatives.push(if conditions.is_empty() {
+                        "true()".to_owned()
+                    } else {
+                        format!("({})", conditions.join(" and "))
+                    });
+                    self.skip_whitespace();
+                    if self.peek() != Some(',') {
+                        break;
+                    }
+                    self.position += 1;
+                }
+                format!("not({})", alternatives.join(" or "))
+            }
+            (
+                "last-child" | "only-child" | "nth-last-child" | "empty" | "first-of-type"
+                | "last-of-type" | "only-of-type" | "nth-of-type" | "nth-last-of-type" | "has",
+                _,
+            ) => {
+                return Err(self.error(&format!(":{} cannot be matched on a stream", name)));
+            }
+            _ => return Err(self.error(&format!("unsupported pseudo-class :{}", name))),
+        };
+        if has_arguments {
+            self.skip_whitespace();
+            self.expect(')')?;
+        }
+        Ok(Some(condition))
+    }
+}
+
+/// An element matched by a `SelectorMatcher`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelectorMatch<'m> {
+    /// The selector that matched, as written.
+    pub selector: &'m str,
+    /// The path of the matched element, such as `/html[1]/body[1]/div[3]`.
+    pub path: &'m str,
+    pub subtree: &'m Subtree,
+    /// The position of the start tag of the matched element.
+    pub location: &'m LocatorImpl,
+}
+
+/// A `ContentHandler` that matches CSS selectors against the elements it receives.
+///
+/// Register selectors with `on_match` or `forward` before the parse, then register the matcher
+/// as the content handler of a Namespace-aware reader. Matching follows the rules of
+/// `xpath::streaming::StreamMatcher`, which does the work: callbacks receive each matched
+/// element with its subtree once its end tag has been seen, and forwarded content handlers
+/// receive the events of the matched subtrees as they arrive.
+pub struct SelectorMatcher<'a, L: Locator> {
+    matcher: StreamMatcher<'a, L>,
+}
+
+impl<'a, L: Locator> Default for SelectorMatcher<'a, L> {
+    fn default() -> Self {
+        SelectorMatcher::new()
+    }
+}
+
+impl<'a, L: Locator> SelectorMatcher<'a, L> {
+    /// Construct a matcher without any selectors.
+    pub fn new() -> Self {
+        SelectorMatcher {
+            matcher: StreamMatcher::new(),
+        }
+    }
+
+    /// Invoke `callback` for each element matched by `selector`.
+    pub fn on_match<F>(&mut self, selector: &Selector, callback: F) -> SResult<()>
+    where
+        F: Fn(&SelectorMatch) -> Result<()> + 'a,
+    {
+        let source = selector.source.clone();
+        self.matcher.on_match(
+            &selector.xpath,
+            &NamespaceSupport::new(),
+            move |found: &Match| match found.node {
+                MatchedNode::Element(ref subtree) => callback(&SelectorMatch {
+                    selector: &source,
+                    path: &found.path,
+                    subtree,
+                    location: &found.location,
+                }),
+                MatchedNode::Attribute(_) => Ok(()),
+            },
+        )
+    }
+
+    /// Forward the subtrees of the elements matched by `selector` to `handler`.
+    pub fn forward<H>(&mut self, selector: &Selector, handler: H) -> SResult<()>
+    where
+        H: ContentHandler<L, AttributesImpl> + 'a,
+    {
+        self.matcher
+            .forward(&selector.xpath, &NamespaceSupport::new(), handler)
+    }
+}
+
+impl<'a, L: Locator, A: Attributes> ContentHandler<L, A> for SelectorMatcher<'a, L> {
+    fn characters(&self, content: &str) -> Result<()> {
+        ContentHandler::<L, A>::characters(&self.matcher, content)
+    }
+    fn end_document(&self) -> Result<()> {
+        ContentHandler::<L, A>::end_document(&self.matcher)
+    }
+    fn end_element(&self, uri: &str, local_name: &str, q_name: &str) -> Result<()> {
+        ContentHandler::<L, A>::end_element(&self.matcher, uri, local_name, q_name)
+    }
+    fn end_prefix_mapping(&self, prefix: &str) -> Result<()> {
+        ContentHandler::<L, A>::end_prefix_mapping(&self.matcher, prefix)
+    }
+    fn ignorable_whitespace(&self, content: &str) -> Result<()> {
+        ContentHandler::<L, A>::ignorable_whitespace(&self.matcher, content)
+    }
+    fn processing_instruction(&self, target: &str, data: &str) -> Result<()> {
+        ContentHandler::<L, A>::processing_instruction(&self.matcher, target, data)
+    }
+    fn set_document_locator(&self, locator: Rc<L>) {
+        ContentHandler::<L, A>::set_document_locator(&self.matcher, locator)
+    }
+    fn skipped_entity(&self, name: &str) -> Result<()> {
+        ContentHandler::<L, A>::skipped_entity(&self.matcher, name)
+    }
+    fn start_document(&self) -> Result<()> {
+        ContentHandler::<L, A>::start_document(&self.matcher)
+    }
+    fn start_element(
+        &self,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attributes: A,
+    ) -> Result<()> {
+        self.matcher
+            .start_element(uri, local_name, q_name, attributes)
+    }
+    fn start_prefix_mapping(&self, prefix: &str, uri: &str) -> Result<()> {
+        ContentHandler::<L, A>::start_prefix_mapping(&self.matcher, prefix, uri)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event::Event;
+    use helpers::XMLNS;
+    use std::cell::RefCell;
+
+    const HTML: &str = "urn:h";
+    const SVG: &str = "urn:svg";
+
+    fn start(uri: &str, q_name: &str, pairs: &[(&str, &str)]) -> Event {
+        let mut attributes = AttributesImpl::new();
+        for &(name, value) in pairs {
+            match name.strip_prefix("xml:") {
+                Some(local_name) => {
+                    attributes.add_attribute(XMLNS, local_name, name, "CDATA", value)
+                }
+                None => attributes.add_attribute("", name, name, "CDATA", value),
+            }
+        }
+        let local_name = q_name.rsplit(':').next().unwrap_or(q_name);
+        Event::start_element(uri, local_name, q_name, &attributes)
+    }
+
+    fn end(uri: &str, q_name: &str) -> Event {
+        let local_name = q_name.rsplit(':').next().unwrap_or(q_name);
+        Event::end_element(uri, local_name, q_name)
+    }
+
+    /// `<html xmlns="urn:h" xmlns:svg="urn:svg"><body class="main  page"><div id="a"
+    /// lang="en-US" title="it's"><p/><p class="x"/><p/></div><div xml:id="b"><svg:rect/><p/>
+    /// </div></body></html>`
+    fn document() -> Vec<Event> {
+        let mut events = vec![
+            Event::StartDocument,
+            start(HTML, "html", &[]),
+            start(HTML, "body", &[("class", "main  page")]),
+            start(
+                HTML,
+                "div",
+                &[("id", "a"), ("lang", "en-US"), ("title", "it's")],
+            ),
+        ];
+        for class in &["", "x", ""] {
+            let pairs: &[(&str, &str)] = if class.is_empty() {
+                &[]
+            } else {
+                &[("class", "x")]
+            };
+            events.push(start(HTML, "p", pairs));
+            events.push(end(HTML, "p"));
+        }
+        events.extend(vec![
+            end(HTML, "div"),
+            start(HTML, "div", &[("xml:id", "b")]),
+            start(SVG, "svg:rect", &[("width", "10")]),
+            end(SVG, "svg:rect"),
+            start(HTML, "p", &[]),
+            end(HTML, "p"),
+            end(HTML, "div"),
+            end(HTML, "body"),
+            end(HTML, "html"),
+            Event::EndDocument,
+        ]);
+        events
+    }
+
+    fn namespaces(default: Option<&str>) -> NamespaceSupport {
+        let mut namespaces = NamespaceSupport::new();
+        namespaces.declare_prefix("svg", SVG);
+        if let Some(uri) = default {
+            namespaces.declare_prefix("", uri);
+        }
+        namespaces
+    }
+
+    /// Return the paths of the elements matched by a selector in the test document.
+    fn select_in(source: &str, default: Option<&str>) -> Vec<String> {
+        let selector = Selector::parse(source, &namespaces(default)).unwrap();
+        let found = RefCell::new(Vec::new());
+        {
+            let mut matcher = SelectorMatcher::<LocatorImpl>::new();
+            matcher
+                .on_match(&selector, |m| {
+                    assert_eq!(m.selector, source);
+                    found.borrow_mut().push(m.path.to_owned());
+                    Ok(())
+                })
+                .unwrap();
+            for event in document() {
+                event.dispatch(&matcher).unwrap();
+            }
+        }
+        found.into_inner()
+    }
+
+    fn select(source: &str) -> Vec<String> {
+        select_in(source, None)
+    }
+
+    const DIV_A: &str = "/html[1]/body[1]/div[1]";
+    const DIV_B: &str = "/html[1]/body[1]/div[2]";
+
+    fn under(parent: &str, children: &[&str]) -> Vec<String> {
+        children
+            .iter()
+            .map(|child| format!("{}/{}", parent, child))
+            .collect()
+    }
+
+    #[test]
+    fn type_and_namespace_selectors() {
+        let all_p = [
+            under(DIV_A, &["p[1]", "p[2]", "p[3]"]),
+            under(DIV_B, &["p[1]"]),
+        ]
+        .concat();
+        assert_eq!(select("p"), all_p);
+        assert_eq!(select("*|p"), all_p);
+        assert_eq!(select_in("p", Some(HTML)), all_p);
+        assert!(select_in("p", Some(SVG)).is_empty());
+        assert!(select("|p").is_empty());
+        assert_eq!(select("svg|*"), under(DIV_B, &["svg:rect[1]"]));
+        assert_eq!(select(":root"), ["/html[1]"]);
+        assert!(select(":root > p").is_empty());
+    }
+
+    #[test]
+    fn ids_classes_and_attributes() {
+        assert_eq!(select("#a, #b"), [DIV_A, DIV_B]);
+        assert_eq!(select(".x"), under(DIV_A, &["p[2]"]));
+        assert_eq!(select("body.main.page > div"), [DIV_A, DIV_B]);
+        assert_eq!(select("[lang|=en]"), [DIV_A]);
+        assert_eq!(select("[title^=\"it'\"][title$='s']"), [DIV_A]);
+        assert_eq!(select("[class~=page]"), ["/html[1]/body[1]"]);
+        assert_eq!(select("[class*=ain]"), ["/html[1]/body[1]"]);
+        assert_eq!(
+            select("svg|rect[width='10']"),
+            under(DIV_B, &["svg:rect[1]"])
+        );
+        assert!(select("[class^='']").is_empty());
+    }
+
+    #[test]
+    fn pseudo_classes() {
+        assert_eq!(
+            select("div > :first-child"),
+            [under(DIV_A, &["p[1]"]), under(DIV_B, &["svg:rect[1]"]),].concat()
+        );
+        assert_eq!(select("p:first-child"), under(DIV_A, &["p[1]"]));
+        assert_eq!(select("p:nth-child(odd)"), under(DIV_A, &["p[1]", "p[3]"]));
+        assert_eq!(
+            select("p:nth-child(-n + 2)"),
+            [under(DIV_A, &["p[1]", "p[2]"]), under(DIV_B, &["p[1]"])].concat()
+        );
+        assert_eq!(
+            select("#a p:not(.x, [title])"),
+            under(DIV_A, &["p[1]", "p[3]"])
+        );
+    }
+
+    #[test]
+    fn selector_translation() {
+        let selector = Selector::parse("div > p.x", &NamespaceSupport::new()).unwrap();
+        assert_eq!(selector.source(), "div > p.x");
+        assert_eq!(
+            selector.to_xpath(),
+            "*[local-name() = 'div']/*[local-name() = 'p' and \
+             contains(concat(' ', normalize-space(@class), ' '), ' x ')]"
+        );
+        assert_eq!(parse_nth("2n+1"), Some((2, 1)));
+        assert_eq!(parse_nth(" -n + 3 "), Some((-1, 3)));
+        assert_eq!(parse_nth("even"), Some((2, 0)));
+        assert_eq!(parse_nth("n-"), None);
+        assert_eq!(literal("a'b\"c"), "concat('a', \"'\", 'b\"c')");
+    }
+
+    #[test]
+    fn forwarding() {
+        let selector = Selector::parse("#b", &NamespaceSupport::new()).unwrap();
+        let recorder = RefCell::new(Vec::new());
+        struct Names<'r>(&'r RefCell<Vec<String>>);
+        impl<'r> ContentHandler<LocatorImpl, AttributesImpl> for Names<'r> {
+            fn start_element(
+                &self,
+                _uri: &str,
+                _local_name: &str,
+                q_name: &str,
+                _attributes: AttributesImpl,
+            ) -> Result<()> {
+                self.0.borrow_mut().push(q_name.to_owned());
+                Ok(())
+            }
+        }
+        {
+            let mut matcher = SelectorMatcher::<LocatorImpl>::new();
+            matcher.forward(&selector, Names(&recorder)).unwrap();
+            for event in document() {
+                event.dispatch(&matcher).unwrap();
+            }
+        }
+        assert_eq!(recorder.into_inner(), ["div", "svg:rect", "p"]);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let errors = [
+            (
+                "p + p",
+                "sibling combinators cannot be matched on a stream at offset 2",
+            ),
+            (
+                "p:last-child",
+                ":last-child cannot be matched on a stream at offset 12",
+            ),
+            ("p:hover", "unsupported pseudo-class :hover at offset 7"),
+            ("p::before", "pseudo-elements cannot be matched at offset 1"),
+            ("p :root", ":root must start the selector at offset 7"),
+            ("x|p", "undeclared prefix \"x\" at offset 2"),
+            ("[a=b i]", "unsupported attribute flag \"i\" at offset 6"),
+            ("[a='b]", "unterminated string at offset 6"),
+            (
+                "p:nth-child(x)",
+                "invalid :nth-child argument \"x\" at offset 13",
+            ),
+            ("p,", "expected a selector at offset 2"),
+            ("p >", "expected a selector at offset 3"),
+            ("p)", "unexpected ')' at offset 1"),
+        ];
+        for &(source, message) in &errors {
+            let error = Selector::parse(source, &NamespaceSupport::new()).expect_err(source);
+            assert_eq!(error.kind(), &ErrorKind::Expression);
+            assert_eq!(
+                error.message(),
+                format!("{} in selector \"{}\"", message, source)
+            );
+        }
+    }
+}
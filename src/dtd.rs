@@ -0,0 +1,1537 @@
+//! DTD validation support for readers.
+//!
+//! `DtdValidator` collects the declarations of a DTD as they are reported through the
+//! `DeclHandler`, `DtdHandler` and `LexicalHandler` interfaces, and then checks the element
+//! structure of a document against them. `parser::DtdParser` reads DTD subsets and reports
+//! their declarations through the same interfaces. A reader that supports the
+//! http://xml.org/sax/features/validation feature drives the validator alongside its content
+//! handler, and reports each error it returns through `ErrorHandler::error`, after giving it the
+//! reader's current position with `ParseErrorImpl::located`.
+//!
+//! Element content models are compiled into Glushkov automata. The XML recommendation requires
+//! content models to be deterministic (Appendix E), in which case the automaton is a
+//! deterministic finite automaton and each child element is matched in constant time.
+//! Non-deterministic models are reported as errors, but are still matched correctly.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use ext::DeclHandler;
+use ext::LexicalHandler;
+use helpers::AttributesImpl;
+use helpers::ErrorKind;
+use helpers::ParseErrorImpl;
+use names;
+use sax2::Attributes;
+use sax2::DtdHandler;
+use sax2::Result;
+
+pub mod parser;
+
+/// A content particle of an element type declaration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Particle {
+    /// A child element of the named type.
+    Name(String),
+    /// A sequence of particles, `(a,b,c)`.
+    Seq(Vec<Particle>),
+    /// A choice between particles, `(a|b|c)`.
+    Choice(Vec<Particle>),
+    /// An optional particle, `a?`.
+    Optional(Box<Particle>),
+    /// A particle repeated zero or more times, `a*`.
+    ZeroOrMore(Box<Particle>),
+    /// A particle repeated one or more times, `a+`.
+    OneOrMore(Box<Particle>),
+}
+
+/// A compiled element content model.
+#[derive(Clone, Debug)]
+pub enum ContentModel {
+    /// `EMPTY`: the element must have no content.
+    Empty,
+    /// `ANY`: the element may contain character data and any declared elements.
+    Any,
+    /// `(#PCDATA|a|b)*`: character data interspersed with the listed element types.
+    Mixed(Vec<String>),
+    /// Element content, described by a content particle.
+    Children(Automaton),
+}
+
+impl ContentModel {
+    /// Parse a content model, in the form reported by `DeclHandler::element_decl`.
+    ///
+    /// White space between tokens is tolerated, so declarations taken directly from a DTD can
+    /// be parsed as well.
+    pub fn parse(model: &str) -> std::result::Result<ContentModel, ParseErrorImpl> {
+        let tokens: String = model
+            .chars()
+            .filter(|c| !names::is_whitespace(*c))
+            .collect();
+        match tokens.as_str() {
+            "EMPTY" => return Ok(ContentModel::Empty),
+            "ANY" => return Ok(ContentModel::Any),
+            _ => {}
+        }
+        if tokens.starts_with("(#PCDATA") {
+            return parse_mixed(&tokens).map(ContentModel::Mixed);
+        }
+        let mut parser = ModelParser {
+            input: &tokens,
+            pos: 0,
+        };
+        let particle = parser.cp()?;
+        match particle {
+            Particle::Name(_) => Err(ParseErrorImpl::of_kind(
+                ErrorKind::Validity,
+                format!("content model '{}' must be enclosed in parentheses", model),
+            )),
+            _ if parser.pos != tokens.len() => Err(ParseErrorImpl::of_kind(
+                ErrorKind::Validity,
+                format!(
+                    "unexpected '{}' in content model '{}'",
+                    &tokens[parser.pos..],
+                    model
+                ),
+            )),
+            particle => Ok(ContentModel::Children(Automaton::new(&particle))),
+        }
+    }
+}
+
+fn parse_mixed(tokens: &str) -> std::result::Result<Vec<String>, ParseErrorImpl> {
+    let body = if tokens.ends_with(")*") {
+        &tokens[1..tokens.len() - 2]
+    } else if tokens == "(#PCDATA)" {
+        "#PCDATA"
+    } else {
+        return Err(ParseErrorImpl::of_kind(
+            ErrorKind::Validity,
+            format!("mixed content model '{}' must end with ')*'", tokens),
+        ));
+    };
+    let mut names = Vec::new();
+    for name in body.split('|').skip(1) {
+        if !names::is_name(name) {
+            return Err(ParseErrorImpl::of_kind(
+                ErrorKind::Validity,
+                format!("invalid element type '{}' in mixed content model", name),
+            ));
+        }
+        if names.iter().any(|n| n == name) {
+            return Err(ParseErrorImpl::of_kind(
+                ErrorKind::Validity,
+                format!(
+                    "element type '{}' appears more than once in mixed content model",
+                    name
+                ),
+            ));
+        }
+        names.push(name.to_owned());
+    }
+    Ok(names)
+}
+
+struct ModelParser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> ModelParser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn cp(&mut self) -> std::result::Result<Particle, ParseErrorImpl> {
+        let particle = if self.peek() == Some('(') {
+            self.pos += 1;
+            let mut items = vec![self.cp()?];
+            let separator = self.peek();
+            while self.peek() == separator && (separator == Some(',') || separator == Some('|')) {
+                self.pos += 1;
+                items.push(self.cp()?);
+            }
+            if self.peek() != Some(')') {
+                return Err(ParseErrorImpl::of_kind(
+                    ErrorKind::Validity,
+                    format!(
+                        "expected ')' at offset {} in content model '{}'",
+                        self.pos, self.input
+                    ),
+                ));
+            }
+            self.pos += 1;
+            if separator == Some('|') {
+                Particle::Choice(items)
+            } else {
+                Particle::Seq(items)
+            }
+        } else {
+            let start = self.pos;
+            while self
+                .peek()
+                .is_some_and(|c| names::is_name_char(c) && c != ',' && c != '|')
+            {
+                self.pos += self.peek().map_or(0, char::len_utf8);
+            }
+            let name = &self.input[start..self.pos];
+            if !names::is_name(name) {
+                return Err(ParseErrorImpl::of_kind(
+                    ErrorKind::Validity,
+                    format!(
+                        "expected an element type at offset {} in content model '{}'",
+                        start, self.input
+                    ),
+                ));
+            }
+            Particle::Name(name.to_owned())
+        };
+        Ok(match self.peek() {
+            Some('?') => {
+                self.pos += 1;
+                Particle::Optional(Box::new(particle))
+            }
+            Some('*') => {
+                self.pos += 1;
+                Particle::ZeroOrMore(Box::new(particle))
+            }
+            Some('+') => {
+                self.pos += 1;
+                Particle::OneOrMore(Box::new(particle))
+            }
+            _ => particle,
+        })
+    }
+}
+
+/// The Glushkov automaton of an element content particle.
+///
+/// Each state after the initial one corresponds to an occurrence of an element type name in
+/// the particle.
+#[derive(Clone, Debug)]
+pub struct Automaton {
+    names: Vec<String>,
+    first: Vec<usize>,
+    follow: Vec<Vec<usize>>,
+    last: Vec<bool>,
+    nullable: bool,
+}
+
+struct Positions {
+    nullable: bool,
+    first: Vec<usize>,
+    last: Vec<usize>,
+}
+
+impl Automaton {
+    /// Compile a content particle.
+    pub fn new(particle: &Particle) -> Self {
+        let mut automaton = Automaton {
+            names: Vec::new(),
+            first: Vec::new(),
+            follow: Vec::new(),
+            last: Vec::new(),
+            nullable: false,
+        };
+        let root = automaton.build(particle);
+        automaton.last = vec![false; automaton.names.len()];
+        for p in &root.last {
+            automaton.last[*p] = true;
+        }
+        automaton.first = root.first;
+        automaton.nullable = root.nullable;
+        automaton
+    }
+
+    fn build(&mut self, particle: &Particle) -> Positions {
+        match *particle {
+            Particle::Name(ref name) => {
+                let p = self.names.len();
+                self.names.push(name.clone());
+                self.follow.push(Vec::new());
+                Positions {
+                    nullable: false,
+                    first: vec![p],
+                    last: vec![p],
+                }
+            }
+            Particle::Seq(ref items) => {
+                let mut acc = Positions {
+                    nullable: true,
+                    first: Vec::new(),
+                    last: Vec::new(),
+                };
+                for item in items {
+                    let next = self.build(item);
+                    for l in &acc.last {
+                        union(&mut self.follow[*l], &next.first);
+                    }
+                    if acc.nullable {
+                        union(&mut acc.first, &next.first);
+                    }
+                    if next.nullable {
+                        union(&mut acc.last, &next.last);
+                    } else {
+                        acc.last = next.last;
+                    }
+                    acc.nullable = acc.nullable && next.nullable;
+                }
+                acc
+            }
+            Particle::Choice(ref items) => {
+                let mut acc = Positions {
+                    nullable: false,
+                    first: Vec::new(),
+                    last: Vec::new(),
+                };
+                for item in items {
+                    let next = self.build(item);
+                    union(&mut acc.first, &next.first);
+                    union(&mut acc.last, &next.last);
+                    acc.nullable = acc.nullable || next.nullable;
+                }
+                acc
+            }
+            Particle::Optional(ref inner) => {
+                let mut positions = self.build(inner);
+                positions.nullable = true;
+                positions
+            }
+            Particle::ZeroOrMore(ref inner) | Particle::OneOrMore(ref inner) => {
+                let mut positions = self.build(inner);
+                for l in &positions.last {
+                    union(&mut self.follow[*l], &positions.first);
+                }
+                if let Particle::ZeroOrMore(_) = *particle {
+                    positions.nullable = true;
+                }
+                positions
+            }
+        }
+    }
+
+    /// Return whether the automaton is deterministic, as required by the XML recommendation.
+    ///
+    /// If it is not, returns the name of an element type that could be matched by more than one
+    /// occurrence in the model.
+    pub fn check_deterministic(&self) -> std::result::Result<(), String> {
+        for set in Some(&self.first).into_iter().chain(self.follow.iter()) {
+            for (i, p) in set.iter().enumerate() {
+                if set[i + 1..]
+                    .iter()
+                    .any(|q| self.names[*q] == self.names[*p])
+                {
+                    return Err(self.names[*p].clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn transitions(&self, state: usize) -> &[usize] {
+        if state == 0 {
+            &self.first
+        } else {
+            &self.follow[state - 1]
+        }
+    }
+
+    fn is_any_accepting(&self, states: &[usize]) -> bool {
+        states.iter().any(|s| self.is_accepting(*s))
+    }
+
+    fn is_accepting(&self, state: usize) -> bool {
+        if state == 0 {
+            self.nullable
+        } else {
+            self.last[state - 1]
+        }
+    }
+
+    /// Return the element types that may appear next from the given set of states.
+    fn expected(&self, states: &[usize]) -> Vec<String> {
+        let mut expected: Vec<String> = Vec::new();
+        for state in states {
+            for p in self.transitions(*state) {
+                if !expected.contains(&self.names[*p]) {
+                    expected.push(self.names[*p].clone());
+                }
+            }
+        }
+        expected
+    }
+
+    fn step(&self, states: &[usize], name: &str) -> Vec<usize> {
+        let mut next = Vec::new();
+        for state in states {
+            for p in self.transitions(*state) {
+                if self.names[*p] == name && !next.contains(&(p + 1)) {
+                    next.push(p + 1);
+                }
+            }
+        }
+        next
+    }
+}
+
+fn union(target: &mut Vec<usize>, source: &[usize]) {
+    for p in source {
+        if !target.contains(p) {
+            target.push(*p);
+        }
+    }
+}
+
+/// The declared type of an attribute.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AttributeType {
+    Cdata,
+    Id,
+    Idref,
+    Idrefs,
+    Entity,
+    Entities,
+    Nmtoken,
+    Nmtokens,
+    /// `NOTATION (a|b)`
+    Notation(Vec<String>),
+    /// `(a|b)`
+    Enumeration(Vec<String>),
+}
+
+impl AttributeType {
+    /// Parse an attribute type, in the form reported by `DeclHandler::attribute_decl`.
+    pub fn parse(attribute_type: &str) -> std::result::Result<AttributeType, ParseErrorImpl> {
+        let tokens: String = attribute_type
+            .chars()
+            .filter(|c| !names::is_whitespace(*c))
+            .collect();
+        Ok(match tokens.as_str() {
+            "CDATA" => AttributeType::Cdata,
+            "ID" => AttributeType::Id,
+            "IDREF" => AttributeType::Idref,
+            "IDREFS" => AttributeType::Idrefs,
+            "ENTITY" => AttributeType::Entity,
+            "ENTITIES" => AttributeType::Entities,
+            "NMTOKEN" => AttributeType::Nmtoken,
+            "NMTOKENS" => AttributeType::Nmtokens,
+            t if t.starts_with("NOTATION(") => {
+                AttributeType::Notation(parse_token_group(&t[8..], names::is_name)?)
+            }
+            t if t.starts_with('(') => {
+                AttributeType::Enumeration(parse_token_group(t, names::is_nmtoken)?)
+            }
+            _ => {
+                return Err(ParseErrorImpl::of_kind(
+                    ErrorKind::Validity,
+                    format!("unknown attribute type '{}'", attribute_type),
+                ))
+            }
+        })
+    }
+
+    /// Return the type name as reported by `Attributes::get_type`.
+    ///
+    /// Enumerated attributes that are not notations are reported as "NMTOKEN".
+    pub fn sax_name(&self) -> &'static str {
+        match *self {
+            AttributeType::Cdata => "CDATA",
+            AttributeType::Id => "ID",
+            AttributeType::Idref => "IDREF",
+            AttributeType::Idrefs => "IDREFS",
+            AttributeType::Entity => "ENTITY",
+            AttributeType::Entities => "ENTITIES",
+            AttributeType::Nmtoken | AttributeType::Enumeration(_) => "NMTOKEN",
+            AttributeType::Nmtokens => "NMTOKENS",
+            AttributeType::Notation(_) => "NOTATION",
+        }
+    }
+}
+
+fn parse_token_group(
+    group: &str,
+    valid: fn(&str) -> bool,
+) -> std::result::Result<Vec<String>, ParseErrorImpl> {
+    if !group.starts_with('(') || !group.ends_with(')') {
+        return Err(ParseErrorImpl::of_kind(
+            ErrorKind::Validity,
+            format!("invalid token group '{}'", group),
+        ));
+    }
+    let mut tokens: Vec<String> = Vec::new();
+    for token in group[1..group.len() - 1].split('|') {
+        if !valid(token) {
+            return Err(ParseErrorImpl::of_kind(
+                ErrorKind::Validity,
+                format!("invalid token '{}' in '{}'", token, group),
+            ));
+        }
+        if tokens.iter().any(|t| t == token) {
+            return Err(ParseErrorImpl::of_kind(
+                ErrorKind::Validity,
+                format!("token '{}' appears more than once in '{}'", token, group),
+            ));
+        }
+        tokens.push(token.to_owned());
+    }
+    Ok(tokens)
+}
+
+/// The default declaration of an attribute.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DefaultDecl {
+    /// `#REQUIRED`: the attribute must always be specified.
+    Required,
+    /// `#IMPLIED`: no default value is provided.
+    Implied,
+    /// `#FIXED "value"`: the attribute, if specified, must have this value.
+    Fixed(String),
+    /// `"value"`: the value used if the attribute is not specified.
+    Default(String),
+}
+
+impl DefaultDecl {
+    fn value(&self) -> Option<&str> {
+        match *self {
+            DefaultDecl::Fixed(ref value) | DefaultDecl::Default(ref value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// An attribute-list declaration for a single attribute.
+#[derive(Clone, Debug)]
+pub struct AttributeDecl {
+    pub name: String,
+    pub attr_type: AttributeType,
+    pub default: DefaultDecl,
+    /// Whether the declaration was read from an external entity or the external subset.
+    pub external: bool,
+}
+
+/// An element type declaration.
+#[derive(Clone, Debug)]
+pub struct ElementDecl {
+    pub model: ContentModel,
+    /// Whether the declaration was read from an external entity or the external subset.
+    pub external: bool,
+}
+
+struct Frame {
+    name: String,
+    states: Vec<usize>,
+}
+
+#[derive(Default)]
+struct ValidatorState {
+    doctype: Option<String>,
+    entity_depth: usize,
+    elements: HashMap<String, ElementDecl>,
+    attributes: HashMap<String, Vec<AttributeDecl>>,
+    notations: HashSet<String>,
+    unparsed_entities: Vec<(String, String)>,
+    ids: HashSet<String>,
+    idrefs: Vec<String>,
+    stack: Vec<Frame>,
+    standalone: bool,
+    seen_root: bool,
+    errors: Vec<ParseErrorImpl>,
+}
+
+impl ValidatorState {
+    fn in_external(&self) -> bool {
+        self.entity_depth > 0
+    }
+}
+
+/// Validates a document against the declarations of its DTD.
+///
+/// Register the validator (or forward to it) as the reader's `DeclHandler`, `DtdHandler` and
+/// `LexicalHandler` while the DTD is read; any errors in the declarations themselves are
+/// collected and can be retrieved with `take_errors` after `end_dtd`. Then call
+/// `start_element`, `characters`, `end_element` and `end_document` as the corresponding content
+/// events are parsed; each returns the validity errors it detected.
+///
+/// Names are compared as qualified names, since DTDs are not Namespace-aware.
+#[derive(Default)]
+pub struct DtdValidator {
+    state: RefCell<ValidatorState>,
+}
+
+impl DtdValidator {
+    /// Construct a validator with no declarations.
+    pub fn new() -> Self {
+        DtdValidator::default()
+    }
+
+    /// Discard all declarations and document state, so the validator can be reused.
+    pub fn reset(&self) {
+        *self.state.borrow_mut() = ValidatorState::default();
+    }
+
+    /// Record whether the document declared `standalone="yes"`, which enables the Standalone
+    /// Document Declaration validity constraint.
+    pub fn set_standalone(&self, standalone: bool) {
+        self.state.borrow_mut().standalone = standalone;
+    }
+
+    /// Remove and return the errors detected in the declarations read so far.
+    pub fn take_errors(&self) -> Vec<ParseErrorImpl> {
+        std::mem::take(&mut self.state.borrow_mut().errors)
+    }
+
+    /// Return the declaration of an element type.
+    pub fn get_element_decl(&self, name: &str) -> Option<ElementDecl> {
+        self.state.borrow().elements.get(name).cloned()
+    }
+
+    /// Return the declaration of an attribute.
+    pub fn get_attribute_decl(&self, element: &str, attribute: &str) -> Option<AttributeDecl> {
+        self.state
+            .borrow()
+            .attributes
+            .get(element)
+            .and_then(|decls| decls.iter().find(|d| d.name == attribute))
+            .cloned()
+    }
+
+    /// Return the attributes of an element as they should be reported to the application: the
+    /// specified attributes with their declared types, followed by any defaulted attributes.
+    ///
+    /// Attributes that are not declared keep the type the reader gave them.
+    pub fn complete_attributes<A: Attributes>(
+        &self,
+        element: &str,
+        attributes: &A,
+    ) -> AttributesImpl {
+        let state = self.state.borrow();
+        let mut complete = AttributesImpl::from_attributes(attributes);
+        if let Some(decls) = state.attributes.get(element) {
+            for decl in decls {
+                match complete.get_q_name_index(&decl.name) {
+                    Some(index) => {
+                        complete.set_type(index, decl.attr_type.sax_name());
+                    }
+                    None => {
+                        if let Some(value) = decl.default.value() {
+                            let local_name = match decl.name.find(':') {
+                                Some(i) => &decl.name[i + 1..],
+                                None => &decl.name,
+                            };
+                            complete.add_attribute(
+                                "",
+                                local_name,
+                                &decl.name,
+                                decl.attr_type.sax_name(),
+                                value,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        complete
+    }
+
+    /// Check a start tag.
+    ///
+    /// `attributes` are the attributes specified in the start tag, before defaults are applied.
+    pub fn start_element<A: Attributes>(
+        &self,
+        q_name: &str,
+        attributes: &A,
+    ) -> Vec<ParseErrorImpl> {
+        let mut guard = self.state.borrow_mut();
+        let state = &mut *guard;
+        let mut errors = Vec::new();
+
+        if !state.seen_root {
+            state.seen_root = true;
+            match state.doctype {
+                None => errors.push(ParseErrorImpl::of_kind(
+                    ErrorKind::Validity,
+                    "document is invalid: no document type declaration found",
+                )),
+                Some(ref doctype) if doctype != q_name => errors.push(ParseErrorImpl::of_kind(
+                    ErrorKind::Validity,
+                    format!(
+                        "root element '{}' does not match the document type declaration name '{}'",
+                        q_name, doctype
+                    ),
+                )),
+                _ => {}
+            }
+        }
+
+        if let Some(parent) = state.stack.last_mut() {
+            match state.elements.get(&parent.name).map(|decl| &decl.model) {
+                Some(ContentModel::Empty) => errors.push(ParseErrorImpl::of_kind(
+                    ErrorKind::Validity,
+                    format!(
+                        "element '{}' is declared EMPTY, but contains element '{}'",
+                        parent.name, q_name
+                    ),
+                )),
+                Some(ContentModel::Mixed(allowed)) if !allowed.iter().any(|a| a == q_name) => {
+                    errors.push(ParseErrorImpl::of_kind(
+                        ErrorKind::Validity,
+                        format!(
+                            "element '{}' is not allowed in the mixed content of '{}'",
+                            q_name, parent.name
+                        ),
+                    ))
+                }
+                Some(ContentModel::Children(automaton)) if !parent.states.is_empty() => {
+                    let next = automaton.step(&parent.states, q_name);
+                    if next.is_empty() {
+                        let expected = automaton.expected(&parent.states);
+                        errors.push(ParseErrorImpl::of_kind(
+                            ErrorKind::Validity,
+                            format!(
+                            "element '{}' is not allowed here in the content of '{}'; expected {}",
+                            q_name,
+                            parent.name,
+                            describe_expected(
+                                &expected,
+                                automaton.is_any_accepting(&parent.states)
+                            )
+                        ),
+                        ));
+                    }
+                    parent.states = next;
+                }
+                _ => {}
+            }
+        }
+
+        if !state.elements.contains_key(q_name) {
+            errors.push(ParseErrorImpl::of_kind(
+                ErrorKind::Validity,
+                format!("element type '{}' is not declared", q_name),
+            ));
+        }
+
+        let decls = state.attributes.get(q_name).cloned().unwrap_or_default();
+        for i in 0..attributes.get_length() as u64 {
+            let name = attributes.get_q_name(i).unwrap_or_default();
+            let value = attributes.get_value(i).unwrap_or_default();
+            match decls.iter().find(|d| d.name == name) {
+                Some(decl) => check_attribute_value(state, q_name, decl, &value, true, &mut errors),
+                None => errors.push(ParseErrorImpl::of_kind(
+                    ErrorKind::Validity,
+                    format!(
+                        "attribute '{}' is not declared for element '{}'",
+                        name, q_name
+                    ),
+                )),
+            }
+        }
+        for decl in &decls {
+            if attributes.get_q_name_index(&decl.name).is_some() {
+                continue;
+            }
+            match decl.default {
+                DefaultDecl::Required => errors.push(ParseErrorImpl::of_kind(
+                    ErrorKind::Validity,
+                    format!(
+                        "attribute '{}' is required for element '{}'",
+                        decl.name, q_name
+                    ),
+                )),
+                DefaultDecl::Fixed(ref value) | DefaultDecl::Default(ref value) => {
+                    if state.standalone && decl.external {
+                        errors.push(ParseErrorImpl::of_kind(ErrorKind::Validity, format!(
+                            "attribute '{}' of element '{}' is defaulted from an external declaration in a standalone document",
+                            decl.name, q_name
+                        )));
+                    }
+                    let value = value.clone();
+                    check_attribute_value(state, q_name, decl, &value, true, &mut errors);
+                }
+                DefaultDecl::Implied => {}
+            }
+        }
+
+        let states = match state.elements.get(q_name) {
+            Some(&ElementDecl {
+                model: ContentModel::Children(_),
+                ..
+            }) => vec![0],
+            _ => Vec::new(),
+        };
+        state.stack.push(Frame {
+            name: q_name.to_owned(),
+            states,
+        });
+        errors
+    }
+
+    /// Return whether character data at the current position is whitespace in element content,
+    /// which the reader should report through `ContentHandler::ignorable_whitespace`.
+    pub fn is_ignorable_whitespace(&self, content: &str) -> bool {
+        let state = self.state.borrow();
+        let in_element_content = state.stack.last().is_some_and(|frame| {
+            matches!(
+                state.elements.get(&frame.name),
+                Some(ElementDecl {
+                    model: ContentModel::Children(_),
+                    ..
+                })
+            )
+        });
+        in_element_content && content.chars().all(names::is_whitespace)
+    }
+
+    /// Check character data appearing in the current element.
+    pub fn characters(&self, content: &str) -> Vec<ParseErrorImpl> {
+        let state = self.state.borrow();
+        let frame = match state.stack.last() {
+            Some(frame) => frame,
+            None => return Vec::new(),
+        };
+        let decl = match state.elements.get(&frame.name) {
+            Some(decl) => decl,
+            None => return Vec::new(),
+        };
+        match decl.model {
+            ContentModel::Empty if !content.is_empty() => vec![ParseErrorImpl::of_kind(
+                ErrorKind::Validity,
+                format!(
+                    "element '{}' is declared EMPTY, but contains character data",
+                    frame.name
+                ),
+            )],
+            ContentModel::Children(_) => {
+                if !content.chars().all(names::is_whitespace) {
+                    vec![ParseErrorImpl::of_kind(
+                        ErrorKind::Validity,
+                        format!(
+                            "character data is not allowed in the element content of '{}'",
+                            frame.name
+                        ),
+                    )]
+                } else if state.standalone && decl.external {
+                    vec![ParseErrorImpl::of_kind(ErrorKind::Validity, format!(
+                        "white space appears in the content of '{}', which is declared externally with element content, in a standalone document",
+                        frame.name
+                    ))]
+                } else {
+                    Vec::new()
+                }
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Check an end tag: the content of the element must be complete.
+    pub fn end_element(&self, q_name: &str) -> Vec<ParseErrorImpl> {
+        let mut state = self.state.borrow_mut();
+        let frame = match state.stack.pop() {
+            Some(frame) => frame,
+            None => return Vec::new(),
+        };
+        debug_assert_eq!(frame.name, q_name);
+        if let Some(&ElementDecl {
+            model: ContentModel::Children(ref automaton),
+            ..
+        }) = state.elements.get(&frame.name)
+        {
+            if !frame.states.is_empty() && !automaton.is_any_accepting(&frame.states) {
+                return vec![ParseErrorImpl::of_kind(
+                    ErrorKind::Validity,
+                    format!(
+                        "the content of element '{}' is incomplete; expected {}",
+                        q_name,
+                        describe_expected(&automaton.expected(&frame.states), false)
+                    ),
+                )];
+            }
+        }
+        Vec::new()
+    }
+
+    /// Check the constraints that can only be verified once the whole document has been read:
+    /// every IDREF must match the ID of some element.
+    pub fn end_document(&self) -> Vec<ParseErrorImpl> {
+        let mut state = self.state.borrow_mut();
+        let idrefs = std::mem::take(&mut state.idrefs);
+        let mut errors = Vec::new();
+        for idref in idrefs {
+            if !state.ids.contains(&idref) {
+                errors.push(ParseErrorImpl::of_kind(
+                    ErrorKind::Validity,
+                    format!("IDREF '{}' does not match the ID of any element", idref),
+                ));
+            }
+        }
+        errors
+    }
+}
+
+fn describe_expected(expected: &[String], can_end: bool) -> String {
+    let mut parts: Vec<String> = expected.iter().map(|e| format!("'{}'", e)).collect();
+    if can_end {
+        parts.push("the end of the element".to_owned());
+    }
+    if parts.is_empty() {
+        "nothing further".to_owned()
+    } else {
+        parts.join(" or ")
+    }
+}
+
+fn check_attribute_value(
+    state: &mut ValidatorState,
+    element: &str,
+    decl: &AttributeDecl,
+    value: &str,
+    in_document: bool,
+    errors: &mut Vec<ParseErrorImpl>,
+) {
+    if let DefaultDecl::Fixed(ref fixed) = decl.default {
+        if value != fixed {
+            errors.push(ParseErrorImpl::of_kind(
+                ErrorKind::Validity,
+                format!(
+                    "attribute '{}' of element '{}' must have the fixed value '{}'",
+                    decl.name, element, fixed
+                ),
+            ));
+        }
+    }
+    let tokens: Vec<&str> = value.split(' ').collect();
+    let invalid = |what: &str| {
+        ParseErrorImpl::of_kind(
+            ErrorKind::Validity,
+            format!(
+                "value '{}' of attribute '{}' of element '{}' is not {}",
+                value, decl.name, element, what
+            ),
+        )
+    };
+    match decl.attr_type {
+        AttributeType::Cdata => {}
+        AttributeType::Id => {
+            if !names::is_name(value) {
+                errors.push(invalid("a valid name"));
+            } else if in_document && !state.ids.insert(value.to_owned()) {
+                errors.push(ParseErrorImpl::of_kind(
+                    ErrorKind::Validity,
+                    format!("ID '{}' appears on more than one element", value),
+                ));
+            }
+        }
+        AttributeType::Idref | AttributeType::Idrefs => {
+            if decl.attr_type == AttributeType::Idref && tokens.len() != 1 {
+                errors.push(invalid("a single name"));
+            }
+            for token in tokens {
+                if !names::is_name(token) {
+                    errors.push(invalid("a list of valid names"));
+                    break;
+                } else if in_document {
+                    state.idrefs.push(token.to_owned());
+                }
+            }
+        }
+        AttributeType::Entity | AttributeType::Entities => {
+            if decl.attr_type == AttributeType::Entity && tokens.len() != 1 {
+                errors.push(invalid("a single entity name"));
+            }
+            for token in tokens {
+                // A default value may name an entity that is declared later in the DTD.
+                let declared = if in_document {
+                    state.unparsed_entities.iter().any(|e| e.0 == token)
+                } else {
+                    names::is_name(token)
+                };
+                if !declared {
+                    errors.push(ParseErrorImpl::of_kind(ErrorKind::Validity, format!(
+                        "attribute '{}' of element '{}' refers to '{}', which is not an unparsed entity",
+                        decl.name, element, token
+                    )));
+                }
+            }
+        }
+        AttributeType::Nmtoken => {
+            if !names::is_nmtoken(value) {
+                errors.push(invalid("a valid name token"));
+            }
+        }
+        AttributeType::Nmtokens => {
+            if !tokens.iter().all(|t| names::is_nmtoken(t)) {
+                errors.push(invalid("a list of valid name tokens"));
+            }
+        }
+        AttributeType::Notation(ref allowed) | AttributeType::Enumeration(ref allowed) => {
+            if !allowed.iter().any(|a| a == value) {
+                errors.push(invalid(&format!("one of ({})", allowed.join("|"))));
+            }
+        }
+    }
+}
+
+impl DeclHandler for DtdValidator {
+    fn attribute_decl(
+        &self,
+        element_name: &str,
+        attribute_name: &str,
+        attribute_type: &str,
+        mode: Option<&str>,
+        value: Option<&str>,
+    ) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        let attr_type = match AttributeType::parse(attribute_type) {
+            Ok(attr_type) => attr_type,
+            Err(e) => {
+                state.errors.push(e);
+                return Ok(());
+            }
+        };
+        let default = match (mode, value) {
+            (Some("#REQUIRED"), _) => DefaultDecl::Required,
+            (Some("#IMPLIED"), _) => DefaultDecl::Implied,
+            (Some("#FIXED"), Some(value)) => DefaultDecl::Fixed(value.to_owned()),
+            (None, Some(value)) => DefaultDecl::Default(value.to_owned()),
+            _ => {
+                state.errors.push(ParseErrorImpl::of_kind(
+                    ErrorKind::Validity,
+                    format!(
+                        "invalid default declaration for attribute '{}' of element '{}'",
+                        attribute_name, element_name
+                    ),
+                ));
+                return Ok(());
+            }
+        };
+        let external = state.in_external();
+        let mut errors = Vec::new();
+        {
+            let decls = state.attributes.entry(element_name.to_owned()).or_default();
+            if decls.iter().any(|d| d.name == attribute_name) {
+                // Only the first declaration is binding.
+                return Ok(());
+            }
+            match attr_type {
+                AttributeType::Id => {
+                    if decls.iter().any(|d| d.attr_type == AttributeType::Id) {
+                        errors.push(ParseErrorImpl::of_kind(
+                            ErrorKind::Validity,
+                            format!(
+                                "element type '{}' has more than one ID attribute",
+                                element_name
+                            ),
+                        ));
+                    }
+                    if default != DefaultDecl::Required && default != DefaultDecl::Implied {
+                        errors.push(ParseErrorImpl::of_kind(
+                            ErrorKind::Validity,
+                            format!(
+                            "ID attribute '{}' of element type '{}' must be #IMPLIED or #REQUIRED",
+                            attribute_name, element_name
+                        ),
+                        ));
+                    }
+                }
+                AttributeType::Notation(_)
+                    if decls
+                        .iter()
+                        .any(|d| matches!(d.attr_type, AttributeType::Notation(_))) =>
+                {
+                    errors.push(ParseErrorImpl::of_kind(
+                        ErrorKind::Validity,
+                        format!(
+                            "element type '{}' has more than one NOTATION attribute",
+                            element_name
+                        ),
+                    ));
+                }
+                _ => {}
+            }
+            decls.push(AttributeDecl {
+                name: attribute_name.to_owned(),
+                attr_type: attr_type.clone(),
+                default: default.clone(),
+                external,
+            });
+        }
+        if let Some(value) = default.value() {
+            let decl = AttributeDecl {
+                name: attribute_name.to_owned(),
+                attr_type,
+                default: DefaultDecl::Implied,
+                external,
+            };
+            check_attribute_value(&mut state, element_name, &decl, value, false, &mut errors);
+        }
+        state.errors.extend(errors);
+        Ok(())
+    }
+
+    fn element_decl(&self, name: &str, model: &str) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        if state.elements.contains_key(name) {
+            state.errors.push(ParseErrorImpl::of_kind(
+                ErrorKind::Validity,
+                format!("element type '{}' is declared more than once", name),
+            ));
+            return Ok(());
+        }
+        match ContentModel::parse(model) {
+            Ok(model) => {
+                if let ContentModel::Children(ref automaton) = model {
+                    if let Err(ambiguous) = automaton.check_deterministic() {
+                        state.errors.push(ParseErrorImpl::of_kind(ErrorKind::Validity, format!(
+                            "content model of element type '{}' is not deterministic: '{}' can be matched more than one way",
+                            name, ambiguous
+                        )));
+                    }
+                }
+                let external = state.in_external();
+                state
+                    .elements
+                    .insert(name.to_owned(), ElementDecl { model, external });
+            }
+            Err(e) => state.errors.push(e),
+        }
+        Ok(())
+    }
+}
+
+impl DtdHandler for DtdValidator {
+    fn notation_decl(
+        &self,
+        name: &str,
+        _public_id: Option<&str>,
+        _system_id: Option<&str>,
+    ) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        if !state.notations.insert(name.to_owned()) {
+            state.errors.push(ParseErrorImpl::of_kind(
+                ErrorKind::Validity,
+                format!("notation '{}' is declared more than once", name),
+            ));
+        }
+        Ok(())
+    }
+
+    fn unparsed_entity_decl(
+        &self,
+        name: &str,
+        _public_id: Option<&str>,
+        _system_id: &str,
+        notation_name: &str,
+    ) -> Result<()> {
+        self.state
+            .borrow_mut()
+            .unparsed_entities
+            .push((name.to_owned(), notation_name.to_owned()));
+        Ok(())
+    }
+}
+
+impl LexicalHandler for DtdValidator {
+    fn end_dtd(&self) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        let mut errors = Vec::new();
+        for (entity, notation) in &state.unparsed_entities {
+            if !state.notations.contains(notation) {
+                errors.push(ParseErrorImpl::of_kind(
+                    ErrorKind::Validity,
+                    format!(
+                        "unparsed entity '{}' refers to undeclared notation '{}'",
+                        entity, notation
+                    ),
+                ));
+            }
+        }
+        for (element, decls) in &state.attributes {
+            for decl in decls {
+                if let AttributeType::Notation(ref allowed) = decl.attr_type {
+                    for notation in allowed {
+                        if !state.notations.contains(notation) {
+                            errors.push(ParseErrorImpl::of_kind(ErrorKind::Validity, format!(
+                                "attribute '{}' of element type '{}' refers to undeclared notation '{}'",
+                                decl.name, element, notation
+                            )));
+                        }
+                    }
+                    if let Some(&ElementDecl {
+                        model: ContentModel::Empty,
+                        ..
+                    }) = state.elements.get(element)
+                    {
+                        errors.push(ParseErrorImpl::of_kind(
+                            ErrorKind::Validity,
+                            format!(
+                                "NOTATION attribute '{}' is declared for EMPTY element type '{}'",
+                                decl.name, element
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+        state.errors.extend(errors);
+        Ok(())
+    }
+
+    fn end_entity(&self, _name: &str) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        state.entity_depth = state.entity_depth.saturating_sub(1);
+        Ok(())
+    }
+
+    fn start_dtd(
+        &self,
+        name: &str,
+        _public_id: Option<&str>,
+        _system_id: Option<&str>,
+    ) -> Result<()> {
+        self.state.borrow_mut().doctype = Some(name.to_owned());
+        Ok(())
+    }
+
+    fn start_entity(&self, _name: &str) -> Result<()> {
+        // Declarations inside any parameter entity, internal or external, count as external
+        // markup declarations for the purposes of the standalone document declaration.
+        self.state.borrow_mut().entity_depth += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(errors: Vec<ParseErrorImpl>) -> Vec<String> {
+        errors.iter().map(|e| e.message().to_owned()).collect()
+    }
+
+    fn attributes(pairs: &[(&str, &str)]) -> AttributesImpl {
+        let mut attributes = AttributesImpl::new();
+        for &(name, value) in pairs {
+            attributes.add_attribute("", name, name, "CDATA", value);
+        }
+        attributes
+    }
+
+    /// Return a validator that has read the given element and attribute declarations.
+    fn validator(elements: &[(&str, &str)], attlists: &[(&str, &str, &str, &str)]) -> DtdValidator {
+        let validator = DtdValidator::new();
+        validator.start_dtd("doc", None, None).unwrap();
+        for &(name, model) in elements {
+            validator.element_decl(name, model).unwrap();
+        }
+        for &(element, name, attr_type, default) in attlists {
+            let (mode, value) = match default {
+                "#REQUIRED" | "#IMPLIED" => (Some(default), None),
+                _ if default.starts_with("#FIXED ") => (Some("#FIXED"), Some(&default[7..])),
+                _ => (None, Some(default)),
+            };
+            validator
+                .attribute_decl(element, name, attr_type, mode, value)
+                .unwrap();
+        }
+        validator.end_dtd().unwrap();
+        validator
+    }
+
+    #[test]
+    fn parse_content_models() {
+        match ContentModel::parse("( a , (b | c)* , d? )").unwrap() {
+            ContentModel::Children(automaton) => assert!(automaton.check_deterministic().is_ok()),
+            model => panic!("unexpected {:?}", model),
+        }
+        match ContentModel::parse("(#PCDATA | a | b)*").unwrap() {
+            ContentModel::Mixed(names) => assert_eq!(names, ["a", "b"]),
+            model => panic!("unexpected {:?}", model),
+        }
+        assert!(matches!(
+            ContentModel::parse("(#PCDATA)").unwrap(),
+            ContentModel::Mixed(ref names) if names.is_empty()
+        ));
+        assert!(matches!(
+            ContentModel::parse(" EMPTY ").unwrap(),
+            ContentModel::Empty
+        ));
+        let errors = [
+            ("a", "content model 'a' must be enclosed in parentheses"),
+            (
+                "(a,b|c)",
+                "expected ')' at offset 4 in content model '(a,b|c)'",
+            ),
+            ("(a)b", "unexpected 'b' in content model '(a)b'"),
+            (
+                "(a,,b)",
+                "expected an element type at offset 3 in content model '(a,,b)'",
+            ),
+            (
+                "(#PCDATA|a)",
+                "mixed content model '(#PCDATA|a)' must end with ')*'",
+            ),
+            (
+                "(#PCDATA|a|a)*",
+                "element type 'a' appears more than once in mixed content model",
+            ),
+        ];
+        for &(model, message) in &errors {
+            let error = ContentModel::parse(model).expect_err(model);
+            assert_eq!(error.kind(), &ErrorKind::Validity);
+            assert_eq!(error.message(), message);
+        }
+    }
+
+    #[test]
+    fn parse_attribute_types() {
+        assert_eq!(
+            AttributeType::parse("IDREFS").unwrap(),
+            AttributeType::Idrefs
+        );
+        assert_eq!(
+            AttributeType::parse("NOTATION (gif | png)").unwrap(),
+            AttributeType::Notation(vec!["gif".to_owned(), "png".to_owned()])
+        );
+        let enumeration = AttributeType::parse("(1|2)").unwrap();
+        assert_eq!(enumeration.sax_name(), "NMTOKEN");
+        assert_eq!(
+            AttributeType::parse("(a|a)").unwrap_err().message(),
+            "token 'a' appears more than once in '(a|a)'"
+        );
+        assert_eq!(
+            AttributeType::parse("STRING").unwrap_err().message(),
+            "unknown attribute type 'STRING'"
+        );
+    }
+
+    #[test]
+    fn non_deterministic_models() {
+        let validator = validator(
+            &[("doc", "((a,b)|(a,c))"), ("a", "EMPTY"), ("c", "EMPTY")],
+            &[],
+        );
+        assert_eq!(
+            messages(validator.take_errors()),
+            ["content model of element type 'doc' is not deterministic: 'a' can be matched more than one way"]
+        );
+        let none = AttributesImpl::new();
+        assert!(validator.start_element("doc", &none).is_empty());
+        for name in &["a", "c"] {
+            assert!(validator.start_element(name, &none).is_empty());
+            assert!(validator.end_element(name).is_empty());
+        }
+        assert!(validator.end_element("doc").is_empty());
+    }
+
+    #[test]
+    fn element_content() {
+        let validator = validator(
+            &[("doc", "(a,b+)"), ("a", "EMPTY"), ("b", "(#PCDATA|a)*")],
+            &[],
+        );
+        assert!(validator.take_errors().is_empty());
+        let none = AttributesImpl::new();
+        assert!(validator.start_element("doc", &none).is_empty());
+        assert!(validator.is_ignorable_whitespace("\n  "));
+        assert!(validator.characters("\n  ").is_empty());
+        assert_eq!(
+            messages(validator.characters("text")),
+            ["character data is not allowed in the element content of 'doc'"]
+        );
+        assert_eq!(
+            messages(validator.start_element("b", &none)),
+            ["element 'b' is not allowed here in the content of 'doc'; expected 'a'"]
+        );
+        assert!(validator.characters("text").is_empty());
+        assert!(!validator.is_ignorable_whitespace(" "));
+        assert!(validator.start_element("a", &none).is_empty());
+        assert_eq!(
+            messages(validator.characters("x")),
+            ["element 'a' is declared EMPTY, but contains character data"]
+        );
+        assert_eq!(
+            messages(validator.start_element("c", &none)),
+            [
+                "element 'a' is declared EMPTY, but contains element 'c'",
+                "element type 'c' is not declared",
+            ]
+        );
+        assert!(validator.end_element("c").is_empty());
+        assert!(validator.end_element("a").is_empty());
+        assert_eq!(
+            messages(validator.start_element("doc", &none)),
+            ["element 'doc' is not allowed in the mixed content of 'b'",]
+        );
+    }
+
+    #[test]
+    fn incomplete_content() {
+        let validator = validator(&[("doc", "(a,b+)"), ("a", "EMPTY"), ("b", "EMPTY")], &[]);
+        let none = AttributesImpl::new();
+        assert!(validator.start_element("doc", &none).is_empty());
+        assert!(validator.start_element("a", &none).is_empty());
+        assert!(validator.end_element("a").is_empty());
+        assert_eq!(
+            messages(validator.end_element("doc")),
+            ["the content of element 'doc' is incomplete; expected 'b'"]
+        );
+    }
+
+    #[test]
+    fn document_type_name() {
+        let validator = validator(&[("other", "ANY")], &[]);
+        assert_eq!(
+            messages(validator.start_element("other", &AttributesImpl::new())),
+            ["root element 'other' does not match the document type declaration name 'doc'"]
+        );
+        let validator = DtdValidator::new();
+        assert_eq!(
+            messages(validator.start_element("doc", &AttributesImpl::new())),
+            [
+                "document is invalid: no document type declaration found",
+                "element type 'doc' is not declared",
+            ]
+        );
+    }
+
+    #[test]
+    fn attribute_values() {
+        let validator = validator(
+            &[("doc", "EMPTY")],
+            &[
+                ("doc", "id", "ID", "#REQUIRED"),
+                ("doc", "version", "CDATA", "#FIXED 1.0"),
+                ("doc", "color", "(red|green)", "red"),
+                ("doc", "size", "NMTOKEN", "#IMPLIED"),
+            ],
+        );
+        assert!(validator.take_errors().is_empty());
+        assert_eq!(
+            messages(validator.start_element(
+                "doc",
+                &attributes(&[
+                    ("version", "2.0"),
+                    ("color", "blue"),
+                    ("size", "a b"),
+                    ("lang", "en"),
+                ])
+            )),
+            [
+                "attribute 'version' of element 'doc' must have the fixed value '1.0'",
+                "value 'blue' of attribute 'color' of element 'doc' is not one of (red|green)",
+                "value 'a b' of attribute 'size' of element 'doc' is not a valid name token",
+                "attribute 'lang' is not declared for element 'doc'",
+                "attribute 'id' is required for element 'doc'",
+            ]
+        );
+
+        let complete = validator.complete_attributes("doc", &attributes(&[("id", "x")]));
+        assert_eq!(complete.get_length(), 3);
+        assert_eq!(complete.get_type(0).as_deref(), Some("ID"));
+        assert_eq!(complete.get_q_name_value("version").as_deref(), Some("1.0"));
+        assert_eq!(
+            complete.get_q_name_type("color").as_deref(),
+            Some("NMTOKEN")
+        );
+    }
+
+    #[test]
+    fn ids_and_idrefs() {
+        let validator = validator(
+            &[("doc", "(p*)"), ("p", "EMPTY")],
+            &[
+                ("p", "id", "ID", "#IMPLIED"),
+                ("p", "refs", "IDREFS", "#IMPLIED"),
+            ],
+        );
+        let none = AttributesImpl::new();
+        validator.start_element("doc", &none);
+        for &(id, refs) in &[("a", "b c"), ("b", "a"), ("a", "a")] {
+            let errors = validator.start_element("p", &attributes(&[("id", id), ("refs", refs)]));
+            if id == "a" && refs == "a" {
+                assert_eq!(
+                    messages(errors),
+                    ["ID 'a' appears on more than one element"]
+                );
+            } else {
+                assert!(errors.is_empty());
+            }
+            validator.end_element("p");
+        }
+        validator.end_element("doc");
+        assert_eq!(
+            messages(validator.end_document()),
+            ["IDREF 'c' does not match the ID of any element"]
+        );
+    }
+
+    #[test]
+    fn declaration_errors() {
+        let validator = DtdValidator::new();
+        validator.start_dtd("doc", None, None).unwrap();
+        validator.element_decl("doc", "EMPTY").unwrap();
+        validator.element_decl("doc", "ANY").unwrap();
+        validator
+            .attribute_decl("doc", "a", "ID", None, Some("x"))
+            .unwrap();
+        validator
+            .attribute_decl("doc", "b", "ID", Some("#IMPLIED"), None)
+            .unwrap();
+        validator
+            .attribute_decl("doc", "b", "CDATA", Some("#IMPLIED"), None)
+            .unwrap();
+        validator
+            .attribute_decl("doc", "n", "NOTATION (gif)", Some("#IMPLIED"), None)
+            .unwrap();
+        validator
+            .unparsed_entity_decl("logo", None, "logo.png", "png")
+            .unwrap();
+        validator
+            .notation_decl("gif", None, Some("image/gif"))
+            .unwrap();
+        validator.notation_decl("gif", None, None).unwrap();
+        validator.end_dtd().unwrap();
+        assert_eq!(
+            messages(validator.take_errors()),
+            [
+                "element type 'doc' is declared more than once",
+                "ID attribute 'a' of element type 'doc' must be #IMPLIED or #REQUIRED",
+                "element type 'doc' has more than one ID attribute",
+                "notation 'gif' is declared more than once",
+                "unparsed entity 'logo' refers to undeclared notation 'png'",
+                "NOTATION attribute 'n' is declared for EMPTY element type 'doc'",
+            ]
+        );
+        assert_eq!(
+            validator.get_attribute_decl("doc", "b").unwrap().attr_type,
+            AttributeType::Id
+        );
+        assert!(validator.take_errors().is_empty());
+        validator.reset();
+        assert!(validator.get_element_decl("doc").is_none());
+    }
+
+    #[test]
+    fn standalone_documents() {
+        let validator = DtdValidator::new();
+        validator.start_dtd("doc", None, Some("doc.dtd")).unwrap();
+        validator.start_entity("[dtd]").unwrap();
+        validator.element_decl("doc", "(p)").unwrap();
+        validator.element_decl("p", "EMPTY").unwrap();
+        validator
+            .attribute_decl("doc", "version", "CDATA", None, Some("1"))
+            .unwrap();
+        validator.end_entity("[dtd]").unwrap();
+        validator.end_dtd().unwrap();
+        assert!(validator.get_element_decl("doc").unwrap().external);
+        validator.set_standalone(true);
+        assert_eq!(
+            messages(validator.start_element("doc", &AttributesImpl::new())),
+            ["attribute 'version' of element 'doc' is defaulted from an external declaration in a standalone document"]
+        );
+        assert_eq!(
+            messages(validator.characters(" ")),
+            ["white space appears in the content of 'doc', which is declared externally with element content, in a standalone document"]
+        );
+    }
+}
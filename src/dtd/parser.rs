@@ -0,0 +1,1859 @@
+//! Parsing of DTD subsets.
+//!
+//! `DtdParser` reads the internal and external subsets of a document type declaration and
+//! reports what they declare to the handlers registered with it: element, attribute and
+//! parsed entity declarations through `DeclHandler`, notation and unparsed entity declarations
+//! through `DtdHandler`, and comments and the boundaries of the DTD, the external subset and
+//! parameter entities through `LexicalHandler`. Readers use it for the `<!DOCTYPE` declaration
+//! of a document, and applications can use it to load a DTD on its own, for instance into a
+//! `DtdValidator`.
+//!
+//! Parameter entity references are expanded between declarations, and also within them and in
+//! entity values outside the internal subset, where the XML recommendation allows them.
+//! Conditional sections are processed in the external subset and external parameter entities.
+//! External parameter entities and the external subset are obtained from the registered
+//! `EntityResolver2`, or else read from `file:` URIs; any other URI that the resolver declines
+//! is an error. Entity expansion is checked against `Limits` with an `EntityGuard`.
+//!
+//! As in SAX, only the first declaration of an entity or of an attribute is reported, and once
+//! an external parameter entity has not been read, because of
+//! `features::EXTERNAL_PARAMETER_ENTITIES`, the entity and attribute-list declarations that
+//! follow are not processed (XML 1.0 section 5.1). Processing instructions are checked and
+//! skipped. Input is UTF-8, and the text declaration of external entities is skipped.
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io::Read;
+
+use common;
+use dtd::AttributeType;
+use dtd::ContentModel;
+use ext::DeclHandler;
+use ext::EntityResolver2;
+use ext::LexicalHandler;
+use features;
+use helpers::LocatorImpl;
+use helpers::ParseErrorImpl;
+use limits::EntityGuard;
+use limits::Limits;
+use names;
+use sax2::DtdHandler;
+use sax2::Result;
+use uri;
+
+/// The name under which the external subset is reported to `LexicalHandler::start_entity`.
+pub const EXTERNAL_SUBSET: &str = "[dtd]";
+
+/// The message of the "PEs in Internal Subset" well-formedness constraint.
+const PE_IN_INTERNAL_SUBSET: &str =
+    "parameter entity references cannot occur within markup declarations in the internal subset";
+
+/// The message for a conditional section that is not in an external entity (section 3.4).
+const CONDITIONAL_SECTION_OUTSIDE: &str =
+    "conditional sections are only allowed in the external subset or external parameter entities";
+
+/// The replacement text of an entity, or where to find it.
+#[derive(Clone, Debug)]
+enum Entity {
+    Internal(String),
+    External {
+        public_id: Option<String>,
+        system_id: String,
+        base_uri: Option<String>,
+    },
+    Unparsed,
+}
+
+/// An entity being read.
+struct Input {
+    chars: Vec<char>,
+    position: usize,
+    line: u64,
+    column: u64,
+    /// The name reported to the `LexicalHandler`, if any.
+    name: Option<String>,
+    /// The name of the parameter entity, for recursion checks.
+    entity: Option<String>,
+    public_id: Option<String>,
+    /// The absolute URI of the entity, the base URI of the declarations it contains.
+    system_id: Option<String>,
+    /// Whether this is the internal subset, in which parameter entity references may not
+    /// occur within markup declarations.
+    internal_subset: bool,
+    /// Whether this is the external subset or an external parameter entity.
+    external: bool,
+}
+
+impl Input {
+    fn new(text: &str) -> Self {
+        let mut chars = Vec::with_capacity(text.len());
+        let mut input = text.chars().peekable();
+        while let Some(c) = input.next() {
+            if c == '\r' {
+                if input.peek() == Some(&'\n') {
+                    input.next();
+                }
+                chars.push('\n');
+            } else {
+                chars.push(c);
+            }
+        }
+        Input {
+            chars,
+            position: 0,
+            line: 1,
+            column: 1,
+            name: None,
+            entity: None,
+            public_id: None,
+            system_id: None,
+            internal_subset: false,
+            external: false,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).cloned()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).cloned()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        let mut rest = self.chars[self.position..].iter();
+        s.chars().all(|c| rest.next() == Some(&c))
+    }
+
+    fn advance(&mut self) {
+        if let Some(c) = self.peek() {
+            self.position += 1;
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+    }
+
+    fn skip(&mut self, s: &str) {
+        for _ in s.chars() {
+            self.advance();
+        }
+    }
+
+    fn location(&self) -> LocatorImpl {
+        LocatorImpl {
+            public_id: self.public_id.clone(),
+            system_id: self.system_id.clone(),
+            line_number: Some(self.line),
+            column_number: Some(self.column),
+        }
+    }
+
+    /// Skip the text declaration at the start of an external entity.
+    fn skip_text_decl(&mut self) {
+        if self.starts_with("<?xml") && self.peek_at(5).is_some_and(names::is_whitespace) {
+            while self.peek().is_some() && !self.starts_with("?>") {
+                self.advance();
+            }
+            self.skip("?>");
+        }
+    }
+}
+
+/// Reads DTD subsets and reports their declarations.
+///
+/// The parser keeps the entities declared so far, so that a document's internal subset and
+/// external subset are read by the same parser, in that order, which `parse_doctype` does.
+/// `parse_internal_subset` and `parse_external_subset` read a single subset.
+pub struct DtdParser<'a> {
+    decl_handler: Option<&'a dyn DeclHandler>,
+    dtd_handler: Option<&'a dyn DtdHandler>,
+    lexical_handler: Option<&'a dyn LexicalHandler>,
+    entity_resolver: Option<&'a dyn EntityResolver2>,
+    base_uri: Option<String>,
+    external_parameter_entities: bool,
+    lexical_handler_parameter_entities: bool,
+    resolve_dtd_uris: bool,
+    limits: Limits,
+    guard: EntityGuard,
+    inputs: Vec<Input>,
+    general_entities: HashMap<String, Entity>,
+    parameter_entities: HashMap<String, Entity>,
+    attributes: HashSet<(String, String)>,
+    /// Whether an external parameter entity was not read, after which entity and
+    /// attribute-list declarations are not processed.
+    skipped: bool,
+    /// The number of open INCLUDE sections.
+    includes: usize,
+}
+
+impl<'a> Default for DtdParser<'a> {
+    fn default() -> Self {
+        DtdParser::new()
+    }
+}
+
+impl<'a> DtdParser<'a> {
+    /// Construct a parser with no handlers, no entity resolver and the default limits.
+    pub fn new() -> Self {
+        let limits = Limits::new();
+        DtdParser {
+            decl_handler: None,
+            dtd_handler: None,
+            lexical_handler: None,
+            entity_resolver: None,
+            base_uri: None,
+            external_parameter_entities: true,
+            lexical_handler_parameter_entities: true,
+            resolve_dtd_uris: true,
+            guard: EntityGuard::new(&limits),
+            limits,
+            inputs: Vec::new(),
+            general_entities: HashMap::new(),
+            parameter_entities: HashMap::new(),
+            attributes: HashSet::new(),
+            skipped: false,
+            includes: 0,
+        }
+    }
+
+    /// Register the handler for element, attribute and parsed entity declarations.
+    pub fn set_decl_handler(&mut self, handler: &'a dyn DeclHandler) {
+        self.decl_handler = Some(handler);
+    }
+
+    /// Register the handler for notation and unparsed entity declarations.
+    pub fn set_dtd_handler(&mut self, handler: &'a dyn DtdHandler) {
+        self.dtd_handler = Some(handler);
+    }
+
+    /// Register the handler for comments and for the boundaries of the DTD and its entities.
+    pub fn set_lexical_handler(&mut self, handler: &'a dyn LexicalHandler) {
+        self.lexical_handler = Some(handler);
+    }
+
+    /// Register the resolver for the external subset and external parameter entities.
+    pub fn set_entity_resolver(&mut self, resolver: &'a dyn EntityResolver2) {
+        self.entity_resolver = Some(resolver);
+    }
+
+    /// Return the base URI against which the system identifiers of the internal subset are
+    /// resolved.
+    pub fn get_base_uri(&self) -> Option<&str> {
+        self.base_uri.as_deref()
+    }
+
+    /// Set the base URI against which the system identifiers of the internal subset are
+    /// resolved, normally the URI of the document.
+    pub fn set_base_uri(&mut self, base_uri: &str) {
+        self.base_uri = Some(base_uri.to_owned());
+    }
+
+    /// Set the limits checked while entities are expanded, and restart their accounting.
+    pub fn set_limits(&mut self, limits: &Limits) {
+        self.limits = limits.clone();
+        self.guard = EntityGuard::new(limits);
+    }
+
+    /// Return the value of a feature, or `None` if the parser does not recognize it.
+    ///
+    /// The recognized features are `features::EXTERNAL_PARAMETER_ENTITIES`,
+    /// `features::LEXICAL_HANDLER_PARAMETER_ENTITIES` and `features::RESOLVE_DTD_URIS`, all
+    /// true by default.
+    pub fn get_feature(&self, name: &str) -> Option<bool> {
+        match name {
+            features::EXTERNAL_PARAMETER_ENTITIES => Some(self.external_parameter_entities),
+            features::LEXICAL_HANDLER_PARAMETER_ENTITIES => {
+                Some(self.lexical_handler_parameter_entities)
+            }
+            features::RESOLVE_DTD_URIS => Some(self.resolve_dtd_uris),
+            _ => None,
+        }
+    }
+
+    /// Set the value of a feature, and return whether the parser recognizes it.
+    pub fn set_feature(&mut self, name: &str, value: bool) -> bool {
+        match name {
+            features::EXTERNAL_PARAMETER_ENTITIES => self.external_parameter_entities = value,
+            features::LEXICAL_HANDLER_PARAMETER_ENTITIES => {
+                self.lexical_handler_parameter_entities = value
+            }
+            features::RESOLVE_DTD_URIS => self.resolve_dtd_uris = value,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Return the replacement text of an internal general entity declared so far.
+    ///
+    /// This is meant for readers expanding entity references in the document.
+    pub fn get_internal_entity(&self, name: &str) -> Option<&str> {
+        match self.general_entities.get(name) {
+            Some(Entity::Internal(ref value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Return whether a general entity was declared, and, for an external parsed entity, its
+    /// public identifier, system identifier and the base URI of its declaration.
+    pub fn get_external_entity(&self, name: &str) -> Option<(Option<&str>, &str, Option<&str>)> {
+        match self.general_entities.get(name) {
+            Some(Entity::External {
+                ref public_id,
+                ref system_id,
+                ref base_uri,
+            }) => Some((public_id.as_deref(), system_id, base_uri.as_deref())),
+            _ => None,
+        }
+    }
+
+    /// Read the document type declaration of a document: report `start_dtd`, read the
+    /// internal subset, if any, then the external subset, and report `end_dtd`.
+    ///
+    /// The external subset is obtained with `EntityResolver2::resolve_entity_ext` if the
+    /// document names one, and with `EntityResolver2::get_external_subset` otherwise. It is
+    /// read only if `features::EXTERNAL_PARAMETER_ENTITIES` is true.
+    pub fn parse_doctype(
+        &mut self,
+        name: &str,
+        public_id: Option<&str>,
+        system_id: Option<&str>,
+        internal_subset: Option<&str>,
+    ) -> Result<()> {
+        if let Some(handler) = self.lexical_handler {
+            handler.start_dtd(name, public_id, system_id)?;
+        }
+        if let Some(subset) = internal_subset {
+            self.parse_internal_subset(subset)?;
+        }
+        if self.external_parameter_entities {
+            let base_uri = self.base_uri.clone();
+            let subset = match system_id {
+                Some(system_id) => Some(self.open(
+                    Some(EXTERNAL_SUBSET),
+                    public_id,
+                    base_uri.as_deref(),
+                    system_id,
+                )?),
+                None => match self.entity_resolver {
+                    Some(resolver) => resolver
+                        .get_external_subset(name, base_uri.as_deref())?
+                        .map(|input| read_entity(input, EXTERNAL_SUBSET))
+                        .transpose()?
+                        .map(|text| (text, None)),
+                    None => None,
+                },
+            };
+            if let Some((text, uri)) = subset {
+                let mut input = Input::new(&text);
+                input.name = Some(EXTERNAL_SUBSET.to_owned());
+                input.public_id = public_id.map(str::to_owned);
+                input.system_id = uri;
+                input.external = true;
+                self.parse(input)?;
+            }
+        } else if system_id.is_some() {
+            self.skipped = true;
+        }
+        if let Some(handler) = self.lexical_handler {
+            handler.end_dtd()?;
+        }
+        Ok(())
+    }
+
+    /// Read an internal subset, the text between the brackets of a document type declaration.
+    pub fn parse_internal_subset(&mut self, subset: &str) -> Result<()> {
+        let mut input = Input::new(subset);
+        input.system_id = self.base_uri.clone();
+        input.internal_subset = true;
+        self.guard
+            .document_read(input.chars.len() as u64, Some(&input.location()))?;
+        self.parse(input)
+    }
+
+    /// Read an external subset, or a DTD on its own, whose absolute URI is `system_id`.
+    ///
+    /// The subset is reported to `LexicalHandler::start_entity` as `[dtd]`, but
+    /// `LexicalHandler::start_dtd` is not called.
+    pub fn parse_external_subset(&mut self, subset: &str, system_id: Option<&str>) -> Result<()> {
+        let mut input = Input::new(subset.trim_start_matches('\u{FEFF}'));
+        input.name = Some(EXTERNAL_SUBSET.to_owned());
+        input.system_id = system_id.map(str::to_owned);
+        input.external = true;
+        self.parse(input)
+    }
+
+    /// Read the external subset, or a DTD on its own, from a stream.
+    pub fn parse_external_stream<R: Read>(
+        &mut self,
+        input: R,
+        system_id: Option<&str>,
+    ) -> Result<()> {
+        let text = read_entity(input, system_id.unwrap_or(EXTERNAL_SUBSET))?;
+        self.parse_external_subset(&text, system_id)
+    }
+
+    fn parse(&mut self, mut input: Input) -> Result<()> {
+        if input.external {
+            input.skip_text_decl();
+        }
+        self.inputs.clear();
+        self.includes = 0;
+        if let Some(ref name) = input.name {
+            if let Some(handler) = self.lexical_handler {
+                handler.start_entity(name)?;
+            }
+        }
+        let name = input.name.clone();
+        self.inputs.push(input);
+        let result = self.subset();
+        for _ in 1..self.inputs.len() {
+            self.guard.end_entity();
+        }
+        self.inputs.clear();
+        result?;
+        if let Some(name) = name {
+            if let Some(handler) = self.lexical_handler {
+                handler.end_entity(&name)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn subset(&mut self) -> Result<()> {
+        loop {
+            self.skip_markup_space()?;
+            let c = match self.peek()? {
+                Some(c) => c,
+                None => break,
+            };
+            let top = self.top();
+            if c != '<' && c != ']' {
+                return Err(self.error(format!("unexpected '{}' in the DTD", c)));
+            }
+            if top.starts_with("<!--") {
+                self.comment()?;
+            } else if top.starts_with("<?") {
+                self.processing_instruction()?;
+            } else if top.starts_with("<!ELEMENT") {
+                self.element_decl()?;
+            } else if top.starts_with("<!ATTLIST") {
+                self.attlist_decl()?;
+            } else if top.starts_with("<!ENTITY") {
+                self.entity_decl()?;
+            } else if top.starts_with("<!NOTATION") {
+                self.notation_decl()?;
+            } else if top.starts_with("<![") {
+                self.conditional_section()?;
+            } else if top.starts_with("]]>") && self.includes > 0 {
+                self.top_mut().skip("]]>");
+                self.includes -= 1;
+            } else {
+                return Err(self.error("expected a markup declaration"));
+            }
+        }
+        if self.includes > 0 {
+            return Err(self.error("unterminated INCLUDE section"));
+        }
+        Ok(())
+    }
+
+    fn top(&self) -> &Input {
+        self.inputs.last().expect("an entity is being read")
+    }
+
+    fn top_mut(&mut self) -> &mut Input {
+        self.inputs.last_mut().expect("an entity is being read")
+    }
+
+    fn location(&self) -> LocatorImpl {
+        self.inputs.last().map(Input::location).unwrap_or_default()
+    }
+
+    fn error<S: Into<String>>(&self, message: S) -> Box<dyn common::Error> {
+        Box::new(ParseErrorImpl::new(message, self.location()))
+    }
+
+    /// Return the next character, finishing the parameter entities that are exhausted, or
+    /// `None` at the end of the subset.
+    fn peek(&mut self) -> Result<Option<char>> {
+        loop {
+            if let Some(c) = self.top().peek() {
+                return Ok(Some(c));
+            }
+            if self.inputs.len() == 1 {
+                return Ok(None);
+            }
+            self.pop()?;
+        }
+    }
+
+    fn pop(&mut self) -> Result<()> {
+        let input = self.inputs.pop().expect("an entity is being read");
+        self.guard.end_entity();
+        if let Some(name) = input.name {
+            if let Some(handler) = self.lexical_handler {
+                handler.end_entity(&name)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Skip white space and the parameter entity references between markup declarations.
+    fn skip_markup_space(&mut self) -> Result<()> {
+        while let Some(c) = self.peek()? {
+            if names::is_whitespace(c) {
+                self.top_mut().advance();
+            } else if c == '%' && self.top().peek_at(1).is_some_and(names::is_name_start_char) {
+                self.reference(false)?;
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Skip white space within a markup declaration, expanding parameter entity references
+    /// where they are allowed, and return whether any was found.
+    fn skip_space(&mut self) -> Result<bool> {
+        let mut found = false;
+        loop {
+            let c = match self.top().peek() {
+                Some(c) => c,
+                None if self.inputs.len() > 1 => {
+                    self.pop()?;
+                    found = true;
+                    continue;
+                }
+                None => return Ok(found),
+            };
+            if names::is_whitespace(c) {
+                self.top_mut().advance();
+                found = true;
+            } else if c == '%' && self.top().peek_at(1).is_some_and(names::is_name_start_char) {
+                if self.top().internal_subset {
+                    return Err(self.error(PE_IN_INTERNAL_SUBSET));
+                }
+                self.reference(false)?;
+                found = true;
+            } else {
+                return Ok(found);
+            }
+        }
+    }
+
+    fn require_space(&mut self, context: &str) -> Result<()> {
+        if self.skip_space()? {
+            Ok(())
+        } else {
+            Err(self.error(format!("white space is required {}", context)))
+        }
+    }
+
+    /// Expand the parameter entity reference at the current position, pushing its replacement
+    /// text, and return false if it was not expanded because its entity was not read.
+    fn reference(&mut self, in_literal: bool) -> Result<bool> {
+        self.top_mut().advance();
+        let name = self.name()?;
+        if self.top().peek() != Some(';') {
+            return Err(self.error(format!("expected ';' after '%{}'", name)));
+        }
+        self.top_mut().advance();
+        if self
+            .inputs
+            .iter()
+            .any(|i| i.entity.as_deref() == Some(&name))
+        {
+            return Err(self.error(format!("parameter entity '%{};' references itself", name)));
+        }
+        let entity = match self.parameter_entities.get(&name) {
+            Some(entity) => entity.clone(),
+            None if self.skipped => return Ok(false),
+            None => {
+                return Err(self.error(format!("parameter entity '%{};' is not declared", name)))
+            }
+        };
+        let reported = format!("%{}", name);
+        let mut input = match entity {
+            Entity::Internal(ref value) => {
+                let mut input = Input::new(value);
+                input.public_id = self.top().public_id.clone();
+                input.system_id = self.top().system_id.clone();
+                input.line = self.top().line;
+                input.column = self.top().column;
+                input.external = self.top().external;
+                input
+            }
+            Entity::External {
+                ref public_id,
+                ref system_id,
+                ref base_uri,
+            } => {
+                if !self.external_parameter_entities {
+                    self.skipped = true;
+                    return Ok(false);
+                }
+                let (text, uri) = self.open(
+                    Some(&reported),
+                    public_id.as_deref(),
+                    base_uri.as_deref(),
+                    system_id,
+                )?;
+                let mut input = Input::new(&text);
+                input.skip_text_decl();
+                input.public_id = public_id.clone();
+                input.system_id = uri;
+                input.external = true;
+                input
+            }
+            Entity::Unparsed => unreachable!("parameter entities are parsed"),
+        };
+        if !in_literal {
+            // The replacement text is enlarged by a space on either side (4.4.8).
+            input.chars.insert(input.position, ' ');
+            input.chars.push(' ');
+        }
+        let location = self.location();
+        self.guard.start_entity(true, Some(&location))?;
+        self.guard
+            .expanded(input.chars.len() as u64, Some(&location))?;
+        input.entity = Some(name);
+        if self.lexical_handler_parameter_entities {
+            if let Some(handler) = self.lexical_handler {
+                handler.start_entity(&reported)?;
+            }
+            input.name = Some(reported);
+        }
+        self.inputs.push(input);
+        Ok(true)
+    }
+
+    /// Obtain the text of an external entity, and its absolute URI if known.
+    fn open(
+        &self,
+        name: Option<&str>,
+        public_id: Option<&str>,
+        base_uri: Option<&str>,
+        system_id: &str,
+    ) -> Result<(String, Option<String>)> {
+        let absolute = match base_uri {
+            Some(base_uri) => uri::resolve(base_uri, system_id),
+            None => system_id.to_owned(),
+        };
+        let resolved = match self.entity_resolver {
+            Some(resolver) => resolver.resolve_entity_ext(name, public_id, base_uri, system_id)?,
+            None => None,
+        };
+        let input: Box<dyn Read> = match resolved {
+            Some(input) => input,
+            None => {
+                let is_file = uri::scheme(&absolute)
+                    .is_some_and(|scheme| scheme.eq_ignore_ascii_case("file"));
+                let path = match uri::to_file_path(&absolute) {
+                    Some(ref path) if is_file => path.clone(),
+                    _ => return Err(self.error(format!("cannot open {}", absolute))),
+                };
+                match std::fs::File::open(&path) {
+                    Ok(file) => Box::new(file),
+                    Err(e) => return Err(self.error(format!("cannot open {}: {}", absolute, e))),
+                }
+            }
+        };
+        let text = read_entity(input, &absolute)?;
+        Ok((text, Some(absolute)))
+    }
+
+    /// Read a `Name` within the current entity.
+    fn name(&mut self) -> Result<String> {
+        let top = self.top_mut();
+        let mut name = String::new();
+        match top.peek() {
+            Some(c) if names::is_name_start_char(c) => {}
+            _ => return Err(self.error("expected a name")),
+        }
+        while let Some(c) = top.peek().filter(|&c| names::is_name_char(c)) {
+            name.push(c);
+            top.advance();
+        }
+        Ok(name)
+    }
+
+    /// Read an `Nmtoken` within the current entity.
+    fn nmtoken(&mut self) -> Result<String> {
+        let top = self.top_mut();
+        let mut token = String::new();
+        while let Some(c) = top.peek().filter(|&c| names::is_name_char(c)) {
+            token.push(c);
+            top.advance();
+        }
+        if token.is_empty() {
+            return Err(self.error("expected a name token"));
+        }
+        Ok(token)
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let top = self.top();
+        if top.starts_with(keyword)
+            && !top
+                .peek_at(keyword.chars().count())
+                .is_some_and(names::is_name_char)
+        {
+            self.top_mut().skip(keyword);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.top().peek() == Some(c) {
+            self.top_mut().advance();
+            Ok(())
+        } else {
+            Err(self.error(format!("expected '{}'", c)))
+        }
+    }
+
+    /// Finish a markup declaration.
+    fn end_decl(&mut self, decl: &str) -> Result<()> {
+        self.skip_space()?;
+        if self.top().peek() == Some('>') {
+            self.top_mut().advance();
+            Ok(())
+        } else {
+            Err(self.error(format!("expected '>' to end the {} declaration", decl)))
+        }
+    }
+
+    /// Read a quoted literal within the current entity, without any expansion.
+    fn literal(&mut self) -> Result<String> {
+        let quote = match self.top().peek() {
+            Some(quote) if quote == '"' || quote == '\'' => quote,
+            _ => return Err(self.error("expected a quoted literal")),
+        };
+        self.top_mut().advance();
+        let mut literal = String::new();
+        loop {
+            match self.top().peek() {
+                Some(c) if c == quote => break,
+                Some(c) => {
+                    self.check_char(c)?;
+                    literal.push(c);
+                    self.top_mut().advance();
+                }
+                None => return Err(self.error("unterminated literal")),
+            }
+        }
+        self.top_mut().advance();
+        Ok(literal)
+    }
+
+    fn check_char(&self, c: char) -> Result<()> {
+        if names::is_char(c) {
+            Ok(())
+        } else {
+            Err(self.error(format!("invalid character U+{:04X}", c as u32)))
+        }
+    }
+
+    fn comment(&mut self) -> Result<()> {
+        self.top_mut().skip("<!--");
+        let mut content = String::new();
+        loop {
+            let top = self.top();
+            if top.starts_with("--") {
+                if !top.starts_with("-->") {
+                    return Err(self.error("'--' is not allowed in a comment"));
+                }
+                break;
+            }
+            match top.peek() {
+                Some(c) => {
+                    self.check_char(c)?;
+                    content.push(c);
+                    self.top_mut().advance();
+                }
+                None => return Err(self.error("unterminated comment")),
+            }
+        }
+        self.top_mut().skip("-->");
+        if let Some(handler) = self.lexical_handler {
+            handler.comment(&content)?;
+        }
+        Ok(())
+    }
+
+    fn processing_instruction(&mut self) -> Result<()> {
+        self.top_mut().skip("<?");
+        let target = self.name()?;
+        if target.eq_ignore_ascii_case("xml") {
+            return Err(self.error(format!(
+                "'{}' is not allowed as a processing instruction target",
+                target
+            )));
+        }
+        loop {
+            let top = self.top();
+            if top.starts_with("?>") {
+                break;
+            }
+            match top.peek() {
+                Some(c) => {
+                    self.check_char(c)?;
+                    self.top_mut().advance();
+                }
+                None => return Err(self.error("unterminated processing instruction")),
+            }
+        }
+        self.top_mut().skip("?>");
+        Ok(())
+    }
+
+    fn element_decl(&mut self) -> Result<()> {
+        self.top_mut().skip("<!ELEMENT");
+        self.require_space("after '<!ELEMENT'")?;
+        let name = self.name()?;
+        self.require_space("after the element type")?;
+        let mut model = String::new();
+        loop {
+            self.skip_space()?;
+            match self.top().peek() {
+                Some('>') => break,
+                Some(c @ '(') | Some(c @ ')') | Some(c @ '|') | Some(c @ ',') => {
+                    model.push(c);
+                    self.top_mut().advance();
+                }
+                Some(c @ '?') | Some(c @ '*') | Some(c @ '+') => {
+                    model.push(c);
+                    self.top_mut().advance();
+                }
+                Some('#') => {
+                    self.top_mut().advance();
+                    model.push('#');
+                    model.push_str(&self.name()?);
+                }
+                Some(c) if names::is_name_start_char(c) => model.push_str(&self.name()?),
+                Some(c) => {
+                    return Err(self.error(format!(
+                        "unexpected '{}' in the content model of '{}'",
+                        c, name
+                    )))
+                }
+                None => return Err(self.error("unterminated element type declaration")),
+            }
+        }
+        if let Err(e) = ContentModel::parse(&model) {
+            return Err(self.error(e.message()));
+        }
+        self.end_decl("element type")?;
+        if let Some(handler) = self.decl_handler {
+            handler.element_decl(&name, &model)?;
+        }
+        Ok(())
+    }
+
+    fn attlist_decl(&mut self) -> Result<()> {
+        self.top_mut().skip("<!ATTLIST");
+        self.require_space("after '<!ATTLIST'")?;
+        let element = self.name()?;
+        loop {
+            let space = self.skip_space()?;
+            if self.top().peek() == Some('>') {
+                self.top_mut().advance();
+                return Ok(());
+            }
+            if !space {
+                return Err(self.error("white space is required before an attribute definition"));
+            }
+            let attribute = self.name()?;
+            self.require_space("after the attribute name")?;
+            let attribute_type = self.attribute_type()?;
+            self.require_space("after the attribute type")?;
+            let (mode, value) = if self.top().peek() == Some('#') {
+                self.top_mut().advance();
+                let mode = format!("#{}", self.name()?);
+                match mode.as_str() {
+                    "#REQUIRED" | "#IMPLIED" => (Some(mode), None),
+                    "#FIXED" => {
+                        self.require_space("after '#FIXED'")?;
+                        (Some(mode), Some(self.attribute_value()?))
+                    }
+                    _ => return Err(self.error(format!("unknown default '{}'", mode))),
+                }
+            } else {
+                (None, Some(self.attribute_value()?))
+            };
+            let key = (element.clone(), attribute.clone());
+            if self.skipped || self.attributes.contains(&key) {
+                continue;
+            }
+            self.attributes.insert(key);
+            if let Some(handler) = self.decl_handler {
+                handler.attribute_decl(
+                    &element,
+                    &attribute,
+                    &attribute_type,
+                    mode.as_deref(),
+                    value.as_deref(),
+                )?;
+            }
+        }
+    }
+
+    /// Read an attribute type, in the form reported by `DeclHandler::attribute_decl`.
+    fn attribute_type(&mut self) -> Result<String> {
+        let mut attribute_type = if self.top().peek() == Some('(') {
+            String::new()
+        } else {
+            let name = self.name()?;
+            if name != "NOTATION" {
+                if AttributeType::parse(&name).is_err() {
+                    return Err(self.error(format!("unknown attribute type '{}'", name)));
+                }
+                return Ok(name);
+            }
+            self.require_space("after 'NOTATION'")?;
+            "NOTATION ".to_owned()
+        };
+        self.expect('(')?;
+        attribute_type.push('(');
+        loop {
+            self.skip_space()?;
+            attribute_type.push_str(&self.nmtoken()?);
+            self.skip_space()?;
+            match self.top().peek() {
+                Some('|') => attribute_type.push('|'),
+                Some(')') => break,
+                _ => return Err(self.error("expected '|' or ')' in the attribute type")),
+            }
+            self.top_mut().advance();
+        }
+        self.top_mut().advance();
+        attribute_type.push(')');
+        if let Err(e) = AttributeType::parse(&attribute_type) {
+            return Err(self.error(e.message()));
+        }
+        Ok(attribute_type)
+    }
+
+    /// Read a default attribute value, and normalize it as a CDATA attribute value.
+    fn attribute_value(&mut self) -> Result<String> {
+        let literal = self.literal()?;
+        let mut value = String::new();
+        let mut open = Vec::new();
+        self.normalize(&literal, &mut value, &mut open)?;
+        Ok(value)
+    }
+
+    fn normalize(
+        &mut self,
+        literal: &str,
+        value: &mut String,
+        open: &mut Vec<String>,
+    ) -> Result<()> {
+        let mut rest = literal;
+        while let Some(c) = rest.chars().next() {
+            match c {
+                '<' => return Err(self.error("'<' is not allowed in an attribute value")),
+                '&' => {
+                    let end = match rest.find(';') {
+                        Some(end) => end,
+                        None => {
+                            return Err(self.error("unterminated reference in an attribute value"))
+                        }
+                    };
+                    let reference = &rest[1..end];
+                    rest = &rest[end + 1..];
+                    if reference.starts_with('#') {
+                        value.push(self.char_reference(reference)?);
+                        continue;
+                    }
+                    if let Some(c) = predefined(reference) {
+                        value.push(c);
+                        continue;
+                    }
+                    if !names::is_name(reference) {
+                        return Err(self.error(format!("invalid reference '&{};'", reference)));
+                    }
+                    match self.general_entities.get(reference).cloned() {
+                        Some(Entity::Internal(ref text)) => {
+                            if open.iter().any(|name| name == reference) {
+                                return Err(self
+                                    .error(format!("entity '&{};' references itself", reference)));
+                            }
+                            let location = self.location();
+                            self.guard.start_entity(false, Some(&location))?;
+                            self.guard
+                                .expanded(text.chars().count() as u64, Some(&location))?;
+                            open.push(reference.to_owned());
+                            let result = self.normalize(text, value, open);
+                            open.pop();
+                            self.guard.end_entity();
+                            result?;
+                        }
+                        Some(_) => {
+                            return Err(self.error(format!(
+                                "attribute values cannot reference the external entity '&{};'",
+                                reference
+                            )))
+                        }
+                        None if self.skipped => {
+                            value.push('&');
+                            value.push_str(reference);
+                            value.push(';');
+                        }
+                        None => {
+                            return Err(
+                                self.error(format!("entity '&{};' is not declared", reference))
+                            )
+                        }
+                    }
+                }
+                c if names::is_whitespace(c) => {
+                    value.push(' ');
+                    rest = &rest[1..];
+                }
+                c => {
+                    value.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the character of a character reference, given without `&` and `;`.
+    fn char_reference(&self, reference: &str) -> Result<char> {
+        let code = match reference.strip_prefix("#x") {
+            Some(hex) if !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+                u32::from_str_radix(hex, 16).ok()
+            }
+            Some(_) => None,
+            None => {
+                let decimal = &reference[1..];
+                if !decimal.is_empty() && decimal.chars().all(|c| c.is_ascii_digit()) {
+                    decimal.parse().ok()
+                } else {
+                    None
+                }
+            }
+        };
+        match code
+            .and_then(std::char::from_u32)
+            .filter(|&c| names::is_char(c))
+        {
+            Some(c) => Ok(c),
+            None => Err(self.error(format!("invalid character reference '&{};'", reference))),
+        }
+    }
+
+    fn entity_decl(&mut self) -> Result<()> {
+        self.top_mut().skip("<!ENTITY");
+        self.require_space("after '<!ENTITY'")?;
+        let parameter = self.top().peek() == Some('%');
+        if parameter {
+            self.top_mut().advance();
+            self.require_space("after '%'")?;
+        }
+        let name = self.name()?;
+        self.require_space("after the entity name")?;
+        let entity = match self.top().peek() {
+            Some('"') | Some('\'') => Entity::Internal(self.entity_value()?),
+            _ => {
+                let (public_id, system_id) = self.external_id(false)?;
+                let system_id = system_id.expect("a system identifier is required");
+                let space = self.skip_space()?;
+                if self.keyword("NDATA") {
+                    if parameter {
+                        return Err(self.error("parameter entities cannot be unparsed"));
+                    }
+                    if !space {
+                        return Err(self.error("white space is required before 'NDATA'"));
+                    }
+                    self.require_space("after 'NDATA'")?;
+                    let notation = self.name()?;
+                    self.end_decl("entity")?;
+                    if !self.skipped && !self.general_entities.contains_key(&name) {
+                        self.general_entities.insert(name.clone(), Entity::Unparsed);
+                        if let Some(handler) = self.dtd_handler {
+                            let system_id = self.report_uri(&system_id);
+                            handler.unparsed_entity_decl(
+                                &name,
+                                public_id.as_deref(),
+                                &system_id,
+                                &notation,
+                            )?;
+                        }
+                    }
+                    return Ok(());
+                }
+                Entity::External {
+                    public_id,
+                    system_id,
+                    base_uri: self.declaration_base(),
+                }
+            }
+        };
+        self.end_decl("entity")?;
+        let (reported, entities) = if parameter {
+            (format!("%{}", name), &mut self.parameter_entities)
+        } else {
+            (name.clone(), &mut self.general_entities)
+        };
+        if self.skipped || entities.contains_key(&name) {
+            return Ok(());
+        }
+        entities.insert(name, entity.clone());
+        if let Some(handler) = self.decl_handler {
+            match entity {
+                Entity::Internal(ref value) => handler.internal_entity_decl(&reported, value)?,
+                Entity::External {
+                    ref public_id,
+                    ref system_id,
+                    ..
+                } => {
+                    let system_id = self.report_uri(system_id);
+                    handler.external_entity_decl(&reported, public_id.as_deref(), &system_id)?
+                }
+                Entity::Unparsed => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the base URI of the declaration being read.
+    fn declaration_base(&self) -> Option<String> {
+        self.inputs
+            .iter()
+            .rev()
+            .find_map(|input| input.system_id.clone())
+            .or_else(|| self.base_uri.clone())
+    }
+
+    /// Return a system identifier as it is reported to the handlers.
+    fn report_uri(&self, system_id: &str) -> String {
+        match self.declaration_base() {
+            Some(ref base) if self.resolve_dtd_uris => uri::resolve(base, system_id),
+            _ => system_id.to_owned(),
+        }
+    }
+
+    /// Read an entity value, expanding parameter entity and character references.
+    fn entity_value(&mut self) -> Result<String> {
+        let quote = self.top().peek().expect("a quote");
+        self.top_mut().advance();
+        let depth = self.inputs.len();
+        let mut value = String::new();
+        loop {
+            let c = match self.top().peek() {
+                Some(c) => c,
+                None if self.inputs.len() > depth => {
+                    self.pop()?;
+                    continue;
+                }
+                None => return Err(self.error("unterminated entity value")),
+            };
+            if c == quote && self.inputs.len() == depth {
+                self.top_mut().advance();
+                return Ok(value);
+            }
+            match c {
+                '%' => {
+                    if !self.top().peek_at(1).is_some_and(names::is_name_start_char) {
+                        return Err(self.error("expected a name after '%'"));
+                    }
+                    if self.top().internal_subset {
+                        return Err(self.error(PE_IN_INTERNAL_SUBSET));
+                    }
+                    self.reference(true)?;
+                }
+                '&' => {
+                    let mut reference = String::new();
+                    self.top_mut().advance();
+                    while let Some(c) = self.top().peek().filter(|&c| c != ';') {
+                        reference.push(c);
+                        self.top_mut().advance();
+                    }
+                    self.expect(';')?;
+                    if reference.starts_with('#') {
+                        value.push(self.char_reference(&reference)?);
+                    } else if names::is_name(&reference) {
+                        // General entity references are bypassed (4.4.7).
+                        value.push('&');
+                        value.push_str(&reference);
+                        value.push(';');
+                    } else {
+                        return Err(self.error(format!("invalid reference '&{};'", reference)));
+                    }
+                }
+                c => {
+                    self.check_char(c)?;
+                    value.push(c);
+                    self.top_mut().advance();
+                }
+            }
+        }
+    }
+
+    /// Read an `ExternalID`, or a `PublicID` too if `public_only` is true, as in a notation
+    /// declaration.
+    fn external_id(&mut self, public_only: bool) -> Result<(Option<String>, Option<String>)> {
+        if self.keyword("SYSTEM") {
+            self.require_space("after 'SYSTEM'")?;
+            let system_id = self.system_literal()?;
+            Ok((None, Some(system_id)))
+        } else if self.keyword("PUBLIC") {
+            self.require_space("after 'PUBLIC'")?;
+            let public_id = self.literal()?;
+            if let Some(c) = public_id.chars().find(|&c| !is_pubid_char(c)) {
+                return Err(self.error(format!("'{}' is not allowed in a public identifier", c)));
+            }
+            let public_id = public_id.split_whitespace().collect::<Vec<_>>().join(" ");
+            let space = self.skip_space()?;
+            match self.top().peek() {
+                Some('"') | Some('\'') if space => {
+                    let system_id = self.system_literal()?;
+                    Ok((Some(public_id), Some(system_id)))
+                }
+                _ if public_only => Ok((Some(public_id), None)),
+                _ => Err(self.error("expected a system identifier after the public identifier")),
+            }
+        } else {
+            Err(self.error("expected 'SYSTEM' or 'PUBLIC'"))
+        }
+    }
+
+    fn system_literal(&mut self) -> Result<String> {
+        let system_id = self.literal()?;
+        if system_id.contains('#') {
+            return Err(self.error(format!(
+                "system identifier '{}' cannot have a fragment",
+                system_id
+            )));
+        }
+        Ok(system_id)
+    }
+
+    fn notation_decl(&mut self) -> Result<()> {
+        self.top_mut().skip("<!NOTATION");
+        self.require_space("after '<!NOTATION'")?;
+        let name = self.name()?;
+        self.require_space("after the notation name")?;
+        let (public_id, system_id) = self.external_id(true)?;
+        self.end_decl("notation")?;
+        if let Some(handler) = self.dtd_handler {
+            let system_id = system_id.map(|system_id| self.report_uri(&system_id));
+            handler.notation_decl(&name, public_id.as_deref(), system_id.as_deref())?;
+        }
+        Ok(())
+    }
+
+    fn conditional_section(&mut self) -> Result<()> {
+        if !self.inputs.iter().any(|input| input.external) {
+            return Err(self.error(CONDITIONAL_SECTION_OUTSIDE));
+        }
+        self.top_mut().skip("<![");
+        self.skip_space()?;
+        let include = if self.keyword("INCLUDE") {
+            true
+        } else if self.keyword("IGNORE") {
+            false
+        } else {
+            return Err(self.error("expected 'INCLUDE' or 'IGNORE'"));
+        };
+        self.skip_space()?;
+        self.expect('[')?;
+        if include {
+            self.includes += 1;
+            return Ok(());
+        }
+        let mut depth = 1;
+        while depth > 0 {
+            let top = self.top();
+            if top.starts_with("<![") {
+                self.top_mut().skip("<![");
+                depth += 1;
+            } else if top.starts_with("]]>") {
+                self.top_mut().skip("]]>");
+                depth -= 1;
+            } else if let Some(c) = top.peek() {
+                self.check_char(c)?;
+                self.top_mut().advance();
+            } else {
+                return Err(self.error("unterminated IGNORE section"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Return the character named by a predefined entity.
+fn predefined(name: &str) -> Option<char> {
+    match name {
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "amp" => Some('&'),
+        "apos" => Some('\''),
+        "quot" => Some('"'),
+        _ => None,
+    }
+}
+
+/// Return whether `c` matches the `PubidChar` production.
+fn is_pubid_char(c: char) -> bool {
+    matches!(c,
+        ' ' | '\r' | '\n' | 'a'..='z' | 'A'..='Z' | '0'..='9'
+        | '-' | '\'' | '(' | ')' | '+' | ',' | '.' | '/' | ':' | '=' | '?' | ';' | '!' | '*'
+        | '#' | '@' | '$' | '_' | '%'
+    )
+}
+
+/// Read an external entity as UTF-8, without its byte order mark.
+fn read_entity<R: Read>(mut input: R, system_id: &str) -> Result<String> {
+    let mut bytes = Vec::new();
+    if let Err(e) = input.read_to_end(&mut bytes) {
+        return Err(Box::new(ParseErrorImpl::new(
+            format!("cannot read {}: {}", system_id, e),
+            LocatorImpl::new(),
+        )));
+    }
+    match String::from_utf8(bytes) {
+        Ok(text) => Ok(text.trim_start_matches('\u{FEFF}').to_owned()),
+        Err(_) => Err(Box::new(ParseErrorImpl::new(
+            format!("{} is not encoded in UTF-8", system_id),
+            LocatorImpl::new(),
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dtd::DtdValidator;
+    use limits::Limit;
+    use std::cell::RefCell;
+
+    /// Records the declarations and lexical events it receives.
+    #[derive(Default)]
+    struct Recorder {
+        events: RefCell<Vec<String>>,
+    }
+
+    impl Recorder {
+        fn push(&self, event: String) -> Result<()> {
+            self.events.borrow_mut().push(event);
+            Ok(())
+        }
+
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut *self.events.borrow_mut())
+        }
+    }
+
+    impl DeclHandler for Recorder {
+        fn attribute_decl(
+            &self,
+            element_name: &str,
+            attribute_name: &str,
+            attribute_type: &str,
+            mode: Option<&str>,
+            value: Option<&str>,
+        ) -> Result<()> {
+            self.push(format!(
+                "attribute {} {} {} {:?} {:?}",
+                element_name, attribute_name, attribute_type, mode, value
+            ))
+        }
+        fn element_decl(&self, name: &str, model: &str) -> Result<()> {
+            self.push(format!("element {} {}", name, model))
+        }
+        fn external_entity_decl(
+            &self,
+            name: &str,
+            public_id: Option<&str>,
+            system_id: &str,
+        ) -> Result<()> {
+            self.push(format!("external {} {:?} {}", name, public_id, system_id))
+        }
+        fn internal_entity_decl(&self, name: &str, value: &str) -> Result<()> {
+            self.push(format!("internal {} {}", name, value))
+        }
+    }
+
+    impl DtdHandler for Recorder {
+        fn notation_decl(
+            &self,
+            name: &str,
+            public_id: Option<&str>,
+            system_id: Option<&str>,
+        ) -> Result<()> {
+            self.push(format!("notation {} {:?} {:?}", name, public_id, system_id))
+        }
+        fn unparsed_entity_decl(
+            &self,
+            name: &str,
+            public_id: Option<&str>,
+            system_id: &str,
+            notation_name: &str,
+        ) -> Result<()> {
+            self.push(format!(
+                "unparsed {} {:?} {} {}",
+                name, public_id, system_id, notation_name
+            ))
+        }
+    }
+
+    impl LexicalHandler for Recorder {
+        fn comment(&self, content: &str) -> Result<()> {
+            self.push(format!("comment {}", content))
+        }
+        fn start_dtd(
+            &self,
+            name: &str,
+            public_id: Option<&str>,
+            system_id: Option<&str>,
+        ) -> Result<()> {
+            self.push(format!(
+                "start_dtd {} {:?} {:?}",
+                name, public_id, system_id
+            ))
+        }
+        fn end_dtd(&self) -> Result<()> {
+            self.push("end_dtd".to_owned())
+        }
+        fn start_entity(&self, name: &str) -> Result<()> {
+            self.push(format!("start_entity {}", name))
+        }
+        fn end_entity(&self, name: &str) -> Result<()> {
+            self.push(format!("end_entity {}", name))
+        }
+    }
+
+    /// Serves entities from memory, by their absolute URI.
+    #[derive(Default)]
+    struct Resources {
+        entities: HashMap<String, String>,
+    }
+
+    impl Resources {
+        fn insert(&mut self, uri: &str, text: &str) {
+            self.entities.insert(uri.to_owned(), text.to_owned());
+        }
+    }
+
+    impl common::EntityResolver for Resources {}
+
+    impl EntityResolver2 for Resources {
+        fn resolve_entity_ext(
+            &self,
+            _name: Option<&str>,
+            _public_id: Option<&str>,
+            base_uri: Option<&str>,
+            system_id: &str,
+        ) -> Result<Option<Box<dyn Read>>> {
+            let uri = match base_uri {
+                Some(base_uri) => uri::resolve(base_uri, system_id),
+                None => system_id.to_owned(),
+            };
+            Ok(self
+                .entities
+                .get(&uri)
+                .map(|text| Box::new(std::io::Cursor::new(text.clone())) as Box<dyn Read>))
+        }
+    }
+
+    fn recording_parser(recorder: &Recorder) -> DtdParser<'_> {
+        let mut parser = DtdParser::new();
+        parser.set_decl_handler(recorder);
+        parser.set_dtd_handler(recorder);
+        parser.set_lexical_handler(recorder);
+        parser.set_base_uri("http://example.org/doc/doc.xml");
+        parser
+    }
+
+    fn message(result: Result<()>) -> String {
+        result.expect_err("an error").to_string()
+    }
+
+    #[test]
+    fn reports_declarations() {
+        let recorder = Recorder::default();
+        let mut parser = recording_parser(&recorder);
+        parser
+            .parse_internal_subset(
+                r#"
+                <!-- declarations -->
+                <!ELEMENT doc ( head , ( p | list )* )>
+                <!ELEMENT p (#PCDATA | em)*>
+                <!ELEMENT em ANY>
+                <!ATTLIST doc
+                    version CDATA #FIXED "1&#x2E;0"
+                    lang NMTOKEN #IMPLIED
+                    kind ( a | b ) "a"
+                    format NOTATION ( gif ) #REQUIRED>
+                <!ENTITY title "A &amp; B">
+                <!ENTITY title "ignored">
+                <!ATTLIST doc version CDATA "ignored" title CDATA "&title;&#9;&lt;x>">
+                <!ENTITY chapter PUBLIC "-//Example//Chapter  One//EN" "chapters/one.xml">
+                <!ENTITY logo SYSTEM "logo.gif" NDATA gif>
+                <!NOTATION gif PUBLIC "-//Example//GIF//EN">
+                <!NOTATION png SYSTEM "png.exe">
+                <?app ignored?>
+                "#,
+            )
+            .unwrap();
+        assert_eq!(
+            recorder.take(),
+            vec![
+                "comment  declarations ",
+                "element doc (head,(p|list)*)",
+                "element p (#PCDATA|em)*",
+                "element em ANY",
+                "attribute doc version CDATA Some(\"#FIXED\") Some(\"1.0\")",
+                "attribute doc lang NMTOKEN Some(\"#IMPLIED\") None",
+                "attribute doc kind (a|b) None Some(\"a\")",
+                "attribute doc format NOTATION (gif) Some(\"#REQUIRED\") None",
+                "internal title A &amp; B",
+                "attribute doc title CDATA None Some(\"A & B\\t<x>\")",
+                "external chapter Some(\"-//Example//Chapter One//EN\") \
+                 http://example.org/doc/chapters/one.xml",
+                "unparsed logo None http://example.org/doc/logo.gif gif",
+                "notation gif Some(\"-//Example//GIF//EN\") None",
+                "notation png None Some(\"http://example.org/doc/png.exe\")",
+            ]
+        );
+        assert_eq!(parser.get_internal_entity("title"), Some("A &amp; B"));
+        assert_eq!(
+            parser.get_external_entity("chapter"),
+            Some((
+                Some("-//Example//Chapter One//EN"),
+                "chapters/one.xml",
+                Some("http://example.org/doc/doc.xml")
+            ))
+        );
+        assert_eq!(parser.get_external_entity("logo"), None);
+    }
+
+    #[test]
+    fn system_identifiers_can_be_left_unresolved() {
+        let recorder = Recorder::default();
+        let mut parser = recording_parser(&recorder);
+        assert!(parser.set_feature(features::RESOLVE_DTD_URIS, false));
+        parser
+            .parse_internal_subset("<!ENTITY e SYSTEM 'e.xml'>")
+            .unwrap();
+        assert_eq!(recorder.take(), vec!["external e None e.xml"]);
+    }
+
+    #[test]
+    fn expands_parameter_entities_between_declarations() {
+        let recorder = Recorder::default();
+        let error = message(recording_parser(&recorder).parse_internal_subset(
+            "<!ENTITY % decls '<!ELEMENT a EMPTY><!ENTITY e \"&#37;\">'>%decls;",
+        ));
+        assert!(error.contains("expected a name after '%'"), "{}", error);
+        let recorder = Recorder::default();
+        let mut parser = recording_parser(&recorder);
+        parser
+            .parse_internal_subset(concat!(
+                "<!ENTITY % decls '<!ELEMENT a EMPTY><!ENTITY e \"&#38;#37;\">'>",
+                "%decls;<!ELEMENT b EMPTY>",
+            ))
+            .unwrap();
+        assert_eq!(
+            recorder.take(),
+            vec![
+                "internal %decls <!ELEMENT a EMPTY><!ENTITY e \"&#37;\">",
+                "start_entity %decls",
+                "element a EMPTY",
+                "internal e %",
+                "end_entity %decls",
+                "element b EMPTY",
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_the_external_subset() {
+        let mut resolver = Resources::default();
+        resolver.insert(
+            "http://example.org/dtd/doc.dtd",
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <!ENTITY % draft "INCLUDE">
+            <!ENTITY % final "IGNORE">
+            <!ENTITY % inline "em | code">
+            <!ENTITY % content "(#PCDATA | %inline;)*">
+            <!ELEMENT p %content;>
+            <!ATTLIST p id ID #IMPLIED>
+            <![%draft;[
+                <!ELEMENT note (#PCDATA)>
+                <![ IGNORE [ <!ELEMENT hidden EMPTY> <![INCLUDE[ ]]> ]]>
+            ]]>
+            <![%final;[ <!ELEMENT final EMPTY> ]]>
+            <!ENTITY % modules SYSTEM "modules.ent">
+            %modules;
+            <!ENTITY combined "%inline; &amp;">
+            "#,
+        );
+        resolver.insert(
+            "http://example.org/dtd/modules.ent",
+            "<!ENTITY chapter SYSTEM 'chapter.xml'>",
+        );
+        let recorder = Recorder::default();
+        let mut parser = recording_parser(&recorder);
+        parser.set_entity_resolver(&resolver);
+        parser
+            .parse_doctype(
+                "doc",
+                Some("-//Example//DTD Doc//EN"),
+                Some("../dtd/doc.dtd"),
+                Some("<!ATTLIST p id CDATA #IMPLIED>"),
+            )
+            .unwrap();
+        assert_eq!(
+            recorder.take(),
+            vec![
+                "start_dtd doc Some(\"-//Example//DTD Doc//EN\") Some(\"../dtd/doc.dtd\")",
+                "attribute p id CDATA Some(\"#IMPLIED\") None",
+                "start_entity [dtd]",
+                "internal %draft INCLUDE",
+                "internal %final IGNORE",
+                "internal %inline em | code",
+                "start_entity %inline",
+                "end_entity %inline",
+                "internal %content (#PCDATA | em | code)*",
+                "start_entity %content",
+                "end_entity %content",
+                "element p (#PCDATA|em|code)*",
+                "start_entity %draft",
+                "end_entity %draft",
+                "element note (#PCDATA)",
+                "start_entity %final",
+                "end_entity %final",
+                "external %modules None http://example.org/dtd/modules.ent",
+                "start_entity %modules",
+                "external chapter None http://example.org/dtd/chapter.xml",
+                "end_entity %modules",
+                "start_entity %inline",
+                "end_entity %inline",
+                "internal combined em | code &amp;",
+                "end_entity [dtd]",
+                "end_dtd",
+            ]
+        );
+    }
+
+    #[test]
+    fn asks_for_an_external_subset() {
+        struct Subset;
+
+        impl common::EntityResolver for Subset {}
+
+        impl EntityResolver2 for Subset {
+            fn get_external_subset(
+                &self,
+                name: &str,
+                _base_uri: Option<&str>,
+            ) -> Result<Option<Box<dyn Read>>> {
+                let subset = format!("<!ELEMENT {} EMPTY>", name);
+                Ok(Some(Box::new(std::io::Cursor::new(subset))))
+            }
+        }
+
+        let recorder = Recorder::default();
+        let mut parser = recording_parser(&recorder);
+        parser.set_entity_resolver(&Subset);
+        parser.parse_doctype("html", None, None, None).unwrap();
+        assert_eq!(
+            recorder.take(),
+            vec![
+                "start_dtd html None None",
+                "start_entity [dtd]",
+                "element html EMPTY",
+                "end_entity [dtd]",
+                "end_dtd",
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_processing_after_an_unread_parameter_entity() {
+        let recorder = Recorder::default();
+        let mut parser = recording_parser(&recorder);
+        assert!(parser.set_feature(features::EXTERNAL_PARAMETER_ENTITIES, false));
+        parser
+            .parse_doctype(
+                "doc",
+                None,
+                None,
+                Some(
+                    "<!ENTITY % ext SYSTEM 'ext.ent'> %ext; %undeclared;
+                     <!ELEMENT doc EMPTY>
+                     <!ENTITY e 'skipped'>
+                     <!ATTLIST doc a CDATA '&skipped;'>",
+                ),
+            )
+            .unwrap();
+        assert_eq!(
+            recorder.take(),
+            vec![
+                "start_dtd doc None None",
+                "external %ext None http://example.org/doc/ext.ent",
+                "element doc EMPTY",
+                "end_dtd",
+            ]
+        );
+    }
+
+    #[test]
+    fn loads_declarations_into_a_validator() {
+        let validator = DtdValidator::new();
+        let mut parser = DtdParser::new();
+        parser.set_decl_handler(&validator);
+        parser.set_dtd_handler(&validator);
+        parser.set_lexical_handler(&validator);
+        parser
+            .parse_external_subset(
+                "<!ELEMENT doc (a, b?)><!ATTLIST doc id ID #REQUIRED>",
+                Some("file:///doc.dtd"),
+            )
+            .unwrap();
+        assert!(validator.get_element_decl("doc").is_some());
+        assert!(validator.get_attribute_decl("doc", "id").is_some());
+        assert!(validator.take_errors().is_empty());
+    }
+
+    #[test]
+    fn reports_well_formedness_errors() {
+        let recorder = Recorder::default();
+        let errors = [
+            ("<!ELEMENT a %b;>", "within markup declarations"),
+            ("<!ENTITY a '%b;'>", "within markup declarations"),
+            ("%undeclared;", "'%undeclared;' is not declared"),
+            ("<!ENTITY % a '%a;'> %a;", "within markup declarations"),
+            ("<!ENTITY % a '%b;'>", "within markup declarations"),
+            ("<![INCLUDE[ ]]>", "conditional sections are only allowed"),
+            ("<!-- a -- b -->", "'--' is not allowed"),
+            ("<!ELEMENT a (b|c>", "expected ')'"),
+            ("<!ELEMENT a (#PCDATA|b)>", "must end with ')*'"),
+            (
+                "<!ATTLIST a b CHARS #IMPLIED>",
+                "unknown attribute type 'CHARS'",
+            ),
+            ("<!ATTLIST a b CDATA '<'>", "'<' is not allowed"),
+            ("<!ATTLIST a b CDATA '&e;'>", "'&e;' is not declared"),
+            ("<!ATTLIST a b CDATA '&#0;'>", "invalid character reference"),
+            ("<!ENTITY a SYSTEM 'a.xml#top'>", "cannot have a fragment"),
+            ("<!ENTITY a PUBLIC '{}' 'a.xml'>", "'{' is not allowed"),
+            (
+                "<!ENTITY % a SYSTEM 'a.gif' NDATA gif>",
+                "cannot be unparsed",
+            ),
+            (
+                "<!NOTATION a SYSTEM>",
+                "white space is required after 'SYSTEM'",
+            ),
+            ("<!NOTATION a SYSTEM >", "expected a quoted literal"),
+            ("<!ELEMENT a EMPTY", "unterminated element type declaration"),
+            (
+                "<!NOTATION a SYSTEM 'a' x>",
+                "expected '>' to end the notation declaration",
+            ),
+            (
+                "<?xml version='1.0'?>",
+                "not allowed as a processing instruction target",
+            ),
+            ("<!DOCTYPE a>", "expected a markup declaration"),
+            ("<!ELEMENT a EMPTY> ]]>", "expected a markup declaration"),
+            ("text", "unexpected 't'"),
+        ];
+        for &(subset, expected) in errors.iter() {
+            let error = message(recording_parser(&recorder).parse_internal_subset(subset));
+            assert!(error.contains(expected), "{}: {}", subset, error);
+        }
+        let error = message(
+            recording_parser(&recorder)
+                .parse_external_subset("<!ENTITY % a '&#37;a;'> <!ELEMENT b %a;>", None),
+        );
+        assert!(error.contains("'%a;' references itself"), "{}", error);
+        let error = message(recording_parser(&recorder).parse_external_subset("<![IGNORE[ ", None));
+        assert!(error.contains("unterminated IGNORE section"), "{}", error);
+        let error =
+            message(recording_parser(&recorder).parse_external_subset("<![INCLUDE[ ", None));
+        assert!(error.contains("unterminated INCLUDE section"), "{}", error);
+    }
+
+    #[test]
+    fn errors_carry_the_location() {
+        let recorder = Recorder::default();
+        let error = recording_parser(&recorder)
+            .parse_internal_subset("<!ELEMENT a EMPTY>\n  <!ELEMENT>")
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "http://example.org/doc/doc.xml:2:12: white space is required after '<!ELEMENT'"
+        );
+    }
+
+    #[test]
+    fn external_entities_must_be_available() {
+        let resolver = Resources::default();
+        let recorder = Recorder::default();
+        let mut parser = recording_parser(&recorder);
+        parser.set_entity_resolver(&resolver);
+        let error = message(parser.parse_doctype("doc", None, Some("missing.dtd"), None));
+        assert!(
+            error.contains("cannot open http://example.org/doc/missing.dtd"),
+            "{}",
+            error
+        );
+        let mut parser = DtdParser::new();
+        let error =
+            message(parser.parse_doctype("doc", None, Some("http://example.org/a.dtd"), None));
+        assert!(
+            error.contains("cannot open http://example.org/a.dtd"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn limits_entity_expansion() {
+        let mut subset = String::from("<!ENTITY a0 'lol'>");
+        for i in 1..10 {
+            let previous = format!("&a{};", i - 1);
+            subset.push_str(&format!("<!ENTITY a{} '{}'>", i, previous.repeat(10)));
+        }
+        subset.push_str("<!ATTLIST doc a CDATA '&a9;'>");
+        let mut limits = Limits::new();
+        limits.set(Limit::EntityExpansions, 1000);
+        let recorder = Recorder::default();
+        let mut parser = recording_parser(&recorder);
+        parser.set_limits(&limits);
+        let error = parser.parse_internal_subset(&subset).err().unwrap();
+        assert!(
+            error.to_string().contains("[entity-expansions]"),
+            "{}",
+            error
+        );
+        let mut limits = Limits::new();
+        limits.set(Limit::EntityDepth, 2);
+        let mut parser = DtdParser::new();
+        parser.set_limits(&limits);
+        let error = parser
+            .parse_external_subset(
+                concat!(
+                    "<!ENTITY % a '<!ELEMENT a EMPTY>'><!ENTITY % b '&#37;a;'>",
+                    "<!ENTITY % c '&#37;b;'>%c;",
+                ),
+                None,
+            )
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("[entity-depth]"), "{}", error);
+    }
+}
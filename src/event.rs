@@ -0,0 +1,291 @@
+//! Owned representations of SAX2 content events.
+//!
+//! The callback interfaces in `sax2` borrow their arguments for the duration of each call.
+//! Consumers that need to hold on to events after the callback returns (pull-style streams,
+//! buffering of subtrees, tests) can use `Event` to take an owned copy and `EventRecorder` to
+//! collect them.
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+use common::Locator;
+use helpers::AttributesImpl;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::Result;
+
+/// A single owned `ContentHandler` event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// See `ContentHandler::start_document`.
+    StartDocument,
+    /// See `ContentHandler::end_document`.
+    EndDocument,
+    /// See `ContentHandler::start_prefix_mapping`.
+    StartPrefixMapping { prefix: String, uri: String },
+    /// See `ContentHandler::end_prefix_mapping`.
+    EndPrefixMapping { prefix: String },
+    /// See `ContentHandler::start_element`.
+    StartElement {
+        uri: String,
+        local_name: String,
+        q_name: String,
+        attributes: AttributesImpl,
+    },
+    /// See `ContentHandler::end_element`.
+    EndElement {
+        uri: String,
+        local_name: String,
+        q_name: String,
+    },
+    /// See `ContentHandler::characters`.
+    Characters(String),
+    /// See `ContentHandler::ignorable_whitespace`.
+    IgnorableWhitespace(String),
+    /// See `ContentHandler::processing_instruction`.
+    ProcessingInstruction { target: String, data: String },
+    /// See `ContentHandler::skipped_entity`.
+    SkippedEntity(String),
+}
+
+impl Event {
+    /// Build a `StartElement` event, taking a snapshot of the attribute list.
+    pub fn start_element<A: Attributes>(
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attributes: &A,
+    ) -> Self {
+        Event::StartElement {
+            uri: uri.to_owned(),
+            local_name: local_name.to_owned(),
+            q_name: q_name.to_owned(),
+            attributes: AttributesImpl::from_attributes(attributes),
+        }
+    }
+
+    /// Build an `EndElement` event.
+    pub fn end_element(uri: &str, local_name: &str, q_name: &str) -> Self {
+        Event::EndElement {
+            uri: uri.to_owned(),
+            local_name: local_name.to_owned(),
+            q_name: q_name.to_owned(),
+        }
+    }
+
+    /// Replay this event into a content handler.
+    pub fn dispatch<L, H>(&self, handler: &H) -> Result<()>
+    where
+        L: Locator,
+        H: ContentHandler<L, AttributesImpl> + ?Sized,
+    {
+        match *self {
+            Event::StartDocument => handler.start_document(),
+            Event::EndDocument => handler.end_document(),
+            Event::StartPrefixMapping {
+                ref prefix,
+                ref uri,
+            } => handler.start_prefix_mapping(prefix, uri),
+            Event::EndPrefixMapping { ref prefix } => handler.end_prefix_mapping(prefix),
+            Event::StartElement {
+                ref uri,
+                ref local_name,
+                ref q_name,
+                ref attributes,
+            } => handler.start_element(uri, local_name, q_name, attributes.clone()),
+            Event::EndElement {
+                ref uri,
+                ref local_name,
+                ref q_name,
+            } => handler.end_element(uri, local_name, q_name),
+            Event::Characters(ref content) => handler.characters(content),
+            Event::IgnorableWhitespace(ref content) => handler.ignorable_whitespace(content),
+            Event::ProcessingInstruction {
+                ref target,
+                ref data,
+            } => handler.processing_instruction(target, data),
+            Event::SkippedEntity(ref name) => handler.skipped_entity(name),
+        }
+    }
+}
+
+/// A `ContentHandler` that records every event it receives.
+///
+/// This is mostly useful for tests and for readers that produce event streams on top of a
+/// callback-driven parser. The events are kept behind a mutex, so a recorder can be shared
+/// with a reader that runs on another thread.
+pub struct EventRecorder<L: Locator> {
+    events: Mutex<Vec<Event>>,
+    locator: PhantomData<L>,
+}
+
+impl<L: Locator> EventRecorder<L> {
+    /// Construct a new, empty recorder.
+    pub fn new() -> Self {
+        EventRecorder {
+            events: Mutex::new(Vec::new()),
+            locator: PhantomData,
+        }
+    }
+
+    /// Return a copy of the events recorded so far.
+    pub fn events(&self) -> Vec<Event> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// Remove and return the events recorded so far.
+    pub fn take_events(&self) -> Vec<Event> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+
+    fn push(&self, event: Event) -> Result<()> {
+        self.events.lock().unwrap().push(event);
+        Ok(())
+    }
+}
+
+impl<L: Locator> Default for EventRecorder<L> {
+    fn default() -> Self {
+        EventRecorder::new()
+    }
+}
+
+impl<L: Locator, A: Attributes> ContentHandler<L, A> for EventRecorder<L> {
+    fn characters(&self, content: &str) -> Result<()> {
+        self.push(Event::Characters(content.to_owned()))
+    }
+    fn end_document(&self) -> Result<()> {
+        self.push(Event::EndDocument)
+    }
+    fn end_element(&self, uri: &str, local_name: &str, q_name: &str) -> Result<()> {
+        self.push(Event::end_element(uri, local_name, q_name))
+    }
+    fn end_prefix_mapping(&self, prefix: &str) -> Result<()> {
+        self.push(Event::EndPrefixMapping {
+            prefix: prefix.to_owned(),
+        })
+    }
+    fn ignorable_whitespace(&self, content: &str) -> Result<()> {
+        self.push(Event::IgnorableWhitespace(content.to_owned()))
+    }
+    fn processing_instruction(&self, target: &str, data: &str) -> Result<()> {
+        self.push(Event::ProcessingInstruction {
+            target: target.to_owned(),
+            data: data.to_owned(),
+        })
+    }
+    fn skipped_entity(&self, name: &str) -> Result<()> {
+        self.push(Event::SkippedEntity(name.to_owned()))
+    }
+    fn start_document(&self) -> Result<()> {
+        self.push(Event::StartDocument)
+    }
+    fn start_element(
+        &self,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attributes: A,
+    ) -> Result<()> {
+        self.push(Event::start_element(uri, local_name, q_name, &attributes))
+    }
+    fn start_prefix_mapping(&self, prefix: &str, uri: &str) -> Result<()> {
+        self.push(Event::StartPrefixMapping {
+            prefix: prefix.to_owned(),
+            uri: uri.to_owned(),
+        })
+    }
+}
+
+/// Return the events a Namespace-aware reader reports for a small document: elements,
+/// attributes, character data and the predefined entity references only. Attributes named `id`
+/// are reported with type `ID`.
+#[cfg(test)]
+pub(crate) fn markup_events(markup: &str) -> Vec<Event> {
+    use helpers::NamespaceSupport;
+
+    fn unescape(text: &str) -> String {
+        text.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&")
+    }
+
+    fn end(namespaces: &mut NamespaceSupport, events: &mut Vec<Event>, name: &str) {
+        let (uri, local_name, q_name) = namespaces.process_name(name, false).unwrap();
+        events.push(Event::end_element(&uri, &local_name, &q_name));
+        for prefix in namespaces.get_declared_prefixes() {
+            events.push(Event::EndPrefixMapping { prefix });
+        }
+        namespaces.pop_context();
+    }
+
+    let mut events = vec![Event::StartDocument];
+    let mut namespaces = NamespaceSupport::new();
+    let mut rest = markup;
+    while !rest.is_empty() {
+        if let Some(tag) = rest.strip_prefix("</") {
+            let close = tag.find('>').unwrap();
+            end(&mut namespaces, &mut events, tag[..close].trim());
+            rest = &tag[close + 1..];
+        } else if let Some(tag) = rest.strip_prefix('<') {
+            let name_end = tag
+                .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+                .unwrap();
+            let name = &tag[..name_end];
+            let mut tail = &tag[name_end..];
+            let mut pairs = Vec::new();
+            let empty = loop {
+                tail = tail.trim_start();
+                if let Some(after) = tail.strip_prefix("/>") {
+                    tail = after;
+                    break true;
+                }
+                if let Some(after) = tail.strip_prefix('>') {
+                    tail = after;
+                    break false;
+                }
+                let equals = tail.find('=').unwrap();
+                let value = tail[equals + 1..].trim_start();
+                let quote = value.chars().next().unwrap();
+                let close = value[1..].find(quote).unwrap() + 1;
+                pairs.push((tail[..equals].trim(), unescape(&value[1..close])));
+                tail = &value[close + 1..];
+            };
+            namespaces.push_context();
+            for (name, value) in &pairs {
+                if *name == "xmlns" || name.starts_with("xmlns:") {
+                    let prefix = name.trim_start_matches("xmlns").trim_start_matches(':');
+                    namespaces.declare_prefix(prefix, value);
+                    events.push(Event::StartPrefixMapping {
+                        prefix: prefix.to_owned(),
+                        uri: value.clone(),
+                    });
+                }
+            }
+            let mut attributes = AttributesImpl::new();
+            for (name, value) in &pairs {
+                let (uri, local_name, q_name) = namespaces.process_name(name, true).unwrap();
+                let attr_type = if *name == "id" { "ID" } else { "CDATA" };
+                attributes.add_attribute(&uri, &local_name, &q_name, attr_type, value);
+            }
+            let (uri, local_name, q_name) = namespaces.process_name(name, false).unwrap();
+            events.push(Event::start_element(
+                &uri,
+                &local_name,
+                &q_name,
+                &attributes,
+            ));
+            if empty {
+                end(&mut namespaces, &mut events, name);
+            }
+            rest = tail;
+        } else {
+            let close = rest.find('<').unwrap_or(rest.len());
+            events.push(Event::Characters(unescape(&rest[..close])));
+            rest = &rest[close..];
+        }
+    }
+    events.push(Event::EndDocument);
+    events
+}
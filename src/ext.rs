@@ -0,0 +1,306 @@
+//! Optional SAX2 extension interfaces.
+//!
+//! These interfaces expose information that the core interfaces in `sax2` do not: the
+//! declarations of the DTD, lexical details such as comments, CDATA sections and entity
+//! boundaries, and richer entity resolution. Readers are not required to support them.
+//!
+//! The SAX2 API definitions from which these interfaces were derived comes with
+//! the following notice:
+//!
+//! > This module, both source code and documentation, is in the Public Domain,
+//! > and comes with NO WARRANTY. See http://www.saxproject.org for further
+//! > information.
+//!
+//! See also http://www.saxproject.org/copying.html
+use common::EntityResolver;
+use common::Locator;
+use sax2::Result;
+
+/// Receives notification of DTD declaration events.
+///
+/// This is an optional extension handler for SAX2 to provide more complete information about
+/// DTD declarations in an XML document. XML readers are not required to recognize this handler,
+/// and it is not part of core-only SAX2 distributions.
+///
+/// Note that data-related DTD declarations (unparsed entities and notations) are already
+/// reported through the `DtdHandler` interface.
+///
+/// If you are using the declaration handler together with a lexical handler, all of the events
+/// will occur between the `start_dtd` and the `end_dtd` events.
+///
+/// Declarations are reported in the order the reader reads them, after parameter entity
+/// references have been expanded. Declarations from the internal subset are reported before
+/// those from the external subset, and declarations inside an IGNORE conditional section are
+/// not reported at all.
+///
+/// To set the `DeclHandler` for an XML reader, use `XmlReaderExt::set_decl_handler`.
+///
+/// Modelled after `org.xml.sax.ext.DeclHandler`
+pub trait DeclHandler {
+    /// Report an attribute type declaration.
+    ///
+    /// Only the effective (first) declaration for an attribute will be reported. The type will
+    /// be one of the strings "CDATA", "ID", "IDREF", "IDREFS", "NMTOKEN", "NMTOKENS", "ENTITY",
+    /// "ENTITIES", a parenthesized token group with the separator "|" and all whitespace
+    /// removed, or the word "NOTATION" followed by a space followed by a parenthesized token
+    /// group with all whitespace removed.
+    ///
+    /// The value will be the value as reported to applications, appropriately normalized and
+    /// with entity and character references expanded.
+    ///
+    /// `mode` is one of "#IMPLIED", "#REQUIRED" or "#FIXED", or `None` if none of these
+    /// applies. `value` is the default value, or `None` if there is none.
+    #[allow(unused_variables)]
+    fn attribute_decl(
+        &self,
+        element_name: &str,
+        attribute_name: &str,
+        attribute_type: &str,
+        mode: Option<&str>,
+        value: Option<&str>,
+    ) -> Result<()> {
+        Ok(())
+    }
+    /// Report an element type declaration.
+    ///
+    /// The content model will consist of the string "EMPTY", the string "ANY", or a
+    /// parenthesised group, optionally followed by an occurrence indicator. The model will be
+    /// normalized so that all parameter entities are fully resolved and all whitespace is
+    /// removed, and will include the enclosing parentheses. Other normalization (such as
+    /// removing redundant parentheses or simplifying occurrence indicators) is at the
+    /// discretion of the parser.
+    #[allow(unused_variables)]
+    fn element_decl(&self, name: &str, model: &str) -> Result<()> {
+        Ok(())
+    }
+    /// Report a parsed external entity declaration.
+    ///
+    /// Only the effective (first) declaration for each entity will be reported.
+    ///
+    /// If the system identifier is a URL, the parser must resolve it fully before passing it to
+    /// the application.
+    ///
+    /// The name of a parameter entity will begin with '%'.
+    #[allow(unused_variables)]
+    fn external_entity_decl(
+        &self,
+        name: &str,
+        public_id: Option<&str>,
+        system_id: &str,
+    ) -> Result<()> {
+        Ok(())
+    }
+    /// Report an internal entity declaration.
+    ///
+    /// Only the effective (first) declaration for each entity will be reported. All parameter
+    /// entities in the value will be expanded, but general entities will not.
+    ///
+    /// The name of a parameter entity will begin with '%'.
+    #[allow(unused_variables)]
+    fn internal_entity_decl(&self, name: &str, value: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Receives notification of lexical events.
+///
+/// This is an optional extension handler for SAX2 to provide lexical information about an XML
+/// document, such as comments and CDATA section boundaries. XML readers are not required to
+/// recognize this handler, and it is not part of core-only SAX2 distributions.
+///
+/// The events in the lexical handler apply to the entire document, not just to the document
+/// element, and all lexical handler events must appear between the content handler's
+/// `start_document` and `end_document` events.
+///
+/// To set the `LexicalHandler` for an XML reader, use `XmlReaderExt::set_lexical_handler`.
+///
+/// Modelled after `org.xml.sax.ext.LexicalHandler`
+pub trait LexicalHandler {
+    /// Report an XML comment anywhere in the document.
+    ///
+    /// This callback will be used for comments inside or outside the document element,
+    /// including comments in the external DTD subset (if read). Comments in the DTD must be
+    /// properly nested inside `start_dtd`/`end_dtd` and `start_entity`/`end_entity` events (if
+    /// used).
+    #[allow(unused_variables)]
+    fn comment(&self, content: &str) -> Result<()> {
+        Ok(())
+    }
+    /// Report the end of a CDATA section.
+    fn end_cdata(&self) -> Result<()> {
+        Ok(())
+    }
+    /// Report the end of DTD declarations.
+    ///
+    /// This method is intended to report the end of the DOCTYPE declaration; if the document
+    /// has no DOCTYPE declaration, this method will not be invoked.
+    fn end_dtd(&self) -> Result<()> {
+        Ok(())
+    }
+    /// Report the end of an entity.
+    ///
+    /// See `start_entity` for the naming of entities.
+    #[allow(unused_variables)]
+    fn end_entity(&self, name: &str) -> Result<()> {
+        Ok(())
+    }
+    /// Report the start of a CDATA section.
+    ///
+    /// The contents of the CDATA section will be reported through the regular
+    /// `ContentHandler::characters` event; this event is intended only to report the boundary.
+    fn start_cdata(&self) -> Result<()> {
+        Ok(())
+    }
+    /// Report the start of DTD declarations, if any.
+    ///
+    /// This method is intended to report the beginning of the DOCTYPE declaration; if the
+    /// document has no DOCTYPE declaration, this method will not be invoked.
+    ///
+    /// All declarations reported through `DtdHandler` or `DeclHandler` events must appear
+    /// between the `start_dtd` and `end_dtd` events. Declarations are assumed to belong to the
+    /// internal DTD subset unless they appear between `start_entity` and `end_entity` events.
+    /// Comments and processing instructions from the DTD should also be reported between the
+    /// `start_dtd` and `end_dtd` events, in their original order of (logical) occurrence; they
+    /// are not required to appear in their correct locations relative to `DtdHandler` or
+    /// `DeclHandler` events, however.
+    ///
+    /// Note that the `start_dtd`/`end_dtd` events will appear within the
+    /// `start_document`/`end_document` events from `ContentHandler` and before the first
+    /// `start_element` event.
+    #[allow(unused_variables)]
+    fn start_dtd(
+        &self,
+        name: &str,
+        public_id: Option<&str>,
+        system_id: Option<&str>,
+    ) -> Result<()> {
+        Ok(())
+    }
+    /// Report the beginning of some internal and external XML entities.
+    ///
+    /// The reporting of parameter entities (including the external DTD subset) is optional, and
+    /// SAX2 drivers that report `LexicalHandler` events may not implement it; you can use the
+    /// http://xml.org/sax/features/lexical-handler/parameter-entities feature to query or
+    /// control the reporting of parameter entities.
+    ///
+    /// General entities are reported with their regular names, parameter entities have '%'
+    /// prepended to their names, and the external DTD subset has the pseudo-entity name
+    /// "[dtd]".
+    ///
+    /// When a SAX2 driver is providing these events, all other events must be properly nested
+    /// within start/end entity events. There is no additional requirement that events from
+    /// `DeclHandler` or `DtdHandler` be properly ordered.
+    ///
+    /// Note that skipped entities will be reported through the `ContentHandler::skipped_entity`
+    /// event.
+    #[allow(unused_variables)]
+    fn start_entity(&self, name: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Extended interface for mapping external entity references to input sources, or providing a
+/// missing external subset.
+///
+/// If a reader supports this interface and the
+/// http://xml.org/sax/features/use-entity-resolver2 feature is true (the default), it calls
+/// these methods instead of `EntityResolver::resolve_entity`, for the external DTD subset,
+/// external parameter entities and external general entities alike.
+///
+/// Modelled after `org.xml.sax.ext.EntityResolver2`
+pub trait EntityResolver2: EntityResolver {
+    /// Allows applications to provide an external subset for documents that don't explicitly
+    /// define one.
+    ///
+    /// Documents with DOCTYPE declarations that omit an external subset can thus augment the
+    /// declarations available for validation, entity processing, and attribute processing
+    /// (normalization, defaulting, and reporting types including ID). This augmentation is
+    /// reported through the `start_dtd` method as if the document text had originally included
+    /// the external subset; this callback is made before any internal subset data or errors are
+    /// reported.
+    ///
+    /// This method can also be used with documents that have no DOCTYPE declaration. When the
+    /// root element is encountered, but no DOCTYPE declaration has been seen, this method is
+    /// invoked. If it returns a value for the external subset, that root element is declared
+    /// to be the root element, giving the effect of splicing a DOCTYPE declaration at the end
+    /// of the prolog of a document that could not otherwise be valid.
+    ///
+    /// `base_uri` is the document's base URI, serving as an additional hint for selecting the
+    /// external subset.
+    #[allow(unused_variables)]
+    fn get_external_subset(
+        &self,
+        name: &str,
+        base_uri: Option<&str>,
+    ) -> Result<Option<Box<dyn std::io::Read>>> {
+        Ok(None)
+    }
+    /// Allows applications to map references to external entities into input sources, or tell
+    /// the parser it should use conventional URI resolution.
+    ///
+    /// `name` identifies the external entity being resolved: "[dtd]" for the external subset,
+    /// or a name starting with "%" to indicate a parameter entity, or `None` for a reference
+    /// that isn't to a named entity. `base_uri` is the URI with respect to which relative
+    /// system identifiers are interpreted; this is always an absolute URI, unless it is `None`
+    /// (likely because the reader was given an input source without a system identifier).
+    /// `system_id` is the system identifier as written in the declaration, which may be a
+    /// relative URI.
+    ///
+    /// Returning `Ok(None)` requests the reader to resolve the system identifier against the
+    /// base URI and open a connection to the resulting URI.
+    ///
+    /// The default implementation delegates to `EntityResolver::resolve_entity` with the
+    /// system identifier as written.
+    #[allow(unused_variables)]
+    fn resolve_entity_ext(
+        &self,
+        name: Option<&str>,
+        public_id: Option<&str>,
+        base_uri: Option<&str>,
+        system_id: &str,
+    ) -> Result<Option<Box<dyn std::io::Read>>> {
+        self.resolve_entity(public_id, system_id)
+    }
+}
+
+/// Extension of `Locator` reporting the XML version and character encoding of the current
+/// entity.
+///
+/// If a reader supports this interface, the http://xml.org/sax/features/use-locator2 feature is
+/// true, and the locator it passes to `ContentHandler::set_document_locator` implements it.
+/// The values are available from the `start_document` event, and change along with the system
+/// identifier when external entities are read.
+///
+/// Modelled after `org.xml.sax.ext.Locator2`
+pub trait Locator2: Locator {
+    /// Return the version of XML used for the entity: "1.0" or "1.1", as given by its XML or
+    /// text declaration, "1.0" by default. Returns `None` if the version is not known yet.
+    fn get_xml_version(&self) -> Option<String>;
+    /// Return the name of the character encoding of the entity, as detected or declared, or
+    /// `None` if it is not known, for instance when the entity was read as characters.
+    fn get_encoding(&self) -> Option<String>;
+}
+
+/// Interface for readers that can report DTD declarations and lexical events.
+///
+/// Readers that process DTDs implement this alongside `XmlReader`. The declaration and lexical
+/// handlers are registered through dedicated methods rather than through the
+/// http://xml.org/sax/properties/declaration-handler and
+/// http://xml.org/sax/properties/lexical-handler properties, since properties are exchanged as
+/// strings.
+pub trait XmlReaderExt<DeH: DeclHandler, LeH: LexicalHandler> {
+    /// Return the current declaration handler.
+    fn get_decl_handler(&self) -> Option<&DeH>;
+    /// Return the current lexical handler.
+    fn get_lexical_handler(&self) -> Option<&LeH>;
+    /// Allow an application to register a DTD declaration handler.
+    ///
+    /// If the application does not register a declaration handler, all declaration events
+    /// reported by the SAX parser will be silently ignored.
+    fn set_decl_handler(&self, handler: DeH);
+    /// Allow an application to register a lexical handler.
+    ///
+    /// If the application does not register a lexical handler, all lexical events reported by
+    /// the SAX parser will be silently ignored.
+    fn set_lexical_handler(&self, handler: LeH);
+}
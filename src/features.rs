@@ -0,0 +1,69 @@
+//! Names of the standard SAX2 feature flags and properties.
+//!
+//! These are the fully-qualified URIs accepted by `XmlReader::get_feature`,
+//! `XmlReader::set_feature`, `XmlReader::get_property_str` and `XmlReader::set_property_str`.
+//! Readers are only required to recognize `NAMESPACES` and `NAMESPACE_PREFIXES`; support for the
+//! others is optional.
+//!
+//! For documentation on Core Features and Properties, see
+//! https://svn.apache.org/repos/asf/xerces/xml-commons/tags/sax-2_0_1/java/external/xdocs/sax/features.html
+
+/// Perform Namespace processing (default true).
+pub const NAMESPACES: &str = "http://xml.org/sax/features/namespaces";
+
+/// Report the original prefixed names and attributes used for Namespace declarations (default
+/// false).
+pub const NAMESPACE_PREFIXES: &str = "http://xml.org/sax/features/namespace-prefixes";
+
+/// Report Namespace declaration attributes in the `helpers::NSDECL` Namespace, as later
+/// revisions of Namespaces in XML do, rather than in no Namespace (default false).
+pub const XMLNS_URIS: &str = "http://xml.org/sax/features/xmlns-uris";
+
+/// Include all external general (text) entities.
+pub const EXTERNAL_GENERAL_ENTITIES: &str = "http://xml.org/sax/features/external-general-entities";
+
+/// Include all external parameter entities, including the external DTD subset.
+pub const EXTERNAL_PARAMETER_ENTITIES: &str =
+    "http://xml.org/sax/features/external-parameter-entities";
+
+/// Report the beginning and end of parameter entities to a registered `LexicalHandler`.
+pub const LEXICAL_HANDLER_PARAMETER_ENTITIES: &str =
+    "http://xml.org/sax/features/lexical-handler/parameter-entities";
+
+/// Resolve system identifiers in declarations before reporting them through `DtdHandler` and
+/// `DeclHandler` (default true).
+pub const RESOLVE_DTD_URIS: &str = "http://xml.org/sax/features/resolve-dtd-uris";
+
+/// Use the `EntityResolver2` methods of a registered entity resolver that implements them
+/// (default true).
+pub const USE_ENTITY_RESOLVER2: &str = "http://xml.org/sax/features/use-entity-resolver2";
+
+/// Read-only during a parse: whether the document declared `standalone="yes"`.
+pub const IS_STANDALONE: &str = "http://xml.org/sax/features/is-standalone";
+
+/// Read-only: whether the reader supports XML 1.1 and Namespaces in XML 1.1, as well as
+/// XML 1.0.
+pub const XML_1_1: &str = "http://xml.org/sax/features/xml-1.1";
+
+/// Read-only: whether the locator passed to `ContentHandler::set_document_locator`
+/// implements `ext::Locator2`.
+pub const USE_LOCATOR2: &str = "http://xml.org/sax/features/use-locator2";
+
+/// Report text, attribute values and names that are not fully normalized, as defined by
+/// Appendix B of XML 1.1, through `ErrorHandler::error` (default false).
+pub const UNICODE_NORMALIZATION_CHECKING: &str =
+    "http://xml.org/sax/features/unicode-normalization-checking";
+
+/// Property under which a `DeclHandler` may be registered.
+pub const DECLARATION_HANDLER: &str = "http://xml.org/sax/properties/declaration-handler";
+
+/// Property under which a `LexicalHandler` may be registered.
+pub const LEXICAL_HANDLER: &str = "http://xml.org/sax/properties/lexical-handler";
+
+/// Report validity errors, as defined by the XML recommendation, through
+/// `ErrorHandler::error` (default false).
+///
+/// When true, the reader also reports whitespace in element content through
+/// `ContentHandler::ignorable_whitespace`, and all external entities are read regardless of the
+/// `EXTERNAL_GENERAL_ENTITIES` and `EXTERNAL_PARAMETER_ENTITIES` features.
+pub const VALIDATION: &str = "http://xml.org/sax/features/validation";
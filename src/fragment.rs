@@ -0,0 +1,78 @@
+//! Reader modes for input that is not a single well-formed document.
+//!
+//! Log files and message queues often carry bare fragments without a single root element, or
+//! several complete documents back to back. `FragmentXmlReader` parses the former as an external
+//! parsed entity, and `DocumentSequenceXmlReader` parses the latter as a series of documents.
+use common::EntityResolver;
+use common::InputSource;
+use common::Locator;
+use helpers::NamespaceSupport;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::DtdHandler;
+use sax2::ErrorHandler;
+use sax2::ParseError;
+use sax2::Result;
+use sax2::XmlReader;
+
+/// Interface for a reader that can parse an XML fragment.
+///
+/// A fragment is parsed according to the `extParsedEnt` production of the XML recommendation:
+/// an optional text declaration followed by element content. Any number of top-level elements,
+/// character data, comments and processing instructions may appear, but a document type
+/// declaration may not.
+pub trait FragmentXmlReader<
+    CH: ContentHandler<L, A>,
+    DH: DtdHandler,
+    ER: EntityResolver,
+    EH: ErrorHandler<E>,
+    L: Locator,
+    A: Attributes,
+    E: ParseError,
+    I: InputSource,
+>: XmlReader<CH, DH, ER, EH, L, A, E, I>
+{
+    /// Parse an XML fragment.
+    ///
+    /// The fragment is parsed as if it were the content of an element in whose scope the
+    /// Namespace declarations of `context` are in force, so prefixes declared there may be used
+    /// without being declared in the fragment itself. No `start_prefix_mapping` or
+    /// `end_prefix_mapping` events are reported for the declarations of `context`, and the
+    /// context object is not modified.
+    ///
+    /// The fragment is reported between a `start_document` and an `end_document` event, like a
+    /// complete document. Character data outside of any element is reported through
+    /// `ContentHandler::characters`.
+    ///
+    /// In all other respects, this method behaves like `XmlReader::parse`.
+    fn parse_fragment(&self, input: &mut I, context: &NamespaceSupport) -> Result<()>;
+}
+
+/// Interface for a reader that can parse a sequence of concatenated XML documents.
+pub trait DocumentSequenceXmlReader<
+    CH: ContentHandler<L, A>,
+    DH: DtdHandler,
+    ER: EntityResolver,
+    EH: ErrorHandler<E>,
+    L: Locator,
+    A: Attributes,
+    E: ParseError,
+    I: InputSource,
+>: XmlReader<CH, DH, ER, EH, L, A, E, I>
+{
+    /// Parse every document in an input source, one after another.
+    ///
+    /// Each document is reported between its own `start_document` and `end_document` events,
+    /// with a fresh DTD and Namespace context, exactly as if it had been parsed on its own.
+    ///
+    /// A new document begins at an XML declaration, a document type declaration or an element
+    /// start tag that follows the root element of the previous document. Whitespace, comments
+    /// and processing instructions after a root element belong to the document of that root
+    /// element, unless an XML declaration follows them. Line and column numbers reported by the
+    /// locator continue to count from the start of the input source.
+    ///
+    /// Parsing stops at the end of input, or at the first fatal error; documents already
+    /// completed are unaffected by an error in a later one. Returns the number of documents
+    /// that were parsed completely.
+    fn parse_documents(&self, input: &mut I) -> Result<u64>;
+}
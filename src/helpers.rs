@@ -0,0 +1,754 @@
+//! Default implementations of some of the SAX2 interfaces, intended to be shared by readers,
+//! filters and applications.
+//!
+//! The SAX2 API definitions from which some of these helpers were derived comes with
+//! the following notice:
+//!
+//! > This module, both source code and documentation, is in the Public Domain,
+//! > and comes with NO WARRANTY. See http://www.saxproject.org for further
+//! > information.
+//!
+//! See also http://www.saxproject.org/copying.html
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use common;
+use common::Locator;
+use ext::Locator2;
+use limits::Limit;
+use sax2::Attributes;
+use sax2::ParseError;
+use security::Refusal;
+
+/// A single entry in an `AttributesImpl` list.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Attribute {
+    /// The Namespace URI, or the empty string if none is available.
+    pub uri: String,
+    /// The local name, or the empty string if Namespace processing is not being performed.
+    pub local_name: String,
+    /// The qualified (prefixed) name, or the empty string if qualified names are not available.
+    pub q_name: String,
+    /// The attribute type as a string.
+    pub attr_type: String,
+    /// The attribute value.
+    pub value: String,
+}
+
+/// Default implementation of the `Attributes` interface.
+///
+/// This class provides a default implementation of the SAX2 `Attributes` interface, with the
+/// addition of manipulators so that the list can be modified or reused.
+///
+/// There are two typical uses of this class:
+///
+/// 1. to take a persistent snapshot of an `Attributes` object in a `start_element` event; or
+/// 2. to construct or modify an `Attributes` object in a SAX2 driver or filter.
+///
+/// Modelled after `org.xml.sax.helpers.AttributesImpl`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AttributesImpl {
+    attributes: Vec<Attribute>,
+}
+
+impl AttributesImpl {
+    /// Construct a new, empty `AttributesImpl` object.
+    pub fn new() -> Self {
+        AttributesImpl::default()
+    }
+
+    /// Copy an existing `Attributes` object.
+    ///
+    /// This constructor is especially useful inside a `start_element` event, since the
+    /// attribute list supplied by the parser is only guaranteed to be valid for the duration of
+    /// the callback.
+    pub fn from_attributes<A: Attributes>(atts: &A) -> Self {
+        let mut copy = AttributesImpl::new();
+        copy.set_attributes(atts);
+        copy
+    }
+
+    /// Add an attribute to the end of the list.
+    ///
+    /// For the sake of speed, this method does no checking to see if the attribute is already
+    /// in the list: that is the responsibility of the application.
+    pub fn add_attribute(
+        &mut self,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attr_type: &str,
+        value: &str,
+    ) {
+        self.attributes.push(Attribute {
+            uri: uri.to_owned(),
+            local_name: local_name.to_owned(),
+            q_name: q_name.to_owned(),
+            attr_type: attr_type.to_owned(),
+            value: value.to_owned(),
+        });
+    }
+
+    /// Clear the attribute list for reuse.
+    pub fn clear(&mut self) {
+        self.attributes.clear();
+    }
+
+    /// Remove an attribute from the list.
+    ///
+    /// Returns the removed attribute, or `None` if the index is out of range.
+    pub fn remove_attribute(&mut self, index: u64) -> Option<Attribute> {
+        let index = index as usize;
+        if index < self.attributes.len() {
+            Some(self.attributes.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Set an attribute in the list.
+    ///
+    /// Returns `false` if the index is out of range, in which case the list is unchanged.
+    pub fn set_attribute(
+        &mut self,
+        index: u64,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attr_type: &str,
+        value: &str,
+    ) -> bool {
+        match self.attributes.get_mut(index as usize) {
+            Some(attribute) => {
+                *attribute = Attribute {
+                    uri: uri.to_owned(),
+                    local_name: local_name.to_owned(),
+                    q_name: q_name.to_owned(),
+                    attr_type: attr_type.to_owned(),
+                    value: value.to_owned(),
+                };
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Copy an entire `Attributes` object, replacing the current contents of the list.
+    pub fn set_attributes<A: Attributes>(&mut self, atts: &A) {
+        self.clear();
+        for i in 0..atts.get_length() as u64 {
+            self.attributes.push(Attribute {
+                uri: atts.get_uri(i).unwrap_or_default(),
+                local_name: atts.get_local_name(i).unwrap_or_default(),
+                q_name: atts.get_q_name(i).unwrap_or_default(),
+                attr_type: atts.get_type(i).unwrap_or_else(|| "CDATA".to_owned()),
+                value: atts.get_value(i).unwrap_or_default(),
+            });
+        }
+    }
+
+    /// Set the type of a specific attribute.
+    ///
+    /// Returns `false` if the index is out of range.
+    pub fn set_type(&mut self, index: u64, attr_type: &str) -> bool {
+        match self.attributes.get_mut(index as usize) {
+            Some(attribute) => {
+                attribute.attr_type = attr_type.to_owned();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set the value of a specific attribute.
+    ///
+    /// Returns `false` if the index is out of range.
+    pub fn set_value(&mut self, index: u64, value: &str) -> bool {
+        match self.attributes.get_mut(index as usize) {
+            Some(attribute) => {
+                attribute.value = value.to_owned();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Return an iterator over the attributes in the list.
+    pub fn iter(&self) -> std::slice::Iter<'_, Attribute> {
+        self.attributes.iter()
+    }
+}
+
+impl Attributes for AttributesImpl {
+    fn get_q_name_index(&self, q_name: &str) -> Option<u64> {
+        self.attributes
+            .iter()
+            .position(|a| a.q_name == q_name)
+            .map(|i| i as u64)
+    }
+
+    fn get_ns_name_index(&self, uri: &str, local_name: &str) -> Option<u64> {
+        self.attributes
+            .iter()
+            .position(|a| a.uri == uri && a.local_name == local_name)
+            .map(|i| i as u64)
+    }
+
+    fn get_length(&self) -> usize {
+        self.attributes.len()
+    }
+
+    fn get_local_name(&self, index: u64) -> Option<String> {
+        self.attributes
+            .get(index as usize)
+            .map(|a| a.local_name.clone())
+    }
+
+    fn get_q_name(&self, index: u64) -> Option<String> {
+        self.attributes
+            .get(index as usize)
+            .map(|a| a.q_name.clone())
+    }
+
+    fn get_type(&self, index: u64) -> Option<String> {
+        self.attributes
+            .get(index as usize)
+            .map(|a| a.attr_type.clone())
+    }
+
+    fn get_uri(&self, index: u64) -> Option<String> {
+        self.attributes.get(index as usize).map(|a| a.uri.clone())
+    }
+
+    fn get_value(&self, index: u64) -> Option<String> {
+        self.attributes.get(index as usize).map(|a| a.value.clone())
+    }
+}
+
+impl<'a> IntoIterator for &'a AttributesImpl {
+    type Item = &'a Attribute;
+    type IntoIter = std::slice::Iter<'a, Attribute>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Provide an optional convenience implementation of `Locator`.
+///
+/// This class is available mainly for application writers, who can use it to make a persistent
+/// snapshot of a locator at any point during a document parse.
+///
+/// Modelled after `org.xml.sax.helpers.LocatorImpl`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LocatorImpl {
+    /// The public identifier, if any.
+    pub public_id: Option<String>,
+    /// The system identifier, if any.
+    pub system_id: Option<String>,
+    /// The line number, if known.
+    pub line_number: Option<u64>,
+    /// The column number, if known.
+    pub column_number: Option<u64>,
+}
+
+impl LocatorImpl {
+    /// Construct a new, empty `LocatorImpl` object.
+    pub fn new() -> Self {
+        LocatorImpl::default()
+    }
+
+    /// Copy the current position of an existing locator.
+    pub fn from_locator<L: Locator + ?Sized>(locator: &L) -> Self {
+        LocatorImpl {
+            public_id: locator.get_public_id(),
+            system_id: locator.get_system_id(),
+            line_number: locator.get_line_number(),
+            column_number: locator.get_column_number(),
+        }
+    }
+}
+
+impl Locator for LocatorImpl {
+    fn get_column_number(&self) -> Option<u64> {
+        self.column_number
+    }
+    fn get_line_number(&self) -> Option<u64> {
+        self.line_number
+    }
+    fn get_public_id(&self) -> Option<String> {
+        self.public_id.clone()
+    }
+    fn get_system_id(&self) -> Option<String> {
+        self.system_id.clone()
+    }
+}
+
+/// Provide an optional convenience implementation of `Locator2`.
+///
+/// Like `LocatorImpl`, it can hold a snapshot of a locator's position, together with the XML
+/// version and encoding of the current entity.
+///
+/// Modelled after `org.xml.sax.ext.Locator2Impl`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Locator2Impl {
+    /// The position.
+    pub location: LocatorImpl,
+    /// The XML version of the entity, if known.
+    pub xml_version: Option<String>,
+    /// The character encoding of the entity, if known.
+    pub encoding: Option<String>,
+}
+
+impl Locator2Impl {
+    /// Construct a new, empty `Locator2Impl` object.
+    pub fn new() -> Self {
+        Locator2Impl::default()
+    }
+
+    /// Copy the current position, XML version and encoding of an existing locator.
+    pub fn from_locator<L: Locator2 + ?Sized>(locator: &L) -> Self {
+        Locator2Impl {
+            location: LocatorImpl::from_locator(locator),
+            xml_version: locator.get_xml_version(),
+            encoding: locator.get_encoding(),
+        }
+    }
+}
+
+impl Locator for Locator2Impl {
+    fn get_column_number(&self) -> Option<u64> {
+        self.location.column_number
+    }
+    fn get_line_number(&self) -> Option<u64> {
+        self.location.line_number
+    }
+    fn get_public_id(&self) -> Option<String> {
+        self.location.public_id.clone()
+    }
+    fn get_system_id(&self) -> Option<String> {
+        self.location.system_id.clone()
+    }
+}
+
+impl Locator2 for Locator2Impl {
+    fn get_xml_version(&self) -> Option<String> {
+        self.xml_version.clone()
+    }
+    fn get_encoding(&self) -> Option<String> {
+        self.encoding.clone()
+    }
+}
+
+/// A locator reporting the position given by the locator of a reader, once the reader has
+/// provided one, and no position before that.
+///
+/// Filters that hand the handler they wrap a locator of their own, reporting more than the
+/// position, can keep the reader's locator in a `LocatorProxy` and forward their `Locator`
+/// methods to it.
+pub struct LocatorProxy<L> {
+    locator: RefCell<Option<Rc<L>>>,
+}
+
+impl<L: Locator> LocatorProxy<L> {
+    /// Construct a proxy without a locator to forward to.
+    pub fn new() -> Self {
+        LocatorProxy {
+            locator: RefCell::new(None),
+        }
+    }
+
+    /// Set the locator to forward to.
+    pub fn set_locator(&self, locator: Rc<L>) {
+        *self.locator.borrow_mut() = Some(locator);
+    }
+
+    /// Return the locator forwarded to, if any.
+    pub fn get_locator(&self) -> Option<Rc<L>> {
+        self.locator.borrow().clone()
+    }
+}
+
+impl<L: Locator> Default for LocatorProxy<L> {
+    fn default() -> Self {
+        LocatorProxy::new()
+    }
+}
+
+impl<L: Locator> Locator for LocatorProxy<L> {
+    fn get_column_number(&self) -> Option<u64> {
+        self.locator.borrow().as_ref()?.get_column_number()
+    }
+    fn get_line_number(&self) -> Option<u64> {
+        self.locator.borrow().as_ref()?.get_line_number()
+    }
+    fn get_public_id(&self) -> Option<String> {
+        self.locator.borrow().as_ref()?.get_public_id()
+    }
+    fn get_system_id(&self) -> Option<String> {
+        self.locator.borrow().as_ref()?.get_system_id()
+    }
+}
+
+/// What a `ParseErrorImpl` reports.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// An error in the document being parsed.
+    #[default]
+    Parse,
+    /// An XPath expression, CSS selector or other expression that cannot be parsed or
+    /// evaluated.
+    Expression,
+    /// A violation of a validity constraint, or a declaration or datatype literal that is not
+    /// valid.
+    Validity,
+    /// An entity, catalog or other resource that cannot be resolved or read.
+    Resolution,
+    /// Access to a URI refused by a `SecurityPolicy`, and why.
+    Access(Refusal),
+    /// A resource limit that was exceeded, and the value of the limit.
+    Limit(Limit, u64),
+}
+
+/// Default implementation of the `ParseError` interface: a message, the location at which
+/// the error was found, if any, and what kind of error it is.
+///
+/// Validators and filters use it to report errors through an `ErrorHandler`, and it is the
+/// error returned by the helpers of this crate, such as the XPath and CSS compilers, the DTD
+/// checks, the entity resolvers, the security policy and the resource limits.
+///
+/// Modelled after `org.xml.sax.SAXParseException`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParseErrorImpl {
+    kind: ErrorKind,
+    message: String,
+    // Boxed, so that results carrying the error stay small.
+    location: Box<LocatorImpl>,
+}
+
+impl ParseErrorImpl {
+    /// Construct an error with the given message and location.
+    pub fn new<S: Into<String>>(message: S, location: LocatorImpl) -> Self {
+        ParseErrorImpl {
+            kind: ErrorKind::Parse,
+            message: message.into(),
+            location: Box::new(location),
+        }
+    }
+
+    /// Construct an error of the given kind with the given message, without a location.
+    pub fn of_kind<S: Into<String>>(kind: ErrorKind, message: S) -> Self {
+        ParseErrorImpl {
+            kind,
+            message: message.into(),
+            location: Box::default(),
+        }
+    }
+
+    /// Construct an error with the given message, at the current position of a locator, or
+    /// without a location if no locator is available.
+    pub fn at<S: Into<String>, L: Locator + ?Sized>(message: S, locator: Option<&L>) -> Self {
+        ParseErrorImpl::new(
+            message,
+            locator.map(LocatorImpl::from_locator).unwrap_or_default(),
+        )
+    }
+
+    /// Return the same error at the current position of a locator, or unchanged if no locator
+    /// is available.
+    pub fn located<L: Locator + ?Sized>(mut self, locator: Option<&L>) -> Self {
+        if let Some(locator) = locator {
+            *self.location = LocatorImpl::from_locator(locator);
+        }
+        self
+    }
+
+    /// Return the kind of error.
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// Return the error message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ParseErrorImpl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref system_id) = self.location.system_id {
+            write!(f, "{}:", system_id)?;
+        }
+        if let Some(line) = self.location.line_number {
+            write!(f, "{}:", line)?;
+            if let Some(column) = self.location.column_number {
+                write!(f, "{}:", column)?;
+            }
+        }
+        if self.location.system_id.is_some() || self.location.line_number.is_some() {
+            f.write_str(" ")?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ParseErrorImpl {}
+
+impl common::Error for ParseErrorImpl {}
+
+impl From<ParseErrorImpl> for Box<dyn common::Error> {
+    fn from(e: ParseErrorImpl) -> Self {
+        Box::new(e)
+    }
+}
+
+impl From<ParseErrorImpl> for Box<dyn common::Error + Send + Sync> {
+    fn from(e: ParseErrorImpl) -> Self {
+        Box::new(e)
+    }
+}
+
+impl Locator for ParseErrorImpl {
+    fn get_column_number(&self) -> Option<u64> {
+        self.location.column_number
+    }
+    fn get_line_number(&self) -> Option<u64> {
+        self.location.line_number
+    }
+    fn get_public_id(&self) -> Option<String> {
+        self.location.public_id.clone()
+    }
+    fn get_system_id(&self) -> Option<String> {
+        self.location.system_id.clone()
+    }
+}
+
+impl ParseError for ParseErrorImpl {}
+
+/// The XML Namespace URI, bound to the "xml" prefix.
+pub const XMLNS: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// The Namespace URI given to `xmlns*` attributes when the
+/// http://xml.org/sax/features/xmlns-uris feature is true.
+pub const NSDECL: &str = "http://www.w3.org/2000/xmlns/";
+
+/// Encapsulate Namespace logic for use by applications using SAX, or internally by SAX drivers.
+///
+/// This class encapsulates the logic of Namespace processing: it tracks the declarations
+/// currently in force for each context and automatically processes qualified XML names into
+/// their Namespace parts; it can also be used in reverse for generating XML qnames from
+/// Namespaces.
+///
+/// Namespace support objects are reusable, but the `reset` method must be invoked between each
+/// session.
+///
+/// The "xml" prefix is always bound to `XMLNS`, and cannot be redeclared. The empty prefix
+/// refers to the default Namespace; declaring it with an empty URI undeclares the default
+/// Namespace.
+///
+/// Modelled after `org.xml.sax.helpers.NamespaceSupport`
+#[derive(Clone, Debug)]
+pub struct NamespaceSupport {
+    contexts: Vec<Vec<(String, String)>>,
+    namespace_decl_uris: bool,
+}
+
+impl Default for NamespaceSupport {
+    fn default() -> Self {
+        NamespaceSupport::new()
+    }
+}
+
+impl NamespaceSupport {
+    /// Create a new Namespace support object, with a single, empty context.
+    pub fn new() -> Self {
+        NamespaceSupport {
+            contexts: vec![Vec::new()],
+            namespace_decl_uris: false,
+        }
+    }
+
+    /// Reset this Namespace support object for reuse.
+    ///
+    /// It is necessary to invoke this method before reusing the Namespace support object for a
+    /// new session. If Namespace declaration URIs are to be supported, that flag must also be
+    /// set to a non-default value.
+    pub fn reset(&mut self) {
+        self.contexts.clear();
+        self.contexts.push(Vec::new());
+        self.namespace_decl_uris = false;
+    }
+
+    /// Start a new Namespace context.
+    ///
+    /// The new context will automatically inherit the declarations of its parent context, but
+    /// it will also keep track of which declarations were made within this context.
+    ///
+    /// Event callback code should start a new context once per element. This means being ready
+    /// to call this in either of two places. For elements that don't include namespace
+    /// declarations, the `ContentHandler::start_element` callback is the right place. For
+    /// elements with such a declaration, it'd be done in the first
+    /// `ContentHandler::start_prefix_mapping` callback.
+    pub fn push_context(&mut self) {
+        self.contexts.push(Vec::new());
+    }
+
+    /// Revert to the previous Namespace context.
+    ///
+    /// Normally, you should pop the context at the end of each XML element. After popping the
+    /// context, all Namespace prefix mappings that were previously in force are restored.
+    ///
+    /// The base context, which holds the declarations made with no context pushed, is never
+    /// popped.
+    pub fn pop_context(&mut self) {
+        if self.contexts.len() > 1 {
+            self.contexts.pop();
+        }
+    }
+
+    /// Return the number of contexts pushed on top of the base context.
+    pub fn depth(&self) -> usize {
+        self.contexts.len() - 1
+    }
+
+    /// Declare a Namespace prefix.
+    ///
+    /// All prefixes must be declared before they are referenced. This method declares a prefix
+    /// in the current Namespace context; the prefix will remain in force until this context is
+    /// popped, unless it is shadowed in a descendant context.
+    ///
+    /// Returns `false` if the prefix is "xml" or "xmlns", which may not be declared, and `true`
+    /// otherwise.
+    pub fn declare_prefix(&mut self, prefix: &str, uri: &str) -> bool {
+        if prefix == "xml" || prefix == "xmlns" {
+            return false;
+        }
+        let context = self
+            .contexts
+            .last_mut()
+            .expect("NamespaceSupport always has a base context");
+        match context.iter_mut().find(|decl| decl.0 == prefix) {
+            Some(decl) => decl.1 = uri.to_owned(),
+            None => context.push((prefix.to_owned(), uri.to_owned())),
+        }
+        true
+    }
+
+    /// Look up a prefix and get the currently-mapped Namespace URI.
+    ///
+    /// This method looks up the prefix in the current context. Use the empty string ("") for
+    /// the default Namespace. Returns `None` if the prefix is not bound, or if it is the empty
+    /// prefix and the default Namespace has been undeclared.
+    pub fn get_uri(&self, prefix: &str) -> Option<&str> {
+        match prefix {
+            "xml" => return Some(XMLNS),
+            "xmlns" if self.namespace_decl_uris => return Some(NSDECL),
+            _ => {}
+        }
+        self.contexts
+            .iter()
+            .rev()
+            .flat_map(|context| context.iter().rev())
+            .find(|decl| decl.0 == prefix)
+            .map(|decl| decl.1.as_str())
+            .filter(|uri| !uri.is_empty())
+    }
+
+    /// Return one of the non-empty prefixes mapped to a Namespace URI.
+    ///
+    /// If more than one prefix is currently mapped to the same URI, this method will make an
+    /// arbitrary selection. This method returns prefixes mapped to a specific Namespace URI, so
+    /// it never returns the empty (default) prefix; use `get_uri("")` to check the default
+    /// Namespace.
+    pub fn get_prefix(&self, uri: &str) -> Option<String> {
+        if uri == XMLNS {
+            return Some("xml".to_owned());
+        }
+        self.get_prefixes_for(uri).into_iter().next()
+    }
+
+    /// Return all prefixes currently declared, except the empty (default) prefix.
+    pub fn get_prefixes(&self) -> Vec<String> {
+        let mut prefixes: Vec<String> = Vec::new();
+        for context in self.contexts.iter().rev() {
+            for decl in context.iter().rev() {
+                if !prefixes.contains(&decl.0) {
+                    prefixes.push(decl.0.clone());
+                }
+            }
+        }
+        prefixes
+            .into_iter()
+            .filter(|prefix| !prefix.is_empty() && self.get_uri(prefix).is_some())
+            .collect()
+    }
+
+    /// Return all non-empty prefixes currently mapped to the given Namespace URI.
+    pub fn get_prefixes_for(&self, uri: &str) -> Vec<String> {
+        self.get_prefixes()
+            .into_iter()
+            .filter(|prefix| self.get_uri(prefix) == Some(uri))
+            .collect()
+    }
+
+    /// Return all prefixes declared in this context, including the empty (default) prefix if it
+    /// was declared or undeclared here.
+    pub fn get_declared_prefixes(&self) -> Vec<String> {
+        self.contexts
+            .last()
+            .map(|context| context.iter().map(|decl| decl.0.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Process a raw XML qualified name, after all declarations in the current context have
+    /// been handled by `declare_prefix`.
+    ///
+    /// Returns the (Namespace URI, local name, qualified name) triple, or `None` if the name
+    /// uses an undeclared prefix or is otherwise malformed. Unprefixed element names take the
+    /// default Namespace, while unprefixed attribute names are in no Namespace; in either case
+    /// an absent Namespace is reported as the empty string.
+    ///
+    /// If the `xmlns-uris` behaviour is disabled (the default), `xmlns` and `xmlns:*` attribute
+    /// names are reported with an empty Namespace URI.
+    pub fn process_name(
+        &self,
+        q_name: &str,
+        is_attribute: bool,
+    ) -> Option<(String, String, String)> {
+        let (prefix, local_name) = match q_name.find(':') {
+            Some(i) => (&q_name[..i], &q_name[i + 1..]),
+            None => ("", q_name),
+        };
+        if local_name.is_empty() || local_name.contains(':') || q_name.starts_with(':') {
+            return None;
+        }
+        if is_attribute && (q_name == "xmlns" || prefix == "xmlns") {
+            let uri = if self.namespace_decl_uris { NSDECL } else { "" };
+            return Some((uri.to_owned(), local_name.to_owned(), q_name.to_owned()));
+        }
+        if prefix.is_empty() {
+            let uri = if is_attribute {
+                ""
+            } else {
+                self.get_uri("").unwrap_or("")
+            };
+            return Some((uri.to_owned(), local_name.to_owned(), q_name.to_owned()));
+        }
+        self.get_uri(prefix)
+            .map(|uri| (uri.to_owned(), local_name.to_owned(), q_name.to_owned()))
+    }
+
+    /// Control whether Namespace declaration attributes are placed into the `NSDECL` Namespace
+    /// by `process_name`.
+    ///
+    /// This may only be changed before any contexts have been pushed.
+    pub fn set_namespace_decl_uris(&mut self, value: bool) {
+        self.namespace_decl_uris = value;
+    }
+
+    /// Return whether Namespace declaration attributes are placed into a Namespace.
+    pub fn is_namespace_decl_uris(&self) -> bool {
+        self.namespace_decl_uris
+    }
+}
@@ -1,3 +1,41 @@
+#[cfg(feature = "async")]
+extern crate futures_core;
+#[cfg(feature = "async")]
+extern crate futures_io;
+#[cfg(feature = "normalization")]
+extern crate unicode_normalization;
+
+pub mod catalog;
 pub mod common;
+pub mod css;
 
+pub mod dtd;
+pub mod event;
+pub mod ext;
+pub mod features;
+pub mod fragment;
+pub mod helpers;
+pub mod limits;
+pub mod names;
+pub mod namespaces;
+#[cfg(feature = "normalization")]
+pub mod normalization;
+pub mod relaxng;
+pub mod resolver;
 pub mod sax2;
+#[cfg(feature = "async")]
+pub mod sax2_async;
+pub mod schematron;
+pub mod security;
+pub mod stream;
+pub mod tree;
+pub mod uri;
+pub mod version;
+#[cfg(feature = "w3c-resources")]
+pub mod w3c;
+pub mod xinclude;
+pub mod xmlbase;
+pub mod xmlid;
+pub mod xmlscope;
+pub mod xpath;
+pub mod xsd;
@@ -0,0 +1,675 @@
+//! Resource limits protecting readers against hostile documents.
+//!
+//! A few hundred bytes of nested entity declarations can expand into gigabytes of text (the
+//! "billion laughs" attack). A reader that accepts untrusted input keeps a `Limits`
+//! configuration, which applications adjust through `XmlReader::set_property_str` with the
+//! property names defined here, and checks each parse against it with an `EntityGuard`.
+//! The `EntityGuard` also enforces the limit on the size of the document entity. Extreme
+//! nesting, millions of attributes or gigabytes of text exhaust memory just as well, and are
+//! checked with a `StructureGuard`.
+//!
+//! Property values are decimal integers, and 0 disables a limit. When a limit is exceeded, the
+//! guard returns an error of kind `ErrorKind::Limit`, which the reader reports through
+//! `ErrorHandler::fatal_error` before abandoning the parse. The kind identifies the limit, and
+//! the message includes its `Limit::code`, so that applications can tell hostile input from
+//! malformed input.
+use std::num::ParseIntError;
+
+use common::Locator;
+use helpers::ErrorKind;
+use helpers::ParseErrorImpl;
+
+/// The maximum number of entity references expanded in a document, counting references in
+/// replacement text as well (default 64,000).
+pub const MAX_ENTITY_EXPANSIONS: &str =
+    "https://crates.io/crates/xml_parser_traits/properties/max-entity-expansions";
+
+/// The maximum nesting depth of entity expansions (default 64).
+pub const MAX_ENTITY_DEPTH: &str =
+    "https://crates.io/crates/xml_parser_traits/properties/max-entity-depth";
+
+/// The maximum total number of characters produced by expanding entities, general and
+/// parameter alike (default 50,000,000).
+pub const MAX_TOTAL_ENTITY_SIZE: &str =
+    "https://crates.io/crates/xml_parser_traits/properties/max-total-entity-size";
+
+/// The maximum ratio between the characters produced by expanding entities and the characters
+/// read from the document entity (default 100). It is only checked once more than
+/// `EXPANSION_RATIO_THRESHOLD` characters have been expanded, so that short documents using
+/// a few entities are not rejected.
+pub const MAX_EXPANSION_RATIO: &str =
+    "https://crates.io/crates/xml_parser_traits/properties/max-expansion-ratio";
+
+/// The maximum number of parameter entity references expanded in the DTD (default 10,000).
+pub const MAX_PARAMETER_ENTITY_EXPANSIONS: &str =
+    "https://crates.io/crates/xml_parser_traits/properties/max-parameter-entity-expansions";
+
+/// The maximum total number of characters produced by expanding parameter entities (default
+/// 1,000,000).
+pub const MAX_PARAMETER_ENTITY_SIZE: &str =
+    "https://crates.io/crates/xml_parser_traits/properties/max-parameter-entity-size";
+
+/// The maximum depth of nested elements (default 5,000).
+pub const MAX_ELEMENT_DEPTH: &str =
+    "https://crates.io/crates/xml_parser_traits/properties/max-element-depth";
+
+/// The maximum number of attributes of an element, counting namespace declarations (default
+/// 10,000).
+pub const MAX_ATTRIBUTES: &str =
+    "https://crates.io/crates/xml_parser_traits/properties/max-attributes";
+
+/// The maximum number of characters of a name, such as an element or attribute name, a
+/// processing instruction target or an entity name (default 1,000).
+pub const MAX_NAME_LENGTH: &str =
+    "https://crates.io/crates/xml_parser_traits/properties/max-name-length";
+
+/// The maximum number of characters of an attribute value, after normalization (default
+/// 10,000,000).
+pub const MAX_ATTRIBUTE_VALUE_LENGTH: &str =
+    "https://crates.io/crates/xml_parser_traits/properties/max-attribute-value-length";
+
+/// The maximum number of characters of a single text node, i.e. of character data that is not
+/// interrupted by markup other than entity references (default 50,000,000).
+pub const MAX_TEXT_LENGTH: &str =
+    "https://crates.io/crates/xml_parser_traits/properties/max-text-length";
+
+/// The maximum number of characters read from the document entity (default 0, i.e.
+/// unlimited, since documents may legitimately be streams of any length).
+pub const MAX_DOCUMENT_SIZE: &str =
+    "https://crates.io/crates/xml_parser_traits/properties/max-document-size";
+
+/// The number of expanded characters from which `MAX_EXPANSION_RATIO` applies.
+pub const EXPANSION_RATIO_THRESHOLD: u64 = 1 << 20;
+
+/// A resource limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Limit {
+    /// See `MAX_ENTITY_EXPANSIONS`.
+    EntityExpansions,
+    /// See `MAX_ENTITY_DEPTH`.
+    EntityDepth,
+    /// See `MAX_TOTAL_ENTITY_SIZE`.
+    TotalEntitySize,
+    /// See `MAX_EXPANSION_RATIO`.
+    ExpansionRatio,
+    /// See `MAX_PARAMETER_ENTITY_EXPANSIONS`.
+    ParameterEntityExpansions,
+    /// See `MAX_PARAMETER_ENTITY_SIZE`.
+    ParameterEntitySize,
+    /// See `MAX_ELEMENT_DEPTH`.
+    ElementDepth,
+    /// See `MAX_ATTRIBUTES`.
+    Attributes,
+    /// See `MAX_NAME_LENGTH`.
+    NameLength,
+    /// See `MAX_ATTRIBUTE_VALUE_LENGTH`.
+    AttributeValueLength,
+    /// See `MAX_TEXT_LENGTH`.
+    TextLength,
+    /// See `MAX_DOCUMENT_SIZE`.
+    DocumentSize,
+}
+
+impl Limit {
+    /// Every limit, in the order of their declaration.
+    pub const ALL: &'static [Limit] = &[
+        Limit::EntityExpansions,
+        Limit::EntityDepth,
+        Limit::TotalEntitySize,
+        Limit::ExpansionRatio,
+        Limit::ParameterEntityExpansions,
+        Limit::ParameterEntitySize,
+        Limit::ElementDepth,
+        Limit::Attributes,
+        Limit::NameLength,
+        Limit::AttributeValueLength,
+        Limit::TextLength,
+        Limit::DocumentSize,
+    ];
+
+    /// Return the name of the property that sets this limit.
+    pub fn property(self) -> &'static str {
+        match self {
+            Limit::EntityExpansions => MAX_ENTITY_EXPANSIONS,
+            Limit::EntityDepth => MAX_ENTITY_DEPTH,
+            Limit::TotalEntitySize => MAX_TOTAL_ENTITY_SIZE,
+            Limit::ExpansionRatio => MAX_EXPANSION_RATIO,
+            Limit::ParameterEntityExpansions => MAX_PARAMETER_ENTITY_EXPANSIONS,
+            Limit::ParameterEntitySize => MAX_PARAMETER_ENTITY_SIZE,
+            Limit::ElementDepth => MAX_ELEMENT_DEPTH,
+            Limit::Attributes => MAX_ATTRIBUTES,
+            Limit::NameLength => MAX_NAME_LENGTH,
+            Limit::AttributeValueLength => MAX_ATTRIBUTE_VALUE_LENGTH,
+            Limit::TextLength => MAX_TEXT_LENGTH,
+            Limit::DocumentSize => MAX_DOCUMENT_SIZE,
+        }
+    }
+
+    /// Return the limit set by a property, if the property name is that of a limit.
+    pub fn from_property(name: &str) -> Option<Limit> {
+        Limit::ALL
+            .iter()
+            .cloned()
+            .find(|limit| limit.property() == name)
+    }
+
+    /// Return the error code reported when this limit is exceeded.
+    pub fn code(self) -> &'static str {
+        match self {
+            Limit::EntityExpansions => "entity-expansions",
+            Limit::EntityDepth => "entity-depth",
+            Limit::TotalEntitySize => "total-entity-size",
+            Limit::ExpansionRatio => "expansion-ratio",
+            Limit::ParameterEntityExpansions => "parameter-entity-expansions",
+            Limit::ParameterEntitySize => "parameter-entity-size",
+            Limit::ElementDepth => "element-depth",
+            Limit::Attributes => "attributes",
+            Limit::NameLength => "name-length",
+            Limit::AttributeValueLength => "attribute-value-length",
+            Limit::TextLength => "text-length",
+            Limit::DocumentSize => "document-size",
+        }
+    }
+
+    /// Return the default value of this limit.
+    pub fn default_value(self) -> u64 {
+        match self {
+            Limit::EntityExpansions => 64_000,
+            Limit::EntityDepth => 64,
+            Limit::TotalEntitySize => 50_000_000,
+            Limit::ExpansionRatio => 100,
+            Limit::ParameterEntityExpansions => 10_000,
+            Limit::ParameterEntitySize => 1_000_000,
+            Limit::ElementDepth => 5_000,
+            Limit::Attributes => 10_000,
+            Limit::NameLength => 1_000,
+            Limit::AttributeValueLength => 10_000_000,
+            Limit::TextLength => 50_000_000,
+            Limit::DocumentSize => 0,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Limit::EntityExpansions => "number of entity references expanded",
+            Limit::EntityDepth => "nesting depth of entity expansions",
+            Limit::TotalEntitySize => "number of characters expanded from entities",
+            Limit::ExpansionRatio => "ratio of expanded characters to document characters",
+            Limit::ParameterEntityExpansions => "number of parameter entity references expanded",
+            Limit::ParameterEntitySize => "number of characters expanded from parameter entities",
+            Limit::ElementDepth => "nesting depth of elements",
+            Limit::Attributes => "number of attributes of an element",
+            Limit::NameLength => "length of a name",
+            Limit::AttributeValueLength => "length of an attribute value",
+            Limit::TextLength => "length of a text node",
+            Limit::DocumentSize => "number of characters of the document",
+        }
+    }
+}
+
+/// The values of all resource limits.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Limits {
+    values: Vec<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            values: Limit::ALL
+                .iter()
+                .map(|limit| limit.default_value())
+                .collect(),
+        }
+    }
+}
+
+impl Limits {
+    /// Construct the default limits.
+    pub fn new() -> Self {
+        Limits::default()
+    }
+
+    /// Construct limits that are all disabled.
+    pub fn unlimited() -> Self {
+        Limits {
+            values: vec![0; Limit::ALL.len()],
+        }
+    }
+
+    fn index(limit: Limit) -> usize {
+        Limit::ALL
+            .iter()
+            .position(|&l| l == limit)
+            .expect("every limit is listed")
+    }
+
+    /// Return the value of a limit; 0 means that it is disabled.
+    pub fn get(&self, limit: Limit) -> u64 {
+        self.values[Limits::index(limit)]
+    }
+
+    /// Set the value of a limit; 0 disables it.
+    pub fn set(&mut self, limit: Limit, value: u64) {
+        self.values[Limits::index(limit)] = value;
+    }
+
+    /// Return the value of a limit property as a string, or `None` if the property name is not
+    /// that of a limit.
+    ///
+    /// This is meant for readers implementing `XmlReader::get_property_str`.
+    pub fn get_property(&self, name: &str) -> Option<String> {
+        Limit::from_property(name).map(|limit| self.get(limit).to_string())
+    }
+
+    /// Set a limit property from its string value. Returns `Ok(false)` if the property name is
+    /// not that of a limit, and an error if the value is not a non-negative integer.
+    ///
+    /// This is meant for readers implementing `XmlReader::set_property_str`.
+    pub fn set_property(&mut self, name: &str, value: &str) -> Result<bool, ParseIntError> {
+        match Limit::from_property(name) {
+            Some(limit) => {
+                self.set(limit, value.trim().parse()?);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Return an error if `count` exceeds a limit.
+    fn check<L: Locator + ?Sized>(
+        &self,
+        limit: Limit,
+        count: u64,
+        locator: Option<&L>,
+    ) -> Result<(), ParseErrorImpl> {
+        let value = self.get(limit);
+        if value != 0 && count > value {
+            Err(exceeded(limit, value, locator))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Return the fatal error reporting that `limit`, of the given value, was exceeded at the
+/// current position of a locator.
+fn exceeded<L: Locator + ?Sized>(limit: Limit, value: u64, locator: Option<&L>) -> ParseErrorImpl {
+    let message = format!(
+        "the {} exceeds the limit of {} [{}]",
+        limit.description(),
+        value,
+        limit.code()
+    );
+    ParseErrorImpl::of_kind(ErrorKind::Limit(limit, value), message).located(locator)
+}
+
+/// Enforces the entity expansion limits and the document size limit during a parse.
+///
+/// The reader reports the characters it reads from the document entity with `document_read`,
+/// each entity it starts and finishes expanding with `start_entity` and `end_entity`, and the
+/// characters it reads from the replacement text of the innermost entity with `expanded`.
+/// Characters produced by a nested entity are counted once, when they are read from the
+/// replacement text of that entity.
+#[derive(Clone, Debug)]
+pub struct EntityGuard {
+    limits: Limits,
+    /// For each entity being expanded, whether it is a parameter entity.
+    open: Vec<bool>,
+    expansions: u64,
+    parameter_expansions: u64,
+    total_size: u64,
+    parameter_size: u64,
+    document_size: u64,
+}
+
+impl EntityGuard {
+    /// Construct a guard for a new parse.
+    pub fn new(limits: &Limits) -> Self {
+        EntityGuard {
+            limits: limits.clone(),
+            open: Vec::new(),
+            expansions: 0,
+            parameter_expansions: 0,
+            total_size: 0,
+            parameter_size: 0,
+            document_size: 0,
+        }
+    }
+
+    /// Return the number of entity references expanded so far.
+    pub fn expansions(&self) -> u64 {
+        self.expansions
+    }
+
+    /// Return the number of characters expanded from entities so far.
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    /// Record that characters were read from the document entity.
+    pub fn document_read<L: Locator + ?Sized>(
+        &mut self,
+        chars: u64,
+        locator: Option<&L>,
+    ) -> Result<(), ParseErrorImpl> {
+        self.document_size += chars;
+        self.limits
+            .check(Limit::DocumentSize, self.document_size, locator)
+    }
+
+    /// Record the start of the expansion of an entity reference.
+    pub fn start_entity<L: Locator + ?Sized>(
+        &mut self,
+        parameter: bool,
+        locator: Option<&L>,
+    ) -> Result<(), ParseErrorImpl> {
+        self.expansions += 1;
+        self.limits
+            .check(Limit::EntityExpansions, self.expansions, locator)?;
+        if parameter {
+            self.parameter_expansions += 1;
+            self.limits.check(
+                Limit::ParameterEntityExpansions,
+                self.parameter_expansions,
+                locator,
+            )?;
+        }
+        self.open.push(parameter);
+        self.limits
+            .check(Limit::EntityDepth, self.open.len() as u64, locator)
+    }
+
+    /// Record the end of the expansion of the innermost entity.
+    pub fn end_entity(&mut self) {
+        self.open.pop();
+    }
+
+    /// Record that characters were read from the replacement text of the innermost entity.
+    pub fn expanded<L: Locator + ?Sized>(
+        &mut self,
+        chars: u64,
+        locator: Option<&L>,
+    ) -> Result<(), ParseErrorImpl> {
+        self.total_size += chars;
+        self.limits
+            .check(Limit::TotalEntitySize, self.total_size, locator)?;
+        if self.open.last() == Some(&true) {
+            self.parameter_size += chars;
+            self.limits
+                .check(Limit::ParameterEntitySize, self.parameter_size, locator)?;
+        }
+        let ratio = self.limits.get(Limit::ExpansionRatio);
+        if ratio != 0
+            && self.total_size > EXPANSION_RATIO_THRESHOLD
+            && self.total_size > ratio.saturating_mul(self.document_size)
+        {
+            return Err(exceeded(Limit::ExpansionRatio, ratio, locator));
+        }
+        Ok(())
+    }
+}
+
+/// Enforces the structural limits during a parse.
+///
+/// The reader checks the length of each name and attribute value it scans with `check_name`
+/// and `check_attribute_value`, which it may call repeatedly with the length scanned so far so
+/// as to stop before buffering an oversized token. It reports each element with
+/// `start_element` and `end_element`, the character data it reads with `text`, and any other
+/// markup with `end_text`, which ends the current text node. The size of the document entity
+/// is left to the `EntityGuard`.
+#[derive(Clone, Debug)]
+pub struct StructureGuard {
+    limits: Limits,
+    depth: u64,
+    text_length: u64,
+}
+
+impl StructureGuard {
+    /// Construct a guard for a new parse.
+    pub fn new(limits: &Limits) -> Self {
+        StructureGuard {
+            limits: limits.clone(),
+            depth: 0,
+            text_length: 0,
+        }
+    }
+
+    /// Return the depth of the current element, 0 outside the document element.
+    pub fn depth(&self) -> u64 {
+        self.depth
+    }
+
+    /// Check the length of a name, in characters.
+    pub fn check_name<L: Locator + ?Sized>(
+        &self,
+        length: u64,
+        locator: Option<&L>,
+    ) -> Result<(), ParseErrorImpl> {
+        self.limits.check(Limit::NameLength, length, locator)
+    }
+
+    /// Check the length of an attribute value, in characters.
+    pub fn check_attribute_value<L: Locator + ?Sized>(
+        &self,
+        length: u64,
+        locator: Option<&L>,
+    ) -> Result<(), ParseErrorImpl> {
+        self.limits
+            .check(Limit::AttributeValueLength, length, locator)
+    }
+
+    /// Check the number of attributes scanned so far in a start tag.
+    pub fn check_attributes<L: Locator + ?Sized>(
+        &self,
+        count: u64,
+        locator: Option<&L>,
+    ) -> Result<(), ParseErrorImpl> {
+        self.limits.check(Limit::Attributes, count, locator)
+    }
+
+    /// Record the start of an element with the given number of attributes.
+    pub fn start_element<L: Locator + ?Sized>(
+        &mut self,
+        attributes: u64,
+        locator: Option<&L>,
+    ) -> Result<(), ParseErrorImpl> {
+        self.text_length = 0;
+        self.check_attributes(attributes, locator)?;
+        self.depth += 1;
+        self.limits.check(Limit::ElementDepth, self.depth, locator)
+    }
+
+    /// Record the end of the current element.
+    pub fn end_element(&mut self) {
+        self.text_length = 0;
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    /// Record that characters of the current text node were read.
+    pub fn text<L: Locator + ?Sized>(
+        &mut self,
+        chars: u64,
+        locator: Option<&L>,
+    ) -> Result<(), ParseErrorImpl> {
+        self.text_length += chars;
+        self.limits
+            .check(Limit::TextLength, self.text_length, locator)
+    }
+
+    /// Record markup ending the current text node.
+    pub fn end_text(&mut self) {
+        self.text_length = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helpers::LocatorImpl;
+
+    const NOWHERE: Option<&LocatorImpl> = None;
+
+    #[test]
+    fn document_size_is_counted_once() {
+        let mut limits = Limits::new();
+        limits.set(Limit::DocumentSize, 100);
+        let mut entities = EntityGuard::new(&limits);
+        let mut structure = StructureGuard::new(&limits);
+        entities.document_read(60, NOWHERE).unwrap();
+        structure.text(60, NOWHERE).unwrap();
+        let e = entities.document_read(41, NOWHERE).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::Limit(Limit::DocumentSize, 100));
+        assert!(e.message().ends_with("[document-size]"));
+    }
+
+    #[test]
+    fn document_size_is_unlimited_by_default() {
+        let mut entities = EntityGuard::new(&Limits::new());
+        entities.document_read(u64::MAX / 2, NOWHERE).unwrap();
+        entities.document_read(u64::MAX / 2, NOWHERE).unwrap();
+    }
+
+    #[test]
+    fn structure_limits() {
+        let mut limits = Limits::new();
+        limits.set(Limit::ElementDepth, 2);
+        limits.set(Limit::Attributes, 3);
+        limits.set(Limit::NameLength, 4);
+        limits.set(Limit::AttributeValueLength, 5);
+        limits.set(Limit::TextLength, 6);
+        let mut guard = StructureGuard::new(&limits);
+        guard.start_element(3, NOWHERE).unwrap();
+        guard.start_element(0, NOWHERE).unwrap();
+        assert_eq!(
+            *guard.start_element(0, NOWHERE).unwrap_err().kind(),
+            ErrorKind::Limit(Limit::ElementDepth, 2)
+        );
+        guard.end_element();
+        guard.end_element();
+        assert_eq!(guard.depth(), 1);
+        assert_eq!(
+            *guard.start_element(4, NOWHERE).unwrap_err().kind(),
+            ErrorKind::Limit(Limit::Attributes, 3)
+        );
+        guard.check_name(4, NOWHERE).unwrap();
+        assert!(guard.check_name(5, NOWHERE).is_err());
+        guard.check_attribute_value(5, NOWHERE).unwrap();
+        assert!(guard.check_attribute_value(6, NOWHERE).is_err());
+        guard.text(4, NOWHERE).unwrap();
+        guard.end_text();
+        guard.text(4, NOWHERE).unwrap();
+        assert_eq!(
+            *guard.text(3, NOWHERE).unwrap_err().kind(),
+            ErrorKind::Limit(Limit::TextLength, 6)
+        );
+    }
+
+    #[test]
+    fn entity_expansions_and_depth() {
+        let mut limits = Limits::unlimited();
+        limits.set(Limit::EntityExpansions, 3);
+        limits.set(Limit::EntityDepth, 2);
+        limits.set(Limit::ParameterEntityExpansions, 1);
+        let mut guard = EntityGuard::new(&limits);
+        guard.start_entity(false, NOWHERE).unwrap();
+        guard.start_entity(true, NOWHERE).unwrap();
+        guard.end_entity();
+        assert_eq!(
+            *guard.start_entity(true, NOWHERE).unwrap_err().kind(),
+            ErrorKind::Limit(Limit::ParameterEntityExpansions, 1)
+        );
+        guard.end_entity();
+        guard.end_entity();
+        assert_eq!(
+            *guard.start_entity(false, NOWHERE).unwrap_err().kind(),
+            ErrorKind::Limit(Limit::EntityExpansions, 3)
+        );
+        assert_eq!(guard.expansions(), 4);
+
+        let mut guard = EntityGuard::new(&limits);
+        guard.start_entity(false, NOWHERE).unwrap();
+        guard.start_entity(false, NOWHERE).unwrap();
+        assert_eq!(
+            *guard.start_entity(false, NOWHERE).unwrap_err().kind(),
+            ErrorKind::Limit(Limit::EntityDepth, 2)
+        );
+    }
+
+    #[test]
+    fn entity_sizes() {
+        let mut limits = Limits::unlimited();
+        limits.set(Limit::TotalEntitySize, 10);
+        limits.set(Limit::ParameterEntitySize, 4);
+        let mut guard = EntityGuard::new(&limits);
+        guard.start_entity(true, NOWHERE).unwrap();
+        guard.expanded(3, NOWHERE).unwrap();
+        guard.start_entity(false, NOWHERE).unwrap();
+        guard.expanded(5, NOWHERE).unwrap();
+        guard.end_entity();
+        assert_eq!(
+            *guard.expanded(2, NOWHERE).unwrap_err().kind(),
+            ErrorKind::Limit(Limit::ParameterEntitySize, 4)
+        );
+        guard.end_entity();
+        assert_eq!(
+            *guard.expanded(1, NOWHERE).unwrap_err().kind(),
+            ErrorKind::Limit(Limit::TotalEntitySize, 10)
+        );
+        assert_eq!(guard.total_size(), 11);
+    }
+
+    #[test]
+    fn expansion_ratio_applies_past_the_threshold() {
+        let mut limits = Limits::unlimited();
+        limits.set(Limit::ExpansionRatio, 2);
+        let mut guard = EntityGuard::new(&limits);
+        guard.document_read(10, NOWHERE).unwrap();
+        guard.start_entity(false, NOWHERE).unwrap();
+        guard.expanded(EXPANSION_RATIO_THRESHOLD, NOWHERE).unwrap();
+        let e = guard.expanded(1, NOWHERE).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::Limit(Limit::ExpansionRatio, 2));
+
+        let mut guard = EntityGuard::new(&limits);
+        guard
+            .document_read(EXPANSION_RATIO_THRESHOLD, NOWHERE)
+            .unwrap();
+        guard.start_entity(false, NOWHERE).unwrap();
+        guard
+            .expanded(2 * EXPANSION_RATIO_THRESHOLD, NOWHERE)
+            .unwrap();
+        assert!(guard.expanded(1, NOWHERE).is_err());
+    }
+
+    #[test]
+    fn errors_are_located() {
+        let mut limits = Limits::unlimited();
+        limits.set(Limit::EntityExpansions, 1);
+        let mut guard = EntityGuard::new(&limits);
+        let locator = LocatorImpl {
+            system_id: Some("doc.xml".to_owned()),
+            line_number: Some(3),
+            column_number: Some(9),
+            ..LocatorImpl::new()
+        };
+        guard.start_entity(false, Some(&locator)).unwrap();
+        let e = guard.start_entity(false, Some(&locator)).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "doc.xml:3:9: the number of entity references expanded exceeds the limit of 1 \
+             [entity-expansions]"
+        );
+    }
+
+    #[test]
+    fn properties() {
+        let mut limits = Limits::new();
+        let property = Limit::EntityDepth.property();
+        assert_eq!(Limit::from_property(property), Some(Limit::EntityDepth));
+        assert_eq!(limits.set_property(property, " 7 "), Ok(true));
+        assert_eq!(limits.get_property(property), Some("7".to_owned()));
+        assert!(limits.set_property(property, "-1").is_err());
+        assert_eq!(limits.set_property("http://example.com/x", "1"), Ok(false));
+        assert_eq!(limits.get_property("http://example.com/x"), None);
+    }
+}
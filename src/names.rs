@@ -0,0 +1,101 @@
+//! Lexical checks for the name productions of the XML and Namespaces in XML recommendations.
+//!
+//! The name checks (`is_name_start_char`, `is_name_char` and the name productions built on
+//! them) follow XML 1.0 Fifth Edition, which adopted the name characters of XML 1.1, so they
+//! apply to documents of either version. `is_whitespace` is also shared. What depends on the
+//! version is the `Char` production: `is_char` gives it for XML 1.0, and `is_char_1_1` and
+//! `is_restricted_char` for XML 1.1.
+
+/// Return whether `c` matches the `NameStartChar` production.
+pub fn is_name_start_char(c: char) -> bool {
+    matches!(c,
+        ':' | 'A'..='Z' | '_' | 'a'..='z'
+        | '\u{C0}'..='\u{D6}'
+        | '\u{D8}'..='\u{F6}'
+        | '\u{F8}'..='\u{2FF}'
+        | '\u{370}'..='\u{37D}'
+        | '\u{37F}'..='\u{1FFF}'
+        | '\u{200C}'..='\u{200D}'
+        | '\u{2070}'..='\u{218F}'
+        | '\u{2C00}'..='\u{2FEF}'
+        | '\u{3001}'..='\u{D7FF}'
+        | '\u{F900}'..='\u{FDCF}'
+        | '\u{FDF0}'..='\u{FFFD}'
+        | '\u{10000}'..='\u{EFFFF}'
+    )
+}
+
+/// Return whether `c` matches the `NameChar` production.
+pub fn is_name_char(c: char) -> bool {
+    match c {
+        '-' | '.' | '0'..='9' | '\u{B7}' | '\u{300}'..='\u{36F}' | '\u{203F}'..='\u{2040}' => true,
+        _ => is_name_start_char(c),
+    }
+}
+
+/// Return whether `s` matches the `Name` production.
+pub fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if is_name_start_char(c) => chars.all(is_name_char),
+        _ => false,
+    }
+}
+
+/// Return whether `s` matches the `Nmtoken` production.
+pub fn is_nmtoken(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(is_name_char)
+}
+
+/// Return whether `s` matches the `NCName` production of Namespaces in XML, i.e. it is a
+/// `Name` without any colon.
+pub fn is_ncname(s: &str) -> bool {
+    !s.contains(':') && is_name(s)
+}
+
+/// Return whether `s` matches the `QName` production of Namespaces in XML: either an `NCName`,
+/// or two `NCName`s separated by a single colon.
+pub fn is_qname(s: &str) -> bool {
+    match s.find(':') {
+        Some(i) => is_ncname(&s[..i]) && is_ncname(&s[i + 1..]),
+        None => is_ncname(s),
+    }
+}
+
+/// Return whether `c` matches the `S` (white space) production.
+pub fn is_whitespace(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\r' || c == '\n'
+}
+
+/// Return whether `c` matches the `Char` production of XML 1.0.
+pub fn is_char(c: char) -> bool {
+    matches!(c,
+        '\t' | '\n' | '\r'
+        | '\u{20}'..='\u{D7FF}'
+        | '\u{E000}'..='\u{FFFD}'
+        | '\u{10000}'..='\u{10FFFF}'
+    )
+}
+
+/// Return whether `c` matches the `Char` production of XML 1.1, which admits every character
+/// but NUL, U+FFFE and U+FFFF.
+pub fn is_char_1_1(c: char) -> bool {
+    matches!(c,
+        '\u{1}'..='\u{D7FF}'
+        | '\u{E000}'..='\u{FFFD}'
+        | '\u{10000}'..='\u{10FFFF}'
+    )
+}
+
+/// Return whether `c` matches the `RestrictedChar` production of XML 1.1: the C0 and C1
+/// control characters other than tab, line feed, carriage return and NEL, which may only appear
+/// in XML 1.1 documents as character references.
+pub fn is_restricted_char(c: char) -> bool {
+    matches!(c,
+        '\u{1}'..='\u{8}'
+        | '\u{B}'..='\u{C}'
+        | '\u{E}'..='\u{1F}'
+        | '\u{7F}'..='\u{84}'
+        | '\u{86}'..='\u{9F}'
+    )
+}
@@ -0,0 +1,549 @@
+//! Namespace processing and well-formedness checking, as specified by Namespaces in XML.
+//!
+//! A reader processes Namespaces by declaring the prefixes bound by `xmlns*` attributes,
+//! resolving the prefixes of element and attribute names, and checking the constraints of the
+//! recommendation, any violation of which is a fatal error:
+//!
+//! * element and attribute names are `QName`s, and processing instruction targets and entity
+//!   names contain no colon;
+//! * every prefix used is declared;
+//! * the `xml` prefix is only bound to its Namespace, which no other prefix is bound to, and
+//!   the `xmlns` prefix and its Namespace are never bound;
+//! * a prefix is not undeclared with an empty URI, except in XML 1.1 documents, to which
+//!   Namespaces in XML 1.1 applies;
+//! * no two attributes of an element have the same Namespace URI and local name.
+//!
+//! `NamespaceFilter` does all of this on the events of a reader that does not process
+//! Namespaces itself, or in front of a handler that needs the checks.
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use common;
+use common::Locator;
+use features;
+use helpers::AttributesImpl;
+use helpers::NamespaceSupport;
+use helpers::ParseErrorImpl;
+use helpers::NSDECL;
+use helpers::XMLNS;
+use names;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::ErrorHandler;
+use sax2::Result;
+use version::XmlVersion;
+
+struct State {
+    support: NamespaceSupport,
+    /// The names of the open elements, innermost last.
+    open: Vec<(String, String, String)>,
+    namespace_prefixes: bool,
+    xmlns_uris: bool,
+    version: XmlVersion,
+}
+
+/// A filter performing Namespace processing for the handler it wraps.
+///
+/// The reader should have the http://xml.org/sax/features/namespaces feature set to false and
+/// the http://xml.org/sax/features/namespace-prefixes feature set to true, so that it reports
+/// qualified names and `xmlns*` attributes as they appear; Namespace URIs and local names it
+/// reports are ignored. The handler then receives the events a Namespace-aware reader would
+/// report: `start_prefix_mapping` and `end_prefix_mapping` around each element declaring
+/// prefixes, and element and attribute names with their Namespace URI and local name.
+///
+/// The `features::NAMESPACE_PREFIXES` and `features::XMLNS_URIS` features of the filter decide
+/// whether `xmlns*` attributes are passed on, and in which Namespace. Violations of Namespace
+/// constraints are reported through `ErrorHandler::fatal_error`, and then returned as errors
+/// to stop the parse.
+pub struct NamespaceFilter<L, CH, EH> {
+    handler: CH,
+    error_handler: EH,
+    locator: RefCell<Option<Rc<L>>>,
+    state: RefCell<State>,
+}
+
+impl<L: Locator, CH, EH: ErrorHandler<ParseErrorImpl>> NamespaceFilter<L, CH, EH> {
+    /// Construct a filter passing events to a handler and reporting Namespace errors to
+    /// `error_handler`.
+    pub fn new(handler: CH, error_handler: EH) -> Self {
+        NamespaceFilter {
+            handler,
+            error_handler,
+            locator: RefCell::new(None),
+            state: RefCell::new(State {
+                support: NamespaceSupport::new(),
+                open: Vec::new(),
+                namespace_prefixes: false,
+                xmlns_uris: false,
+                version: XmlVersion::V1_0,
+            }),
+        }
+    }
+
+    /// Return the wrapped handler.
+    pub fn get_content_handler(&self) -> &CH {
+        &self.handler
+    }
+
+    /// Return the error handler.
+    pub fn get_error_handler(&self) -> &EH {
+        &self.error_handler
+    }
+
+    /// Return the value of a feature, or `None` if the feature name is not one the filter
+    /// recognizes.
+    pub fn get_feature(&self, name: &str) -> Option<bool> {
+        let state = self.state.borrow();
+        match name {
+            features::NAMESPACES => Some(true),
+            features::NAMESPACE_PREFIXES => Some(state.namespace_prefixes),
+            features::XMLNS_URIS => Some(state.xmlns_uris),
+            _ => None,
+        }
+    }
+
+    /// Set the value of a feature, before the document starts. Returns false if the feature
+    /// name is not one the filter recognizes, or if it is `features::NAMESPACES`, which cannot
+    /// be turned off.
+    pub fn set_feature(&self, name: &str, value: bool) -> bool {
+        let mut state = self.state.borrow_mut();
+        match name {
+            features::NAMESPACES => value,
+            features::NAMESPACE_PREFIXES => {
+                state.namespace_prefixes = value;
+                true
+            }
+            features::XMLNS_URIS => {
+                state.xmlns_uris = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Set the version of the documents to be processed, as reported by the reader's
+    /// `ext::Locator2`. In XML 1.1 documents, `xmlns:prefix=""` undeclares a prefix, and
+    /// `start_prefix_mapping` reports it with an empty URI.
+    pub fn set_xml_version(&self, version: XmlVersion) {
+        self.state.borrow_mut().version = version;
+    }
+
+    fn fatal_error(&self, message: String) -> Box<dyn common::Error> {
+        let locator = self.locator.borrow();
+        let error = ParseErrorImpl::at(message, locator.as_ref().map(|l| &**l));
+        match self.error_handler.fatal_error(&error) {
+            Ok(()) => Box::new(error),
+            Err(e) => e,
+        }
+    }
+
+    /// Declare the prefixes bound by the `xmlns*` attributes of an element, and return them
+    /// in document order.
+    fn declare<A: Attributes>(&self, state: &mut State, attributes: &A) -> Result<Vec<String>> {
+        let mut declared = Vec::new();
+        for index in 0..attributes.get_length() as u64 {
+            let q_name = attributes.get_q_name(index).unwrap_or_default();
+            let prefix = if q_name == "xmlns" {
+                ""
+            } else if let Some(prefix) = q_name.strip_prefix("xmlns:") {
+                prefix
+            } else {
+                continue;
+            };
+            let uri = attributes.get_value(index).unwrap_or_default();
+            if !prefix.is_empty() && !names::is_ncname(prefix) {
+                return Err(self.fatal_error(format!("\"{}\" is not a valid prefix", prefix)));
+            }
+            match prefix {
+                "xmlns" => {
+                    return Err(self.fatal_error("the xmlns prefix must not be declared".to_owned()))
+                }
+                "xml" if uri != XMLNS => {
+                    return Err(self
+                        .fatal_error(format!("the xml prefix must not be bound to \"{}\"", uri)))
+                }
+                "xml" => continue,
+                _ => {}
+            }
+            if uri == XMLNS || uri == NSDECL {
+                return Err(self.fatal_error(format!(
+                    "the Namespace \"{}\" must not be bound to {}",
+                    uri,
+                    if prefix.is_empty() {
+                        "the default Namespace".to_owned()
+                    } else {
+                        format!("the prefix {}", prefix)
+                    }
+                )));
+            }
+            if !prefix.is_empty() && uri.is_empty() && state.version == XmlVersion::V1_0 {
+                return Err(
+                    self.fatal_error(format!("the prefix {} must not be undeclared", prefix))
+                );
+            }
+            state.support.declare_prefix(prefix, &uri);
+            declared.push(prefix.to_owned());
+        }
+        Ok(declared)
+    }
+
+    /// Return the Namespace-processed attributes of an element.
+    fn process_attributes<A: Attributes>(
+        &self,
+        state: &State,
+        attributes: &A,
+    ) -> Result<AttributesImpl> {
+        let mut processed = AttributesImpl::new();
+        // Declarations may share an expanded name with other attributes when they are not
+        // reported in the `NSDECL` Namespace, so they are left out of the duplicate check.
+        let mut names = HashSet::new();
+        for index in 0..attributes.get_length() as u64 {
+            let q_name = attributes.get_q_name(index).unwrap_or_default();
+            let is_declaration = q_name == "xmlns" || q_name.starts_with("xmlns:");
+            if is_declaration && !state.namespace_prefixes {
+                continue;
+            }
+            if !names::is_qname(&q_name) {
+                return Err(
+                    self.fatal_error(format!("attribute name \"{}\" is not a QName", q_name))
+                );
+            }
+            let (uri, local_name, _) = match state.support.process_name(&q_name, true) {
+                Some(name) => name,
+                None => {
+                    return Err(self.fatal_error(format!(
+                        "the prefix of attribute {} is not declared",
+                        q_name
+                    )))
+                }
+            };
+            if !is_declaration && !names.insert((uri.clone(), local_name.clone())) {
+                return Err(self.fatal_error(format!(
+                    "attribute {{{}}}{} is specified more than once",
+                    uri, local_name
+                )));
+            }
+            processed.add_attribute(
+                &uri,
+                &local_name,
+                &q_name,
+                &attributes
+                    .get_type(index)
+                    .unwrap_or_else(|| "CDATA".to_owned()),
+                &attributes.get_value(index).unwrap_or_default(),
+            );
+        }
+        Ok(processed)
+    }
+
+    fn check_no_colon(&self, what: &str, name: &str) -> Result<()> {
+        if name.contains(':') {
+            Err(self.fatal_error(format!("{} \"{}\" contains a colon", what, name)))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<L, A, CH, EH> ContentHandler<L, A> for NamespaceFilter<L, CH, EH>
+where
+    L: Locator,
+    A: Attributes,
+    CH: ContentHandler<L, AttributesImpl>,
+    EH: ErrorHandler<ParseErrorImpl>,
+{
+    fn characters(&self, content: &str) -> Result<()> {
+        self.handler.characters(content)
+    }
+    fn end_document(&self) -> Result<()> {
+        self.handler.end_document()
+    }
+    fn end_element(&self, _uri: &str, _local_name: &str, _q_name: &str) -> Result<()> {
+        let (name, declared) = {
+            let mut state = self.state.borrow_mut();
+            let name = state.open.pop().unwrap_or_default();
+            let declared = state.support.get_declared_prefixes();
+            state.support.pop_context();
+            (name, declared)
+        };
+        self.handler.end_element(&name.0, &name.1, &name.2)?;
+        for prefix in &declared {
+            self.handler.end_prefix_mapping(prefix)?;
+        }
+        Ok(())
+    }
+    fn end_prefix_mapping(&self, _prefix: &str) -> Result<()> {
+        Ok(())
+    }
+    fn ignorable_whitespace(&self, content: &str) -> Result<()> {
+        self.handler.ignorable_whitespace(content)
+    }
+    fn processing_instruction(&self, target: &str, data: &str) -> Result<()> {
+        self.check_no_colon("processing instruction target", target)?;
+        self.handler.processing_instruction(target, data)
+    }
+    fn set_document_locator(&self, locator: Rc<L>) {
+        *self.locator.borrow_mut() = Some(locator.clone());
+        self.handler.set_document_locator(locator);
+    }
+    fn skipped_entity(&self, name: &str) -> Result<()> {
+        self.check_no_colon("entity name", name.trim_start_matches('%'))?;
+        self.handler.skipped_entity(name)
+    }
+    fn start_document(&self) -> Result<()> {
+        {
+            let mut guard = self.state.borrow_mut();
+            let state = &mut *guard;
+            state.support.reset();
+            state.support.set_namespace_decl_uris(state.xmlns_uris);
+            state.open.clear();
+        }
+        self.handler.start_document()
+    }
+    fn start_element(
+        &self,
+        _uri: &str,
+        _local_name: &str,
+        q_name: &str,
+        attributes: A,
+    ) -> Result<()> {
+        let mut guard = self.state.borrow_mut();
+        let state = &mut *guard;
+        state.support.push_context();
+        let declared = self.declare(state, &attributes)?;
+        if !names::is_qname(q_name) {
+            return Err(self.fatal_error(format!("element name \"{}\" is not a QName", q_name)));
+        }
+        if q_name.starts_with("xmlns:") {
+            return Err(
+                self.fatal_error(format!("element name \"{}\" has the xmlns prefix", q_name))
+            );
+        }
+        let name = match state.support.process_name(q_name, false) {
+            Some(name) => name,
+            None => {
+                return Err(
+                    self.fatal_error(format!("the prefix of element {} is not declared", q_name))
+                )
+            }
+        };
+        let processed = self.process_attributes(state, &attributes)?;
+        let mappings: Vec<(String, String)> = declared
+            .into_iter()
+            .map(|prefix| {
+                let uri = state.support.get_uri(&prefix).unwrap_or("").to_owned();
+                (prefix, uri)
+            })
+            .collect();
+        state.open.push(name.clone());
+        drop(guard);
+        for (prefix, uri) in &mappings {
+            self.handler.start_prefix_mapping(prefix, uri)?;
+        }
+        self.handler
+            .start_element(&name.0, &name.1, &name.2, processed)
+    }
+    fn start_prefix_mapping(&self, _prefix: &str, _uri: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event::Event;
+    use event::EventRecorder;
+    use helpers::LocatorImpl;
+
+    #[derive(Default)]
+    struct Errors(RefCell<Vec<String>>);
+
+    impl ErrorHandler<ParseErrorImpl> for Errors {
+        fn fatal_error(&self, e: &ParseErrorImpl) -> Result<()> {
+            self.0.borrow_mut().push(e.to_string());
+            Ok(())
+        }
+    }
+
+    type Filter = NamespaceFilter<LocatorImpl, EventRecorder<LocatorImpl>, Errors>;
+
+    fn prefixes_filter() -> Filter {
+        let filter = NamespaceFilter::new(EventRecorder::new(), Errors::default());
+        filter.set_feature(features::NAMESPACE_PREFIXES, true);
+        filter
+    }
+
+    fn attributes(pairs: &[(&str, &str)]) -> AttributesImpl {
+        let mut attributes = AttributesImpl::new();
+        for &(q_name, value) in pairs {
+            attributes.add_attribute("", "", q_name, "CDATA", value);
+        }
+        attributes
+    }
+
+    fn start(filter: &Filter, q_name: &str, pairs: &[(&str, &str)]) -> Result<()> {
+        ContentHandler::<LocatorImpl, AttributesImpl>::start_element(
+            filter,
+            "",
+            "",
+            q_name,
+            attributes(pairs),
+        )
+    }
+
+    fn start_document(filter: &Filter) {
+        ContentHandler::<LocatorImpl, AttributesImpl>::start_document(filter).unwrap();
+    }
+
+    #[test]
+    fn declaration_and_attribute_with_the_same_local_name() {
+        let filter = prefixes_filter();
+        start_document(&filter);
+        start(&filter, "e", &[("xmlns:a", "urn:x"), ("a", "1")]).unwrap();
+        assert!(filter.get_error_handler().0.borrow().is_empty());
+        let events = filter.get_content_handler().take_events();
+        match events.last() {
+            Some(Event::StartElement { attributes, .. }) => {
+                assert_eq!(attributes.get_length(), 2);
+                assert_eq!(attributes.get_q_name_value("a").as_deref(), Some("1"));
+                assert_eq!(
+                    attributes.get_q_name_value("xmlns:a").as_deref(),
+                    Some("urn:x")
+                );
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn declarations_in_the_nsdecl_namespace() {
+        let filter = prefixes_filter();
+        filter.set_feature(features::XMLNS_URIS, true);
+        start_document(&filter);
+        start(&filter, "e", &[("xmlns:a", "urn:x"), ("a", "1")]).unwrap();
+        assert!(filter.get_error_handler().0.borrow().is_empty());
+    }
+
+    #[test]
+    fn duplicate_expanded_names() {
+        let filter = prefixes_filter();
+        start_document(&filter);
+        let result = start(
+            &filter,
+            "e",
+            &[
+                ("xmlns:a", "urn:x"),
+                ("xmlns:b", "urn:x"),
+                ("a:n", "1"),
+                ("b:n", "2"),
+            ],
+        );
+        assert!(result.is_err());
+        assert_eq!(
+            *filter.get_error_handler().0.borrow(),
+            ["attribute {urn:x}n is specified more than once"]
+        );
+    }
+
+    #[test]
+    fn prefix_undeclaration() {
+        let filter = prefixes_filter();
+        start_document(&filter);
+        assert!(start(&filter, "e", &[("xmlns:a", "")]).is_err());
+
+        let filter = prefixes_filter();
+        filter.set_xml_version(XmlVersion::V1_1);
+        start_document(&filter);
+        start(&filter, "a:e", &[("xmlns:a", "urn:x")]).unwrap();
+        start(&filter, "f", &[("xmlns:a", "")]).unwrap();
+        assert!(start(&filter, "a:g", &[]).is_err());
+        let events = filter.get_content_handler().take_events();
+        assert!(events.contains(&Event::StartPrefixMapping {
+            prefix: "a".to_owned(),
+            uri: String::new(),
+        }));
+    }
+
+    #[test]
+    fn reserved_prefixes_and_names() {
+        for pairs in &[
+            &[("xmlns:xmlns", "urn:x")][..],
+            &[("xmlns:xml", "urn:x")][..],
+            &[("xmlns:p", XMLNS)][..],
+            &[("xmlns", NSDECL)][..],
+            &[("xmlns:1p", "urn:x")][..],
+            &[("p:a", "1")][..],
+            &[("a:b:c", "1")][..],
+        ] {
+            let filter = prefixes_filter();
+            start_document(&filter);
+            assert!(start(&filter, "e", pairs).is_err(), "{:?}", pairs);
+            assert_eq!(filter.get_error_handler().0.borrow().len(), 1);
+        }
+        let filter = prefixes_filter();
+        start_document(&filter);
+        start(&filter, "e", &[("xmlns:xml", XMLNS)]).unwrap();
+        assert!(start(&filter, "p:e", &[]).is_err());
+        assert!(
+            ContentHandler::<LocatorImpl, AttributesImpl>::processing_instruction(
+                &filter, "a:b", ""
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn resolves_names_and_reports_mappings() {
+        let filter = NamespaceFilter::new(EventRecorder::new(), Errors::default());
+        start_document(&filter);
+        start(
+            &filter,
+            "p:e",
+            &[("xmlns:p", "urn:p"), ("xmlns", "urn:d"), ("p:a", "1")],
+        )
+        .unwrap();
+        start(&filter, "f", &[("b", "2")]).unwrap();
+        ContentHandler::<LocatorImpl, AttributesImpl>::end_element(&filter, "", "", "f").unwrap();
+        ContentHandler::<LocatorImpl, AttributesImpl>::end_element(&filter, "", "", "p:e").unwrap();
+        let events = filter.get_content_handler().take_events();
+        let summary: Vec<String> = events
+            .iter()
+            .map(|event| match event {
+                Event::StartPrefixMapping { prefix, uri } => format!("+{}={}", prefix, uri),
+                Event::EndPrefixMapping { prefix } => format!("-{}", prefix),
+                Event::StartElement {
+                    uri,
+                    local_name,
+                    attributes,
+                    ..
+                } => format!(
+                    "<{{{}}}{} {}",
+                    uri,
+                    local_name,
+                    attributes
+                        .iter()
+                        .map(|a| format!("{{{}}}{}", a.uri, a.local_name))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                ),
+                Event::EndElement { local_name, .. } => format!("</{}", local_name),
+                other => format!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                "StartDocument",
+                "+p=urn:p",
+                "+=urn:d",
+                "<{urn:p}e {urn:p}a",
+                "<{urn:d}f {}b",
+                "</f",
+                "</e",
+                "-p",
+                "-",
+            ]
+        );
+    }
+}
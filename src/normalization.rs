@@ -0,0 +1,460 @@
+//! Unicode normalization checking, as specified by Appendix B of XML 1.1.
+//!
+//! Text is fully normalized when it is in Unicode Normalization Form C (NFC) and none of its
+//! relevant constructs (character data between markup, attribute values, names, comments and
+//! processing instructions) begins with a composing character, one that could combine with
+//! whatever precedes it. Such text cannot change when concatenated with other fully
+//! normalized text, so that string comparisons of documents give consistent results.
+//!
+//! Readers check it when the http://xml.org/sax/features/unicode-normalization-checking feature
+//! is set, and report text that is not fully normalized as an error, which does not prevent
+//! processing. `NormalizationFilter` does the same on the events of any reader, and can also
+//! normalize character data on its way to the handler it wraps.
+//!
+//! This module is only available with the `normalization` feature of the crate.
+use std::cell::RefCell;
+use std::iter;
+use std::rc::Rc;
+
+use unicode_normalization::char::canonical_combining_class;
+use unicode_normalization::is_nfc;
+use unicode_normalization::is_nfc_quick;
+use unicode_normalization::IsNormalized;
+use unicode_normalization::UnicodeNormalization;
+
+use common::Locator;
+use ext::LexicalHandler;
+use features;
+use helpers::AttributesImpl;
+use helpers::ParseErrorImpl;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::ErrorHandler;
+use sax2::Result;
+
+/// Pass character data on in NFC rather than as the reader reported it (default false).
+pub const NORMALIZE_CHARACTERS: &str =
+    "https://crates.io/crates/xml_parser_traits/features/normalize-characters";
+
+/// Return whether `c` is a composing character: one with a non-zero canonical combining class,
+/// or that is the second character of the canonical decomposition of a primary composite.
+pub fn is_composing_char(c: char) -> bool {
+    canonical_combining_class(c) != 0 || is_nfc_quick(iter::once(c)) == IsNormalized::Maybe
+}
+
+/// Return whether text is fully normalized: in NFC, and not beginning with a composing
+/// character.
+pub fn is_fully_normalized(text: &str) -> bool {
+    !text.starts_with(is_composing_char) && is_nfc(text)
+}
+
+/// Return text in Unicode Normalization Form C.
+///
+/// Text beginning with a composing character remains so, and thus not fully normalized.
+pub fn normalize(text: &str) -> String {
+    if is_nfc(text) {
+        text.to_owned()
+    } else {
+        text.nfc().collect()
+    }
+}
+
+struct State {
+    /// The character data reported since the last markup.
+    text: String,
+    checking: bool,
+    normalizing: bool,
+}
+
+/// A filter checking that the content of documents is fully normalized for the handler it
+/// wraps.
+///
+/// Element and attribute names, attribute values, processing instruction targets and data,
+/// skipped entity names and, when the filter is also registered as the `LexicalHandler`,
+/// comments are checked as they are reported. Character data is collected up to the next
+/// markup and checked as a whole, since a reader may report it in several `characters` events;
+/// the handler receives it in a single event, and errors about it are reported at the position
+/// of that markup. CDATA section and entity boundaries count as markup.
+///
+/// The `features::UNICODE_NORMALIZATION_CHECKING` feature of the filter, true by default,
+/// decides whether text that is not fully normalized is reported through
+/// `ErrorHandler::error`, and the `NORMALIZE_CHARACTERS` feature whether character data is
+/// passed on in NFC. Attribute values and names are always passed on unchanged.
+pub struct NormalizationFilter<L, CH, EH> {
+    handler: CH,
+    error_handler: EH,
+    locator: RefCell<Option<Rc<L>>>,
+    state: RefCell<State>,
+}
+
+impl<L: Locator, CH, EH: ErrorHandler<ParseErrorImpl>> NormalizationFilter<L, CH, EH> {
+    /// Construct a filter passing events to a handler and reporting text that is not fully
+    /// normalized to `error_handler`.
+    pub fn new(handler: CH, error_handler: EH) -> Self {
+        NormalizationFilter {
+            handler,
+            error_handler,
+            locator: RefCell::new(None),
+            state: RefCell::new(State {
+                text: String::new(),
+                checking: true,
+                normalizing: false,
+            }),
+        }
+    }
+
+    /// Return the wrapped handler.
+    pub fn get_content_handler(&self) -> &CH {
+        &self.handler
+    }
+
+    /// Return the error handler.
+    pub fn get_error_handler(&self) -> &EH {
+        &self.error_handler
+    }
+
+    /// Return the value of a feature, or `None` if the feature name is not one the filter
+    /// recognizes.
+    pub fn get_feature(&self, name: &str) -> Option<bool> {
+        let state = self.state.borrow();
+        match name {
+            features::UNICODE_NORMALIZATION_CHECKING => Some(state.checking),
+            NORMALIZE_CHARACTERS => Some(state.normalizing),
+            _ => None,
+        }
+    }
+
+    /// Set the value of a feature. Returns false if the feature name is not one the filter
+    /// recognizes.
+    pub fn set_feature(&self, name: &str, value: bool) -> bool {
+        let mut state = self.state.borrow_mut();
+        match name {
+            features::UNICODE_NORMALIZATION_CHECKING => state.checking = value,
+            NORMALIZE_CHARACTERS => state.normalizing = value,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Report `what` if checking is on and `text` is not fully normalized.
+    fn check<F: FnOnce() -> String>(&self, what: F, text: &str) -> Result<()> {
+        if !self.state.borrow().checking || is_fully_normalized(text) {
+            return Ok(());
+        }
+        let locator = self.locator.borrow();
+        let error = ParseErrorImpl::at(
+            format!("{} is not fully normalized", what()),
+            locator.as_ref().map(|l| &**l),
+        );
+        self.error_handler.error(&error)
+    }
+
+    /// Pass on the character data collected since the last markup.
+    fn flush(&self) -> Result<()>
+    where
+        CH: ContentHandler<L, AttributesImpl>,
+    {
+        let (text, normalizing) = {
+            let mut state = self.state.borrow_mut();
+            (state.text.split_off(0), state.normalizing)
+        };
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.check(|| "character data".to_owned(), &text)?;
+        if normalizing {
+            self.handler.characters(&normalize(&text))
+        } else {
+            self.handler.characters(&text)
+        }
+    }
+}
+
+impl<L, A, CH, EH> ContentHandler<L, A> for NormalizationFilter<L, CH, EH>
+where
+    L: Locator,
+    A: Attributes,
+    CH: ContentHandler<L, AttributesImpl>,
+    EH: ErrorHandler<ParseErrorImpl>,
+{
+    fn characters(&self, content: &str) -> Result<()> {
+        self.state.borrow_mut().text.push_str(content);
+        Ok(())
+    }
+    fn end_document(&self) -> Result<()> {
+        self.flush()?;
+        self.handler.end_document()
+    }
+    fn end_element(&self, uri: &str, local_name: &str, q_name: &str) -> Result<()> {
+        self.flush()?;
+        self.handler.end_element(uri, local_name, q_name)
+    }
+    fn end_prefix_mapping(&self, prefix: &str) -> Result<()> {
+        self.handler.end_prefix_mapping(prefix)
+    }
+    fn ignorable_whitespace(&self, content: &str) -> Result<()> {
+        self.flush()?;
+        self.handler.ignorable_whitespace(content)
+    }
+    fn processing_instruction(&self, target: &str, data: &str) -> Result<()> {
+        self.flush()?;
+        self.check(
+            || format!("the target of processing instruction {}", target),
+            target,
+        )?;
+        self.check(
+            || format!("the data of processing instruction {}", target),
+            data,
+        )?;
+        self.handler.processing_instruction(target, data)
+    }
+    fn set_document_locator(&self, locator: Rc<L>) {
+        *self.locator.borrow_mut() = Some(locator.clone());
+        self.handler.set_document_locator(locator);
+    }
+    fn skipped_entity(&self, name: &str) -> Result<()> {
+        self.flush()?;
+        self.check(|| format!("the name of entity {}", name), name)?;
+        self.handler.skipped_entity(name)
+    }
+    fn start_document(&self) -> Result<()> {
+        self.state.borrow_mut().text.clear();
+        self.handler.start_document()
+    }
+    fn start_element(
+        &self,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attributes: A,
+    ) -> Result<()> {
+        self.flush()?;
+        self.check(|| format!("the name of element {}", q_name), q_name)?;
+        let attributes = AttributesImpl::from_attributes(&attributes);
+        for attribute in &attributes {
+            self.check(
+                || format!("the name of attribute {}", attribute.q_name),
+                &attribute.q_name,
+            )?;
+            self.check(
+                || format!("the value of attribute {}", attribute.q_name),
+                &attribute.value,
+            )?;
+        }
+        self.handler
+            .start_element(uri, local_name, q_name, attributes)
+    }
+    fn start_prefix_mapping(&self, prefix: &str, uri: &str) -> Result<()> {
+        self.flush()?;
+        self.handler.start_prefix_mapping(prefix, uri)
+    }
+}
+
+impl<L, CH, EH> LexicalHandler for NormalizationFilter<L, CH, EH>
+where
+    L: Locator,
+    CH: ContentHandler<L, AttributesImpl> + LexicalHandler,
+    EH: ErrorHandler<ParseErrorImpl>,
+{
+    fn comment(&self, content: &str) -> Result<()> {
+        self.flush()?;
+        self.check(|| "comment".to_owned(), content)?;
+        self.handler.comment(content)
+    }
+    fn end_cdata(&self) -> Result<()> {
+        self.flush()?;
+        self.handler.end_cdata()
+    }
+    fn end_dtd(&self) -> Result<()> {
+        self.handler.end_dtd()
+    }
+    fn end_entity(&self, name: &str) -> Result<()> {
+        self.flush()?;
+        self.handler.end_entity(name)
+    }
+    fn start_cdata(&self) -> Result<()> {
+        self.flush()?;
+        self.handler.start_cdata()
+    }
+    fn start_dtd(
+        &self,
+        name: &str,
+        public_id: Option<&str>,
+        system_id: Option<&str>,
+    ) -> Result<()> {
+        self.handler.start_dtd(name, public_id, system_id)
+    }
+    fn start_entity(&self, name: &str) -> Result<()> {
+        self.flush()?;
+        self.handler.start_entity(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event::Event;
+    use event::EventRecorder;
+    use helpers::LocatorImpl;
+
+    #[derive(Default)]
+    struct Errors(RefCell<Vec<String>>);
+
+    impl ErrorHandler<ParseErrorImpl> for Errors {
+        fn error(&self, e: &ParseErrorImpl) -> Result<()> {
+            self.0.borrow_mut().push(e.to_string());
+            Ok(())
+        }
+    }
+
+    type Filter = NormalizationFilter<LocatorImpl, EventRecorder<LocatorImpl>, Errors>;
+
+    fn filter(normalizing: bool) -> Filter {
+        let filter = NormalizationFilter::new(EventRecorder::new(), Errors::default());
+        filter.set_feature(NORMALIZE_CHARACTERS, normalizing);
+        filter
+    }
+
+    fn start(pairs: &[(&str, &str)]) -> Event {
+        let mut attributes = AttributesImpl::new();
+        for &(q_name, value) in pairs {
+            attributes.add_attribute("", q_name, q_name, "CDATA", value);
+        }
+        Event::start_element("", "e", "e", &attributes)
+    }
+
+    fn run(filter: &Filter, events: Vec<Event>) -> (Vec<Event>, Vec<String>) {
+        Event::StartDocument.dispatch(filter).unwrap();
+        for event in events {
+            event.dispatch(filter).unwrap();
+        }
+        Event::EndDocument.dispatch(filter).unwrap();
+        let mut events = filter.get_content_handler().take_events();
+        events.retain(|e| *e != Event::StartDocument && *e != Event::EndDocument);
+        (events, filter.get_error_handler().0.take())
+    }
+
+    fn characters(text: &str) -> Event {
+        Event::Characters(text.to_owned())
+    }
+
+    #[test]
+    fn full_normalization() {
+        assert!(is_fully_normalized("caf\u{E9}"));
+        assert!(!is_fully_normalized("cafe\u{301}"));
+        assert!(!is_fully_normalized("\u{301}a"));
+        assert!(!is_fully_normalized("\u{338}"));
+        assert!(is_composing_char('\u{301}'));
+        assert!(!is_composing_char('e'));
+        assert_eq!(normalize("cafe\u{301}"), "caf\u{E9}");
+        assert_eq!(normalize("\u{301}a"), "\u{301}a");
+    }
+
+    #[test]
+    fn character_data_is_normalized() {
+        let (events, errors) = run(
+            &filter(true),
+            vec![
+                start(&[]),
+                characters("cafe\u{301}"),
+                Event::end_element("", "e", "e"),
+            ],
+        );
+        assert_eq!(
+            events,
+            [
+                start(&[]),
+                characters("caf\u{E9}"),
+                Event::end_element("", "e", "e")
+            ]
+        );
+        assert_eq!(errors, ["character data is not fully normalized"]);
+    }
+
+    #[test]
+    fn character_data_is_passed_on_unchanged_by_default() {
+        let (events, errors) = run(&filter(false), vec![characters("cafe\u{301}")]);
+        assert_eq!(events, [characters("cafe\u{301}")]);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn text_split_across_characters_events() {
+        let (events, errors) = run(
+            &filter(true),
+            vec![
+                start(&[]),
+                characters("caf"),
+                characters("e"),
+                characters("\u{301} au lait"),
+                Event::end_element("", "e", "e"),
+            ],
+        );
+        assert_eq!(events[1..2], [characters("caf\u{E9} au lait")]);
+        assert_eq!(errors.len(), 1);
+
+        let (events, errors) = run(
+            &filter(true),
+            vec![characters("caf\u{E9}"), characters(" cr\u{E8}me")],
+        );
+        assert_eq!(events, [characters("caf\u{E9} cr\u{E8}me")]);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn composing_character_after_markup() {
+        let (events, errors) = run(
+            &filter(true),
+            vec![
+                characters("e"),
+                start(&[]),
+                characters("\u{301}"),
+                Event::end_element("", "e", "e"),
+            ],
+        );
+        assert_eq!(events[2], characters("\u{301}"));
+        assert_eq!(errors, ["character data is not fully normalized"]);
+    }
+
+    #[test]
+    fn attribute_values_and_names() {
+        let (events, errors) = run(
+            &filter(true),
+            vec![start(&[
+                ("a", "cafe\u{301}"),
+                ("b", "caf\u{E9}"),
+                ("\u{E9}", ""),
+            ])],
+        );
+        assert_eq!(
+            events,
+            [start(&[
+                ("a", "cafe\u{301}"),
+                ("b", "caf\u{E9}"),
+                ("\u{E9}", "")
+            ])]
+        );
+        assert_eq!(errors, ["the value of attribute a is not fully normalized"]);
+
+        let (_, errors) = run(&filter(false), vec![start(&[("e\u{301}", "\u{301}")])]);
+        assert_eq!(
+            errors,
+            [
+                "the name of attribute e\u{301} is not fully normalized",
+                "the value of attribute e\u{301} is not fully normalized",
+            ]
+        );
+    }
+
+    #[test]
+    fn checking_can_be_turned_off() {
+        let filter = filter(true);
+        filter.set_feature(features::UNICODE_NORMALIZATION_CHECKING, false);
+        let (events, errors) = run(
+            &filter,
+            vec![start(&[("a", "\u{301}")]), characters("e\u{301}")],
+        );
+        assert_eq!(events[1], characters("\u{E9}"));
+        assert!(errors.is_empty());
+    }
+}
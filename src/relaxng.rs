@@ -0,0 +1,2312 @@
+//! RELAX NG validation of SAX event streams.
+//!
+//! A RELAX NG schema is loaded into a `Grammar`, either from the XML syntax, by parsing the
+//! schema document with any `XmlReader` and a `SchemaBuilder` as content handler, or from the
+//! compact syntax with `Grammar::parse_compact`. A `Validator` then checks the events of
+//! instance documents against the grammar, using the derivative algorithm described by James
+//! Clark in "An algorithm for RELAX NG validation", and reports violations through an
+//! `ErrorHandler`, at the position given by the document locator.
+//!
+//! Datatypes from the built-in library (`string` and `token`) and from the XML Schema datatype
+//! library are supported, with all the parameters of the latter, including `pattern`.
+//! `include`, `externalRef` and `external` are not supported, since they require loading
+//! further schema documents; such schemas must be combined beforehand.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use common::Locator;
+use helpers::LocatorImpl;
+use helpers::ParseErrorImpl;
+use helpers::NSDECL;
+use helpers::XMLNS;
+use names;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::ErrorHandler;
+use sax2::Result;
+use xsd::regex::Regex;
+use xsd::BuiltinType;
+use xsd::Facets;
+use xsd::SimpleType;
+use xsd::WhiteSpace;
+
+/// The RELAX NG Namespace URI.
+pub const RELAXNG_NS: &str = "http://relaxng.org/ns/structure/1.0";
+
+/// The URI of the XML Schema datatype library.
+pub const XSD_DATATYPES: &str = "http://www.w3.org/2001/XMLSchema-datatypes";
+
+type SchemaResult<T> = std::result::Result<T, ParseErrorImpl>;
+
+/// A set of names, against which element and attribute names are matched.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NameClass {
+    /// Any name, optionally except those in the given name class.
+    AnyName(Option<Box<NameClass>>),
+    /// Any name in the given Namespace, optionally except those in the given name class.
+    NsName(String, Option<Box<NameClass>>),
+    /// The name with the given Namespace URI and local name.
+    Name(String, String),
+    /// The names in either of two name classes.
+    Choice(Box<NameClass>, Box<NameClass>),
+}
+
+impl NameClass {
+    /// Return whether the name class contains the given name.
+    pub fn contains(&self, uri: &str, local_name: &str) -> bool {
+        match self {
+            NameClass::AnyName(except) => except
+                .as_ref()
+                .is_none_or(|except| !except.contains(uri, local_name)),
+            NameClass::NsName(ns, except) => {
+                ns == uri
+                    && except
+                        .as_ref()
+                        .is_none_or(|except| !except.contains(uri, local_name))
+            }
+            NameClass::Name(ns, local) => ns == uri && local == local_name,
+            NameClass::Choice(a, b) => a.contains(uri, local_name) || b.contains(uri, local_name),
+        }
+    }
+}
+
+impl fmt::Display for NameClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NameClass::AnyName(_) => f.write_str("*"),
+            NameClass::NsName(ns, _) => write!(f, "{{{}}}*", ns),
+            NameClass::Name(ns, local) if ns.is_empty() => f.write_str(local),
+            NameClass::Name(ns, local) => write!(f, "{{{}}}{}", ns, local),
+            NameClass::Choice(a, b) => write!(f, "{}|{}", a, b),
+        }
+    }
+}
+
+/// A datatype, against which character content and attribute values are checked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Datatype {
+    /// The `string` type of the built-in library: any string, compared exactly.
+    String,
+    /// The `token` type of the built-in library: any string, compared after collapsing white
+    /// space.
+    Token,
+    /// A type of the XML Schema datatype library, restricted by its parameters.
+    Xsd(Box<SimpleType>),
+}
+
+impl Datatype {
+    /// Return whether a string is a valid literal of the datatype.
+    pub fn allows(&self, literal: &str) -> bool {
+        match self {
+            Datatype::String | Datatype::Token => true,
+            Datatype::Xsd(simple_type) => simple_type.validate(literal).is_ok(),
+        }
+    }
+
+    /// Return whether a string denotes the same value as the expected literal.
+    pub fn equal(&self, expected: &str, literal: &str) -> bool {
+        match self {
+            Datatype::String => expected == literal,
+            Datatype::Token => {
+                WhiteSpace::Collapse.normalize(expected) == WhiteSpace::Collapse.normalize(literal)
+            }
+            Datatype::Xsd(simple_type) => {
+                let facets = Facets {
+                    enumeration: vec![expected.to_owned()],
+                    ..simple_type.facets.clone()
+                };
+                SimpleType::with_facets(simple_type.base, facets)
+                    .validate(literal)
+                    .is_ok()
+            }
+        }
+    }
+}
+
+/// A pattern of the simplified RELAX NG syntax.
+///
+/// References to named patterns are indices into the definitions of the `Grammar`. The `After`
+/// pattern never appears in a schema; it is produced during validation, to hold the pattern
+/// that applies once the current element ends.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    Empty,
+    NotAllowed,
+    Text,
+    Choice(Rc<Pattern>, Rc<Pattern>),
+    Interleave(Rc<Pattern>, Rc<Pattern>),
+    Group(Rc<Pattern>, Rc<Pattern>),
+    OneOrMore(Rc<Pattern>),
+    List(Rc<Pattern>),
+    Data(Datatype, Option<Rc<Pattern>>),
+    Value(Datatype, String),
+    Attribute(NameClass, Rc<Pattern>),
+    Element(NameClass, Rc<Pattern>),
+    Ref(usize),
+    After(Rc<Pattern>, Rc<Pattern>),
+}
+
+impl Pattern {
+    fn is_not_allowed(&self) -> bool {
+        *self == Pattern::NotAllowed
+    }
+
+    fn has_alternative(&self, other: &Pattern) -> bool {
+        match self {
+            _ if self == other => true,
+            Pattern::Choice(a, b) => a.has_alternative(other) || b.has_alternative(other),
+            _ => false,
+        }
+    }
+}
+
+fn empty() -> Rc<Pattern> {
+    Rc::new(Pattern::Empty)
+}
+
+fn not_allowed() -> Rc<Pattern> {
+    Rc::new(Pattern::NotAllowed)
+}
+
+fn choice(a: Rc<Pattern>, b: Rc<Pattern>) -> Rc<Pattern> {
+    if a.is_not_allowed() || b.has_alternative(&a) {
+        b
+    } else if b.is_not_allowed() || a.has_alternative(&b) {
+        a
+    } else {
+        Rc::new(Pattern::Choice(a, b))
+    }
+}
+
+fn group(a: Rc<Pattern>, b: Rc<Pattern>) -> Rc<Pattern> {
+    if a.is_not_allowed() || b.is_not_allowed() {
+        not_allowed()
+    } else if *a == Pattern::Empty {
+        b
+    } else if *b == Pattern::Empty {
+        a
+    } else {
+        Rc::new(Pattern::Group(a, b))
+    }
+}
+
+fn interleave(a: Rc<Pattern>, b: Rc<Pattern>) -> Rc<Pattern> {
+    if a.is_not_allowed() || b.is_not_allowed() {
+        not_allowed()
+    } else if *a == Pattern::Empty {
+        b
+    } else if *b == Pattern::Empty {
+        a
+    } else {
+        Rc::new(Pattern::Interleave(a, b))
+    }
+}
+
+fn after(a: Rc<Pattern>, b: Rc<Pattern>) -> Rc<Pattern> {
+    if a.is_not_allowed() || b.is_not_allowed() {
+        not_allowed()
+    } else {
+        Rc::new(Pattern::After(a, b))
+    }
+}
+
+fn one_or_more(p: Rc<Pattern>) -> Rc<Pattern> {
+    if p.is_not_allowed() {
+        p
+    } else {
+        Rc::new(Pattern::OneOrMore(p))
+    }
+}
+
+fn apply_after<F: Fn(Rc<Pattern>) -> Rc<Pattern>>(f: &F, p: &Rc<Pattern>) -> Rc<Pattern> {
+    match &**p {
+        Pattern::After(a, b) => after(a.clone(), f(b.clone())),
+        Pattern::Choice(a, b) => choice(apply_after(f, a), apply_after(f, b)),
+        _ => not_allowed(),
+    }
+}
+
+fn is_whitespace(s: &str) -> bool {
+    s.chars().all(names::is_whitespace)
+}
+
+/// A compiled RELAX NG schema: a start pattern and the named patterns it refers to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Grammar {
+    start: Rc<Pattern>,
+    defines: Vec<Rc<Pattern>>,
+}
+
+impl Grammar {
+    /// Construct a grammar from a start pattern and the definitions referred to by `Ref`
+    /// patterns.
+    ///
+    /// Returns an error if a reference is out of range, or if a definition refers to itself
+    /// other than through an element pattern.
+    pub fn new(start: Rc<Pattern>, defines: Vec<Rc<Pattern>>) -> SchemaResult<Self> {
+        let grammar = Grammar { start, defines };
+        let mut references = Vec::new();
+        collect_references(&grammar.start, false, &mut references);
+        for define in &grammar.defines {
+            collect_references(define, false, &mut references);
+        }
+        if let Some(index) = references.iter().find(|i| **i >= grammar.defines.len()) {
+            return Err(ParseErrorImpl::new(
+                format!("reference to undefined pattern {}", index),
+                LocatorImpl::new(),
+            ));
+        }
+        let mut state = vec![0u8; grammar.defines.len()];
+        for index in 0..grammar.defines.len() {
+            grammar.check_recursion(index, &mut state)?;
+        }
+        Ok(grammar)
+    }
+
+    /// Parse a schema in the compact syntax.
+    pub fn parse_compact(schema: &str) -> SchemaResult<Self> {
+        let root = CompactParser::new(schema)?.parse()?;
+        Grammar::from_node(&root)
+    }
+
+    /// Return the start pattern.
+    pub fn get_start(&self) -> &Rc<Pattern> {
+        &self.start
+    }
+
+    /// Return the definitions referred to by `Ref` patterns.
+    pub fn get_defines(&self) -> &[Rc<Pattern>] {
+        &self.defines
+    }
+
+    fn from_node(root: &Node) -> SchemaResult<Self> {
+        let mut converter = Converter::default();
+        let start = converter.pattern(root, &Context::default())?;
+        let defines = converter
+            .defines
+            .into_iter()
+            .map(|define| define.unwrap_or_else(not_allowed))
+            .collect();
+        Grammar::new(start, defines)
+    }
+
+    fn check_recursion(&self, index: usize, state: &mut [u8]) -> SchemaResult<()> {
+        match state[index] {
+            1 => {
+                return Err(ParseErrorImpl::new(
+                    "recursive reference not inside an element",
+                    LocatorImpl::new(),
+                ))
+            }
+            2 => return Ok(()),
+            _ => {}
+        }
+        state[index] = 1;
+        let mut references = Vec::new();
+        collect_references(&self.defines[index], true, &mut references);
+        for reference in references {
+            self.check_recursion(reference, state)?;
+        }
+        state[index] = 2;
+        Ok(())
+    }
+
+    fn resolve<'a>(&'a self, p: &'a Rc<Pattern>) -> &'a Rc<Pattern> {
+        match **p {
+            Pattern::Ref(index) => self.resolve(&self.defines[index]),
+            _ => p,
+        }
+    }
+
+    fn nullable(&self, p: &Rc<Pattern>) -> bool {
+        match &**p {
+            Pattern::Empty | Pattern::Text => true,
+            Pattern::Group(a, b) | Pattern::Interleave(a, b) => {
+                self.nullable(a) && self.nullable(b)
+            }
+            Pattern::Choice(a, b) => self.nullable(a) || self.nullable(b),
+            Pattern::OneOrMore(a) => self.nullable(a),
+            Pattern::Ref(_) => self.nullable(self.resolve(p)),
+            _ => false,
+        }
+    }
+
+    /// Derive by character content. When `lenient`, data that does not match its datatype is
+    /// accepted, so that validation can continue after reporting it.
+    fn text_deriv(&self, p: &Rc<Pattern>, text: &str, lenient: bool) -> Rc<Pattern> {
+        match &**p {
+            Pattern::Choice(a, b) => choice(
+                self.text_deriv(a, text, lenient),
+                self.text_deriv(b, text, lenient),
+            ),
+            Pattern::Interleave(a, b) => choice(
+                interleave(self.text_deriv(a, text, lenient), b.clone()),
+                interleave(a.clone(), self.text_deriv(b, text, lenient)),
+            ),
+            Pattern::Group(a, b) => {
+                let derived = group(self.text_deriv(a, text, lenient), b.clone());
+                if self.nullable(a) {
+                    choice(derived, self.text_deriv(b, text, lenient))
+                } else {
+                    derived
+                }
+            }
+            Pattern::After(a, b) => after(self.text_deriv(a, text, lenient), b.clone()),
+            Pattern::OneOrMore(a) => group(
+                self.text_deriv(a, text, lenient),
+                choice(p.clone(), empty()),
+            ),
+            Pattern::Text => p.clone(),
+            Pattern::Value(datatype, value) if datatype.equal(value, text) => empty(),
+            Pattern::Data(datatype, except)
+                if datatype.allows(text)
+                    && except.as_ref().is_none_or(|except| {
+                        !self.nullable(&self.text_deriv(except, text, false))
+                    }) =>
+            {
+                empty()
+            }
+            Pattern::List(a) if self.nullable(&self.list_deriv(a, text)) => empty(),
+            Pattern::Value(..) | Pattern::Data(..) | Pattern::List(_) if lenient => empty(),
+            Pattern::Ref(_) => self.text_deriv(self.resolve(p), text, lenient),
+            _ => not_allowed(),
+        }
+    }
+
+    fn list_deriv(&self, p: &Rc<Pattern>, text: &str) -> Rc<Pattern> {
+        text.split(names::is_whitespace)
+            .filter(|token| !token.is_empty())
+            .fold(p.clone(), |p, token| self.text_deriv(&p, token, false))
+    }
+
+    fn value_match(&self, p: &Rc<Pattern>, value: &str) -> bool {
+        (self.nullable(p) && is_whitespace(value))
+            || self.nullable(&self.text_deriv(p, value, false))
+    }
+
+    fn start_tag_open_deriv(&self, p: &Rc<Pattern>, uri: &str, local_name: &str) -> Rc<Pattern> {
+        match &**p {
+            Pattern::Choice(a, b) => choice(
+                self.start_tag_open_deriv(a, uri, local_name),
+                self.start_tag_open_deriv(b, uri, local_name),
+            ),
+            Pattern::Element(name_class, content) if name_class.contains(uri, local_name) => {
+                after(content.clone(), empty())
+            }
+            Pattern::Interleave(a, b) => choice(
+                apply_after(
+                    &|x| interleave(x, b.clone()),
+                    &self.start_tag_open_deriv(a, uri, local_name),
+                ),
+                apply_after(
+                    &|x| interleave(a.clone(), x),
+                    &self.start_tag_open_deriv(b, uri, local_name),
+                ),
+            ),
+            Pattern::OneOrMore(a) => apply_after(
+                &|x| group(x, choice(p.clone(), empty())),
+                &self.start_tag_open_deriv(a, uri, local_name),
+            ),
+            Pattern::Group(a, b) => {
+                let derived = apply_after(
+                    &|x| group(x, b.clone()),
+                    &self.start_tag_open_deriv(a, uri, local_name),
+                );
+                if self.nullable(a) {
+                    choice(derived, self.start_tag_open_deriv(b, uri, local_name))
+                } else {
+                    derived
+                }
+            }
+            Pattern::After(a, b) => apply_after(
+                &|x| after(x, b.clone()),
+                &self.start_tag_open_deriv(a, uri, local_name),
+            ),
+            Pattern::Ref(_) => self.start_tag_open_deriv(self.resolve(p), uri, local_name),
+            _ => not_allowed(),
+        }
+    }
+
+    fn att_deriv(&self, p: &Rc<Pattern>, uri: &str, local_name: &str, value: &str) -> Rc<Pattern> {
+        match &**p {
+            Pattern::After(a, b) => after(self.att_deriv(a, uri, local_name, value), b.clone()),
+            Pattern::Choice(a, b) => choice(
+                self.att_deriv(a, uri, local_name, value),
+                self.att_deriv(b, uri, local_name, value),
+            ),
+            Pattern::Group(a, b) => choice(
+                group(self.att_deriv(a, uri, local_name, value), b.clone()),
+                group(a.clone(), self.att_deriv(b, uri, local_name, value)),
+            ),
+            Pattern::Interleave(a, b) => choice(
+                interleave(self.att_deriv(a, uri, local_name, value), b.clone()),
+                interleave(a.clone(), self.att_deriv(b, uri, local_name, value)),
+            ),
+            Pattern::OneOrMore(a) => group(
+                self.att_deriv(a, uri, local_name, value),
+                choice(p.clone(), empty()),
+            ),
+            Pattern::Attribute(name_class, content)
+                if name_class.contains(uri, local_name) && self.value_match(content, value) =>
+            {
+                empty()
+            }
+            Pattern::Ref(_) => self.att_deriv(self.resolve(p), uri, local_name, value),
+            _ => not_allowed(),
+        }
+    }
+
+    /// Derive by the end of the attributes. When `lenient`, missing attributes are treated as
+    /// if they were present, so that validation can continue after reporting them.
+    fn start_tag_close_deriv(&self, p: &Rc<Pattern>, lenient: bool) -> Rc<Pattern> {
+        match &**p {
+            Pattern::After(a, b) => after(self.start_tag_close_deriv(a, lenient), b.clone()),
+            Pattern::Choice(a, b) => choice(
+                self.start_tag_close_deriv(a, lenient),
+                self.start_tag_close_deriv(b, lenient),
+            ),
+            Pattern::Group(a, b) => group(
+                self.start_tag_close_deriv(a, lenient),
+                self.start_tag_close_deriv(b, lenient),
+            ),
+            Pattern::Interleave(a, b) => interleave(
+                self.start_tag_close_deriv(a, lenient),
+                self.start_tag_close_deriv(b, lenient),
+            ),
+            Pattern::OneOrMore(a) => one_or_more(self.start_tag_close_deriv(a, lenient)),
+            Pattern::Attribute(..) if lenient => empty(),
+            Pattern::Attribute(..) => not_allowed(),
+            Pattern::Ref(_) => self.start_tag_close_deriv(self.resolve(p), lenient),
+            _ => p.clone(),
+        }
+    }
+
+    /// Derive by an end tag. When `lenient`, missing content is ignored, so that validation
+    /// can continue after reporting it.
+    fn end_tag_deriv(&self, p: &Rc<Pattern>, lenient: bool) -> Rc<Pattern> {
+        match &**p {
+            Pattern::Choice(a, b) => choice(
+                self.end_tag_deriv(a, lenient),
+                self.end_tag_deriv(b, lenient),
+            ),
+            Pattern::After(a, b) if lenient || self.nullable(a) => b.clone(),
+            _ => not_allowed(),
+        }
+    }
+
+    /// Collect the name classes of the elements that may start at this point.
+    fn expected_elements(&self, p: &Rc<Pattern>, expected: &mut Vec<NameClass>) {
+        match &**p {
+            Pattern::Choice(a, b) | Pattern::Interleave(a, b) => {
+                self.expected_elements(a, expected);
+                self.expected_elements(b, expected);
+            }
+            Pattern::Group(a, b) => {
+                self.expected_elements(a, expected);
+                if self.nullable(a) {
+                    self.expected_elements(b, expected);
+                }
+            }
+            Pattern::OneOrMore(a) | Pattern::After(a, _) => self.expected_elements(a, expected),
+            Pattern::Element(name_class, _) if !expected.contains(name_class) => {
+                expected.push(name_class.clone())
+            }
+            Pattern::Ref(_) => self.expected_elements(self.resolve(p), expected),
+            _ => {}
+        }
+    }
+
+    /// Collect the name classes of the attributes that may still occur at this point.
+    fn expected_attributes(&self, p: &Rc<Pattern>, expected: &mut Vec<NameClass>) {
+        match &**p {
+            Pattern::Choice(a, b) | Pattern::Interleave(a, b) | Pattern::Group(a, b) => {
+                self.expected_attributes(a, expected);
+                self.expected_attributes(b, expected);
+            }
+            Pattern::OneOrMore(a) | Pattern::After(a, _) => self.expected_attributes(a, expected),
+            Pattern::Attribute(name_class, _) if !expected.contains(name_class) => {
+                expected.push(name_class.clone())
+            }
+            Pattern::Ref(_) => self.expected_attributes(self.resolve(p), expected),
+            _ => {}
+        }
+    }
+}
+
+fn collect_references(p: &Pattern, stop_at_elements: bool, references: &mut Vec<usize>) {
+    match p {
+        Pattern::Ref(index) => references.push(*index),
+        Pattern::Element(..) if stop_at_elements => {}
+        Pattern::Choice(a, b)
+        | Pattern::Interleave(a, b)
+        | Pattern::Group(a, b)
+        | Pattern::After(a, b) => {
+            collect_references(a, stop_at_elements, references);
+            collect_references(b, stop_at_elements, references);
+        }
+        Pattern::OneOrMore(a)
+        | Pattern::List(a)
+        | Pattern::Attribute(_, a)
+        | Pattern::Element(_, a)
+        | Pattern::Data(_, Some(a)) => collect_references(a, stop_at_elements, references),
+        _ => {}
+    }
+}
+
+fn describe(expected: &[NameClass]) -> String {
+    match expected.len() {
+        0 => String::new(),
+        1 => format!("; expected \"{}\"", expected[0]),
+        _ => format!(
+            "; expected one of {}",
+            expected
+                .iter()
+                .map(|name_class| format!("\"{}\"", name_class))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+    }
+}
+
+#[derive(Debug)]
+struct ValidatorState {
+    pattern: Rc<Pattern>,
+    text: String,
+    /// For each open element, whether it has had a child element.
+    open: Vec<bool>,
+    /// The depth of the invalid subtree being skipped, or zero.
+    skip_depth: usize,
+    error_count: usize,
+}
+
+/// Validates a document against a RELAX NG grammar, as it is being parsed.
+///
+/// Register the validator as the content handler of a reader, or feed it the events of a
+/// filter. Violations are reported through `ErrorHandler::error`, at the position of the
+/// document locator if one was supplied. After a violation, validation continues: an element
+/// that is not allowed is skipped along with its content, and missing attributes and content
+/// are assumed to be present.
+pub struct Validator<L: Locator, EH: ErrorHandler<ParseErrorImpl>> {
+    grammar: Rc<Grammar>,
+    error_handler: EH,
+    locator: RefCell<Option<Rc<L>>>,
+    state: RefCell<ValidatorState>,
+}
+
+impl<L: Locator, EH: ErrorHandler<ParseErrorImpl>> Validator<L, EH> {
+    /// Construct a validator for the given grammar, reporting violations to `error_handler`.
+    pub fn new(grammar: Rc<Grammar>, error_handler: EH) -> Self {
+        let state = RefCell::new(ValidatorState {
+            pattern: grammar.start.clone(),
+            text: String::new(),
+            open: Vec::new(),
+            skip_depth: 0,
+            error_count: 0,
+        });
+        Validator {
+            grammar,
+            error_handler,
+            locator: RefCell::new(None),
+            state,
+        }
+    }
+
+    /// Return the error handler.
+    pub fn get_error_handler(&self) -> &EH {
+        &self.error_handler
+    }
+
+    /// Return the number of violations reported for the current document.
+    pub fn error_count(&self) -> usize {
+        self.state.borrow().error_count
+    }
+
+    /// Return whether the document validated so far is free of violations.
+    pub fn is_valid(&self) -> bool {
+        self.error_count() == 0
+    }
+
+    /// Prepare the validator for a new document.
+    pub fn reset(&self) {
+        let mut state = self.state.borrow_mut();
+        state.pattern = self.grammar.start.clone();
+        state.text.clear();
+        state.open.clear();
+        state.skip_depth = 0;
+        state.error_count = 0;
+    }
+
+    fn report(&self, state: &mut ValidatorState, message: String) -> Result<()> {
+        state.error_count += 1;
+        let locator = self.locator.borrow();
+        self.error_handler
+            .error(&ParseErrorImpl::at(message, locator.as_ref().map(|l| &**l)))
+    }
+
+    /// Validate the pending character content. `whole_content` is set when the text is the
+    /// entire content of an element, in which case it is checked even if it is empty.
+    fn flush_text(&self, state: &mut ValidatorState, whole_content: bool) -> Result<()> {
+        let text = std::mem::take(&mut state.text);
+        let whitespace = is_whitespace(&text);
+        if whitespace && !whole_content {
+            return Ok(());
+        }
+        let derive = |lenient| {
+            let derived = self.grammar.text_deriv(&state.pattern, &text, lenient);
+            if whitespace {
+                choice(state.pattern.clone(), derived)
+            } else {
+                derived
+            }
+        };
+        let derived = derive(false);
+        if derived.is_not_allowed() {
+            let recovered = derive(true);
+            if !recovered.is_not_allowed() {
+                state.pattern = recovered;
+            }
+            let shown: String = text.trim().chars().take(40).collect();
+            return self.report(
+                state,
+                format!("character content \"{}\" not allowed here", shown),
+            );
+        }
+        state.pattern = derived;
+        Ok(())
+    }
+}
+
+impl<L, A, EH> ContentHandler<L, A> for Validator<L, EH>
+where
+    L: Locator,
+    A: Attributes,
+    EH: ErrorHandler<ParseErrorImpl>,
+{
+    fn characters(&self, content: &str) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        if state.skip_depth == 0 && !state.open.is_empty() {
+            state.text.push_str(content);
+        }
+        Ok(())
+    }
+    fn end_document(&self) -> Result<()> {
+        let mut guard = self.state.borrow_mut();
+        let state = &mut *guard;
+        if state.skip_depth == 0 && !self.grammar.nullable(&state.pattern) {
+            let mut expected = Vec::new();
+            self.grammar
+                .expected_elements(&state.pattern, &mut expected);
+            let message = format!("document incomplete{}", describe(&expected));
+            return self.report(state, message);
+        }
+        Ok(())
+    }
+    fn end_element(&self, _uri: &str, local_name: &str, q_name: &str) -> Result<()> {
+        let mut guard = self.state.borrow_mut();
+        let state = &mut *guard;
+        if state.skip_depth > 0 {
+            state.skip_depth -= 1;
+            return Ok(());
+        }
+        let had_child = state.open.pop().unwrap_or(false);
+        self.flush_text(state, !had_child)?;
+        let mut ended = self.grammar.end_tag_deriv(&state.pattern, false);
+        if ended.is_not_allowed() {
+            let mut expected = Vec::new();
+            self.grammar
+                .expected_elements(&state.pattern, &mut expected);
+            let name = if q_name.is_empty() {
+                local_name
+            } else {
+                q_name
+            };
+            let message = format!("element \"{}\" incomplete{}", name, describe(&expected));
+            self.report(state, message)?;
+            ended = self.grammar.end_tag_deriv(&state.pattern, true);
+        }
+        state.pattern = ended;
+        Ok(())
+    }
+    fn ignorable_whitespace(&self, content: &str) -> Result<()> {
+        ContentHandler::<L, A>::characters(self, content)
+    }
+    fn set_document_locator(&self, locator: Rc<L>) {
+        *self.locator.borrow_mut() = Some(locator);
+    }
+    fn start_document(&self) -> Result<()> {
+        self.reset();
+        Ok(())
+    }
+    fn start_element(
+        &self,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attributes: A,
+    ) -> Result<()> {
+        let mut guard = self.state.borrow_mut();
+        let state = &mut *guard;
+        if state.skip_depth > 0 {
+            state.skip_depth += 1;
+            return Ok(());
+        }
+        if let Some(had_child) = state.open.last_mut() {
+            *had_child = true;
+        }
+        self.flush_text(state, false)?;
+        let local_name = if local_name.is_empty() {
+            q_name
+        } else {
+            local_name
+        };
+        let name = if q_name.is_empty() {
+            local_name
+        } else {
+            q_name
+        };
+
+        let opened = self
+            .grammar
+            .start_tag_open_deriv(&state.pattern, uri, local_name);
+        if opened.is_not_allowed() {
+            let mut expected = Vec::new();
+            self.grammar
+                .expected_elements(&state.pattern, &mut expected);
+            let message = format!(
+                "element \"{}\" not allowed here{}",
+                name,
+                describe(&expected)
+            );
+            state.skip_depth = 1;
+            return self.report(state, message);
+        }
+
+        let mut pattern = opened;
+        for index in 0..attributes.get_length() as u64 {
+            let attr_uri = attributes.get_uri(index).unwrap_or_default();
+            let attr_q_name = attributes.get_q_name(index).unwrap_or_default();
+            if attr_uri == NSDECL || attr_q_name == "xmlns" || attr_q_name.starts_with("xmlns:") {
+                continue;
+            }
+            let attr_local_name = match attributes.get_local_name(index) {
+                Some(ref local) if !local.is_empty() => local.clone(),
+                _ => attr_q_name.clone(),
+            };
+            let value = attributes.get_value(index).unwrap_or_default();
+            let derived = self
+                .grammar
+                .att_deriv(&pattern, &attr_uri, &attr_local_name, &value);
+            if derived.is_not_allowed() {
+                let mut expected = Vec::new();
+                self.grammar.expected_attributes(&pattern, &mut expected);
+                let message = if expected
+                    .iter()
+                    .any(|name_class| name_class.contains(&attr_uri, &attr_local_name))
+                {
+                    format!(
+                        "value \"{}\" of attribute \"{}\" is invalid",
+                        value, attr_q_name
+                    )
+                } else {
+                    format!(
+                        "attribute \"{}\" not allowed on element \"{}\"",
+                        attr_q_name, name
+                    )
+                };
+                self.report(state, message)?;
+            } else {
+                pattern = derived;
+            }
+        }
+
+        let mut closed = self.grammar.start_tag_close_deriv(&pattern, false);
+        if closed.is_not_allowed() {
+            let mut expected = Vec::new();
+            self.grammar.expected_attributes(&pattern, &mut expected);
+            let message = format!(
+                "element \"{}\" missing required attribute{}",
+                name,
+                describe(&expected)
+            );
+            self.report(state, message)?;
+            closed = self.grammar.start_tag_close_deriv(&pattern, true);
+        }
+        if closed.is_not_allowed() {
+            state.skip_depth = 1;
+        } else {
+            state.pattern = closed;
+            state.open.push(false);
+        }
+        Ok(())
+    }
+}
+
+/// An element of a schema in the XML syntax, or its equivalent for the compact syntax.
+#[derive(Clone, Debug)]
+struct Node {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Node>,
+    text: String,
+    /// The Namespace prefixes in scope, innermost last.
+    namespaces: Rc<Vec<(String, String)>>,
+    location: LocatorImpl,
+}
+
+impl Node {
+    fn new(name: &str, location: LocatorImpl) -> Self {
+        Node {
+            name: name.to_owned(),
+            attributes: Vec::new(),
+            children: Vec::new(),
+            text: String::new(),
+            namespaces: Rc::new(Vec::new()),
+            location,
+        }
+    }
+
+    fn with_attribute(mut self, name: &str, value: &str) -> Self {
+        self.attributes.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    fn with_child(mut self, child: Node) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.0 == name)
+            .map(|attribute| attribute.1.trim())
+    }
+
+    fn required_attribute(&self, name: &str) -> SchemaResult<&str> {
+        self.attribute(name).ok_or_else(|| {
+            self.error(format!(
+                "\"{}\" element missing \"{}\" attribute",
+                self.name, name
+            ))
+        })
+    }
+
+    fn error<S: Into<String>>(&self, message: S) -> ParseErrorImpl {
+        ParseErrorImpl::new(message, self.location.clone())
+    }
+
+    fn resolve_qname(&self, q_name: &str, default_uri: &str) -> SchemaResult<(String, String)> {
+        let (prefix, local_name) = match q_name.find(':') {
+            Some(i) => (&q_name[..i], &q_name[i + 1..]),
+            None => return Ok((default_uri.to_owned(), q_name.to_owned())),
+        };
+        if prefix == "xml" {
+            return Ok((XMLNS.to_owned(), local_name.to_owned()));
+        }
+        match self
+            .namespaces
+            .iter()
+            .rev()
+            .find(|binding| binding.0 == prefix)
+        {
+            Some(binding) => Ok((binding.1.clone(), local_name.to_owned())),
+            None => Err(self.error(format!("undeclared prefix \"{}\"", prefix))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Context {
+    ns: String,
+    datatype_library: String,
+}
+
+impl Context {
+    fn enter(&self, node: &Node) -> Context {
+        Context {
+            ns: node.attribute("ns").unwrap_or(&self.ns).to_owned(),
+            datatype_library: node
+                .attribute("datatypeLibrary")
+                .unwrap_or(&self.datatype_library)
+                .to_owned(),
+        }
+    }
+}
+
+const START: &str = "#start";
+
+/// Converts schema elements into patterns, simplifying the syntax along the way.
+#[derive(Default)]
+struct Converter {
+    defines: Vec<Option<Rc<Pattern>>>,
+    /// For each definition, the combine method seen so far, and whether a definition without
+    /// a combine method was seen.
+    combine: Vec<(Option<String>, bool)>,
+    scopes: Vec<HashMap<String, usize>>,
+}
+
+impl Converter {
+    fn grammar(&mut self, node: &Node, ctx: &Context) -> SchemaResult<Rc<Pattern>> {
+        let mut scope = HashMap::new();
+        self.register(node, &mut scope)?;
+        let start = match scope.get(START) {
+            Some(start) => *start,
+            None => return Err(node.error("grammar has no start pattern")),
+        };
+        self.scopes.push(scope);
+        let result = self.grammar_content(node, ctx);
+        self.scopes.pop();
+        result.map(|_| Rc::new(Pattern::Ref(start)))
+    }
+
+    fn register(&mut self, node: &Node, scope: &mut HashMap<String, usize>) -> SchemaResult<()> {
+        for child in &node.children {
+            let key = match child.name.as_str() {
+                "start" => START,
+                "define" => child.required_attribute("name")?,
+                "div" => {
+                    self.register(child, scope)?;
+                    continue;
+                }
+                "include" => return Err(child.error("\"include\" is not supported")),
+                other => return Err(child.error(format!("\"{}\" not allowed in grammar", other))),
+            };
+            if !scope.contains_key(key) {
+                scope.insert(key.to_owned(), self.defines.len());
+                self.defines.push(None);
+                self.combine.push((None, false));
+            }
+        }
+        Ok(())
+    }
+
+    fn grammar_content(&mut self, node: &Node, ctx: &Context) -> SchemaResult<()> {
+        for child in &node.children {
+            let ctx = ctx.enter(child);
+            let key = match child.name.as_str() {
+                "div" => {
+                    self.grammar_content(child, &ctx)?;
+                    continue;
+                }
+                "start" => START,
+                _ => child.required_attribute("name")?,
+            };
+            let slot = self.scopes.last().map_or(0, |scope| scope[key]);
+            let body = self.fold(child, &ctx, group)?;
+            self.combine_define(child, slot, body)?;
+        }
+        Ok(())
+    }
+
+    fn combine_define(&mut self, node: &Node, slot: usize, body: Rc<Pattern>) -> SchemaResult<()> {
+        let name = node.attribute("name").unwrap_or("start");
+        match node.attribute("combine") {
+            None if self.combine[slot].1 => {
+                return Err(node.error(format!(
+                    "multiple definitions of \"{}\" without \"combine\"",
+                    name
+                )))
+            }
+            None => self.combine[slot].1 = true,
+            Some(method) if method != "choice" && method != "interleave" => {
+                return Err(node.error(format!("invalid combine method \"{}\"", method)))
+            }
+            Some(method) => {
+                if let Some(ref seen) = self.combine[slot].0 {
+                    if seen != method {
+                        return Err(
+                            node.error(format!("conflicting combine methods for \"{}\"", name))
+                        );
+                    }
+                }
+                self.combine[slot].0 = Some(method.to_owned());
+            }
+        }
+        let combined = match self.defines[slot].take() {
+            None => body,
+            Some(previous) => match self.combine[slot].0.as_deref() {
+                Some("interleave") => interleave(previous, body),
+                _ => choice(previous, body),
+            },
+        };
+        self.defines[slot] = Some(combined);
+        Ok(())
+    }
+
+    fn lookup(&self, node: &Node, parent: bool) -> SchemaResult<Rc<Pattern>> {
+        let name = node.required_attribute("name")?;
+        let depth = self.scopes.len();
+        let scope = if parent {
+            depth.checked_sub(2).map(|i| &self.scopes[i])
+        } else {
+            self.scopes.last()
+        };
+        match scope.and_then(|scope| scope.get(name)) {
+            Some(index) => Ok(Rc::new(Pattern::Ref(*index))),
+            None => Err(node.error(format!("reference to undefined pattern \"{}\"", name))),
+        }
+    }
+
+    /// Combine the patterns of the children of a node.
+    fn fold(
+        &mut self,
+        node: &Node,
+        ctx: &Context,
+        combine: fn(Rc<Pattern>, Rc<Pattern>) -> Rc<Pattern>,
+    ) -> SchemaResult<Rc<Pattern>> {
+        self.fold_children(node, &node.children, ctx, combine)
+    }
+
+    fn fold_children(
+        &mut self,
+        node: &Node,
+        children: &[Node],
+        ctx: &Context,
+        combine: fn(Rc<Pattern>, Rc<Pattern>) -> Rc<Pattern>,
+    ) -> SchemaResult<Rc<Pattern>> {
+        let mut result: Option<Rc<Pattern>> = None;
+        for child in children {
+            let pattern = self.pattern(child, ctx)?;
+            result = Some(match result {
+                Some(previous) => combine(previous, pattern),
+                None => pattern,
+            });
+        }
+        result.ok_or_else(|| node.error(format!("\"{}\" element has no pattern", node.name)))
+    }
+
+    fn pattern(&mut self, node: &Node, ctx: &Context) -> SchemaResult<Rc<Pattern>> {
+        let ctx = ctx.enter(node);
+        Ok(match node.name.as_str() {
+            "element" | "attribute" => {
+                let is_element = node.name == "element";
+                let (name_class, rest) = match node.attribute("name") {
+                    Some(name) => {
+                        let (uri, local_name) = if is_element {
+                            node.resolve_qname(name, &ctx.ns)?
+                        } else {
+                            node.resolve_qname(name, node.attribute("ns").unwrap_or(""))?
+                        };
+                        (NameClass::Name(uri, local_name), &node.children[..])
+                    }
+                    None => match node.children.split_first() {
+                        Some((first, rest)) => (self.name_class(first, &ctx)?, rest),
+                        None => return Err(node.error("missing name class")),
+                    },
+                };
+                if is_element {
+                    let content = self.fold_children(node, rest, &ctx, group)?;
+                    Rc::new(Pattern::Element(name_class, content))
+                } else if rest.is_empty() {
+                    Rc::new(Pattern::Attribute(name_class, Rc::new(Pattern::Text)))
+                } else {
+                    let content = self.fold_children(node, rest, &ctx, group)?;
+                    Rc::new(Pattern::Attribute(name_class, content))
+                }
+            }
+            "group" => self.fold(node, &ctx, group)?,
+            "interleave" => self.fold(node, &ctx, interleave)?,
+            "choice" => self.fold(node, &ctx, choice)?,
+            "optional" => choice(self.fold(node, &ctx, group)?, empty()),
+            "zeroOrMore" => choice(one_or_more(self.fold(node, &ctx, group)?), empty()),
+            "oneOrMore" => one_or_more(self.fold(node, &ctx, group)?),
+            "mixed" => interleave(self.fold(node, &ctx, group)?, Rc::new(Pattern::Text)),
+            "list" => Rc::new(Pattern::List(self.fold(node, &ctx, group)?)),
+            "empty" => empty(),
+            "text" => Rc::new(Pattern::Text),
+            "notAllowed" => not_allowed(),
+            "ref" => self.lookup(node, false)?,
+            "parentRef" => self.lookup(node, true)?,
+            "grammar" => self.grammar(node, &ctx)?,
+            "data" => {
+                let mut params = Vec::new();
+                let mut except = None;
+                for child in &node.children {
+                    match child.name.as_str() {
+                        "param" => params.push((child.required_attribute("name")?, &child.text)),
+                        "except" => except = Some(self.fold(child, &ctx.enter(child), choice)?),
+                        other => {
+                            return Err(child.error(format!("\"{}\" not allowed in data", other)))
+                        }
+                    }
+                }
+                let datatype = datatype(node, &ctx, node.required_attribute("type")?, &params)?;
+                Rc::new(Pattern::Data(datatype, except))
+            }
+            "value" => {
+                let datatype = match node.attribute("type") {
+                    Some(name) => datatype(node, &ctx, name, &[])?,
+                    None => Datatype::Token,
+                };
+                if !datatype.allows(&node.text) {
+                    return Err(node.error(format!("invalid value \"{}\"", node.text)));
+                }
+                Rc::new(Pattern::Value(datatype, node.text.clone()))
+            }
+            "externalRef" | "include" => {
+                return Err(node.error(format!("\"{}\" is not supported", node.name)))
+            }
+            other => return Err(node.error(format!("unexpected \"{}\" element", other))),
+        })
+    }
+
+    fn name_class(&mut self, node: &Node, ctx: &Context) -> SchemaResult<NameClass> {
+        let ctx = ctx.enter(node);
+        let except = |converter: &mut Converter| -> SchemaResult<Option<Box<NameClass>>> {
+            match node.children.first() {
+                Some(child) if child.name == "except" => {
+                    let ctx = ctx.enter(child);
+                    converter
+                        .name_class_choice(child, &child.children, &ctx)
+                        .map(|name_class| Some(Box::new(name_class)))
+                }
+                Some(child) => Err(child.error(format!("unexpected \"{}\" element", child.name))),
+                None => Ok(None),
+            }
+        };
+        Ok(match node.name.as_str() {
+            "name" => {
+                let (uri, local_name) = node.resolve_qname(node.text.trim(), &ctx.ns)?;
+                NameClass::Name(uri, local_name)
+            }
+            "anyName" => NameClass::AnyName(except(self)?),
+            "nsName" => NameClass::NsName(ctx.ns.clone(), except(self)?),
+            "choice" => self.name_class_choice(node, &node.children, &ctx)?,
+            other => return Err(node.error(format!("\"{}\" is not a name class", other))),
+        })
+    }
+
+    fn name_class_choice(
+        &mut self,
+        node: &Node,
+        children: &[Node],
+        ctx: &Context,
+    ) -> SchemaResult<NameClass> {
+        let mut result: Option<NameClass> = None;
+        for child in children {
+            let name_class = self.name_class(child, ctx)?;
+            result = Some(match result {
+                Some(previous) => NameClass::Choice(Box::new(previous), Box::new(name_class)),
+                None => name_class,
+            });
+        }
+        result.ok_or_else(|| node.error(format!("\"{}\" element has no name class", node.name)))
+    }
+}
+
+fn datatype(
+    node: &Node,
+    ctx: &Context,
+    name: &str,
+    params: &[(&str, &String)],
+) -> SchemaResult<Datatype> {
+    match ctx.datatype_library.as_str() {
+        "" => {
+            if !params.is_empty() {
+                return Err(node.error(format!("datatype \"{}\" has no parameters", name)));
+            }
+            match name {
+                "string" => Ok(Datatype::String),
+                "token" => Ok(Datatype::Token),
+                _ => Err(node.error(format!("unknown datatype \"{}\"", name))),
+            }
+        }
+        XSD_DATATYPES => {
+            let base = BuiltinType::from_name(name)
+                .ok_or_else(|| node.error(format!("unknown datatype \"{}\"", name)))?;
+            let mut facets = Facets::default();
+            for &(param, raw) in params {
+                let value = raw.trim();
+                let length = || {
+                    value
+                        .parse::<usize>()
+                        .map_err(|_| node.error(format!("invalid \"{}\" parameter", param)))
+                };
+                match param {
+                    "length" => facets.length = Some(length()?),
+                    "minLength" => facets.min_length = Some(length()?),
+                    "maxLength" => facets.max_length = Some(length()?),
+                    "totalDigits" => facets.total_digits = Some(length()?),
+                    "fractionDigits" => facets.fraction_digits = Some(length()?),
+                    "minInclusive" => facets.min_inclusive = Some(value.to_owned()),
+                    "maxInclusive" => facets.max_inclusive = Some(value.to_owned()),
+                    "minExclusive" => facets.min_exclusive = Some(value.to_owned()),
+                    "maxExclusive" => facets.max_exclusive = Some(value.to_owned()),
+                    "pattern" => facets.patterns.push(Regex::new(raw).map_err(|e| {
+                        node.error(format!("invalid \"pattern\" parameter: {}", e.message()))
+                    })?),
+                    _ => return Err(node.error(format!("unknown parameter \"{}\"", param))),
+                }
+            }
+            Ok(Datatype::Xsd(Box::new(SimpleType::with_facets(
+                base, facets,
+            ))))
+        }
+        library => Err(node.error(format!("unsupported datatype library \"{}\"", library))),
+    }
+}
+
+#[derive(Debug, Default)]
+struct BuilderState {
+    /// The Namespace prefixes in scope, and the number declared by each open element.
+    bindings: Vec<(String, String)>,
+    declared: Vec<usize>,
+    pending: Vec<(String, String)>,
+    snapshot: Rc<Vec<(String, String)>>,
+    stack: Vec<Node>,
+    root: Option<Node>,
+    skip_depth: usize,
+}
+
+/// Builds a `Grammar` from the events of a schema document in the XML syntax.
+///
+/// Register the builder as the content handler of a Namespace-aware reader, parse the schema
+/// document, then call `build`. Elements and attributes from other Namespaces (annotations)
+/// are ignored.
+pub struct SchemaBuilder<L: Locator> {
+    locator: RefCell<Option<Rc<L>>>,
+    state: RefCell<BuilderState>,
+}
+
+impl<L: Locator> Default for SchemaBuilder<L> {
+    fn default() -> Self {
+        SchemaBuilder::new()
+    }
+}
+
+impl<L: Locator> SchemaBuilder<L> {
+    /// Construct a new schema builder.
+    pub fn new() -> Self {
+        SchemaBuilder {
+            locator: RefCell::new(None),
+            state: RefCell::new(BuilderState::default()),
+        }
+    }
+
+    /// Compile the schema document that was parsed into a grammar.
+    pub fn build(&self) -> SchemaResult<Grammar> {
+        match self.state.borrow().root {
+            Some(ref root) => Grammar::from_node(root),
+            None => Err(ParseErrorImpl::new(
+                "no RELAX NG pattern found",
+                LocatorImpl::new(),
+            )),
+        }
+    }
+}
+
+impl<L: Locator, A: Attributes> ContentHandler<L, A> for SchemaBuilder<L> {
+    fn characters(&self, content: &str) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        if state.skip_depth == 0 {
+            if let Some(node) = state.stack.last_mut() {
+                node.text.push_str(content);
+            }
+        }
+        Ok(())
+    }
+    fn end_element(&self, _uri: &str, _local_name: &str, _q_name: &str) -> Result<()> {
+        let mut guard = self.state.borrow_mut();
+        let state = &mut *guard;
+        let declared = state.declared.pop().unwrap_or(0);
+        if declared > 0 {
+            let len = state.bindings.len() - declared;
+            state.bindings.truncate(len);
+            state.snapshot = Rc::new(state.bindings.clone());
+        }
+        if state.skip_depth > 0 {
+            state.skip_depth -= 1;
+            return Ok(());
+        }
+        if let Some(node) = state.stack.pop() {
+            match state.stack.last_mut() {
+                Some(parent) => parent.children.push(node),
+                None => state.root = Some(node),
+            }
+        }
+        Ok(())
+    }
+    fn set_document_locator(&self, locator: Rc<L>) {
+        *self.locator.borrow_mut() = Some(locator);
+    }
+    fn start_document(&self) -> Result<()> {
+        *self.state.borrow_mut() = BuilderState::default();
+        Ok(())
+    }
+    fn start_element(
+        &self,
+        uri: &str,
+        local_name: &str,
+        _q_name: &str,
+        attributes: A,
+    ) -> Result<()> {
+        let mut guard = self.state.borrow_mut();
+        let state = &mut *guard;
+        let pending = std::mem::take(&mut state.pending);
+        state.declared.push(pending.len());
+        if !pending.is_empty() {
+            state.bindings.extend(pending);
+            state.snapshot = Rc::new(state.bindings.clone());
+        }
+        if state.skip_depth > 0 || uri != RELAXNG_NS {
+            state.skip_depth += 1;
+            return Ok(());
+        }
+        let location = self
+            .locator
+            .borrow()
+            .as_ref()
+            .map(|locator| LocatorImpl::from_locator(&**locator))
+            .unwrap_or_default();
+        let mut node = Node::new(local_name, location);
+        node.namespaces = state.snapshot.clone();
+        for index in 0..attributes.get_length() as u64 {
+            if attributes.get_uri(index).is_none_or(|uri| uri.is_empty()) {
+                let name = match attributes.get_local_name(index) {
+                    Some(ref local) if !local.is_empty() => local.clone(),
+                    _ => attributes.get_q_name(index).unwrap_or_default(),
+                };
+                if name != "xmlns" && !name.starts_with("xmlns:") {
+                    let value = attributes.get_value(index).unwrap_or_default();
+                    node.attributes.push((name, value));
+                }
+            }
+        }
+        state.stack.push(node);
+        Ok(())
+    }
+    fn start_prefix_mapping(&self, prefix: &str, uri: &str) -> Result<()> {
+        self.state
+            .borrow_mut()
+            .pending
+            .push((prefix.to_owned(), uri.to_owned()));
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TokenKind {
+    /// An identifier or keyword.
+    Name(String),
+    /// An identifier escaped with a backslash, which is never a keyword.
+    Escaped(String),
+    /// A prefixed name.
+    CName(String, String),
+    /// A prefix followed by `:*`.
+    NsName(String),
+    Literal(String),
+    Punct(&'static str),
+    Eof,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    line: u64,
+    column: u64,
+}
+
+const KEYWORDS: &[&str] = &[
+    "attribute",
+    "default",
+    "datatypes",
+    "div",
+    "element",
+    "empty",
+    "external",
+    "grammar",
+    "include",
+    "inherit",
+    "list",
+    "mixed",
+    "namespace",
+    "notAllowed",
+    "parent",
+    "start",
+    "string",
+    "text",
+    "token",
+];
+
+const PUNCTUATION: &[&str] = &[
+    "|=", "&=", ">>", "=", "{", "}", "(", ")", "[", "]", ",", "|", "&", "?", "*", "+", "-", "~",
+];
+
+/// Replace the `\x{...}` escapes of the compact syntax.
+fn unescape(input: &str) -> SchemaResult<String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(i) = rest.find("\\x") {
+        output.push_str(&rest[..i]);
+        let escape = &rest[i + 1..];
+        let digits = escape.trim_start_matches('x');
+        let replaced = digits
+            .strip_prefix('{')
+            .and_then(|digits| {
+                digits
+                    .find('}')
+                    .map(|end| (&digits[..end], &digits[end + 1..]))
+            })
+            .map(|(hex, after)| {
+                (
+                    u32::from_str_radix(hex, 16)
+                        .ok()
+                        .and_then(std::char::from_u32),
+                    after,
+                )
+            });
+        match replaced {
+            Some((Some(c), after)) => {
+                output.push(c);
+                rest = after;
+            }
+            Some((None, _)) => {
+                return Err(ParseErrorImpl::new(
+                    "invalid character escape",
+                    LocatorImpl::new(),
+                ))
+            }
+            None => {
+                output.push('\\');
+                rest = escape;
+            }
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+fn tokenize(input: &str) -> SchemaResult<Vec<Token>> {
+    let chars: Vec<char> = unescape(input)?.chars().collect();
+    let mut tokens = Vec::new();
+    let (mut i, mut line, mut line_start) = (0, 1u64, 0);
+    let name_start = |c: char| c != ':' && names::is_name_start_char(c);
+    let name_char = |c: char| c != ':' && names::is_name_char(c);
+    loop {
+        while i < chars.len() && (names::is_whitespace(chars[i]) || chars[i] == '#') {
+            if chars[i] == '#' {
+                while i < chars.len() && chars[i] != '\n' && chars[i] != '\r' {
+                    i += 1;
+                }
+                continue;
+            }
+            if chars[i] == '\n' {
+                line += 1;
+                line_start = i + 1;
+            }
+            i += 1;
+        }
+        let column = (i - line_start + 1) as u64;
+        let error = move |message: &str| {
+            ParseErrorImpl::new(
+                message,
+                LocatorImpl {
+                    line_number: Some(line),
+                    column_number: Some(column),
+                    ..LocatorImpl::new()
+                },
+            )
+        };
+        if i >= chars.len() {
+            tokens.push(Token {
+                kind: TokenKind::Eof,
+                line,
+                column,
+            });
+            return Ok(tokens);
+        }
+        let c = chars[i];
+        let kind = if c == '"' || c == '\'' {
+            let triple = chars.get(i + 1) == Some(&c) && chars.get(i + 2) == Some(&c);
+            let delimiter = if triple { 3 } else { 1 };
+            i += delimiter;
+            let start = i;
+            loop {
+                if i >= chars.len() || (!triple && (chars[i] == '\n' || chars[i] == '\r')) {
+                    return Err(error("unterminated literal"));
+                }
+                if chars[i] == c && (!triple || chars[i..].starts_with(&[c, c, c])) {
+                    break;
+                }
+                if chars[i] == '\n' {
+                    line += 1;
+                    line_start = i + 1;
+                }
+                i += 1;
+            }
+            let literal = chars[start..i].iter().collect();
+            i += delimiter;
+            TokenKind::Literal(literal)
+        } else if c == '\\' || name_start(c) {
+            let escaped = c == '\\';
+            if escaped {
+                i += 1;
+            }
+            let start = i;
+            if i >= chars.len() || !name_start(chars[i]) {
+                return Err(error("expected an identifier"));
+            }
+            while i < chars.len() && name_char(chars[i]) {
+                i += 1;
+            }
+            let name: String = chars[start..i].iter().collect();
+            if !escaped && chars.get(i) == Some(&':') && chars.get(i + 1) == Some(&'*') {
+                i += 2;
+                TokenKind::NsName(name)
+            } else if !escaped
+                && chars.get(i) == Some(&':')
+                && chars.get(i + 1).is_some_and(|c| name_start(*c))
+            {
+                let start = i + 1;
+                i = start;
+                while i < chars.len() && name_char(chars[i]) {
+                    i += 1;
+                }
+                TokenKind::CName(name, chars[start..i].iter().collect())
+            } else if escaped {
+                TokenKind::Escaped(name)
+            } else {
+                TokenKind::Name(name)
+            }
+        } else {
+            let punct = PUNCTUATION
+                .iter()
+                .copied()
+                .find(|p| {
+                    p.chars()
+                        .enumerate()
+                        .all(|(j, pc)| chars.get(i + j) == Some(&pc))
+                })
+                .ok_or_else(|| error(&format!("unexpected character '{}'", c)))?;
+            i += punct.len();
+            TokenKind::Punct(punct)
+        };
+        tokens.push(Token { kind, line, column });
+    }
+}
+
+/// Parses the compact syntax into the equivalent elements of the XML syntax.
+struct CompactParser {
+    tokens: Vec<Token>,
+    position: usize,
+    namespaces: Vec<(String, String)>,
+    default_namespace: String,
+    datatypes: Vec<(String, String)>,
+}
+
+impl CompactParser {
+    fn new(input: &str) -> SchemaResult<Self> {
+        Ok(CompactParser {
+            tokens: tokenize(input)?,
+            position: 0,
+            namespaces: vec![("xml".to_owned(), XMLNS.to_owned())],
+            default_namespace: String::new(),
+            datatypes: vec![("xsd".to_owned(), XSD_DATATYPES.to_owned())],
+        })
+    }
+
+    fn peek(&self) -> &TokenKind {
+        &self.tokens[self.position].kind
+    }
+
+    fn peek_at(&self, offset: usize) -> &TokenKind {
+        let index = (self.position + offset).min(self.tokens.len() - 1);
+        &self.tokens[index].kind
+    }
+
+    fn next(&mut self) -> TokenKind {
+        let kind = self.peek().clone();
+        if self.position + 1 < self.tokens.len() {
+            self.position += 1;
+        }
+        kind
+    }
+
+    fn location(&self) -> LocatorImpl {
+        let token = &self.tokens[self.position];
+        LocatorImpl {
+            line_number: Some(token.line),
+            column_number: Some(token.column),
+            ..LocatorImpl::new()
+        }
+    }
+
+    fn error<S: Into<String>>(&self, message: S) -> ParseErrorImpl {
+        ParseErrorImpl::new(message, self.location())
+    }
+
+    fn node(&self, name: &str) -> Node {
+        Node::new(name, self.location())
+    }
+
+    fn at(&self, punct: &'static str) -> bool {
+        *self.peek() == TokenKind::Punct(punct)
+    }
+
+    fn expect(&mut self, punct: &'static str) -> SchemaResult<()> {
+        if self.at(punct) {
+            self.next();
+            Ok(())
+        } else {
+            Err(self.error(format!("expected \"{}\"", punct)))
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            TokenKind::Name(name) => name == keyword,
+            _ => false,
+        }
+    }
+
+    fn identifier(&mut self) -> SchemaResult<String> {
+        match self.next() {
+            TokenKind::Escaped(name) => Ok(name),
+            TokenKind::Name(ref name) if !KEYWORDS.contains(&name.as_str()) => Ok(name.clone()),
+            _ => Err(self.error("expected an identifier")),
+        }
+    }
+
+    fn literal(&mut self) -> SchemaResult<String> {
+        let mut literal = match self.next() {
+            TokenKind::Literal(literal) => literal,
+            _ => return Err(self.error("expected a literal")),
+        };
+        while self.at("~") {
+            self.next();
+            match self.next() {
+                TokenKind::Literal(more) => literal.push_str(&more),
+                _ => return Err(self.error("expected a literal")),
+            }
+        }
+        Ok(literal)
+    }
+
+    fn skip_annotations(&mut self) -> SchemaResult<()> {
+        while self.at("[") {
+            let mut depth = 0;
+            loop {
+                match self.next() {
+                    TokenKind::Punct("[") => depth += 1,
+                    TokenKind::Punct("]") => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    TokenKind::Eof => return Err(self.error("unterminated annotation")),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn skip_follow_annotations(&mut self) -> SchemaResult<()> {
+        while self.at(">>") {
+            self.next();
+            match self.next() {
+                TokenKind::Name(_) | TokenKind::CName(..) | TokenKind::Escaped(_) => {}
+                _ => return Err(self.error("expected an annotation element name")),
+            }
+            if !self.at("[") {
+                return Err(self.error("expected \"[\""));
+            }
+            self.skip_annotations()?;
+        }
+        Ok(())
+    }
+
+    fn namespace_uri(&mut self) -> SchemaResult<String> {
+        if self.is_keyword("inherit") {
+            self.next();
+            Ok(String::new())
+        } else {
+            self.literal()
+        }
+    }
+
+    fn parse(mut self) -> SchemaResult<Node> {
+        loop {
+            self.skip_annotations()?;
+            if self.is_keyword("namespace") {
+                self.next();
+                let prefix = self.identifier()?;
+                self.expect("=")?;
+                let uri = self.namespace_uri()?;
+                self.namespaces.push((prefix, uri));
+            } else if self.is_keyword("default")
+                && *self.peek_at(1) == TokenKind::Name("namespace".to_owned())
+            {
+                self.next();
+                self.next();
+                let prefix = if self.at("=") {
+                    None
+                } else {
+                    Some(self.identifier()?)
+                };
+                self.expect("=")?;
+                let uri = self.namespace_uri()?;
+                if let Some(prefix) = prefix {
+                    self.namespaces.push((prefix, uri.clone()));
+                }
+                self.default_namespace = uri;
+            } else if self.is_keyword("datatypes") {
+                self.next();
+                let prefix = self.identifier()?;
+                self.expect("=")?;
+                let uri = self.literal()?;
+                self.datatypes.push((prefix, uri));
+            } else {
+                break;
+            }
+        }
+        let root = if self.is_grammar_content() || *self.peek() == TokenKind::Eof {
+            let mut grammar = self.node("grammar");
+            grammar.children = self.grammar_content()?;
+            grammar
+        } else {
+            self.pattern()?
+        };
+        if *self.peek() != TokenKind::Eof {
+            return Err(self.error("unexpected input after pattern"));
+        }
+        Ok(root)
+    }
+
+    fn is_grammar_content(&self) -> bool {
+        let assignment = match self.peek_at(1) {
+            TokenKind::Punct(punct) => ["=", "|=", "&="].contains(punct),
+            _ => false,
+        };
+        match self.peek() {
+            TokenKind::Name(name) if name == "div" => *self.peek_at(1) == TokenKind::Punct("{"),
+            TokenKind::Name(name) if name == "include" => true,
+            TokenKind::Name(name) if name == "start" => assignment,
+            TokenKind::Name(name) => assignment && !KEYWORDS.contains(&name.as_str()),
+            TokenKind::Escaped(_) => assignment,
+            _ => false,
+        }
+    }
+
+    fn grammar_content(&mut self) -> SchemaResult<Vec<Node>> {
+        let mut content = Vec::new();
+        loop {
+            self.skip_annotations()?;
+            if *self.peek() == TokenKind::Eof || self.at("}") {
+                return Ok(content);
+            }
+            if self.is_keyword("include") {
+                return Err(self.error("\"include\" is not supported"));
+            }
+            if self.is_keyword("div") {
+                let mut div = self.node("div");
+                self.next();
+                self.expect("{")?;
+                div.children = self.grammar_content()?;
+                self.expect("}")?;
+                content.push(div);
+                continue;
+            }
+            let mut definition = if self.is_keyword("start") {
+                self.next();
+                self.node("start")
+            } else {
+                let node = self.node("define");
+                let name = self.identifier()?;
+                node.with_attribute("name", &name)
+            };
+            match self.next() {
+                TokenKind::Punct("=") => {}
+                TokenKind::Punct("|=") => {
+                    definition = definition.with_attribute("combine", "choice")
+                }
+                TokenKind::Punct("&=") => {
+                    definition = definition.with_attribute("combine", "interleave")
+                }
+                _ => return Err(self.error("expected \"=\", \"|=\" or \"&=\"")),
+            }
+            let body = self.pattern()?;
+            content.push(definition.with_child(body));
+        }
+    }
+
+    fn pattern(&mut self) -> SchemaResult<Node> {
+        let location = self.location();
+        let first = self.particle()?;
+        let (punct, name) = if self.at(",") {
+            (",", "group")
+        } else if self.at("|") {
+            ("|", "choice")
+        } else if self.at("&") {
+            ("&", "interleave")
+        } else {
+            return Ok(first);
+        };
+        let mut node = Node::new(name, location).with_child(first);
+        while self.at(punct) {
+            self.next();
+            let particle = self.particle()?;
+            node.children.push(particle);
+        }
+        Ok(node)
+    }
+
+    fn particle(&mut self) -> SchemaResult<Node> {
+        let location = self.location();
+        let primary = self.primary()?;
+        self.skip_follow_annotations()?;
+        let wrapper = if self.at("?") {
+            "optional"
+        } else if self.at("*") {
+            "zeroOrMore"
+        } else if self.at("+") {
+            "oneOrMore"
+        } else {
+            return Ok(primary);
+        };
+        self.next();
+        self.skip_follow_annotations()?;
+        Ok(Node::new(wrapper, location).with_child(primary))
+    }
+
+    fn braced_pattern(&mut self) -> SchemaResult<Node> {
+        self.expect("{")?;
+        let pattern = self.pattern()?;
+        self.expect("}")?;
+        Ok(pattern)
+    }
+
+    fn primary(&mut self) -> SchemaResult<Node> {
+        self.skip_annotations()?;
+        let node = self.node("");
+        let named = |name: &str| Node {
+            name: name.to_owned(),
+            ..node.clone()
+        };
+        Ok(match self.next() {
+            TokenKind::Name(ref keyword) if keyword == "element" || keyword == "attribute" => {
+                let name_class = self.name_class(keyword == "element")?;
+                let body = self.braced_pattern()?;
+                named(keyword).with_child(name_class).with_child(body)
+            }
+            TokenKind::Name(ref keyword) if keyword == "mixed" || keyword == "list" => {
+                let body = self.braced_pattern()?;
+                named(keyword).with_child(body)
+            }
+            TokenKind::Name(ref keyword)
+                if keyword == "empty" || keyword == "text" || keyword == "notAllowed" =>
+            {
+                named(keyword)
+            }
+            TokenKind::Name(ref keyword) if keyword == "parent" => {
+                let name = self.identifier()?;
+                named("parentRef").with_attribute("name", &name)
+            }
+            TokenKind::Name(ref keyword) if keyword == "grammar" => {
+                self.expect("{")?;
+                let mut grammar = named("grammar");
+                grammar.children = self.grammar_content()?;
+                self.expect("}")?;
+                grammar
+            }
+            TokenKind::Name(ref keyword) if keyword == "external" => {
+                return Err(node.error("\"external\" is not supported"))
+            }
+            TokenKind::Name(ref keyword) if keyword == "string" || keyword == "token" => {
+                self.datatype(named("data"), "", keyword)?
+            }
+            TokenKind::Punct("(") => {
+                let pattern = self.pattern()?;
+                self.expect(")")?;
+                pattern
+            }
+            TokenKind::Literal(mut literal) => {
+                while self.at("~") {
+                    self.next();
+                    literal.push_str(&self.literal()?);
+                }
+                let mut value = named("value");
+                value.text = literal;
+                value
+            }
+            TokenKind::CName(prefix, local_name) => {
+                let library = match self.datatypes.iter().rev().find(|d| d.0 == prefix) {
+                    Some(binding) => binding.1.clone(),
+                    None => {
+                        return Err(
+                            node.error(format!("undeclared datatypes prefix \"{}\"", prefix))
+                        )
+                    }
+                };
+                self.datatype(named("data"), &library, &local_name)?
+            }
+            TokenKind::Escaped(name) => named("ref").with_attribute("name", &name),
+            TokenKind::Name(ref name) if !KEYWORDS.contains(&name.as_str()) => {
+                named("ref").with_attribute("name", name)
+            }
+            _ => return Err(node.error("expected a pattern")),
+        })
+    }
+
+    fn datatype(&mut self, node: Node, library: &str, name: &str) -> SchemaResult<Node> {
+        let mut node = node
+            .with_attribute("datatypeLibrary", library)
+            .with_attribute("type", name);
+        if let TokenKind::Literal(_) = self.peek() {
+            node.name = "value".to_owned();
+            node.text = self.literal()?;
+            return Ok(node);
+        }
+        if self.at("{") {
+            self.next();
+            while !self.at("}") {
+                self.skip_annotations()?;
+                let param = self.node("param");
+                let name = match self.next() {
+                    TokenKind::Name(name) | TokenKind::Escaped(name) => name,
+                    _ => return Err(self.error("expected a parameter name")),
+                };
+                self.expect("=")?;
+                let mut param = param.with_attribute("name", &name);
+                param.text = self.literal()?;
+                node.children.push(param);
+            }
+            self.next();
+        }
+        if self.at("-") {
+            let except = self.node("except");
+            self.next();
+            let pattern = self.primary()?;
+            node.children.push(except.with_child(pattern));
+        }
+        Ok(node)
+    }
+
+    fn name_class(&mut self, is_element: bool) -> SchemaResult<Node> {
+        let location = self.location();
+        let first = self.simple_name_class(is_element)?;
+        if !self.at("|") {
+            return Ok(first);
+        }
+        let mut node = Node::new("choice", location).with_child(first);
+        while self.at("|") {
+            self.next();
+            let name_class = self.simple_name_class(is_element)?;
+            node.children.push(name_class);
+        }
+        Ok(node)
+    }
+
+    fn namespace(&self, prefix: &str) -> SchemaResult<String> {
+        match self
+            .namespaces
+            .iter()
+            .rev()
+            .find(|binding| binding.0 == prefix)
+        {
+            Some(binding) => Ok(binding.1.clone()),
+            None => Err(self.error(format!("undeclared prefix \"{}\"", prefix))),
+        }
+    }
+
+    fn simple_name_class(&mut self, is_element: bool) -> SchemaResult<Node> {
+        self.skip_annotations()?;
+        let node = self.node("");
+        let (mut name_class, may_except) = match self.next() {
+            TokenKind::Name(name) | TokenKind::Escaped(name) => {
+                let ns = if is_element {
+                    self.default_namespace.clone()
+                } else {
+                    String::new()
+                };
+                let mut name_class = Node {
+                    name: "name".to_owned(),
+                    ..node
+                }
+                .with_attribute("ns", &ns);
+                name_class.text = name;
+                (name_class, false)
+            }
+            TokenKind::CName(prefix, local_name) => {
+                let ns = self.namespace(&prefix)?;
+                let mut name_class = Node {
+                    name: "name".to_owned(),
+                    ..node
+                }
+                .with_attribute("ns", &ns);
+                name_class.text = local_name;
+                (name_class, false)
+            }
+            TokenKind::NsName(prefix) => {
+                let ns = self.namespace(&prefix)?;
+                let name_class = Node {
+                    name: "nsName".to_owned(),
+                    ..node
+                }
+                .with_attribute("ns", &ns);
+                (name_class, true)
+            }
+            TokenKind::Punct("*") => (
+                Node {
+                    name: "anyName".to_owned(),
+                    ..node
+                },
+                true,
+            ),
+            TokenKind::Punct("(") => {
+                let name_class = self.name_class(is_element)?;
+                self.expect(")")?;
+                (name_class, false)
+            }
+            _ => return Err(node.error("expected a name class")),
+        };
+        self.skip_follow_annotations()?;
+        if may_except && self.at("-") {
+            let except = self.node("except");
+            self.next();
+            let excepted = self.simple_name_class(is_element)?;
+            name_class.children.push(except.with_child(excepted));
+        }
+        Ok(name_class)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event::Event;
+    use helpers::AttributesImpl;
+
+    #[derive(Default)]
+    struct Errors(RefCell<Vec<String>>);
+
+    impl ErrorHandler<ParseErrorImpl> for Errors {
+        fn error(&self, e: &ParseErrorImpl) -> Result<()> {
+            self.0.borrow_mut().push(e.to_string());
+            Ok(())
+        }
+    }
+
+    fn start(name: &str, pairs: &[(&str, &str)]) -> Event {
+        let mut attributes = AttributesImpl::new();
+        for &(q_name, value) in pairs {
+            attributes.add_attribute("", q_name, q_name, "CDATA", value);
+        }
+        Event::start_element("", name, name, &attributes)
+    }
+
+    fn end(name: &str) -> Event {
+        Event::end_element("", name, name)
+    }
+
+    fn text(content: &str) -> Event {
+        Event::Characters(content.to_owned())
+    }
+
+    /// Validate a document made of `events`, and return the violations reported.
+    fn validate(grammar: Grammar, events: Vec<Event>) -> Vec<String> {
+        let validator = Validator::<LocatorImpl, _>::new(Rc::new(grammar), Errors::default());
+        ContentHandler::<LocatorImpl, AttributesImpl>::start_document(&validator).unwrap();
+        for event in events {
+            event.dispatch(&validator).unwrap();
+        }
+        ContentHandler::<LocatorImpl, AttributesImpl>::end_document(&validator).unwrap();
+        assert_eq!(
+            validator.is_valid(),
+            validator.get_error_handler().0.borrow().is_empty()
+        );
+        let errors = validator.get_error_handler().0.borrow().clone();
+        errors
+    }
+
+    fn compact(schema: &str) -> Grammar {
+        Grammar::parse_compact(schema).unwrap()
+    }
+
+    const CARDS: &str = r#"
+        start = element cards { card* }
+        card = element card {
+            attribute id { xsd:NCName }?,
+            element name { text },
+            element email { text }+
+        }
+    "#;
+
+    #[test]
+    fn accepts_a_valid_document() {
+        let errors = validate(
+            compact(CARDS),
+            vec![
+                start("cards", &[]),
+                start("card", &[("id", "c1")]),
+                start("name", &[]),
+                text("Ann"),
+                end("name"),
+                start("email", &[]),
+                text("ann@example.com"),
+                end("email"),
+                start("email", &[]),
+                end("email"),
+                end("card"),
+                text("\n"),
+                end("cards"),
+            ],
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    #[test]
+    fn reports_elements_and_attributes_that_do_not_match() {
+        let errors = validate(
+            compact(CARDS),
+            vec![
+                start("cards", &[]),
+                start("card", &[("id", "1 2"), ("rank", "1")]),
+                start("email", &[]),
+                end("email"),
+                end("card"),
+                end("cards"),
+            ],
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "value \"1 2\" of attribute \"id\" is invalid",
+                "attribute \"rank\" not allowed on element \"card\"",
+                "element \"email\" not allowed here; expected \"name\"",
+                "element \"card\" incomplete; expected \"name\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_incomplete_content() {
+        let errors = validate(
+            compact(CARDS),
+            vec![
+                start("cards", &[]),
+                start("card", &[]),
+                start("name", &[]),
+                end("name"),
+                end("card"),
+                end("cards"),
+            ],
+        );
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].contains("element \"card\" incomplete"));
+    }
+
+    #[test]
+    fn reports_character_content_where_none_is_allowed() {
+        let errors = validate(
+            compact("element empty { empty }"),
+            vec![start("empty", &[]), text("text"), end("empty")],
+        );
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].contains("character content \"text\" not allowed here"));
+    }
+
+    #[test]
+    fn reports_violations_at_the_locator_position() {
+        let grammar = Rc::new(compact("element a { empty }"));
+        let validator = Validator::<LocatorImpl, _>::new(grammar, Errors::default());
+        let locator = LocatorImpl {
+            line_number: Some(3),
+            column_number: Some(7),
+            ..LocatorImpl::new()
+        };
+        let handler: &dyn ContentHandler<LocatorImpl, AttributesImpl> = &validator;
+        handler.set_document_locator(Rc::new(locator));
+        handler.start_document().unwrap();
+        handler
+            .start_element("", "b", "b", AttributesImpl::new())
+            .unwrap();
+        let errors = validator.get_error_handler().0.borrow();
+        assert!(errors[0].starts_with("3:7: element \"b\" not allowed here"));
+    }
+
+    #[test]
+    fn enforces_xsd_datatype_parameters() {
+        let grammar = || {
+            compact(
+                r#"element r {
+                    element age { xsd:integer { minInclusive = "0" maxInclusive = "150" } },
+                    element zip { xsd:token { pattern = "\d{5}(-\d{4})?" } }
+                }"#,
+            )
+        };
+        let document = |age: &str, zip: &str| {
+            vec![
+                start("r", &[]),
+                start("age", &[]),
+                text(age),
+                end("age"),
+                start("zip", &[]),
+                text(zip),
+                end("zip"),
+                end("r"),
+            ]
+        };
+        assert!(validate(grammar(), document("42", " 12345-6789 ")).is_empty());
+        assert_eq!(validate(grammar(), document("200", "12345")).len(), 1);
+        let errors = validate(grammar(), document("42", "1234"));
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].contains("\"1234\""));
+    }
+
+    #[test]
+    fn rejects_invalid_parameters_when_the_schema_is_loaded() {
+        let invalid = |param: &str| {
+            Grammar::parse_compact(&format!("element a {{ xsd:string {{ {} }} }}", param))
+                .expect_err(param)
+                .to_string()
+        };
+        assert!(invalid(r#"pattern = "[z-a]""#).contains("invalid \"pattern\" parameter"));
+        assert!(invalid(r#"pattern = "\p{IsBasicLatin}""#).contains("not supported"));
+        assert!(invalid(r#"minLength = "x""#).contains("invalid \"minLength\" parameter"));
+        assert!(invalid(r#"colour = "red""#).contains("unknown parameter \"colour\""));
+    }
+
+    #[test]
+    fn builds_a_grammar_from_the_xml_syntax() {
+        let builder = SchemaBuilder::<LocatorImpl>::new();
+        let rng = |local_name: &str, pairs: &[(&str, &str)]| {
+            let mut attributes = AttributesImpl::new();
+            for &(name, value) in pairs {
+                attributes.add_attribute("", name, name, "CDATA", value);
+            }
+            Event::start_element(RELAXNG_NS, local_name, local_name, &attributes)
+        };
+        let schema = vec![
+            Event::StartDocument,
+            rng("element", &[("name", "doc")]),
+            rng("choice", &[]),
+            rng("value", &[]),
+            text("yes"),
+            Event::end_element(RELAXNG_NS, "value", "value"),
+            rng("value", &[]),
+            text("no"),
+            Event::end_element(RELAXNG_NS, "value", "value"),
+            Event::end_element(RELAXNG_NS, "choice", "choice"),
+            Event::end_element(RELAXNG_NS, "element", "element"),
+            Event::EndDocument,
+        ];
+        for event in &schema {
+            event.dispatch(&builder).unwrap();
+        }
+        let grammar = || builder.build().unwrap();
+        assert!(validate(
+            grammar(),
+            vec![start("doc", &[]), text(" yes "), end("doc")]
+        )
+        .is_empty());
+        let errors = validate(
+            grammar(),
+            vec![start("doc", &[]), text("maybe"), end("doc")],
+        );
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+    }
+}
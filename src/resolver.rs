@@ -0,0 +1,970 @@
+//! Entity resolvers for common resolution strategies.
+//!
+//! `SandboxResolver` confines external entities to a directory tree and to resources held in
+//! memory, so that a document cannot make the reader open arbitrary files. The combinators
+//! build a single resolver out of several: `ChainResolver` asks each in turn,
+//! `CachingResolver` keeps the resources another resolver returns across parses, and
+//! `RecordingResolver` records each resolution for auditing.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::io::Cursor;
+use std::io::Read;
+use std::mem;
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use common;
+use common::EntityResolver;
+use ext::EntityResolver2;
+use helpers::ErrorKind;
+use helpers::ParseErrorImpl;
+use sax2::Result;
+use uri;
+
+fn refuse<T>(message: String) -> Result<T> {
+    Err(Box::new(ParseErrorImpl::of_kind(
+        ErrorKind::Resolution,
+        message,
+    )))
+}
+
+/// An entity resolver that serves resources from memory and from a directory tree, and nothing
+/// else.
+///
+/// System identifiers are resolved against the base URI of the referencing entity, as
+/// specified by RFC 3986: the one given to `EntityResolver2::resolve_entity_ext` when the
+/// reader provides it, and otherwise the one set with `set_base_uri`, which defaults to
+/// `file:///`. A resource registered with `insert` under the resulting URI is returned first.
+/// Otherwise, a `file:` URI is mapped to the file with the same path beneath the root
+/// directory, as if the root were the root of the file system: `file:///dtd/doc.dtd` names
+/// `dtd/doc.dtd` in the root.
+///
+/// Any other URI is refused with a `ParseErrorImpl`, as is a path leaving the root through `..`
+/// segments, escapes or symbolic links, and a file that cannot be opened. The resolver never
+/// declines an entity, so that the reader never opens a URI by itself.
+pub struct SandboxResolver {
+    root: Option<PathBuf>,
+    base_uri: RefCell<String>,
+    resources: HashMap<String, Rc<[u8]>>,
+}
+
+impl SandboxResolver {
+    /// Construct a resolver serving the files beneath a directory, and resources added with
+    /// `insert`.
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        let root = root.as_ref();
+        SandboxResolver {
+            root: Some(std::fs::canonicalize(root).unwrap_or_else(|_| root.to_owned())),
+            base_uri: RefCell::new("file:///".to_owned()),
+            resources: HashMap::new(),
+        }
+    }
+
+    /// Construct a resolver serving only the resources added with `insert`.
+    pub fn in_memory() -> Self {
+        SandboxResolver {
+            root: None,
+            base_uri: RefCell::new("file:///".to_owned()),
+            resources: HashMap::new(),
+        }
+    }
+
+    /// Return the root directory, if any.
+    pub fn get_root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    /// Return the base URI against which system identifiers are resolved when the reader does
+    /// not provide one.
+    pub fn get_base_uri(&self) -> String {
+        self.base_uri.borrow().clone()
+    }
+
+    /// Set the base URI against which system identifiers are resolved when the reader does not
+    /// provide one, typically the URI of the document being parsed.
+    pub fn set_base_uri(&self, base_uri: &str) {
+        *self.base_uri.borrow_mut() = base_uri.to_owned();
+    }
+
+    /// Register the content of a resource under an absolute URI, replacing any previous
+    /// content.
+    pub fn insert<C: Into<Vec<u8>>>(&mut self, uri: &str, content: C) {
+        self.resources
+            .insert(uri.to_owned(), Rc::from(content.into()));
+    }
+
+    /// Remove the resource registered under a URI, and return whether there was one.
+    pub fn remove(&mut self, uri: &str) -> bool {
+        self.resources.remove(uri).is_some()
+    }
+
+    /// Return the path beneath the root of the file named by a `file:` URI, or an error if the
+    /// URI does not name a file beneath the root.
+    pub fn map_uri(&self, absolute: &str) -> Result<PathBuf> {
+        let root = match self.root {
+            Some(ref root) => root,
+            None => return refuse(format!("{} is not an available resource", absolute)),
+        };
+        let is_file =
+            uri::scheme(absolute).is_some_and(|scheme| scheme.eq_ignore_ascii_case("file"));
+        let path = match uri::to_file_path(absolute) {
+            Some(ref path) if is_file => path.to_string_lossy().into_owned(),
+            _ => return refuse(format!("{} is not a local file: URI", absolute)),
+        };
+        let mut mapped = root.clone();
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return refuse(format!("{} leaves the root directory", absolute)),
+                segment if segment.contains('\0') || segment.contains('\\') => {
+                    return refuse(format!("{} is not a valid file name", absolute))
+                }
+                segment => mapped.push(segment),
+            }
+        }
+        if let Ok(canonical) = std::fs::canonicalize(&mapped) {
+            if !canonical.starts_with(root) {
+                return refuse(format!("{} leaves the root directory", absolute));
+            }
+        }
+        Ok(mapped)
+    }
+
+    fn open(&self, base_uri: &str, system_id: &str) -> Result<Option<Box<dyn Read>>> {
+        let absolute = uri::resolve(base_uri, system_id);
+        let (resource, _) = uri::split_fragment(&absolute);
+        if let Some(content) = self.resources.get(resource) {
+            return Ok(Some(Box::new(Cursor::new(content.clone()))));
+        }
+        let path = self.map_uri(resource)?;
+        match std::fs::File::open(&path) {
+            Ok(file) => Ok(Some(Box::new(file))),
+            Err(e) => refuse(format!("cannot open {}: {}", resource, e)),
+        }
+    }
+}
+
+impl EntityResolver for SandboxResolver {
+    fn resolve_entity(
+        &self,
+        _public_id: Option<&str>,
+        system_id: &str,
+    ) -> Result<Option<Box<dyn Read>>> {
+        let base_uri = self.get_base_uri();
+        self.open(&base_uri, system_id)
+    }
+}
+
+impl EntityResolver2 for SandboxResolver {
+    fn resolve_entity_ext(
+        &self,
+        _name: Option<&str>,
+        _public_id: Option<&str>,
+        base_uri: Option<&str>,
+        system_id: &str,
+    ) -> Result<Option<Box<dyn Read>>> {
+        match base_uri {
+            Some(base_uri) => self.open(base_uri, system_id),
+            None => self.resolve_entity(None, system_id),
+        }
+    }
+}
+
+/// An entity resolver asking several resolvers in turn.
+///
+/// Each resolver is asked in the order in which it was added, until one returns input. If
+/// none does, the entity is declined, leaving it to the reader. An error raised by a resolver
+/// is returned at once, unless `set_fallback_on_error` was called, in which case the next
+/// resolvers are still asked and the first error is only returned if none of them returns
+/// input. The same applies to `EntityResolver2::get_external_subset`, and the base URI and
+/// entity name given to `EntityResolver2::resolve_entity_ext` are passed on to every resolver.
+///
+/// A resolver implementing only `EntityResolver` can be added once it implements
+/// `EntityResolver2` with the default methods, which resolve the system identifier as written.
+#[derive(Default)]
+pub struct ChainResolver<'a> {
+    resolvers: Vec<Box<dyn EntityResolver2 + 'a>>,
+    fallback_on_error: bool,
+}
+
+impl<'a> ChainResolver<'a> {
+    /// Construct an empty chain, which declines every entity.
+    pub fn new() -> Self {
+        ChainResolver::default()
+    }
+
+    /// Add a resolver at the end of the chain.
+    pub fn push<ER: EntityResolver2 + 'a>(&mut self, resolver: ER) {
+        self.resolvers.push(Box::new(resolver));
+    }
+
+    /// Return the number of resolvers in the chain.
+    pub fn len(&self) -> usize {
+        self.resolvers.len()
+    }
+
+    /// Return whether the chain is empty.
+    pub fn is_empty(&self) -> bool {
+        self.resolvers.is_empty()
+    }
+
+    /// Return whether an error raised by a resolver lets the next resolvers be asked.
+    pub fn get_fallback_on_error(&self) -> bool {
+        self.fallback_on_error
+    }
+
+    /// Set whether an error raised by a resolver lets the next resolvers be asked (default
+    /// false).
+    pub fn set_fallback_on_error(&mut self, fallback_on_error: bool) {
+        self.fallback_on_error = fallback_on_error;
+    }
+
+    /// Ask each resolver in turn with `resolve`.
+    fn first<F>(&self, resolve: F) -> Result<Option<Box<dyn Read>>>
+    where
+        F: Fn(&dyn EntityResolver2) -> Result<Option<Box<dyn Read>>>,
+    {
+        let mut first_error = None;
+        for resolver in &self.resolvers {
+            match resolve(&**resolver) {
+                Ok(Some(input)) => return Ok(Some(input)),
+                Ok(None) => {}
+                Err(e) if self.fallback_on_error => {
+                    first_error.get_or_insert(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<'a> EntityResolver for ChainResolver<'a> {
+    fn resolve_entity(
+        &self,
+        public_id: Option<&str>,
+        system_id: &str,
+    ) -> Result<Option<Box<dyn Read>>> {
+        self.first(|resolver| resolver.resolve_entity(public_id, system_id))
+    }
+}
+
+impl<'a> EntityResolver2 for ChainResolver<'a> {
+    fn get_external_subset(
+        &self,
+        name: &str,
+        base_uri: Option<&str>,
+    ) -> Result<Option<Box<dyn Read>>> {
+        self.first(|resolver| resolver.get_external_subset(name, base_uri))
+    }
+
+    fn resolve_entity_ext(
+        &self,
+        name: Option<&str>,
+        public_id: Option<&str>,
+        base_uri: Option<&str>,
+        system_id: &str,
+    ) -> Result<Option<Box<dyn Read>>> {
+        self.first(|resolver| resolver.resolve_entity_ext(name, public_id, base_uri, system_id))
+    }
+}
+
+/// The public identifier of an entity and its absolute URI.
+type Key = (Option<String>, String);
+
+/// A resource cached by a `CachingResolver`.
+struct CacheEntry {
+    /// The content of the resource, `None` for an entity that was declined.
+    content: Option<Rc<[u8]>>,
+    /// The time of the last use of the entry.
+    used: u64,
+}
+
+struct Cache {
+    entries: HashMap<Key, CacheEntry>,
+    bytes: usize,
+    clock: u64,
+}
+
+/// An entity resolver memoizing the results of another resolver.
+///
+/// The input returned by the wrapped resolver is read in full and kept under the public
+/// identifier and the absolute URI of the entity, so that later parses resolving the same
+/// entity reuse it; that the entity was declined is remembered too. The absolute URI is the
+/// system identifier resolved against the base URI given to
+/// `EntityResolver2::resolve_entity_ext`, or the system identifier itself when it is absolute;
+/// entities with a relative system identifier and no base URI, and the external subsets
+/// returned by `EntityResolver2::get_external_subset`, are passed through without caching.
+/// Errors are not cached. When
+/// either the number of entries or their total size in bytes would exceed its bound, the
+/// least recently used entries are evicted; a resource larger than the size bound is returned
+/// without being cached.
+pub struct CachingResolver<ER> {
+    resolver: ER,
+    max_entries: usize,
+    max_bytes: usize,
+    cache: RefCell<Cache>,
+}
+
+impl<ER: EntityResolver2> CachingResolver<ER> {
+    /// Construct a resolver caching at most `max_entries` resolutions, of at most `max_bytes`
+    /// bytes in total.
+    pub fn new(resolver: ER, max_entries: usize, max_bytes: usize) -> Self {
+        CachingResolver {
+            resolver,
+            max_entries,
+            max_bytes,
+            cache: RefCell::new(Cache {
+                entries: HashMap::new(),
+                bytes: 0,
+                clock: 0,
+            }),
+        }
+    }
+
+    /// Return the wrapped resolver.
+    pub fn get_entity_resolver(&self) -> &ER {
+        &self.resolver
+    }
+
+    /// Return the number of cached resolutions.
+    pub fn len(&self) -> usize {
+        self.cache.borrow().entries.len()
+    }
+
+    /// Return whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the total size in bytes of the cached resources.
+    pub fn size(&self) -> usize {
+        self.cache.borrow().bytes
+    }
+
+    /// Remove all cached resolutions.
+    pub fn clear(&self) {
+        let mut cache = self.cache.borrow_mut();
+        cache.entries.clear();
+        cache.bytes = 0;
+    }
+
+    fn store(&self, key: Key, content: Option<Rc<[u8]>>) {
+        let size = content.as_ref().map_or(0, |content| content.len());
+        if self.max_entries == 0 || size > self.max_bytes {
+            return;
+        }
+        let mut cache = self.cache.borrow_mut();
+        while cache.entries.len() >= self.max_entries || cache.bytes + size > self.max_bytes {
+            let oldest = cache
+                .entries
+                .iter()
+                .min_by_key(|&(_, entry)| entry.used)
+                .map(|(key, _)| key.clone());
+            let removed = oldest.and_then(|key| cache.entries.remove(&key));
+            match removed {
+                Some(entry) => {
+                    cache.bytes -= entry.content.map_or(0, |content| content.len());
+                }
+                None => break,
+            }
+        }
+        cache.clock += 1;
+        let used = cache.clock;
+        cache.bytes += size;
+        cache.entries.insert(key, CacheEntry { content, used });
+    }
+
+    /// Return the cached resolution of an entity, or resolve it with `resolve` and cache it.
+    fn cached<F>(
+        &self,
+        public_id: Option<&str>,
+        absolute: Option<String>,
+        resolve: F,
+    ) -> Result<Option<Box<dyn Read>>>
+    where
+        F: FnOnce() -> Result<Option<Box<dyn Read>>>,
+    {
+        let absolute = match absolute {
+            Some(absolute) => absolute,
+            None => return resolve(),
+        };
+        let key = (public_id.map(str::to_owned), absolute);
+        {
+            let mut cache = self.cache.borrow_mut();
+            cache.clock += 1;
+            let now = cache.clock;
+            if let Some(entry) = cache.entries.get_mut(&key) {
+                entry.used = now;
+                return Ok(entry
+                    .content
+                    .clone()
+                    .map(|content| Box::new(Cursor::new(content)) as Box<dyn Read>));
+            }
+        }
+        match resolve()? {
+            Some(mut input) => {
+                let mut bytes = Vec::new();
+                input.read_to_end(&mut bytes).map_err(|e| {
+                    Box::new(ParseErrorImpl::of_kind(
+                        ErrorKind::Resolution,
+                        format!("cannot read {}: {}", key.1, e),
+                    )) as Box<dyn common::Error>
+                })?;
+                let content: Rc<[u8]> = Rc::from(bytes);
+                self.store(key, Some(content.clone()));
+                Ok(Some(Box::new(Cursor::new(content))))
+            }
+            None => {
+                self.store(key, None);
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl<ER: EntityResolver2> EntityResolver for CachingResolver<ER> {
+    fn resolve_entity(
+        &self,
+        public_id: Option<&str>,
+        system_id: &str,
+    ) -> Result<Option<Box<dyn Read>>> {
+        let absolute = Some(system_id.to_owned()).filter(|_| uri::is_absolute(system_id));
+        self.cached(public_id, absolute, || {
+            self.resolver.resolve_entity(public_id, system_id)
+        })
+    }
+}
+
+impl<ER: EntityResolver2> EntityResolver2 for CachingResolver<ER> {
+    fn get_external_subset(
+        &self,
+        name: &str,
+        base_uri: Option<&str>,
+    ) -> Result<Option<Box<dyn Read>>> {
+        self.resolver.get_external_subset(name, base_uri)
+    }
+
+    fn resolve_entity_ext(
+        &self,
+        name: Option<&str>,
+        public_id: Option<&str>,
+        base_uri: Option<&str>,
+        system_id: &str,
+    ) -> Result<Option<Box<dyn Read>>> {
+        let absolute = match base_uri {
+            Some(base_uri) => Some(uri::resolve(base_uri, system_id)),
+            None => Some(system_id.to_owned()).filter(|_| uri::is_absolute(system_id)),
+        };
+        self.cached(public_id, absolute, || {
+            self.resolver
+                .resolve_entity_ext(name, public_id, base_uri, system_id)
+        })
+    }
+}
+
+/// The outcome of a resolution recorded by a `RecordingResolver`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The resolver returned input.
+    Resolved,
+    /// The resolver declined the entity, leaving it to the reader.
+    Declined,
+    /// The resolver raised an error, with the given description.
+    Failed(String),
+}
+
+/// A resolution recorded by a `RecordingResolver`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Resolution {
+    /// The name of the entity, as given to `EntityResolver2::resolve_entity_ext`, or the name
+    /// of the document element for `EntityResolver2::get_external_subset`.
+    pub name: Option<String>,
+    /// The public identifier of the entity, if any.
+    pub public_id: Option<String>,
+    /// The base URI given by the reader, if any.
+    pub base_uri: Option<String>,
+    /// The system identifier of the entity as written, empty for an external subset requested
+    /// with `EntityResolver2::get_external_subset`.
+    pub system_id: String,
+    /// The outcome of the resolution.
+    pub outcome: Outcome,
+}
+
+type Log<'a> = dyn Fn(&Resolution) + 'a;
+
+/// An entity resolver recording each resolution made by another resolver.
+///
+/// Resolutions are passed as they happen to the callback registered with `on_resolution`, for
+/// instance to write them to an audit log. The most recent ones are also kept until they are
+/// taken with `take_resolutions`, up to `get_max_resolutions`; older ones are then dropped,
+/// and counted by `dropped`.
+pub struct RecordingResolver<'a, ER> {
+    resolver: ER,
+    resolutions: RefCell<VecDeque<Resolution>>,
+    max_resolutions: usize,
+    dropped: RefCell<u64>,
+    log: Option<Box<Log<'a>>>,
+}
+
+/// The number of resolutions a `RecordingResolver` keeps by default.
+pub const DEFAULT_MAX_RESOLUTIONS: usize = 1024;
+
+impl<'a, ER: EntityResolver2> RecordingResolver<'a, ER> {
+    /// Construct a resolver recording the resolutions made by another resolver, and keeping
+    /// at most `DEFAULT_MAX_RESOLUTIONS` of them.
+    pub fn new(resolver: ER) -> Self {
+        RecordingResolver {
+            resolver,
+            resolutions: RefCell::new(VecDeque::new()),
+            max_resolutions: DEFAULT_MAX_RESOLUTIONS,
+            dropped: RefCell::new(0),
+            log: None,
+        }
+    }
+
+    /// Return the maximum number of resolutions kept.
+    pub fn get_max_resolutions(&self) -> usize {
+        self.max_resolutions
+    }
+
+    /// Set the maximum number of resolutions kept, dropping the oldest ones beyond it.
+    pub fn set_max_resolutions(&mut self, max_resolutions: usize) {
+        self.max_resolutions = max_resolutions;
+        let mut resolutions = self.resolutions.borrow_mut();
+        while resolutions.len() > max_resolutions {
+            resolutions.pop_front();
+            *self.dropped.borrow_mut() += 1;
+        }
+    }
+
+    /// Return the number of resolutions dropped so far because too many were kept.
+    pub fn dropped(&self) -> u64 {
+        *self.dropped.borrow()
+    }
+
+    /// Return the wrapped resolver.
+    pub fn get_entity_resolver(&self) -> &ER {
+        &self.resolver
+    }
+
+    /// Invoke `callback` for each resolution, as it happens.
+    pub fn on_resolution<F: Fn(&Resolution) + 'a>(&mut self, callback: F) {
+        self.log = Some(Box::new(callback));
+    }
+
+    /// Return the resolutions kept so far, oldest first.
+    pub fn get_resolutions(&self) -> Vec<Resolution> {
+        self.resolutions.borrow().iter().cloned().collect()
+    }
+
+    /// Return the resolutions kept so far, oldest first, and forget them.
+    pub fn take_resolutions(&self) -> Vec<Resolution> {
+        mem::take(&mut *self.resolutions.borrow_mut()).into()
+    }
+
+    fn record(
+        &self,
+        mut resolution: Resolution,
+        result: Result<Option<Box<dyn Read>>>,
+    ) -> Result<Option<Box<dyn Read>>> {
+        resolution.outcome = match result {
+            Ok(Some(_)) => Outcome::Resolved,
+            Ok(None) => Outcome::Declined,
+            Err(ref e) => Outcome::Failed(e.to_string()),
+        };
+        if let Some(ref log) = self.log {
+            log(&resolution);
+        }
+        if self.max_resolutions == 0 {
+            *self.dropped.borrow_mut() += 1;
+            return result;
+        }
+        let mut resolutions = self.resolutions.borrow_mut();
+        if resolutions.len() >= self.max_resolutions {
+            resolutions.pop_front();
+            *self.dropped.borrow_mut() += 1;
+        }
+        resolutions.push_back(resolution);
+        result
+    }
+}
+
+impl<'a, ER: EntityResolver2> EntityResolver for RecordingResolver<'a, ER> {
+    fn resolve_entity(
+        &self,
+        public_id: Option<&str>,
+        system_id: &str,
+    ) -> Result<Option<Box<dyn Read>>> {
+        let resolution = Resolution {
+            name: None,
+            public_id: public_id.map(str::to_owned),
+            base_uri: None,
+            system_id: system_id.to_owned(),
+            outcome: Outcome::Declined,
+        };
+        self.record(
+            resolution,
+            self.resolver.resolve_entity(public_id, system_id),
+        )
+    }
+}
+
+impl<'a, ER: EntityResolver2> EntityResolver2 for RecordingResolver<'a, ER> {
+    fn get_external_subset(
+        &self,
+        name: &str,
+        base_uri: Option<&str>,
+    ) -> Result<Option<Box<dyn Read>>> {
+        let resolution = Resolution {
+            name: Some(name.to_owned()),
+            public_id: None,
+            base_uri: base_uri.map(str::to_owned),
+            system_id: String::new(),
+            outcome: Outcome::Declined,
+        };
+        self.record(
+            resolution,
+            self.resolver.get_external_subset(name, base_uri),
+        )
+    }
+
+    fn resolve_entity_ext(
+        &self,
+        name: Option<&str>,
+        public_id: Option<&str>,
+        base_uri: Option<&str>,
+        system_id: &str,
+    ) -> Result<Option<Box<dyn Read>>> {
+        let resolution = Resolution {
+            name: name.map(str::to_owned),
+            public_id: public_id.map(str::to_owned),
+            base_uri: base_uri.map(str::to_owned),
+            system_id: system_id.to_owned(),
+            outcome: Outcome::Declined,
+        };
+        self.record(
+            resolution,
+            self.resolver
+                .resolve_entity_ext(name, public_id, base_uri, system_id),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn read(input: Option<Box<dyn Read>>) -> Option<String> {
+        input.map(|mut input| {
+            let mut content = String::new();
+            input.read_to_string(&mut content).unwrap();
+            content
+        })
+    }
+
+    /// A resolver returning the URI it resolved, and counting its calls.
+    #[derive(Default)]
+    struct Echo {
+        calls: Cell<usize>,
+    }
+
+    impl EntityResolver for Echo {
+        fn resolve_entity(
+            &self,
+            _public_id: Option<&str>,
+            system_id: &str,
+        ) -> Result<Option<Box<dyn Read>>> {
+            self.calls.set(self.calls.get() + 1);
+            Ok(Some(Box::new(Cursor::new(system_id.to_owned()))))
+        }
+    }
+
+    impl EntityResolver2 for Echo {
+        fn get_external_subset(
+            &self,
+            name: &str,
+            _base_uri: Option<&str>,
+        ) -> Result<Option<Box<dyn Read>>> {
+            self.calls.set(self.calls.get() + 1);
+            Ok(Some(Box::new(Cursor::new(format!(
+                "<!ELEMENT {} ANY>",
+                name
+            )))))
+        }
+
+        fn resolve_entity_ext(
+            &self,
+            _name: Option<&str>,
+            _public_id: Option<&str>,
+            base_uri: Option<&str>,
+            system_id: &str,
+        ) -> Result<Option<Box<dyn Read>>> {
+            self.calls.set(self.calls.get() + 1);
+            let absolute = uri::resolve(base_uri.unwrap_or(""), system_id);
+            Ok(Some(Box::new(Cursor::new(absolute))))
+        }
+    }
+
+    /// A resolver declining every entity.
+    struct Decline;
+
+    impl EntityResolver for Decline {
+        fn resolve_entity(
+            &self,
+            _public_id: Option<&str>,
+            _system_id: &str,
+        ) -> Result<Option<Box<dyn Read>>> {
+            Ok(None)
+        }
+    }
+
+    impl EntityResolver2 for Decline {}
+
+    /// A resolver failing on every entity.
+    struct Fail;
+
+    impl EntityResolver for Fail {
+        fn resolve_entity(
+            &self,
+            _public_id: Option<&str>,
+            system_id: &str,
+        ) -> Result<Option<Box<dyn Read>>> {
+            refuse(format!("{} is refused", system_id))
+        }
+    }
+
+    impl EntityResolver2 for Fail {}
+
+    fn message<T>(result: Result<T>) -> String {
+        match result {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn sandbox_serves_resources_from_memory() {
+        let mut sandbox = SandboxResolver::in_memory();
+        sandbox.insert("file:///dtd/doc.dtd", "<!ELEMENT doc ANY>");
+        assert_eq!(sandbox.get_base_uri(), "file:///");
+        let result = sandbox.resolve_entity(None, "dtd/doc.dtd#top").unwrap();
+        assert_eq!(read(result).as_deref(), Some("<!ELEMENT doc ANY>"));
+        sandbox.set_base_uri("file:///dtd/main.xml");
+        let result = sandbox.resolve_entity(None, "doc.dtd").unwrap();
+        assert_eq!(read(result).as_deref(), Some("<!ELEMENT doc ANY>"));
+        let result = sandbox
+            .resolve_entity_ext(None, None, Some("file:///other/x.xml"), "../dtd/doc.dtd")
+            .unwrap();
+        assert_eq!(read(result).as_deref(), Some("<!ELEMENT doc ANY>"));
+        assert!(sandbox.remove("file:///dtd/doc.dtd"));
+        assert!(!sandbox.remove("file:///dtd/doc.dtd"));
+        assert_eq!(
+            message(sandbox.resolve_entity(None, "doc.dtd")),
+            "file:///dtd/doc.dtd is not an available resource"
+        );
+        assert_eq!(sandbox.get_root(), None);
+    }
+
+    #[test]
+    fn sandbox_confines_files_to_the_root() {
+        let dir = std::env::temp_dir().join(format!("sandbox-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("dtd")).unwrap();
+        std::fs::write(dir.join("dtd").join("doc.dtd"), "<!ELEMENT doc EMPTY>").unwrap();
+        let sandbox = SandboxResolver::new(&dir);
+        let root = sandbox.get_root().unwrap().to_owned();
+        assert_eq!(root, std::fs::canonicalize(&dir).unwrap());
+
+        let result = sandbox.resolve_entity(None, "/dtd/doc.dtd").unwrap();
+        assert_eq!(read(result).as_deref(), Some("<!ELEMENT doc EMPTY>"));
+        assert_eq!(
+            sandbox.map_uri("file:///dtd/./doc.dtd").unwrap(),
+            root.join("dtd").join("doc.dtd")
+        );
+        assert_eq!(
+            message(sandbox.map_uri("file:///dtd/../../etc/passwd")),
+            "file:///dtd/../../etc/passwd leaves the root directory"
+        );
+        assert_eq!(
+            message(sandbox.resolve_entity(None, "http://example.org/doc.dtd")),
+            "http://example.org/doc.dtd is not a local file: URI"
+        );
+        assert_eq!(
+            message(sandbox.map_uri("file://host/doc.dtd")),
+            "file://host/doc.dtd is not a local file: URI"
+        );
+        assert_eq!(
+            message(sandbox.map_uri("file:///a%5Cb")),
+            "file:///a%5Cb is not a valid file name"
+        );
+        assert!(message(sandbox.resolve_entity(None, "/missing.dtd"))
+            .starts_with("cannot open file:///missing.dtd: "));
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("/etc", dir.join("escape")).unwrap();
+            assert_eq!(
+                message(sandbox.map_uri("file:///escape/passwd")),
+                "file:///escape/passwd leaves the root directory"
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn chain_asks_resolvers_in_turn() {
+        let mut chain = ChainResolver::new();
+        chain.push(Decline);
+        let mut memory = SandboxResolver::in_memory();
+        memory.insert("http://example.org/a.dtd", "a");
+        chain.push(memory);
+        let result = chain
+            .resolve_entity_ext(None, None, Some("http://example.org/doc.xml"), "a.dtd")
+            .unwrap();
+        assert_eq!(read(result).as_deref(), Some("a"));
+        let result = chain
+            .get_external_subset("doc", Some("http://example.org/doc.xml"))
+            .unwrap();
+        assert!(result.is_none());
+        let mut chain = ChainResolver::new();
+        chain.push(Echo::default());
+        let result = chain.get_external_subset("doc", None).unwrap();
+        assert_eq!(read(result).as_deref(), Some("<!ELEMENT doc ANY>"));
+    }
+
+    #[test]
+    fn chain_errors() {
+        let mut chain = ChainResolver::new();
+        chain.push(Fail);
+        chain.push(Echo::default());
+        assert!(chain.resolve_entity(None, "a.dtd").is_err());
+        chain.set_fallback_on_error(true);
+        let result = chain.resolve_entity(None, "a.dtd").unwrap();
+        assert_eq!(read(result).as_deref(), Some("a.dtd"));
+        let mut chain = ChainResolver::new();
+        chain.set_fallback_on_error(true);
+        chain.push(Fail);
+        chain.push(Decline);
+        let error = chain.resolve_entity(None, "a.dtd").err().unwrap();
+        assert_eq!(error.to_string(), "a.dtd is refused");
+    }
+
+    #[test]
+    fn cache_is_keyed_on_the_absolute_uri() {
+        let cache = CachingResolver::new(Echo::default(), 16, 1024);
+        let first = cache
+            .resolve_entity_ext(None, None, Some("http://a/x/doc.xml"), "e.dtd")
+            .unwrap();
+        assert_eq!(read(first).as_deref(), Some("http://a/x/e.dtd"));
+        let second = cache
+            .resolve_entity_ext(None, None, Some("http://a/y/doc.xml"), "e.dtd")
+            .unwrap();
+        assert_eq!(read(second).as_deref(), Some("http://a/y/e.dtd"));
+        let again = cache
+            .resolve_entity_ext(None, None, Some("http://a/y/other.xml"), "e.dtd")
+            .unwrap();
+        assert_eq!(read(again).as_deref(), Some("http://a/y/e.dtd"));
+        assert_eq!(cache.get_entity_resolver().calls.get(), 2);
+        assert_eq!(cache.len(), 2);
+        let absolute = cache.resolve_entity(None, "http://a/y/e.dtd").unwrap();
+        assert_eq!(read(absolute).as_deref(), Some("http://a/y/e.dtd"));
+        assert_eq!(cache.get_entity_resolver().calls.get(), 2);
+    }
+
+    #[test]
+    fn cache_passes_through_what_it_cannot_key() {
+        let cache = CachingResolver::new(Echo::default(), 16, 1024);
+        cache.resolve_entity(None, "e.dtd").unwrap();
+        cache.resolve_entity(None, "e.dtd").unwrap();
+        cache.get_external_subset("doc", None).unwrap();
+        assert_eq!(cache.get_entity_resolver().calls.get(), 3);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let cache = CachingResolver::new(Echo::default(), 2, 1024);
+        cache.resolve_entity(None, "urn:a").unwrap();
+        cache.resolve_entity(None, "urn:b").unwrap();
+        cache.resolve_entity(None, "urn:a").unwrap();
+        cache.resolve_entity(None, "urn:c").unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.size(), 10);
+        cache.resolve_entity(None, "urn:a").unwrap();
+        assert_eq!(cache.get_entity_resolver().calls.get(), 3);
+        cache.resolve_entity(None, "urn:b").unwrap();
+        assert_eq!(cache.get_entity_resolver().calls.get(), 4);
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.size(), 0);
+    }
+
+    #[test]
+    fn recording_forwards_and_records() {
+        let seen = Cell::new(0);
+        let mut recording = RecordingResolver::new(Echo::default());
+        recording.on_resolution(|_| seen.set(seen.get() + 1));
+        let result = recording
+            .resolve_entity_ext(
+                Some("%e"),
+                Some("-//E//EN"),
+                Some("http://a/doc.xml"),
+                "e.dtd",
+            )
+            .unwrap();
+        assert_eq!(read(result).as_deref(), Some("http://a/e.dtd"));
+        recording
+            .get_external_subset("doc", Some("http://a/doc.xml"))
+            .unwrap();
+        let resolutions = recording.take_resolutions();
+        assert_eq!(
+            resolutions,
+            vec![
+                Resolution {
+                    name: Some("%e".to_owned()),
+                    public_id: Some("-//E//EN".to_owned()),
+                    base_uri: Some("http://a/doc.xml".to_owned()),
+                    system_id: "e.dtd".to_owned(),
+                    outcome: Outcome::Resolved,
+                },
+                Resolution {
+                    name: Some("doc".to_owned()),
+                    public_id: None,
+                    base_uri: Some("http://a/doc.xml".to_owned()),
+                    system_id: String::new(),
+                    outcome: Outcome::Resolved,
+                },
+            ]
+        );
+        assert!(recording.get_resolutions().is_empty());
+        drop(recording);
+        assert_eq!(seen.get(), 2);
+    }
+
+    #[test]
+    fn recording_is_capped() {
+        let mut recording = RecordingResolver::new(Fail);
+        recording.set_max_resolutions(2);
+        for system_id in &["a", "b", "c"] {
+            assert!(recording.resolve_entity(None, system_id).is_err());
+        }
+        let resolutions = recording.get_resolutions();
+        assert_eq!(resolutions.len(), 2);
+        assert_eq!(resolutions[0].system_id, "b");
+        assert_eq!(
+            resolutions[1].outcome,
+            Outcome::Failed("c is refused".to_owned())
+        );
+        assert_eq!(recording.dropped(), 1);
+        recording.set_max_resolutions(0);
+        assert!(recording.resolve_entity(None, "d").is_err());
+        assert!(recording.get_resolutions().is_empty());
+        assert_eq!(recording.dropped(), 4);
+    }
+}
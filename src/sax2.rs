@@ -1,11 +1,11 @@
-/// The SAX2 API definitions from which these interfaces were derived comes with
-/// the following notice:
-///
-/// > This module, both source code and documentation, is in the Public Domain,
-/// > and comes with NO WARRANTY. See http://www.saxproject.org for further
-/// > information.
-///
-/// See also http://www.saxproject.org/copying.html
+//! The SAX2 API definitions from which these interfaces were derived comes with
+//! the following notice:
+//!
+//! > This module, both source code and documentation, is in the Public Domain,
+//! > and comes with NO WARRANTY. See http://www.saxproject.org for further
+//! > information.
+//!
+//! See also http://www.saxproject.org/copying.html
 use std::rc::Rc;
 
 use common::EntityResolver;
@@ -16,7 +16,7 @@ use common::Locator;
 /// Modelled after `org.xml.sax.SAXParseException`
 pub trait ParseError: Error + Locator {}
 
-pub type Result<T> = std::result::Result<T, Box<Error>>;
+pub type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
 /// Interface for a list of XML attributes.
 ///
@@ -385,7 +385,7 @@ pub trait ContentHandler<L: Locator, A: Attributes> {
     ///
     /// * the Namespace URI and local name are required when the namespaces property is true (the
     ///   default), and are optional when the namespaces property is false (if one is specified,
-    /// both must be);
+    ///   both must be);
     /// * the qualified name is required when the namespace-prefixes property is true, and is
     ///   optional when the namespace-prefixes property is false (the default).
     ///
@@ -458,19 +458,19 @@ pub trait XmlReader<
     /// Return the current content handler.
     fn get_content_handler(&self) -> Option<&CH>;
     /// Return the current content handler (mutable).
-    fn get_content_handler_mut(&self) -> Option<&mut CH>;
+    fn get_content_handler_mut(&mut self) -> Option<&mut CH>;
     /// Return the current DTD handler.
     fn get_dtd_handler(&self) -> Option<&DH>;
     /// Return the current DTD handler (mutable).
-    fn get_dtd_handler_mut(&self) -> Option<&mut DH>;
+    fn get_dtd_handler_mut(&mut self) -> Option<&mut DH>;
     /// Return the current entity resolver.
     fn get_entity_resolver(&self) -> Option<&ER>;
     /// Return the current entity resolver (mutable).
-    fn get_entity_resolver_mut(&self) -> Option<&mut DH>;
+    fn get_entity_resolver_mut(&mut self) -> Option<&mut ER>;
     /// Return the current error handler.
     fn get_error_handler(&self) -> Option<&EH>;
     /// Return the current error handler (mutable).
-    fn get_error_handler_mut(&self) -> Option<&mut EH>;
+    fn get_error_handler_mut(&mut self) -> Option<&mut EH>;
     /// Look up the value of a feature flag.
     ///
     /// The feature name is any fully-qualified URI. It is possible for an XmlReader to recognize a
@@ -593,3 +593,28 @@ pub trait XmlReader<
     // TODO: Add variant that takes/returns downcastable Boxed things?
     fn set_property_str(&self, name: &str, value: &str) -> Result<()>;
 }
+
+/// Interface for an XML filter.
+///
+/// An XML filter is like an XML reader, except that it obtains its events from another XML
+/// reader rather than a primary source like an XML document or database. Filters can modify a
+/// stream of events as they pass on to the final application.
+///
+/// A filter implements `XmlReader` itself, registers itself as the handler of its parent
+/// reader, and forwards the (possibly modified) events it receives to the handlers registered
+/// with it. Parsing with the filter parses with its parent.
+///
+/// Modelled after `org.xml.sax.XMLFilter`
+pub trait XmlFilter<R> {
+    /// Get the parent reader.
+    ///
+    /// This method allows the application to query the parent reader (which may be another
+    /// filter). It is generally a bad idea to perform any operations on the parent reader
+    /// directly: they should all pass through this filter.
+    fn get_parent(&self) -> Option<&R>;
+    /// Set the parent reader.
+    ///
+    /// This method allows the application to link the filter to a parent reader (which may be
+    /// another filter).
+    fn set_parent(&self, parent: R);
+}
@@ -0,0 +1,1290 @@
+//! Asynchronous counterparts of the SAX2 reader and content handler interfaces.
+//!
+//! The interfaces in `sax2` are synchronous: `XmlReader::parse` blocks until the whole document
+//! has been read. The interfaces here read from a `futures_io::AsyncRead` instead, and deliver
+//! the document either to an `AsyncContentHandler` whose callbacks may themselves await, or as a
+//! `Stream` of owned `Event`s.
+//!
+//! `BufferedXmlReader` implements `AsyncXmlReader` on top of any synchronous `XmlReader`: it
+//! reads the whole input asynchronously, runs the synchronous reader over it, and then hands
+//! the recorded events to the content handler one at a time, awaiting each callback.
+//!
+//! The futures and streams returned by the interfaces are `Send`, so a parse can be spawned on
+//! a multi-threaded executor. `BufferedXmlReader` also offers `parse_local` and `events_local`,
+//! which return the `LocalBoxFuture` and `LocalBoxStream` variants and work with readers and
+//! handlers that are not `Sync`.
+//!
+//! For the same reason, the futures resolve to this module's `Result`, whose error is `Send`
+//! and `Sync`, unlike the error of `sax2::Result`. An error of the synchronous reader, or of a
+//! handler wrapped in `BlockingHandler`, is passed on as is if it is a `ParseErrorImpl`, and
+//! otherwise replaced by a `ParseErrorImpl` with the same message.
+//!
+//! `futures_io::AsyncRead` is implemented for `&[u8]`, so in-memory documents can be parsed
+//! without any runtime-specific I/O. Tokio readers can be adapted with the `compat` layer from
+//! `tokio-util`.
+//!
+//! These interfaces are only available when the `async` cargo feature is enabled.
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+use futures_core::Stream;
+use futures_io::AsyncRead;
+
+use common;
+use common::EntityResolver;
+use common::InputSource;
+use common::Locator;
+use event::Event;
+use event::EventRecorder;
+use helpers::AttributesImpl;
+use helpers::ErrorKind;
+use helpers::ParseErrorImpl;
+use sax2;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::DtdHandler;
+use sax2::ErrorHandler;
+use sax2::ParseError;
+use sax2::XmlReader;
+
+/// The result of the asynchronous interfaces, whose error can be sent to other threads.
+pub type Result<T> = std::result::Result<T, Box<dyn common::Error + Send + Sync>>;
+
+/// A boxed future, as returned by the asynchronous handler and reader methods.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A boxed future that is not `Send`, as returned by `BufferedXmlReader::parse_local`.
+pub type LocalBoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// A boxed stream, as returned by `AsyncXmlReader::events`.
+pub type BoxStream<'a, T> = Pin<Box<dyn Stream<Item = T> + Send + 'a>>;
+
+/// A boxed stream that is not `Send`, as returned by `BufferedXmlReader::events_local`.
+pub type LocalBoxStream<'a, T> = Pin<Box<dyn Stream<Item = T> + 'a>>;
+
+fn done<'a>() -> BoxFuture<'a, Result<()>> {
+    Box::pin(std::future::ready(Ok(())))
+}
+
+/// Turn the result of a synchronous reader or handler into one whose error can be sent to other
+/// threads.
+fn sendable<T>(result: sax2::Result<T>) -> Result<T> {
+    result.map_err(|e| {
+        let e: Box<dyn std::error::Error> = e;
+        match e.downcast::<ParseErrorImpl>() {
+            Ok(e) => e as Box<dyn common::Error + Send + Sync>,
+            Err(e) => ParseErrorImpl::of_kind(ErrorKind::Parse, e.to_string()).into(),
+        }
+    })
+}
+
+/// A single asynchronous input source for an XML entity.
+///
+/// This is the asynchronous counterpart of `common::InputSource`: the bytes of the entity are
+/// read through `futures_io::AsyncRead` rather than `std::io::Read`.
+pub trait AsyncInputSource: AsyncRead + Unpin {
+    /// Create a new input source with a system identifier.
+    ///
+    /// See `InputSource::new`.
+    fn new(system_id: &str) -> Self;
+    /// Get the character encoding being used for the input source.
+    fn get_encoding(&self) -> Option<String>;
+    /// Get the public identifier for this input source.
+    fn get_public_id(&self) -> Option<String>;
+    /// Get the system identifier for this input source.
+    fn get_system_id(&self) -> Option<String>;
+    /// Set the character encoding, if known.
+    fn set_encoding(&self, encoding: &str);
+    /// Set the public identifier for this input source.
+    fn set_public_id(&self, public_id: &str);
+    /// Set the system identifier for this input source.
+    fn set_system_id(&self, system_id: &str);
+}
+
+/// Receives asynchronous notification of the logical content of a document.
+///
+/// Every callback mirrors the method of the same name on `ContentHandler`, but returns a future
+/// which the reader awaits before reporting the next event. The arguments are passed by value,
+/// so that the future can hold on to them without borrowing from the reader. The default
+/// implementations resolve immediately.
+///
+/// The futures must be `Send`. A handler that keeps its state in a `RefCell` can still do its
+/// work before returning an already-resolved future, as `BlockingHandler` does.
+pub trait AsyncContentHandler<L: Locator, A: Attributes> {
+    /// See `ContentHandler::characters`.
+    #[allow(unused_variables)]
+    fn characters(&self, content: String) -> BoxFuture<'_, Result<()>> {
+        done()
+    }
+    /// See `ContentHandler::end_document`.
+    fn end_document(&self) -> BoxFuture<'_, Result<()>> {
+        done()
+    }
+    /// See `ContentHandler::end_element`.
+    #[allow(unused_variables)]
+    fn end_element(
+        &self,
+        uri: String,
+        local_name: String,
+        q_name: String,
+    ) -> BoxFuture<'_, Result<()>> {
+        done()
+    }
+    /// See `ContentHandler::end_prefix_mapping`.
+    #[allow(unused_variables)]
+    fn end_prefix_mapping(&self, prefix: String) -> BoxFuture<'_, Result<()>> {
+        done()
+    }
+    /// See `ContentHandler::ignorable_whitespace`.
+    #[allow(unused_variables)]
+    fn ignorable_whitespace(&self, content: String) -> BoxFuture<'_, Result<()>> {
+        done()
+    }
+    /// See `ContentHandler::processing_instruction`.
+    #[allow(unused_variables)]
+    fn processing_instruction(&self, target: String, data: String) -> BoxFuture<'_, Result<()>> {
+        done()
+    }
+    /// See `ContentHandler::set_document_locator`.
+    ///
+    /// This callback is synchronous, since it only hands over the locator.
+    #[allow(unused_variables)]
+    fn set_document_locator(&self, locator: std::rc::Rc<L>) {}
+    /// See `ContentHandler::skipped_entity`.
+    #[allow(unused_variables)]
+    fn skipped_entity(&self, name: String) -> BoxFuture<'_, Result<()>> {
+        done()
+    }
+    /// See `ContentHandler::start_document`.
+    fn start_document(&self) -> BoxFuture<'_, Result<()>> {
+        done()
+    }
+    /// See `ContentHandler::start_element`.
+    #[allow(unused_variables)]
+    fn start_element<'a>(
+        &'a self,
+        uri: String,
+        local_name: String,
+        q_name: String,
+        attributes: A,
+    ) -> BoxFuture<'a, Result<()>>
+    where
+        A: 'a,
+    {
+        done()
+    }
+    /// See `ContentHandler::start_prefix_mapping`.
+    #[allow(unused_variables)]
+    fn start_prefix_mapping(&self, prefix: String, uri: String) -> BoxFuture<'_, Result<()>> {
+        done()
+    }
+}
+
+/// Adapts a synchronous `ContentHandler` for use with an `AsyncXmlReader`.
+///
+/// Each callback runs the wrapped handler to completion and returns an already-resolved future,
+/// so the wrapped handler should not block for long.
+pub struct BlockingHandler<H> {
+    handler: H,
+}
+
+impl<H> BlockingHandler<H> {
+    /// Wrap a synchronous content handler.
+    pub fn new(handler: H) -> Self {
+        BlockingHandler { handler }
+    }
+
+    /// Return the wrapped content handler.
+    pub fn get_ref(&self) -> &H {
+        &self.handler
+    }
+
+    /// Unwrap the synchronous content handler.
+    pub fn into_inner(self) -> H {
+        self.handler
+    }
+}
+
+impl<L: Locator, A: Attributes, H: ContentHandler<L, A>> AsyncContentHandler<L, A>
+    for BlockingHandler<H>
+{
+    fn characters(&self, content: String) -> BoxFuture<'_, Result<()>> {
+        Box::pin(std::future::ready(sendable(
+            self.handler.characters(&content),
+        )))
+    }
+    fn end_document(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(std::future::ready(sendable(self.handler.end_document())))
+    }
+    fn end_element(
+        &self,
+        uri: String,
+        local_name: String,
+        q_name: String,
+    ) -> BoxFuture<'_, Result<()>> {
+        Box::pin(std::future::ready(sendable(self.handler.end_element(
+            &uri,
+            &local_name,
+            &q_name,
+        ))))
+    }
+    fn end_prefix_mapping(&self, prefix: String) -> BoxFuture<'_, Result<()>> {
+        Box::pin(std::future::ready(sendable(
+            self.handler.end_prefix_mapping(&prefix),
+        )))
+    }
+    fn ignorable_whitespace(&self, content: String) -> BoxFuture<'_, Result<()>> {
+        Box::pin(std::future::ready(sendable(
+            self.handler.ignorable_whitespace(&content),
+        )))
+    }
+    fn processing_instruction(&self, target: String, data: String) -> BoxFuture<'_, Result<()>> {
+        Box::pin(std::future::ready(sendable(
+            self.handler.processing_instruction(&target, &data),
+        )))
+    }
+    fn set_document_locator(&self, locator: std::rc::Rc<L>) {
+        self.handler.set_document_locator(locator)
+    }
+    fn skipped_entity(&self, name: String) -> BoxFuture<'_, Result<()>> {
+        Box::pin(std::future::ready(sendable(
+            self.handler.skipped_entity(&name),
+        )))
+    }
+    fn start_document(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(std::future::ready(sendable(self.handler.start_document())))
+    }
+    fn start_element<'a>(
+        &'a self,
+        uri: String,
+        local_name: String,
+        q_name: String,
+        attributes: A,
+    ) -> BoxFuture<'a, Result<()>>
+    where
+        A: 'a,
+    {
+        Box::pin(std::future::ready(sendable(self.handler.start_element(
+            &uri,
+            &local_name,
+            &q_name,
+            attributes,
+        ))))
+    }
+    fn start_prefix_mapping(&self, prefix: String, uri: String) -> BoxFuture<'_, Result<()>> {
+        Box::pin(std::future::ready(sendable(
+            self.handler.start_prefix_mapping(&prefix, &uri),
+        )))
+    }
+}
+
+/// Interface for reading an XML document asynchronously.
+///
+/// This is the asynchronous counterpart of `sax2::XmlReader`. Features and properties work
+/// exactly as they do there; handlers are registered through `&mut self`, so that they cannot
+/// be replaced while a parse borrows the reader. Instead of blocking, the parse methods return
+/// a future that drives the parse as the input becomes readable, or a stream that yields each
+/// event as it is parsed.
+///
+/// As with the synchronous reader, events are delivered strictly in document order, and the
+/// reader must wait for each handler future to complete before reporting the next event.
+pub trait AsyncXmlReader<
+    CH: AsyncContentHandler<L, A>,
+    DH: DtdHandler,
+    ER: EntityResolver,
+    EH: ErrorHandler<E>,
+    L: Locator,
+    A: Attributes,
+    E: ParseError,
+    I: AsyncInputSource,
+>
+{
+    /// Return the current content handler.
+    fn get_content_handler(&self) -> Option<&CH>;
+    /// Return the current content handler (mutable).
+    fn get_content_handler_mut(&mut self) -> Option<&mut CH>;
+    /// Return the current DTD handler.
+    fn get_dtd_handler(&self) -> Option<&DH>;
+    /// Return the current DTD handler (mutable).
+    fn get_dtd_handler_mut(&mut self) -> Option<&mut DH>;
+    /// Return the current entity resolver.
+    fn get_entity_resolver(&self) -> Option<&ER>;
+    /// Return the current entity resolver (mutable).
+    fn get_entity_resolver_mut(&mut self) -> Option<&mut ER>;
+    /// Return the current error handler.
+    fn get_error_handler(&self) -> Option<&EH>;
+    /// Return the current error handler (mutable).
+    fn get_error_handler_mut(&mut self) -> Option<&mut EH>;
+    /// Look up the value of a feature flag.
+    ///
+    /// See `XmlReader::get_feature`.
+    fn get_feature(&self, name: &str) -> sax2::Result<bool>;
+    /// Look up the value of a property.
+    ///
+    /// See `XmlReader::get_property_str`.
+    fn get_property_str(&self, name: &str) -> sax2::Result<String>;
+    /// Parse an XML document, delivering events to the registered content handler.
+    ///
+    /// The returned future completes once parsing has ended, either because the end of input
+    /// was reached or because a handler returned an error. Dropping the future abandons the
+    /// parse.
+    fn parse<'a>(&'a self, input: &'a mut I) -> BoxFuture<'a, Result<()>>;
+    /// Parse an XML document from a system identifier (URI).
+    ///
+    /// See `XmlReader::parse_uri`.
+    fn parse_uri<'a>(&'a self, system_id: &'a str) -> BoxFuture<'a, Result<()>>;
+    /// Parse an XML document, yielding its content events as a stream.
+    ///
+    /// The registered content handler is not called; every event it would have received is
+    /// yielded instead, in the same order. DTD, entity resolution and error events are still
+    /// delivered to their registered handlers. The stream ends after `Event::EndDocument`, or
+    /// after yielding the first error.
+    fn events<'a>(&'a self, input: &'a mut I) -> BoxStream<'a, Result<Event>>;
+    /// Allow an application to register a content event handler.
+    ///
+    /// See `XmlReader::set_content_handler`.
+    fn set_content_handler(&mut self, handler: CH);
+    /// Allow an application to register a DTD event handler.
+    ///
+    /// See `XmlReader::set_dtd_handler`.
+    fn set_dtd_handler(&mut self, handler: DH);
+    /// Allow an application to register an entity resolver.
+    ///
+    /// See `XmlReader::set_entity_resolver`.
+    fn set_entity_resolver(&mut self, resolver: ER);
+    /// Allow an application to register an error event handler.
+    ///
+    /// See `XmlReader::set_error_handler`.
+    fn set_error_handler(&mut self, handler: EH);
+    /// Set the value of a feature flag.
+    ///
+    /// See `XmlReader::set_feature`.
+    fn set_feature(&self, name: &str, value: bool) -> sax2::Result<()>;
+    /// Set the value of a property.
+    ///
+    /// See `XmlReader::set_property_str`.
+    fn set_property_str(&self, name: &str, value: &str) -> sax2::Result<()>;
+}
+
+/// An `AsyncXmlReader` that runs a synchronous `XmlReader` over input read asynchronously.
+///
+/// The whole input is read into memory before the wrapped reader sees any of it, so this is
+/// suited to documents of bounded size, such as request and response bodies. The wrapped
+/// reader reports the content to an `EventRecorder`; once it returns, the recorded events are
+/// delivered to the content handler, and the parse result of the wrapped reader is returned
+/// after the last of them. DTD, entity resolution and error events are reported by the wrapped
+/// reader while it runs. No document locator is passed to the content handler.
+///
+/// The wrapped reader is given the bytes as an input source of type `S`, which carries over
+/// the system identifier, public identifier and encoding of the asynchronous input.
+///
+/// `parse` and `events` return `Send` futures and streams, which requires the wrapped reader
+/// and the content handler to be `Sync`. `parse_local` and `events_local` have no such
+/// requirement.
+pub struct BufferedXmlReader<R, CH, S> {
+    reader: R,
+    content_handler: Option<CH>,
+    // Only names the input source type: whether the reader is `Send` or `Sync` does not depend
+    // on it.
+    source: PhantomData<fn() -> S>,
+}
+
+impl<R, CH, S> BufferedXmlReader<R, CH, S> {
+    /// Wrap a synchronous reader.
+    pub fn new(reader: R) -> Self {
+        BufferedXmlReader {
+            reader,
+            content_handler: None,
+            source: PhantomData,
+        }
+    }
+
+    /// Return the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Return the wrapped reader (mutable).
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Unwrap the synchronous reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R, CH, S: InputSource + From<Vec<u8>>> BufferedXmlReader<R, CH, S> {
+    /// Parse an XML document, delivering events to the registered content handler.
+    ///
+    /// This is `AsyncXmlReader::parse` for readers and handlers that are not `Sync`.
+    pub fn parse_local<'a, DH, ER, EH, L, E, I>(
+        &'a self,
+        input: &'a mut I,
+    ) -> LocalBoxFuture<'a, Result<()>>
+    where
+        R: XmlReader<EventRecorder<L>, DH, ER, EH, L, AttributesImpl, E, S>,
+        CH: AsyncContentHandler<L, AttributesImpl>,
+        DH: DtdHandler,
+        ER: EntityResolver,
+        EH: ErrorHandler<E>,
+        L: Locator,
+        E: ParseError,
+        I: AsyncInputSource,
+    {
+        Box::pin(self.start_parse::<DH, ER, EH, L, E, I>(input))
+    }
+
+    /// Parse an XML document, yielding its content events as a stream.
+    ///
+    /// This is `AsyncXmlReader::events` for readers that are not `Sync`.
+    pub fn events_local<'a, DH, ER, EH, L, E, I>(
+        &'a self,
+        input: &'a mut I,
+    ) -> LocalBoxStream<'a, Result<Event>>
+    where
+        R: XmlReader<EventRecorder<L>, DH, ER, EH, L, AttributesImpl, E, S>,
+        DH: DtdHandler,
+        ER: EntityResolver,
+        EH: ErrorHandler<E>,
+        L: Locator,
+        E: ParseError,
+        I: AsyncInputSource,
+    {
+        Box::pin(Events {
+            buffer: self.buffer::<DH, ER, EH, L, E, I>(input),
+            recorded: None,
+            finished: false,
+        })
+    }
+
+    fn record<DH, ER, EH, L, E, F>(&self, parse: F) -> Recorded
+    where
+        F: FnOnce(&R) -> sax2::Result<()>,
+        R: XmlReader<EventRecorder<L>, DH, ER, EH, L, AttributesImpl, E, S>,
+        DH: DtdHandler,
+        ER: EntityResolver,
+        EH: ErrorHandler<E>,
+        L: Locator,
+        E: ParseError,
+    {
+        match self.reader.get_content_handler() {
+            Some(recorder) => {
+                recorder.take_events();
+            }
+            None => self.reader.set_content_handler(EventRecorder::new()),
+        }
+        let end = sendable(parse(&self.reader));
+        let events = self
+            .reader
+            .get_content_handler()
+            .map(EventRecorder::take_events)
+            .unwrap_or_default();
+        Recorded {
+            events: events.into(),
+            end,
+        }
+    }
+
+    fn buffer<'a, DH, ER, EH, L, E, I>(
+        &'a self,
+        input: &'a mut I,
+    ) -> Buffer<'a, I, impl FnOnce(Vec<u8>) -> Recorded + Unpin + 'a>
+    where
+        R: XmlReader<EventRecorder<L>, DH, ER, EH, L, AttributesImpl, E, S>,
+        DH: DtdHandler,
+        ER: EntityResolver,
+        EH: ErrorHandler<E>,
+        L: Locator,
+        E: ParseError,
+        I: AsyncInputSource,
+    {
+        let system_id = input.get_system_id();
+        let public_id = input.get_public_id();
+        let encoding = input.get_encoding();
+        let record = move |bytes: Vec<u8>| {
+            let mut source = S::from(bytes);
+            if let Some(ref system_id) = system_id {
+                source.set_system_id(system_id);
+            }
+            if let Some(ref public_id) = public_id {
+                source.set_public_id(public_id);
+            }
+            if let Some(ref encoding) = encoding {
+                source.set_encoding(encoding);
+            }
+            self.record::<DH, ER, EH, L, E, _>(move |reader| reader.parse(&mut source))
+        };
+        Buffer {
+            input,
+            bytes: Vec::new(),
+            record: Some(record),
+        }
+    }
+
+    fn start_parse<'a, DH, ER, EH, L, E, I>(
+        &'a self,
+        input: &'a mut I,
+    ) -> Parse<'a, CH, I, impl FnOnce(Vec<u8>) -> Recorded + Unpin + 'a>
+    where
+        R: XmlReader<EventRecorder<L>, DH, ER, EH, L, AttributesImpl, E, S>,
+        CH: AsyncContentHandler<L, AttributesImpl>,
+        DH: DtdHandler,
+        ER: EntityResolver,
+        EH: ErrorHandler<E>,
+        L: Locator,
+        E: ParseError,
+        I: AsyncInputSource,
+    {
+        Parse {
+            buffer: Some(self.buffer::<DH, ER, EH, L, E, I>(input)),
+            recorded: None,
+            handler: self.content_handler.as_ref(),
+            deliver: deliver::<L, CH>,
+            pending: None,
+        }
+    }
+}
+
+impl<R, CH, S, DH, ER, EH, L, E, I> AsyncXmlReader<CH, DH, ER, EH, L, AttributesImpl, E, I>
+    for BufferedXmlReader<R, CH, S>
+where
+    R: XmlReader<EventRecorder<L>, DH, ER, EH, L, AttributesImpl, E, S> + Sync,
+    CH: AsyncContentHandler<L, AttributesImpl> + Sync,
+    DH: DtdHandler,
+    ER: EntityResolver,
+    EH: ErrorHandler<E>,
+    L: Locator,
+    E: ParseError,
+    S: InputSource + From<Vec<u8>>,
+    I: AsyncInputSource + Send,
+{
+    fn get_content_handler(&self) -> Option<&CH> {
+        self.content_handler.as_ref()
+    }
+    fn get_content_handler_mut(&mut self) -> Option<&mut CH> {
+        self.content_handler.as_mut()
+    }
+    fn get_dtd_handler(&self) -> Option<&DH> {
+        self.reader.get_dtd_handler()
+    }
+    fn get_dtd_handler_mut(&mut self) -> Option<&mut DH> {
+        self.reader.get_dtd_handler_mut()
+    }
+    fn get_entity_resolver(&self) -> Option<&ER> {
+        self.reader.get_entity_resolver()
+    }
+    fn get_entity_resolver_mut(&mut self) -> Option<&mut ER> {
+        self.reader.get_entity_resolver_mut()
+    }
+    fn get_error_handler(&self) -> Option<&EH> {
+        self.reader.get_error_handler()
+    }
+    fn get_error_handler_mut(&mut self) -> Option<&mut EH> {
+        self.reader.get_error_handler_mut()
+    }
+    fn get_feature(&self, name: &str) -> sax2::Result<bool> {
+        self.reader.get_feature(name)
+    }
+    fn get_property_str(&self, name: &str) -> sax2::Result<String> {
+        self.reader.get_property_str(name)
+    }
+    fn parse<'a>(&'a self, input: &'a mut I) -> BoxFuture<'a, Result<()>> {
+        Box::pin(self.start_parse::<DH, ER, EH, L, E, I>(input))
+    }
+    /// Parse an XML document from a system identifier (URI).
+    ///
+    /// The wrapped reader opens and reads the document itself, synchronously, when this method
+    /// is called; only the delivery of the events is asynchronous.
+    fn parse_uri<'a>(&'a self, system_id: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(Parse::<CH, &[u8], fn(Vec<u8>) -> Recorded> {
+            buffer: None,
+            recorded: Some(
+                self.record::<DH, ER, EH, L, E, _>(|reader| reader.parse_uri(system_id)),
+            ),
+            handler: self.content_handler.as_ref(),
+            deliver: deliver::<L, CH>,
+            pending: None,
+        })
+    }
+    fn events<'a>(&'a self, input: &'a mut I) -> BoxStream<'a, Result<Event>> {
+        Box::pin(Events {
+            buffer: self.buffer::<DH, ER, EH, L, E, I>(input),
+            recorded: None,
+            finished: false,
+        })
+    }
+    fn set_content_handler(&mut self, handler: CH) {
+        self.content_handler = Some(handler);
+    }
+    fn set_dtd_handler(&mut self, handler: DH) {
+        self.reader.set_dtd_handler(handler)
+    }
+    fn set_entity_resolver(&mut self, resolver: ER) {
+        self.reader.set_entity_resolver(resolver)
+    }
+    fn set_error_handler(&mut self, handler: EH) {
+        self.reader.set_error_handler(handler)
+    }
+    fn set_feature(&self, name: &str, value: bool) -> sax2::Result<()> {
+        self.reader.set_feature(name, value)
+    }
+    fn set_property_str(&self, name: &str, value: &str) -> sax2::Result<()> {
+        self.reader.set_property_str(name, value)
+    }
+}
+
+/// The events recorded by the wrapped reader, and the result it returned.
+struct Recorded {
+    events: VecDeque<Event>,
+    end: Result<()>,
+}
+
+impl Recorded {
+    fn take_end(&mut self) -> Result<()> {
+        std::mem::replace(&mut self.end, Ok(()))
+    }
+}
+
+/// Reads the whole input, then hands it to `record`.
+struct Buffer<'a, I: 'a, F> {
+    input: &'a mut I,
+    bytes: Vec<u8>,
+    record: Option<F>,
+}
+
+impl<'a, I: AsyncRead + Unpin, F: FnOnce(Vec<u8>) -> Recorded> Buffer<'a, I, F> {
+    fn poll_record(&mut self, cx: &mut Context) -> Poll<Result<Recorded>> {
+        let mut chunk = [0; 8192];
+        loop {
+            match Pin::new(&mut *self.input).poll_read(cx, &mut chunk) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(0)) => {
+                    let record = self.record.take().expect("input polled after completion");
+                    return Poll::Ready(Ok(record(std::mem::take(&mut self.bytes))));
+                }
+                Poll::Ready(Ok(n)) => self.bytes.extend_from_slice(&chunk[..n]),
+                Poll::Ready(Err(ref e)) if e.kind() == io::ErrorKind::Interrupted => {}
+                Poll::Ready(Err(e)) => {
+                    return Poll::Ready(Err(ParseErrorImpl::of_kind(
+                        ErrorKind::Resolution,
+                        format!("cannot read the document: {}", e),
+                    )
+                    .into()))
+                }
+            }
+        }
+    }
+}
+
+/// The future returned by `BufferedXmlReader::parse`.
+struct Parse<'a, CH: 'a, I: 'a, F> {
+    buffer: Option<Buffer<'a, I, F>>,
+    recorded: Option<Recorded>,
+    handler: Option<&'a CH>,
+    deliver: fn(&'a CH, Event) -> BoxFuture<'a, Result<()>>,
+    pending: Option<BoxFuture<'a, Result<()>>>,
+}
+
+impl<'a, CH, I, F> Future for Parse<'a, CH, I, F>
+where
+    I: AsyncRead + Unpin,
+    F: FnOnce(Vec<u8>) -> Recorded + Unpin,
+{
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(ref mut pending) = this.pending {
+                match pending.as_mut().poll(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Err(e)) => {
+                        this.pending = None;
+                        return Poll::Ready(Err(e));
+                    }
+                    Poll::Ready(Ok(())) => this.pending = None,
+                }
+            }
+            let recorded = match this.recorded {
+                Some(ref mut recorded) => recorded,
+                None => {
+                    let buffer = this.buffer.as_mut().expect("no input to parse");
+                    match buffer.poll_record(cx) {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                        Poll::Ready(Ok(recorded)) => this.recorded.get_or_insert(recorded),
+                    }
+                }
+            };
+            match recorded.events.pop_front() {
+                Some(event) => {
+                    if let Some(handler) = this.handler {
+                        this.pending = Some((this.deliver)(handler, event));
+                    }
+                }
+                None => return Poll::Ready(recorded.take_end()),
+            }
+        }
+    }
+}
+
+/// The stream returned by `BufferedXmlReader::events`.
+struct Events<'a, I: 'a, F> {
+    buffer: Buffer<'a, I, F>,
+    recorded: Option<Recorded>,
+    finished: bool,
+}
+
+impl<'a, I, F> Stream for Events<'a, I, F>
+where
+    I: AsyncRead + Unpin,
+    F: FnOnce(Vec<u8>) -> Recorded + Unpin,
+{
+    type Item = Result<Event>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Event>>> {
+        let this = self.get_mut();
+        if this.finished {
+            return Poll::Ready(None);
+        }
+        let recorded = match this.recorded {
+            Some(ref mut recorded) => recorded,
+            None => match this.buffer.poll_record(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => {
+                    this.finished = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+                Poll::Ready(Ok(recorded)) => this.recorded.get_or_insert(recorded),
+            },
+        };
+        match recorded.events.pop_front() {
+            Some(event) => Poll::Ready(Some(Ok(event))),
+            None => {
+                this.finished = true;
+                Poll::Ready(recorded.take_end().err().map(Err))
+            }
+        }
+    }
+}
+
+fn deliver<L, CH>(handler: &CH, event: Event) -> BoxFuture<'_, Result<()>>
+where
+    L: Locator,
+    CH: AsyncContentHandler<L, AttributesImpl>,
+{
+    match event {
+        Event::StartDocument => handler.start_document(),
+        Event::EndDocument => handler.end_document(),
+        Event::StartPrefixMapping { prefix, uri } => handler.start_prefix_mapping(prefix, uri),
+        Event::EndPrefixMapping { prefix } => handler.end_prefix_mapping(prefix),
+        Event::StartElement {
+            uri,
+            local_name,
+            q_name,
+            attributes,
+        } => handler.start_element(uri, local_name, q_name, attributes),
+        Event::EndElement {
+            uri,
+            local_name,
+            q_name,
+        } => handler.end_element(uri, local_name, q_name),
+        Event::Characters(content) => handler.characters(content),
+        Event::IgnorableWhitespace(content) => handler.ignorable_whitespace(content),
+        Event::ProcessingInstruction { target, data } => {
+            handler.processing_instruction(target, data)
+        }
+        Event::SkippedEntity(name) => handler.skipped_entity(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::io::Read;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::task::Wake;
+    use std::task::Waker;
+    use std::thread;
+
+    use helpers::LocatorImpl;
+
+    struct Dtd;
+    impl DtdHandler for Dtd {}
+
+    struct Resolver;
+    impl EntityResolver for Resolver {}
+
+    struct Errors;
+    impl ErrorHandler<ParseErrorImpl> for Errors {}
+
+    /// A synchronous input source over bytes in memory.
+    struct Source {
+        bytes: Cursor<Vec<u8>>,
+        system_id: Mutex<Option<String>>,
+    }
+
+    impl From<Vec<u8>> for Source {
+        fn from(bytes: Vec<u8>) -> Self {
+            Source {
+                bytes: Cursor::new(bytes),
+                system_id: Mutex::new(None),
+            }
+        }
+    }
+
+    impl Read for Source {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.bytes.read(buf)
+        }
+    }
+
+    impl InputSource for Source {
+        fn new(system_id: &str) -> Self {
+            let source = Source::from(Vec::new());
+            source.set_system_id(system_id);
+            source
+        }
+        fn get_encoding(&self) -> Option<String> {
+            None
+        }
+        fn get_public_id(&self) -> Option<String> {
+            None
+        }
+        fn get_system_id(&self) -> Option<String> {
+            self.system_id.lock().unwrap().clone()
+        }
+        fn set_encoding(&self, _encoding: &str) {}
+        fn set_public_id(&self, _public_id: &str) {}
+        fn set_system_id(&self, system_id: &str) {
+            *self.system_id.lock().unwrap() = Some(system_id.to_owned());
+        }
+    }
+
+    /// An asynchronous input source that hands out at most four bytes per read, and is pending
+    /// before every read.
+    struct Input {
+        bytes: Vec<u8>,
+        position: usize,
+        ready: bool,
+        system_id: RefCell<Option<String>>,
+    }
+
+    impl Input {
+        fn of(text: &str) -> Self {
+            let input = <Input as AsyncInputSource>::new("mem:doc.xml");
+            Input {
+                bytes: text.as_bytes().to_vec(),
+                ..input
+            }
+        }
+    }
+
+    impl AsyncRead for Input {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            if !this.ready {
+                this.ready = true;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            this.ready = false;
+            let n = buf.len().min(4).min(this.bytes.len() - this.position);
+            buf[..n].copy_from_slice(&this.bytes[this.position..this.position + n]);
+            this.position += n;
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    impl AsyncInputSource for Input {
+        fn new(system_id: &str) -> Self {
+            Input {
+                bytes: Vec::new(),
+                position: 0,
+                ready: false,
+                system_id: RefCell::new(Some(system_id.to_owned())),
+            }
+        }
+        fn get_encoding(&self) -> Option<String> {
+            None
+        }
+        fn get_public_id(&self) -> Option<String> {
+            None
+        }
+        fn get_system_id(&self) -> Option<String> {
+            self.system_id.borrow().clone()
+        }
+        fn set_encoding(&self, _encoding: &str) {}
+        fn set_public_id(&self, _public_id: &str) {}
+        fn set_system_id(&self, system_id: &str) {
+            *self.system_id.borrow_mut() = Some(system_id.to_owned());
+        }
+    }
+
+    /// A synchronous reader for a toy markup of start, end and empty tags and text.
+    struct TagReader {
+        recorder: EventRecorder<LocatorImpl>,
+        system_id: Mutex<Option<String>>,
+    }
+
+    impl TagReader {
+        fn new() -> Self {
+            TagReader {
+                recorder: EventRecorder::new(),
+                system_id: Mutex::new(None),
+            }
+        }
+    }
+
+    impl
+        XmlReader<
+            EventRecorder<LocatorImpl>,
+            Dtd,
+            Resolver,
+            Errors,
+            LocatorImpl,
+            AttributesImpl,
+            ParseErrorImpl,
+            Source,
+        > for TagReader
+    {
+        fn get_content_handler(&self) -> Option<&EventRecorder<LocatorImpl>> {
+            Some(&self.recorder)
+        }
+        fn get_content_handler_mut(&mut self) -> Option<&mut EventRecorder<LocatorImpl>> {
+            Some(&mut self.recorder)
+        }
+        fn get_dtd_handler(&self) -> Option<&Dtd> {
+            None
+        }
+        fn get_dtd_handler_mut(&mut self) -> Option<&mut Dtd> {
+            None
+        }
+        fn get_entity_resolver(&self) -> Option<&Resolver> {
+            None
+        }
+        fn get_entity_resolver_mut(&mut self) -> Option<&mut Resolver> {
+            None
+        }
+        fn get_error_handler(&self) -> Option<&Errors> {
+            None
+        }
+        fn get_error_handler_mut(&mut self) -> Option<&mut Errors> {
+            None
+        }
+        fn get_feature(&self, _name: &str) -> sax2::Result<bool> {
+            Ok(false)
+        }
+        fn get_property_str(&self, _name: &str) -> sax2::Result<String> {
+            Ok(String::new())
+        }
+        fn parse(&self, input: &mut Source) -> sax2::Result<()> {
+            *self.system_id.lock().unwrap() = input.get_system_id();
+            let mut text = String::new();
+            input
+                .read_to_string(&mut text)
+                .map_err(|e| ParseErrorImpl::of_kind(ErrorKind::Resolution, e.to_string()))?;
+            let handler: &dyn ContentHandler<LocatorImpl, AttributesImpl> = &self.recorder;
+            handler.start_document()?;
+            let mut rest = &text[..];
+            while let Some(open) = rest.find('<') {
+                if open > 0 {
+                    handler.characters(&rest[..open])?;
+                }
+                let close = match rest[open..].find('>') {
+                    Some(close) => open + close,
+                    None => {
+                        return Err(
+                            ParseErrorImpl::of_kind(ErrorKind::Parse, "unterminated tag").into(),
+                        )
+                    }
+                };
+                let tag = &rest[open + 1..close];
+                if let Some(name) = tag.strip_prefix('/') {
+                    handler.end_element("", name, name)?;
+                } else if let Some(name) = tag.strip_suffix('/') {
+                    handler.start_element("", name, name, AttributesImpl::new())?;
+                    handler.end_element("", name, name)?;
+                } else {
+                    handler.start_element("", tag, tag, AttributesImpl::new())?;
+                }
+                rest = &rest[close + 1..];
+            }
+            if !rest.is_empty() {
+                handler.characters(rest)?;
+            }
+            handler.end_document()
+        }
+        fn parse_uri(&self, system_id: &str) -> sax2::Result<()> {
+            let mut source = Source::from(b"<uri/>".to_vec());
+            source.set_system_id(system_id);
+            self.parse(&mut source)
+        }
+        fn set_content_handler(&self, _handler: EventRecorder<LocatorImpl>) {}
+        fn set_dtd_handler(&self, _handler: Dtd) {}
+        fn set_entity_resolver(&self, _resolver: Resolver) {}
+        fn set_error_handler(&self, _handler: Errors) {}
+        fn set_feature(&self, _name: &str, _value: bool) -> sax2::Result<()> {
+            Ok(())
+        }
+        fn set_property_str(&self, _name: &str, _value: &str) -> sax2::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A future that is pending once before it resolves.
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = Result<()>;
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+            if self.0 {
+                Poll::Ready(Ok(()))
+            } else {
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    /// An asynchronous handler that logs its callbacks, and fails on the text "stop".
+    struct Log {
+        entries: Mutex<Vec<String>>,
+    }
+
+    impl Log {
+        fn new() -> Self {
+            Log {
+                entries: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn entries(&self) -> Vec<String> {
+            self.entries.lock().unwrap().clone()
+        }
+
+        fn push(&self, entry: String) -> BoxFuture<'_, Result<()>> {
+            self.entries.lock().unwrap().push(entry);
+            Box::pin(YieldOnce(false))
+        }
+    }
+
+    impl AsyncContentHandler<LocatorImpl, AttributesImpl> for Log {
+        fn characters(&self, content: String) -> BoxFuture<'_, Result<()>> {
+            if content == "stop" {
+                return Box::pin(std::future::ready(Err(ParseErrorImpl::of_kind(
+                    ErrorKind::Parse,
+                    "stopped",
+                )
+                .into())));
+            }
+            self.push(format!("text {}", content))
+        }
+        fn end_document(&self) -> BoxFuture<'_, Result<()>> {
+            self.push("end".to_owned())
+        }
+        fn end_element(
+            &self,
+            _uri: String,
+            local_name: String,
+            _q_name: String,
+        ) -> BoxFuture<'_, Result<()>> {
+            self.push(format!("/{}", local_name))
+        }
+        fn start_document(&self) -> BoxFuture<'_, Result<()>> {
+            self.push("start".to_owned())
+        }
+        fn start_element<'a>(
+            &'a self,
+            _uri: String,
+            local_name: String,
+            _q_name: String,
+            _attributes: AttributesImpl,
+        ) -> BoxFuture<'a, Result<()>>
+        where
+            AttributesImpl: 'a,
+        {
+            self.push(local_name)
+        }
+    }
+
+    struct Unpark(thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    struct Next<'a, S: 'a>(&'a mut S);
+
+    impl<'a, S: Stream + Unpin> Future for Next<'a, S> {
+        type Output = Option<S::Item>;
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<S::Item>> {
+            Pin::new(&mut *self.0).poll_next(cx)
+        }
+    }
+
+    fn collect<S: Stream + Unpin>(mut stream: S) -> Vec<S::Item> {
+        let mut items = Vec::new();
+        while let Some(item) = block_on(Next(&mut stream)) {
+            items.push(item);
+        }
+        items
+    }
+
+    fn is_send<T: Send>(_: &T) {}
+
+    fn reader() -> BufferedXmlReader<TagReader, Log, Source> {
+        let mut reader = BufferedXmlReader::new(TagReader::new());
+        AsyncXmlReader::<_, _, _, _, _, _, _, Input>::set_content_handler(&mut reader, Log::new());
+        reader
+    }
+
+    fn log(reader: &BufferedXmlReader<TagReader, Log, Source>) -> Vec<String> {
+        AsyncXmlReader::<_, _, _, _, _, _, _, Input>::get_content_handler(reader)
+            .unwrap()
+            .entries()
+    }
+
+    #[test]
+    fn parse_awaits_each_callback_in_document_order() {
+        let reader = reader();
+        let mut input = Input::of("<a>hello <b>world</b></a>");
+        let parse = reader.parse(&mut input);
+        is_send(&parse);
+        block_on(parse).unwrap();
+        assert_eq!(
+            log(&reader),
+            vec![
+                "start",
+                "a",
+                "text hello ",
+                "b",
+                "text world",
+                "/b",
+                "/a",
+                "end"
+            ]
+        );
+        assert_eq!(
+            *reader.get_ref().system_id.lock().unwrap(),
+            Some("mem:doc.xml".to_owned())
+        );
+    }
+
+    #[test]
+    fn parse_reports_the_reader_error_after_the_events_before_it() {
+        let reader = reader();
+        let mut input = Input::of("<a>text<b");
+        let e = block_on(reader.parse(&mut input)).expect_err("unterminated tag");
+        assert_eq!(e.to_string(), "unterminated tag");
+        assert_eq!(log(&reader), vec!["start", "a", "text text"]);
+    }
+
+    #[test]
+    fn parse_stops_at_the_first_handler_error() {
+        let reader = reader();
+        let mut input = Input::of("<a>stop</a>");
+        let e = block_on(reader.parse(&mut input)).expect_err("handler error");
+        assert_eq!(e.to_string(), "stopped");
+        assert_eq!(log(&reader), vec!["start", "a"]);
+    }
+
+    #[test]
+    fn parse_uri_lets_the_wrapped_reader_open_the_document() {
+        let reader = reader();
+        let parse = AsyncXmlReader::<_, _, _, _, _, _, _, Input>::parse_uri(&reader, "mem:uri");
+        block_on(parse).unwrap();
+        assert_eq!(log(&reader), vec!["start", "uri", "/uri", "end"]);
+        assert_eq!(
+            *reader.get_ref().system_id.lock().unwrap(),
+            Some("mem:uri".to_owned())
+        );
+    }
+
+    #[test]
+    fn events_yields_owned_events() {
+        let reader = reader();
+        let mut input = Input::of("<a>x</a>");
+        let stream = reader.events(&mut input);
+        is_send(&stream);
+        let events: Vec<Event> = collect(stream).into_iter().map(|e| e.unwrap()).collect();
+        assert_eq!(
+            events,
+            vec![
+                Event::StartDocument,
+                Event::start_element("", "a", "a", &AttributesImpl::new()),
+                Event::Characters("x".to_owned()),
+                Event::end_element("", "a", "a"),
+                Event::EndDocument,
+            ]
+        );
+        assert!(log(&reader).is_empty());
+    }
+
+    #[test]
+    fn events_ends_with_the_reader_error() {
+        let reader = reader();
+        let mut input = Input::of("x<");
+        let items = collect(reader.events(&mut input));
+        assert_eq!(items.len(), 3);
+        assert_eq!(
+            items[2].as_ref().expect_err("unterminated tag").to_string(),
+            "unterminated tag"
+        );
+    }
+
+    #[test]
+    fn local_variants_accept_handlers_that_are_not_sync() {
+        struct Count(Cell<usize>);
+        impl ContentHandler<LocatorImpl, AttributesImpl> for Count {
+            fn start_element(
+                &self,
+                _uri: &str,
+                _local_name: &str,
+                _q_name: &str,
+                _attributes: AttributesImpl,
+            ) -> sax2::Result<()> {
+                self.0.set(self.0.get() + 1);
+                Ok(())
+            }
+        }
+        let mut reader = BufferedXmlReader::new(TagReader::new());
+        reader.content_handler = Some(BlockingHandler::new(Count(Cell::new(0))));
+        let mut input = Input::of("<a><b/></a>");
+        block_on(reader.parse_local(&mut input)).unwrap();
+        let count = reader.content_handler.as_ref().unwrap().get_ref();
+        assert_eq!(count.0.get(), 2);
+        let mut input = Input::of("<a/>");
+        assert_eq!(collect(reader.events_local(&mut input)).len(), 4);
+    }
+}
@@ -0,0 +1,1086 @@
+//! Schematron rule-based validation.
+//!
+//! A `Schema` is loaded from the tree of an ISO Schematron (or Schematron 1.5) schema document,
+//! built with a `tree::TreeBuilder`. Rule contexts, assertions, reports and variables are
+//! compiled as XPath 1.0 expressions when the schema is loaded. A document is validated once
+//! it has been buffered: either call `Schema::validate` on its tree, or register a `Validator`
+//! as the content handler of a reader, which builds the tree and reports the findings through
+//! `ErrorHandler::error` and `ErrorHandler::warning` at the end of the document.
+//!
+//! Supported are `ns`, `let`, `phase`, `pattern`, `rule` (including abstract rules, which are
+//! extended with `extends` from within their pattern), `assert`, `report` and `diagnostics`;
+//! messages may use `value-of` and `name`.
+//! `include` and abstract patterns are not supported.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use common;
+use common::Locator;
+use ext::LexicalHandler;
+use helpers::ErrorKind;
+use helpers::LocatorImpl;
+use helpers::NamespaceSupport;
+use helpers::ParseErrorImpl;
+use names;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::ErrorHandler;
+use sax2::ParseError;
+use sax2::Result;
+use tree::Document;
+use tree::NodeId;
+use tree::NodeKind;
+use tree::TreeBuilder;
+use xpath::Environment;
+use xpath::XPath;
+
+/// The ISO Schematron Namespace URI.
+pub const SCHEMATRON_NS: &str = "http://purl.oclc.org/dsdl/schematron";
+
+/// The Schematron 1.5 Namespace URI.
+pub const SCHEMATRON_1_5_NS: &str = "http://www.ascc.net/xml/schematron";
+
+/// Whether a finding comes from an assertion or a report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FindingKind {
+    /// An `assert` whose test was false.
+    FailedAssert,
+    /// A `report` whose test was true.
+    SuccessfulReport,
+}
+
+/// A failed assertion or successful report, found while validating a document.
+///
+/// The location is that of the context node of the rule, in the validated document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finding {
+    pub kind: FindingKind,
+    /// The identifier of the pattern, if any.
+    pub pattern: Option<String>,
+    /// The context expression of the rule.
+    pub rule_context: String,
+    /// The test expression of the assertion or report.
+    pub test: String,
+    pub id: Option<String>,
+    pub role: Option<String>,
+    pub flag: Option<String>,
+    /// The message, with whitespace normalized.
+    pub message: String,
+    /// The referenced diagnostics, as pairs of identifier and message.
+    pub diagnostics: Vec<(String, String)>,
+    /// The path of the context node, such as `/invoice[1]/line[2]`.
+    pub path: String,
+    pub location: LocatorImpl,
+}
+
+impl Finding {
+    /// Return whether the finding should be reported as an error rather than a warning.
+    ///
+    /// The role decides if it is one of the usual severities; otherwise failed assertions are
+    /// errors and successful reports are warnings.
+    pub fn is_error(&self) -> bool {
+        match self.role.as_ref().map(|role| role.to_lowercase()) {
+            Some(ref role) if role == "error" || role == "fatal" => true,
+            Some(ref role) if ["warning", "warn", "info", "information"].contains(&&**role) => {
+                false
+            }
+            _ => self.kind == FindingKind::FailedAssert,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", ParseErrorImpl::new("", self.location.clone()))?;
+        match self.kind {
+            FindingKind::FailedAssert => write!(f, "assertion \"{}\" failed", self.test)?,
+            FindingKind::SuccessfulReport => write!(f, "report \"{}\"", self.test)?,
+        }
+        write!(f, " at {}: {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for Finding {}
+
+impl common::Error for Finding {}
+
+impl Locator for Finding {
+    fn get_column_number(&self) -> Option<u64> {
+        self.location.column_number
+    }
+    fn get_line_number(&self) -> Option<u64> {
+        self.location.line_number
+    }
+    fn get_public_id(&self) -> Option<String> {
+        self.location.public_id.clone()
+    }
+    fn get_system_id(&self) -> Option<String> {
+        self.location.system_id.clone()
+    }
+}
+
+impl ParseError for Finding {}
+
+#[derive(Clone, Debug)]
+enum MessagePart {
+    Text(String),
+    ValueOf(XPath),
+    Name(Option<XPath>),
+}
+
+type Message = Vec<MessagePart>;
+
+#[derive(Clone, Debug)]
+struct Let {
+    name: String,
+    value: XPath,
+}
+
+#[derive(Clone, Debug)]
+struct Check {
+    kind: FindingKind,
+    test: XPath,
+    id: Option<String>,
+    role: Option<String>,
+    flag: Option<String>,
+    diagnostics: Vec<String>,
+    message: Message,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    context: XPath,
+    lets: Vec<Let>,
+    checks: Vec<Check>,
+}
+
+#[derive(Clone, Debug)]
+struct Pattern {
+    id: Option<String>,
+    lets: Vec<Let>,
+    rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug)]
+struct Phase {
+    id: String,
+    active: Vec<String>,
+    lets: Vec<Let>,
+}
+
+/// A rule as read from the schema, before `extends` references are resolved.
+struct RawRule {
+    id: Option<String>,
+    is_abstract: bool,
+    context: Option<XPath>,
+    lets: Vec<Let>,
+    checks: Vec<Check>,
+    extends: Vec<(String, NodeId)>,
+}
+
+/// A compiled Schematron schema.
+#[derive(Clone, Debug)]
+pub struct Schema {
+    default_phase: Option<String>,
+    lets: Vec<Let>,
+    phases: Vec<Phase>,
+    patterns: Vec<Pattern>,
+    diagnostics: HashMap<String, Message>,
+}
+
+struct Loader<'a> {
+    document: &'a Document,
+    uri: String,
+    namespaces: NamespaceSupport,
+}
+
+impl<'a> Loader<'a> {
+    fn error(&self, node: NodeId, message: String) -> ParseErrorImpl {
+        ParseErrorImpl::new(message, self.document.location(node).clone())
+    }
+
+    fn children(&self, node: NodeId) -> Vec<(NodeId, &'a str)> {
+        let document = self.document;
+        document
+            .children(node)
+            .iter()
+            .filter(|child| {
+                document.is_element(**child) && document.namespace_uri(**child) == self.uri
+            })
+            .map(|child| (*child, document.local_name(*child)))
+            .collect()
+    }
+
+    fn attribute(&self, node: NodeId, name: &str) -> Option<String> {
+        self.document
+            .attribute_value(node, "", name)
+            .map(str::to_owned)
+    }
+
+    fn required(&self, node: NodeId, name: &str) -> std::result::Result<String, ParseErrorImpl> {
+        self.attribute(node, name).ok_or_else(|| {
+            self.error(
+                node,
+                format!(
+                    "missing attribute \"{}\" on \"{}\"",
+                    name,
+                    self.document.q_name(node)
+                ),
+            )
+        })
+    }
+
+    fn compile(&self, node: NodeId, source: &str) -> std::result::Result<XPath, ParseErrorImpl> {
+        XPath::compile(source, &self.namespaces)
+            .map_err(|e| self.error(node, format!("invalid expression \"{}\": {}", source, e)))
+    }
+
+    fn let_(&self, node: NodeId) -> std::result::Result<Let, ParseErrorImpl> {
+        let name = self.required(node, "name")?;
+        if !names::is_ncname(&name) {
+            return Err(self.error(node, format!("invalid variable name \"{}\"", name)));
+        }
+        let value = match self.attribute(node, "value") {
+            Some(value) => self.compile(node, &value)?,
+            None => return Err(self.error(node, "a variable must have a value".to_owned())),
+        };
+        Ok(Let { name, value })
+    }
+
+    fn message(&self, node: NodeId) -> std::result::Result<Message, ParseErrorImpl> {
+        let document = self.document;
+        let mut message = Vec::new();
+        for child in document.children(node) {
+            match document.kind(*child) {
+                NodeKind::Text(text) => message.push(MessagePart::Text(text.clone())),
+                NodeKind::Element {
+                    uri, local_name, ..
+                } if *uri == self.uri => match local_name.as_str() {
+                    "value-of" => {
+                        let select = self.required(*child, "select")?;
+                        message.push(MessagePart::ValueOf(self.compile(*child, &select)?));
+                    }
+                    "name" => {
+                        let path = match self.attribute(*child, "path") {
+                            Some(path) => Some(self.compile(*child, &path)?),
+                            None => None,
+                        };
+                        message.push(MessagePart::Name(path));
+                    }
+                    _ => message.extend(self.message(*child)?),
+                },
+                NodeKind::Element { .. } => message.extend(self.message(*child)?),
+                _ => {}
+            }
+        }
+        Ok(message)
+    }
+
+    fn check(&self, node: NodeId, kind: FindingKind) -> std::result::Result<Check, ParseErrorImpl> {
+        let test = self.required(node, "test")?;
+        Ok(Check {
+            kind,
+            test: self.compile(node, &test)?,
+            id: self.attribute(node, "id"),
+            role: self.attribute(node, "role"),
+            flag: self.attribute(node, "flag"),
+            diagnostics: self
+                .attribute(node, "diagnostics")
+                .map(|ids| {
+                    ids.split(names::is_whitespace)
+                        .filter(|id| !id.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            message: self.message(node)?,
+        })
+    }
+
+    fn rule(&self, node: NodeId) -> std::result::Result<RawRule, ParseErrorImpl> {
+        let is_abstract = self.attribute(node, "abstract").as_deref() == Some("true");
+        let context = match self.attribute(node, "context") {
+            Some(context) if !is_abstract => Some(
+                XPath::compile_pattern(&context, &self.namespaces).map_err(|e| {
+                    self.error(node, format!("invalid rule context \"{}\": {}", context, e))
+                })?,
+            ),
+            Some(_) => {
+                return Err(self.error(node, "an abstract rule may not have a context".to_owned()))
+            }
+            None if is_abstract => None,
+            None => return Err(self.required(node, "context").unwrap_err()),
+        };
+        let mut rule = RawRule {
+            id: self.attribute(node, "id"),
+            is_abstract,
+            context,
+            lets: Vec::new(),
+            checks: Vec::new(),
+            extends: Vec::new(),
+        };
+        if is_abstract && rule.id.is_none() {
+            return Err(self.error(node, "an abstract rule must have an id".to_owned()));
+        }
+        for (child, name) in self.children(node) {
+            match name {
+                "let" => rule.lets.push(self.let_(child)?),
+                "assert" => rule
+                    .checks
+                    .push(self.check(child, FindingKind::FailedAssert)?),
+                "report" => rule
+                    .checks
+                    .push(self.check(child, FindingKind::SuccessfulReport)?),
+                "extends" => rule.extends.push((self.required(child, "rule")?, child)),
+                _ => {}
+            }
+        }
+        Ok(rule)
+    }
+
+    /// Append the checks of the abstract rule `id`, and of the rules it extends in turn.
+    fn extend(
+        &self,
+        rule: &mut Rule,
+        id: &str,
+        node: NodeId,
+        abstract_rules: &HashMap<String, RawRule>,
+        visiting: &mut Vec<String>,
+    ) -> std::result::Result<(), ParseErrorImpl> {
+        let base = abstract_rules
+            .get(id)
+            .ok_or_else(|| self.error(node, format!("no abstract rule with id \"{}\"", id)))?;
+        if visiting.iter().any(|visited| visited == id) {
+            return Err(self.error(node, format!("abstract rule \"{}\" extends itself", id)));
+        }
+        visiting.push(id.to_owned());
+        rule.lets.extend(base.lets.iter().cloned());
+        rule.checks.extend(base.checks.iter().cloned());
+        for (base_id, base_node) in &base.extends {
+            self.extend(rule, base_id, *base_node, abstract_rules, visiting)?;
+        }
+        visiting.pop();
+        Ok(())
+    }
+}
+
+impl Schema {
+    /// Load a schema from the tree of a schema document.
+    pub fn from_document(document: &Document) -> std::result::Result<Schema, ParseErrorImpl> {
+        let root = document.document_element().ok_or_else(|| {
+            ParseErrorImpl::new("the schema document is empty", LocatorImpl::new())
+        })?;
+        let uri = document.namespace_uri(root).to_owned();
+        let mut loader = Loader {
+            document,
+            uri,
+            namespaces: NamespaceSupport::new(),
+        };
+        if (loader.uri != SCHEMATRON_NS && loader.uri != SCHEMATRON_1_5_NS)
+            || document.local_name(root) != "schema"
+        {
+            return Err(loader.error(root, "not a Schematron schema".to_owned()));
+        }
+        match loader.attribute(root, "queryBinding") {
+            None => {}
+            Some(ref binding) if ["xslt", "xslt1", "xpath"].contains(&binding.as_str()) => {}
+            Some(binding) => {
+                return Err(loader.error(root, format!("unsupported query binding \"{}\"", binding)))
+            }
+        }
+        for (child, name) in loader.children(root) {
+            if name == "ns" {
+                let prefix = loader.required(child, "prefix")?;
+                let uri = loader.required(child, "uri")?;
+                loader.namespaces.declare_prefix(&prefix, &uri);
+            }
+        }
+        let mut schema = Schema {
+            default_phase: loader.attribute(root, "defaultPhase"),
+            lets: Vec::new(),
+            phases: Vec::new(),
+            patterns: Vec::new(),
+            diagnostics: HashMap::new(),
+        };
+        let mut raw_patterns = Vec::new();
+        for (child, name) in loader.children(root) {
+            match name {
+                "include" => return Err(loader.error(child, "include is not supported".to_owned())),
+                "let" => schema.lets.push(loader.let_(child)?),
+                "phase" => {
+                    let mut phase = Phase {
+                        id: loader.required(child, "id")?,
+                        active: Vec::new(),
+                        lets: Vec::new(),
+                    };
+                    for (grandchild, name) in loader.children(child) {
+                        match name {
+                            "active" => phase.active.push(loader.required(grandchild, "pattern")?),
+                            "let" => phase.lets.push(loader.let_(grandchild)?),
+                            _ => {}
+                        }
+                    }
+                    schema.phases.push(phase);
+                }
+                "pattern" => {
+                    if loader.attribute(child, "abstract").is_some()
+                        || loader.attribute(child, "is-a").is_some()
+                    {
+                        return Err(
+                            loader.error(child, "abstract patterns are not supported".to_owned())
+                        );
+                    }
+                    let mut lets = Vec::new();
+                    let mut rules = Vec::new();
+                    let mut abstract_rules = HashMap::new();
+                    for (grandchild, name) in loader.children(child) {
+                        match name {
+                            "let" => lets.push(loader.let_(grandchild)?),
+                            "rule" => {
+                                let rule = loader.rule(grandchild)?;
+                                if rule.is_abstract {
+                                    let id = rule.id.clone().unwrap_or_default();
+                                    abstract_rules.insert(id, rule);
+                                } else {
+                                    rules.push(rule);
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    raw_patterns.push((loader.attribute(child, "id"), lets, rules, abstract_rules));
+                }
+                "diagnostics" => {
+                    for (grandchild, name) in loader.children(child) {
+                        if name == "diagnostic" {
+                            let id = loader.required(grandchild, "id")?;
+                            schema.diagnostics.insert(id, loader.message(grandchild)?);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        // An abstract rule can only be extended by the rules of its own pattern.
+        for (id, lets, raw_rules, abstract_rules) in raw_patterns {
+            let mut rules = Vec::new();
+            for raw in raw_rules {
+                let mut rule = Rule {
+                    context: raw.context.expect("concrete rules have a context"),
+                    lets: raw.lets,
+                    checks: raw.checks,
+                };
+                for (base_id, node) in &raw.extends {
+                    loader.extend(&mut rule, base_id, *node, &abstract_rules, &mut Vec::new())?;
+                }
+                rules.push(rule);
+            }
+            schema.patterns.push(Pattern { id, lets, rules });
+        }
+        for phase in &schema.phases {
+            for active in &phase.active {
+                if !schema
+                    .patterns
+                    .iter()
+                    .any(|p| p.id.as_ref() == Some(active))
+                {
+                    return Err(ParseErrorImpl::new(
+                        format!(
+                            "phase \"{}\" activates unknown pattern \"{}\"",
+                            phase.id, active
+                        ),
+                        LocatorImpl::new(),
+                    ));
+                }
+            }
+        }
+        for check in schema
+            .patterns
+            .iter()
+            .flat_map(|p| &p.rules)
+            .flat_map(|r| &r.checks)
+        {
+            if let Some(id) = check
+                .diagnostics
+                .iter()
+                .find(|id| !schema.diagnostics.contains_key(*id))
+            {
+                return Err(ParseErrorImpl::new(
+                    format!("unknown diagnostic \"{}\"", id),
+                    LocatorImpl::new(),
+                ));
+            }
+        }
+        Ok(schema)
+    }
+
+    /// Return the identifiers of the phases declared by the schema.
+    pub fn phases(&self) -> Vec<&str> {
+        self.phases.iter().map(|phase| phase.id.as_str()).collect()
+    }
+
+    /// Return the default phase declared by the schema, if any.
+    pub fn default_phase(&self) -> Option<&str> {
+        self.default_phase.as_deref()
+    }
+
+    /// Validate a document, returning the findings in document order of their context nodes.
+    ///
+    /// `phase` selects the patterns to apply; `None` selects the default phase of the schema,
+    /// and `"#ALL"`, the default if the schema declares none, selects all patterns.
+    pub fn validate(
+        &self,
+        document: &Document,
+        phase: Option<&str>,
+    ) -> std::result::Result<Vec<Finding>, ParseErrorImpl> {
+        let phase_id = phase
+            .or_else(|| self.default_phase())
+            .filter(|id| *id != "#ALL");
+        let phase =
+            match phase_id {
+                Some(id) => Some(self.phases.iter().find(|phase| phase.id == id).ok_or_else(
+                    || {
+                        ParseErrorImpl::of_kind(
+                            ErrorKind::Expression,
+                            format!("unknown phase \"{}\"", id),
+                        )
+                    },
+                )?),
+                None => None,
+            };
+        let root = document.root();
+        let mut environment = Environment::new();
+        let global_lets = self
+            .lets
+            .iter()
+            .chain(phase.into_iter().flat_map(|p| &p.lets));
+        for variable in global_lets {
+            let value = variable.value.evaluate(document, root, &environment)?;
+            environment.set_variable(&variable.name, value);
+        }
+        let mut findings = Vec::new();
+        for pattern in &self.patterns {
+            let active = phase.is_none_or(|phase| {
+                pattern
+                    .id
+                    .as_ref()
+                    .is_some_and(|id| phase.active.contains(id))
+            });
+            if active {
+                self.validate_pattern(pattern, document, environment.clone(), &mut findings)?;
+            }
+        }
+        findings.sort_by_key(|(node, _)| *node);
+        Ok(findings.into_iter().map(|(_, finding)| finding).collect())
+    }
+
+    fn validate_pattern(
+        &self,
+        pattern: &Pattern,
+        document: &Document,
+        mut environment: Environment,
+        findings: &mut Vec<(NodeId, Finding)>,
+    ) -> std::result::Result<(), ParseErrorImpl> {
+        let root = document.root();
+        for variable in &pattern.lets {
+            let value = variable.value.evaluate(document, root, &environment)?;
+            environment.set_variable(&variable.name, value);
+        }
+        // Each node is checked by the first rule of the pattern whose context it matches.
+        let mut fired: HashMap<NodeId, usize> = HashMap::new();
+        for (index, rule) in pattern.rules.iter().enumerate() {
+            for node in rule.context.select(document, root, &environment)? {
+                fired.entry(node).or_insert(index);
+            }
+        }
+        let mut nodes: Vec<(NodeId, usize)> = fired.into_iter().collect();
+        nodes.sort();
+        for (node, index) in nodes {
+            let rule = &pattern.rules[index];
+            let mut environment = environment.clone();
+            for variable in &rule.lets {
+                let value = variable.value.evaluate(document, node, &environment)?;
+                environment.set_variable(&variable.name, value);
+            }
+            for check in &rule.checks {
+                let result = check.test.evaluate_boolean(document, node, &environment)?;
+                if result != (check.kind == FindingKind::SuccessfulReport) {
+                    continue;
+                }
+                let mut diagnostics = Vec::new();
+                for id in &check.diagnostics {
+                    let message =
+                        self.render(&self.diagnostics[id], document, node, &environment)?;
+                    diagnostics.push((id.clone(), message));
+                }
+                let finding = Finding {
+                    kind: check.kind,
+                    pattern: pattern.id.clone(),
+                    rule_context: rule.context.source().to_owned(),
+                    test: check.test.source().to_owned(),
+                    id: check.id.clone(),
+                    role: check.role.clone(),
+                    flag: check.flag.clone(),
+                    message: self.render(&check.message, document, node, &environment)?,
+                    diagnostics,
+                    path: document.path(node),
+                    location: document.location(node).clone(),
+                };
+                findings.push((node, finding));
+            }
+        }
+        Ok(())
+    }
+
+    fn render(
+        &self,
+        message: &[MessagePart],
+        document: &Document,
+        node: NodeId,
+        environment: &Environment,
+    ) -> std::result::Result<String, ParseErrorImpl> {
+        let mut text = String::new();
+        for part in message {
+            match part {
+                MessagePart::Text(s) => text.push_str(s),
+                MessagePart::ValueOf(select) => {
+                    text.push_str(&select.evaluate_string(document, node, environment)?)
+                }
+                MessagePart::Name(path) => {
+                    let target = match path {
+                        Some(path) => path.select(document, node, environment)?.first().cloned(),
+                        None => Some(node),
+                    };
+                    if let Some(target) = target {
+                        text.push_str(document.q_name(target));
+                    }
+                }
+            }
+        }
+        Ok(text
+            .split(names::is_whitespace)
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<&str>>()
+            .join(" "))
+    }
+}
+
+/// Validates a document against a Schematron schema.
+///
+/// Register the validator as the content handler of a Namespace-aware reader, and optionally
+/// as its lexical handler so that comments are visible to rules. The document is buffered as
+/// a tree; at the end of the document, the rules of the selected phase are evaluated and each
+/// finding is reported through `ErrorHandler::error` if `Finding::is_error` holds, and through
+/// `ErrorHandler::warning` otherwise. An expression that cannot be evaluated ends the parse
+/// with an error.
+pub struct Validator<L: Locator, EH: ErrorHandler<Finding>> {
+    schema: Rc<Schema>,
+    error_handler: EH,
+    phase: RefCell<Option<String>>,
+    builder: TreeBuilder<L>,
+}
+
+impl<L: Locator, EH: ErrorHandler<Finding>> Validator<L, EH> {
+    /// Construct a validator for the given schema, reporting findings to `error_handler`.
+    pub fn new(schema: Rc<Schema>, error_handler: EH) -> Self {
+        Validator {
+            schema,
+            error_handler,
+            phase: RefCell::new(None),
+            builder: TreeBuilder::new(),
+        }
+    }
+
+    /// Select the phase to validate; `None` selects the default phase of the schema.
+    pub fn set_phase(&self, phase: Option<&str>) {
+        *self.phase.borrow_mut() = phase.map(str::to_owned);
+    }
+
+    /// Return the error handler.
+    pub fn get_error_handler(&self) -> &EH {
+        &self.error_handler
+    }
+}
+
+impl<L, A, EH> ContentHandler<L, A> for Validator<L, EH>
+where
+    L: Locator,
+    A: Attributes,
+    EH: ErrorHandler<Finding>,
+{
+    fn characters(&self, content: &str) -> Result<()> {
+        ContentHandler::<L, A>::characters(&self.builder, content)
+    }
+    fn end_document(&self) -> Result<()> {
+        let document = self.builder.take_document();
+        let phase = self.phase.borrow();
+        let findings = self
+            .schema
+            .validate(&document, phase.as_deref())
+            .map_err(|e| Box::new(e) as Box<dyn common::Error>)?;
+        for finding in &findings {
+            if finding.is_error() {
+                self.error_handler.error(finding)?;
+            } else {
+                self.error_handler.warning(finding)?;
+            }
+        }
+        Ok(())
+    }
+    fn end_element(&self, uri: &str, local_name: &str, q_name: &str) -> Result<()> {
+        ContentHandler::<L, A>::end_element(&self.builder, uri, local_name, q_name)
+    }
+    fn ignorable_whitespace(&self, content: &str) -> Result<()> {
+        ContentHandler::<L, A>::ignorable_whitespace(&self.builder, content)
+    }
+    fn processing_instruction(&self, target: &str, data: &str) -> Result<()> {
+        ContentHandler::<L, A>::processing_instruction(&self.builder, target, data)
+    }
+    fn set_document_locator(&self, locator: Rc<L>) {
+        ContentHandler::<L, A>::set_document_locator(&self.builder, locator)
+    }
+    fn start_document(&self) -> Result<()> {
+        ContentHandler::<L, A>::start_document(&self.builder)
+    }
+    fn start_element(
+        &self,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attributes: A,
+    ) -> Result<()> {
+        self.builder
+            .start_element(uri, local_name, q_name, attributes)
+    }
+    fn start_prefix_mapping(&self, prefix: &str, uri: &str) -> Result<()> {
+        ContentHandler::<L, A>::start_prefix_mapping(&self.builder, prefix, uri)
+    }
+}
+
+impl<L: Locator, EH: ErrorHandler<Finding>> LexicalHandler for Validator<L, EH> {
+    fn comment(&self, content: &str) -> Result<()> {
+        self.builder.comment(content)
+    }
+    fn end_dtd(&self) -> Result<()> {
+        self.builder.end_dtd()
+    }
+    fn start_dtd(
+        &self,
+        name: &str,
+        public_id: Option<&str>,
+        system_id: Option<&str>,
+    ) -> Result<()> {
+        self.builder.start_dtd(name, public_id, system_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event::markup_events;
+
+    fn document(markup: &str) -> Document {
+        let builder = TreeBuilder::<LocatorImpl>::new();
+        for event in markup_events(markup) {
+            event.dispatch(&builder).unwrap();
+        }
+        builder.take_document()
+    }
+
+    /// Load a schema whose `schema` element has the given attributes and content.
+    fn load(attributes: &str, content: &str) -> std::result::Result<Schema, String> {
+        let markup = format!(
+            "<schema xmlns=\"{}\" {}>{}</schema>",
+            SCHEMATRON_NS, attributes, content
+        );
+        Schema::from_document(&document(&markup)).map_err(|e| e.to_string())
+    }
+
+    const INVOICE: &str = concat!(
+        "<invoice currency=\"EUR\" total=\"150\">",
+        "<line amount=\"100\"/><line amount=\"50\"/><line/>",
+        "</invoice>"
+    );
+
+    /// Validate the invoice, and describe the findings by path and message.
+    fn findings(schema: &Schema, phase: Option<&str>) -> std::result::Result<Vec<String>, String> {
+        let findings = schema
+            .validate(&document(INVOICE), phase)
+            .map_err(|e| e.to_string())?;
+        Ok(findings
+            .iter()
+            .map(|finding| format!("{} {}", finding.path, finding.message))
+            .collect())
+    }
+
+    #[test]
+    fn asserts_and_reports() {
+        let schema = load(
+            "",
+            concat!(
+                "<pattern id=\"lines\"><rule context=\"line\">",
+                "<assert test=\"@amount\" id=\"amount\" flag=\"incomplete\">",
+                "A line has an   amount.</assert>",
+                "<report test=\"@amount &gt; 75\" role=\"info\">",
+                "Line <value-of select=\"count(preceding-sibling::line) + 1\"/> of ",
+                "<name path=\"..\"/> is large.</report>",
+                "</rule></pattern>"
+            ),
+        )
+        .unwrap();
+        let findings = schema.validate(&document(INVOICE), None).unwrap();
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].kind, FindingKind::SuccessfulReport);
+        assert_eq!(findings[0].path, "/invoice[1]/line[1]");
+        assert_eq!(findings[0].message, "Line 1 of invoice is large.");
+        assert_eq!(findings[0].test, "@amount > 75");
+        assert!(!findings[0].is_error());
+        assert_eq!(findings[1].kind, FindingKind::FailedAssert);
+        assert_eq!(findings[1].path, "/invoice[1]/line[3]");
+        assert_eq!(findings[1].message, "A line has an amount.");
+        assert_eq!(findings[1].pattern.as_deref(), Some("lines"));
+        assert_eq!(findings[1].rule_context, "line");
+        assert_eq!(findings[1].id.as_deref(), Some("amount"));
+        assert_eq!(findings[1].flag.as_deref(), Some("incomplete"));
+        assert!(findings[1].is_error());
+        assert_eq!(
+            findings[1].to_string(),
+            "assertion \"@amount\" failed at /invoice[1]/line[3]: A line has an amount."
+        );
+    }
+
+    #[test]
+    fn first_matching_rule_fires() {
+        let schema = load(
+            "",
+            concat!(
+                "<pattern id=\"a\">",
+                "<rule context=\"line[@amount &gt; 75]\">",
+                "<report test=\"true()\">big</report></rule>",
+                "<rule context=\"line\"><report test=\"true()\">line</report></rule>",
+                "<rule context=\"line[1]\"><report test=\"true()\">never</report></rule>",
+                "<rule context=\"invoice\"><report test=\"true()\">invoice</report></rule>",
+                "</pattern>",
+                "<pattern id=\"b\">",
+                "<rule context=\"line[1]\"><report test=\"true()\">first</report></rule>",
+                "</pattern>"
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            findings(&schema, None).unwrap(),
+            [
+                "/invoice[1] invoice",
+                "/invoice[1]/line[1] big",
+                "/invoice[1]/line[1] first",
+                "/invoice[1]/line[2] line",
+                "/invoice[1]/line[3] line",
+            ]
+        );
+    }
+
+    #[test]
+    fn variables() {
+        let schema = load(
+            "",
+            concat!(
+                "<let name=\"limit\" value=\"60\"/>",
+                "<pattern id=\"p\"><let name=\"count\" value=\"count(//line)\"/>",
+                "<rule context=\"line[@amount]\"><let name=\"amount\" value=\"number(@amount)\"/>",
+                "<report test=\"$amount &gt; $limit\">",
+                "<value-of select=\"$amount\"/> of <value-of select=\"$count\"/> lines</report>",
+                "</rule></pattern>"
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            findings(&schema, None).unwrap(),
+            ["/invoice[1]/line[1] 100 of 3 lines"]
+        );
+        let error = load(
+            "",
+            "<pattern><rule context=\"line\"><assert test=\"$missing\"/></rule></pattern>",
+        )
+        .unwrap();
+        assert!(findings(&error, None).is_err());
+        assert_eq!(
+            load("", "<let name=\"a b\" value=\"1\"/>").unwrap_err(),
+            "invalid variable name \"a b\""
+        );
+    }
+
+    #[test]
+    fn phases() {
+        let content = concat!(
+            "<let name=\"limit\" value=\"1000\"/>",
+            "<phase id=\"totals\"><active pattern=\"totals\"/>",
+            "<let name=\"limit\" value=\"100\"/></phase>",
+            "<phase id=\"lines\"><active pattern=\"lines\"/></phase>",
+            "<pattern id=\"totals\"><rule context=\"invoice\">",
+            "<report test=\"@total &gt; $limit\">total over <value-of select=\"$limit\"/></report>",
+            "</rule></pattern>",
+            "<pattern id=\"lines\"><rule context=\"line\">",
+            "<assert test=\"@amount\">no amount</assert></rule></pattern>"
+        );
+        let schema_with_default = load("defaultPhase=\"totals\"", content).unwrap();
+        assert_eq!(schema_with_default.phases(), ["totals", "lines"]);
+        assert_eq!(schema_with_default.default_phase(), Some("totals"));
+        assert_eq!(
+            findings(&schema_with_default, None).unwrap(),
+            ["/invoice[1] total over 100"]
+        );
+        assert_eq!(
+            findings(&schema_with_default, Some("lines")).unwrap(),
+            ["/invoice[1]/line[3] no amount"]
+        );
+        assert_eq!(
+            findings(&schema_with_default, Some("#ALL")).unwrap(),
+            ["/invoice[1]/line[3] no amount"]
+        );
+        assert_eq!(
+            findings(&schema_with_default, Some("other")).unwrap_err(),
+            "unknown phase \"other\""
+        );
+        let schema_without_default = load("", content).unwrap();
+        assert_eq!(findings(&schema_without_default, None).unwrap().len(), 1);
+        assert_eq!(
+            load("", "<phase id=\"x\"><active pattern=\"none\"/></phase>").unwrap_err(),
+            "phase \"x\" activates unknown pattern \"none\""
+        );
+    }
+
+    #[test]
+    fn abstract_rules() {
+        let schema = load(
+            "",
+            concat!(
+                "<pattern id=\"p\">",
+                "<rule abstract=\"true\" id=\"amount\">",
+                "<let name=\"name\" value=\"local-name()\"/>",
+                "<assert test=\"@amount\"><value-of select=\"$name\"/> has no amount</assert>",
+                "</rule>",
+                "<rule abstract=\"true\" id=\"positive\"><extends rule=\"amount\"/>",
+                "<assert test=\"not(@amount &lt; 60)\">small</assert></rule>",
+                "<rule context=\"line\"><extends rule=\"positive\"/></rule>",
+                "</pattern>"
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            findings(&schema, None).unwrap(),
+            [
+                "/invoice[1]/line[2] small",
+                "/invoice[1]/line[3] line has no amount"
+            ]
+        );
+        let errors = [
+            (
+                concat!(
+                    "<pattern><rule abstract=\"true\" id=\"a\"><assert test=\"1\"/></rule>",
+                    "</pattern>",
+                    "<pattern><rule context=\"line\"><extends rule=\"a\"/></rule></pattern>"
+                ),
+                "no abstract rule with id \"a\"",
+            ),
+            (
+                concat!(
+                    "<pattern><rule abstract=\"true\" id=\"a\"><extends rule=\"a\"/></rule>",
+                    "<rule context=\"line\"><extends rule=\"a\"/></rule></pattern>"
+                ),
+                "abstract rule \"a\" extends itself",
+            ),
+            (
+                "<pattern><rule abstract=\"true\" id=\"a\" context=\"line\"/></pattern>",
+                "an abstract rule may not have a context",
+            ),
+            (
+                "<pattern><rule abstract=\"true\"/></pattern>",
+                "an abstract rule must have an id",
+            ),
+        ];
+        for &(content, message) in &errors {
+            assert_eq!(load("", content).unwrap_err(), message);
+        }
+    }
+
+    #[test]
+    fn diagnostics() {
+        let schema = load(
+            "",
+            concat!(
+                "<pattern><rule context=\"line\">",
+                "<assert test=\"@amount\" diagnostics=\"where  hint\">missing</assert>",
+                "</rule></pattern>",
+                "<diagnostics>",
+                "<diagnostic id=\"where\">",
+                "line <value-of select=\"count(preceding-sibling::*) + 1\"/></diagnostic>",
+                "<diagnostic id=\"hint\">Add an <emph>amount</emph> attribute.</diagnostic>",
+                "</diagnostics>"
+            ),
+        )
+        .unwrap();
+        let findings = schema.validate(&document(INVOICE), None).unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings[0].diagnostics,
+            [
+                ("where".to_owned(), "line 3".to_owned()),
+                ("hint".to_owned(), "Add an amount attribute.".to_owned()),
+            ]
+        );
+        assert_eq!(
+            load(
+                "",
+                concat!(
+                    "<pattern><rule context=\"line\">",
+                    "<assert test=\"1\" diagnostics=\"none\"/></rule></pattern>"
+                )
+            )
+            .unwrap_err(),
+            "unknown diagnostic \"none\""
+        );
+    }
+
+    #[derive(Default)]
+    struct Findings(RefCell<Vec<String>>);
+
+    impl ErrorHandler<Finding> for Findings {
+        fn error(&self, finding: &Finding) -> Result<()> {
+            self.0
+                .borrow_mut()
+                .push(format!("error: {}", finding.message));
+            Ok(())
+        }
+        fn warning(&self, finding: &Finding) -> Result<()> {
+            self.0
+                .borrow_mut()
+                .push(format!("warning: {}", finding.message));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn validator() {
+        let schema = load(
+            "",
+            concat!(
+                "<pattern><rule context=\"line\">",
+                "<assert test=\"@amount\">no amount</assert>",
+                "<report test=\"@amount = 50\">fifty</report>",
+                "<report test=\"@amount = 100\" role=\"fatal\">hundred</report>",
+                "</rule></pattern>"
+            ),
+        )
+        .unwrap();
+        let validator = Validator::<LocatorImpl, _>::new(Rc::new(schema), Findings::default());
+        for event in markup_events(INVOICE) {
+            event.dispatch(&validator).unwrap();
+        }
+        assert_eq!(
+            validator.get_error_handler().0.take(),
+            ["error: hundred", "warning: fifty", "error: no amount"]
+        );
+    }
+}
@@ -0,0 +1,518 @@
+//! A secure processing profile for parsing untrusted documents.
+//!
+//! A document can declare external entities and an external DTD subset whose system
+//! identifiers point at local files or internal network services; a reader that loads them
+//! discloses their contents or probes the network on behalf of whoever wrote the document
+//! (XML External Entity attacks). Setting the `SECURE_PROCESSING` feature locks a reader down
+//! in one call: external general entities, external parameter entities and the external DTD
+//! subset are no longer loaded, only `file:` URIs are accepted, and only beneath the directory
+//! set with the `FILE_ROOT` property.
+//!
+//! Readers keep a `SecurityPolicy`, which answers `XmlReader::get_feature`,
+//! `XmlReader::set_feature`, `XmlReader::get_property_str` and `XmlReader::set_property_str`
+//! for the names it recognizes. `parse_uri` calls `SecurityPolicy::check_uri` before opening
+//! the document, and the entity resolver registered by the application is wrapped in a
+//! `SecureResolver`, so that the mappings it provides still apply but anything it leaves to
+//! the reader is checked against the policy.
+//!
+//! The entity expansion limits of the `limits` module are independent of this profile, and
+//! apply by default.
+use std::io::Read;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use common::EntityResolver;
+use ext::EntityResolver2;
+use features;
+use helpers::ErrorKind;
+use helpers::ParseErrorImpl;
+use sax2::Result;
+use uri;
+
+/// Restrict the processing of external resources, so that untrusted documents can be parsed
+/// safely (default false).
+///
+/// Setting this feature to true sets `features::EXTERNAL_GENERAL_ENTITIES`,
+/// `features::EXTERNAL_PARAMETER_ENTITIES` and `LOAD_EXTERNAL_DTD` to false, and
+/// `ALLOWED_URI_SCHEMES` to `file`; these can still be changed individually afterwards. While
+/// it is true, `file:` URIs are only accepted beneath `FILE_ROOT`, and not at all if it is
+/// unset. Setting it back to false restores the values that setting it to true replaced, except
+/// for those that have been set explicitly in the meantime.
+pub const SECURE_PROCESSING: &str =
+    "https://crates.io/crates/xml_parser_traits/features/secure-processing";
+
+/// Load the external DTD subset when it is not required for validation (default true).
+pub const LOAD_EXTERNAL_DTD: &str =
+    "https://crates.io/crates/xml_parser_traits/features/load-external-dtd";
+
+/// The comma-separated URI schemes that the reader may open, or the empty string for any
+/// scheme (default empty). Schemes are compared case-insensitively.
+pub const ALLOWED_URI_SCHEMES: &str =
+    "https://crates.io/crates/xml_parser_traits/properties/allowed-uri-schemes";
+
+/// The directory beneath which `file:` URIs are accepted, or the empty string for no
+/// restriction beyond that of `SECURE_PROCESSING` (default empty).
+pub const FILE_ROOT: &str = "https://crates.io/crates/xml_parser_traits/properties/file-root";
+
+/// The kind of external resource that a reader is about to load.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resource {
+    /// The document entity, as given to `parse_uri`.
+    Document,
+    /// An external general entity.
+    GeneralEntity,
+    /// An external parameter entity.
+    ParameterEntity,
+    /// The external DTD subset.
+    ExternalSubset,
+    /// A resource that is not an entity, such as an included document.
+    Other,
+}
+
+impl Resource {
+    /// Return the kind of resource identified by the `name` argument of
+    /// `EntityResolver2::resolve_entity_ext`.
+    pub fn from_entity_name(name: Option<&str>) -> Self {
+        match name {
+            Some("[dtd]") => Resource::ExternalSubset,
+            Some(name) if name.starts_with('%') => Resource::ParameterEntity,
+            Some(_) => Resource::GeneralEntity,
+            None => Resource::Other,
+        }
+    }
+}
+
+/// The reason why a `SecurityPolicy` refused access to a URI.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Refusal {
+    /// The URI has a scheme that is not allowed.
+    Scheme(String),
+    /// The URI is a `file:` URI naming another host.
+    RemoteFile,
+    /// The URI names a file outside the allowed root, or no file access is allowed.
+    OutsideRoot,
+}
+
+/// Return the error reporting that a `SecurityPolicy` refused access to a URI.
+fn refused(uri: &str, refusal: Refusal) -> ParseErrorImpl {
+    let message = match refusal {
+        Refusal::Scheme(ref scheme) => format!(
+            "access to {} is refused: the {} scheme is not allowed",
+            uri, scheme
+        ),
+        Refusal::RemoteFile => format!(
+            "access to {} is refused: it names a file on another host",
+            uri
+        ),
+        Refusal::OutsideRoot => format!(
+            "access to {} is refused: it is outside the allowed directory",
+            uri
+        ),
+    };
+    ParseErrorImpl::of_kind(ErrorKind::Access(refusal), message)
+}
+
+/// The restrictions that a reader applies to external resources.
+///
+/// The default policy places no restriction, as the SAX features prescribe;
+/// `SecurityPolicy::secure` returns the policy set by the `SECURE_PROCESSING` feature.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SecurityPolicy {
+    secure_processing: bool,
+    external_general_entities: bool,
+    external_parameter_entities: bool,
+    load_external_dtd: bool,
+    allowed_schemes: Vec<String>,
+    file_root: Option<PathBuf>,
+    /// The values that the secure profile replaced and has not been overridden since.
+    replaced: Replaced,
+}
+
+/// The values of the settings changed by `SECURE_PROCESSING` before it was set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Replaced {
+    external_general_entities: Option<bool>,
+    external_parameter_entities: Option<bool>,
+    load_external_dtd: Option<bool>,
+    allowed_schemes: Option<Vec<String>>,
+}
+
+impl Default for SecurityPolicy {
+    fn default() -> Self {
+        SecurityPolicy {
+            secure_processing: false,
+            external_general_entities: true,
+            external_parameter_entities: true,
+            load_external_dtd: true,
+            allowed_schemes: Vec::new(),
+            file_root: None,
+            replaced: Replaced::default(),
+        }
+    }
+}
+
+impl SecurityPolicy {
+    /// Construct the default, unrestricted policy.
+    pub fn new() -> Self {
+        SecurityPolicy::default()
+    }
+
+    /// Construct the policy with `SECURE_PROCESSING` set, accepting `file:` URIs beneath a
+    /// directory, if any.
+    pub fn secure(file_root: Option<&Path>) -> Self {
+        let mut policy = SecurityPolicy::default();
+        policy.set_secure_processing(true);
+        policy.file_root = file_root.map(normalize);
+        policy
+    }
+
+    fn set_secure_processing(&mut self, value: bool) {
+        if value == self.secure_processing {
+            return;
+        }
+        self.secure_processing = value;
+        if value {
+            self.replaced = Replaced {
+                external_general_entities: Some(self.external_general_entities),
+                external_parameter_entities: Some(self.external_parameter_entities),
+                load_external_dtd: Some(self.load_external_dtd),
+                allowed_schemes: Some(std::mem::replace(
+                    &mut self.allowed_schemes,
+                    vec!["file".to_owned()],
+                )),
+            };
+            self.external_general_entities = false;
+            self.external_parameter_entities = false;
+            self.load_external_dtd = false;
+        } else {
+            let replaced = std::mem::take(&mut self.replaced);
+            if let Some(value) = replaced.external_general_entities {
+                self.external_general_entities = value;
+            }
+            if let Some(value) = replaced.external_parameter_entities {
+                self.external_parameter_entities = value;
+            }
+            if let Some(value) = replaced.load_external_dtd {
+                self.load_external_dtd = value;
+            }
+            if let Some(schemes) = replaced.allowed_schemes {
+                self.allowed_schemes = schemes;
+            }
+        }
+    }
+
+    /// Return the value of a feature, or `None` if the feature name is not one this policy
+    /// governs.
+    pub fn get_feature(&self, name: &str) -> Option<bool> {
+        match name {
+            SECURE_PROCESSING => Some(self.secure_processing),
+            LOAD_EXTERNAL_DTD => Some(self.load_external_dtd),
+            features::EXTERNAL_GENERAL_ENTITIES => Some(self.external_general_entities),
+            features::EXTERNAL_PARAMETER_ENTITIES => Some(self.external_parameter_entities),
+            _ => None,
+        }
+    }
+
+    /// Set the value of a feature. Returns false if the feature name is not one this policy
+    /// governs.
+    pub fn set_feature(&mut self, name: &str, value: bool) -> bool {
+        match name {
+            SECURE_PROCESSING => self.set_secure_processing(value),
+            LOAD_EXTERNAL_DTD => {
+                self.load_external_dtd = value;
+                self.replaced.load_external_dtd = None;
+            }
+            features::EXTERNAL_GENERAL_ENTITIES => {
+                self.external_general_entities = value;
+                self.replaced.external_general_entities = None;
+            }
+            features::EXTERNAL_PARAMETER_ENTITIES => {
+                self.external_parameter_entities = value;
+                self.replaced.external_parameter_entities = None;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Return the value of a property as a string, or `None` if the property name is not one
+    /// this policy governs.
+    pub fn get_property(&self, name: &str) -> Option<String> {
+        match name {
+            ALLOWED_URI_SCHEMES => Some(self.allowed_schemes.join(",")),
+            FILE_ROOT => Some(
+                self.file_root
+                    .as_ref()
+                    .map_or_else(String::new, |root| root.to_string_lossy().into_owned()),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Set a property from its string value. Returns false if the property name is not one
+    /// this policy governs.
+    pub fn set_property(&mut self, name: &str, value: &str) -> bool {
+        match name {
+            ALLOWED_URI_SCHEMES => {
+                self.allowed_schemes = value
+                    .split(',')
+                    .map(|scheme| scheme.trim().to_ascii_lowercase())
+                    .filter(|scheme| !scheme.is_empty())
+                    .collect();
+                self.replaced.allowed_schemes = None;
+            }
+            FILE_ROOT => {
+                self.file_root = if value.is_empty() {
+                    None
+                } else {
+                    Some(normalize(Path::new(value)))
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Return whether resources of the given kind are loaded at all. Readers that honour the
+    /// features themselves skip the resources for which this returns false without opening
+    /// them.
+    pub fn allows(&self, resource: Resource) -> bool {
+        match resource {
+            Resource::GeneralEntity => self.external_general_entities,
+            Resource::ParameterEntity => self.external_parameter_entities,
+            Resource::ExternalSubset => self.load_external_dtd,
+            Resource::Document | Resource::Other => true,
+        }
+    }
+
+    /// Check that the reader may open a URI, which should be absolute; a relative URI
+    /// reference is taken as a path relative to the current directory.
+    ///
+    /// Readers call this before opening any URI themselves, starting with the one given to
+    /// `parse_uri`, but not for input provided by an entity resolver.
+    pub fn check_uri(&self, uri: &str) -> std::result::Result<(), ParseErrorImpl> {
+        let scheme = uri::scheme(uri).map(|scheme| scheme.to_ascii_lowercase());
+        if let Some(ref scheme) = scheme {
+            if !self.allowed_schemes.is_empty() && !self.allowed_schemes.contains(scheme) {
+                return Err(refused(uri, Refusal::Scheme(scheme.clone())));
+            }
+        }
+        match scheme.as_deref() {
+            None | Some("file") => {}
+            Some(_) => return Ok(()),
+        }
+        if self.file_root.is_none() && !self.secure_processing {
+            return Ok(());
+        }
+        let path = match uri::to_file_path(uri) {
+            Some(path) => path,
+            None => return Err(refused(uri, Refusal::RemoteFile)),
+        };
+        let inside = self
+            .file_root
+            .as_ref()
+            .is_some_and(|root| is_beneath(&path, root));
+        if inside {
+            Ok(())
+        } else {
+            Err(refused(uri, Refusal::OutsideRoot))
+        }
+    }
+}
+
+/// Return a path made absolute and without `.` or `..` components, following symbolic links
+/// where the path exists.
+fn normalize(path: &Path) -> PathBuf {
+    if let Ok(canonical) = std::fs::canonicalize(path) {
+        return canonical;
+    }
+    let mut normalized = match std::env::current_dir() {
+        Ok(directory) if path.is_relative() => directory,
+        _ => PathBuf::new(),
+    };
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Return whether a path lies beneath a normalized root directory.
+fn is_beneath(path: &Path, root: &Path) -> bool {
+    normalize(path).starts_with(root)
+}
+
+/// An entity resolver that applies a `SecurityPolicy` to the entities that another resolver
+/// leaves to the reader.
+///
+/// The input returned by the wrapped resolver is used as is, so that applications can still
+/// provide the entities they trust, for instance through a catalog. Otherwise, an entity of a
+/// kind the policy does not load resolves to empty input, and an entity whose URI the policy
+/// refuses is reported as an error of kind `ErrorKind::Access`; only the entities that pass
+/// are left to the reader.
+///
+/// `EntityResolver::resolve_entity` does not tell what kind of entity is resolved, so only the
+/// URI is checked; readers that support `EntityResolver2` get the full policy.
+pub struct SecureResolver<ER> {
+    resolver: ER,
+    policy: SecurityPolicy,
+}
+
+impl<ER> SecureResolver<ER> {
+    /// Construct a resolver applying a policy to the entities another resolver does not
+    /// resolve.
+    pub fn new(resolver: ER, policy: SecurityPolicy) -> Self {
+        SecureResolver { resolver, policy }
+    }
+
+    /// Return the wrapped resolver.
+    pub fn get_entity_resolver(&self) -> &ER {
+        &self.resolver
+    }
+
+    /// Return the policy.
+    pub fn get_policy(&self) -> &SecurityPolicy {
+        &self.policy
+    }
+
+    /// Return the policy, for modification.
+    pub fn get_policy_mut(&mut self) -> &mut SecurityPolicy {
+        &mut self.policy
+    }
+
+    fn check(
+        &self,
+        resource: Resource,
+        base_uri: Option<&str>,
+        system_id: &str,
+    ) -> Result<Option<Box<dyn Read>>> {
+        if !self.policy.allows(resource) {
+            return Ok(Some(Box::new(std::io::empty())));
+        }
+        let absolute = match base_uri {
+            Some(base) => uri::resolve(base, system_id),
+            None => system_id.to_owned(),
+        };
+        match self.policy.check_uri(&absolute) {
+            Ok(()) => Ok(None),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+}
+
+impl<ER: EntityResolver> EntityResolver for SecureResolver<ER> {
+    fn resolve_entity(
+        &self,
+        public_id: Option<&str>,
+        system_id: &str,
+    ) -> Result<Option<Box<dyn Read>>> {
+        match self.resolver.resolve_entity(public_id, system_id)? {
+            Some(input) => Ok(Some(input)),
+            None => self.check(Resource::Other, None, system_id),
+        }
+    }
+}
+
+impl<ER: EntityResolver2> EntityResolver2 for SecureResolver<ER> {
+    fn get_external_subset(
+        &self,
+        name: &str,
+        base_uri: Option<&str>,
+    ) -> Result<Option<Box<dyn Read>>> {
+        self.resolver.get_external_subset(name, base_uri)
+    }
+
+    fn resolve_entity_ext(
+        &self,
+        name: Option<&str>,
+        public_id: Option<&str>,
+        base_uri: Option<&str>,
+        system_id: &str,
+    ) -> Result<Option<Box<dyn Read>>> {
+        match self
+            .resolver
+            .resolve_entity_ext(name, public_id, base_uri, system_id)?
+        {
+            Some(input) => Ok(Some(input)),
+            None => self.check(Resource::from_entity_name(name), base_uri, system_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secure_profile_restores_what_it_replaced() {
+        let mut policy = SecurityPolicy::new();
+        policy.set_feature(features::EXTERNAL_GENERAL_ENTITIES, false);
+        policy.set_property(ALLOWED_URI_SCHEMES, "http, FILE");
+        policy.set_feature(SECURE_PROCESSING, true);
+        assert_eq!(policy.get_feature(LOAD_EXTERNAL_DTD), Some(false));
+        assert_eq!(
+            policy.get_property(ALLOWED_URI_SCHEMES).as_deref(),
+            Some("file")
+        );
+        policy.set_feature(features::EXTERNAL_PARAMETER_ENTITIES, true);
+        policy.set_feature(SECURE_PROCESSING, false);
+        assert_eq!(
+            policy.get_feature(features::EXTERNAL_GENERAL_ENTITIES),
+            Some(false)
+        );
+        assert_eq!(
+            policy.get_feature(features::EXTERNAL_PARAMETER_ENTITIES),
+            Some(true)
+        );
+        assert_eq!(policy.get_feature(LOAD_EXTERNAL_DTD), Some(true));
+        assert_eq!(
+            policy.get_property(ALLOWED_URI_SCHEMES).as_deref(),
+            Some("http,file")
+        );
+    }
+
+    #[test]
+    fn explicit_settings_survive_turning_the_profile_off() {
+        let mut policy = SecurityPolicy::secure(None);
+        policy.set_property(ALLOWED_URI_SCHEMES, "https");
+        policy.set_feature(LOAD_EXTERNAL_DTD, false);
+        policy.set_feature(SECURE_PROCESSING, false);
+        assert_eq!(
+            policy.get_property(ALLOWED_URI_SCHEMES).as_deref(),
+            Some("https")
+        );
+        assert_eq!(policy.get_feature(LOAD_EXTERNAL_DTD), Some(false));
+        assert_eq!(
+            policy.get_feature(features::EXTERNAL_GENERAL_ENTITIES),
+            Some(true)
+        );
+        policy.set_feature(SECURE_PROCESSING, false);
+        assert_eq!(policy.get_feature(LOAD_EXTERNAL_DTD), Some(false));
+    }
+
+    #[test]
+    fn checks_uris() {
+        let policy = SecurityPolicy::new();
+        assert!(policy.check_uri("http://example.com/a.dtd").is_ok());
+        assert!(policy.check_uri("file:///etc/passwd").is_ok());
+
+        let root = std::env::temp_dir();
+        let policy = SecurityPolicy::secure(Some(&root));
+        assert!(matches!(
+            policy.check_uri("http://example.com/a.dtd").map_err(|e| e.kind().clone()),
+            Err(ErrorKind::Access(Refusal::Scheme(ref scheme))) if scheme == "http"
+        ));
+        let inside = format!("file://{}/a.dtd", normalize(&root).display());
+        assert!(policy.check_uri(&inside).is_ok());
+        let escape = format!("file://{}/../a.dtd", normalize(&root).display());
+        assert!(policy.check_uri(&escape).is_err());
+        assert!(policy.check_uri("file://host/a.dtd").is_err());
+        assert!(SecurityPolicy::secure(None).check_uri(&inside).is_err());
+    }
+}
@@ -0,0 +1,566 @@
+//! Support for long-lived XML streams, as used by XMPP and similar protocols.
+//!
+//! Such protocols open a root element (the stream header) that stays open for the lifetime of
+//! the connection, and exchange complete top-level children of it (stanzas). A normal
+//! `ContentHandler` would never see the end of the document, so `StanzaCollector` regroups the
+//! event stream: it reports the stream header, buffers each top-level child until it is
+//! complete, and hands it to a `StanzaHandler` as a unit.
+use std::cell::RefCell;
+use std::marker::PhantomData;
+
+use common::EntityResolver;
+use common::InputSource;
+use common::Locator;
+use event::Event;
+use helpers::AttributesImpl;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::DtdHandler;
+use sax2::ErrorHandler;
+use sax2::ParseError;
+use sax2::Result;
+use sax2::XmlReader;
+
+/// A complete top-level child of a stream's root element.
+///
+/// The stanza holds the events from its own `start_element` to its matching `end_element`,
+/// preceded by `start_prefix_mapping` events for every Namespace mapping in scope for it and
+/// followed by the matching `end_prefix_mapping` events. The mappings declared on the root
+/// element are included, unless the stanza element declares the same prefix, so that the
+/// stanza can be replayed on its own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stanza {
+    events: Vec<Event>,
+}
+
+impl Stanza {
+    /// Return the events that make up this stanza, in document order.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Return the (uri, local name, qualified name) of the stanza element.
+    pub fn name(&self) -> Option<(&str, &str, &str)> {
+        self.events.iter().find_map(|event| match *event {
+            Event::StartElement {
+                ref uri,
+                ref local_name,
+                ref q_name,
+                ..
+            } => Some((uri.as_str(), local_name.as_str(), q_name.as_str())),
+            _ => None,
+        })
+    }
+
+    /// Return the attributes of the stanza element.
+    pub fn attributes(&self) -> Option<&AttributesImpl> {
+        self.events.iter().find_map(|event| match *event {
+            Event::StartElement { ref attributes, .. } => Some(attributes),
+            _ => None,
+        })
+    }
+
+    /// Replay the stanza into a content handler, as if it had been parsed on its own.
+    pub fn replay<L, H>(&self, handler: &H) -> Result<()>
+    where
+        L: Locator,
+        H: ContentHandler<L, AttributesImpl> + ?Sized,
+    {
+        for event in &self.events {
+            event.dispatch(handler)?;
+        }
+        Ok(())
+    }
+}
+
+/// Receives notification of the structure of a long-lived XML stream.
+pub trait StanzaHandler {
+    /// Receives notification of the stream header, i.e. the start tag of the root element.
+    ///
+    /// `prefixes` lists the Namespace mappings declared on the root element, in the order they
+    /// were reported.
+    #[allow(unused_variables)]
+    fn stream_start(
+        &self,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attributes: &AttributesImpl,
+        prefixes: &[(String, String)],
+    ) -> Result<()> {
+        Ok(())
+    }
+    /// Receives a complete top-level child of the root element.
+    #[allow(unused_variables)]
+    fn stanza(&self, stanza: Stanza) -> Result<()> {
+        Ok(())
+    }
+    /// Receives character data appearing directly inside the root element, between stanzas.
+    ///
+    /// In XMPP this is whitespace used as a keepalive.
+    #[allow(unused_variables)]
+    fn stream_characters(&self, content: &str) -> Result<()> {
+        Ok(())
+    }
+    /// Receives notification that the root element has been closed.
+    fn stream_end(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct CollectorState {
+    depth: usize,
+    /// The Namespace mappings declared on the root element.
+    root_prefixes: Vec<(String, String)>,
+    pending_prefixes: Vec<(String, String)>,
+    stanza_prefixes: Vec<String>,
+    buffer: Vec<Event>,
+}
+
+/// A `ContentHandler` that groups the events of a long-lived stream into stanzas.
+///
+/// Register a `StanzaCollector` as the content handler of an `XmlReader` (ideally an
+/// `XmlStreamReader`), and it will forward the stream header, each complete stanza and the
+/// stream end to the wrapped `StanzaHandler`. Only the stanza currently being parsed is held
+/// in memory.
+pub struct StanzaCollector<L: Locator, H: StanzaHandler> {
+    handler: H,
+    state: RefCell<CollectorState>,
+    locator: PhantomData<L>,
+}
+
+impl<L: Locator, H: StanzaHandler> StanzaCollector<L, H> {
+    /// Construct a collector that reports to the given stanza handler.
+    pub fn new(handler: H) -> Self {
+        StanzaCollector {
+            handler,
+            state: RefCell::new(CollectorState::default()),
+            locator: PhantomData,
+        }
+    }
+
+    /// Return the wrapped stanza handler.
+    pub fn get_handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// Discard any partially received stanza and wait for a new stream header.
+    ///
+    /// Call this alongside `XmlStreamReader::reset` when the stream is restarted.
+    pub fn reset(&self) {
+        *self.state.borrow_mut() = CollectorState::default();
+    }
+
+    fn buffer(&self, event: Event) -> Result<()> {
+        self.state.borrow_mut().buffer.push(event);
+        Ok(())
+    }
+}
+
+impl<L: Locator, A: Attributes, H: StanzaHandler> ContentHandler<L, A> for StanzaCollector<L, H> {
+    fn characters(&self, content: &str) -> Result<()> {
+        if self.state.borrow().depth > 1 {
+            self.buffer(Event::Characters(content.to_owned()))
+        } else {
+            self.handler.stream_characters(content)
+        }
+    }
+    fn end_document(&self) -> Result<()> {
+        self.reset();
+        Ok(())
+    }
+    fn end_element(&self, uri: &str, local_name: &str, q_name: &str) -> Result<()> {
+        let stanza = {
+            let mut state = self.state.borrow_mut();
+            state.depth = state.depth.saturating_sub(1);
+            if state.depth == 0 {
+                None
+            } else {
+                state
+                    .buffer
+                    .push(Event::end_element(uri, local_name, q_name));
+                if state.depth > 1 {
+                    return Ok(());
+                }
+                // The end_prefix_mapping events for the stanza element only arrive after this
+                // callback returns, so synthesize them now rather than delaying delivery.
+                let prefixes = std::mem::take(&mut state.stanza_prefixes);
+                for prefix in prefixes {
+                    state.buffer.push(Event::EndPrefixMapping { prefix });
+                }
+                Some(Stanza {
+                    events: std::mem::take(&mut state.buffer),
+                })
+            }
+        };
+        match stanza {
+            Some(stanza) => self.handler.stanza(stanza),
+            None => self.handler.stream_end(),
+        }
+    }
+    fn end_prefix_mapping(&self, prefix: &str) -> Result<()> {
+        if self.state.borrow().depth > 1 {
+            self.buffer(Event::EndPrefixMapping {
+                prefix: prefix.to_owned(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+    fn ignorable_whitespace(&self, content: &str) -> Result<()> {
+        if self.state.borrow().depth > 1 {
+            self.buffer(Event::IgnorableWhitespace(content.to_owned()))
+        } else {
+            self.handler.stream_characters(content)
+        }
+    }
+    fn processing_instruction(&self, target: &str, data: &str) -> Result<()> {
+        if self.state.borrow().depth > 1 {
+            self.buffer(Event::ProcessingInstruction {
+                target: target.to_owned(),
+                data: data.to_owned(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+    fn skipped_entity(&self, name: &str) -> Result<()> {
+        if self.state.borrow().depth > 1 {
+            self.buffer(Event::SkippedEntity(name.to_owned()))
+        } else {
+            Ok(())
+        }
+    }
+    fn start_document(&self) -> Result<()> {
+        self.reset();
+        Ok(())
+    }
+    fn start_element(
+        &self,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attributes: A,
+    ) -> Result<()> {
+        let (depth, prefixes) = {
+            let mut state = self.state.borrow_mut();
+            state.depth += 1;
+            let prefixes = std::mem::take(&mut state.pending_prefixes);
+            (state.depth, prefixes)
+        };
+        if depth == 1 {
+            self.state.borrow_mut().root_prefixes = prefixes.clone();
+            let attributes = AttributesImpl::from_attributes(&attributes);
+            return self
+                .handler
+                .stream_start(uri, local_name, q_name, &attributes, &prefixes);
+        }
+        let mut state = self.state.borrow_mut();
+        let prefixes = if depth == 2 {
+            let mut in_scope: Vec<_> = state
+                .root_prefixes
+                .iter()
+                .filter(|inherited| prefixes.iter().all(|own| own.0 != inherited.0))
+                .cloned()
+                .collect();
+            in_scope.extend(prefixes);
+            state.stanza_prefixes = in_scope.iter().map(|p| p.0.clone()).collect();
+            in_scope
+        } else {
+            prefixes
+        };
+        for (prefix, uri) in prefixes {
+            state.buffer.push(Event::StartPrefixMapping { prefix, uri });
+        }
+        state
+            .buffer
+            .push(Event::start_element(uri, local_name, q_name, &attributes));
+        Ok(())
+    }
+    fn start_prefix_mapping(&self, prefix: &str, uri: &str) -> Result<()> {
+        self.state
+            .borrow_mut()
+            .pending_prefixes
+            .push((prefix.to_owned(), uri.to_owned()));
+        Ok(())
+    }
+}
+
+/// Interface for a reader that can parse a long-lived XML stream.
+///
+/// In addition to the `XmlReader` contract, a stream reader must report each event as soon as
+/// the input that completes it has been read, rather than waiting for the end of the document,
+/// and must not read further ahead in the input than it needs to. This allows the application
+/// to react to an element (such as an XMPP `<proceed/>`) before the peer sends anything else,
+/// and to hand the underlying transport to another layer (such as TLS) without losing data.
+pub trait XmlStreamReader<
+    CH: ContentHandler<L, A>,
+    DH: DtdHandler,
+    ER: EntityResolver,
+    EH: ErrorHandler<E>,
+    L: Locator,
+    A: Attributes,
+    E: ParseError,
+    I: InputSource,
+>: XmlReader<CH, DH, ER, EH, L, A, E, I>
+{
+    /// Restart the stream.
+    ///
+    /// This may be called from within an event callback during a parse. Once the callback
+    /// returns, the reader discards its element stack, Namespace context and any buffered input,
+    /// and continues parsing the same input source as if it were at the start of a new document:
+    /// an XML declaration is accepted again, and the next root element is a new stream header.
+    /// The reader reports `start_document` before any events from the new stream, but does not
+    /// report `end_element` or `end_document` for the abandoned one.
+    ///
+    /// Handler registrations, feature flags and properties are unchanged by a restart.
+    fn reset(&self) -> Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event::EventRecorder;
+    use helpers::LocatorImpl;
+
+    const STREAMS: &str = "http://etherx.jabber.org/streams";
+    const CLIENT: &str = "jabber:client";
+
+    /// Records the notifications of a stream, stanzas by the events they replay.
+    #[derive(Default)]
+    struct Log(RefCell<Vec<String>>);
+
+    impl Log {
+        fn push(&self, entry: String) {
+            self.0.borrow_mut().push(entry);
+        }
+    }
+
+    impl StanzaHandler for Log {
+        fn stream_start(
+            &self,
+            _uri: &str,
+            _local_name: &str,
+            q_name: &str,
+            attributes: &AttributesImpl,
+            prefixes: &[(String, String)],
+        ) -> Result<()> {
+            let prefixes: Vec<_> = prefixes.iter().map(|p| p.0.as_str()).collect();
+            self.push(format!(
+                "start {} to={} [{}]",
+                q_name,
+                attributes.get_q_name_value("to").unwrap_or_default(),
+                prefixes.join(" ")
+            ));
+            Ok(())
+        }
+        fn stanza(&self, stanza: Stanza) -> Result<()> {
+            let recorder = EventRecorder::<LocatorImpl>::new();
+            stanza.replay(&recorder)?;
+            let events: Vec<_> = recorder.events().iter().map(describe).collect();
+            self.push(events.join(" "));
+            Ok(())
+        }
+        fn stream_characters(&self, content: &str) -> Result<()> {
+            self.push(format!("{:?}", content));
+            Ok(())
+        }
+        fn stream_end(&self) -> Result<()> {
+            self.push("end".to_owned());
+            Ok(())
+        }
+    }
+
+    fn describe(event: &Event) -> String {
+        match *event {
+            Event::StartPrefixMapping {
+                ref prefix,
+                ref uri,
+            } => format!("+{}={}", prefix, uri),
+            Event::EndPrefixMapping { ref prefix } => format!("-{}", prefix),
+            Event::StartElement {
+                ref uri,
+                ref q_name,
+                ..
+            } => format!("<{}:{}>", uri, q_name),
+            Event::EndElement { ref q_name, .. } => format!("</{}>", q_name),
+            Event::Characters(ref content) => format!("{:?}", content),
+            ref other => format!("{:?}", other),
+        }
+    }
+
+    type Collector = StanzaCollector<LocatorImpl, Log>;
+
+    fn collector() -> Collector {
+        StanzaCollector::new(Log::default())
+    }
+
+    fn prefix(collector: &Collector, prefix: &str, uri: &str) {
+        Event::StartPrefixMapping {
+            prefix: prefix.to_owned(),
+            uri: uri.to_owned(),
+        }
+        .dispatch(collector)
+        .unwrap();
+    }
+
+    fn end_prefix(collector: &Collector, prefix: &str) {
+        Event::EndPrefixMapping {
+            prefix: prefix.to_owned(),
+        }
+        .dispatch(collector)
+        .unwrap();
+    }
+
+    fn start(collector: &Collector, uri: &str, q_name: &str) {
+        let mut attributes = AttributesImpl::new();
+        attributes.add_attribute("", "to", "to", "CDATA", "example.org");
+        let local_name = q_name.rsplit(':').next().unwrap();
+        Event::start_element(uri, local_name, q_name, &attributes)
+            .dispatch(collector)
+            .unwrap();
+    }
+
+    fn end(collector: &Collector, uri: &str, q_name: &str) {
+        let local_name = q_name.rsplit(':').next().unwrap();
+        Event::end_element(uri, local_name, q_name)
+            .dispatch(collector)
+            .unwrap();
+    }
+
+    fn text(collector: &Collector, content: &str) {
+        Event::Characters(content.to_owned())
+            .dispatch(collector)
+            .unwrap();
+    }
+
+    /// Open a stream whose root element declares the default and `stream` prefixes.
+    fn open(collector: &Collector) {
+        Event::StartDocument.dispatch(collector).unwrap();
+        prefix(collector, "", CLIENT);
+        prefix(collector, "stream", STREAMS);
+        start(collector, STREAMS, "stream:stream");
+    }
+
+    fn log(collector: &Collector) -> Vec<String> {
+        collector.get_handler().0.take()
+    }
+
+    #[test]
+    fn stanza_boundaries() {
+        let collector = collector();
+        open(&collector);
+        assert_eq!(
+            log(&collector),
+            ["start stream:stream to=example.org [ stream]"]
+        );
+        text(&collector, " ");
+        start(&collector, CLIENT, "message");
+        start(&collector, CLIENT, "body");
+        text(&collector, "hi");
+        assert_eq!(log(&collector), [r#"" ""#]);
+        end(&collector, CLIENT, "body");
+        start(&collector, CLIENT, "thread");
+        end(&collector, CLIENT, "thread");
+        assert!(log(&collector).is_empty());
+        end(&collector, CLIENT, "message");
+        start(&collector, CLIENT, "presence");
+        end(&collector, CLIENT, "presence");
+        text(&collector, "\n");
+        end(&collector, STREAMS, "stream:stream");
+        assert_eq!(
+            log(&collector),
+            [
+                concat!(
+                    "+=jabber:client +stream=http://etherx.jabber.org/streams ",
+                    "<jabber:client:message> <jabber:client:body> \"hi\" </body> ",
+                    "<jabber:client:thread> </thread> </message> - -stream"
+                ),
+                concat!(
+                    "+=jabber:client +stream=http://etherx.jabber.org/streams ",
+                    "<jabber:client:presence> </presence> - -stream"
+                ),
+                r#""\n""#,
+                "end",
+            ]
+        );
+    }
+
+    #[test]
+    fn inherited_and_own_prefix_mappings() {
+        let collector = collector();
+        open(&collector);
+        log(&collector);
+        prefix(&collector, "", "jabber:server");
+        prefix(&collector, "db", "jabber:server:dialback");
+        start(&collector, "jabber:server:dialback", "db:result");
+        prefix(&collector, "x", "urn:x");
+        start(&collector, "urn:x", "x:data");
+        end(&collector, "urn:x", "x:data");
+        end_prefix(&collector, "x");
+        end(&collector, "jabber:server:dialback", "db:result");
+        end_prefix(&collector, "");
+        end_prefix(&collector, "db");
+        start(&collector, CLIENT, "iq");
+        end(&collector, CLIENT, "iq");
+        assert_eq!(
+            log(&collector),
+            [
+                concat!(
+                    "+stream=http://etherx.jabber.org/streams +=jabber:server ",
+                    "+db=jabber:server:dialback <jabber:server:dialback:db:result> ",
+                    "+x=urn:x <urn:x:x:data> </x:data> -x </db:result> -stream - -db"
+                ),
+                concat!(
+                    "+=jabber:client +stream=http://etherx.jabber.org/streams ",
+                    "<jabber:client:iq> </iq> - -stream"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn reset_discards_the_partial_stanza() {
+        let collector = collector();
+        open(&collector);
+        start(&collector, CLIENT, "starttls");
+        start(&collector, CLIENT, "required");
+        collector.reset();
+        Event::StartDocument.dispatch(&collector).unwrap();
+        prefix(&collector, "", CLIENT);
+        start(&collector, STREAMS, "stream");
+        start(&collector, CLIENT, "iq");
+        end(&collector, CLIENT, "iq");
+        assert_eq!(
+            log(&collector),
+            [
+                "start stream:stream to=example.org [ stream]",
+                "start stream to=example.org []",
+                "+=jabber:client <jabber:client:iq> </iq> -",
+            ]
+        );
+    }
+
+    #[test]
+    fn second_document() {
+        let collector = collector();
+        open(&collector);
+        start(&collector, CLIENT, "message");
+        Event::EndDocument.dispatch(&collector).unwrap();
+        Event::StartDocument.dispatch(&collector).unwrap();
+        start(&collector, "", "stream");
+        start(&collector, "", "message");
+        end(&collector, "", "message");
+        end(&collector, "", "stream");
+        assert_eq!(
+            log(&collector),
+            [
+                "start stream:stream to=example.org [ stream]",
+                "start stream to=example.org []",
+                "<:message> </message>",
+                "end",
+            ]
+        );
+    }
+}
@@ -0,0 +1,515 @@
+//! An in-memory tree of a document, built from SAX events.
+//!
+//! The tree follows the data model of XPath 1.0: a root node, with element, attribute, text,
+//! namespace, comment and processing instruction nodes below it. Nodes are identified by
+//! `NodeId`s, which are allocated in document order, so that sorting node identifiers sorts the
+//! nodes in document order. Each node records the position of the event that created it, for
+//! diagnostics.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use common::Locator;
+use ext::LexicalHandler;
+use helpers::LocatorImpl;
+use helpers::NSDECL;
+use helpers::XMLNS;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::Result;
+
+/// The identifier of a node in a `Document`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    /// Return the index of the node, which is also its position in document order.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// The kind of a node, with the information specific to that kind.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    Root,
+    Element {
+        uri: String,
+        local_name: String,
+        q_name: String,
+    },
+    Attribute {
+        uri: String,
+        local_name: String,
+        q_name: String,
+        value: String,
+    },
+    /// A Namespace node: one of the Namespace bindings in scope on an element.
+    Namespace {
+        prefix: String,
+        uri: String,
+    },
+    Text(String),
+    Comment(String),
+    ProcessingInstruction {
+        target: String,
+        data: String,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct NodeData {
+    kind: NodeKind,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    attributes: Vec<NodeId>,
+    namespace_nodes: Vec<NodeId>,
+    namespaces: Vec<(String, String)>,
+    location: LocatorImpl,
+}
+
+/// An in-memory document.
+#[derive(Clone, Debug)]
+pub struct Document {
+    nodes: Vec<NodeData>,
+    ids: HashMap<String, NodeId>,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Document::new()
+    }
+}
+
+impl Document {
+    /// Construct a document consisting only of a root node.
+    pub fn new() -> Self {
+        let mut document = Document {
+            nodes: Vec::new(),
+            ids: HashMap::new(),
+        };
+        document.push(NodeKind::Root, None, LocatorImpl::new());
+        document
+    }
+
+    fn push(&mut self, kind: NodeKind, parent: Option<NodeId>, location: LocatorImpl) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(NodeData {
+            kind,
+            parent,
+            children: Vec::new(),
+            attributes: Vec::new(),
+            namespace_nodes: Vec::new(),
+            namespaces: Vec::new(),
+            location,
+        });
+        id
+    }
+
+    fn data(&self, node: NodeId) -> &NodeData {
+        &self.nodes[node.0]
+    }
+
+    /// Return the root node.
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    /// Return the document element, if there is one.
+    pub fn document_element(&self) -> Option<NodeId> {
+        self.children(self.root())
+            .iter()
+            .cloned()
+            .find(|child| self.is_element(*child))
+    }
+
+    /// Return all nodes of the document, in document order.
+    pub fn nodes(&self) -> impl Iterator<Item = NodeId> {
+        (0..self.nodes.len()).map(NodeId)
+    }
+
+    /// Return the kind of a node.
+    pub fn kind(&self, node: NodeId) -> &NodeKind {
+        &self.data(node).kind
+    }
+
+    /// Return whether a node is an element.
+    pub fn is_element(&self, node: NodeId) -> bool {
+        matches!(self.kind(node), NodeKind::Element { .. })
+    }
+
+    /// Return the parent of a node. The parent of an attribute or Namespace node is the element
+    /// it belongs to.
+    pub fn parent(&self, node: NodeId) -> Option<NodeId> {
+        self.data(node).parent
+    }
+
+    /// Return the children of a node, in document order.
+    pub fn children(&self, node: NodeId) -> &[NodeId] {
+        &self.data(node).children
+    }
+
+    /// Return the attributes of an element.
+    pub fn attributes(&self, node: NodeId) -> &[NodeId] {
+        &self.data(node).attributes
+    }
+
+    /// Return the Namespace nodes of an element, one for each Namespace binding in scope on it,
+    /// including the binding of the "xml" prefix.
+    pub fn namespace_nodes(&self, node: NodeId) -> &[NodeId] {
+        &self.data(node).namespace_nodes
+    }
+
+    /// Return the element with the given unique identifier: the value of an attribute of type
+    /// ID, or of an `xml:id` attribute. If several elements have the same identifier, the
+    /// first one is returned.
+    pub fn element_by_id(&self, id: &str) -> Option<NodeId> {
+        self.ids.get(id).cloned()
+    }
+
+    /// Return the Namespace declarations made on an element, as prefix and URI pairs.
+    pub fn declared_namespaces(&self, node: NodeId) -> &[(String, String)] {
+        &self.data(node).namespaces
+    }
+
+    /// Return the position of the event that created a node.
+    pub fn location(&self, node: NodeId) -> &LocatorImpl {
+        &self.data(node).location
+    }
+
+    /// Return the Namespace URI of an element or attribute, or the empty string.
+    pub fn namespace_uri(&self, node: NodeId) -> &str {
+        match self.kind(node) {
+            NodeKind::Element { uri, .. } | NodeKind::Attribute { uri, .. } => uri,
+            _ => "",
+        }
+    }
+
+    /// Return the local name of an element or attribute, the prefix of a Namespace node, the
+    /// target of a processing instruction, or the empty string.
+    pub fn local_name(&self, node: NodeId) -> &str {
+        match self.kind(node) {
+            NodeKind::Element { local_name, .. } | NodeKind::Attribute { local_name, .. } => {
+                local_name
+            }
+            NodeKind::Namespace { prefix, .. } => prefix,
+            NodeKind::ProcessingInstruction { target, .. } => target,
+            _ => "",
+        }
+    }
+
+    /// Return the qualified name of an element or attribute, the prefix of a Namespace node,
+    /// the target of a processing instruction, or the empty string.
+    pub fn q_name(&self, node: NodeId) -> &str {
+        match self.kind(node) {
+            NodeKind::Element { q_name, .. } | NodeKind::Attribute { q_name, .. } => q_name,
+            NodeKind::Namespace { prefix, .. } => prefix,
+            NodeKind::ProcessingInstruction { target, .. } => target,
+            _ => "",
+        }
+    }
+
+    /// Return the value of an attribute of an element.
+    pub fn attribute_value(&self, node: NodeId, uri: &str, local_name: &str) -> Option<&str> {
+        self.attributes(node)
+            .iter()
+            .find_map(|attribute| match self.kind(*attribute) {
+                NodeKind::Attribute {
+                    uri: u,
+                    local_name: l,
+                    value,
+                    ..
+                } if u == uri && l == local_name => Some(value.as_str()),
+                _ => None,
+            })
+    }
+
+    /// Return the string-value of a node: the concatenated text of all descendant text nodes
+    /// for the root and elements, and the value or content of other nodes.
+    pub fn string_value(&self, node: NodeId) -> String {
+        match self.kind(node) {
+            NodeKind::Root | NodeKind::Element { .. } => {
+                let mut value = String::new();
+                self.append_text(node, &mut value);
+                value
+            }
+            NodeKind::Attribute { value, .. } => value.clone(),
+            NodeKind::Namespace { uri, .. } => uri.clone(),
+            NodeKind::Text(text) | NodeKind::Comment(text) => text.clone(),
+            NodeKind::ProcessingInstruction { data, .. } => data.clone(),
+        }
+    }
+
+    fn append_text(&self, node: NodeId, value: &mut String) {
+        for child in self.children(node) {
+            match self.kind(*child) {
+                NodeKind::Text(text) => value.push_str(text),
+                NodeKind::Element { .. } => self.append_text(*child, value),
+                _ => {}
+            }
+        }
+    }
+
+    /// Return a location path that selects exactly this node, such as
+    /// `/invoice[1]/line[2]/@id`, for diagnostics.
+    pub fn path(&self, node: NodeId) -> String {
+        let parent = match self.parent(node) {
+            Some(parent) => parent,
+            None => return "/".to_owned(),
+        };
+        let mut path = self.path(parent);
+        if path == "/" {
+            path.clear();
+        }
+        let kind = self.kind(node);
+        let position = |same: &dyn Fn(&NodeKind) -> bool| {
+            self.children(parent)
+                .iter()
+                .take_while(|sibling| **sibling != node)
+                .filter(|sibling| same(self.kind(**sibling)))
+                .count()
+                + 1
+        };
+        let step = match kind {
+            NodeKind::Root => String::new(),
+            NodeKind::Attribute { q_name, .. } => format!("@{}", q_name),
+            NodeKind::Namespace { prefix, .. } if prefix.is_empty() => {
+                "namespace::*[not(local-name())]".to_owned()
+            }
+            NodeKind::Namespace { prefix, .. } => format!("namespace::{}", prefix),
+            NodeKind::Element { q_name, .. } => format!(
+                "{}[{}]",
+                q_name,
+                position(&|other| match other {
+                    NodeKind::Element { q_name: other, .. } => other == q_name,
+                    _ => false,
+                })
+            ),
+            NodeKind::Text(_) => format!(
+                "text()[{}]",
+                position(&|other| matches!(other, NodeKind::Text(_)))
+            ),
+            NodeKind::Comment(_) => format!(
+                "comment()[{}]",
+                position(&|other| matches!(other, NodeKind::Comment(_)))
+            ),
+            NodeKind::ProcessingInstruction { target, .. } => format!(
+                "processing-instruction('{}')[{}]",
+                target,
+                position(&|other| match other {
+                    NodeKind::ProcessingInstruction { target: other, .. } => other == target,
+                    _ => false,
+                })
+            ),
+        };
+        format!("{}/{}", path, step)
+    }
+}
+
+#[derive(Debug, Default)]
+struct TreeState {
+    document: Document,
+    stack: Vec<NodeId>,
+    pending: Vec<(String, String)>,
+    in_dtd: bool,
+}
+
+/// Builds a `Document` from SAX events.
+///
+/// Register the builder as the content handler, and optionally as the lexical handler to keep
+/// comments, of a Namespace-aware reader; once the document has been parsed, take it with
+/// `take_document`. Namespace declaration attributes are not kept as attributes; they are
+/// available through `Document::declared_namespaces`, and as the Namespace nodes of the
+/// elements in their scope. Attributes reported with the type ID, and `xml:id` attributes,
+/// identify their element for `Document::element_by_id`.
+pub struct TreeBuilder<L: Locator> {
+    locator: RefCell<Option<Rc<L>>>,
+    state: RefCell<TreeState>,
+}
+
+impl<L: Locator> Default for TreeBuilder<L> {
+    fn default() -> Self {
+        TreeBuilder::new()
+    }
+}
+
+impl<L: Locator> TreeBuilder<L> {
+    /// Construct a new tree builder.
+    pub fn new() -> Self {
+        TreeBuilder {
+            locator: RefCell::new(None),
+            state: RefCell::new(TreeState::default()),
+        }
+    }
+
+    /// Take the document built so far, leaving an empty one in its place.
+    pub fn take_document(&self) -> Document {
+        let mut state = self.state.borrow_mut();
+        state.stack.clear();
+        std::mem::take(&mut state.document)
+    }
+
+    fn location(&self) -> LocatorImpl {
+        self.locator
+            .borrow()
+            .as_ref()
+            .map(|locator| LocatorImpl::from_locator(&**locator))
+            .unwrap_or_default()
+    }
+
+    fn append(&self, kind: NodeKind) {
+        let location = self.location();
+        let mut guard = self.state.borrow_mut();
+        let state = &mut *guard;
+        let parent = state.stack.last().cloned().unwrap_or(NodeId(0));
+        let id = state.document.push(kind, Some(parent), location);
+        state.document.nodes[parent.0].children.push(id);
+    }
+
+    fn text(&self, content: &str) {
+        let mut guard = self.state.borrow_mut();
+        let state = &mut *guard;
+        let parent = match state.stack.last() {
+            Some(parent) => *parent,
+            None => return,
+        };
+        if let Some(last) = state.document.nodes[parent.0].children.last().cloned() {
+            if let NodeKind::Text(ref mut text) = state.document.nodes[last.0].kind {
+                text.push_str(content);
+                return;
+            }
+        }
+        drop(guard);
+        self.append(NodeKind::Text(content.to_owned()));
+    }
+}
+
+impl<L: Locator, A: Attributes> ContentHandler<L, A> for TreeBuilder<L> {
+    fn characters(&self, content: &str) -> Result<()> {
+        self.text(content);
+        Ok(())
+    }
+    fn end_element(&self, _uri: &str, _local_name: &str, _q_name: &str) -> Result<()> {
+        self.state.borrow_mut().stack.pop();
+        Ok(())
+    }
+    fn ignorable_whitespace(&self, content: &str) -> Result<()> {
+        self.text(content);
+        Ok(())
+    }
+    fn processing_instruction(&self, target: &str, data: &str) -> Result<()> {
+        self.append(NodeKind::ProcessingInstruction {
+            target: target.to_owned(),
+            data: data.to_owned(),
+        });
+        Ok(())
+    }
+    fn set_document_locator(&self, locator: Rc<L>) {
+        *self.locator.borrow_mut() = Some(locator);
+    }
+    fn start_document(&self) -> Result<()> {
+        *self.state.borrow_mut() = TreeState::default();
+        Ok(())
+    }
+    fn start_element(
+        &self,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attributes: A,
+    ) -> Result<()> {
+        let location = self.location();
+        let mut guard = self.state.borrow_mut();
+        let state = &mut *guard;
+        let parent = state.stack.last().cloned().unwrap_or(NodeId(0));
+        let kind = NodeKind::Element {
+            uri: uri.to_owned(),
+            local_name: local_name.to_owned(),
+            q_name: q_name.to_owned(),
+        };
+        let element = state.document.push(kind, Some(parent), location.clone());
+        state.document.nodes[parent.0].children.push(element);
+        let declared = std::mem::take(&mut state.pending);
+        let mut in_scope: Vec<(String, String)> = state.document.nodes[parent.0]
+            .namespace_nodes
+            .iter()
+            .filter_map(|node| match state.document.kind(*node) {
+                NodeKind::Namespace { prefix, uri } => Some((prefix.clone(), uri.clone())),
+                _ => None,
+            })
+            .collect();
+        if in_scope.is_empty() {
+            in_scope.push(("xml".to_owned(), XMLNS.to_owned()));
+        }
+        for (prefix, uri) in &declared {
+            in_scope.retain(|binding| binding.0 != *prefix);
+            if !uri.is_empty() {
+                in_scope.push((prefix.clone(), uri.clone()));
+            }
+        }
+        for (prefix, uri) in in_scope {
+            let kind = NodeKind::Namespace { prefix, uri };
+            let namespace = state.document.push(kind, Some(element), location.clone());
+            state.document.nodes[element.0]
+                .namespace_nodes
+                .push(namespace);
+        }
+        state.document.nodes[element.0].namespaces = declared;
+        for index in 0..attributes.get_length() as u64 {
+            let attr_uri = attributes.get_uri(index).unwrap_or_default();
+            let attr_q_name = attributes.get_q_name(index).unwrap_or_default();
+            if attr_uri == NSDECL || attr_q_name == "xmlns" || attr_q_name.starts_with("xmlns:") {
+                continue;
+            }
+            let local_name = attributes.get_local_name(index).unwrap_or_default();
+            let value = attributes.get_value(index).unwrap_or_default();
+            let is_xml_id = (attr_uri == XMLNS && local_name == "id") || attr_q_name == "xml:id";
+            if is_xml_id || attributes.get_type(index).as_deref() == Some("ID") {
+                let id = value
+                    .split(' ')
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<&str>>();
+                state.document.ids.entry(id.join(" ")).or_insert(element);
+            }
+            let kind = NodeKind::Attribute {
+                uri: attr_uri,
+                local_name,
+                q_name: attr_q_name,
+                value,
+            };
+            let attribute = state.document.push(kind, Some(element), location.clone());
+            state.document.nodes[element.0].attributes.push(attribute);
+        }
+        state.stack.push(element);
+        Ok(())
+    }
+    fn start_prefix_mapping(&self, prefix: &str, uri: &str) -> Result<()> {
+        self.state
+            .borrow_mut()
+            .pending
+            .push((prefix.to_owned(), uri.to_owned()));
+        Ok(())
+    }
+}
+
+impl<L: Locator> LexicalHandler for TreeBuilder<L> {
+    fn comment(&self, content: &str) -> Result<()> {
+        if !self.state.borrow().in_dtd {
+            self.append(NodeKind::Comment(content.to_owned()));
+        }
+        Ok(())
+    }
+    fn end_dtd(&self) -> Result<()> {
+        self.state.borrow_mut().in_dtd = false;
+        Ok(())
+    }
+    fn start_dtd(
+        &self,
+        _name: &str,
+        _public_id: Option<&str>,
+        _system_id: Option<&str>,
+    ) -> Result<()> {
+        self.state.borrow_mut().in_dtd = true;
+        Ok(())
+    }
+}
@@ -0,0 +1,376 @@
+//! Resolution of URI references, as specified by RFC 3986.
+//!
+//! System identifiers, `xml:base` attributes, the `href` attributes of XInclude elements and
+//! the entries of catalogs are URI references, which are interpreted relative to a base URI.
+//! These functions work on the syntax of URIs alone and never access the resources they
+//! identify.
+use std::path::PathBuf;
+
+/// The five components of a URI reference. The path is always present, though possibly empty.
+struct Components<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: &'a str,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+impl<'a> Components<'a> {
+    /// Split a URI reference into its components (RFC 3986, Appendix B).
+    fn parse(reference: &'a str) -> Self {
+        let (rest, fragment) = match reference.find('#') {
+            Some(i) => (&reference[..i], Some(&reference[i + 1..])),
+            None => (reference, None),
+        };
+        let (rest, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+        let (scheme, rest) = match rest.find(':') {
+            Some(i) if is_scheme(&rest[..i]) => (Some(&rest[..i]), &rest[i + 1..]),
+            _ => (None, rest),
+        };
+        let (authority, path) = if let Some(rest) = rest.strip_prefix("//") {
+            match rest.find('/') {
+                Some(i) => (Some(&rest[..i]), &rest[i..]),
+                None => (Some(rest), ""),
+            }
+        } else {
+            (None, rest)
+        };
+        Components {
+            scheme,
+            authority,
+            path,
+            query,
+            fragment,
+        }
+    }
+}
+
+/// Recompose a URI reference from its components (RFC 3986, section 5.3).
+fn recompose(
+    scheme: Option<&str>,
+    authority: Option<&str>,
+    path: &str,
+    query: Option<&str>,
+    fragment: Option<&str>,
+) -> String {
+    let mut result = String::new();
+    if let Some(scheme) = scheme {
+        result.push_str(scheme);
+        result.push(':');
+    }
+    if let Some(authority) = authority {
+        result.push_str("//");
+        result.push_str(authority);
+    }
+    result.push_str(path);
+    if let Some(query) = query {
+        result.push('?');
+        result.push_str(query);
+    }
+    if let Some(fragment) = fragment {
+        result.push('#');
+        result.push_str(fragment);
+    }
+    result
+}
+
+fn is_scheme(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+        }
+        _ => false,
+    }
+}
+
+/// Remove the `.` and `..` segments from a path (RFC 3986, section 5.2.4).
+fn remove_dot_segments(path: &str) -> String {
+    let mut input = path;
+    let mut output: Vec<&str> = Vec::new();
+    while !input.is_empty() {
+        if let Some(rest) = input.strip_prefix("../") {
+            input = rest;
+        } else if let Some(rest) = input.strip_prefix("./") {
+            input = rest;
+        } else if input.starts_with("/./") {
+            input = &input[2..];
+        } else if input == "/." {
+            input = "/";
+        } else if input.starts_with("/../") || input == "/.." {
+            input = if input == "/.." { "/" } else { &input[3..] };
+            output.pop();
+        } else if input == "." || input == ".." {
+            input = "";
+        } else {
+            let end = input
+                .char_indices()
+                .skip(1)
+                .find(|&(_, c)| c == '/')
+                .map_or(input.len(), |(i, _)| i);
+            output.push(&input[..end]);
+            input = &input[end..];
+        }
+    }
+    output.concat()
+}
+
+/// Remove the `.` and `..` segments from a relative path, keeping the `..` segments that
+/// would climb above its start.
+fn remove_dot_segments_relative(path: &str) -> String {
+    let mut output: Vec<&str> = Vec::new();
+    let segments: Vec<&str> = path.split('/').collect();
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i == segments.len() - 1;
+        match *segment {
+            "." if last => output.push(""),
+            "." => {}
+            ".." if output.last().is_some_and(|s| *s != "..") => {
+                output.pop();
+                if last {
+                    output.push("");
+                }
+            }
+            segment => output.push(segment),
+        }
+    }
+    output.join("/")
+}
+
+/// Return whether a URI reference is an absolute URI, i.e. it has a scheme.
+pub fn is_absolute(reference: &str) -> bool {
+    Components::parse(reference).scheme.is_some()
+}
+
+/// Return the scheme of a URI reference, if it is an absolute URI.
+pub fn scheme(reference: &str) -> Option<&str> {
+    Components::parse(reference).scheme
+}
+
+/// Resolve a URI reference against a base URI (RFC 3986, section 5.2).
+///
+/// If the base URI is itself relative, the result is relative too, but still has the dot
+/// segments of the reference resolved against those of the base, which is what is needed to
+/// combine nested relative `xml:base` attributes.
+pub fn resolve(base: &str, reference: &str) -> String {
+    let r = Components::parse(reference);
+    if r.scheme.is_some() {
+        return recompose(
+            r.scheme,
+            r.authority,
+            &remove_dot_segments(r.path),
+            r.query,
+            r.fragment,
+        );
+    }
+    let b = Components::parse(base);
+    if r.authority.is_some() {
+        return recompose(
+            b.scheme,
+            r.authority,
+            &remove_dot_segments(r.path),
+            r.query,
+            r.fragment,
+        );
+    }
+    if r.path.is_empty() {
+        return recompose(
+            b.scheme,
+            b.authority,
+            b.path,
+            r.query.or(b.query),
+            r.fragment,
+        );
+    }
+    let path = if r.path.starts_with('/') {
+        remove_dot_segments(r.path)
+    } else if b.authority.is_some() && b.path.is_empty() {
+        remove_dot_segments(&format!("/{}", r.path))
+    } else {
+        let directory = b.path.rfind('/').map_or("", |i| &b.path[..=i]);
+        let merged = format!("{}{}", directory, r.path);
+        if b.scheme.is_none() && b.authority.is_none() && !merged.starts_with('/') {
+            remove_dot_segments_relative(&merged)
+        } else {
+            remove_dot_segments(&merged)
+        }
+    };
+    recompose(b.scheme, b.authority, &path, r.query, r.fragment)
+}
+
+/// Return a URI reference without its fragment identifier, and the fragment identifier.
+pub fn split_fragment(reference: &str) -> (&str, Option<&str>) {
+    match reference.find('#') {
+        Some(i) => (&reference[..i], Some(&reference[i + 1..])),
+        None => (reference, None),
+    }
+}
+
+/// Decode the `%XX` escapes of a URI component.
+///
+/// Returns `None` if an escape is malformed or the decoded bytes are not UTF-8.
+pub fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Return the file system path of a `file:` URI, or of a relative URI reference taken as a
+/// path relative to the current directory.
+///
+/// Returns `None` for URIs of other schemes, for `file:` URIs naming a host other than
+/// `localhost`, and for malformed escapes.
+pub fn to_file_path(uri: &str) -> Option<PathBuf> {
+    let c = Components::parse(uri);
+    match c.scheme {
+        Some(scheme) if scheme.eq_ignore_ascii_case("file") => match c.authority {
+            None | Some("") | Some("localhost") => {}
+            Some(_) => return None,
+        },
+        Some(_) => return None,
+        None => {}
+    }
+    percent_decode(c.path).map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "http://a/b/c/d;p?q";
+
+    #[test]
+    fn resolves_normal_examples() {
+        // RFC 3986, section 5.4.1
+        let examples = [
+            ("g:h", "g:h"),
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q#s"),
+            ("g#s", "http://a/b/c/g#s"),
+            ("g?y#s", "http://a/b/c/g?y#s"),
+            (";x", "http://a/b/c/;x"),
+            ("g;x", "http://a/b/c/g;x"),
+            ("g;x?y#s", "http://a/b/c/g;x?y#s"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("./", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../", "http://a/"),
+            ("../../g", "http://a/g"),
+        ];
+        for &(reference, expected) in &examples {
+            assert_eq!(resolve(BASE, reference), expected, "{}", reference);
+        }
+    }
+
+    #[test]
+    fn resolves_abnormal_examples() {
+        // RFC 3986, section 5.4.2
+        let examples = [
+            ("../../../g", "http://a/g"),
+            ("../../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("/../g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            (".g", "http://a/b/c/.g"),
+            ("g..", "http://a/b/c/g.."),
+            ("..g", "http://a/b/c/..g"),
+            ("./../g", "http://a/b/g"),
+            ("./g/.", "http://a/b/c/g/"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+            ("g;x=1/./y", "http://a/b/c/g;x=1/y"),
+            ("g;x=1/../y", "http://a/b/c/y"),
+            ("g?y/./x", "http://a/b/c/g?y/./x"),
+            ("g?y/../x", "http://a/b/c/g?y/../x"),
+            ("g#s/./x", "http://a/b/c/g#s/./x"),
+            ("g#s/../x", "http://a/b/c/g#s/../x"),
+            ("http:g", "http:g"),
+        ];
+        for &(reference, expected) in &examples {
+            assert_eq!(resolve(BASE, reference), expected, "{}", reference);
+        }
+    }
+
+    #[test]
+    fn resolves_non_ascii_references() {
+        assert_eq!(resolve("urn:x", "\u{e9}.dtd"), "urn:\u{e9}.dtd");
+        assert_eq!(resolve("urn:a/b", "\u{e9}/../c.dtd"), "urn:a/c.dtd");
+        assert_eq!(resolve("", "urn:\u{e9}t\u{e9}/./a"), "urn:\u{e9}t\u{e9}/a");
+        assert_eq!(
+            resolve(
+                "http://h/r\u{e9}pertoire/doc.xml",
+                "\u{fc}ber/n\u{e4}chste.dtd"
+            ),
+            "http://h/r\u{e9}pertoire/\u{fc}ber/n\u{e4}chste.dtd"
+        );
+        assert_eq!(
+            resolve("file:///d/", "\u{65e5}\u{672c}/../x"),
+            "file:///d/x"
+        );
+    }
+
+    #[test]
+    fn resolves_relative_bases() {
+        assert_eq!(resolve("a/b/", "../c"), "a/c");
+        assert_eq!(resolve("a/", "../../c"), "../c");
+        assert_eq!(resolve("", "x/y"), "x/y");
+    }
+
+    #[test]
+    fn splits_components() {
+        assert!(is_absolute("urn:x"));
+        assert!(!is_absolute("a/b:c"));
+        assert_eq!(scheme("HTTP://h/"), Some("HTTP"));
+        assert_eq!(scheme("/p"), None);
+        assert_eq!(split_fragment("a#b#c"), ("a", Some("b#c")));
+        assert_eq!(split_fragment("a"), ("a", None));
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(percent_decode("a%20b%C3%A9").as_deref(), Some("a b\u{e9}"));
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%FF"), None);
+        assert_eq!(percent_decode("%\u{e9}x"), None);
+    }
+
+    #[test]
+    fn maps_file_uris() {
+        assert_eq!(
+            to_file_path("file:///tmp/a%20b"),
+            Some(PathBuf::from("/tmp/a b"))
+        );
+        assert_eq!(
+            to_file_path("file://localhost/x"),
+            Some(PathBuf::from("/x"))
+        );
+        assert_eq!(to_file_path("file://remote/x"), None);
+        assert_eq!(to_file_path("http://h/x"), None);
+        assert_eq!(to_file_path("rel/x"), Some(PathBuf::from("rel/x")));
+    }
+}
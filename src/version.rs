@@ -0,0 +1,227 @@
+//! The differences between XML 1.0 and XML 1.1 that affect readers.
+//!
+//! XML 1.1 admits control characters that XML 1.0 forbids, provided that those of the C0 and C1
+//! ranges appear as character references, and recognizes NEL (U+0085) and LINE SEPARATOR
+//! (U+2028) as line ends, for the sake of mainframe and Unicode text. Its name characters are
+//! those that XML 1.0 Fifth Edition adopted, so `names` applies to both versions. Namespaces in
+//! XML 1.1 additionally allows prefixes to be undeclared, which `NamespaceFilter` supports.
+//!
+//! A reader parsing XML 1.1 advertises it with the http://xml.org/sax/features/xml-1.1 feature,
+//! and reports the version of each entity through `ext::Locator2::get_xml_version`. Entities
+//! without an XML or text declaration are XML 1.0; an external entity declared as XML 1.1 may
+//! not be referenced from an XML 1.0 document.
+use std::fmt;
+
+use names;
+
+/// A version of XML.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum XmlVersion {
+    /// XML 1.0, the version of entities without a declaration.
+    #[default]
+    V1_0,
+    /// XML 1.1.
+    V1_1,
+}
+
+impl XmlVersion {
+    /// Return the version given in an XML or text declaration, or `None` if it does not match
+    /// the `VersionNum` production.
+    ///
+    /// Versions "1.2" and later are processed as XML 1.0, as XML 1.0 Fifth Edition requires.
+    pub fn from_version_num(version: &str) -> Option<Self> {
+        match version.strip_prefix("1.") {
+            Some("1") => Some(XmlVersion::V1_1),
+            Some(minor) if !minor.is_empty() && minor.bytes().all(|b| b.is_ascii_digit()) => {
+                Some(XmlVersion::V1_0)
+            }
+            _ => None,
+        }
+    }
+
+    /// Return the version number, as reported by `ext::Locator2::get_xml_version`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            XmlVersion::V1_0 => "1.0",
+            XmlVersion::V1_1 => "1.1",
+        }
+    }
+
+    /// Return whether `c` matches the `Char` production, i.e. may be part of a document,
+    /// literally or as a character reference.
+    pub fn is_char(self, c: char) -> bool {
+        match self {
+            XmlVersion::V1_0 => names::is_char(c),
+            XmlVersion::V1_1 => names::is_char_1_1(c),
+        }
+    }
+
+    /// Return whether `c` may appear literally in a document, as opposed to as a character
+    /// reference.
+    pub fn is_literal_char(self, c: char) -> bool {
+        match self {
+            XmlVersion::V1_0 => names::is_char(c),
+            XmlVersion::V1_1 => names::is_char_1_1(c) && !names::is_restricted_char(c),
+        }
+    }
+
+    /// Return the first character of literal text that may not appear literally, if any.
+    ///
+    /// The text is expected to have gone through line-end normalization.
+    pub fn find_invalid_char(self, text: &str) -> Option<char> {
+        text.chars().find(|&c| !self.is_literal_char(c))
+    }
+
+    /// Return whether `c` is a line end of its own, besides carriage return and line feed.
+    pub fn is_line_end(self, c: char) -> bool {
+        self == XmlVersion::V1_1 && (c == '\u{85}' || c == '\u{2028}')
+    }
+
+    /// Normalize the line ends of text read from an entity, as specified in section 2.11 of
+    /// the recommendation: every carriage return followed by a line feed, every carriage
+    /// return not followed by one, and, in XML 1.1, every NEL, carriage return followed by
+    /// NEL, and LINE SEPARATOR, is translated to a single line feed.
+    ///
+    /// Text read in chunks should go through a `LineEndNormalizer` instead, which handles line
+    /// ends split across chunks.
+    pub fn normalize_line_ends(self, text: &str) -> String {
+        LineEndNormalizer::new(self).push(text)
+    }
+}
+
+impl fmt::Display for XmlVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Normalizes the line ends of text read in chunks, as `XmlVersion::normalize_line_ends`
+/// does.
+///
+/// The version can be changed with `set_version` once the XML declaration has been read: the
+/// XML 1.1 line ends may not appear within it, since they are not recognized before the
+/// version is known.
+#[derive(Clone, Debug)]
+pub struct LineEndNormalizer {
+    version: XmlVersion,
+    /// Whether the last chunk ended with a carriage return, which has already been translated.
+    after_cr: bool,
+}
+
+impl LineEndNormalizer {
+    /// Construct a normalizer for the given version.
+    pub fn new(version: XmlVersion) -> Self {
+        LineEndNormalizer {
+            version,
+            after_cr: false,
+        }
+    }
+
+    /// Return the version whose line ends are recognized.
+    pub fn get_version(&self) -> XmlVersion {
+        self.version
+    }
+
+    /// Set the version whose line ends are recognized.
+    pub fn set_version(&mut self, version: XmlVersion) {
+        self.version = version;
+    }
+
+    /// Normalize the next chunk of text.
+    pub fn push(&mut self, chunk: &str) -> String {
+        let mut normalized = String::with_capacity(chunk.len());
+        for c in chunk.chars() {
+            let after_cr = self.after_cr;
+            self.after_cr = c == '\r';
+            match c {
+                '\r' => normalized.push('\n'),
+                '\n' if after_cr => {}
+                '\u{85}' if after_cr && self.version == XmlVersion::V1_1 => {}
+                c if self.version.is_line_end(c) => normalized.push('\n'),
+                c => normalized.push(c),
+            }
+        }
+        normalized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_numbers() {
+        assert_eq!(XmlVersion::from_version_num("1.0"), Some(XmlVersion::V1_0));
+        assert_eq!(XmlVersion::from_version_num("1.1"), Some(XmlVersion::V1_1));
+        assert_eq!(XmlVersion::from_version_num("1.2"), Some(XmlVersion::V1_0));
+        assert_eq!(XmlVersion::from_version_num("1.10"), Some(XmlVersion::V1_0));
+        for version in ["", "1", "1.", "2.0", "1.x", "1.1 ", " 1.0", "1.-1"] {
+            assert_eq!(XmlVersion::from_version_num(version), None, "{:?}", version);
+        }
+    }
+
+    #[test]
+    fn restricted_chars() {
+        for c in [
+            '\u{1}', '\u{8}', '\u{B}', '\u{C}', '\u{1F}', '\u{7F}', '\u{84}', '\u{9F}',
+        ] {
+            assert!(names::is_restricted_char(c), "{:?}", c);
+            assert!(XmlVersion::V1_1.is_char(c), "{:?}", c);
+            assert!(!XmlVersion::V1_1.is_literal_char(c), "{:?}", c);
+            assert_eq!(XmlVersion::V1_0.is_char(c), c >= '\u{7F}', "{:?}", c);
+        }
+        for c in ['\t', '\n', '\r', ' ', '\u{85}', '\u{A0}', '\u{2028}'] {
+            assert!(!names::is_restricted_char(c), "{:?}", c);
+            assert!(XmlVersion::V1_1.is_literal_char(c), "{:?}", c);
+        }
+        assert!(!names::is_restricted_char('\u{0}'));
+        assert!(!XmlVersion::V1_1.is_char('\u{0}'));
+        assert_eq!(
+            XmlVersion::V1_1.find_invalid_char("a\u{85}b\u{1}c"),
+            Some('\u{1}')
+        );
+        assert_eq!(XmlVersion::V1_0.find_invalid_char("a\u{85}b\u{7F}c"), None);
+        assert_eq!(XmlVersion::V1_0.find_invalid_char("a\u{1}"), Some('\u{1}'));
+    }
+
+    #[test]
+    fn line_ends() {
+        let text = "a\rb\r\nc\nd\u{85}e\r\u{85}f\u{2028}g";
+        assert_eq!(
+            XmlVersion::V1_0.normalize_line_ends(text),
+            "a\nb\nc\nd\u{85}e\n\u{85}f\u{2028}g"
+        );
+        assert_eq!(
+            XmlVersion::V1_1.normalize_line_ends(text),
+            "a\nb\nc\nd\ne\nf\ng"
+        );
+        assert_eq!(XmlVersion::V1_0.normalize_line_ends("\r\r\n\n"), "\n\n\n");
+    }
+
+    #[test]
+    fn line_ends_split_across_chunks() {
+        let mut normalizer = LineEndNormalizer::new(XmlVersion::V1_0);
+        assert_eq!(normalizer.push("a\r"), "a\n");
+        assert_eq!(normalizer.push("\nb\r"), "b\n");
+        assert_eq!(normalizer.push("\r"), "\n");
+        assert_eq!(normalizer.push("c"), "c");
+        assert_eq!(normalizer.push("\n"), "\n");
+
+        let mut normalizer = LineEndNormalizer::new(XmlVersion::V1_1);
+        assert_eq!(normalizer.push("a\r"), "a\n");
+        assert_eq!(normalizer.push("\u{85}b\r"), "b\n");
+        assert_eq!(normalizer.push("\u{2028}"), "\n");
+    }
+
+    #[test]
+    fn version_set_after_the_declaration() {
+        let mut normalizer = LineEndNormalizer::new(XmlVersion::V1_0);
+        assert_eq!(
+            normalizer.push("<?xml version='1.1'?>\u{85}"),
+            "<?xml version='1.1'?>\u{85}"
+        );
+        normalizer.set_version(XmlVersion::V1_1);
+        assert_eq!(normalizer.get_version(), XmlVersion::V1_1);
+        assert_eq!(normalizer.push("\u{85}"), "\n");
+    }
+}
@@ -0,0 +1,243 @@
+//! An embedded pack of the DTDs and schemas published by the W3C, available with the
+//! `w3c-resources` feature.
+//!
+//! Documents using XHTML 1.0 and 1.1, SVG 1.1, XML Schema or XLink reference DTDs and schemas
+//! on www.w3.org, which the W3C asks applications not to download on every parse. `W3cResolver`
+//! serves them from the copies embedded in the crate instead, so that such documents can be
+//! validated without network access.
+//!
+//! The pack holds the files found beneath `resources/w3c` when the crate is built, laid out as
+//! described by `LOCATIONS`; `resources/w3c/README.md` lists them with their origin.
+use std::io::Cursor;
+use std::io::Read;
+
+use catalog;
+use common::EntityResolver;
+use ext::EntityResolver2;
+use sax2::Result;
+use uri;
+
+/// The embedded files, by path relative to `resources/w3c`.
+static RESOURCES: &[(&str, &[u8])] = include!(concat!(env!("OUT_DIR"), "/w3c_resources.rs"));
+
+/// The locations on www.w3.org of the embedded files, with the directory of the pack that
+/// holds the files published beneath each of them.
+pub const LOCATIONS: &[(&str, &str)] = &[
+    ("http://www.w3.org/TR/xhtml1/DTD/", "xhtml1/"),
+    ("http://www.w3.org/TR/xhtml11/DTD/", "xhtml11/"),
+    ("http://www.w3.org/MarkUp/DTD/", "xhtml-modularization/"),
+    ("http://www.w3.org/Graphics/SVG/1.1/DTD/", "svg11/"),
+    ("http://www.w3.org/2001/", "xsd/"),
+    ("http://www.w3.org/XML/2008/06/", "xlink/"),
+];
+
+/// The public identifiers of the embedded DTDs and entity sets, with their system
+/// identifiers.
+pub const PUBLIC_IDS: &[(&str, &str)] = &[
+    (
+        "-//W3C//DTD XHTML 1.0 Strict//EN",
+        "http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd",
+    ),
+    (
+        "-//W3C//DTD XHTML 1.0 Transitional//EN",
+        "http://www.w3.org/TR/xhtml1/DTD/xhtml1-transitional.dtd",
+    ),
+    (
+        "-//W3C//DTD XHTML 1.0 Frameset//EN",
+        "http://www.w3.org/TR/xhtml1/DTD/xhtml1-frameset.dtd",
+    ),
+    (
+        "-//W3C//ENTITIES Latin 1 for XHTML//EN",
+        "http://www.w3.org/TR/xhtml1/DTD/xhtml-lat1.ent",
+    ),
+    (
+        "-//W3C//ENTITIES Symbols for XHTML//EN",
+        "http://www.w3.org/TR/xhtml1/DTD/xhtml-symbol.ent",
+    ),
+    (
+        "-//W3C//ENTITIES Special for XHTML//EN",
+        "http://www.w3.org/TR/xhtml1/DTD/xhtml-special.ent",
+    ),
+    (
+        "-//W3C//DTD XHTML 1.1//EN",
+        "http://www.w3.org/TR/xhtml11/DTD/xhtml11.dtd",
+    ),
+    (
+        "-//W3C//DTD SVG 1.1//EN",
+        "http://www.w3.org/Graphics/SVG/1.1/DTD/svg11.dtd",
+    ),
+    (
+        "-//W3C//DTD SVG 1.1 Basic//EN",
+        "http://www.w3.org/Graphics/SVG/1.1/DTD/svg11-basic.dtd",
+    ),
+    (
+        "-//W3C//DTD SVG 1.1 Tiny//EN",
+        "http://www.w3.org/Graphics/SVG/1.1/DTD/svg11-tiny.dtd",
+    ),
+    (
+        "-//W3C//DTD XMLSCHEMA 200102//EN",
+        "http://www.w3.org/2001/XMLSchema.dtd",
+    ),
+];
+
+/// Return the embedded file with the given path relative to `resources/w3c`.
+pub fn get(name: &str) -> Option<&'static [u8]> {
+    RESOURCES
+        .iter()
+        .find(|&&(n, _)| n == name)
+        .map(|&(_, content)| content)
+}
+
+/// Return the paths of the embedded files.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    RESOURCES.iter().map(|&(name, _)| name)
+}
+
+/// Return the path in the pack of the file with the given URI, whether or not it is embedded.
+/// The `https` scheme is taken as `http`.
+pub fn locate(uri: &str) -> Option<String> {
+    let (uri, _) = uri::split_fragment(uri);
+    let uri = match uri.strip_prefix("https:") {
+        Some(rest) => format!("http:{}", rest),
+        None => uri.to_owned(),
+    };
+    LOCATIONS.iter().find_map(|&(location, directory)| {
+        uri.strip_prefix(location)
+            .filter(|rest| !rest.is_empty() && !rest.split('/').any(|s| s == ".."))
+            .map(|rest| format!("{}{}", directory, rest))
+    })
+}
+
+/// An entity resolver serving the embedded W3C resources.
+///
+/// An entity is looked up by its public identifier, if it is one of `PUBLIC_IDS`, and
+/// otherwise by its system identifier, resolved against the base URI given to
+/// `EntityResolver2::resolve_entity_ext`, if any. Entities that are not embedded are declined,
+/// so that the resolver can be chained with others.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct W3cResolver;
+
+impl W3cResolver {
+    /// Construct a resolver serving the embedded resources.
+    pub fn new() -> Self {
+        W3cResolver
+    }
+
+    fn open(&self, public_id: Option<&str>, system_id: &str) -> Option<Box<dyn Read>> {
+        let by_public_id = public_id.and_then(|public_id| {
+            let public_id = catalog::normalize_public_id(public_id);
+            PUBLIC_IDS
+                .iter()
+                .find(|&&(p, _)| p == public_id)
+                .map(|&(_, system_id)| system_id)
+        });
+        by_public_id
+            .into_iter()
+            .chain(Some(system_id))
+            .filter_map(locate)
+            .find_map(|name| get(&name))
+            .map(|content| Box::new(Cursor::new(content)) as Box<dyn Read>)
+    }
+}
+
+impl EntityResolver for W3cResolver {
+    fn resolve_entity(
+        &self,
+        public_id: Option<&str>,
+        system_id: &str,
+    ) -> Result<Option<Box<dyn Read>>> {
+        Ok(self.open(public_id, system_id))
+    }
+}
+
+impl EntityResolver2 for W3cResolver {
+    fn resolve_entity_ext(
+        &self,
+        _name: Option<&str>,
+        public_id: Option<&str>,
+        base_uri: Option<&str>,
+        system_id: &str,
+    ) -> Result<Option<Box<dyn Read>>> {
+        let absolute = match base_uri {
+            Some(base_uri) => uri::resolve(base_uri, system_id),
+            None => system_id.to_owned(),
+        };
+        Ok(self.open(public_id, &absolute))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The schemas that are referenced by location rather than by public identifier.
+    const SCHEMAS: &[&str] = &[
+        "http://www.w3.org/2001/xml.xsd",
+        "http://www.w3.org/2001/XMLSchema.xsd",
+        "http://www.w3.org/XML/2008/06/xlink.xsd",
+    ];
+
+    #[test]
+    fn locates_files_in_the_pack() {
+        assert_eq!(
+            locate("http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd").as_deref(),
+            Some("xhtml1/xhtml1-strict.dtd")
+        );
+        assert_eq!(
+            locate("https://www.w3.org/2001/xml.xsd#lang").as_deref(),
+            Some("xsd/xml.xsd")
+        );
+        assert_eq!(
+            locate("http://www.w3.org/MarkUp/DTD/xhtml-base-1.mod").as_deref(),
+            Some("xhtml-modularization/xhtml-base-1.mod")
+        );
+        assert_eq!(locate("http://www.w3.org/2001/"), None);
+        assert_eq!(locate("http://www.w3.org/2001/../etc/passwd"), None);
+        assert_eq!(locate("http://example.org/2001/xml.xsd"), None);
+        for &(_, system_id) in PUBLIC_IDS {
+            assert!(locate(system_id).is_some(), "{}", system_id);
+        }
+    }
+
+    #[test]
+    fn declines_what_is_not_embedded() {
+        let resolver = W3cResolver::new();
+        assert!(resolver
+            .resolve_entity(None, "http://example.org/doc.dtd")
+            .unwrap()
+            .is_none());
+        assert!(resolver
+            .resolve_entity_ext(None, None, Some("http://example.org/"), "doc.dtd")
+            .unwrap()
+            .is_none());
+        assert!(get("xsd/missing.xsd").is_none());
+        for name in names() {
+            assert!(get(name).is_some(), "{}", name);
+        }
+    }
+
+    /// Every resource the pack is meant to hold must be embedded, and served both by public
+    /// identifier and by system identifier.
+    #[test]
+    fn serves_the_whole_pack() {
+        let resolver = W3cResolver::new();
+        let read = |input: Option<Box<dyn Read>>| {
+            let mut content = Vec::new();
+            input.expect("embedded").read_to_end(&mut content).unwrap();
+            content
+        };
+        for &(public_id, system_id) in PUBLIC_IDS {
+            let by_public_id = resolver
+                .resolve_entity(Some(public_id), "unknown.dtd")
+                .unwrap();
+            assert!(by_public_id.is_some(), "{} is not embedded", public_id);
+            let by_system_id = resolver.resolve_entity(None, system_id).unwrap();
+            assert_eq!(read(by_public_id), read(by_system_id));
+        }
+        for system_id in SCHEMAS {
+            let input = resolver.resolve_entity(None, system_id).unwrap();
+            assert!(input.is_some(), "{} is not embedded", system_id);
+            assert!(read(input).starts_with(b"<?xml"));
+        }
+    }
+}
@@ -0,0 +1,1542 @@
+//! XInclude 1.0 processing.
+//!
+//! `XIncludeFilter` sits between a reader and the application's content handler, and replaces
+//! each `xi:include` element in the event stream with the resource it refers to: the events of
+//! an included XML document, or of the part of it identified by an XPointer, or the characters
+//! of an included text resource. If a resource cannot be included, the contents of the
+//! `xi:fallback` child of the `xi:include` element are used instead.
+//!
+//! Resources are located through an `EntityResolver`, which is offered the absolute URI of each
+//! resource as its system identifier; if it declines, `file:` URIs are opened from the file
+//! system. Included XML resources are parsed by a `ResourceParser`, and may themselves contain
+//! `xi:include` elements, which are processed in turn. An inclusion that would include itself,
+//! directly or indirectly, is a fatal error.
+//!
+//! Shorthand pointers and the `element()` XPointer scheme are supported; pointer parts of other
+//! schemes are skipped. Elements are identified by `xml:id` attributes and by attributes that
+//! the parser reports with type `ID`. Included elements receive `xml:base` and `xml:lang`
+//! attributes where needed to keep their base URI and language (base URI and language fixup).
+//!
+//! Since the including document is processed as a stream, an `xi:include` element that refers
+//! to the document it appears in (with an empty or missing `href`) cannot be processed, and is
+//! treated as a resource error.
+use std::cell::RefCell;
+use std::io::Read;
+use std::rc::Rc;
+
+use common;
+use common::EntityResolver;
+use common::Locator;
+use event::Event;
+use event::EventRecorder;
+use helpers::AttributesImpl;
+use helpers::NamespaceSupport;
+use helpers::ParseErrorImpl;
+use helpers::XMLNS;
+use names;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::Result;
+use uri;
+
+/// The XInclude Namespace URI.
+pub const XINCLUDE_NS: &str = "http://www.w3.org/2001/XInclude";
+
+/// Parses the XML resources included by an `XIncludeFilter`.
+///
+/// The resource read from `input`, whose system identifier is `system_id`, is parsed as a
+/// complete document, and its events are reported to `handler` as a Namespace-aware reader
+/// reports them to its content handler. Since readers may not be used for a nested parse, a
+/// new reader is normally used for each resource.
+///
+/// Closures with the signature of `parse_resource` implement this trait.
+pub trait ResourceParser<L: Locator> {
+    /// Parse an XML resource.
+    fn parse_resource(
+        &self,
+        input: &mut dyn Read,
+        system_id: &str,
+        handler: &dyn ContentHandler<L, AttributesImpl>,
+    ) -> Result<()>;
+}
+
+impl<L, F> ResourceParser<L> for F
+where
+    L: Locator,
+    F: Fn(&mut dyn Read, &str, &dyn ContentHandler<L, AttributesImpl>) -> Result<()>,
+{
+    fn parse_resource(
+        &self,
+        input: &mut dyn Read,
+        system_id: &str,
+        handler: &dyn ContentHandler<L, AttributesImpl>,
+    ) -> Result<()> {
+        self(input, system_id, handler)
+    }
+}
+
+/// A content handler that performs XInclude processing on the events passing through it.
+///
+/// Register the filter as the content handler of a Namespace-aware reader. Events are
+/// forwarded to the downstream content handler, except that `xi:include` elements are
+/// replaced by the resources they include, and `xi:fallback` elements by their contents if the
+/// inclusion failed.
+///
+/// Relative `href` attributes are resolved against the base URI of the `xi:include` element,
+/// which takes `xml:base` attributes into account. The base URI of the document is the one set
+/// with `set_base_uri`, or else the system identifier reported by the locator.
+///
+/// Violations of the XInclude recommendation, and resource errors for `xi:include` elements
+/// without a fallback, are fatal errors returned from the callback in which they are detected.
+pub struct XIncludeFilter<L: Locator, CH, ER, P> {
+    handler: CH,
+    resolver: ER,
+    parser: P,
+    base_uri: RefCell<Option<String>>,
+    processor: Processor<L>,
+}
+
+impl<L, CH, ER, P> XIncludeFilter<L, CH, ER, P>
+where
+    L: Locator,
+    CH: ContentHandler<L, AttributesImpl>,
+    ER: EntityResolver,
+    P: ResourceParser<L>,
+{
+    /// Construct a filter forwarding to `handler`, locating resources with `resolver` and
+    /// parsing the included XML resources with `parser`.
+    pub fn new(handler: CH, resolver: ER, parser: P) -> Self {
+        XIncludeFilter {
+            handler,
+            resolver,
+            parser,
+            base_uri: RefCell::new(None),
+            processor: Processor::new(String::new(), None, None, Vec::new()),
+        }
+    }
+
+    /// Return the downstream content handler.
+    pub fn get_content_handler(&self) -> &CH {
+        &self.handler
+    }
+
+    /// Return the entity resolver used to locate resources.
+    pub fn get_entity_resolver(&self) -> &ER {
+        &self.resolver
+    }
+
+    /// Set the base URI of the documents to be processed, in place of the system identifier
+    /// reported by the locator.
+    pub fn set_base_uri(&self, base_uri: &str) {
+        *self.base_uri.borrow_mut() = Some(base_uri.to_owned());
+    }
+
+    fn services(&self) -> Services<'_, L> {
+        Services {
+            resolver: &self.resolver,
+            parser: &self.parser,
+        }
+    }
+}
+
+impl<L, A, CH, ER, P> ContentHandler<L, A> for XIncludeFilter<L, CH, ER, P>
+where
+    L: Locator,
+    A: Attributes,
+    CH: ContentHandler<L, AttributesImpl>,
+    ER: EntityResolver,
+    P: ResourceParser<L>,
+{
+    fn characters(&self, content: &str) -> Result<()> {
+        self.processor.characters(&self.handler, content)
+    }
+    fn end_document(&self) -> Result<()> {
+        self.handler.end_document()
+    }
+    fn end_element(&self, uri: &str, local_name: &str, q_name: &str) -> Result<()> {
+        self.processor
+            .end_element(&self.handler, uri, local_name, q_name)
+    }
+    fn end_prefix_mapping(&self, _prefix: &str) -> Result<()> {
+        // Mappings are forwarded by the processor along with the elements they belong to.
+        Ok(())
+    }
+    fn ignorable_whitespace(&self, content: &str) -> Result<()> {
+        self.processor.ignorable_whitespace(&self.handler, content)
+    }
+    fn processing_instruction(&self, target: &str, data: &str) -> Result<()> {
+        self.processor
+            .processing_instruction(&self.handler, target, data)
+    }
+    fn set_document_locator(&self, locator: Rc<L>) {
+        self.processor.set_document_locator(locator.clone());
+        self.handler.set_document_locator(locator);
+    }
+    fn skipped_entity(&self, name: &str) -> Result<()> {
+        self.processor.skipped_entity(&self.handler, name)
+    }
+    fn start_document(&self) -> Result<()> {
+        let base_uri = match *self.base_uri.borrow() {
+            Some(ref base_uri) => base_uri.clone(),
+            None => self.processor.locator_system_id().unwrap_or_default(),
+        };
+        self.processor.reset(base_uri);
+        self.handler.start_document()
+    }
+    fn start_element(
+        &self,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attributes: A,
+    ) -> Result<()> {
+        self.processor.start_element(
+            self.services(),
+            &self.handler,
+            uri,
+            local_name,
+            q_name,
+            AttributesImpl::from_attributes(&attributes),
+        )
+    }
+    fn start_prefix_mapping(&self, prefix: &str, uri: &str) -> Result<()> {
+        self.processor.start_prefix_mapping(prefix, uri);
+        Ok(())
+    }
+}
+
+/// The means to locate and parse resources, shared by the processors of all included
+/// documents.
+struct Services<'s, L: Locator> {
+    resolver: &'s dyn EntityResolver,
+    parser: &'s dyn ResourceParser<L>,
+}
+
+impl<'s, L: Locator> Clone for Services<'s, L> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'s, L: Locator> Copy for Services<'s, L> {}
+
+/// What happens to the content of an open element.
+enum FrameKind {
+    /// The element and its content are forwarded.
+    Copied,
+    /// An `xi:include` element. `resource_error` describes why the inclusion failed, if it did.
+    Include {
+        resource_error: Option<String>,
+        fallback: bool,
+    },
+    /// An `xi:fallback` element whose content replaces a failed inclusion.
+    Fallback,
+    /// The element and its content are dropped.
+    Skipped,
+}
+
+struct Frame {
+    kind: FrameKind,
+    base: String,
+    lang: Option<String>,
+    /// The prefixes mapped downstream for a copied element, to be unmapped after its end tag.
+    prefixes: Vec<String>,
+}
+
+/// An `element()` pointer part, or a shorthand pointer: the element reached by following the
+/// child sequence `steps` from the element with the ID `id`, or from the document.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ElementPointer {
+    id: Option<String>,
+    steps: Vec<u64>,
+}
+
+/// Parse the value of an `xpointer` attribute into its `element()` parts. Parts of other
+/// schemes are skipped.
+fn parse_pointer(pointer: &str) -> std::result::Result<Vec<ElementPointer>, String> {
+    if names::is_ncname(pointer) {
+        return Ok(vec![ElementPointer {
+            id: Some(pointer.to_owned()),
+            steps: Vec::new(),
+        }]);
+    }
+    let invalid = || format!("invalid XPointer \"{}\"", pointer);
+    let mut parts = Vec::new();
+    let mut rest = pointer.trim_start_matches(names::is_whitespace);
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let open = rest.find('(').ok_or_else(invalid)?;
+        let scheme = &rest[..open];
+        if !names::is_qname(scheme) {
+            return Err(invalid());
+        }
+        // The scheme data extends to the matching parenthesis; "^" escapes "(", ")" and "^".
+        let mut data = String::new();
+        let mut depth = 0;
+        let mut chars = rest[open + 1..].char_indices();
+        let end = loop {
+            match chars.next() {
+                Some((_, '^')) => match chars.next() {
+                    Some((_, c)) if c == '(' || c == ')' || c == '^' => data.push(c),
+                    _ => return Err(invalid()),
+                },
+                Some((_, '(')) => {
+                    depth += 1;
+                    data.push('(');
+                }
+                Some((i, ')')) if depth == 0 => break open + 1 + i,
+                Some((_, c)) => {
+                    if c == ')' {
+                        depth -= 1;
+                    }
+                    data.push(c);
+                }
+                None => return Err(invalid()),
+            }
+        };
+        if scheme == "element" {
+            parts.push(parse_element_scheme(&data).ok_or_else(invalid)?);
+        }
+        rest = rest[end + 1..].trim_start_matches(names::is_whitespace);
+    }
+    Ok(parts)
+}
+
+/// Parse the data of an `element()` pointer part.
+fn parse_element_scheme(data: &str) -> Option<ElementPointer> {
+    let mut segments = data.split('/');
+    let id = match segments.next()? {
+        "" => None,
+        id if names::is_ncname(id) => Some(id.to_owned()),
+        _ => return None,
+    };
+    let steps = segments
+        .map(|step| match step.parse::<u64>() {
+            Ok(n) if n > 0 && step.chars().all(|c| c.is_ascii_digit()) => Some(n),
+            _ => None,
+        })
+        .collect::<Option<Vec<u64>>>()?;
+    if id.is_none() && steps.is_empty() {
+        return None;
+    }
+    Some(ElementPointer { id, steps })
+}
+
+/// Tracks the position of the elements of an included document, to find the element
+/// identified by a pointer.
+struct Selection {
+    pointer: ElementPointer,
+    /// Whether the whole document is included, rather than the identified element only.
+    whole_document: bool,
+    /// The child sequence of the current element.
+    path: Vec<u64>,
+    /// The number of child elements seen so far, for the document and each open element.
+    counts: Vec<u64>,
+    /// The depth of the element with the pointer's ID, while it is open.
+    anchor: Option<usize>,
+    anchored: bool,
+    /// The depth of the identified element, while it is open.
+    selected: Option<usize>,
+    found: bool,
+}
+
+impl Selection {
+    fn new(pointer: Option<ElementPointer>) -> Self {
+        let whole_document = pointer.is_none();
+        Selection {
+            pointer: pointer.unwrap_or(ElementPointer {
+                id: None,
+                steps: vec![1],
+            }),
+            whole_document,
+            path: Vec::new(),
+            counts: vec![0],
+            anchor: None,
+            anchored: false,
+            selected: None,
+            found: false,
+        }
+    }
+
+    /// Track the start of an element, and return whether it is the identified element.
+    fn start(&mut self, attributes: &AttributesImpl) -> bool {
+        let position = {
+            let count = self.counts.last_mut().expect("the document is counted");
+            *count += 1;
+            *count
+        };
+        self.path.push(position);
+        self.counts.push(0);
+        let depth = self.path.len();
+        if self.found {
+            return false;
+        }
+        let origin = match self.pointer.id {
+            None => Some(0),
+            Some(ref id) => {
+                if !self.anchored && has_id(attributes, id) {
+                    self.anchor = Some(depth);
+                    self.anchored = true;
+                }
+                self.anchor
+            }
+        };
+        match origin {
+            Some(origin)
+                if depth == origin + self.pointer.steps.len()
+                    && self.path[origin..] == self.pointer.steps[..] =>
+            {
+                self.selected = Some(depth);
+                self.found = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Track the end of an element.
+    fn end(&mut self) {
+        let depth = self.path.len();
+        self.path.pop();
+        self.counts.pop();
+        if self.anchor == Some(depth) {
+            self.anchor = None;
+        }
+        if self.selected == Some(depth) {
+            self.selected = None;
+        }
+    }
+
+    /// Return whether the current position is inside the included part of the document.
+    fn inside(&self) -> bool {
+        self.selected.is_some() || (self.whole_document && self.path.is_empty())
+    }
+}
+
+fn has_id(attributes: &AttributesImpl, id: &str) -> bool {
+    attributes.iter().any(|attribute| {
+        attribute.value == id
+            && (attribute.attr_type == "ID"
+                || (attribute.uri == XMLNS && attribute.local_name == "id"))
+    })
+}
+
+/// Return whether a pointer identifies an element in a recorded document.
+fn identifies_element(pointer: &ElementPointer, events: &[Event]) -> bool {
+    let mut selection = Selection::new(Some(pointer.clone()));
+    for event in events {
+        match *event {
+            Event::StartElement { ref attributes, .. } if selection.start(attributes) => {
+                return true
+            }
+            Event::EndElement { .. } => selection.end(),
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Decode a text resource, in the encoding given by the `encoding` attribute or else detected
+/// from its byte order mark.
+fn decode(bytes: &[u8], encoding: Option<&str>) -> std::result::Result<String, String> {
+    let encoding = match encoding {
+        Some(encoding) => encoding.to_ascii_uppercase(),
+        None if bytes.starts_with(&[0xFE, 0xFF]) || bytes.starts_with(&[0xFF, 0xFE]) => {
+            "UTF-16".to_owned()
+        }
+        None => "UTF-8".to_owned(),
+    };
+    let utf16 = |bytes: &[u8], big_endian: bool| {
+        let units = bytes.chunks(2).map(|pair| match *pair {
+            [a, b] if big_endian => Ok(u16::from_be_bytes([a, b])),
+            [a, b] => Ok(u16::from_le_bytes([a, b])),
+            _ => Err(()),
+        });
+        let mut text = String::new();
+        for unit in std::char::decode_utf16(
+            units
+                .collect::<std::result::Result<Vec<u16>, ()>>()
+                .map_err(|_| "truncated UTF-16 text".to_owned())?,
+        ) {
+            text.push(unit.map_err(|_| "malformed UTF-16 text".to_owned())?);
+        }
+        Ok(text)
+    };
+    match encoding.as_str() {
+        "UTF-8" => {
+            let bytes = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(bytes);
+            String::from_utf8(bytes.to_vec()).map_err(|_| "malformed UTF-8 text".to_owned())
+        }
+        "UTF-16" => match bytes {
+            [0xFF, 0xFE, rest @ ..] => utf16(rest, false),
+            [0xFE, 0xFF, rest @ ..] => utf16(rest, true),
+            _ => utf16(bytes, true),
+        },
+        "UTF-16BE" => utf16(bytes, true),
+        "UTF-16LE" => utf16(bytes, false),
+        "ISO-8859-1" | "LATIN1" => Ok(bytes.iter().map(|&b| b as char).collect()),
+        "US-ASCII" | "ASCII" if bytes.is_ascii() => Ok(bytes.iter().map(|&b| b as char).collect()),
+        "US-ASCII" | "ASCII" => Err("non-ASCII byte in US-ASCII text".to_owned()),
+        _ => Err(format!("unsupported encoding \"{}\"", encoding)),
+    }
+}
+
+/// The base URI and language of the parent of an `xi:include` element, against which the
+/// included elements are fixed up.
+struct Fixup {
+    base: String,
+    lang: Option<String>,
+}
+
+struct State<L: Locator> {
+    locator: Option<Rc<L>>,
+    document_base: String,
+    frames: Vec<Frame>,
+    namespaces: NamespaceSupport,
+    /// Prefix mappings reported for the next element.
+    pending: Vec<(String, String)>,
+    /// The resources being included, outermost first, with the pointers used for them.
+    chain: Vec<(String, Option<String>)>,
+    /// For an included document, the part of it that is included.
+    selection: Option<Selection>,
+}
+
+impl<L: Locator> State<L> {
+    fn forwarding(&self) -> bool {
+        self.selection.as_ref().is_none_or(Selection::inside)
+            && match self.frames.last() {
+                None => true,
+                Some(frame) => matches!(frame.kind, FrameKind::Copied | FrameKind::Fallback),
+            }
+    }
+
+    fn fatal_error(&self, message: &str) -> Box<dyn common::Error> {
+        Box::new(ParseErrorImpl::at(message, self.locator.as_deref()))
+    }
+}
+
+/// The XInclude processing of a single document, the including one or an included one.
+struct Processor<L: Locator> {
+    state: RefCell<State<L>>,
+    fixup: Option<Fixup>,
+}
+
+impl<L: Locator> Processor<L> {
+    fn new(
+        document_base: String,
+        selection: Option<Selection>,
+        fixup: Option<Fixup>,
+        chain: Vec<(String, Option<String>)>,
+    ) -> Self {
+        Processor {
+            state: RefCell::new(State {
+                locator: None,
+                document_base,
+                frames: Vec::new(),
+                namespaces: NamespaceSupport::new(),
+                pending: Vec::new(),
+                chain,
+                selection,
+            }),
+            fixup,
+        }
+    }
+
+    fn reset(&self, document_base: String) {
+        let mut state = self.state.borrow_mut();
+        state.frames.clear();
+        state.namespaces.reset();
+        state.pending.clear();
+        state.chain = vec![(document_base.clone(), None)];
+        state.document_base = document_base;
+    }
+
+    fn set_document_locator(&self, locator: Rc<L>) {
+        self.state.borrow_mut().locator = Some(locator);
+    }
+
+    fn locator_system_id(&self) -> Option<String> {
+        self.state
+            .borrow()
+            .locator
+            .as_ref()
+            .and_then(|locator| locator.get_system_id())
+    }
+
+    fn found(&self) -> bool {
+        self.state
+            .borrow()
+            .selection
+            .as_ref()
+            .is_some_and(|selection| selection.found)
+    }
+
+    fn start_prefix_mapping(&self, prefix: &str, uri: &str) {
+        self.state
+            .borrow_mut()
+            .pending
+            .push((prefix.to_owned(), uri.to_owned()));
+    }
+
+    fn start_element(
+        &self,
+        services: Services<L>,
+        out: &dyn ContentHandler<L, AttributesImpl>,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        mut attributes: AttributesImpl,
+    ) -> Result<()> {
+        let mut guard = self.state.borrow_mut();
+        let state = &mut *guard;
+        let (parent_base, parent_lang) = match state.frames.last() {
+            Some(frame) => (frame.base.clone(), frame.lang.clone()),
+            None => (state.document_base.clone(), None),
+        };
+        let base_attribute = attributes.get_ns_name_value(XMLNS, "base");
+        let base = match base_attribute {
+            Some(ref base) => uri::resolve(&parent_base, base),
+            None => parent_base.clone(),
+        };
+        let lang = attributes
+            .get_ns_name_value(XMLNS, "lang")
+            .or_else(|| parent_lang.clone());
+        let pending = std::mem::take(&mut state.pending);
+        state.namespaces.push_context();
+        for (prefix, ns_uri) in &pending {
+            state.namespaces.declare_prefix(prefix, ns_uri);
+        }
+        let selected_root = state
+            .selection
+            .as_mut()
+            .is_some_and(|selection| selection.start(&attributes));
+        let is_xinclude = uri == XINCLUDE_NS;
+        let kind = if selected_root {
+            FrameKind::Copied
+        } else if !state.selection.as_ref().is_none_or(Selection::inside) {
+            FrameKind::Skipped
+        } else {
+            match state.frames.last_mut().map(|frame| &mut frame.kind) {
+                Some(&mut FrameKind::Skipped) => FrameKind::Skipped,
+                Some(&mut FrameKind::Include {
+                    ref resource_error,
+                    ref mut fallback,
+                }) => match local_name {
+                    "fallback" if is_xinclude && *fallback => {
+                        return Err(
+                            state.fatal_error("xi:include has more than one xi:fallback child")
+                        );
+                    }
+                    "fallback" if is_xinclude => {
+                        *fallback = true;
+                        if resource_error.is_some() {
+                            FrameKind::Fallback
+                        } else {
+                            FrameKind::Skipped
+                        }
+                    }
+                    "include" if is_xinclude => {
+                        return Err(state.fatal_error("xi:include has an xi:include child"));
+                    }
+                    _ => FrameKind::Skipped,
+                },
+                _ => match local_name {
+                    "include" if is_xinclude => FrameKind::Include {
+                        resource_error: None,
+                        fallback: false,
+                    },
+                    "fallback" if is_xinclude => {
+                        return Err(state.fatal_error("xi:fallback is not a child of xi:include"));
+                    }
+                    _ => FrameKind::Copied,
+                },
+            }
+        };
+        let copied = matches!(kind, FrameKind::Copied);
+        let include = matches!(kind, FrameKind::Include { .. });
+        let mappings = if !copied {
+            Vec::new()
+        } else if selected_root {
+            let namespaces = &state.namespaces;
+            let mut mappings: Vec<(String, String)> = namespaces
+                .get_prefixes()
+                .into_iter()
+                .filter_map(|prefix| {
+                    let ns_uri = namespaces.get_uri(&prefix)?.to_owned();
+                    Some((prefix, ns_uri))
+                })
+                .collect();
+            if let Some(default) = namespaces.get_uri("") {
+                mappings.insert(0, (String::new(), default.to_owned()));
+            }
+            mappings
+        } else {
+            pending
+        };
+        if let (true, Some(fixup)) = (selected_root, self.fixup.as_ref()) {
+            if base != fixup.base || base_attribute.is_some() {
+                set_attribute(&mut attributes, "base", &base);
+            }
+            if lang != fixup.lang {
+                set_attribute(&mut attributes, "lang", lang.as_deref().unwrap_or(""));
+            }
+        }
+        state.frames.push(Frame {
+            kind,
+            base: base.clone(),
+            lang: lang.clone(),
+            prefixes: mappings.iter().map(|mapping| mapping.0.clone()).collect(),
+        });
+        if include {
+            let fixup = Fixup {
+                base: parent_base,
+                lang: parent_lang,
+            };
+            let request = Request::new(state, &attributes, base)?;
+            let chain = state.chain.clone();
+            drop(guard);
+            let error = request.perform(services, out, chain, fixup)?;
+            if let Some(frame) = self.state.borrow_mut().frames.last_mut() {
+                if let FrameKind::Include {
+                    ref mut resource_error,
+                    ..
+                } = frame.kind
+                {
+                    *resource_error = error;
+                }
+            }
+            return Ok(());
+        }
+        drop(guard);
+        if copied {
+            for (prefix, ns_uri) in mappings {
+                out.start_prefix_mapping(&prefix, &ns_uri)?;
+            }
+            return out.start_element(uri, local_name, q_name, attributes);
+        }
+        Ok(())
+    }
+
+    fn end_element(
+        &self,
+        out: &dyn ContentHandler<L, AttributesImpl>,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+    ) -> Result<()> {
+        let frame = {
+            let mut state = self.state.borrow_mut();
+            state.namespaces.pop_context();
+            if let Some(selection) = state.selection.as_mut() {
+                selection.end();
+            }
+            match state.frames.pop() {
+                Some(frame) => frame,
+                None => return Ok(()),
+            }
+        };
+        match frame.kind {
+            FrameKind::Copied => {
+                out.end_element(uri, local_name, q_name)?;
+                for prefix in frame.prefixes.iter().rev() {
+                    out.end_prefix_mapping(prefix)?;
+                }
+                Ok(())
+            }
+            FrameKind::Include {
+                resource_error: Some(ref message),
+                fallback: false,
+            } => Err(self.state.borrow().fatal_error(message)),
+            _ => Ok(()),
+        }
+    }
+
+    fn characters(&self, out: &dyn ContentHandler<L, AttributesImpl>, content: &str) -> Result<()> {
+        if self.state.borrow().forwarding() {
+            out.characters(content)?;
+        }
+        Ok(())
+    }
+
+    fn ignorable_whitespace(
+        &self,
+        out: &dyn ContentHandler<L, AttributesImpl>,
+        content: &str,
+    ) -> Result<()> {
+        if self.state.borrow().forwarding() {
+            out.ignorable_whitespace(content)?;
+        }
+        Ok(())
+    }
+
+    fn processing_instruction(
+        &self,
+        out: &dyn ContentHandler<L, AttributesImpl>,
+        target: &str,
+        data: &str,
+    ) -> Result<()> {
+        if self.state.borrow().forwarding() {
+            out.processing_instruction(target, data)?;
+        }
+        Ok(())
+    }
+
+    fn skipped_entity(
+        &self,
+        out: &dyn ContentHandler<L, AttributesImpl>,
+        name: &str,
+    ) -> Result<()> {
+        if self.state.borrow().forwarding() {
+            out.skipped_entity(name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Set an attribute in the XML Namespace, replacing any previous value.
+fn set_attribute(attributes: &mut AttributesImpl, local_name: &str, value: &str) {
+    match attributes.get_ns_name_index(XMLNS, local_name) {
+        Some(index) => {
+            attributes.set_value(index, value);
+        }
+        None => attributes.add_attribute(
+            XMLNS,
+            local_name,
+            &format!("xml:{}", local_name),
+            "CDATA",
+            value,
+        ),
+    }
+}
+
+/// The validated attributes of an `xi:include` element.
+struct Request<L: Locator> {
+    href: String,
+    text: bool,
+    xpointer: Option<String>,
+    encoding: Option<String>,
+    base: String,
+    locator: Option<Rc<L>>,
+}
+
+impl<L: Locator> Request<L> {
+    fn new(state: &State<L>, attributes: &AttributesImpl, base: String) -> Result<Self> {
+        let href = attributes.get_ns_name_value("", "href").unwrap_or_default();
+        let xpointer = attributes.get_ns_name_value("", "xpointer");
+        let text = match attributes.get_ns_name_value("", "parse").as_deref() {
+            None | Some("xml") => false,
+            Some("text") => true,
+            Some(parse) => {
+                return Err(state.fatal_error(&format!(
+                    "invalid value \"{}\" for the parse attribute of xi:include",
+                    parse
+                )))
+            }
+        };
+        if href.is_empty() && xpointer.is_none() {
+            return Err(
+                state.fatal_error("xi:include has neither an href nor an xpointer attribute")
+            );
+        }
+        if text && xpointer.is_some() {
+            return Err(
+                state.fatal_error("xi:include has an xpointer attribute with parse=\"text\"")
+            );
+        }
+        if href.contains('#') {
+            return Err(state.fatal_error(&format!(
+                "the href attribute of xi:include has a fragment identifier: \"{}\"",
+                href
+            )));
+        }
+        for name in &["accept", "accept-language"] {
+            if let Some(value) = attributes.get_ns_name_value("", name) {
+                if !value.chars().all(|c| (' '..='~').contains(&c)) {
+                    return Err(state.fatal_error(&format!(
+                        "the {} attribute of xi:include contains characters outside #x20-#x7E",
+                        name
+                    )));
+                }
+            }
+        }
+        Ok(Request {
+            href,
+            text,
+            xpointer,
+            encoding: attributes.get_ns_name_value("", "encoding"),
+            base,
+            locator: state.locator.clone(),
+        })
+    }
+
+    fn fatal_error(&self, message: &str) -> Box<dyn common::Error> {
+        Box::new(ParseErrorImpl::at(message, self.locator.as_deref()))
+    }
+
+    /// Include the resource, and return the resource error that prevented it, if any.
+    fn perform(
+        &self,
+        services: Services<L>,
+        out: &dyn ContentHandler<L, AttributesImpl>,
+        mut chain: Vec<(String, Option<String>)>,
+        fixup: Fixup,
+    ) -> Result<Option<String>> {
+        if self.href.is_empty() {
+            return Ok(Some(
+                "a document cannot include parts of itself when processed as a stream".to_owned(),
+            ));
+        }
+        let resource = uri::resolve(&self.base, &self.href);
+        let link = (resource.clone(), self.xpointer.clone());
+        if !self.text && chain.contains(&link) {
+            return Err(self.fatal_error(&format!("inclusion loop: {} includes itself", resource)));
+        }
+        let mut input = match services.resolver.resolve_entity(None, &resource) {
+            Ok(Some(input)) => input,
+            Ok(None) => match uri::to_file_path(&resource).map(std::fs::File::open) {
+                Some(Ok(file)) => Box::new(file) as Box<dyn Read>,
+                Some(Err(e)) => return Ok(Some(format!("cannot open {}: {}", resource, e))),
+                None => return Ok(Some(format!("cannot retrieve {}", resource))),
+            },
+            Err(e) => return Ok(Some(format!("cannot resolve {}: {}", resource, e))),
+        };
+        if self.text {
+            let mut bytes = Vec::new();
+            if let Err(e) = input.read_to_end(&mut bytes) {
+                return Ok(Some(format!("cannot read {}: {}", resource, e)));
+            }
+            let text = match decode(&bytes, self.encoding.as_deref()) {
+                Ok(text) => text,
+                Err(message) => {
+                    return Ok(Some(format!("cannot decode {}: {}", resource, message)))
+                }
+            };
+            if let Some(c) = text.chars().find(|&c| !names::is_char(c)) {
+                return Err(self.fatal_error(&format!(
+                    "{} contains the character #x{:X}, which is not allowed in XML",
+                    resource, c as u32
+                )));
+            }
+            if !text.is_empty() {
+                out.characters(&text)?;
+            }
+            return Ok(None);
+        }
+        let parts = match self.xpointer {
+            None => None,
+            Some(ref xpointer) => {
+                let parts =
+                    parse_pointer(xpointer).map_err(|message| self.fatal_error(&message))?;
+                if parts.is_empty() {
+                    return Ok(Some(format!(
+                        "the XPointer \"{}\" uses no supported scheme",
+                        xpointer
+                    )));
+                }
+                Some(parts)
+            }
+        };
+        chain.push(link);
+        let included = |pointer: Option<ElementPointer>| Included {
+            services,
+            out,
+            processor: Processor::new(
+                resource.clone(),
+                Some(Selection::new(pointer)),
+                Some(Fixup {
+                    base: fixup.base.clone(),
+                    lang: fixup.lang.clone(),
+                }),
+                chain.clone(),
+            ),
+        };
+        let handler = match parts {
+            Some(ref parts) if parts.len() > 1 => {
+                // The first part that identifies an element wins, so the resource has to be
+                // seen completely before anything can be included.
+                let recorder = EventRecorder::<L>::new();
+                services
+                    .parser
+                    .parse_resource(&mut input, &resource, &recorder)?;
+                let events = recorder.take_events();
+                let pointer = parts
+                    .iter()
+                    .find(|pointer| identifies_element(pointer, &events));
+                let handler = included(pointer.cloned());
+                if pointer.is_some() {
+                    for event in &events {
+                        event.dispatch(&handler)?;
+                    }
+                }
+                handler
+            }
+            _ => {
+                let handler = included(parts.and_then(|parts| parts.into_iter().next()));
+                services
+                    .parser
+                    .parse_resource(&mut input, &resource, &handler)?;
+                handler
+            }
+        };
+        if !handler.processor.found() {
+            return Ok(Some(format!(
+                "the XPointer \"{}\" identifies no element in {}",
+                self.xpointer.as_deref().unwrap_or_default(),
+                resource
+            )));
+        }
+        Ok(None)
+    }
+}
+
+/// The content handler receiving the events of an included XML resource.
+struct Included<'i, L: Locator> {
+    services: Services<'i, L>,
+    out: &'i dyn ContentHandler<L, AttributesImpl>,
+    processor: Processor<L>,
+}
+
+impl<'i, L: Locator> ContentHandler<L, AttributesImpl> for Included<'i, L> {
+    fn characters(&self, content: &str) -> Result<()> {
+        self.processor.characters(self.out, content)
+    }
+    fn end_element(&self, uri: &str, local_name: &str, q_name: &str) -> Result<()> {
+        self.processor
+            .end_element(self.out, uri, local_name, q_name)
+    }
+    fn ignorable_whitespace(&self, content: &str) -> Result<()> {
+        self.processor.ignorable_whitespace(self.out, content)
+    }
+    fn processing_instruction(&self, target: &str, data: &str) -> Result<()> {
+        self.processor
+            .processing_instruction(self.out, target, data)
+    }
+    fn set_document_locator(&self, locator: Rc<L>) {
+        self.processor.set_document_locator(locator);
+    }
+    fn skipped_entity(&self, name: &str) -> Result<()> {
+        self.processor.skipped_entity(self.out, name)
+    }
+    fn start_element(
+        &self,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attributes: AttributesImpl,
+    ) -> Result<()> {
+        self.processor
+            .start_element(self.services, self.out, uri, local_name, q_name, attributes)
+    }
+    fn start_prefix_mapping(&self, prefix: &str, uri: &str) -> Result<()> {
+        self.processor.start_prefix_mapping(prefix, uri);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event::markup_events;
+    use helpers::LocatorImpl;
+    use resolver::SandboxResolver;
+
+    const DOC: &str = "http://example.org/doc.xml";
+
+    /// Parse an XML resource with `event::markup_events`.
+    fn parse(
+        input: &mut dyn Read,
+        _system_id: &str,
+        handler: &dyn ContentHandler<LocatorImpl, AttributesImpl>,
+    ) -> Result<()> {
+        let mut markup = String::new();
+        input.read_to_string(&mut markup).unwrap();
+        for event in markup_events(&markup) {
+            event.dispatch(handler)?;
+        }
+        Ok(())
+    }
+
+    /// Render the events received downstream as markup, without the Namespace declarations.
+    fn render(events: &[Event]) -> String {
+        let mut markup = String::new();
+        for event in events {
+            match *event {
+                Event::StartElement {
+                    ref q_name,
+                    ref attributes,
+                    ..
+                } => {
+                    markup.push('<');
+                    markup.push_str(q_name);
+                    for attribute in attributes {
+                        markup.push_str(&format!(" {}=\"{}\"", attribute.q_name, attribute.value));
+                    }
+                    markup.push('>');
+                }
+                Event::EndElement { ref q_name, .. } => markup.push_str(&format!("</{}>", q_name)),
+                Event::Characters(ref content) => markup.push_str(content),
+                _ => {}
+            }
+        }
+        markup
+    }
+
+    /// Process a document with the given resources available, and return the markup received
+    /// downstream or the fatal error.
+    fn process(document: &str, resources: &[(&str, &[u8])]) -> std::result::Result<String, String> {
+        let mut resolver = SandboxResolver::in_memory();
+        for &(uri, content) in resources {
+            resolver.insert(uri, content);
+        }
+        let filter = XIncludeFilter::new(EventRecorder::<LocatorImpl>::new(), resolver, parse);
+        filter.set_base_uri(DOC);
+        parse(&mut document.as_bytes(), DOC, &filter).map_err(|e| e.to_string())?;
+        Ok(render(&filter.get_content_handler().events()))
+    }
+
+    /// Return the content of the document element in rendered markup.
+    fn content(markup: &str) -> String {
+        markup[markup.find('>').unwrap() + 1..markup.len() - "</doc>".len()].to_owned()
+    }
+
+    fn xi(content: &str) -> String {
+        format!("<doc xmlns:xi=\"{}\">{}</doc>", XINCLUDE_NS, content)
+    }
+
+    #[test]
+    fn pointers() {
+        let id = |id: &str, steps: &[u64]| ElementPointer {
+            id: Some(id.to_owned()),
+            steps: steps.to_vec(),
+        };
+        let root = |steps: &[u64]| ElementPointer {
+            id: None,
+            steps: steps.to_vec(),
+        };
+        assert_eq!(parse_pointer("intro"), Ok(vec![id("intro", &[])]));
+        assert_eq!(
+            parse_pointer("element(intro/2/1)"),
+            Ok(vec![id("intro", &[2, 1])])
+        );
+        assert_eq!(parse_pointer("element(/1/3)"), Ok(vec![root(&[1, 3])]));
+        assert_eq!(
+            parse_pointer(" xmlns(a=urn:a) element(a)  element(/1) "),
+            Ok(vec![id("a", &[]), root(&[1])])
+        );
+        assert_eq!(parse_pointer("xparse_pointer(//a[f(1)])"), Ok(vec![]));
+        assert_eq!(
+            parse_pointer("other(^(^)^^) element(/2)"),
+            Ok(vec![root(&[2])])
+        );
+        assert_eq!(
+            parse_pointer("other(a(b)c) element(/2)"),
+            Ok(vec![root(&[2])])
+        );
+        assert_eq!(parse_pointer("other(^)) element(/2)"), Ok(vec![root(&[2])]));
+        for invalid in [
+            "",
+            " ",
+            "element()",
+            "element(/0)",
+            "element(/a)",
+            "element(/+1)",
+            "element(a/)",
+            "element(a b)",
+            "element(/1",
+            "element(^a)",
+            "other(^",
+            "1a(x)",
+            "element(/1) tail",
+        ] {
+            assert_eq!(
+                parse_pointer(invalid),
+                Err(format!("invalid XPointer \"{}\"", invalid)),
+                "{:?}",
+                invalid
+            );
+        }
+    }
+
+    /// Return the positions of the elements a pointer selects among elements with the given
+    /// depths and `xml:id` attributes.
+    fn select(xpointer: Option<&str>, elements: &[(usize, Option<&str>)]) -> Vec<usize> {
+        let pointer = xpointer.map(|xpointer| parse_pointer(xpointer).unwrap().remove(0));
+        let mut selection = Selection::new(pointer);
+        let mut depth = 0;
+        let mut selected = Vec::new();
+        for (position, &(element_depth, id)) in elements.iter().enumerate() {
+            while depth >= element_depth {
+                selection.end();
+                depth -= 1;
+            }
+            let mut attributes = AttributesImpl::new();
+            if let Some(id) = id {
+                attributes.add_attribute(XMLNS, "id", "xml:id", "CDATA", id);
+            }
+            if selection.start(&attributes) {
+                selected.push(position);
+            }
+            depth += 1;
+        }
+        selected
+    }
+
+    #[test]
+    fn child_sequences() {
+        let elements = [
+            (1, None),
+            (2, Some("a")),
+            (3, None),
+            (3, Some("b")),
+            (4, None),
+            (2, None),
+            (3, Some("c")),
+        ];
+        assert_eq!(select(None, &elements), [0]);
+        assert_eq!(select(Some("element(/1)"), &elements), [0]);
+        assert_eq!(select(Some("element(/1/1/2)"), &elements), [3]);
+        assert_eq!(select(Some("element(/1/2/1)"), &elements), [6]);
+        assert_eq!(
+            select(Some("element(/1/3)"), &elements),
+            Vec::<usize>::new()
+        );
+        assert_eq!(select(Some("element(/2)"), &elements), Vec::<usize>::new());
+        assert_eq!(select(Some("b"), &elements), [3]);
+        assert_eq!(select(Some("element(a/2/1)"), &elements), [4]);
+        assert_eq!(select(Some("element(a/1)"), &elements), [2]);
+        assert_eq!(select(Some("element(c/1)"), &elements), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn includes_documents_and_elements() {
+        let part: &[u8] = b"<part><sec id=\"s1\"><p>one</p></sec><sec><p>two</p></sec></part>";
+        let resources = [("http://example.org/part.xml", part)];
+        assert_eq!(
+            process(&xi("<xi:include href=\"part.xml\"/>"), &resources).unwrap(),
+            concat!(
+                "<doc xmlns:xi=\"http://www.w3.org/2001/XInclude\">",
+                "<part xml:base=\"http://example.org/part.xml\"><sec id=\"s1\"><p>one</p></sec>",
+                "<sec><p>two</p></sec></part></doc>"
+            )
+        );
+        let included = |xpointer: &str| {
+            let document = xi(&format!(
+                "<xi:include href=\"part.xml\" xpointer=\"{}\"/>",
+                xpointer
+            ));
+            let markup = process(&document, &resources).unwrap();
+            content(&markup)
+        };
+        let base = "xml:base=\"http://example.org/part.xml\"";
+        assert_eq!(
+            included("s1"),
+            format!("<sec id=\"s1\" {}><p>one</p></sec>", base)
+        );
+        assert_eq!(included("element(/1/2/1)"), format!("<p {}>two</p>", base));
+        assert_eq!(included("element(s1/1)"), format!("<p {}>one</p>", base));
+        assert_eq!(
+            included("element(nowhere)element(/1/2)"),
+            format!("<sec {}><p>two</p></sec>", base)
+        );
+    }
+
+    #[test]
+    fn include_attributes() {
+        let errors = [
+            (
+                "parse=\"html\" href=\"a.xml\"",
+                "invalid value \"html\" for the parse attribute of xi:include",
+            ),
+            (
+                "",
+                "xi:include has neither an href nor an xpointer attribute",
+            ),
+            (
+                "href=\"\"",
+                "xi:include has neither an href nor an xpointer attribute",
+            ),
+            (
+                "href=\"a.txt\" parse=\"text\" xpointer=\"a\"",
+                "xi:include has an xpointer attribute with parse=\"text\"",
+            ),
+            (
+                "href=\"a.xml#a\"",
+                "the href attribute of xi:include has a fragment identifier: \"a.xml#a\"",
+            ),
+            (
+                "href=\"a.xml\" accept=\"text/\u{E9}\"",
+                "the accept attribute of xi:include contains characters outside #x20-#x7E",
+            ),
+            (
+                "href=\"a.xml\" accept-language=\"\u{1}\"",
+                "the accept-language attribute of xi:include contains characters outside #x20-#x7E",
+            ),
+            (
+                "href=\"a.xml\" xpointer=\"element(\"",
+                "invalid XPointer \"element(\"",
+            ),
+        ];
+        for &(attributes, message) in &errors {
+            let document = xi(&format!("<xi:include {}/>", attributes));
+            assert_eq!(
+                process(&document, &[("http://example.org/a.xml", b"<a/>")]),
+                Err(message.to_owned())
+            );
+        }
+        let document = xi(
+            "<xi:include href=\"a.xml\" parse=\"xml\" accept=\"text/xml\" accept-language=\"en\"/>",
+        );
+        assert!(process(&document, &[("http://example.org/a.xml", b"<a/>")]).is_ok());
+    }
+
+    #[test]
+    fn fallbacks() {
+        let resources = [("http://example.org/a.xml", &b"<a/>"[..])];
+        let fallback = "<xi:fallback><p>missing</p></xi:fallback>";
+        assert_eq!(
+            process(
+                &xi(&format!(
+                    "<xi:include href=\"b.xml\">{}</xi:include>",
+                    fallback
+                )),
+                &resources
+            )
+            .unwrap(),
+            "<doc xmlns:xi=\"http://www.w3.org/2001/XInclude\"><p>missing</p></doc>"
+        );
+        assert_eq!(
+            process(
+                &xi(&format!(
+                    "<xi:include href=\"a.xml\">{}<ignored/></xi:include>",
+                    fallback
+                )),
+                &resources
+            )
+            .unwrap(),
+            concat!(
+                "<doc xmlns:xi=\"http://www.w3.org/2001/XInclude\">",
+                "<a xml:base=\"http://example.org/a.xml\"></a></doc>"
+            )
+        );
+        let nested = concat!(
+            "<xi:fallback><xi:include href=\"c.xml\">",
+            "<xi:fallback>last</xi:fallback></xi:include></xi:fallback>"
+        );
+        assert_eq!(
+            process(
+                &xi(&format!(
+                    "<xi:include href=\"b.xml\">{}</xi:include>",
+                    nested
+                )),
+                &resources
+            )
+            .unwrap(),
+            "<doc xmlns:xi=\"http://www.w3.org/2001/XInclude\">last</doc>"
+        );
+        assert_eq!(
+            process(
+                &xi("<xi:include href=\"b.xml\" xpointer=\"x\"><xi:fallback/></xi:include>"),
+                &resources
+            )
+            .unwrap(),
+            "<doc xmlns:xi=\"http://www.w3.org/2001/XInclude\"></doc>"
+        );
+        let error = process(&xi("<xi:include href=\"b.xml\"/>"), &resources).unwrap_err();
+        assert!(
+            error.starts_with("cannot resolve http://example.org/b.xml: "),
+            "{}",
+            error
+        );
+        assert_eq!(
+            process(
+                &xi("<xi:include href=\"a.xml\" xpointer=\"x\"/>"),
+                &resources
+            ),
+            Err("the XPointer \"x\" identifies no element in http://example.org/a.xml".to_owned())
+        );
+        let errors = [
+            ("<xi:fallback/>", "xi:fallback is not a child of xi:include"),
+            (
+                "<xi:include href=\"a.xml\"><xi:fallback/><xi:fallback/></xi:include>",
+                "xi:include has more than one xi:fallback child",
+            ),
+            (
+                "<xi:include href=\"a.xml\"><xi:include href=\"a.xml\"/></xi:include>",
+                "xi:include has an xi:include child",
+            ),
+        ];
+        for &(content, message) in &errors {
+            assert_eq!(process(&xi(content), &resources), Err(message.to_owned()));
+        }
+    }
+
+    #[test]
+    fn inclusion_loops() {
+        let a = xi("<xi:include href=\"b.xml\" xpointer=\"four\"/>");
+        let b = xi(concat!(
+            "<s id=\"one\"><xi:include href=\"b.xml\" xpointer=\"two\"/></s>",
+            "<s id=\"two\">two</s>",
+            "<s id=\"three\"><xi:include href=\"b.xml\" xpointer=\"three\"/></s>",
+            "<s id=\"four\"><xi:include href=\"a.xml\"/></s>",
+        ));
+        let resources = [
+            ("http://example.org/a.xml", a.as_bytes()),
+            ("http://example.org/b.xml", b.as_bytes()),
+            ("http://example.org/doc.xml", b"<doc/>"),
+        ];
+        let include = |href: &str, xpointer: &str| {
+            let document = xi(&format!(
+                "<xi:include href=\"{}\" xpointer=\"{}\"/>",
+                href, xpointer
+            ));
+            process(&document, &resources).map(|markup| content(&markup))
+        };
+        // The same resource may be included again with another pointer.
+        assert_eq!(
+            include("b.xml", "one"),
+            Ok(concat!(
+                "<s id=\"one\" xml:base=\"http://example.org/b.xml\">",
+                "<s id=\"two\">two</s></s>"
+            )
+            .to_owned())
+        );
+        assert_eq!(
+            include("b.xml", "three"),
+            Err("inclusion loop: http://example.org/b.xml includes itself".to_owned())
+        );
+        // a.xml is included again without a pointer, so the loop closes at b.xml.
+        assert_eq!(
+            include("a.xml", "element(/1)"),
+            Err("inclusion loop: http://example.org/b.xml includes itself".to_owned())
+        );
+        assert_eq!(
+            process(&xi("<xi:include href=\"a.xml\"/>"), &resources),
+            Err("inclusion loop: http://example.org/a.xml includes itself".to_owned())
+        );
+        assert_eq!(
+            process(&xi("<xi:include href=\"doc.xml\"/>"), &resources),
+            Err("inclusion loop: http://example.org/doc.xml includes itself".to_owned())
+        );
+        // Text inclusions cannot start a loop.
+        let text = xi("<xi:include href=\"t.xml\" parse=\"text\"/>");
+        let resources = [("http://example.org/t.xml", text.as_bytes())];
+        assert!(process(&xi("<xi:include href=\"t.xml\"/>"), &resources).is_ok());
+    }
+
+    #[test]
+    fn base_and_language_fixup() {
+        let resources = [
+            (
+                "http://example.org/sub/fr.xml",
+                &b"<p xml:lang=\"fr\"><q/></p>"[..],
+            ),
+            ("http://example.org/sub/none.xml", &b"<p><q/></p>"[..]),
+            (
+                "http://example.org/sub/en.xml",
+                &b"<p xml:lang=\"en\" xml:base=\"x/\"><q/></p>"[..],
+            ),
+        ];
+        let include = |href: &str| {
+            let document = format!(
+                "<doc xmlns:xi=\"{}\" xml:lang=\"en\"><xi:include href=\"{}\"/></doc>",
+                XINCLUDE_NS, href
+            );
+            let markup = process(&document, &resources).unwrap();
+            content(&markup)
+        };
+        assert_eq!(
+            include("sub/fr.xml"),
+            "<p xml:lang=\"fr\" xml:base=\"http://example.org/sub/fr.xml\"><q></q></p>"
+        );
+        assert_eq!(
+            include("sub/none.xml"),
+            "<p xml:base=\"http://example.org/sub/none.xml\" xml:lang=\"\"><q></q></p>"
+        );
+        assert_eq!(
+            include("sub/en.xml"),
+            "<p xml:lang=\"en\" xml:base=\"http://example.org/sub/x/\"><q></q></p>"
+        );
+    }
+
+    #[test]
+    fn text_inclusion() {
+        let utf16: Vec<u8> = [0xFF, 0xFE]
+            .iter()
+            .copied()
+            .chain(
+                "caf\u{E9} \u{1D11E}"
+                    .encode_utf16()
+                    .flat_map(u16::to_le_bytes),
+            )
+            .collect();
+        let utf16be: Vec<u8> = "ok".encode_utf16().flat_map(u16::to_be_bytes).collect();
+        let resources = [
+            ("http://example.org/utf8.txt", &b"\xEF\xBB\xBFa < b"[..]),
+            ("http://example.org/utf16.txt", &utf16[..]),
+            ("http://example.org/utf16be.txt", &utf16be[..]),
+            ("http://example.org/latin1.txt", &b"caf\xE9"[..]),
+            ("http://example.org/control.txt", &b"a\x01"[..]),
+            ("http://example.org/odd.txt", &b"\xFF\xFEa"[..]),
+        ];
+        let include = |attributes: &str| {
+            let document = xi(&format!(
+                "<xi:include parse=\"text\" {}><xi:fallback>[fallback]</xi:fallback></xi:include>",
+                attributes
+            ));
+            process(&document, &resources).map(|markup| content(&markup))
+        };
+        assert_eq!(include("href=\"utf8.txt\""), Ok("a < b".to_owned()));
+        assert_eq!(
+            include("href=\"utf16.txt\""),
+            Ok("caf\u{E9} \u{1D11E}".to_owned())
+        );
+        assert_eq!(
+            include("href=\"utf16.txt\" encoding=\"utf-16\""),
+            Ok("caf\u{E9} \u{1D11E}".to_owned())
+        );
+        assert_eq!(
+            include("href=\"utf16be.txt\" encoding=\"UTF-16BE\""),
+            Ok("ok".to_owned())
+        );
+        assert_eq!(
+            include("href=\"latin1.txt\" encoding=\"ISO-8859-1\""),
+            Ok("caf\u{E9}".to_owned())
+        );
+        assert_eq!(include("href=\"latin1.txt\""), Ok("[fallback]".to_owned()));
+        assert_eq!(
+            include("href=\"latin1.txt\" encoding=\"US-ASCII\""),
+            Ok("[fallback]".to_owned())
+        );
+        assert_eq!(
+            include("href=\"utf8.txt\" encoding=\"EBCDIC\""),
+            Ok("[fallback]".to_owned())
+        );
+        assert_eq!(include("href=\"odd.txt\""), Ok("[fallback]".to_owned()));
+        assert_eq!(
+            include("href=\"control.txt\""),
+            Err(concat!(
+                "http://example.org/control.txt contains the character #x1, ",
+                "which is not allowed in XML"
+            )
+            .to_owned())
+        );
+        assert_eq!(
+            decode(b"\xE9", None),
+            Err("malformed UTF-8 text".to_owned())
+        );
+        assert_eq!(
+            decode(b"\xFF\xFEa", None),
+            Err("truncated UTF-16 text".to_owned())
+        );
+        assert_eq!(
+            decode(b"\x00\xD8\x00a", Some("UTF-16LE")),
+            Err("malformed UTF-16 text".to_owned())
+        );
+        assert_eq!(
+            decode(b"x", Some("EBCDIC")),
+            Err("unsupported encoding \"EBCDIC\"".to_owned())
+        );
+    }
+}
@@ -0,0 +1,535 @@
+//! Tracking of base URIs, as specified by XML Base.
+//!
+//! Relative URI references in a document, such as links, are resolved against the base URI of
+//! the element containing them. That is the URI of the entity the element appears in, unless
+//! the element or one of its ancestors in the same entity carries an `xml:base` attribute,
+//! which is itself resolved against the base URI of the parent.
+//!
+//! `XmlBaseFilter` keeps track of base URIs as the events of a document go through it, and
+//! hands the wrapped handler a `BaseLocator` in place of the reader's locator, through which
+//! the base URI is available during every event. Entity boundaries are taken from the
+//! `LexicalHandler` events, and the URIs of external entities from the `DeclHandler` events, so
+//! the filter should be registered as those handlers too when the reader supports them.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use common::Locator;
+use ext::DeclHandler;
+use ext::LexicalHandler;
+use helpers::LocatorProxy;
+use helpers::XMLNS;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::Result;
+use uri;
+
+/// The base URIs in scope at the current point of a document.
+#[derive(Default)]
+struct State {
+    /// The base URI set with `XmlBaseFilter::set_base_uri`, if any.
+    configured: Option<String>,
+    /// The URI of the document entity, if known.
+    document_base: Option<String>,
+    /// The absolute URIs of the external entities declared so far, by name.
+    entities: HashMap<String, String>,
+    /// The base URIs of the open elements and entities, innermost last.
+    stack: Vec<String>,
+}
+
+impl State {
+    fn current(&self) -> Option<&str> {
+        self.stack
+            .last()
+            .map(String::as_str)
+            .or(self.document_base.as_deref())
+    }
+
+    fn resolve(&self, reference: &str) -> String {
+        uri::resolve(self.current().unwrap_or(""), reference)
+    }
+}
+
+/// A locator that also reports the base URI at the current point of a document.
+///
+/// It reports the position given by the reader's locator, if the reader provided one.
+pub struct BaseLocator<L> {
+    locator: LocatorProxy<L>,
+    state: RefCell<State>,
+}
+
+impl<L: Locator> BaseLocator<L> {
+    fn new() -> Self {
+        BaseLocator {
+            locator: LocatorProxy::new(),
+            state: RefCell::new(State::default()),
+        }
+    }
+
+    /// Return the base URI at the current point of the document: that of the current element,
+    /// or, outside the document element, that of the current entity. Returns `None` if the
+    /// URI of the document is unknown and no `xml:base` attribute applies.
+    ///
+    /// The base URI of the current element takes its own `xml:base` attribute into account,
+    /// from its `start_element` event to its `end_element` event inclusive.
+    pub fn get_base_uri(&self) -> Option<String> {
+        self.state
+            .borrow()
+            .current()
+            .filter(|base| !base.is_empty())
+            .map(str::to_owned)
+    }
+
+    /// Return the URI of the document entity, if known.
+    pub fn get_document_base_uri(&self) -> Option<String> {
+        self.state.borrow().document_base.clone()
+    }
+
+    /// Return the reader's locator, if it provided one.
+    pub fn get_locator(&self) -> Option<Rc<L>> {
+        self.locator.get_locator()
+    }
+}
+
+impl<L: Locator> Locator for BaseLocator<L> {
+    fn get_column_number(&self) -> Option<u64> {
+        self.locator.get_column_number()
+    }
+    fn get_line_number(&self) -> Option<u64> {
+        self.locator.get_line_number()
+    }
+    fn get_public_id(&self) -> Option<String> {
+        self.locator.get_public_id()
+    }
+    fn get_system_id(&self) -> Option<String> {
+        self.locator.get_system_id()
+    }
+}
+
+/// A filter tracking base URIs for the handler it wraps.
+///
+/// The base URI of the document is the one set with `set_base_uri`, or else the system
+/// identifier reported by the reader's locator when the document starts. `xml:base`
+/// attributes are recognized by namespace URI, or by qualified name when the reader does not
+/// process namespaces. The events are passed on unchanged.
+pub struct XmlBaseFilter<L, CH> {
+    handler: CH,
+    locator: Rc<BaseLocator<L>>,
+}
+
+impl<L: Locator, CH> XmlBaseFilter<L, CH> {
+    /// Construct a filter passing events to a handler.
+    pub fn new(handler: CH) -> Self {
+        XmlBaseFilter {
+            handler,
+            locator: Rc::new(BaseLocator::new()),
+        }
+    }
+
+    /// Return the wrapped handler.
+    pub fn get_content_handler(&self) -> &CH {
+        &self.handler
+    }
+
+    /// Return the locator handed to the wrapped handler.
+    pub fn get_locator(&self) -> Rc<BaseLocator<L>> {
+        self.locator.clone()
+    }
+
+    /// Set the base URI of the documents to be processed, in place of the system identifier
+    /// reported by the locator.
+    pub fn set_base_uri(&self, base_uri: &str) {
+        self.locator.state.borrow_mut().configured = Some(base_uri.to_owned());
+    }
+
+    fn declare(&self, name: &str, system_id: &str) {
+        let mut state = self.locator.state.borrow_mut();
+        let absolute = state.resolve(system_id);
+        state.entities.insert(name.to_owned(), absolute);
+    }
+}
+
+impl<L, A, CH> ContentHandler<L, A> for XmlBaseFilter<L, CH>
+where
+    L: Locator,
+    A: Attributes,
+    CH: ContentHandler<BaseLocator<L>, A>,
+{
+    fn characters(&self, content: &str) -> Result<()> {
+        self.handler.characters(content)
+    }
+    fn end_document(&self) -> Result<()> {
+        self.handler.end_document()
+    }
+    fn end_element(&self, uri: &str, local_name: &str, q_name: &str) -> Result<()> {
+        let result = self.handler.end_element(uri, local_name, q_name);
+        self.locator.state.borrow_mut().stack.pop();
+        result
+    }
+    fn end_prefix_mapping(&self, prefix: &str) -> Result<()> {
+        self.handler.end_prefix_mapping(prefix)
+    }
+    fn ignorable_whitespace(&self, content: &str) -> Result<()> {
+        self.handler.ignorable_whitespace(content)
+    }
+    fn processing_instruction(&self, target: &str, data: &str) -> Result<()> {
+        self.handler.processing_instruction(target, data)
+    }
+    fn set_document_locator(&self, locator: Rc<L>) {
+        self.locator.locator.set_locator(locator);
+        self.handler.set_document_locator(self.locator.clone());
+    }
+    fn skipped_entity(&self, name: &str) -> Result<()> {
+        self.handler.skipped_entity(name)
+    }
+    fn start_document(&self) -> Result<()> {
+        let located = {
+            let mut state = self.locator.state.borrow_mut();
+            state.stack.clear();
+            state.entities.clear();
+            state.document_base = state
+                .configured
+                .clone()
+                .or_else(|| self.locator.locator.get_system_id());
+            self.locator.locator.get_locator().is_some()
+        };
+        if !located {
+            self.handler.set_document_locator(self.locator.clone());
+        }
+        self.handler.start_document()
+    }
+    fn start_element(
+        &self,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attributes: A,
+    ) -> Result<()> {
+        {
+            let mut state = self.locator.state.borrow_mut();
+            let base = match attributes
+                .get_ns_name_value(XMLNS, "base")
+                .or_else(|| attributes.get_q_name_value("xml:base"))
+            {
+                Some(ref base) => state.resolve(base.trim()),
+                None => state.current().unwrap_or("").to_owned(),
+            };
+            state.stack.push(base);
+        }
+        self.handler
+            .start_element(uri, local_name, q_name, attributes)
+    }
+    fn start_prefix_mapping(&self, prefix: &str, uri: &str) -> Result<()> {
+        self.handler.start_prefix_mapping(prefix, uri)
+    }
+}
+
+impl<L: Locator, CH: DeclHandler> DeclHandler for XmlBaseFilter<L, CH> {
+    fn attribute_decl(
+        &self,
+        element_name: &str,
+        attribute_name: &str,
+        attribute_type: &str,
+        mode: Option<&str>,
+        value: Option<&str>,
+    ) -> Result<()> {
+        self.handler
+            .attribute_decl(element_name, attribute_name, attribute_type, mode, value)
+    }
+    fn element_decl(&self, name: &str, model: &str) -> Result<()> {
+        self.handler.element_decl(name, model)
+    }
+    fn external_entity_decl(
+        &self,
+        name: &str,
+        public_id: Option<&str>,
+        system_id: &str,
+    ) -> Result<()> {
+        self.declare(name, system_id);
+        self.handler
+            .external_entity_decl(name, public_id, system_id)
+    }
+    fn internal_entity_decl(&self, name: &str, value: &str) -> Result<()> {
+        self.handler.internal_entity_decl(name, value)
+    }
+}
+
+impl<L: Locator, CH: LexicalHandler> LexicalHandler for XmlBaseFilter<L, CH> {
+    fn comment(&self, content: &str) -> Result<()> {
+        self.handler.comment(content)
+    }
+    fn end_cdata(&self) -> Result<()> {
+        self.handler.end_cdata()
+    }
+    fn end_dtd(&self) -> Result<()> {
+        self.handler.end_dtd()
+    }
+    fn end_entity(&self, name: &str) -> Result<()> {
+        let result = self.handler.end_entity(name);
+        self.locator.state.borrow_mut().stack.pop();
+        result
+    }
+    fn start_cdata(&self) -> Result<()> {
+        self.handler.start_cdata()
+    }
+    fn start_dtd(
+        &self,
+        name: &str,
+        public_id: Option<&str>,
+        system_id: Option<&str>,
+    ) -> Result<()> {
+        if let Some(system_id) = system_id {
+            self.declare("[dtd]", system_id);
+        }
+        self.handler.start_dtd(name, public_id, system_id)
+    }
+    /// The replacement text of an external entity has the URI of the entity as its base URI,
+    /// while that of an internal entity keeps the base URI of the reference.
+    fn start_entity(&self, name: &str) -> Result<()> {
+        {
+            let mut state = self.locator.state.borrow_mut();
+            let base = match state.entities.get(name) {
+                Some(absolute) => absolute.clone(),
+                None => state.current().unwrap_or("").to_owned(),
+            };
+            state.stack.push(base);
+        }
+        self.handler.start_entity(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event::Event;
+    use helpers::AttributesImpl;
+    use helpers::LocatorImpl;
+
+    /// Records the base URI reported by the locator at every element boundary, and at the
+    /// start of every entity.
+    #[derive(Default)]
+    struct Bases {
+        locator: RefCell<Option<Rc<BaseLocator<LocatorImpl>>>>,
+        seen: RefCell<Vec<String>>,
+    }
+
+    impl Bases {
+        fn record(&self, what: &str) {
+            let base = self.locator.borrow().as_ref().unwrap().get_base_uri();
+            let base = base.unwrap_or_else(|| "-".to_owned());
+            self.seen.borrow_mut().push(format!("{} {}", what, base));
+        }
+    }
+
+    impl ContentHandler<BaseLocator<LocatorImpl>, AttributesImpl> for Bases {
+        fn set_document_locator(&self, locator: Rc<BaseLocator<LocatorImpl>>) {
+            *self.locator.borrow_mut() = Some(locator);
+        }
+        fn start_element(
+            &self,
+            _uri: &str,
+            _local_name: &str,
+            q_name: &str,
+            _attributes: AttributesImpl,
+        ) -> Result<()> {
+            self.record(q_name);
+            Ok(())
+        }
+        fn end_element(&self, _uri: &str, _local_name: &str, q_name: &str) -> Result<()> {
+            self.record(&format!("/{}", q_name));
+            Ok(())
+        }
+    }
+
+    impl DeclHandler for Bases {}
+
+    impl LexicalHandler for Bases {
+        fn start_entity(&self, name: &str) -> Result<()> {
+            self.record(&format!("&{}", name));
+            Ok(())
+        }
+    }
+
+    type Filter = XmlBaseFilter<LocatorImpl, Bases>;
+
+    fn start(q_name: &str, base: Option<&str>) -> Event {
+        let mut attributes = AttributesImpl::new();
+        if let Some(base) = base {
+            attributes.add_attribute(XMLNS, "base", "xml:base", "CDATA", base);
+        }
+        Event::start_element("", q_name, q_name, &attributes)
+    }
+
+    fn end(q_name: &str) -> Event {
+        Event::end_element("", q_name, q_name)
+    }
+
+    fn located(system_id: &str) -> Filter {
+        let filter = XmlBaseFilter::new(Bases::default());
+        let mut locator = LocatorImpl::new();
+        locator.system_id = Some(system_id.to_owned());
+        locator.line_number = Some(7);
+        ContentHandler::<LocatorImpl, AttributesImpl>::set_document_locator(
+            &filter,
+            Rc::new(locator),
+        );
+        filter
+    }
+
+    fn run(filter: &Filter, events: Vec<Event>) -> Vec<String> {
+        for event in events {
+            event.dispatch(filter).unwrap();
+        }
+        filter.get_content_handler().seen.take()
+    }
+
+    #[test]
+    fn nested_relative_bases() {
+        let filter = located("http://example.org/docs/index.xml");
+        let seen = run(
+            &filter,
+            vec![
+                Event::StartDocument,
+                start("doc", None),
+                start("a", Some("guide/")),
+                start("b", Some("../api/ ")),
+                start("c", Some("ref.xml")),
+                end("c"),
+                end("b"),
+                start("d", Some("http://example.com/x/")),
+                start("e", Some("/y/")),
+                end("e"),
+                end("d"),
+                end("a"),
+                end("doc"),
+                Event::EndDocument,
+            ],
+        );
+        assert_eq!(
+            seen,
+            [
+                "doc http://example.org/docs/index.xml",
+                "a http://example.org/docs/guide/",
+                "b http://example.org/docs/api/",
+                "c http://example.org/docs/api/ref.xml",
+                "/c http://example.org/docs/api/ref.xml",
+                "/b http://example.org/docs/api/",
+                "d http://example.com/x/",
+                "e http://example.com/y/",
+                "/e http://example.com/y/",
+                "/d http://example.com/x/",
+                "/a http://example.org/docs/guide/",
+                "/doc http://example.org/docs/index.xml",
+            ]
+        );
+        let locator = filter.get_locator();
+        assert_eq!(
+            locator.get_base_uri().as_deref(),
+            Some("http://example.org/docs/index.xml")
+        );
+        assert_eq!(locator.get_line_number(), Some(7));
+        assert_eq!(
+            locator.get_system_id().as_deref(),
+            Some("http://example.org/docs/index.xml")
+        );
+    }
+
+    #[test]
+    fn relative_bases_without_a_document_uri() {
+        let filter = XmlBaseFilter::<LocatorImpl, _>::new(Bases::default());
+        let seen = run(
+            &filter,
+            vec![
+                Event::StartDocument,
+                start("doc", None),
+                start("a", Some("a/b/")),
+                start("b", Some("../c/")),
+                end("b"),
+                end("a"),
+                end("doc"),
+            ],
+        );
+        assert_eq!(
+            seen,
+            ["doc -", "a a/b/", "b a/c/", "/b a/c/", "/a a/b/", "/doc -"]
+        );
+        let locator = filter.get_locator();
+        assert_eq!(locator.get_line_number(), None);
+        assert!(locator.get_locator().is_none());
+    }
+
+    #[test]
+    fn configured_base_uri() {
+        let filter = located("file:///tmp/in.xml");
+        filter.set_base_uri("http://example.org/out/");
+        let seen = run(
+            &filter,
+            vec![
+                Event::StartDocument,
+                start("doc", Some("page.html")),
+                end("doc"),
+            ],
+        );
+        assert_eq!(
+            seen,
+            [
+                "doc http://example.org/out/page.html",
+                "/doc http://example.org/out/page.html",
+            ]
+        );
+        assert_eq!(
+            filter.get_locator().get_document_base_uri().as_deref(),
+            Some("http://example.org/out/")
+        );
+    }
+
+    #[test]
+    fn qualified_names_without_namespace_processing() {
+        let filter = located("http://example.org/a/doc.xml");
+        let mut attributes = AttributesImpl::new();
+        attributes.add_attribute("", "", "xml:base", "CDATA", "b/");
+        let seen = run(
+            &filter,
+            vec![
+                Event::StartDocument,
+                Event::start_element("", "", "doc", &attributes),
+            ],
+        );
+        assert_eq!(seen, ["doc http://example.org/a/b/"]);
+    }
+
+    #[test]
+    fn entity_bases() {
+        let filter = located("http://example.org/a/doc.xml");
+        Event::StartDocument.dispatch(&filter).unwrap();
+        filter.start_dtd("doc", None, Some("dtd/doc.dtd")).unwrap();
+        filter
+            .external_entity_decl("chapter", None, "chapters/one.xml")
+            .unwrap();
+        filter.internal_entity_decl("note", "<n/>").unwrap();
+        filter.end_dtd().unwrap();
+        let seen = |event: Event| run(&filter, vec![event]);
+        assert_eq!(
+            seen(start("doc", Some("sub/"))),
+            ["doc http://example.org/a/sub/"]
+        );
+        filter.start_entity("chapter").unwrap();
+        assert_eq!(
+            seen(start("chapter", None)),
+            [
+                "&chapter http://example.org/a/chapters/one.xml",
+                "chapter http://example.org/a/chapters/one.xml",
+            ]
+        );
+        seen(end("chapter"));
+        filter.end_entity("chapter").unwrap();
+        filter.start_entity("note").unwrap();
+        assert_eq!(
+            seen(start("n", None)),
+            [
+                "&note http://example.org/a/sub/",
+                "n http://example.org/a/sub/"
+            ]
+        );
+    }
+}
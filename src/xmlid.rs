@@ -0,0 +1,368 @@
+//! Support for `xml:id`, as specified by xml:id Version 1.0.
+//!
+//! An `xml:id` attribute gives its element an identifier, like an attribute declared with type
+//! `ID` in a DTD, but without requiring one. Its value is normalized like that of an `ID`
+//! attribute, must be an `NCName`, and must be unique within the document; violations are
+//! xml:id errors, which do not prevent processing.
+//!
+//! `XmlIdFilter` applies these rules to the events of a document on their way to a handler:
+//! it reports `xml:id` attributes with type `ID` and their normalized value, reports xml:id
+//! errors through `ErrorHandler::error`, and builds an `IdIndex` of the identified elements.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use common::Locator;
+use helpers::AttributesImpl;
+use helpers::LocatorImpl;
+use helpers::ParseErrorImpl;
+use helpers::XMLNS;
+use names;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::ErrorHandler;
+use sax2::Result;
+
+/// Normalize the value of an `ID` attribute: remove leading and trailing spaces, and collapse
+/// sequences of spaces into single spaces.
+///
+/// The value is expected to have gone through the attribute-value normalization of the XML
+/// recommendation, which turns all white space characters into spaces.
+pub fn normalize_id(value: &str) -> String {
+    value
+        .split(' ')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// An element identified by an `xml:id` attribute.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdEntry {
+    /// The namespace URI of the element, empty if none.
+    pub uri: String,
+    /// The local name of the element.
+    pub local_name: String,
+    /// The qualified name of the element.
+    pub q_name: String,
+    /// The position of the element, as reported by the locator.
+    pub location: LocatorImpl,
+}
+
+/// The elements of a document identified by `xml:id` attributes, by identifier.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IdIndex {
+    entries: HashMap<String, IdEntry>,
+}
+
+impl IdIndex {
+    /// Construct an empty index.
+    pub fn new() -> Self {
+        IdIndex::default()
+    }
+
+    /// Return the element with the given identifier, if any.
+    pub fn get(&self, id: &str) -> Option<&IdEntry> {
+        self.entries.get(id)
+    }
+
+    /// Return whether an element has the given identifier.
+    pub fn contains(&self, id: &str) -> bool {
+        self.entries.contains_key(id)
+    }
+
+    /// Return the number of identified elements.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Return whether no element is identified.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Return the identifiers and the elements they identify, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &IdEntry)> {
+        self.entries.iter().map(|(id, entry)| (id.as_str(), entry))
+    }
+}
+
+/// A filter processing `xml:id` attributes for the handler it wraps.
+///
+/// `xml:id` attributes are recognized by namespace URI, or by qualified name when the reader
+/// does not process namespaces. The handler receives them with type `ID` and their normalized
+/// value; other events are passed on unchanged. An `xml:id` whose value is not an `NCName` is
+/// reported and left out of the index, and so is any but the first of several elements with
+/// the same identifier.
+pub struct XmlIdFilter<L, CH, EH> {
+    handler: CH,
+    error_handler: EH,
+    locator: RefCell<Option<Rc<L>>>,
+    index: RefCell<IdIndex>,
+}
+
+impl<L: Locator, CH, EH: ErrorHandler<ParseErrorImpl>> XmlIdFilter<L, CH, EH> {
+    /// Construct a filter passing events to a handler and reporting xml:id errors to
+    /// `error_handler`.
+    pub fn new(handler: CH, error_handler: EH) -> Self {
+        XmlIdFilter {
+            handler,
+            error_handler,
+            locator: RefCell::new(None),
+            index: RefCell::new(IdIndex::new()),
+        }
+    }
+
+    /// Return the wrapped handler.
+    pub fn get_content_handler(&self) -> &CH {
+        &self.handler
+    }
+
+    /// Return the error handler.
+    pub fn get_error_handler(&self) -> &EH {
+        &self.error_handler
+    }
+
+    /// Return the element with the given identifier among those seen so far, if any.
+    pub fn lookup(&self, id: &str) -> Option<IdEntry> {
+        self.index.borrow().get(id).cloned()
+    }
+
+    /// Return the index of the elements identified in the current document, and clear it.
+    pub fn take_index(&self) -> IdIndex {
+        self.index.replace(IdIndex::new())
+    }
+
+    fn location(&self) -> LocatorImpl {
+        self.locator
+            .borrow()
+            .as_ref()
+            .map(|locator| LocatorImpl::from_locator(&**locator))
+            .unwrap_or_default()
+    }
+
+    fn report(&self, message: String) -> Result<()> {
+        self.error_handler
+            .error(&ParseErrorImpl::new(message, self.location()))
+    }
+}
+
+impl<L, A, CH, EH> ContentHandler<L, A> for XmlIdFilter<L, CH, EH>
+where
+    L: Locator,
+    A: Attributes,
+    CH: ContentHandler<L, AttributesImpl>,
+    EH: ErrorHandler<ParseErrorImpl>,
+{
+    fn characters(&self, content: &str) -> Result<()> {
+        self.handler.characters(content)
+    }
+    fn end_document(&self) -> Result<()> {
+        self.handler.end_document()
+    }
+    fn end_element(&self, uri: &str, local_name: &str, q_name: &str) -> Result<()> {
+        self.handler.end_element(uri, local_name, q_name)
+    }
+    fn end_prefix_mapping(&self, prefix: &str) -> Result<()> {
+        self.handler.end_prefix_mapping(prefix)
+    }
+    fn ignorable_whitespace(&self, content: &str) -> Result<()> {
+        self.handler.ignorable_whitespace(content)
+    }
+    fn processing_instruction(&self, target: &str, data: &str) -> Result<()> {
+        self.handler.processing_instruction(target, data)
+    }
+    fn set_document_locator(&self, locator: Rc<L>) {
+        *self.locator.borrow_mut() = Some(locator.clone());
+        self.handler.set_document_locator(locator);
+    }
+    fn skipped_entity(&self, name: &str) -> Result<()> {
+        self.handler.skipped_entity(name)
+    }
+    fn start_document(&self) -> Result<()> {
+        self.index.replace(IdIndex::new());
+        self.handler.start_document()
+    }
+    fn start_element(
+        &self,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attributes: A,
+    ) -> Result<()> {
+        let mut attributes = AttributesImpl::from_attributes(&attributes);
+        // A reader that does not process namespaces reports every attribute with an empty
+        // namespace URI, so the qualified name is the only way to recognize `xml:id` then.
+        let found = attributes
+            .get_ns_name_index(XMLNS, "id")
+            .or_else(|| attributes.get_q_name_index("xml:id"));
+        if let Some(index) = found {
+            let id = normalize_id(&attributes.get_value(index).unwrap_or_default());
+            attributes.set_type(index, "ID");
+            attributes.set_value(index, &id);
+            if !names::is_ncname(&id) {
+                self.report(format!("xml:id \"{}\" is not an NCName", id))?;
+            } else if let Some(first) = self.lookup(&id) {
+                let line = first
+                    .location
+                    .line_number
+                    .map_or_else(String::new, |line| format!(" on line {}", line));
+                self.report(format!(
+                    "xml:id \"{}\" is already the identifier of element {}{}",
+                    id, first.q_name, line
+                ))?;
+            } else {
+                let entry = IdEntry {
+                    uri: uri.to_owned(),
+                    local_name: local_name.to_owned(),
+                    q_name: q_name.to_owned(),
+                    location: self.location(),
+                };
+                self.index.borrow_mut().entries.insert(id, entry);
+            }
+        }
+        self.handler
+            .start_element(uri, local_name, q_name, attributes)
+    }
+    fn start_prefix_mapping(&self, prefix: &str, uri: &str) -> Result<()> {
+        self.handler.start_prefix_mapping(prefix, uri)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event::Event;
+    use event::EventRecorder;
+
+    #[derive(Default)]
+    struct Errors(RefCell<Vec<String>>);
+
+    impl ErrorHandler<ParseErrorImpl> for Errors {
+        fn error(&self, e: &ParseErrorImpl) -> Result<()> {
+            self.0.borrow_mut().push(e.to_string());
+            Ok(())
+        }
+    }
+
+    type Filter = XmlIdFilter<LocatorImpl, EventRecorder<LocatorImpl>, Errors>;
+
+    fn filter() -> Filter {
+        let filter = XmlIdFilter::new(EventRecorder::new(), Errors::default());
+        let mut locator = LocatorImpl::new();
+        locator.line_number = Some(3);
+        ContentHandler::<LocatorImpl, AttributesImpl>::set_document_locator(
+            &filter,
+            Rc::new(locator),
+        );
+        Event::StartDocument.dispatch(&filter).unwrap();
+        filter
+    }
+
+    /// Pass on the start of an element with an `xml:id` attribute, and return the type and
+    /// value of the attribute the handler received.
+    fn start(filter: &Filter, q_name: &str, id: &str) -> (String, String) {
+        let mut attributes = AttributesImpl::new();
+        attributes.add_attribute("", "class", "class", "CDATA", "c");
+        attributes.add_attribute(XMLNS, "id", "xml:id", "CDATA", id);
+        Event::start_element("", q_name, q_name, &attributes)
+            .dispatch(filter)
+            .unwrap();
+        match filter.get_content_handler().take_events().pop() {
+            Some(Event::StartElement { attributes, .. }) => {
+                assert_eq!(attributes.get_q_name_value("class").as_deref(), Some("c"));
+                (
+                    attributes.get_q_name_type("xml:id").unwrap(),
+                    attributes.get_q_name_value("xml:id").unwrap(),
+                )
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    fn errors(filter: &Filter) -> Vec<String> {
+        filter.get_error_handler().0.take()
+    }
+
+    #[test]
+    fn id_normalization() {
+        assert_eq!(normalize_id("  a  "), "a");
+        assert_eq!(normalize_id(" a  b c "), "a b c");
+        assert_eq!(normalize_id("   "), "");
+        let filter = filter();
+        assert_eq!(
+            start(&filter, "p", "  intro "),
+            ("ID".to_owned(), "intro".to_owned())
+        );
+        assert!(errors(&filter).is_empty());
+        assert_eq!(filter.lookup("intro").unwrap().q_name, "p");
+        assert!(filter.lookup("  intro ").is_none());
+    }
+
+    #[test]
+    fn ids_must_be_ncnames() {
+        let filter = filter();
+        for id in ["a b", "1a", "a:b", ""] {
+            assert_eq!(start(&filter, "p", id).1, id);
+        }
+        start(&filter, "p", "_a-1.b");
+        assert_eq!(
+            errors(&filter),
+            [
+                "3: xml:id \"a b\" is not an NCName",
+                "3: xml:id \"1a\" is not an NCName",
+                "3: xml:id \"a:b\" is not an NCName",
+                "3: xml:id \"\" is not an NCName",
+            ]
+        );
+        let index = filter.take_index();
+        assert_eq!(index.len(), 1);
+        assert!(index.contains("_a-1.b"));
+    }
+
+    #[test]
+    fn duplicate_ids() {
+        let filter = filter();
+        start(&filter, "p", "a");
+        start(&filter, "div", " a");
+        start(&filter, "span", "b");
+        assert_eq!(
+            errors(&filter),
+            ["3: xml:id \"a\" is already the identifier of element p on line 3"]
+        );
+        let index = filter.take_index();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.get("a").unwrap().q_name, "p");
+        assert_eq!(index.get("a").unwrap().location.line_number, Some(3));
+        assert!(filter.take_index().is_empty());
+    }
+
+    #[test]
+    fn index_is_cleared_for_another_document() {
+        let filter = filter();
+        start(&filter, "p", "a");
+        Event::EndDocument.dispatch(&filter).unwrap();
+        Event::StartDocument.dispatch(&filter).unwrap();
+        assert!(filter.lookup("a").is_none());
+        start(&filter, "p", "a");
+        assert!(errors(&filter).is_empty());
+    }
+
+    #[test]
+    fn without_namespace_processing() {
+        let filter = filter();
+        let mut attributes = AttributesImpl::new();
+        attributes.add_attribute("", "", "xml:id", "CDATA", " a ");
+        Event::start_element("", "", "p", &attributes)
+            .dispatch(&filter)
+            .unwrap();
+        match filter.get_content_handler().take_events().pop() {
+            Some(Event::StartElement { attributes, .. }) => {
+                assert_eq!(attributes.get_q_name_type("xml:id").as_deref(), Some("ID"));
+                assert_eq!(attributes.get_q_name_value("xml:id").as_deref(), Some("a"));
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(filter.lookup("a").is_some());
+    }
+}
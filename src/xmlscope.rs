@@ -0,0 +1,491 @@
+//! Tracking of inherited attributes such as `xml:lang` and `xml:space`.
+//!
+//! Some attributes apply to the element carrying them and to all its descendants, until a
+//! descendant overrides them: `xml:lang` gives the language of the content, and `xml:space`
+//! whether its white space is significant. Applications can define attributes of their own
+//! that behave the same way.
+//!
+//! `ScopeFilter` keeps track of the values of these attributes as the events of a document go
+//! through it, and hands the wrapped handler a `ScopeLocator` in place of the reader's
+//! locator, through which the values in scope are available during every event.
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use common::Locator;
+use helpers::LocatorProxy;
+use helpers::XMLNS;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::Result;
+
+/// The handling of white space requested by `xml:space`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Space {
+    /// `xml:space="default"`, or no `xml:space` in scope: the application's default handling
+    /// applies.
+    Default,
+    /// `xml:space="preserve"`: white space is significant and must be preserved.
+    Preserve,
+}
+
+/// The index of `xml:lang` among the tracked attributes.
+const LANG: usize = 0;
+
+/// The index of `xml:space` among the tracked attributes.
+const SPACE: usize = 1;
+
+struct State {
+    /// The namespace URIs and local names of the tracked attributes.
+    names: Vec<(String, String)>,
+    /// The values of the tracked attributes in scope for each open element, innermost last.
+    stack: Vec<Vec<Option<String>>>,
+}
+
+impl State {
+    fn value(&self, index: usize) -> Option<&str> {
+        self.stack
+            .last()
+            .and_then(|values| values[index].as_deref())
+    }
+
+    fn index(&self, uri: &str, local_name: &str) -> Option<usize> {
+        self.names
+            .iter()
+            .position(|(u, l)| u == uri && l == local_name)
+    }
+}
+
+/// A locator that also reports the values of the inherited attributes in scope at the current
+/// point of a document.
+///
+/// The values in scope for the current element take its own attributes into account, from its
+/// `start_element` event to its `end_element` event inclusive. The locator reports the
+/// position given by the reader's locator, if the reader provided one.
+pub struct ScopeLocator<L> {
+    locator: LocatorProxy<L>,
+    state: RefCell<State>,
+}
+
+impl<L: Locator> ScopeLocator<L> {
+    fn new() -> Self {
+        ScopeLocator {
+            locator: LocatorProxy::new(),
+            state: RefCell::new(State {
+                names: vec![
+                    (XMLNS.to_owned(), "lang".to_owned()),
+                    (XMLNS.to_owned(), "space".to_owned()),
+                ],
+                stack: Vec::new(),
+            }),
+        }
+    }
+
+    /// Return the language in scope, as given by `xml:lang`. Returns `None` if there is none,
+    /// or if it was reset with `xml:lang=""`.
+    pub fn get_lang(&self) -> Option<String> {
+        self.state
+            .borrow()
+            .value(LANG)
+            .filter(|lang| !lang.is_empty())
+            .map(str::to_owned)
+    }
+
+    /// Return the handling of white space in scope, as given by `xml:space`.
+    pub fn get_space(&self) -> Space {
+        match self.state.borrow().value(SPACE) {
+            Some("preserve") => Space::Preserve,
+            _ => Space::Default,
+        }
+    }
+
+    /// Return the value in scope of an attribute registered with `ScopeFilter::inherit`, or of
+    /// `xml:lang` or `xml:space`. Returns `None` if no element in scope carries it, or if the
+    /// attribute is not tracked.
+    pub fn get_inherited(&self, uri: &str, local_name: &str) -> Option<String> {
+        let state = self.state.borrow();
+        state
+            .index(uri, local_name)
+            .and_then(|index| state.value(index))
+            .map(str::to_owned)
+    }
+
+    /// Return the depth of the current element, 0 outside the document element.
+    pub fn depth(&self) -> usize {
+        self.state.borrow().stack.len()
+    }
+
+    /// Return the reader's locator, if it provided one.
+    pub fn get_locator(&self) -> Option<Rc<L>> {
+        self.locator.get_locator()
+    }
+}
+
+impl<L: Locator> Locator for ScopeLocator<L> {
+    fn get_column_number(&self) -> Option<u64> {
+        self.locator.get_column_number()
+    }
+    fn get_line_number(&self) -> Option<u64> {
+        self.locator.get_line_number()
+    }
+    fn get_public_id(&self) -> Option<String> {
+        self.locator.get_public_id()
+    }
+    fn get_system_id(&self) -> Option<String> {
+        self.locator.get_system_id()
+    }
+}
+
+/// A filter tracking `xml:lang`, `xml:space` and other inherited attributes for the handler it
+/// wraps.
+///
+/// Attributes are recognized by namespace URI, or for the attributes in the XML namespace by
+/// qualified name when the reader does not process namespaces. `xml:space` values other than
+/// `default` and `preserve` are ignored. The events are passed on unchanged.
+pub struct ScopeFilter<L, CH> {
+    handler: CH,
+    locator: Rc<ScopeLocator<L>>,
+}
+
+impl<L: Locator, CH> ScopeFilter<L, CH> {
+    /// Construct a filter passing events to a handler.
+    pub fn new(handler: CH) -> Self {
+        ScopeFilter {
+            handler,
+            locator: Rc::new(ScopeLocator::new()),
+        }
+    }
+
+    /// Return the wrapped handler.
+    pub fn get_content_handler(&self) -> &CH {
+        &self.handler
+    }
+
+    /// Return the locator handed to the wrapped handler.
+    pub fn get_locator(&self) -> Rc<ScopeLocator<L>> {
+        self.locator.clone()
+    }
+
+    /// Track an attribute, given by namespace URI and local name, as inherited by descendants.
+    ///
+    /// Attributes should be registered before the document starts.
+    pub fn inherit(&self, uri: &str, local_name: &str) {
+        let mut state = self.locator.state.borrow_mut();
+        if state.index(uri, local_name).is_none() {
+            state.names.push((uri.to_owned(), local_name.to_owned()));
+            for values in &mut state.stack {
+                values.push(None);
+            }
+        }
+    }
+}
+
+impl<L, A, CH> ContentHandler<L, A> for ScopeFilter<L, CH>
+where
+    L: Locator,
+    A: Attributes,
+    CH: ContentHandler<ScopeLocator<L>, A>,
+{
+    fn characters(&self, content: &str) -> Result<()> {
+        self.handler.characters(content)
+    }
+    fn end_document(&self) -> Result<()> {
+        self.handler.end_document()
+    }
+    fn end_element(&self, uri: &str, local_name: &str, q_name: &str) -> Result<()> {
+        let result = self.handler.end_element(uri, local_name, q_name);
+        self.locator.state.borrow_mut().stack.pop();
+        result
+    }
+    fn end_prefix_mapping(&self, prefix: &str) -> Result<()> {
+        self.handler.end_prefix_mapping(prefix)
+    }
+    fn ignorable_whitespace(&self, content: &str) -> Result<()> {
+        self.handler.ignorable_whitespace(content)
+    }
+    fn processing_instruction(&self, target: &str, data: &str) -> Result<()> {
+        self.handler.processing_instruction(target, data)
+    }
+    fn set_document_locator(&self, locator: Rc<L>) {
+        self.locator.locator.set_locator(locator);
+        self.handler.set_document_locator(self.locator.clone());
+    }
+    fn skipped_entity(&self, name: &str) -> Result<()> {
+        self.handler.skipped_entity(name)
+    }
+    fn start_document(&self) -> Result<()> {
+        self.locator.state.borrow_mut().stack.clear();
+        if self.locator.locator.get_locator().is_none() {
+            self.handler.set_document_locator(self.locator.clone());
+        }
+        self.handler.start_document()
+    }
+    fn start_element(
+        &self,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attributes: A,
+    ) -> Result<()> {
+        {
+            let mut state = self.locator.state.borrow_mut();
+            let mut values = match state.stack.last() {
+                Some(values) => values.clone(),
+                None => vec![None; state.names.len()],
+            };
+            for (index, (u, l)) in state.names.iter().enumerate() {
+                let value = attributes.get_ns_name_value(u, l).or_else(|| {
+                    if u == XMLNS {
+                        attributes.get_q_name_value(&format!("xml:{}", l))
+                    } else {
+                        None
+                    }
+                });
+                match value {
+                    Some(ref space)
+                        if index == SPACE && space != "default" && space != "preserve" => {}
+                    Some(value) => values[index] = Some(value),
+                    None => {}
+                }
+            }
+            state.stack.push(values);
+        }
+        self.handler
+            .start_element(uri, local_name, q_name, attributes)
+    }
+    fn start_prefix_mapping(&self, prefix: &str, uri: &str) -> Result<()> {
+        self.handler.start_prefix_mapping(prefix, uri)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event::Event;
+    use helpers::AttributesImpl;
+    use helpers::LocatorImpl;
+
+    const ITS: &str = "http://www.w3.org/2005/11/its";
+
+    /// An element boundary, with the language, white space handling and depth in scope.
+    type Scope = (String, Option<String>, Space, usize);
+
+    /// Records the values in scope at every element boundary.
+    #[derive(Default)]
+    struct Scopes {
+        locator: RefCell<Option<Rc<ScopeLocator<LocatorImpl>>>>,
+        seen: RefCell<Vec<Scope>>,
+    }
+
+    impl Scopes {
+        fn record(&self, what: String) {
+            let locator = self.locator.borrow();
+            let locator = locator.as_ref().unwrap();
+            self.seen.borrow_mut().push((
+                what,
+                locator.get_lang(),
+                locator.get_space(),
+                locator.depth(),
+            ));
+        }
+    }
+
+    impl ContentHandler<ScopeLocator<LocatorImpl>, AttributesImpl> for Scopes {
+        fn set_document_locator(&self, locator: Rc<ScopeLocator<LocatorImpl>>) {
+            *self.locator.borrow_mut() = Some(locator);
+        }
+        fn start_element(
+            &self,
+            _uri: &str,
+            _local_name: &str,
+            q_name: &str,
+            _attributes: AttributesImpl,
+        ) -> Result<()> {
+            self.record(q_name.to_owned());
+            Ok(())
+        }
+        fn end_element(&self, _uri: &str, _local_name: &str, q_name: &str) -> Result<()> {
+            self.record(format!("/{}", q_name));
+            Ok(())
+        }
+    }
+
+    type Filter = ScopeFilter<LocatorImpl, Scopes>;
+
+    fn start(q_name: &str, pairs: &[(&str, &str)]) -> Event {
+        let mut attributes = AttributesImpl::new();
+        for &(name, value) in pairs {
+            match name.strip_prefix("xml:") {
+                Some(local) => attributes.add_attribute(XMLNS, local, name, "CDATA", value),
+                None => attributes.add_attribute(ITS, name, name, "CDATA", value),
+            }
+        }
+        Event::start_element("", q_name, q_name, &attributes)
+    }
+
+    fn end(q_name: &str) -> Event {
+        Event::end_element("", q_name, q_name)
+    }
+
+    fn run(filter: &Filter, events: Vec<Event>) -> Vec<Scope> {
+        for event in events {
+            event.dispatch(filter).unwrap();
+        }
+        filter.get_content_handler().seen.take()
+    }
+
+    fn scope(what: &str, lang: Option<&str>, space: Space, depth: usize) -> Scope {
+        (what.to_owned(), lang.map(str::to_owned), space, depth)
+    }
+
+    #[test]
+    fn lang_inheritance() {
+        let filter = ScopeFilter::new(Scopes::default());
+        let seen = run(
+            &filter,
+            vec![
+                Event::StartDocument,
+                start("doc", &[("xml:lang", "en")]),
+                start("p", &[]),
+                end("p"),
+                start("p", &[("xml:lang", "fr")]),
+                start("q", &[]),
+                end("q"),
+                end("p"),
+                start("p", &[("xml:lang", "")]),
+                end("p"),
+                start("p", &[]),
+                end("p"),
+                end("doc"),
+            ],
+        );
+        assert_eq!(
+            seen,
+            [
+                scope("doc", Some("en"), Space::Default, 1),
+                scope("p", Some("en"), Space::Default, 2),
+                scope("/p", Some("en"), Space::Default, 2),
+                scope("p", Some("fr"), Space::Default, 2),
+                scope("q", Some("fr"), Space::Default, 3),
+                scope("/q", Some("fr"), Space::Default, 3),
+                scope("/p", Some("fr"), Space::Default, 2),
+                scope("p", None, Space::Default, 2),
+                scope("/p", None, Space::Default, 2),
+                scope("p", Some("en"), Space::Default, 2),
+                scope("/p", Some("en"), Space::Default, 2),
+                scope("/doc", Some("en"), Space::Default, 1),
+            ]
+        );
+        let locator = filter.get_locator();
+        assert_eq!(locator.get_lang(), None);
+        assert_eq!(locator.depth(), 0);
+    }
+
+    #[test]
+    fn space_inheritance() {
+        let filter = ScopeFilter::new(Scopes::default());
+        let seen = run(
+            &filter,
+            vec![
+                Event::StartDocument,
+                start("doc", &[]),
+                start("pre", &[("xml:space", "preserve")]),
+                start("b", &[]),
+                start("c", &[("xml:space", "default")]),
+                end("c"),
+                start("d", &[("xml:space", "bogus")]),
+                end("d"),
+                end("b"),
+                end("pre"),
+                start("p", &[]),
+                end("p"),
+                end("doc"),
+            ],
+        );
+        let spaces: Vec<_> = seen.iter().map(|s| (s.0.as_str(), s.2)).collect();
+        assert_eq!(
+            spaces,
+            [
+                ("doc", Space::Default),
+                ("pre", Space::Preserve),
+                ("b", Space::Preserve),
+                ("c", Space::Default),
+                ("/c", Space::Default),
+                ("d", Space::Preserve),
+                ("/d", Space::Preserve),
+                ("/b", Space::Preserve),
+                ("/pre", Space::Preserve),
+                ("p", Space::Default),
+                ("/p", Space::Default),
+                ("/doc", Space::Default),
+            ]
+        );
+    }
+
+    #[test]
+    fn application_attributes() {
+        let filter = ScopeFilter::new(Scopes::default());
+        filter.inherit(ITS, "translate");
+        run(
+            &filter,
+            vec![
+                Event::StartDocument,
+                start("doc", &[("translate", "no"), ("xml:lang", "de")]),
+                start("p", &[]),
+            ],
+        );
+        let locator = filter.get_locator();
+        assert_eq!(
+            locator.get_inherited(ITS, "translate").as_deref(),
+            Some("no")
+        );
+        assert_eq!(locator.get_inherited(XMLNS, "lang").as_deref(), Some("de"));
+        assert_eq!(locator.get_inherited(ITS, "dir"), None);
+        run(&filter, vec![start("q", &[("translate", "yes")])]);
+        assert_eq!(
+            locator.get_inherited(ITS, "translate").as_deref(),
+            Some("yes")
+        );
+        run(&filter, vec![end("q"), end("p")]);
+        assert_eq!(
+            locator.get_inherited(ITS, "translate").as_deref(),
+            Some("no")
+        );
+    }
+
+    #[test]
+    fn second_document() {
+        let filter = ScopeFilter::new(Scopes::default());
+        run(
+            &filter,
+            vec![
+                Event::StartDocument,
+                start("doc", &[("xml:lang", "en"), ("xml:space", "preserve")]),
+            ],
+        );
+        let seen = run(&filter, vec![Event::StartDocument, start("doc", &[])]);
+        assert_eq!(seen, [scope("doc", None, Space::Default, 1)]);
+    }
+
+    #[test]
+    fn qualified_names_without_namespace_processing() {
+        let filter = ScopeFilter::new(Scopes::default());
+        let mut locator = LocatorImpl::new();
+        locator.line_number = Some(2);
+        ContentHandler::<LocatorImpl, AttributesImpl>::set_document_locator(
+            &filter,
+            Rc::new(locator),
+        );
+        let mut attributes = AttributesImpl::new();
+        attributes.add_attribute("", "", "xml:lang", "CDATA", "nl");
+        attributes.add_attribute("", "", "xml:space", "CDATA", "preserve");
+        let seen = run(
+            &filter,
+            vec![
+                Event::StartDocument,
+                Event::start_element("", "", "doc", &attributes),
+            ],
+        );
+        assert_eq!(seen, [scope("doc", Some("nl"), Space::Preserve, 1)]);
+        assert_eq!(filter.get_locator().get_line_number(), Some(2));
+    }
+}
@@ -0,0 +1,1686 @@
+//! XPath 1.0 expressions, evaluated over a `tree::Document`.
+//!
+//! Expressions are compiled once with `XPath::compile`, which resolves the Namespace prefixes
+//! they use against a `NamespaceSupport`, and may then be evaluated any number of times against
+//! different documents and context nodes. Variables and extension functions are supplied
+//! through an `Environment`.
+//!
+//! The whole of XPath 1.0 is supported: all thirteen axes, the core function library, and the
+//! node-set, boolean, number and string types with their conversions and comparison rules.
+//!
+//! The `streaming` module matches a streamable subset of XPath against SAX events, without
+//! building a tree.
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use helpers::ErrorKind;
+use helpers::NamespaceSupport;
+use helpers::ParseErrorImpl;
+use helpers::XMLNS;
+use names;
+use tree::Document;
+use tree::NodeId;
+use tree::NodeKind;
+
+pub mod streaming;
+
+type XResult<T> = std::result::Result<T, ParseErrorImpl>;
+
+/// The result of evaluating an expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// A set of nodes, in document order, without duplicates.
+    NodeSet(Vec<NodeId>),
+    Boolean(bool),
+    Number(f64),
+    String(String),
+}
+
+impl Value {
+    /// Convert the value to a boolean, as by the `boolean()` function.
+    pub fn boolean(&self) -> bool {
+        match self {
+            Value::NodeSet(nodes) => !nodes.is_empty(),
+            Value::Boolean(b) => *b,
+            Value::Number(n) => *n != 0.0 && !n.is_nan(),
+            Value::String(s) => !s.is_empty(),
+        }
+    }
+
+    /// Convert the value to a number, as by the `number()` function.
+    pub fn number(&self, document: &Document) -> f64 {
+        match self {
+            Value::Boolean(true) => 1.0,
+            Value::Boolean(false) => 0.0,
+            Value::Number(n) => *n,
+            _ => string_to_number(&self.string(document)),
+        }
+    }
+
+    /// Convert the value to a string, as by the `string()` function.
+    pub fn string(&self, document: &Document) -> String {
+        match self {
+            Value::NodeSet(nodes) => nodes
+                .first()
+                .map(|node| document.string_value(*node))
+                .unwrap_or_default(),
+            Value::Boolean(b) => b.to_string(),
+            Value::Number(n) => number_to_string(*n),
+            Value::String(s) => s.clone(),
+        }
+    }
+}
+
+/// Convert a string to a number, following the rules of the `number()` function.
+pub fn string_to_number(s: &str) -> f64 {
+    let s = s.trim_matches(names::is_whitespace);
+    let unsigned = s.strip_prefix('-').unwrap_or(s);
+    let mut parts = unsigned.splitn(2, '.');
+    let integer = parts.next().unwrap_or("");
+    let fraction = parts.next();
+    let digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    let valid = digits(integer)
+        && fraction.is_none_or(digits)
+        && (!integer.is_empty() || fraction.is_some_and(|f| !f.is_empty()));
+    if valid {
+        s.parse().unwrap_or(f64::NAN)
+    } else {
+        f64::NAN
+    }
+}
+
+/// Convert a number to a string, following the rules of the `string()` function.
+pub fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_owned()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.to_owned()
+    } else if n == 0.0 {
+        "0".to_owned()
+    } else {
+        n.to_string()
+    }
+}
+
+/// An extension function.
+///
+/// The function receives the document, the context node and the values of its arguments.
+pub type Function =
+    Rc<dyn Fn(&Document, NodeId, &[Value]) -> std::result::Result<Value, ParseErrorImpl>>;
+
+/// The variables and extension functions available to expressions.
+///
+/// Variables are named by their local name if they are not in a Namespace, and by
+/// `{uri}local-name` otherwise. Extension functions must be in a Namespace, so that they cannot
+/// be confused with the core functions: they are named `{uri}local-name`, and called from
+/// expressions with a prefix bound to that URI.
+#[derive(Clone, Default)]
+pub struct Environment {
+    variables: HashMap<String, Value>,
+    functions: HashMap<String, Function>,
+}
+
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Environment")
+            .field("variables", &self.variables)
+            .field(
+                "functions",
+                &self.functions.keys().collect::<Vec<&String>>(),
+            )
+            .finish()
+    }
+}
+
+impl Environment {
+    /// Construct an environment without any variables or functions.
+    pub fn new() -> Self {
+        Environment::default()
+    }
+
+    /// Register an extension function, replacing any previous function with the same name.
+    pub fn set_function<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(&Document, NodeId, &[Value]) -> std::result::Result<Value, ParseErrorImpl> + 'static,
+    {
+        self.functions.insert(name.to_owned(), Rc::new(function));
+    }
+
+    /// Return an extension function.
+    pub fn get_function(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    /// Remove an extension function, returning it.
+    pub fn remove_function(&mut self, name: &str) -> Option<Function> {
+        self.functions.remove(name)
+    }
+
+    /// Bind a variable, replacing any previous binding.
+    pub fn set_variable(&mut self, name: &str, value: Value) {
+        self.variables.insert(name.to_owned(), value);
+    }
+
+    /// Return the value of a variable.
+    pub fn get_variable(&self, name: &str) -> Option<&Value> {
+        self.variables.get(name)
+    }
+
+    /// Remove a variable binding, returning its value.
+    pub fn remove_variable(&mut self, name: &str) -> Option<Value> {
+        self.variables.remove(name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Axis {
+    Ancestor,
+    AncestorOrSelf,
+    Attribute,
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    Following,
+    FollowingSibling,
+    Namespace,
+    Parent,
+    Preceding,
+    PrecedingSibling,
+    Itself,
+}
+
+impl Axis {
+    fn from_name(name: &str) -> Option<Axis> {
+        Some(match name {
+            "ancestor" => Axis::Ancestor,
+            "ancestor-or-self" => Axis::AncestorOrSelf,
+            "attribute" => Axis::Attribute,
+            "child" => Axis::Child,
+            "descendant" => Axis::Descendant,
+            "descendant-or-self" => Axis::DescendantOrSelf,
+            "following" => Axis::Following,
+            "following-sibling" => Axis::FollowingSibling,
+            "namespace" => Axis::Namespace,
+            "parent" => Axis::Parent,
+            "preceding" => Axis::Preceding,
+            "preceding-sibling" => Axis::PrecedingSibling,
+            "self" => Axis::Itself,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum NodeTest {
+    /// A name test with a Namespace URI and local name.
+    Name(String, String),
+    /// A `prefix:*` name test.
+    Namespace(String),
+    /// The `*` name test.
+    Any,
+    Node,
+    Text,
+    Comment,
+    ProcessingInstruction(Option<String>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Step {
+    axis: Axis,
+    test: NodeTest,
+    predicates: Vec<Expr>,
+}
+
+impl Step {
+    fn descendant_or_self() -> Step {
+        Step {
+            axis: Axis::DescendantOrSelf,
+            test: NodeTest::Node,
+            predicates: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CompareOp {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ArithmeticOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum PathStart {
+    Root,
+    Context,
+    Expr(Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Compare(CompareOp, Box<Expr>, Box<Expr>),
+    Arithmetic(ArithmeticOp, Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Union(Box<Expr>, Box<Expr>),
+    Path(PathStart, Vec<Step>),
+    Filter(Box<Expr>, Vec<Expr>),
+    Literal(String),
+    Number(f64),
+    Variable(String),
+    Function(String, Vec<Expr>),
+}
+
+/// The core functions, with their minimum and maximum number of arguments.
+const FUNCTIONS: &[(&str, usize, usize)] = &[
+    ("last", 0, 0),
+    ("position", 0, 0),
+    ("count", 1, 1),
+    ("id", 1, 1),
+    ("local-name", 0, 1),
+    ("namespace-uri", 0, 1),
+    ("name", 0, 1),
+    ("string", 0, 1),
+    ("concat", 2, usize::MAX),
+    ("starts-with", 2, 2),
+    ("contains", 2, 2),
+    ("substring-before", 2, 2),
+    ("substring-after", 2, 2),
+    ("substring", 2, 3),
+    ("string-length", 0, 1),
+    ("normalize-space", 0, 1),
+    ("translate", 3, 3),
+    ("boolean", 1, 1),
+    ("not", 1, 1),
+    ("lang", 1, 1),
+    ("true", 0, 0),
+    ("false", 0, 0),
+    ("number", 0, 1),
+    ("sum", 1, 1),
+    ("floor", 1, 1),
+    ("ceiling", 1, 1),
+    ("round", 1, 1),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Literal(String),
+    Variable(String),
+    /// An operator, including `/`, `//` and `|`.
+    Operator(&'static str),
+    Punct(&'static str),
+    /// The `*` name test.
+    Star,
+    /// A name test, which may be prefixed.
+    Name(String),
+    /// A `prefix:*` name test.
+    NsWildcard(String),
+    NodeType(String),
+    Function(String),
+    Axis(String),
+}
+
+const OPERATORS: &[&str] = &["//", "!=", "<=", ">=", "/", "|", "+", "-", "=", "<", ">"];
+
+const OPERATOR_NAMES: &[&str] = &["and", "or", "mod", "div"];
+
+fn tokenize(source: &str) -> XResult<Vec<Token>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut i = 0;
+    let ncname_start = |c: char| c != ':' && names::is_name_start_char(c);
+    let ncname_char = |c: char| c != ':' && names::is_name_char(c);
+    let read_ncname = |i: &mut usize| -> String {
+        let start = *i;
+        while *i < chars.len() && ncname_char(chars[*i]) {
+            *i += 1;
+        }
+        chars[start..*i].iter().collect()
+    };
+    loop {
+        while i < chars.len() && names::is_whitespace(chars[i]) {
+            i += 1;
+        }
+        if i >= chars.len() {
+            return Ok(tokens);
+        }
+        // A '*' or a name is an operator if it follows a token that can end an operand.
+        let operator_context = match tokens.last() {
+            None | Some(Token::Operator(_)) => false,
+            Some(Token::Punct(punct)) => !["@", "::", "(", "[", ","].contains(punct),
+            _ => true,
+        };
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+        let token = match c {
+            '(' | ')' | '[' | ']' | ',' | '@' => {
+                i += 1;
+                Token::Punct(match c {
+                    '(' => "(",
+                    ')' => ")",
+                    '[' => "[",
+                    ']' => "]",
+                    ',' => ",",
+                    _ => "@",
+                })
+            }
+            '.' if next == Some('.') => {
+                i += 2;
+                Token::Punct("..")
+            }
+            '.' if !next.is_some_and(|n| n.is_ascii_digit()) => {
+                i += 1;
+                Token::Punct(".")
+            }
+            '0'..='9' | '.' => {
+                let start = i;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+                if i < chars.len() && chars[i] == '.' {
+                    i += 1;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+                let number: String = chars[start..i].iter().collect();
+                Token::Number(number.parse().unwrap_or(f64::NAN))
+            }
+            '"' | '\'' => {
+                let start = i + 1;
+                let end = chars[start..].iter().position(|q| *q == c).ok_or_else(|| {
+                    ParseErrorImpl::of_kind(ErrorKind::Expression, "unterminated string literal")
+                })?;
+                i = start + end + 1;
+                Token::Literal(chars[start..start + end].iter().collect())
+            }
+            '$' => {
+                i += 1;
+                if i >= chars.len() || !ncname_start(chars[i]) {
+                    return Err(ParseErrorImpl::of_kind(
+                        ErrorKind::Expression,
+                        "expected a variable name after '$'",
+                    ));
+                }
+                let mut name = read_ncname(&mut i);
+                if chars.get(i) == Some(&':') && chars.get(i + 1).is_some_and(|c| ncname_start(*c))
+                {
+                    i += 1;
+                    name.push(':');
+                    name.push_str(&read_ncname(&mut i));
+                }
+                Token::Variable(name)
+            }
+            ':' if next == Some(':') => {
+                i += 2;
+                Token::Punct("::")
+            }
+            '*' if operator_context => {
+                i += 1;
+                Token::Operator("*")
+            }
+            '*' => {
+                i += 1;
+                Token::Star
+            }
+            _ if ncname_start(c) => {
+                let mut name = read_ncname(&mut i);
+                if operator_context {
+                    match OPERATOR_NAMES.iter().find(|op| **op == name) {
+                        Some(op) => Token::Operator(op),
+                        None => {
+                            return Err(ParseErrorImpl::of_kind(
+                                ErrorKind::Expression,
+                                format!("unexpected name \"{}\"", name),
+                            ))
+                        }
+                    }
+                } else if chars.get(i) == Some(&':') && chars.get(i + 1) == Some(&'*') {
+                    i += 2;
+                    Token::NsWildcard(name)
+                } else {
+                    if chars.get(i) == Some(&':')
+                        && chars.get(i + 1).is_some_and(|c| ncname_start(*c))
+                    {
+                        i += 1;
+                        name.push(':');
+                        name.push_str(&read_ncname(&mut i));
+                    }
+                    let mut j = i;
+                    while j < chars.len() && names::is_whitespace(chars[j]) {
+                        j += 1;
+                    }
+                    match chars.get(j) {
+                        Some('(') => match name.as_str() {
+                            "comment" | "text" | "processing-instruction" | "node" => {
+                                Token::NodeType(name)
+                            }
+                            _ => Token::Function(name),
+                        },
+                        Some(':') if chars.get(j + 1) == Some(&':') => Token::Axis(name),
+                        _ => Token::Name(name),
+                    }
+                }
+            }
+            _ => {
+                let operator = OPERATORS
+                    .iter()
+                    .copied()
+                    .find(|op| {
+                        op.chars()
+                            .enumerate()
+                            .all(|(k, oc)| chars.get(i + k) == Some(&oc))
+                    })
+                    .ok_or_else(|| {
+                        ParseErrorImpl::of_kind(
+                            ErrorKind::Expression,
+                            format!("unexpected character '{}'", c),
+                        )
+                    })?;
+                i += operator.len();
+                Token::Operator(operator)
+            }
+        };
+        tokens.push(token);
+    }
+}
+
+fn expanded_name(uri: &str, local_name: &str) -> String {
+    if uri.is_empty() {
+        local_name.to_owned()
+    } else {
+        format!("{{{}}}{}", uri, local_name)
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    namespaces: &'a NamespaceSupport,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn at(&self, token: &Token) -> bool {
+        self.peek() == Some(token)
+    }
+
+    fn at_operator(&self, operators: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Operator(op)) if operators.contains(op) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, punct: &'static str) -> XResult<()> {
+        if self.at(&Token::Punct(punct)) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(ParseErrorImpl::of_kind(
+                ErrorKind::Expression,
+                format!("expected '{}'", punct),
+            ))
+        }
+    }
+
+    fn resolve(&self, q_name: &str) -> XResult<(String, String)> {
+        match q_name.find(':') {
+            Some(i) => match self.namespaces.get_uri(&q_name[..i]) {
+                Some(uri) if !uri.is_empty() => Ok((uri.to_owned(), q_name[i + 1..].to_owned())),
+                _ => Err(ParseErrorImpl::of_kind(
+                    ErrorKind::Expression,
+                    format!("undeclared prefix \"{}\"", &q_name[..i]),
+                )),
+            },
+            None => Ok((String::new(), q_name.to_owned())),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        operators: &[&'static str],
+        operand: fn(&mut Self) -> XResult<Expr>,
+        combine: fn(&'static str, Expr, Expr) -> Expr,
+    ) -> XResult<Expr> {
+        let mut left = operand(self)?;
+        while let Some(op) = self.at_operator(operators) {
+            self.position += 1;
+            let right = operand(self)?;
+            left = combine(op, left, right);
+        }
+        Ok(left)
+    }
+
+    fn or_expr(&mut self) -> XResult<Expr> {
+        self.binary(&["or"], Parser::and_expr, |_, a, b| {
+            Expr::Or(Box::new(a), Box::new(b))
+        })
+    }
+
+    fn and_expr(&mut self) -> XResult<Expr> {
+        self.binary(&["and"], Parser::equality_expr, |_, a, b| {
+            Expr::And(Box::new(a), Box::new(b))
+        })
+    }
+
+    fn equality_expr(&mut self) -> XResult<Expr> {
+        self.binary(&["=", "!="], Parser::relational_expr, |op, a, b| {
+            let op = if op == "=" {
+                CompareOp::Equal
+            } else {
+                CompareOp::NotEqual
+            };
+            Expr::Compare(op, Box::new(a), Box::new(b))
+        })
+    }
+
+    fn relational_expr(&mut self) -> XResult<Expr> {
+        self.binary(
+            &["<", "<=", ">", ">="],
+            Parser::additive_expr,
+            |op, a, b| {
+                let op = match op {
+                    "<" => CompareOp::Less,
+                    "<=" => CompareOp::LessOrEqual,
+                    ">" => CompareOp::Greater,
+                    _ => CompareOp::GreaterOrEqual,
+                };
+                Expr::Compare(op, Box::new(a), Box::new(b))
+            },
+        )
+    }
+
+    fn additive_expr(&mut self) -> XResult<Expr> {
+        self.binary(&["+", "-"], Parser::multiplicative_expr, |op, a, b| {
+            let op = if op == "+" {
+                ArithmeticOp::Add
+            } else {
+                ArithmeticOp::Subtract
+            };
+            Expr::Arithmetic(op, Box::new(a), Box::new(b))
+        })
+    }
+
+    fn multiplicative_expr(&mut self) -> XResult<Expr> {
+        self.binary(&["*", "div", "mod"], Parser::unary_expr, |op, a, b| {
+            let op = match op {
+                "*" => ArithmeticOp::Multiply,
+                "div" => ArithmeticOp::Divide,
+                _ => ArithmeticOp::Modulo,
+            };
+            Expr::Arithmetic(op, Box::new(a), Box::new(b))
+        })
+    }
+
+    fn unary_expr(&mut self) -> XResult<Expr> {
+        if self.at_operator(&["-"]).is_some() {
+            self.position += 1;
+            Ok(Expr::Negate(Box::new(self.unary_expr()?)))
+        } else {
+            self.union_expr()
+        }
+    }
+
+    fn union_expr(&mut self) -> XResult<Expr> {
+        self.binary(&["|"], Parser::path_expr, |_, a, b| {
+            Expr::Union(Box::new(a), Box::new(b))
+        })
+    }
+
+    fn path_expr(&mut self) -> XResult<Expr> {
+        match self.peek() {
+            Some(Token::Variable(_))
+            | Some(Token::Literal(_))
+            | Some(Token::Number(_))
+            | Some(Token::Function(_))
+            | Some(Token::Punct("(")) => {
+                let primary = self.primary_expr()?;
+                let predicates = self.predicates()?;
+                let filter = if predicates.is_empty() {
+                    primary
+                } else {
+                    Expr::Filter(Box::new(primary), predicates)
+                };
+                if self.at_operator(&["/", "//"]).is_some() {
+                    let mut steps = Vec::new();
+                    self.continue_path(&mut steps)?;
+                    Ok(Expr::Path(PathStart::Expr(Box::new(filter)), steps))
+                } else {
+                    Ok(filter)
+                }
+            }
+            _ => self.location_path(),
+        }
+    }
+
+    fn continue_path(&mut self, steps: &mut Vec<Step>) -> XResult<()> {
+        while let Some(op) = self.at_operator(&["/", "//"]) {
+            self.position += 1;
+            if op == "//" {
+                steps.push(Step::descendant_or_self());
+            }
+            steps.push(self.step()?);
+        }
+        Ok(())
+    }
+
+    fn location_path(&mut self) -> XResult<Expr> {
+        let mut steps = Vec::new();
+        let start = match self.at_operator(&["/", "//"]) {
+            Some("/") => {
+                self.position += 1;
+                if !self.at_step() {
+                    return Ok(Expr::Path(PathStart::Root, steps));
+                }
+                PathStart::Root
+            }
+            Some(_) => {
+                self.position += 1;
+                steps.push(Step::descendant_or_self());
+                PathStart::Root
+            }
+            None => PathStart::Context,
+        };
+        steps.push(self.step()?);
+        self.continue_path(&mut steps)?;
+        Ok(Expr::Path(start, steps))
+    }
+
+    fn at_step(&self) -> bool {
+        match self.peek() {
+            Some(Token::Punct(punct)) => [".", "..", "@"].contains(punct),
+            Some(Token::Axis(_))
+            | Some(Token::Star)
+            | Some(Token::Name(_))
+            | Some(Token::NsWildcard(_))
+            | Some(Token::NodeType(_)) => true,
+            _ => false,
+        }
+    }
+
+    fn step(&mut self) -> XResult<Step> {
+        let axis = match self.peek() {
+            Some(Token::Punct(".")) | Some(Token::Punct("..")) => {
+                let axis = if self.next() == Some(Token::Punct(".")) {
+                    Axis::Itself
+                } else {
+                    Axis::Parent
+                };
+                return Ok(Step {
+                    axis,
+                    test: NodeTest::Node,
+                    predicates: Vec::new(),
+                });
+            }
+            Some(Token::Punct("@")) => {
+                self.position += 1;
+                Axis::Attribute
+            }
+            Some(Token::Axis(name)) => {
+                let axis = Axis::from_name(name).ok_or_else(|| {
+                    ParseErrorImpl::of_kind(
+                        ErrorKind::Expression,
+                        format!("unknown axis \"{}\"", name),
+                    )
+                })?;
+                self.position += 1;
+                self.expect("::")?;
+                axis
+            }
+            _ => Axis::Child,
+        };
+        let test = match self.next() {
+            Some(Token::Star) => NodeTest::Any,
+            Some(Token::Name(name)) => {
+                let (uri, local_name) = self.resolve(&name)?;
+                NodeTest::Name(uri, local_name)
+            }
+            Some(Token::NsWildcard(prefix)) => {
+                let (uri, _) = self.resolve(&format!("{}:*", prefix))?;
+                NodeTest::Namespace(uri)
+            }
+            Some(Token::NodeType(node_type)) => {
+                self.expect("(")?;
+                let test = match node_type.as_str() {
+                    "comment" => NodeTest::Comment,
+                    "text" => NodeTest::Text,
+                    "node" => NodeTest::Node,
+                    _ => match self.peek() {
+                        Some(Token::Literal(target)) => {
+                            let target = target.clone();
+                            self.position += 1;
+                            NodeTest::ProcessingInstruction(Some(target))
+                        }
+                        _ => NodeTest::ProcessingInstruction(None),
+                    },
+                };
+                self.expect(")")?;
+                test
+            }
+            _ => {
+                return Err(ParseErrorImpl::of_kind(
+                    ErrorKind::Expression,
+                    "expected a node test",
+                ))
+            }
+        };
+        let predicates = self.predicates()?;
+        Ok(Step {
+            axis,
+            test,
+            predicates,
+        })
+    }
+
+    fn predicates(&mut self) -> XResult<Vec<Expr>> {
+        let mut predicates = Vec::new();
+        while self.at(&Token::Punct("[")) {
+            self.position += 1;
+            predicates.push(self.or_expr()?);
+            self.expect("]")?;
+        }
+        Ok(predicates)
+    }
+
+    fn primary_expr(&mut self) -> XResult<Expr> {
+        match self.next() {
+            Some(Token::Variable(name)) => {
+                let (uri, local_name) = self.resolve(&name)?;
+                Ok(Expr::Variable(expanded_name(&uri, &local_name)))
+            }
+            Some(Token::Literal(literal)) => Ok(Expr::Literal(literal)),
+            Some(Token::Number(number)) => Ok(Expr::Number(number)),
+            Some(Token::Punct("(")) => {
+                let expr = self.or_expr()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Function(name)) => {
+                self.expect("(")?;
+                let mut arguments = Vec::new();
+                if !self.at(&Token::Punct(")")) {
+                    arguments.push(self.or_expr()?);
+                    while self.at(&Token::Punct(",")) {
+                        self.position += 1;
+                        arguments.push(self.or_expr()?);
+                    }
+                }
+                self.expect(")")?;
+                if name.contains(':') {
+                    let (uri, local_name) = self.resolve(&name)?;
+                    return Ok(Expr::Function(expanded_name(&uri, &local_name), arguments));
+                }
+                let &(_, min, max) = FUNCTIONS
+                    .iter()
+                    .find(|function| function.0 == name)
+                    .ok_or_else(|| {
+                        ParseErrorImpl::of_kind(
+                            ErrorKind::Expression,
+                            format!("unknown function \"{}\"", name),
+                        )
+                    })?;
+                if arguments.len() < min || arguments.len() > max {
+                    return Err(ParseErrorImpl::of_kind(
+                        ErrorKind::Expression,
+                        format!("wrong number of arguments to \"{}\"", name),
+                    ));
+                }
+                Ok(Expr::Function(name, arguments))
+            }
+            _ => Err(ParseErrorImpl::of_kind(
+                ErrorKind::Expression,
+                "expected an expression",
+            )),
+        }
+    }
+}
+
+/// A compiled XPath expression.
+#[derive(Clone, Debug, PartialEq)]
+pub struct XPath {
+    source: String,
+    expr: Expr,
+}
+
+impl XPath {
+    /// Compile an expression, resolving the Namespace prefixes it uses against `namespaces`.
+    ///
+    /// As in XPath 1.0, the default Namespace does not apply to unprefixed names.
+    pub fn compile(source: &str, namespaces: &NamespaceSupport) -> XResult<XPath> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            namespaces,
+        };
+        let expr = parser.or_expr()?;
+        if parser.position < parser.tokens.len() {
+            return Err(ParseErrorImpl::of_kind(
+                ErrorKind::Expression,
+                format!("unexpected input in expression \"{}\"", source),
+            ));
+        }
+        Ok(XPath {
+            source: source.to_owned(),
+            expr,
+        })
+    }
+
+    /// Compile an XSLT-style match pattern.
+    ///
+    /// Relative location paths in the pattern are anchored anywhere in the document, so that
+    /// evaluating the compiled expression from any node selects all the nodes that match the
+    /// pattern: `line/@id` is compiled as `//line/@id`.
+    pub fn compile_pattern(source: &str, namespaces: &NamespaceSupport) -> XResult<XPath> {
+        fn anchor(expr: &mut Expr) {
+            match expr {
+                Expr::Union(a, b) => {
+                    anchor(a);
+                    anchor(b);
+                }
+                Expr::Path(start, steps) if *start == PathStart::Context => {
+                    *start = PathStart::Root;
+                    steps.insert(0, Step::descendant_or_self());
+                }
+                _ => {}
+            }
+        }
+        let mut xpath = XPath::compile(source, namespaces)?;
+        anchor(&mut xpath.expr);
+        Ok(xpath)
+    }
+
+    /// Return the source text of the expression.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Evaluate the expression with the given context node.
+    pub fn evaluate(
+        &self,
+        document: &Document,
+        node: NodeId,
+        environment: &Environment,
+    ) -> XResult<Value> {
+        let evaluator = Evaluator {
+            document,
+            environment,
+        };
+        evaluator.eval(
+            &self.expr,
+            &Context {
+                node,
+                position: 1,
+                size: 1,
+            },
+        )
+    }
+
+    /// Evaluate the expression and convert the result to a boolean.
+    pub fn evaluate_boolean(
+        &self,
+        document: &Document,
+        node: NodeId,
+        environment: &Environment,
+    ) -> XResult<bool> {
+        Ok(self.evaluate(document, node, environment)?.boolean())
+    }
+
+    /// Evaluate the expression and convert the result to a string.
+    pub fn evaluate_string(
+        &self,
+        document: &Document,
+        node: NodeId,
+        environment: &Environment,
+    ) -> XResult<String> {
+        Ok(self.evaluate(document, node, environment)?.string(document))
+    }
+
+    /// Evaluate an expression that returns a node-set.
+    pub fn select(
+        &self,
+        document: &Document,
+        node: NodeId,
+        environment: &Environment,
+    ) -> XResult<Vec<NodeId>> {
+        match self.evaluate(document, node, environment)? {
+            Value::NodeSet(nodes) => Ok(nodes),
+            _ => Err(ParseErrorImpl::of_kind(
+                ErrorKind::Expression,
+                format!("expression \"{}\" does not return a node-set", self.source),
+            )),
+        }
+    }
+}
+
+struct Context {
+    node: NodeId,
+    position: usize,
+    size: usize,
+}
+
+struct Evaluator<'a> {
+    document: &'a Document,
+    environment: &'a Environment,
+}
+
+impl<'a> Evaluator<'a> {
+    fn eval(&self, expr: &Expr, context: &Context) -> XResult<Value> {
+        Ok(match expr {
+            Expr::Or(a, b) => {
+                Value::Boolean(self.eval(a, context)?.boolean() || self.eval(b, context)?.boolean())
+            }
+            Expr::And(a, b) => {
+                Value::Boolean(self.eval(a, context)?.boolean() && self.eval(b, context)?.boolean())
+            }
+            Expr::Compare(op, a, b) => {
+                let a = self.eval(a, context)?;
+                let b = self.eval(b, context)?;
+                Value::Boolean(self.compare(*op, &a, &b))
+            }
+            Expr::Arithmetic(op, a, b) => {
+                let a = self.eval(a, context)?.number(self.document);
+                let b = self.eval(b, context)?.number(self.document);
+                Value::Number(match op {
+                    ArithmeticOp::Add => a + b,
+                    ArithmeticOp::Subtract => a - b,
+                    ArithmeticOp::Multiply => a * b,
+                    ArithmeticOp::Divide => a / b,
+                    ArithmeticOp::Modulo => a % b,
+                })
+            }
+            Expr::Negate(a) => Value::Number(-self.eval(a, context)?.number(self.document)),
+            Expr::Union(a, b) => {
+                let mut nodes = self.node_set(a, context)?;
+                nodes.extend(self.node_set(b, context)?);
+                nodes.sort();
+                nodes.dedup();
+                Value::NodeSet(nodes)
+            }
+            Expr::Path(start, steps) => {
+                let mut nodes = match start {
+                    PathStart::Root => vec![self.document.root()],
+                    PathStart::Context => vec![context.node],
+                    PathStart::Expr(expr) => self.node_set(expr, context)?,
+                };
+                for step in steps {
+                    nodes = self.step(&nodes, step)?;
+                }
+                Value::NodeSet(nodes)
+            }
+            Expr::Filter(primary, predicates) => {
+                let nodes = self.node_set(primary, context)?;
+                Value::NodeSet(self.filter(nodes, predicates)?)
+            }
+            Expr::Literal(literal) => Value::String(literal.clone()),
+            Expr::Number(number) => Value::Number(*number),
+            Expr::Variable(name) => {
+                self.environment
+                    .get_variable(name)
+                    .cloned()
+                    .ok_or_else(|| {
+                        ParseErrorImpl::of_kind(
+                            ErrorKind::Expression,
+                            format!("undefined variable \"{}\"", name),
+                        )
+                    })?
+            }
+            Expr::Function(name, arguments) => self.function(name, arguments, context)?,
+        })
+    }
+
+    fn node_set(&self, expr: &Expr, context: &Context) -> XResult<Vec<NodeId>> {
+        match self.eval(expr, context)? {
+            Value::NodeSet(nodes) => Ok(nodes),
+            _ => Err(ParseErrorImpl::of_kind(
+                ErrorKind::Expression,
+                "expression does not return a node-set",
+            )),
+        }
+    }
+
+    fn filter(&self, mut nodes: Vec<NodeId>, predicates: &[Expr]) -> XResult<Vec<NodeId>> {
+        for predicate in predicates {
+            let size = nodes.len();
+            let mut kept = Vec::with_capacity(size);
+            for (index, node) in nodes.into_iter().enumerate() {
+                let context = Context {
+                    node,
+                    position: index + 1,
+                    size,
+                };
+                let keep = match self.eval(predicate, &context)? {
+                    Value::Number(n) => n == context.position as f64,
+                    value => value.boolean(),
+                };
+                if keep {
+                    kept.push(node);
+                }
+            }
+            nodes = kept;
+        }
+        Ok(nodes)
+    }
+
+    fn step(&self, nodes: &[NodeId], step: &Step) -> XResult<Vec<NodeId>> {
+        let mut result = Vec::new();
+        for node in nodes {
+            let candidates = self
+                .axis(*node, step.axis)
+                .into_iter()
+                .filter(|candidate| self.matches(*candidate, &step.test, step.axis))
+                .collect();
+            result.extend(self.filter(candidates, &step.predicates)?);
+        }
+        result.sort();
+        result.dedup();
+        Ok(result)
+    }
+
+    /// Return the nodes on an axis, in the order of the axis: reverse document order for the
+    /// reverse axes.
+    fn axis(&self, node: NodeId, axis: Axis) -> Vec<NodeId> {
+        let document = self.document;
+        let mut nodes = Vec::new();
+        match axis {
+            Axis::Child => nodes.extend_from_slice(document.children(node)),
+            Axis::Attribute => nodes.extend_from_slice(document.attributes(node)),
+            Axis::Itself => nodes.push(node),
+            Axis::Parent => nodes.extend(document.parent(node)),
+            Axis::Descendant | Axis::DescendantOrSelf => {
+                if axis == Axis::DescendantOrSelf {
+                    nodes.push(node);
+                }
+                self.descendants(node, &mut nodes);
+            }
+            Axis::Ancestor | Axis::AncestorOrSelf => {
+                if axis == Axis::AncestorOrSelf {
+                    nodes.push(node);
+                }
+                let mut current = document.parent(node);
+                while let Some(ancestor) = current {
+                    nodes.push(ancestor);
+                    current = document.parent(ancestor);
+                }
+            }
+            Axis::Namespace => nodes.extend_from_slice(document.namespace_nodes(node)),
+            Axis::FollowingSibling | Axis::PrecedingSibling => {
+                if let (Some(parent), false) = (document.parent(node), self.is_attached(node)) {
+                    let siblings = document.children(parent);
+                    let index = siblings.iter().position(|sibling| *sibling == node);
+                    let index = index.expect("a node is a child of its parent");
+                    if axis == Axis::FollowingSibling {
+                        nodes.extend_from_slice(&siblings[index + 1..]);
+                    } else {
+                        nodes.extend(siblings[..index].iter().rev());
+                    }
+                }
+            }
+            Axis::Following => nodes.extend(document.nodes().filter(|other| {
+                *other > node && !self.is_attached(*other) && !self.is_ancestor(node, *other)
+            })),
+            Axis::Preceding => nodes.extend(
+                document
+                    .nodes()
+                    .take_while(|other| *other < node)
+                    .filter(|other| !self.is_attached(*other) && !self.is_ancestor(*other, node))
+                    .collect::<Vec<NodeId>>()
+                    .into_iter()
+                    .rev(),
+            ),
+        }
+        nodes
+    }
+
+    /// Return whether a node is an attribute or Namespace node, which are attached to an
+    /// element without being its children.
+    fn is_attached(&self, node: NodeId) -> bool {
+        matches!(
+            self.document.kind(node),
+            NodeKind::Attribute { .. } | NodeKind::Namespace { .. }
+        )
+    }
+
+    fn is_ancestor(&self, ancestor: NodeId, node: NodeId) -> bool {
+        let mut current = self.document.parent(node);
+        while let Some(parent) = current {
+            if parent == ancestor {
+                return true;
+            }
+            current = self.document.parent(parent);
+        }
+        false
+    }
+
+    fn descendants(&self, node: NodeId, nodes: &mut Vec<NodeId>) {
+        for child in self.document.children(node) {
+            nodes.push(*child);
+            self.descendants(*child, nodes);
+        }
+    }
+
+    fn matches(&self, node: NodeId, test: &NodeTest, axis: Axis) -> bool {
+        let kind = self.document.kind(node);
+        let principal = match kind {
+            NodeKind::Attribute { .. } => axis == Axis::Attribute,
+            NodeKind::Namespace { .. } => axis == Axis::Namespace,
+            NodeKind::Element { .. } => axis != Axis::Attribute && axis != Axis::Namespace,
+            _ => false,
+        };
+        match test {
+            NodeTest::Name(uri, local_name) => {
+                principal
+                    && self.document.namespace_uri(node) == uri
+                    && self.document.local_name(node) == local_name
+            }
+            NodeTest::Namespace(uri) => principal && self.document.namespace_uri(node) == uri,
+            NodeTest::Any => principal,
+            NodeTest::Node => true,
+            NodeTest::Text => matches!(kind, NodeKind::Text(_)),
+            NodeTest::Comment => matches!(kind, NodeKind::Comment(_)),
+            NodeTest::ProcessingInstruction(target) => match kind {
+                NodeKind::ProcessingInstruction { target: t, .. } => {
+                    target.as_ref().is_none_or(|target| target == t)
+                }
+                _ => false,
+            },
+        }
+    }
+
+    fn compare(&self, op: CompareOp, a: &Value, b: &Value) -> bool {
+        let document = self.document;
+        // A node-set compared with a boolean is converted to a boolean.
+        match (a, b) {
+            (Value::NodeSet(_), Value::Boolean(_)) => {
+                return self.compare(op, &Value::Boolean(a.boolean()), b)
+            }
+            (Value::Boolean(_), Value::NodeSet(_)) => {
+                return self.compare(op, a, &Value::Boolean(b.boolean()))
+            }
+            _ => {}
+        }
+        let atoms = |value: &Value| match value {
+            Value::NodeSet(nodes) => nodes
+                .iter()
+                .map(|node| Value::String(document.string_value(*node)))
+                .collect(),
+            _ => vec![value.clone()],
+        };
+        let (a_atoms, b_atoms) = (atoms(a), atoms(b));
+        a_atoms.iter().any(|x| {
+            b_atoms.iter().any(|y| match op {
+                CompareOp::Equal | CompareOp::NotEqual => {
+                    let equal = match (x, y) {
+                        (Value::Boolean(_), _) | (_, Value::Boolean(_)) => {
+                            x.boolean() == y.boolean()
+                        }
+                        (Value::Number(_), _) | (_, Value::Number(_)) => {
+                            x.number(document) == y.number(document)
+                        }
+                        _ => x.string(document) == y.string(document),
+                    };
+                    equal == (op == CompareOp::Equal)
+                }
+                _ => {
+                    let (x, y) = (x.number(document), y.number(document));
+                    match op {
+                        CompareOp::Less => x < y,
+                        CompareOp::LessOrEqual => x <= y,
+                        CompareOp::Greater => x > y,
+                        _ => x >= y,
+                    }
+                }
+            })
+        })
+    }
+
+    fn function(&self, name: &str, arguments: &[Expr], context: &Context) -> XResult<Value> {
+        let document = self.document;
+        if name.starts_with('{') {
+            let function = self.environment.get_function(name).ok_or_else(|| {
+                ParseErrorImpl::of_kind(
+                    ErrorKind::Expression,
+                    format!("unknown function \"{}\"", name),
+                )
+            })?;
+            let mut values = Vec::with_capacity(arguments.len());
+            for argument in arguments {
+                values.push(self.eval(argument, context)?);
+            }
+            return function(document, context.node, &values);
+        }
+        let argument = |index: usize| self.eval(&arguments[index], context);
+        let string_argument = |index: usize| -> XResult<String> {
+            if index < arguments.len() {
+                Ok(argument(index)?.string(document))
+            } else {
+                Ok(document.string_value(context.node))
+            }
+        };
+        let number_argument =
+            |index: usize| -> XResult<f64> { Ok(argument(index)?.number(document)) };
+        let node_argument = || -> XResult<Option<NodeId>> {
+            if arguments.is_empty() {
+                Ok(Some(context.node))
+            } else {
+                Ok(self.node_set(&arguments[0], context)?.first().cloned())
+            }
+        };
+        Ok(match name {
+            "last" => Value::Number(context.size as f64),
+            "position" => Value::Number(context.position as f64),
+            "count" => Value::Number(self.node_set(&arguments[0], context)?.len() as f64),
+            "id" => {
+                let ids = match argument(0)? {
+                    Value::NodeSet(nodes) => nodes
+                        .iter()
+                        .map(|node| document.string_value(*node))
+                        .collect::<Vec<String>>()
+                        .join(" "),
+                    value => value.string(document),
+                };
+                let mut nodes: Vec<NodeId> = ids
+                    .split(names::is_whitespace)
+                    .filter_map(|id| document.element_by_id(id))
+                    .collect();
+                nodes.sort();
+                nodes.dedup();
+                Value::NodeSet(nodes)
+            }
+            "local-name" | "namespace-uri" | "name" => {
+                let value = node_argument()?.map(|node| match name {
+                    "local-name" => document.local_name(node),
+                    "namespace-uri" => document.namespace_uri(node),
+                    _ => document.q_name(node),
+                });
+                Value::String(value.unwrap_or("").to_owned())
+            }
+            "string" => Value::String(string_argument(0)?),
+            "concat" => {
+                let mut result = String::new();
+                for index in 0..arguments.len() {
+                    result.push_str(&string_argument(index)?);
+                }
+                Value::String(result)
+            }
+            "starts-with" => Value::Boolean(string_argument(0)?.starts_with(&string_argument(1)?)),
+            "contains" => Value::Boolean(string_argument(0)?.contains(&string_argument(1)?)),
+            "substring-before" => {
+                let s = string_argument(0)?;
+                let pattern = string_argument(1)?;
+                Value::String(s.find(&pattern).map_or("", |i| &s[..i]).to_owned())
+            }
+            "substring-after" => {
+                let s = string_argument(0)?;
+                let pattern = string_argument(1)?;
+                Value::String(
+                    s.find(&pattern)
+                        .map_or("", |i| &s[i + pattern.len()..])
+                        .to_owned(),
+                )
+            }
+            "substring" => {
+                let s = string_argument(0)?;
+                let start = round(number_argument(1)?);
+                let end = if arguments.len() > 2 {
+                    start + round(number_argument(2)?)
+                } else {
+                    f64::INFINITY
+                };
+                Value::String(
+                    s.chars()
+                        .enumerate()
+                        .filter(|(i, _)| {
+                            let position = (*i + 1) as f64;
+                            position >= start && position < end
+                        })
+                        .map(|(_, c)| c)
+                        .collect(),
+                )
+            }
+            "string-length" => Value::Number(string_argument(0)?.chars().count() as f64),
+            "normalize-space" => Value::String(
+                string_argument(0)?
+                    .split(names::is_whitespace)
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<&str>>()
+                    .join(" "),
+            ),
+            "translate" => {
+                let from: Vec<char> = string_argument(1)?.chars().collect();
+                let to: Vec<char> = string_argument(2)?.chars().collect();
+                Value::String(
+                    string_argument(0)?
+                        .chars()
+                        .filter_map(|c| match from.iter().position(|f| *f == c) {
+                            Some(i) => to.get(i).cloned(),
+                            None => Some(c),
+                        })
+                        .collect(),
+                )
+            }
+            "boolean" => Value::Boolean(argument(0)?.boolean()),
+            "not" => Value::Boolean(!argument(0)?.boolean()),
+            "lang" => {
+                let lang = string_argument(0)?.to_lowercase();
+                let mut current = Some(context.node);
+                let mut declared = None;
+                while let Some(node) = current {
+                    declared = document.attribute_value(node, XMLNS, "lang");
+                    if declared.is_some() {
+                        break;
+                    }
+                    current = document.parent(node);
+                }
+                Value::Boolean(declared.is_some_and(|declared| {
+                    let declared = declared.to_lowercase();
+                    declared == lang
+                        || (declared.starts_with(&lang) && declared[lang.len()..].starts_with('-'))
+                }))
+            }
+            "true" => Value::Boolean(true),
+            "false" => Value::Boolean(false),
+            "number" if arguments.is_empty() => {
+                Value::Number(string_to_number(&document.string_value(context.node)))
+            }
+            "number" => Value::Number(number_argument(0)?),
+            "sum" => Value::Number(
+                self.node_set(&arguments[0], context)?
+                    .iter()
+                    .map(|node| string_to_number(&document.string_value(*node)))
+                    .sum(),
+            ),
+            "floor" => Value::Number(number_argument(0)?.floor()),
+            "ceiling" => Value::Number(number_argument(0)?.ceil()),
+            "round" => Value::Number(round(number_argument(0)?)),
+            _ => {
+                return Err(ParseErrorImpl::of_kind(
+                    ErrorKind::Expression,
+                    format!("unknown function \"{}\"", name),
+                ))
+            }
+        })
+    }
+}
+
+/// Round a number as by the `round()` function: to the closest integer, rounding halves
+/// towards positive infinity.
+fn round(n: f64) -> f64 {
+    if n.is_nan() || n.is_infinite() {
+        n
+    } else if (-0.5..0.0).contains(&n) {
+        -0.0
+    } else {
+        (n + 0.5).floor()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event::Event;
+    use helpers::AttributesImpl;
+    use helpers::LocatorImpl;
+    use tree::TreeBuilder;
+
+    const URN_X: &str = "urn:x";
+
+    fn start(q_name: &str, pairs: &[(&str, &str)]) -> Event {
+        let mut attributes = AttributesImpl::new();
+        for &(name, value) in pairs {
+            let attr_type = if name == "id" { "ID" } else { "CDATA" };
+            attributes.add_attribute("", name, name, attr_type, value);
+        }
+        match q_name.find(':') {
+            Some(i) => Event::start_element(URN_X, &q_name[i + 1..], q_name, &attributes),
+            None => Event::start_element("", q_name, q_name, &attributes),
+        }
+    }
+
+    fn end(q_name: &str) -> Event {
+        match q_name.find(':') {
+            Some(i) => Event::end_element(URN_X, &q_name[i + 1..], q_name),
+            None => Event::end_element("", q_name, q_name),
+        }
+    }
+
+    fn text(content: &str) -> Event {
+        Event::Characters(content.to_owned())
+    }
+
+    /// Build the document
+    /// `<doc xmlns:x="urn:x" xml:lang="en-GB"><chapter id="c1" n="1"><title>One</title><p>a</p>
+    /// <p>b</p></chapter><chapter id="c2" n="2"><title>Two</title><p> c  d </p><x:note/>
+    /// </chapter></doc>`.
+    fn document() -> Document {
+        let builder = TreeBuilder::<LocatorImpl>::new();
+        let mut events = vec![
+            Event::StartDocument,
+            Event::StartPrefixMapping {
+                prefix: "x".to_owned(),
+                uri: URN_X.to_owned(),
+            },
+        ];
+        let mut attributes = AttributesImpl::new();
+        attributes.add_attribute(XMLNS, "lang", "xml:lang", "CDATA", "en-GB");
+        events.push(Event::start_element("", "doc", "doc", &attributes));
+        for &(id, n, title, paragraphs) in &[
+            ("c1", "1", "One", &["a", "b"][..]),
+            ("c2", "2", "Two", &[" c  d "][..]),
+        ] {
+            events.push(start("chapter", &[("id", id), ("n", n)]));
+            events.extend(vec![start("title", &[]), text(title), end("title")]);
+            for paragraph in paragraphs {
+                events.extend(vec![start("p", &[]), text(paragraph), end("p")]);
+            }
+            if id == "c2" {
+                events.extend(vec![start("x:note", &[]), end("x:note")]);
+            }
+            events.push(end("chapter"));
+        }
+        events.extend(vec![end("doc"), Event::EndDocument]);
+        for event in events {
+            event.dispatch(&builder).unwrap();
+        }
+        builder.take_document()
+    }
+
+    fn namespaces() -> NamespaceSupport {
+        let mut namespaces = NamespaceSupport::new();
+        namespaces.declare_prefix("y", URN_X);
+        namespaces
+    }
+
+    fn compile(source: &str) -> XPath {
+        XPath::compile(source, &namespaces()).unwrap()
+    }
+
+    fn string(document: &Document, source: &str) -> String {
+        compile(source)
+            .evaluate_string(document, document.root(), &Environment::new())
+            .unwrap()
+    }
+
+    fn names(document: &Document, source: &str) -> Vec<String> {
+        compile(source)
+            .select(document, document.root(), &Environment::new())
+            .unwrap()
+            .into_iter()
+            .map(|node| match document.kind(node) {
+                NodeKind::Element { .. } | NodeKind::Attribute { .. } => {
+                    document.q_name(node).to_owned()
+                }
+                _ => document.string_value(node),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn location_paths() {
+        let document = document();
+        assert_eq!(names(&document, "/doc/chapter/title"), ["title", "title"]);
+        assert_eq!(names(&document, "//p/text()"), ["a", "b", " c  d "]);
+        assert_eq!(names(&document, "//chapter[2]/@*"), ["id", "n"]);
+        assert_eq!(names(&document, "//y:note"), ["x:note"]);
+        assert_eq!(names(&document, "//p[last()]/.."), ["chapter", "chapter"]);
+        assert_eq!(
+            names(&document, "//title[. = 'Two']/following-sibling::*"),
+            ["p", "x:note"]
+        );
+        assert_eq!(names(&document, "(//p)[3]/preceding::p"), ["p", "p"]);
+        assert_eq!(
+            names(&document, "//y:note/ancestor-or-self::*"),
+            ["doc", "chapter", "x:note"]
+        );
+        assert_eq!(
+            names(&document, "//p[1] | //title"),
+            ["title", "p", "title", "p"]
+        );
+        assert_eq!(names(&document, "id('c2 c1')/@n"), ["n", "n"]);
+        assert_eq!(string(&document, "count(/doc/namespace::*)"), "2");
+    }
+
+    #[test]
+    fn functions_and_operators() {
+        let document = document();
+        let cases = [
+            ("concat('a', 1, true())", "a1true"),
+            ("substring('12345', 1.5, 2.6)", "234"),
+            ("substring-before('1999/04/01', '/')", "1999"),
+            ("substring-after('1999/04/01', '/')", "04/01"),
+            ("translate('--aaa--', 'abc-', 'ABC')", "AAA"),
+            ("normalize-space(//p[. != 'a' and . != 'b'])", "c d"),
+            ("string-length(//title)", "3"),
+            ("sum(//chapter/@n) div 4", "0.75"),
+            ("7 mod -3", "1"),
+            ("round(-2.5)", "-2"),
+            ("floor(-0.5)", "-1"),
+            ("1 div 0", "Infinity"),
+            ("number('x')", "NaN"),
+            ("//chapter/@n = 2", "true"),
+            ("//chapter/@n > 5", "false"),
+            ("name(//y:note)", "x:note"),
+            ("local-name(//y:note)", "note"),
+            ("namespace-uri(//y:note)", "urn:x"),
+            ("boolean(//chapter[lang('en')])", "true"),
+            ("not(//p[lang('fr')])", "true"),
+            ("-(3 - 5) * 2", "4"),
+        ];
+        for &(source, expected) in &cases {
+            assert_eq!(string(&document, source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(string_to_number(" -12.5 "), -12.5);
+        assert_eq!(string_to_number(".5"), 0.5);
+        assert!(string_to_number("1e3").is_nan());
+        assert!(string_to_number("+1").is_nan());
+        assert!(string_to_number(".").is_nan());
+        assert_eq!(number_to_string(-0.0), "0");
+        assert_eq!(number_to_string(2.0), "2");
+        assert_eq!(number_to_string(f64::NEG_INFINITY), "-Infinity");
+        assert!(!Value::Number(f64::NAN).boolean());
+        assert!(Value::String("false".to_owned()).boolean());
+        assert!(!Value::NodeSet(Vec::new()).boolean());
+    }
+
+    #[test]
+    fn environment() {
+        let document = document();
+        let mut environment = Environment::new();
+        environment.set_variable("n", Value::Number(2.0));
+        environment.set_variable("{urn:x}title", Value::String("One".to_owned()));
+        environment.set_function("{urn:x}twice", |document, _, arguments| {
+            let n = arguments.first().map_or(0.0, |a| a.number(document));
+            Ok(Value::Number(n * 2.0))
+        });
+        let xpath = compile("//chapter[@n = $n]/title = $y:title or y:twice($n) = 4");
+        assert!(xpath
+            .evaluate_boolean(&document, document.root(), &environment)
+            .unwrap());
+        assert_eq!(
+            compile("string(//chapter[@n = $n]/title)")
+                .evaluate_string(&document, document.root(), &environment)
+                .unwrap(),
+            "Two"
+        );
+        environment.remove_variable("n");
+        assert_eq!(
+            compile("$n")
+                .evaluate(&document, document.root(), &environment)
+                .expect_err("unbound")
+                .message(),
+            "undefined variable \"n\""
+        );
+        assert!(environment.remove_function("{urn:x}twice").is_some());
+        assert!(environment.get_function("{urn:x}twice").is_none());
+    }
+
+    #[test]
+    fn patterns() {
+        let document = document();
+        let pattern = XPath::compile_pattern("p | chapter/@n", &namespaces()).unwrap();
+        let chapter = compile("//chapter[2]")
+            .select(&document, document.root(), &Environment::new())
+            .unwrap()[0];
+        let matches = pattern
+            .select(&document, chapter, &Environment::new())
+            .unwrap();
+        assert_eq!(matches.len(), 5);
+        assert_eq!(pattern.source(), "p | chapter/@n");
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let errors = [
+            ("'abc", "unterminated string literal"),
+            ("$", "expected a variable name after '$'"),
+            ("//p[", "expected a node test"),
+            ("child::", "expected a node test"),
+            ("sideways::p", "unknown axis \"sideways\""),
+            ("z:p", "undeclared prefix \"z\""),
+            ("frobnicate()", "unknown function \"frobnicate\""),
+            ("count()", "wrong number of arguments to \"count\""),
+            ("1 2", "unexpected input in expression \"1 2\""),
+            ("a # b", "unexpected character '#'"),
+        ];
+        for &(source, message) in &errors {
+            let error = XPath::compile(source, &namespaces()).expect_err(source);
+            assert_eq!(error.kind(), &ErrorKind::Expression);
+            assert_eq!(error.message(), message, "{}", source);
+        }
+        let document = document();
+        assert_eq!(
+            compile("1 + 1")
+                .select(&document, document.root(), &Environment::new())
+                .expect_err("not a node-set")
+                .message(),
+            "expression \"1 + 1\" does not return a node-set"
+        );
+    }
+}
@@ -0,0 +1,991 @@
+//! Streaming evaluation of XPath location paths over SAX events.
+//!
+//! A `StreamMatcher` is a `ContentHandler` that recognizes the nodes selected by a set of
+//! location paths as the events go by, without building a tree, and either hands each match to
+//! a callback or forwards the events of each matched subtree to another `ContentHandler`. Only
+//! the subtree of an element matched for a callback is held in memory, until its end tag.
+//!
+//! Expressions are restricted to the streamable subset of XPath: location paths, or unions of
+//! them, made of child (`/`) and descendant (`//`) steps with element name tests, optionally
+//! ending in an attribute step. Predicates may only look at the attributes, name and position of
+//! the element, as in `product[@type='book']`, `item[2]` or `item[position() < 3 and @id]`;
+//! `last()`, the content of the element and variables are not streamable. As in XSLT patterns,
+//! a relative path such as `product/price` matches anywhere in the document.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use common;
+use common::Locator;
+use event::Event;
+use helpers::Attribute;
+use helpers::AttributesImpl;
+use helpers::ErrorKind;
+use helpers::LocatorImpl;
+use helpers::NamespaceSupport;
+use helpers::ParseErrorImpl;
+use helpers::NSDECL;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::Result;
+use tree::Document;
+use tree::NodeId;
+use tree::TreeBuilder;
+
+use super::Axis;
+use super::Context;
+use super::Environment;
+use super::Evaluator;
+use super::Expr;
+use super::NodeTest;
+use super::PathStart;
+use super::Step;
+use super::Value;
+use super::XPath;
+use super::XResult;
+
+/// The events of a matched element, from its start tag to its end tag.
+///
+/// The events are preceded by the `start_prefix_mapping` events for the element and followed
+/// by the matching `end_prefix_mapping` events.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Subtree {
+    events: Vec<Event>,
+}
+
+impl Subtree {
+    /// Return the events that make up this subtree, in document order.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Return the (uri, local name, qualified name) of the element.
+    pub fn name(&self) -> Option<(&str, &str, &str)> {
+        self.events.iter().find_map(|event| match *event {
+            Event::StartElement {
+                ref uri,
+                ref local_name,
+                ref q_name,
+                ..
+            } => Some((uri.as_str(), local_name.as_str(), q_name.as_str())),
+            _ => None,
+        })
+    }
+
+    /// Return the attributes of the element.
+    pub fn attributes(&self) -> Option<&AttributesImpl> {
+        self.events.iter().find_map(|event| match *event {
+            Event::StartElement { ref attributes, .. } => Some(attributes),
+            _ => None,
+        })
+    }
+
+    /// Return the character content of the element and its descendants, as by the XPath
+    /// `string()` function.
+    pub fn string_value(&self) -> String {
+        let mut value = String::new();
+        for event in &self.events {
+            match *event {
+                Event::Characters(ref content) | Event::IgnorableWhitespace(ref content) => {
+                    value.push_str(content)
+                }
+                _ => {}
+            }
+        }
+        value
+    }
+
+    /// Replay the subtree into a content handler.
+    pub fn replay<L, H>(&self, handler: &H) -> Result<()>
+    where
+        L: Locator,
+        H: ContentHandler<L, AttributesImpl> + ?Sized,
+    {
+        for event in &self.events {
+            event.dispatch(handler)?;
+        }
+        Ok(())
+    }
+}
+
+/// A node matched by a `StreamMatcher`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MatchedNode {
+    Element(Subtree),
+    Attribute(Attribute),
+}
+
+/// A match reported to a callback registered with `StreamMatcher::on_match`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    /// The expression that matched, as registered.
+    pub expression: String,
+    /// The path of the matched node, such as `/catalog[1]/product[3]/@type`.
+    pub path: String,
+    pub node: MatchedNode,
+    /// The position of the start tag of the matched element, or of the element that carries
+    /// the matched attribute.
+    pub location: LocatorImpl,
+}
+
+#[derive(Clone, Debug)]
+struct StreamStep {
+    /// Whether the step applies to descendants of the context, rather than to its children.
+    descendant: bool,
+    attribute: bool,
+    test: NodeTest,
+    predicates: Vec<Expr>,
+}
+
+type StreamPath = Vec<StreamStep>;
+
+fn streamable_predicate(expr: &Expr) -> bool {
+    match expr {
+        Expr::Or(a, b) | Expr::And(a, b) | Expr::Compare(_, a, b) | Expr::Arithmetic(_, a, b) => {
+            streamable_predicate(a) && streamable_predicate(b)
+        }
+        Expr::Negate(a) => streamable_predicate(a),
+        Expr::Path(PathStart::Context, steps) => {
+            steps.len() == 1
+                && steps[0].axis == Axis::Attribute
+                && steps[0].predicates.iter().all(streamable_predicate)
+        }
+        Expr::Literal(_) | Expr::Number(_) => true,
+        Expr::Function(name, arguments) => {
+            let uses_content = arguments.is_empty()
+                && ["string", "string-length", "normalize-space", "number"].contains(&&**name);
+            // last() depends on the following siblings, and id() and lang() on other elements.
+            let uses_document = ["last", "id", "lang"].contains(&&**name) || name.starts_with('{');
+            !uses_document && !uses_content && arguments.iter().all(streamable_predicate)
+        }
+        _ => false,
+    }
+}
+
+fn stream_path(steps: &[Step], paths: &mut Vec<StreamPath>) -> XResult<()> {
+    let mut path = Vec::new();
+    let mut descendant = false;
+    for (index, step) in steps.iter().enumerate() {
+        let is_last = index + 1 == steps.len();
+        match step.axis {
+            Axis::DescendantOrSelf if step.test == NodeTest::Node && step.predicates.is_empty() => {
+                descendant = true;
+                continue;
+            }
+            Axis::Descendant if step.predicates.is_empty() => descendant = true,
+            Axis::Child => {}
+            Axis::Attribute if is_last && step.predicates.is_empty() => {}
+            Axis::Attribute => {
+                return Err(ParseErrorImpl::of_kind(
+                    ErrorKind::Expression,
+                    "an attribute step must be the last step, without predicates",
+                ))
+            }
+            axis => {
+                return Err(ParseErrorImpl::of_kind(
+                    ErrorKind::Expression,
+                    format!("the {:?} axis is not streamable", axis),
+                ))
+            }
+        }
+        match step.test {
+            NodeTest::Name(..) | NodeTest::Namespace(_) | NodeTest::Any => {}
+            NodeTest::Node if step.axis == Axis::Attribute => {}
+            _ => {
+                return Err(ParseErrorImpl::of_kind(
+                    ErrorKind::Expression,
+                    "only elements and attributes can be matched",
+                ))
+            }
+        }
+        if !step.predicates.iter().all(streamable_predicate) {
+            return Err(ParseErrorImpl::of_kind(
+                ErrorKind::Expression,
+                "predicates may only test the attributes, name and position of an element",
+            ));
+        }
+        path.push(StreamStep {
+            descendant,
+            attribute: step.axis == Axis::Attribute,
+            test: step.test.clone(),
+            predicates: step.predicates.clone(),
+        });
+        descendant = false;
+    }
+    if path.is_empty() {
+        return Err(ParseErrorImpl::of_kind(
+            ErrorKind::Expression,
+            "the root node cannot be matched",
+        ));
+    }
+    paths.push(path);
+    Ok(())
+}
+
+fn stream_paths(expr: &Expr, paths: &mut Vec<StreamPath>) -> XResult<()> {
+    match expr {
+        Expr::Union(a, b) => {
+            stream_paths(a, paths)?;
+            stream_paths(b, paths)
+        }
+        Expr::Path(PathStart::Root, steps) => stream_path(steps, paths),
+        _ => Err(ParseErrorImpl::of_kind(
+            ErrorKind::Expression,
+            "only location paths are streamable",
+        )),
+    }
+}
+
+fn name_matches(test: &NodeTest, uri: &str, local_name: &str) -> bool {
+    match test {
+        NodeTest::Name(test_uri, test_local_name) => {
+            test_uri == uri && test_local_name == local_name
+        }
+        NodeTest::Namespace(test_uri) => test_uri == uri,
+        _ => true,
+    }
+}
+
+type Callback<'a> = Box<dyn Fn(&Match) -> Result<()> + 'a>;
+
+enum Action<'a, L: Locator> {
+    Callback(Callback<'a>),
+    Forward(Box<dyn ContentHandler<L, AttributesImpl> + 'a>),
+}
+
+struct Target<'a, L: Locator> {
+    expression: String,
+    paths: Vec<StreamPath>,
+    action: Action<'a, L>,
+}
+
+#[derive(Default)]
+struct Frame {
+    /// The (target, path, step) triples whose step applies to the children of this element.
+    active: Vec<(usize, usize, usize)>,
+    /// Candidates seen so far for each (target, path, step, predicate) among the children.
+    positions: HashMap<(usize, usize, usize, usize), usize>,
+    /// Number of children seen so far with each qualified name.
+    names: HashMap<String, usize>,
+    path: String,
+}
+
+struct Capture {
+    target: usize,
+    depth: usize,
+    events: Vec<Event>,
+    prefixes: Vec<String>,
+    path: String,
+    location: LocatorImpl,
+}
+
+#[derive(Default)]
+struct MatcherState {
+    frames: Vec<Frame>,
+    pending_prefixes: Vec<(String, String)>,
+    /// Subtrees being buffered for callbacks.
+    captures: Vec<Capture>,
+    /// Subtrees being forwarded, as (target, depth, prefixes).
+    forwarding: Vec<(usize, usize, Vec<String>)>,
+}
+
+/// A `ContentHandler` that matches streamable XPath expressions against the events it
+/// receives.
+///
+/// Register expressions with `on_match` or `forward` before the parse, then register the
+/// matcher as the content handler of a Namespace-aware reader. Handlers registered with
+/// `forward` receive `set_document_locator`, `start_document` and `end_document`, and in
+/// between the events of each matched subtree, so that they see a document whose top-level
+/// elements are the matches. A match nested inside another match of the same expression is part
+/// of the outer subtree and is not forwarded a second time; callbacks, on the other hand, are
+/// invoked for every match, once its end tag has been seen. Attribute matches are reported
+/// when their element starts.
+pub struct StreamMatcher<'a, L: Locator> {
+    targets: Vec<Target<'a, L>>,
+    locator: RefCell<Option<Rc<L>>>,
+    state: RefCell<MatcherState>,
+}
+
+impl<'a, L: Locator> Default for StreamMatcher<'a, L> {
+    fn default() -> Self {
+        StreamMatcher::new()
+    }
+}
+
+impl<'a, L: Locator> StreamMatcher<'a, L> {
+    /// Construct a matcher without any expressions.
+    pub fn new() -> Self {
+        let state = MatcherState {
+            frames: vec![Frame::default()],
+            ..MatcherState::default()
+        };
+        StreamMatcher {
+            targets: Vec::new(),
+            locator: RefCell::new(None),
+            state: RefCell::new(state),
+        }
+    }
+
+    /// Invoke `callback` for each node matched by `expression`, whose Namespace prefixes are
+    /// resolved against `namespaces`.
+    pub fn on_match<F>(
+        &mut self,
+        expression: &str,
+        namespaces: &NamespaceSupport,
+        callback: F,
+    ) -> XResult<()>
+    where
+        F: Fn(&Match) -> Result<()> + 'a,
+    {
+        self.add(expression, namespaces, Action::Callback(Box::new(callback)))
+    }
+
+    /// Forward the subtrees of the elements matched by `expression`, whose Namespace prefixes
+    /// are resolved against `namespaces`, to `handler`.
+    pub fn forward<H>(
+        &mut self,
+        expression: &str,
+        namespaces: &NamespaceSupport,
+        handler: H,
+    ) -> XResult<()>
+    where
+        H: ContentHandler<L, AttributesImpl> + 'a,
+    {
+        self.add(expression, namespaces, Action::Forward(Box::new(handler)))
+    }
+
+    fn add(
+        &mut self,
+        expression: &str,
+        namespaces: &NamespaceSupport,
+        action: Action<'a, L>,
+    ) -> XResult<()> {
+        let xpath = XPath::compile_pattern(expression, namespaces)?;
+        let mut paths = Vec::new();
+        stream_paths(&xpath.expr, &mut paths)?;
+        let forward = matches!(action, Action::Forward(_));
+        if forward
+            && paths
+                .iter()
+                .any(|path| path.last().is_some_and(|step| step.attribute))
+        {
+            return Err(ParseErrorImpl::of_kind(
+                ErrorKind::Expression,
+                "attributes cannot be forwarded to a content handler",
+            ));
+        }
+        self.targets.push(Target {
+            expression: expression.to_owned(),
+            paths,
+            action,
+        });
+        let active = self.initial_steps();
+        self.state.borrow_mut().frames[0].active = active;
+        Ok(())
+    }
+
+    fn initial_steps(&self) -> Vec<(usize, usize, usize)> {
+        let mut active = Vec::new();
+        for (target, entry) in self.targets.iter().enumerate() {
+            for path in 0..entry.paths.len() {
+                active.push((target, path, 0));
+            }
+        }
+        active
+    }
+
+    fn handlers(&self) -> impl Iterator<Item = &(dyn ContentHandler<L, AttributesImpl> + 'a)> {
+        self.targets
+            .iter()
+            .filter_map(|target| match target.action {
+                Action::Forward(ref handler) => Some(&**handler),
+                Action::Callback(_) => None,
+            })
+    }
+
+    fn location(&self) -> LocatorImpl {
+        self.locator
+            .borrow()
+            .as_ref()
+            .map(|locator| LocatorImpl::from_locator(&**locator))
+            .unwrap_or_default()
+    }
+
+    /// Pass an event to the subtrees being buffered or forwarded.
+    fn content(&self, event: Event) -> Result<()> {
+        let forwarding: Vec<usize> = {
+            let mut state = self.state.borrow_mut();
+            for capture in &mut state.captures {
+                capture.events.push(event.clone());
+            }
+            state.forwarding.iter().map(|f| f.0).collect()
+        };
+        for target in forwarding {
+            if let Action::Forward(ref handler) = self.targets[target].action {
+                event.dispatch(&**handler)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluate the predicates of a step for a candidate element, counting positions among the
+    /// children of `parent`.
+    fn predicates_hold(
+        &self,
+        key: (usize, usize, usize),
+        step: &StreamStep,
+        parent: &mut Frame,
+        element: &(Document, NodeId),
+    ) -> XResult<bool> {
+        let environment = Environment::new();
+        let evaluator = Evaluator {
+            document: &element.0,
+            environment: &environment,
+        };
+        for (index, predicate) in step.predicates.iter().enumerate() {
+            let position = parent
+                .positions
+                .entry((key.0, key.1, key.2, index))
+                .or_insert(0);
+            *position += 1;
+            let context = Context {
+                node: element.1,
+                position: *position,
+                size: *position,
+            };
+            let holds = match evaluator.eval(predicate, &context)? {
+                Value::Number(n) => n == context.position as f64,
+                value => value.boolean(),
+            };
+            if !holds {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<'a, L: Locator, A: Attributes> ContentHandler<L, A> for StreamMatcher<'a, L> {
+    fn characters(&self, content: &str) -> Result<()> {
+        self.content(Event::Characters(content.to_owned()))
+    }
+    fn end_document(&self) -> Result<()> {
+        for handler in self.handlers() {
+            handler.end_document()?;
+        }
+        Ok(())
+    }
+    fn end_element(&self, uri: &str, local_name: &str, q_name: &str) -> Result<()> {
+        self.content(Event::end_element(uri, local_name, q_name))?;
+        let (closed_captures, closed_forwards) = {
+            let mut guard = self.state.borrow_mut();
+            let state = &mut *guard;
+            let depth = state.frames.len() - 1;
+            if depth > 0 {
+                state.frames.pop();
+            }
+            let (closed, open): (Vec<Capture>, Vec<Capture>) = std::mem::take(&mut state.captures)
+                .into_iter()
+                .partition(|capture| capture.depth == depth);
+            state.captures = open;
+            let (closed_forwards, open_forwards): (Vec<_>, Vec<_>) =
+                std::mem::take(&mut state.forwarding)
+                    .into_iter()
+                    .partition(|forward| forward.1 == depth);
+            state.forwarding = open_forwards;
+            (closed, closed_forwards)
+        };
+        // The end_prefix_mapping events for the matched element only arrive after this
+        // callback returns, so synthesize them now rather than delaying delivery.
+        for (target, _, prefixes) in closed_forwards {
+            if let Action::Forward(ref handler) = self.targets[target].action {
+                for prefix in prefixes {
+                    handler.end_prefix_mapping(&prefix)?;
+                }
+            }
+        }
+        for capture in closed_captures {
+            let mut events = capture.events;
+            for prefix in capture.prefixes {
+                events.push(Event::EndPrefixMapping { prefix });
+            }
+            let target = &self.targets[capture.target];
+            if let Action::Callback(ref callback) = target.action {
+                callback(&Match {
+                    expression: target.expression.clone(),
+                    path: capture.path,
+                    node: MatchedNode::Element(Subtree { events }),
+                    location: capture.location,
+                })?;
+            }
+        }
+        Ok(())
+    }
+    fn end_prefix_mapping(&self, prefix: &str) -> Result<()> {
+        self.content(Event::EndPrefixMapping {
+            prefix: prefix.to_owned(),
+        })
+    }
+    fn ignorable_whitespace(&self, content: &str) -> Result<()> {
+        self.content(Event::IgnorableWhitespace(content.to_owned()))
+    }
+    fn processing_instruction(&self, target: &str, data: &str) -> Result<()> {
+        self.content(Event::ProcessingInstruction {
+            target: target.to_owned(),
+            data: data.to_owned(),
+        })
+    }
+    fn set_document_locator(&self, locator: Rc<L>) {
+        for handler in self.handlers() {
+            handler.set_document_locator(locator.clone());
+        }
+        *self.locator.borrow_mut() = Some(locator);
+    }
+    fn skipped_entity(&self, name: &str) -> Result<()> {
+        self.content(Event::SkippedEntity(name.to_owned()))
+    }
+    fn start_document(&self) -> Result<()> {
+        *self.state.borrow_mut() = MatcherState {
+            frames: vec![Frame {
+                active: self.initial_steps(),
+                ..Frame::default()
+            }],
+            ..MatcherState::default()
+        };
+        for handler in self.handlers() {
+            handler.start_document()?;
+        }
+        Ok(())
+    }
+    fn start_element(
+        &self,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attributes: A,
+    ) -> Result<()> {
+        let attributes = AttributesImpl::from_attributes(&attributes);
+        let location = self.location();
+        let mut guard = self.state.borrow_mut();
+        let state = &mut *guard;
+        let depth = state.frames.len();
+        let parent = state
+            .frames
+            .last_mut()
+            .expect("the root frame is never popped");
+        let count = parent.names.entry(q_name.to_owned()).or_insert(0);
+        *count += 1;
+        let path = format!("{}/{}[{}]", parent.path, q_name, count);
+
+        // The element is only turned into a tree if a predicate needs to be evaluated.
+        let mut element = None;
+        let mut active = Vec::new();
+        let mut matched = Vec::new();
+        for (target, path_index, step_index) in parent.active.clone() {
+            let steps = &self.targets[target].paths[path_index];
+            let step = &steps[step_index];
+            if step.descendant && !active.contains(&(target, path_index, step_index)) {
+                active.push((target, path_index, step_index));
+            }
+            if step.attribute || !name_matches(&step.test, uri, local_name) {
+                continue;
+            }
+            if !step.predicates.is_empty() {
+                if element.is_none() {
+                    let builder = TreeBuilder::<L>::new();
+                    builder.start_element(uri, local_name, q_name, attributes.clone())?;
+                    let document = builder.take_document();
+                    let node = document.document_element().expect("an element was added");
+                    element = Some((document, node));
+                }
+                let key = (target, path_index, step_index);
+                let element = element.as_ref().expect("the element was built");
+                let holds = self
+                    .predicates_hold(key, step, parent, element)
+                    .map_err(|e| Box::new(e) as Box<dyn common::Error>)?;
+                if !holds {
+                    continue;
+                }
+            }
+            if step_index + 1 == steps.len() {
+                if !matched.contains(&target) {
+                    matched.push(target);
+                }
+            } else if !active.contains(&(target, path_index, step_index + 1)) {
+                active.push((target, path_index, step_index + 1));
+            }
+        }
+
+        let mut attribute_matches = Vec::new();
+        for &(target, path_index, step_index) in &active {
+            let step = &self.targets[target].paths[path_index][step_index];
+            if !step.attribute {
+                continue;
+            }
+            for attribute in attributes.iter() {
+                let is_declaration = attribute.uri == NSDECL
+                    || attribute.q_name == "xmlns"
+                    || attribute.q_name.starts_with("xmlns:");
+                if !is_declaration
+                    && name_matches(&step.test, &attribute.uri, &attribute.local_name)
+                {
+                    attribute_matches.push((target, attribute.clone()));
+                }
+            }
+        }
+
+        let prefixes = std::mem::take(&mut state.pending_prefixes);
+        let mut events: Vec<Event> = prefixes
+            .iter()
+            .map(|(prefix, uri)| Event::StartPrefixMapping {
+                prefix: prefix.clone(),
+                uri: uri.clone(),
+            })
+            .collect();
+        events.push(Event::StartElement {
+            uri: uri.to_owned(),
+            local_name: local_name.to_owned(),
+            q_name: q_name.to_owned(),
+            attributes,
+        });
+        let prefix_names: Vec<String> = prefixes.into_iter().map(|p| p.0).collect();
+        for capture in &mut state.captures {
+            capture.events.extend(events.iter().cloned());
+        }
+        for target in matched {
+            match self.targets[target].action {
+                Action::Callback(_) => state.captures.push(Capture {
+                    target,
+                    depth,
+                    events: events.clone(),
+                    prefixes: prefix_names.clone(),
+                    path: path.clone(),
+                    location: location.clone(),
+                }),
+                Action::Forward(_) => {
+                    if !state.forwarding.iter().any(|f| f.0 == target) {
+                        state.forwarding.push((target, depth, prefix_names.clone()));
+                    }
+                }
+            }
+        }
+        let forwarding: Vec<usize> = state.forwarding.iter().map(|f| f.0).collect();
+        state.frames.push(Frame {
+            active,
+            path: path.clone(),
+            ..Frame::default()
+        });
+        drop(guard);
+
+        for target in forwarding {
+            if let Action::Forward(ref handler) = self.targets[target].action {
+                for event in &events {
+                    event.dispatch(&**handler)?;
+                }
+            }
+        }
+        for (target, attribute) in attribute_matches {
+            let target = &self.targets[target];
+            if let Action::Callback(ref callback) = target.action {
+                callback(&Match {
+                    expression: target.expression.clone(),
+                    path: format!("{}/@{}", path, attribute.q_name),
+                    node: MatchedNode::Attribute(attribute),
+                    location: location.clone(),
+                })?;
+            }
+        }
+        Ok(())
+    }
+    fn start_prefix_mapping(&self, prefix: &str, uri: &str) -> Result<()> {
+        self.state
+            .borrow_mut()
+            .pending_prefixes
+            .push((prefix.to_owned(), uri.to_owned()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start(name: &str, pairs: &[(&str, &str)]) -> Event {
+        let mut attributes = AttributesImpl::new();
+        for &(q_name, value) in pairs {
+            attributes.add_attribute("", q_name, q_name, "CDATA", value);
+        }
+        Event::start_element("", name, name, &attributes)
+    }
+
+    fn end(name: &str) -> Event {
+        Event::end_element("", name, name)
+    }
+
+    fn text(content: &str) -> Event {
+        Event::Characters(content.to_owned())
+    }
+
+    /// `<catalog><product type="book" id="p1"><title>A</title><price>10</price></product>
+    /// <product type="cd"><title>B</title></product><product type="book" id="p3"><title>C</title>
+    /// <section><section/></section></product></catalog>`
+    fn catalog() -> Vec<Event> {
+        vec![
+            Event::StartDocument,
+            start("catalog", &[]),
+            start("product", &[("type", "book"), ("id", "p1")]),
+            start("title", &[]),
+            text("A"),
+            end("title"),
+            start("price", &[]),
+            text("10"),
+            end("price"),
+            end("product"),
+            start("product", &[("type", "cd")]),
+            start("title", &[]),
+            text("B"),
+            end("title"),
+            end("product"),
+            start("product", &[("type", "book"), ("id", "p3")]),
+            start("title", &[]),
+            text("C"),
+            end("title"),
+            start("section", &[]),
+            start("section", &[]),
+            end("section"),
+            end("section"),
+            end("product"),
+            end("catalog"),
+            Event::EndDocument,
+        ]
+    }
+
+    fn run(matcher: &StreamMatcher<LocatorImpl>, events: &[Event]) {
+        for event in events {
+            event.dispatch(matcher).unwrap();
+        }
+    }
+
+    /// Return the path and string value of each node matched by `expression`.
+    fn matches(expression: &str) -> Vec<(String, String)> {
+        let found = RefCell::new(Vec::new());
+        {
+            let mut matcher = StreamMatcher::<LocatorImpl>::new();
+            matcher
+                .on_match(expression, &NamespaceSupport::new(), |m| {
+                    let value = match m.node {
+                        MatchedNode::Element(ref subtree) => subtree.string_value(),
+                        MatchedNode::Attribute(ref attribute) => attribute.value.clone(),
+                    };
+                    found.borrow_mut().push((m.path.clone(), value));
+                    Ok(())
+                })
+                .unwrap();
+            run(&matcher, &catalog());
+        }
+        found.into_inner()
+    }
+
+    fn paths(expression: &str) -> Vec<String> {
+        matches(expression).into_iter().map(|m| m.0).collect()
+    }
+
+    #[test]
+    fn location_paths() {
+        assert_eq!(
+            matches("product[@type='book']/title"),
+            [
+                ("/catalog[1]/product[1]/title[1]".to_owned(), "A".to_owned()),
+                ("/catalog[1]/product[3]/title[1]".to_owned(), "C".to_owned()),
+            ]
+        );
+        assert_eq!(
+            matches("/catalog/*/price | //product[2]/title"),
+            [
+                (
+                    "/catalog[1]/product[1]/price[1]".to_owned(),
+                    "10".to_owned()
+                ),
+                ("/catalog[1]/product[2]/title[1]".to_owned(), "B".to_owned()),
+            ]
+        );
+        assert!(paths("/product").is_empty());
+    }
+
+    #[test]
+    fn positional_predicates() {
+        assert_eq!(paths("product[3]"), ["/catalog[1]/product[3]"]);
+        assert_eq!(
+            paths("product[position() < 3 and @id]"),
+            ["/catalog[1]/product[1]"]
+        );
+        assert_eq!(
+            paths("product[@type='book'][2]"),
+            ["/catalog[1]/product[3]"]
+        );
+    }
+
+    #[test]
+    fn attributes() {
+        assert_eq!(
+            matches("//product/@id"),
+            [
+                ("/catalog[1]/product[1]/@id".to_owned(), "p1".to_owned()),
+                ("/catalog[1]/product[3]/@id".to_owned(), "p3".to_owned()),
+            ]
+        );
+        assert_eq!(paths("product[2]/@*"), ["/catalog[1]/product[2]/@type"]);
+    }
+
+    #[test]
+    fn nested_matches_are_reported_when_they_end() {
+        assert_eq!(
+            paths("//section"),
+            [
+                "/catalog[1]/product[3]/section[1]/section[1]",
+                "/catalog[1]/product[3]/section[1]",
+            ]
+        );
+    }
+
+    #[test]
+    fn subtrees() {
+        let subtrees = RefCell::new(Vec::new());
+        {
+            let mut matcher = StreamMatcher::<LocatorImpl>::new();
+            matcher
+                .on_match("product[1]", &NamespaceSupport::new(), |m| {
+                    if let MatchedNode::Element(ref subtree) = m.node {
+                        subtrees.borrow_mut().push(subtree.clone());
+                    }
+                    Ok(())
+                })
+                .unwrap();
+            run(&matcher, &catalog());
+        }
+        let subtrees = subtrees.into_inner();
+        assert_eq!(subtrees.len(), 1);
+        let subtree = &subtrees[0];
+        assert_eq!(subtree.name(), Some(("", "product", "product")));
+        assert_eq!(
+            subtree.attributes().unwrap().get_q_name_value("id"),
+            Some("p1".to_owned())
+        );
+        assert_eq!(subtree.events(), &catalog()[2..10]);
+        let builder = TreeBuilder::<LocatorImpl>::new();
+        subtree.replay(&builder).unwrap();
+        let document = builder.take_document();
+        assert_eq!(
+            document.string_value(document.document_element().unwrap()),
+            "A10"
+        );
+    }
+
+    /// A content handler that logs the events forwarded to it.
+    struct Log<'r>(&'r RefCell<Vec<String>>);
+
+    impl<'r> ContentHandler<LocatorImpl, AttributesImpl> for Log<'r> {
+        fn characters(&self, content: &str) -> Result<()> {
+            self.0.borrow_mut().push(content.to_owned());
+            Ok(())
+        }
+        fn end_document(&self) -> Result<()> {
+            self.0.borrow_mut().push("end".to_owned());
+            Ok(())
+        }
+        fn end_element(&self, _uri: &str, _local_name: &str, q_name: &str) -> Result<()> {
+            self.0.borrow_mut().push(format!("</{}>", q_name));
+            Ok(())
+        }
+        fn start_document(&self) -> Result<()> {
+            self.0.borrow_mut().push("start".to_owned());
+            Ok(())
+        }
+        fn start_element(
+            &self,
+            _uri: &str,
+            _local_name: &str,
+            q_name: &str,
+            _attributes: AttributesImpl,
+        ) -> Result<()> {
+            self.0.borrow_mut().push(format!("<{}>", q_name));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn forwarding() {
+        let log = RefCell::new(Vec::new());
+        {
+            let mut matcher = StreamMatcher::<LocatorImpl>::new();
+            matcher
+                .forward(
+                    "section | product[2]/title",
+                    &NamespaceSupport::new(),
+                    Log(&log),
+                )
+                .unwrap();
+            run(&matcher, &catalog());
+        }
+        assert_eq!(
+            log.into_inner(),
+            [
+                "start",
+                "<title>",
+                "B",
+                "</title>",
+                "<section>",
+                "<section>",
+                "</section>",
+                "</section>",
+                "end",
+            ]
+        );
+    }
+
+    #[test]
+    fn non_streamable_expressions() {
+        let errors = [
+            (
+                "product[last()]",
+                "predicates may only test the attributes, name and position of an element",
+            ),
+            (
+                "product[title = 'A']",
+                "predicates may only test the attributes, name and position of an element",
+            ),
+            (
+                "product[string()]",
+                "predicates may only test the attributes, name and position of an element",
+            ),
+            (
+                "title/ancestor::product",
+                "the Ancestor axis is not streamable",
+            ),
+            (
+                "product/@id/..",
+                "an attribute step must be the last step, without predicates",
+            ),
+            ("//text()", "only elements and attributes can be matched"),
+            ("count(//product)", "only location paths are streamable"),
+            ("/", "the root node cannot be matched"),
+        ];
+        for &(expression, message) in &errors {
+            let mut matcher = StreamMatcher::<LocatorImpl>::new();
+            let error = matcher
+                .on_match(expression, &NamespaceSupport::new(), |_| Ok(()))
+                .expect_err(expression);
+            assert_eq!(error.kind(), &ErrorKind::Expression);
+            assert_eq!(error.message(), message, "{}", expression);
+        }
+        let log = RefCell::new(Vec::new());
+        let mut matcher = StreamMatcher::<LocatorImpl>::new();
+        assert_eq!(
+            matcher
+                .forward("product/@id", &NamespaceSupport::new(), Log(&log))
+                .expect_err("attribute")
+                .message(),
+            "attributes cannot be forwarded to a content handler"
+        );
+    }
+}
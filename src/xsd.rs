@@ -0,0 +1,1118 @@
+//! XML Schema (XSD 1.0) validation: interfaces, a schema engine and the built-in simple types.
+//!
+//! A schema engine loads a set of schema documents through a `SchemaLoader`, resolving
+//! `xs:include`, `xs:import` and `xs:redefine` through an `EntityResolver`, and compiles them
+//! into a `Schema`. The schema then hands out `ValidatorHandler`s: content handlers that
+//! validate the events passing through them on the fly and forward them downstream, reporting
+//! violations through an `ErrorHandler`.
+//!
+//! `loader::Loader` is such an engine: it compiles schema documents into a
+//! `components::SchemaSet`, whose `validator::Validator` checks complex types and their content
+//! models, substitution groups, `xsi:type` and `xsi:nil`, wildcards, default and fixed values,
+//! and identity constraints, and reports the type assigned to each element and attribute.
+//!
+//! The built-in simple types of XML Schema Part 2 and their facets are provided here as well,
+//! since they are needed by every schema engine as well as by applications that want to check
+//! individual values. The regular expressions of the `pattern` facet are compiled by
+//! `regex::Regex`.
+//!
+//! Modelled after `javax.xml.validation` and `org.w3c.dom.TypeInfo`
+use std::cmp::Ordering;
+
+use common::EntityResolver;
+use common::Locator;
+use helpers::AttributesImpl;
+use helpers::ErrorKind;
+use helpers::ParseErrorImpl;
+use names;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::ErrorHandler;
+use sax2::ParseError;
+use sax2::Result;
+use xsd::regex::Regex;
+
+mod categories;
+pub mod components;
+pub mod loader;
+pub mod regex;
+pub mod validator;
+
+/// The XML Schema Namespace URI.
+pub const XSD_NS: &str = "http://www.w3.org/2001/XMLSchema";
+
+/// The XML Schema instance Namespace URI, used for `xsi:type`, `xsi:nil`,
+/// `xsi:schemaLocation` and `xsi:noNamespaceSchemaLocation`.
+pub const XSI_NS: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
+/// Derivation by restriction, for `TypeInfo::is_derived_from`.
+pub const DERIVATION_RESTRICTION: u32 = 0x1;
+/// Derivation by extension, for `TypeInfo::is_derived_from`.
+pub const DERIVATION_EXTENSION: u32 = 0x2;
+/// Derivation by union, for `TypeInfo::is_derived_from`.
+pub const DERIVATION_UNION: u32 = 0x4;
+/// Derivation by list, for `TypeInfo::is_derived_from`.
+pub const DERIVATION_LIST: u32 = 0x8;
+
+/// The type of an element or attribute, as determined by validation.
+///
+/// Modelled after `org.w3c.dom.TypeInfo`
+pub trait TypeInfo {
+    /// Return the name of the type, or `None` for an anonymous type.
+    fn get_type_name(&self) -> Option<String>;
+    /// Return the Namespace of the type, or `None` if the type has no Namespace.
+    fn get_type_namespace(&self) -> Option<String>;
+    /// Return whether this type is derived from the given type, by any of the derivation
+    /// methods set in `derivation_method` (a combination of the `DERIVATION_*` flags). Zero
+    /// means any derivation method.
+    fn is_derived_from(
+        &self,
+        type_namespace: &str,
+        type_name: &str,
+        derivation_method: u32,
+    ) -> bool;
+}
+
+/// A compiled set of schema components, from which validators are created.
+///
+/// A schema is immutable once loaded, and may be used to validate any number of documents.
+///
+/// Modelled after `javax.xml.validation.Schema`
+pub trait Schema {
+    /// The type of validator handler produced by this schema, for documents whose events come
+    /// with locators of type `L`, forwarding to content handlers of type `CH` and reporting to
+    /// error handlers of type `EH`.
+    type Handler<L: Locator, CH, EH>;
+    /// Create a new validator handler for a single document, with no content handler or error
+    /// handler set.
+    fn new_validator_handler<L: Locator, CH, EH>(&self) -> Self::Handler<L, CH, EH>;
+}
+
+/// Loads schema documents and compiles them into a `Schema`.
+///
+/// Modelled after `javax.xml.validation.SchemaFactory`
+pub trait SchemaLoader<ER: EntityResolver, EH: ErrorHandler<E>, E: ParseError> {
+    /// The type of schema produced by this loader.
+    type Schema: Schema;
+    /// Register the entity resolver used to locate schema documents.
+    ///
+    /// Every schema document, including the initial ones and those referenced by `xs:include`,
+    /// `xs:import` and `xs:redefine`, is first offered to the resolver; only if it declines
+    /// does the loader fall back to its own resolution of the system identifier. For
+    /// `xs:import`, the target Namespace is passed as the public identifier.
+    fn set_entity_resolver(&self, resolver: ER);
+    /// Register the error handler that receives errors found in the schema documents.
+    fn set_error_handler(&self, handler: EH);
+    /// Load and compile the schema documents with the given system identifiers into a single
+    /// schema.
+    fn new_schema(&self, system_ids: &[&str]) -> Result<Self::Schema>;
+}
+
+/// A content handler that validates the events passing through it against a schema.
+///
+/// A validator handler sits between a reader (or filter) and the application's content
+/// handler. Each event is validated and then forwarded downstream, with the attribute list
+/// augmented by any attributes defaulted by the schema. Violations are reported through the
+/// registered error handler, using `ErrorHandler::error`, with the locator supplied through
+/// `set_document_locator`.
+///
+/// Which parts of XSD 1.0 Structures are validated is up to the implementation;
+/// `validator::Validator` documents its own coverage.
+///
+/// During a `start_element` callback downstream, and until the matching `end_element`,
+/// `get_element_type_info` returns the type assigned to the current element.
+///
+/// Modelled after `javax.xml.validation.ValidatorHandler`
+pub trait ValidatorHandler<L, A, CH, EH, E>: ContentHandler<L, A>
+where
+    L: Locator,
+    A: Attributes,
+    CH: ContentHandler<L, AttributesImpl>,
+    EH: ErrorHandler<E>,
+    E: ParseError,
+{
+    /// The type information reported for elements and attributes.
+    type Type: TypeInfo;
+    /// Return the downstream content handler.
+    fn get_content_handler(&self) -> Option<&CH>;
+    /// Set the downstream content handler that receives the validated events.
+    fn set_content_handler(&mut self, handler: CH);
+    /// Return the error handler.
+    fn get_error_handler(&self) -> Option<&EH>;
+    /// Set the error handler that receives validation errors.
+    fn set_error_handler(&mut self, handler: EH);
+    /// Return the type assigned to the current element, or `None` outside of an element or if
+    /// the element could not be assigned a type.
+    fn get_element_type_info(&self) -> Option<Self::Type>;
+    /// Return the type assigned to an attribute of the current element, by its index in the
+    /// attribute list forwarded downstream.
+    fn get_attribute_type_info(&self, index: u64) -> Option<Self::Type>;
+}
+
+/// The `whiteSpace` facet: how white space in a literal is normalized before validation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WhiteSpace {
+    /// No normalization is done.
+    Preserve,
+    /// Tabs, line feeds and carriage returns are replaced by spaces.
+    Replace,
+    /// After replacement, runs of spaces are collapsed and leading and trailing spaces removed.
+    Collapse,
+}
+
+impl WhiteSpace {
+    /// Normalize a literal.
+    pub fn normalize(self, literal: &str) -> String {
+        match self {
+            WhiteSpace::Preserve => literal.to_owned(),
+            WhiteSpace::Replace => literal
+                .chars()
+                .map(|c| if names::is_whitespace(c) { ' ' } else { c })
+                .collect(),
+            WhiteSpace::Collapse => literal
+                .split(names::is_whitespace)
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<&str>>()
+                .join(" "),
+        }
+    }
+}
+
+/// The built-in simple types of XML Schema Part 2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BuiltinType {
+    AnySimpleType,
+    String,
+    NormalizedString,
+    Token,
+    Language,
+    Name,
+    NCName,
+    Id,
+    Idref,
+    Idrefs,
+    Entity,
+    Entities,
+    Nmtoken,
+    Nmtokens,
+    QName,
+    Notation,
+    AnyUri,
+    Boolean,
+    Decimal,
+    Integer,
+    NonPositiveInteger,
+    NegativeInteger,
+    Long,
+    Int,
+    Short,
+    Byte,
+    NonNegativeInteger,
+    UnsignedLong,
+    UnsignedInt,
+    UnsignedShort,
+    UnsignedByte,
+    PositiveInteger,
+    Float,
+    Double,
+    Duration,
+    DateTime,
+    Time,
+    Date,
+    GYearMonth,
+    GYear,
+    GMonthDay,
+    GDay,
+    GMonth,
+    HexBinary,
+    Base64Binary,
+}
+
+const BUILTIN_NAMES: &[(&str, BuiltinType)] = &[
+    ("anySimpleType", BuiltinType::AnySimpleType),
+    ("string", BuiltinType::String),
+    ("normalizedString", BuiltinType::NormalizedString),
+    ("token", BuiltinType::Token),
+    ("language", BuiltinType::Language),
+    ("Name", BuiltinType::Name),
+    ("NCName", BuiltinType::NCName),
+    ("ID", BuiltinType::Id),
+    ("IDREF", BuiltinType::Idref),
+    ("IDREFS", BuiltinType::Idrefs),
+    ("ENTITY", BuiltinType::Entity),
+    ("ENTITIES", BuiltinType::Entities),
+    ("NMTOKEN", BuiltinType::Nmtoken),
+    ("NMTOKENS", BuiltinType::Nmtokens),
+    ("QName", BuiltinType::QName),
+    ("NOTATION", BuiltinType::Notation),
+    ("anyURI", BuiltinType::AnyUri),
+    ("boolean", BuiltinType::Boolean),
+    ("decimal", BuiltinType::Decimal),
+    ("integer", BuiltinType::Integer),
+    ("nonPositiveInteger", BuiltinType::NonPositiveInteger),
+    ("negativeInteger", BuiltinType::NegativeInteger),
+    ("long", BuiltinType::Long),
+    ("int", BuiltinType::Int),
+    ("short", BuiltinType::Short),
+    ("byte", BuiltinType::Byte),
+    ("nonNegativeInteger", BuiltinType::NonNegativeInteger),
+    ("unsignedLong", BuiltinType::UnsignedLong),
+    ("unsignedInt", BuiltinType::UnsignedInt),
+    ("unsignedShort", BuiltinType::UnsignedShort),
+    ("unsignedByte", BuiltinType::UnsignedByte),
+    ("positiveInteger", BuiltinType::PositiveInteger),
+    ("float", BuiltinType::Float),
+    ("double", BuiltinType::Double),
+    ("duration", BuiltinType::Duration),
+    ("dateTime", BuiltinType::DateTime),
+    ("time", BuiltinType::Time),
+    ("date", BuiltinType::Date),
+    ("gYearMonth", BuiltinType::GYearMonth),
+    ("gYear", BuiltinType::GYear),
+    ("gMonthDay", BuiltinType::GMonthDay),
+    ("gDay", BuiltinType::GDay),
+    ("gMonth", BuiltinType::GMonth),
+    ("hexBinary", BuiltinType::HexBinary),
+    ("base64Binary", BuiltinType::Base64Binary),
+];
+
+impl BuiltinType {
+    /// Look up a built-in type by its local name in the XML Schema Namespace.
+    pub fn from_name(local_name: &str) -> Option<BuiltinType> {
+        BUILTIN_NAMES
+            .iter()
+            .find(|entry| entry.0 == local_name)
+            .map(|entry| entry.1)
+    }
+
+    /// Return the local name of the type in the XML Schema Namespace.
+    pub fn name(self) -> &'static str {
+        BUILTIN_NAMES
+            .iter()
+            .find(|entry| entry.1 == self)
+            .map(|entry| entry.0)
+            .unwrap_or("anySimpleType")
+    }
+
+    /// Return the value of the `whiteSpace` facet for this type.
+    pub fn white_space(self) -> WhiteSpace {
+        match self {
+            BuiltinType::String | BuiltinType::AnySimpleType => WhiteSpace::Preserve,
+            BuiltinType::NormalizedString => WhiteSpace::Replace,
+            _ => WhiteSpace::Collapse,
+        }
+    }
+
+    /// Return the built-in type this type is derived from by restriction, or `None` for
+    /// `anySimpleType`, the primitive types and the list types.
+    pub fn base(self) -> Option<BuiltinType> {
+        Some(match self {
+            BuiltinType::NormalizedString => BuiltinType::String,
+            BuiltinType::Token => BuiltinType::NormalizedString,
+            BuiltinType::Language | BuiltinType::Name | BuiltinType::Nmtoken => BuiltinType::Token,
+            BuiltinType::NCName => BuiltinType::Name,
+            BuiltinType::Id | BuiltinType::Idref | BuiltinType::Entity => BuiltinType::NCName,
+            BuiltinType::Integer => BuiltinType::Decimal,
+            BuiltinType::NonPositiveInteger
+            | BuiltinType::Long
+            | BuiltinType::NonNegativeInteger => BuiltinType::Integer,
+            BuiltinType::NegativeInteger => BuiltinType::NonPositiveInteger,
+            BuiltinType::Int => BuiltinType::Long,
+            BuiltinType::Short => BuiltinType::Int,
+            BuiltinType::Byte => BuiltinType::Short,
+            BuiltinType::UnsignedLong | BuiltinType::PositiveInteger => {
+                BuiltinType::NonNegativeInteger
+            }
+            BuiltinType::UnsignedInt => BuiltinType::UnsignedLong,
+            BuiltinType::UnsignedShort => BuiltinType::UnsignedInt,
+            BuiltinType::UnsignedByte => BuiltinType::UnsignedShort,
+            _ => return None,
+        })
+    }
+
+    /// Return whether this is a list type, whose value is a space-separated list of items.
+    pub fn is_list(self) -> bool {
+        matches!(
+            self,
+            BuiltinType::Idrefs | BuiltinType::Entities | BuiltinType::Nmtokens
+        )
+    }
+
+    /// Return whether this type is `decimal` or derived from it.
+    pub fn is_decimal(self) -> bool {
+        self == BuiltinType::Decimal || self.integer_bounds().is_some()
+    }
+
+    fn integer_bounds(self) -> Option<(Option<&'static str>, Option<&'static str>)> {
+        Some(match self {
+            BuiltinType::Integer => (None, None),
+            BuiltinType::NonPositiveInteger => (None, Some("0")),
+            BuiltinType::NegativeInteger => (None, Some("-1")),
+            BuiltinType::Long => (Some("-9223372036854775808"), Some("9223372036854775807")),
+            BuiltinType::Int => (Some("-2147483648"), Some("2147483647")),
+            BuiltinType::Short => (Some("-32768"), Some("32767")),
+            BuiltinType::Byte => (Some("-128"), Some("127")),
+            BuiltinType::NonNegativeInteger => (Some("0"), None),
+            BuiltinType::UnsignedLong => (Some("0"), Some("18446744073709551615")),
+            BuiltinType::UnsignedInt => (Some("0"), Some("4294967295")),
+            BuiltinType::UnsignedShort => (Some("0"), Some("65535")),
+            BuiltinType::UnsignedByte => (Some("0"), Some("255")),
+            BuiltinType::PositiveInteger => (Some("1"), None),
+            _ => return None,
+        })
+    }
+
+    /// Validate a literal against this type.
+    ///
+    /// Returns the literal after white space normalization.
+    pub fn validate(self, literal: &str) -> std::result::Result<String, ParseErrorImpl> {
+        let value = self.white_space().normalize(literal);
+        if self.is_list() {
+            let item = match self {
+                BuiltinType::Idrefs => BuiltinType::Idref,
+                BuiltinType::Entities => BuiltinType::Entity,
+                _ => BuiltinType::Nmtoken,
+            };
+            if value.is_empty() {
+                return Err(invalid(literal, self));
+            }
+            for token in value.split(' ') {
+                item.validate(token)?;
+            }
+            return Ok(value);
+        }
+        let valid = match self {
+            BuiltinType::AnySimpleType
+            | BuiltinType::String
+            | BuiltinType::NormalizedString
+            | BuiltinType::Token
+            | BuiltinType::AnyUri => true,
+            BuiltinType::Language => is_language(&value),
+            BuiltinType::Name => names::is_name(&value),
+            BuiltinType::NCName | BuiltinType::Id | BuiltinType::Idref | BuiltinType::Entity => {
+                names::is_ncname(&value)
+            }
+            BuiltinType::Nmtoken => names::is_nmtoken(&value),
+            BuiltinType::QName | BuiltinType::Notation => names::is_qname(&value),
+            BuiltinType::Boolean => matches!(value.as_str(), "true" | "false" | "1" | "0"),
+            BuiltinType::Decimal => Decimal::parse(&value).is_some(),
+            BuiltinType::Float | BuiltinType::Double => parse_float(&value).is_some(),
+            BuiltinType::Duration => is_duration(&value),
+            BuiltinType::DateTime => match value.find('T') {
+                Some(i) => is_date(&value[..i]) && is_time(&value[i + 1..]),
+                None => false,
+            },
+            BuiltinType::Time => is_time(&value),
+            BuiltinType::Date => is_date_with_timezone(&value, is_date),
+            BuiltinType::GYearMonth => is_date_with_timezone(&value, |v| {
+                let (year, rest) = match split_year(v) {
+                    Some(split) => split,
+                    None => return false,
+                };
+                rest.len() == 3
+                    && rest.starts_with('-')
+                    && parse_fixed(&rest[1..], 2).is_some_and(|m| (1..=12).contains(&m))
+                    && year.is_some()
+            }),
+            BuiltinType::GYear => is_date_with_timezone(&value, |v| {
+                split_year(v).is_some_and(|(_, rest)| rest.is_empty())
+            }),
+            BuiltinType::GMonthDay => is_date_with_timezone(&value, |v| {
+                v.len() == 7
+                    && v.starts_with("--")
+                    && &v[4..5] == "-"
+                    && match (parse_fixed(&v[2..4], 2), parse_fixed(&v[5..7], 2)) {
+                        (Some(m), Some(d)) => {
+                            (1..=12).contains(&m) && d >= 1 && d <= days_in_month(2000, m)
+                        }
+                        _ => false,
+                    }
+            }),
+            BuiltinType::GDay => is_date_with_timezone(&value, |v| {
+                v.len() == 5
+                    && v.starts_with("---")
+                    && parse_fixed(&v[3..], 2).is_some_and(|d| (1..=31).contains(&d))
+            }),
+            BuiltinType::GMonth => is_date_with_timezone(&value, |v| {
+                v.len() == 4
+                    && v.starts_with("--")
+                    && parse_fixed(&v[2..], 2).is_some_and(|m| (1..=12).contains(&m))
+            }),
+            BuiltinType::HexBinary => {
+                value.len().is_multiple_of(2) && value.chars().all(|c| c.is_ascii_hexdigit())
+            }
+            BuiltinType::Base64Binary => base64_length(&value).is_some(),
+            _ => match self.integer_bounds() {
+                Some((min, max)) => match Decimal::parse(&value) {
+                    Some(ref decimal) if decimal.is_integer_literal => {
+                        min.is_none_or(|min| {
+                            decimal.cmp(&Decimal::parse(min).unwrap()) != Ordering::Less
+                        }) && max.is_none_or(|max| {
+                            decimal.cmp(&Decimal::parse(max).unwrap()) != Ordering::Greater
+                        })
+                    }
+                    _ => false,
+                },
+                None => false,
+            },
+        };
+        if valid {
+            Ok(value)
+        } else {
+            Err(invalid(literal, self))
+        }
+    }
+
+    /// Return the length of a valid, normalized value, in the units used by the `length`,
+    /// `minLength` and `maxLength` facets: octets for binary types, items for list types, and
+    /// characters otherwise.
+    pub fn value_length(self, value: &str) -> usize {
+        match self {
+            BuiltinType::HexBinary => value.len() / 2,
+            BuiltinType::Base64Binary => base64_length(value).unwrap_or(0),
+            _ if self.is_list() => value.split(' ').filter(|s| !s.is_empty()).count(),
+            _ => value.chars().count(),
+        }
+    }
+}
+
+fn invalid(literal: &str, builtin: BuiltinType) -> ParseErrorImpl {
+    ParseErrorImpl::of_kind(
+        ErrorKind::Validity,
+        format!(
+            "'{}' is not a valid value of type xs:{}",
+            literal,
+            builtin.name()
+        ),
+    )
+}
+
+fn is_language(value: &str) -> bool {
+    let mut parts = value.split('-');
+    let first = parts.next().unwrap_or("");
+    (1..=8).contains(&first.len())
+        && first.chars().all(|c| c.is_ascii_alphabetic())
+        && parts.all(|part| {
+            (1..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+fn parse_float(value: &str) -> Option<f64> {
+    match value {
+        "INF" => return Some(f64::INFINITY),
+        "-INF" => return Some(f64::NEG_INFINITY),
+        "NaN" => return Some(f64::NAN),
+        _ => {}
+    }
+    let (mantissa, exponent) = match value.find(['e', 'E']) {
+        Some(i) => (&value[..i], Some(&value[i + 1..])),
+        None => (value, None),
+    };
+    Decimal::parse(mantissa)?;
+    if let Some(exponent) = exponent {
+        let digits = exponent.trim_start_matches(['+', '-']);
+        if digits.is_empty()
+            || exponent.len() - digits.len() > 1
+            || !digits.chars().all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+    }
+    value.parse::<f64>().ok()
+}
+
+fn parse_fixed(digits: &str, width: usize) -> Option<u32> {
+    if digits.len() == width && digits.chars().all(|c| c.is_ascii_digit()) {
+        digits.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+/// Split a leading year (at least four digits, optionally negative, not 0000) from the rest.
+///
+/// Returns `Some((None, rest))` if the year is syntactically valid but out of range for
+/// `i64`.
+fn split_year(value: &str) -> Option<(Option<i64>, &str)> {
+    let unsigned = value.strip_prefix('-').unwrap_or(value);
+    let digits = unsigned
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(unsigned.len());
+    let year = &unsigned[..digits];
+    if year.len() < 4 || (year.len() > 4 && year.starts_with('0')) || year == "0000" {
+        return None;
+    }
+    let parsed = value[..value.len() - unsigned.len() + digits]
+        .parse::<i64>()
+        .ok();
+    Some((parsed, &unsigned[digits..]))
+}
+
+fn is_timezone(tz: &str) -> bool {
+    if tz.is_empty() || tz == "Z" {
+        return true;
+    }
+    if tz.len() != 6 || !(tz.starts_with('+') || tz.starts_with('-')) || &tz[3..4] != ":" {
+        return false;
+    }
+    match (parse_fixed(&tz[1..3], 2), parse_fixed(&tz[4..6], 2)) {
+        (Some(14), Some(0)) => true,
+        (Some(h), Some(m)) => h < 14 && m < 60,
+        _ => false,
+    }
+}
+
+fn split_timezone(value: &str) -> (&str, &str) {
+    if let Some(stripped) = value.strip_suffix('Z') {
+        return (stripped, "Z");
+    }
+    if value.len() > 6 {
+        let (head, tz) = value.split_at(value.len() - 6);
+        if (tz.starts_with('+') || tz.starts_with('-')) && &tz[3..4] == ":" {
+            return (head, tz);
+        }
+    }
+    (value, "")
+}
+
+fn is_date_with_timezone<F: Fn(&str) -> bool>(value: &str, check: F) -> bool {
+    let (head, tz) = split_timezone(value);
+    is_timezone(tz) && check(head)
+}
+
+fn is_date(value: &str) -> bool {
+    let (year, rest) = match split_year(value) {
+        Some(split) => split,
+        None => return false,
+    };
+    if rest.len() != 6 || &rest[0..1] != "-" || &rest[3..4] != "-" {
+        return false;
+    }
+    match (parse_fixed(&rest[1..3], 2), parse_fixed(&rest[4..6], 2)) {
+        (Some(m), Some(d)) => {
+            (1..=12).contains(&m) && d >= 1 && d <= days_in_month(year.unwrap_or(2000), m)
+        }
+        _ => false,
+    }
+}
+
+fn is_time(value: &str) -> bool {
+    let (head, tz) = split_timezone(value);
+    if !is_timezone(tz) || head.len() < 8 || &head[2..3] != ":" || &head[5..6] != ":" {
+        return false;
+    }
+    let fraction = &head[8..];
+    if !fraction.is_empty()
+        && (fraction.len() < 2
+            || !fraction.starts_with('.')
+            || !fraction[1..].chars().all(|c| c.is_ascii_digit()))
+    {
+        return false;
+    }
+    match (
+        parse_fixed(&head[0..2], 2),
+        parse_fixed(&head[3..5], 2),
+        parse_fixed(&head[6..8], 2),
+    ) {
+        (Some(24), Some(0), Some(0)) => fraction.chars().all(|c| c == '.' || c == '0'),
+        (Some(h), Some(m), Some(s)) => h < 24 && m < 60 && s < 60,
+        _ => false,
+    }
+}
+
+fn is_duration(value: &str) -> bool {
+    let body = value.strip_prefix('-').unwrap_or(value);
+    let body = match body.strip_prefix('P') {
+        Some(body) if !body.is_empty() => body,
+        _ => return false,
+    };
+    let (date, time) = match body.find('T') {
+        Some(i) if i + 1 < body.len() => (&body[..i], Some(&body[i + 1..])),
+        Some(_) => return false,
+        None => (body, None),
+    };
+    duration_fields(date, &['Y', 'M', 'D'], false)
+        && time.is_none_or(|time| duration_fields(time, &['H', 'M', 'S'], true))
+}
+
+fn duration_fields(mut fields: &str, designators: &[char], fractional_seconds: bool) -> bool {
+    let mut next = 0;
+    while !fields.is_empty() {
+        let end = match fields.find(|c: char| !c.is_ascii_digit() && c != '.') {
+            Some(end) if end > 0 => end,
+            _ => return false,
+        };
+        let number = &fields[..end];
+        let designator = fields[end..].chars().next().unwrap_or(' ');
+        let position = match designators[next..].iter().position(|d| *d == designator) {
+            Some(position) => next + position,
+            None => return false,
+        };
+        let is_seconds = fractional_seconds && designator == 'S';
+        let number_ok = if is_seconds {
+            Decimal::parse(number).is_some_and(|d| !d.negative && !number.starts_with('+'))
+        } else {
+            number.chars().all(|c| c.is_ascii_digit())
+        };
+        if !number_ok {
+            return false;
+        }
+        next = position + 1;
+        fields = &fields[end + 1..];
+    }
+    true
+}
+
+fn base64_length(value: &str) -> Option<usize> {
+    let compact: Vec<u8> = value.bytes().filter(|b| *b != b' ').collect();
+    if !compact.len().is_multiple_of(4) {
+        return None;
+    }
+    let padding = compact.iter().rev().take_while(|b| **b == b'=').count();
+    if padding > 2 {
+        return None;
+    }
+    let data = &compact[..compact.len() - padding];
+    if !data
+        .iter()
+        .all(|b| b.is_ascii_alphanumeric() || *b == b'+' || *b == b'/')
+    {
+        return None;
+    }
+    Some(compact.len() / 4 * 3 - padding)
+}
+
+/// A decimal number in a form that can be compared exactly, regardless of its magnitude.
+#[derive(Clone, Debug)]
+struct Decimal {
+    negative: bool,
+    /// Integer digits without leading zeros.
+    integer: String,
+    /// Fraction digits without trailing zeros.
+    fraction: String,
+    is_integer_literal: bool,
+}
+
+impl Decimal {
+    fn parse(literal: &str) -> Option<Decimal> {
+        let (negative, unsigned) = match literal.as_bytes().first() {
+            Some(b'-') => (true, &literal[1..]),
+            Some(b'+') => (false, &literal[1..]),
+            _ => (false, literal),
+        };
+        let (integer, fraction, is_integer_literal) = match unsigned.find('.') {
+            Some(i) => (&unsigned[..i], &unsigned[i + 1..], false),
+            None => (unsigned, "", true),
+        };
+        if (integer.is_empty() && fraction.is_empty())
+            || !integer.chars().all(|c| c.is_ascii_digit())
+            || !fraction.chars().all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+        let integer = integer.trim_start_matches('0').to_owned();
+        let fraction = fraction.trim_end_matches('0').to_owned();
+        let is_zero = integer.is_empty() && fraction.is_empty();
+        Some(Decimal {
+            negative: negative && !is_zero,
+            integer,
+            fraction,
+            is_integer_literal,
+        })
+    }
+
+    fn total_digits(&self) -> usize {
+        let digits = self.integer.len() + self.fraction.len();
+        if digits == 0 {
+            1
+        } else if self.integer.is_empty() {
+            self.fraction.trim_start_matches('0').len()
+        } else {
+            digits
+        }
+    }
+
+    fn cmp_magnitude(&self, other: &Decimal) -> Ordering {
+        self.integer
+            .len()
+            .cmp(&other.integer.len())
+            .then_with(|| self.integer.cmp(&other.integer))
+            .then_with(|| self.fraction.cmp(&other.fraction))
+    }
+
+    fn cmp(&self, other: &Decimal) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => self.cmp_magnitude(other),
+            (true, true) => other.cmp_magnitude(self),
+        }
+    }
+}
+
+/// The constraining facets that may restrict a built-in type.
+///
+/// Ordering facets (`minInclusive` and friends) are applied to the numeric types only.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Facets {
+    pub length: Option<usize>,
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    pub enumeration: Vec<String>,
+    pub min_inclusive: Option<String>,
+    pub max_inclusive: Option<String>,
+    pub min_exclusive: Option<String>,
+    pub max_exclusive: Option<String>,
+    pub total_digits: Option<usize>,
+    pub fraction_digits: Option<usize>,
+    /// The `pattern` facets, all of which a value must match. The patterns given in a single
+    /// derivation step of a schema are alternatives, and should be joined with `|` into one.
+    pub patterns: Vec<Regex>,
+    /// Overrides the `whiteSpace` facet of the base type, if it is less strict.
+    pub white_space: Option<WhiteSpace>,
+}
+
+impl Facets {
+    /// Return the ordering facets, with the orderings of a value relative to the limit that
+    /// each one rejects.
+    fn ordering_facets(&self) -> [(&'static str, &Option<String>, &'static [Ordering]); 4] {
+        [
+            ("minInclusive", &self.min_inclusive, &[Ordering::Less]),
+            ("maxInclusive", &self.max_inclusive, &[Ordering::Greater]),
+            (
+                "minExclusive",
+                &self.min_exclusive,
+                &[Ordering::Less, Ordering::Equal],
+            ),
+            (
+                "maxExclusive",
+                &self.max_exclusive,
+                &[Ordering::Greater, Ordering::Equal],
+            ),
+        ]
+    }
+}
+
+/// A simple type: a built-in type, optionally restricted by facets.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimpleType {
+    pub base: BuiltinType,
+    pub facets: Facets,
+}
+
+impl SimpleType {
+    /// Construct an unrestricted simple type.
+    pub fn new(base: BuiltinType) -> Self {
+        SimpleType {
+            base,
+            facets: Facets::default(),
+        }
+    }
+
+    /// Construct a simple type restricted by the given facets.
+    pub fn with_facets(base: BuiltinType, facets: Facets) -> Self {
+        SimpleType { base, facets }
+    }
+
+    /// Validate a literal against this type.
+    ///
+    /// Returns the literal after white space normalization.
+    pub fn validate(&self, literal: &str) -> std::result::Result<String, ParseErrorImpl> {
+        let base = self.base;
+        let literal = match self.facets.white_space {
+            Some(white_space) => white_space.normalize(literal),
+            None => literal.to_owned(),
+        };
+        let value = base.validate(&literal)?;
+        let facets = &self.facets;
+        let fail = |facet: &str, detail: &dyn std::fmt::Display| {
+            Err(ParseErrorImpl::of_kind(
+                ErrorKind::Validity,
+                format!(
+                    "'{}' is not facet-valid with respect to {} '{}' for type xs:{}",
+                    value,
+                    facet,
+                    detail,
+                    base.name()
+                ),
+            ))
+        };
+
+        let length = base.value_length(&value);
+        if let Some(expected) = facets.length {
+            if length != expected {
+                return fail("length", &expected);
+            }
+        }
+        if let Some(min) = facets.min_length {
+            if length < min {
+                return fail("minLength", &min);
+            }
+        }
+        if let Some(max) = facets.max_length {
+            if length > max {
+                return fail("maxLength", &max);
+            }
+        }
+
+        if let Some(pattern) = facets.patterns.iter().find(|p| !p.is_match(&value)) {
+            return fail("pattern", pattern);
+        }
+
+        if base.is_decimal() {
+            let decimal = Decimal::parse(&value).expect("validated decimal");
+            for (name, facet, rejected) in facets.ordering_facets() {
+                if let Some(limit) = facet.as_ref().and_then(|b| Decimal::parse(b)) {
+                    if rejected.contains(&decimal.cmp(&limit)) {
+                        return fail(name, facet.as_ref().unwrap());
+                    }
+                }
+            }
+            if let Some(total) = facets.total_digits {
+                if decimal.total_digits() > total {
+                    return fail("totalDigits", &total);
+                }
+            }
+            if let Some(fraction) = facets.fraction_digits {
+                if decimal.fraction.len() > fraction {
+                    return fail("fractionDigits", &fraction);
+                }
+            }
+            if !facets.enumeration.is_empty()
+                && !facets
+                    .enumeration
+                    .iter()
+                    .any(|e| Decimal::parse(e).is_some_and(|e| e.cmp(&decimal) == Ordering::Equal))
+            {
+                return fail("enumeration", &facets.enumeration.join("|"));
+            }
+        } else if base == BuiltinType::Float || base == BuiltinType::Double {
+            let number = parse_float(&value).expect("validated float");
+            for (name, facet, rejected) in facets.ordering_facets() {
+                if let Some(limit) = facet.as_ref().and_then(|b| parse_float(b)) {
+                    if number
+                        .partial_cmp(&limit)
+                        .is_none_or(|order| rejected.contains(&order))
+                    {
+                        return fail(name, facet.as_ref().unwrap());
+                    }
+                }
+            }
+            if !facets.enumeration.is_empty()
+                && !facets.enumeration.iter().any(|e| {
+                    parse_float(e).is_some_and(|e| e == number || (e.is_nan() && number.is_nan()))
+                })
+            {
+                return fail("enumeration", &facets.enumeration.join("|"));
+            }
+        } else if !facets.enumeration.is_empty()
+            && !facets
+                .enumeration
+                .iter()
+                .any(|e| base.white_space().normalize(e) == value)
+        {
+            return fail("enumeration", &facets.enumeration.join("|"));
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(source: &str) -> Regex {
+        Regex::new(source).unwrap()
+    }
+
+    #[test]
+    fn white_space() {
+        assert_eq!(WhiteSpace::Preserve.normalize(" a\tb "), " a\tb ");
+        assert_eq!(WhiteSpace::Replace.normalize(" a\tb\n"), " a b ");
+        assert_eq!(WhiteSpace::Collapse.normalize("  a \t\n b  "), "a b");
+    }
+
+    #[test]
+    fn builtin_types() {
+        let valid = [
+            (BuiltinType::Boolean, "1"),
+            (BuiltinType::Decimal, "-12.50"),
+            (BuiltinType::Integer, " +42 "),
+            (BuiltinType::Byte, "-128"),
+            (BuiltinType::UnsignedLong, "18446744073709551615"),
+            (BuiltinType::Double, "-INF"),
+            (BuiltinType::Float, "1.5e3"),
+            (BuiltinType::Date, "2004-02-29Z"),
+            (BuiltinType::DateTime, "2001-10-26T21:32:52.12679+02:00"),
+            (BuiltinType::Duration, "P1Y2M3DT10H30M"),
+            (BuiltinType::GMonthDay, "--02-29"),
+            (BuiltinType::HexBinary, "0fB7"),
+            (BuiltinType::Base64Binary, "aGVsbG8="),
+            (BuiltinType::Language, "en-GB"),
+            (BuiltinType::NCName, "a.b-c"),
+            (BuiltinType::QName, "x:y"),
+            (BuiltinType::Idrefs, " a  b "),
+        ];
+        for &(builtin, literal) in &valid {
+            assert!(
+                builtin.validate(literal).is_ok(),
+                "{:?} rejected {:?}",
+                builtin,
+                literal
+            );
+        }
+        let invalid = [
+            (BuiltinType::Boolean, "yes"),
+            (BuiltinType::Decimal, "1e3"),
+            (BuiltinType::Integer, "1.0"),
+            (BuiltinType::Byte, "128"),
+            (BuiltinType::NonNegativeInteger, "-1"),
+            (BuiltinType::Date, "2003-02-29"),
+            (BuiltinType::Time, "24:01:00"),
+            (BuiltinType::Duration, "P"),
+            (BuiltinType::HexBinary, "abc"),
+            (BuiltinType::Base64Binary, "aGVsbG8"),
+            (BuiltinType::NCName, "a:b"),
+            (BuiltinType::Idrefs, ""),
+        ];
+        for &(builtin, literal) in &invalid {
+            assert!(
+                builtin.validate(literal).is_err(),
+                "{:?} accepted {:?}",
+                builtin,
+                literal
+            );
+        }
+    }
+
+    #[test]
+    fn normalized_values() {
+        assert_eq!(BuiltinType::Token.validate(" a \t b ").unwrap(), "a b");
+        assert_eq!(BuiltinType::String.validate(" a\t").unwrap(), " a\t");
+        assert_eq!(BuiltinType::Nmtokens.value_length("a b c"), 3);
+        assert_eq!(BuiltinType::HexBinary.value_length("0a0b"), 2);
+        assert_eq!(BuiltinType::Base64Binary.value_length("aGVsbG8="), 5);
+        assert_eq!(BuiltinType::from_name("int"), Some(BuiltinType::Int));
+        assert_eq!(BuiltinType::from_name("anyType"), None);
+    }
+
+    #[test]
+    fn length_facets() {
+        let facets = Facets {
+            min_length: Some(2),
+            max_length: Some(3),
+            ..Facets::default()
+        };
+        let string = SimpleType::with_facets(BuiltinType::String, facets.clone());
+        assert!(string.validate("ab").is_ok());
+        assert_eq!(
+            string.validate("abcd").expect_err("too long").message(),
+            "'abcd' is not facet-valid with respect to maxLength '3' for type xs:string"
+        );
+        let list = SimpleType::with_facets(BuiltinType::Nmtokens, facets);
+        assert!(list.validate("a b c").is_ok());
+        assert!(list.validate("a").is_err());
+    }
+
+    #[test]
+    fn numeric_facets() {
+        let facets = Facets {
+            min_exclusive: Some("0".to_owned()),
+            max_inclusive: Some("100.5".to_owned()),
+            total_digits: Some(3),
+            fraction_digits: Some(1),
+            ..Facets::default()
+        };
+        let decimal = SimpleType::with_facets(BuiltinType::Decimal, facets);
+        assert!(decimal.validate("99.50").is_ok());
+        assert!(decimal.validate("0.1").is_ok());
+        for &(literal, facet) in &[
+            ("0", "minExclusive"),
+            ("100.6", "maxInclusive"),
+            ("12.34", "totalDigits"),
+            ("1.05", "fractionDigits"),
+        ] {
+            let error = decimal.validate(literal).expect_err(literal);
+            assert_eq!(error.kind(), &ErrorKind::Validity);
+            assert!(error.message().contains(facet), "{}", error.message());
+        }
+
+        let double = SimpleType::with_facets(
+            BuiltinType::Double,
+            Facets {
+                max_exclusive: Some("1e2".to_owned()),
+                ..Facets::default()
+            },
+        );
+        assert!(double.validate("99.9").is_ok());
+        assert!(double.validate("100").is_err());
+        assert!(double.validate("NaN").is_err());
+    }
+
+    #[test]
+    fn enumeration_facet() {
+        let facets = Facets {
+            enumeration: vec!["red".to_owned(), " green ".to_owned()],
+            ..Facets::default()
+        };
+        let token = SimpleType::with_facets(BuiltinType::Token, facets);
+        assert!(token.validate(" green").is_ok());
+        assert_eq!(
+            token
+                .validate("blue")
+                .expect_err("not enumerated")
+                .message(),
+            "'blue' is not facet-valid with respect to enumeration 'red| green ' for type xs:token"
+        );
+
+        let facets = Facets {
+            enumeration: vec!["1.0".to_owned(), "2".to_owned()],
+            ..Facets::default()
+        };
+        let integer = SimpleType::with_facets(BuiltinType::Integer, facets);
+        assert!(integer.validate("01").is_ok());
+        assert!(integer.validate("3").is_err());
+    }
+
+    #[test]
+    fn pattern_facets() {
+        let facets = Facets {
+            patterns: vec![pattern("[A-Z]{2}\\d+|X"), pattern(".{3}")],
+            ..Facets::default()
+        };
+        let code = SimpleType::with_facets(BuiltinType::Token, facets);
+        assert_eq!(code.validate(" AB1 ").unwrap(), "AB1");
+        assert!(code.validate("AB12").is_err());
+        assert_eq!(
+            code.validate("X").expect_err("second pattern").message(),
+            "'X' is not facet-valid with respect to pattern '.{3}' for type xs:token"
+        );
+    }
+
+    #[test]
+    fn white_space_facet() {
+        let facets = Facets {
+            white_space: Some(WhiteSpace::Collapse),
+            length: Some(3),
+            ..Facets::default()
+        };
+        let collapsed = SimpleType::with_facets(BuiltinType::String, facets);
+        assert_eq!(collapsed.validate("  a b ").unwrap(), "a b");
+        assert!(SimpleType::new(BuiltinType::String)
+            .validate("  a b ")
+            .is_ok());
+    }
+}
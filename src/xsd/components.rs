@@ -0,0 +1,930 @@
+//! The components of a schema compiled by `loader::Loader`, and the types they assign.
+//!
+//! Components refer to each other by their index in the tables of `Components`, so that
+//! recursive definitions, such as an element whose type contains the element itself, need no
+//! reference cycles. Content models are kept as expressions over element declarations and
+//! wildcards, which `validator::Validator` matches against the children of an element by
+//! computing derivatives, one child at a time.
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use common::Locator;
+use helpers::ErrorKind;
+use helpers::ParseErrorImpl;
+use names;
+use xsd::parse_float;
+use xsd::validator::Validator;
+use xsd::BuiltinType;
+use xsd::Decimal;
+use xsd::Facets;
+use xsd::Schema;
+use xsd::SimpleType;
+use xsd::TypeInfo;
+use xsd::WhiteSpace;
+use xsd::BUILTIN_NAMES;
+use xsd::DERIVATION_EXTENSION;
+use xsd::DERIVATION_LIST;
+use xsd::DERIVATION_RESTRICTION;
+use xsd::DERIVATION_UNION;
+use xsd::XSD_NS;
+
+/// Blocks substitution group members in place of an element, alongside the `DERIVATION_*`
+/// flags in the `block` of an element declaration.
+pub(crate) const BLOCK_SUBSTITUTION: u32 = 0x10;
+
+/// An expanded name: a Namespace URI, empty for no Namespace, and a local name.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct QName {
+    pub(crate) uri: String,
+    pub(crate) local_name: String,
+}
+
+impl QName {
+    pub(crate) fn new(uri: &str, local_name: &str) -> Self {
+        QName {
+            uri: uri.to_owned(),
+            local_name: local_name.to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for QName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.uri.is_empty() {
+            f.write_str(&self.local_name)
+        } else {
+            write!(f, "{{{}}}{}", self.uri, self.local_name)
+        }
+    }
+}
+
+/// The index of a type definition in `Components::types`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct TypeId(pub(crate) usize);
+
+/// The index of an element declaration in `Components::elements`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct ElementId(pub(crate) usize);
+
+/// The index of an identity constraint in `Components::constraints`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct ConstraintId(pub(crate) usize);
+
+/// The type definition `xs:anyType`, the root of the type hierarchy.
+pub(crate) const ANY_TYPE: TypeId = TypeId(0);
+
+/// A `default` or `fixed` value of an element or attribute.
+#[derive(Clone, Debug)]
+pub(crate) struct ValueConstraint {
+    pub(crate) value: String,
+    pub(crate) fixed: bool,
+}
+
+/// The Namespaces allowed by a wildcard. An absent Namespace is the empty string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum NamespaceConstraint {
+    /// `##any`.
+    Any,
+    /// `##other`: any Namespace but the given one, and not the absent Namespace.
+    Not(String),
+    /// A list of Namespaces.
+    Set(Vec<String>),
+}
+
+impl NamespaceConstraint {
+    pub(crate) fn allows(&self, uri: &str) -> bool {
+        match *self {
+            NamespaceConstraint::Any => true,
+            NamespaceConstraint::Not(ref other) => !uri.is_empty() && uri != other,
+            NamespaceConstraint::Set(ref set) => set.iter().any(|allowed| allowed == uri),
+        }
+    }
+
+    /// The intersection of two constraints, for the attribute wildcards of a type that has
+    /// several. The intersection of two different `##other` constraints cannot be expressed in
+    /// XSD 1.0, and the first of them is kept.
+    pub(crate) fn intersect(&self, other: &NamespaceConstraint) -> NamespaceConstraint {
+        match (self, other) {
+            (&NamespaceConstraint::Any, _) => other.clone(),
+            (_, &NamespaceConstraint::Any) => self.clone(),
+            (&NamespaceConstraint::Set(ref set), _) | (_, &NamespaceConstraint::Set(ref set)) => {
+                let constraint = if let NamespaceConstraint::Set(_) = *self {
+                    other
+                } else {
+                    self
+                };
+                NamespaceConstraint::Set(
+                    set.iter()
+                        .filter(|uri| constraint.allows(uri))
+                        .cloned()
+                        .collect(),
+                )
+            }
+            (&NamespaceConstraint::Not(_), &NamespaceConstraint::Not(_)) => self.clone(),
+        }
+    }
+
+    /// The union of two constraints, for the attribute wildcard of a type derived by extension.
+    /// The union of two different `##other` constraints cannot be expressed in XSD 1.0, and is
+    /// widened to any Namespace but the absent one.
+    pub(crate) fn union(&self, other: &NamespaceConstraint) -> NamespaceConstraint {
+        match (self, other) {
+            (&NamespaceConstraint::Any, _) | (_, &NamespaceConstraint::Any) => {
+                NamespaceConstraint::Any
+            }
+            (NamespaceConstraint::Set(a), NamespaceConstraint::Set(b)) => {
+                let mut set = a.clone();
+                set.extend(b.iter().filter(|uri| !a.contains(uri)).cloned());
+                NamespaceConstraint::Set(set)
+            }
+            (&NamespaceConstraint::Not(ref not), &NamespaceConstraint::Set(ref set))
+            | (&NamespaceConstraint::Set(ref set), &NamespaceConstraint::Not(ref not)) => {
+                if set.contains(not) && set.iter().any(|uri| uri.is_empty()) {
+                    NamespaceConstraint::Any
+                } else if set.contains(not) {
+                    NamespaceConstraint::Not(String::new())
+                } else {
+                    NamespaceConstraint::Not(not.clone())
+                }
+            }
+            (NamespaceConstraint::Not(a), NamespaceConstraint::Not(b)) => {
+                if a == b {
+                    self.clone()
+                } else {
+                    NamespaceConstraint::Not(String::new())
+                }
+            }
+        }
+    }
+}
+
+/// How the elements or attributes matched by a wildcard are validated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ProcessContents {
+    /// A global declaration is required, and used for validation.
+    Strict,
+    /// A global declaration is used for validation if there is one.
+    Lax,
+    /// No validation.
+    Skip,
+}
+
+/// An `xs:any` or `xs:anyAttribute` wildcard.
+#[derive(Clone, Debug)]
+pub(crate) struct Wildcard {
+    pub(crate) namespaces: NamespaceConstraint,
+    pub(crate) process_contents: ProcessContents,
+}
+
+/// A content model, as an expression that matches sequences of child elements.
+#[derive(Debug)]
+pub(crate) enum Particle {
+    /// Matches the empty sequence.
+    Empty,
+    /// Matches nothing.
+    NotAllowed,
+    /// Matches an element of the declaration, or of one of its substitution group members.
+    Element(ElementId),
+    /// Matches an element allowed by the wildcard.
+    Any(Rc<Wildcard>),
+    Sequence(Rc<Particle>, Rc<Particle>),
+    Choice(Rc<Particle>, Rc<Particle>),
+    /// Matches the elements matched by both particles, in any order (`xs:all`).
+    Interleave(Rc<Particle>, Rc<Particle>),
+    /// Matches between `min` and `max` (unbounded if `None`) repetitions of the particle.
+    Repeat(Rc<Particle>, u64, Option<u64>),
+}
+
+pub(crate) fn empty() -> Rc<Particle> {
+    Rc::new(Particle::Empty)
+}
+
+pub(crate) fn not_allowed() -> Rc<Particle> {
+    Rc::new(Particle::NotAllowed)
+}
+
+pub(crate) fn sequence(a: Rc<Particle>, b: Rc<Particle>) -> Rc<Particle> {
+    match (&*a, &*b) {
+        (&Particle::NotAllowed, _) | (_, &Particle::Empty) => a,
+        (_, &Particle::NotAllowed) | (&Particle::Empty, _) => b,
+        _ => Rc::new(Particle::Sequence(a, b)),
+    }
+}
+
+pub(crate) fn choice(a: Rc<Particle>, b: Rc<Particle>) -> Rc<Particle> {
+    match (&*a, &*b) {
+        (&Particle::NotAllowed, _) => b,
+        (_, &Particle::NotAllowed) => a,
+        _ if Rc::ptr_eq(&a, &b) => a,
+        _ => Rc::new(Particle::Choice(a, b)),
+    }
+}
+
+pub(crate) fn interleave(a: Rc<Particle>, b: Rc<Particle>) -> Rc<Particle> {
+    match (&*a, &*b) {
+        (&Particle::NotAllowed, _) | (_, &Particle::Empty) => a,
+        (_, &Particle::NotAllowed) | (&Particle::Empty, _) => b,
+        _ => Rc::new(Particle::Interleave(a, b)),
+    }
+}
+
+pub(crate) fn repeat(p: Rc<Particle>, min: u64, max: Option<u64>) -> Rc<Particle> {
+    match (&*p, min, max) {
+        (_, 1, Some(1)) => p,
+        (&Particle::NotAllowed, 0, _) | (&Particle::Empty, _, _) | (_, 0, Some(0)) => empty(),
+        (&Particle::NotAllowed, _, _) => p,
+        _ => Rc::new(Particle::Repeat(p, min, max)),
+    }
+}
+
+/// An attribute declaration, together with how it is used by a complex type.
+#[derive(Clone, Debug)]
+pub(crate) struct AttributeUse {
+    pub(crate) name: QName,
+    pub(crate) type_id: TypeId,
+    pub(crate) required: bool,
+    /// The value constraint of the use, or else of the declaration.
+    pub(crate) constraint: Option<ValueConstraint>,
+}
+
+/// The content allowed by a complex type.
+#[derive(Clone, Debug)]
+pub(crate) enum Content {
+    /// Neither character data nor elements.
+    Empty,
+    /// Character data that is a valid value of the simple type.
+    Simple(TypeId),
+    /// Elements matched by the particle, and character data as well if `mixed`.
+    Elements { particle: Rc<Particle>, mixed: bool },
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct ComplexType {
+    pub(crate) attributes: Vec<AttributeUse>,
+    pub(crate) wildcard: Option<Wildcard>,
+    pub(crate) content: Content,
+}
+
+/// The variety of a simple type.
+#[derive(Clone, Debug)]
+pub(crate) enum Variety {
+    /// A built-in type, possibly restricted by facets.
+    Atomic(SimpleType),
+    /// A space-separated list of values of the item type. The `length` facets count items.
+    List { item: TypeId, facets: Facets },
+    /// A value of any of the member types, tried in order.
+    Union {
+        members: Vec<TypeId>,
+        facets: Facets,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum TypeKind {
+    Simple(Variety),
+    Complex(ComplexType),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct TypeDef {
+    /// The name of the type, or `None` for an anonymous type.
+    pub(crate) name: Option<QName>,
+    /// The base type, or `None` for `xs:anyType`.
+    pub(crate) base: Option<TypeId>,
+    /// The `DERIVATION_*` flag for the derivation from the base type.
+    pub(crate) derivation: u32,
+    pub(crate) is_abstract: bool,
+    /// The derivations (`DERIVATION_EXTENSION` and `DERIVATION_RESTRICTION`) by which types
+    /// may not be substituted for this one in instances.
+    pub(crate) block: u32,
+    /// The derivations by which other types may not be derived from this one.
+    pub(crate) final_derivations: u32,
+    pub(crate) kind: TypeKind,
+}
+
+impl TypeDef {
+    pub(crate) fn simple(
+        name: Option<QName>,
+        base: TypeId,
+        derivation: u32,
+        variety: Variety,
+    ) -> Self {
+        TypeDef {
+            name,
+            base: Some(base),
+            derivation,
+            is_abstract: false,
+            block: 0,
+            final_derivations: 0,
+            kind: TypeKind::Simple(variety),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct ElementDecl {
+    pub(crate) name: QName,
+    pub(crate) type_id: TypeId,
+    pub(crate) nillable: bool,
+    pub(crate) constraint: Option<ValueConstraint>,
+    pub(crate) is_abstract: bool,
+    /// The `DERIVATION_*` flags and `BLOCK_SUBSTITUTION`.
+    pub(crate) block: u32,
+    /// The derivations by which the types of substitution group members may not be derived
+    /// from the type of this element.
+    pub(crate) final_derivations: u32,
+    /// The head of the substitution group of this element, if any.
+    pub(crate) head: Option<ElementId>,
+    /// The elements that may appear in place of this one, directly or indirectly.
+    pub(crate) substitutes: Vec<ElementId>,
+    pub(crate) constraints: Vec<ConstraintId>,
+}
+
+/// A name test of a restricted XPath expression in an identity constraint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum NameTest {
+    Any,
+    Namespace(String),
+    Name(QName),
+}
+
+impl NameTest {
+    pub(crate) fn matches(&self, uri: &str, local_name: &str) -> bool {
+        match *self {
+            NameTest::Any => true,
+            NameTest::Namespace(ref namespace) => namespace == uri,
+            NameTest::Name(ref name) => name.uri == uri && name.local_name == local_name,
+        }
+    }
+}
+
+/// One alternative of the `xpath` of a selector or field: a path of child steps, possibly
+/// preceded by `.//` and, in fields, followed by an attribute step.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Path {
+    pub(crate) descendant: bool,
+    pub(crate) steps: Vec<NameTest>,
+    pub(crate) attribute: Option<NameTest>,
+}
+
+impl Path {
+    /// Return whether the path selects an element reached by the given names of elements,
+    /// from the child of the context element down to the element itself.
+    pub(crate) fn selects(&self, path: &[QName]) -> bool {
+        let steps = self.steps.len();
+        if path.len() < steps || (!self.descendant && path.len() != steps) {
+            return false;
+        }
+        path[path.len() - steps..]
+            .iter()
+            .zip(&self.steps)
+            .all(|(name, test)| test.matches(&name.uri, &name.local_name))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ConstraintKind {
+    Unique,
+    Key,
+    Keyref(ConstraintId),
+}
+
+/// An `xs:unique`, `xs:key` or `xs:keyref` identity constraint.
+#[derive(Clone, Debug)]
+pub(crate) struct IdentityConstraint {
+    pub(crate) name: QName,
+    pub(crate) kind: ConstraintKind,
+    pub(crate) selector: Vec<Path>,
+    pub(crate) fields: Vec<Vec<Path>>,
+}
+
+/// A value compared by identity constraints: values of numeric types compare by their
+/// numeric value, all other values by their normalized literal.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct KeyValue {
+    space: &'static str,
+    value: String,
+}
+
+impl fmt::Display for KeyValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.value)
+    }
+}
+
+/// The components of a compiled schema.
+#[derive(Debug)]
+pub(crate) struct Components {
+    pub(crate) types: Vec<TypeDef>,
+    pub(crate) elements: Vec<ElementDecl>,
+    pub(crate) constraints: Vec<IdentityConstraint>,
+    pub(crate) global_types: HashMap<QName, TypeId>,
+    pub(crate) global_elements: HashMap<QName, ElementId>,
+    pub(crate) global_attributes: HashMap<QName, AttributeUse>,
+}
+
+impl Components {
+    /// Construct the components holding `xs:anyType` and the built-in simple types only.
+    pub(crate) fn new() -> Self {
+        let any = Rc::new(Particle::Any(Rc::new(Wildcard {
+            namespaces: NamespaceConstraint::Any,
+            process_contents: ProcessContents::Lax,
+        })));
+        let any_type = TypeDef {
+            name: Some(QName::new(XSD_NS, "anyType")),
+            base: None,
+            derivation: DERIVATION_RESTRICTION,
+            is_abstract: false,
+            block: 0,
+            final_derivations: 0,
+            kind: TypeKind::Complex(ComplexType {
+                attributes: Vec::new(),
+                wildcard: Some(Wildcard {
+                    namespaces: NamespaceConstraint::Any,
+                    process_contents: ProcessContents::Lax,
+                }),
+                content: Content::Elements {
+                    particle: repeat(any, 0, None),
+                    mixed: true,
+                },
+            }),
+        };
+        let mut components = Components {
+            types: vec![any_type],
+            elements: Vec::new(),
+            constraints: Vec::new(),
+            global_types: HashMap::new(),
+            global_elements: HashMap::new(),
+            global_attributes: HashMap::new(),
+        };
+        for &(name, builtin) in BUILTIN_NAMES {
+            let (base, derivation) = match builtin.base() {
+                Some(base) => (components.builtin(base), DERIVATION_RESTRICTION),
+                None if builtin.is_list() => (
+                    components.builtin(BuiltinType::AnySimpleType),
+                    DERIVATION_LIST,
+                ),
+                None if builtin == BuiltinType::AnySimpleType => (ANY_TYPE, DERIVATION_RESTRICTION),
+                None => (
+                    components.builtin(BuiltinType::AnySimpleType),
+                    DERIVATION_RESTRICTION,
+                ),
+            };
+            let variety = Variety::Atomic(SimpleType::new(builtin));
+            let id = components.push_type(TypeDef::simple(
+                Some(QName::new(XSD_NS, name)),
+                base,
+                derivation,
+                variety,
+            ));
+            components.global_types.insert(QName::new(XSD_NS, name), id);
+        }
+        components
+            .global_types
+            .insert(QName::new(XSD_NS, "anyType"), ANY_TYPE);
+        components
+    }
+
+    pub(crate) fn push_type(&mut self, def: TypeDef) -> TypeId {
+        self.types.push(def);
+        TypeId(self.types.len() - 1)
+    }
+
+    /// Return the definition of a built-in simple type.
+    pub(crate) fn builtin(&self, builtin: BuiltinType) -> TypeId {
+        let index = BUILTIN_NAMES
+            .iter()
+            .position(|entry| entry.1 == builtin)
+            .unwrap_or(0);
+        TypeId(1 + index)
+    }
+
+    pub(crate) fn type_def(&self, id: TypeId) -> &TypeDef {
+        &self.types[id.0]
+    }
+
+    pub(crate) fn element(&self, id: ElementId) -> &ElementDecl {
+        &self.elements[id.0]
+    }
+
+    pub(crate) fn constraint(&self, id: ConstraintId) -> &IdentityConstraint {
+        &self.constraints[id.0]
+    }
+
+    /// Return whether the type is a simple type.
+    pub(crate) fn is_simple(&self, id: TypeId) -> bool {
+        matches!(self.type_def(id).kind, TypeKind::Simple(_))
+    }
+
+    /// Return the simple type of the values of a simple type, or of the content of a complex
+    /// type with simple content.
+    pub(crate) fn value_type(&self, id: TypeId) -> Option<TypeId> {
+        match self.type_def(id).kind {
+            TypeKind::Simple(_) => Some(id),
+            TypeKind::Complex(ComplexType {
+                content: Content::Simple(simple),
+                ..
+            }) => Some(simple),
+            _ => None,
+        }
+    }
+
+    /// Describe a type for messages.
+    pub(crate) fn describe(&self, id: TypeId) -> String {
+        match self.type_def(id).name {
+            Some(ref name) if name.uri == XSD_NS => format!("xs:{}", name.local_name),
+            Some(ref name) => name.to_string(),
+            None => "anonymous type".to_owned(),
+        }
+    }
+
+    /// Return the derivation methods by which `derived` is derived from `base`, following the
+    /// base type definitions and the member types of unions, or `None` if it is not derived
+    /// from it. A type is derived from itself with no derivation method.
+    pub(crate) fn derivation(&self, derived: TypeId, base: TypeId) -> Option<u32> {
+        let mut methods = 0;
+        let mut current = derived;
+        loop {
+            if current == base {
+                return Some(methods);
+            }
+            if let TypeKind::Simple(Variety::Union { ref members, .. }) = self.type_def(base).kind {
+                if let Some(found) = members
+                    .iter()
+                    .find_map(|member| self.derivation(current, *member))
+                {
+                    return Some(methods | found | DERIVATION_UNION);
+                }
+            }
+            let def = self.type_def(current);
+            match def.base {
+                Some(next) if next != current => {
+                    methods |= def.derivation;
+                    current = next;
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// Validate a literal against a simple type. Returns the literal after white space
+    /// normalization.
+    pub(crate) fn validate(
+        &self,
+        id: TypeId,
+        literal: &str,
+    ) -> std::result::Result<String, ParseErrorImpl> {
+        match self.type_def(id).kind {
+            TypeKind::Simple(Variety::Atomic(ref simple)) => simple.validate(literal),
+            TypeKind::Simple(Variety::List { item, ref facets }) => {
+                let value = WhiteSpace::Collapse.normalize(literal);
+                let items: Vec<&str> = value.split(' ').filter(|s| !s.is_empty()).collect();
+                let mut normalized = Vec::new();
+                for item_literal in &items {
+                    normalized.push(self.validate(item, item_literal)?);
+                }
+                let value = normalized.join(" ");
+                self.check_facets(id, &value, items.len(), facets)?;
+                Ok(value)
+            }
+            TypeKind::Simple(Variety::Union {
+                ref members,
+                ref facets,
+            }) => {
+                let value = members
+                    .iter()
+                    .find_map(|member| self.validate(*member, literal).ok())
+                    .ok_or_else(|| {
+                        ParseErrorImpl::of_kind(
+                            ErrorKind::Validity,
+                            format!(
+                                "'{}' is not a valid value of any member type of {}",
+                                literal,
+                                self.describe(id)
+                            ),
+                        )
+                    })?;
+                self.check_facets(id, &value, value.chars().count(), facets)?;
+                Ok(value)
+            }
+            TypeKind::Complex(_) => match self.value_type(id) {
+                Some(simple) => self.validate(simple, literal),
+                None => Err(ParseErrorImpl::of_kind(
+                    ErrorKind::Validity,
+                    format!("{} is not a simple type", self.describe(id)),
+                )),
+            },
+        }
+    }
+
+    /// Check the facets of a list or union type, which apply to the whole value.
+    fn check_facets(
+        &self,
+        id: TypeId,
+        value: &str,
+        length: usize,
+        facets: &Facets,
+    ) -> std::result::Result<(), ParseErrorImpl> {
+        let fail = |facet: &str, detail: &dyn fmt::Display| {
+            Err(ParseErrorImpl::of_kind(
+                ErrorKind::Validity,
+                format!(
+                    "'{}' is not facet-valid with respect to {} '{}' for type {}",
+                    value,
+                    facet,
+                    detail,
+                    self.describe(id)
+                ),
+            ))
+        };
+        if let Some(expected) = facets.length {
+            if length != expected {
+                return fail("length", &expected);
+            }
+        }
+        if let Some(min) = facets.min_length {
+            if length < min {
+                return fail("minLength", &min);
+            }
+        }
+        if let Some(max) = facets.max_length {
+            if length > max {
+                return fail("maxLength", &max);
+            }
+        }
+        if let Some(pattern) = facets.patterns.iter().find(|p| !p.is_match(value)) {
+            return fail("pattern", pattern);
+        }
+        if !facets.enumeration.is_empty()
+            && !facets
+                .enumeration
+                .iter()
+                .any(|e| WhiteSpace::Collapse.normalize(e) == value)
+        {
+            return fail("enumeration", &facets.enumeration.join("|"));
+        }
+        Ok(())
+    }
+
+    /// Return the built-in type that a valid, normalized value of a simple type is a value of.
+    fn primitive(&self, id: TypeId, value: &str) -> Option<BuiltinType> {
+        match self.type_def(id).kind {
+            TypeKind::Simple(Variety::Atomic(ref simple)) => Some(simple.base),
+            TypeKind::Simple(Variety::Union { ref members, .. }) => members
+                .iter()
+                .find(|member| self.validate(**member, value).is_ok())
+                .and_then(|member| self.primitive(*member, value)),
+            TypeKind::Simple(Variety::List { .. }) => None,
+            TypeKind::Complex(_) => self
+                .value_type(id)
+                .and_then(|simple| self.primitive(simple, value)),
+        }
+    }
+
+    /// Return the value compared by identity constraints and fixed values for a valid,
+    /// normalized value of a simple type.
+    pub(crate) fn key_value(&self, id: TypeId, value: &str) -> KeyValue {
+        match self.primitive(id, value) {
+            Some(builtin) if builtin.is_decimal() => {
+                let decimal = Decimal::parse(value).expect("validated decimal");
+                KeyValue {
+                    space: "decimal",
+                    value: format!(
+                        "{}{}{}{}",
+                        if decimal.negative { "-" } else { "" },
+                        if decimal.integer.is_empty() {
+                            "0"
+                        } else {
+                            &decimal.integer
+                        },
+                        if decimal.fraction.is_empty() { "" } else { "." },
+                        decimal.fraction
+                    ),
+                }
+            }
+            Some(BuiltinType::Float) | Some(BuiltinType::Double) => KeyValue {
+                space: "double",
+                value: format!("{:?}", parse_float(value).expect("validated float")),
+            },
+            _ => KeyValue {
+                space: "string",
+                value: value.to_owned(),
+            },
+        }
+    }
+
+    /// Return whether the values of a simple type are IDs, or references to IDs.
+    pub(crate) fn id_kind(&self, id: TypeId) -> Option<BuiltinType> {
+        match self.type_def(id).kind {
+            TypeKind::Simple(Variety::Atomic(ref simple)) => match simple.base {
+                BuiltinType::Id | BuiltinType::Idref | BuiltinType::Idrefs => Some(simple.base),
+                _ => None,
+            },
+            TypeKind::Simple(Variety::List { item, .. }) => match self.id_kind(item) {
+                Some(BuiltinType::Idref) => Some(BuiltinType::Idrefs),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Return the names that can start a match of a particle, for messages.
+    pub(crate) fn expected(&self, particle: &Particle, names: &mut Vec<String>) {
+        let mut add = |name: String| {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        };
+        match *particle {
+            Particle::Empty | Particle::NotAllowed => {}
+            Particle::Element(id) => {
+                let element = self.element(id);
+                if !element.is_abstract {
+                    add(element.name.to_string());
+                }
+                for substitute in &element.substitutes {
+                    add(self.element(*substitute).name.to_string());
+                }
+            }
+            Particle::Any(ref wildcard) => add(match wildcard.namespaces {
+                NamespaceConstraint::Any => "any element".to_owned(),
+                NamespaceConstraint::Not(ref uri) if uri.is_empty() => {
+                    "any element in a Namespace".to_owned()
+                }
+                NamespaceConstraint::Not(ref uri) => {
+                    format!("any element not in the Namespace \"{}\"", uri)
+                }
+                NamespaceConstraint::Set(ref set) => format!(
+                    "any element in {}",
+                    set.iter()
+                        .map(|uri| if uri.is_empty() {
+                            "no Namespace".to_owned()
+                        } else {
+                            format!("\"{}\"", uri)
+                        })
+                        .collect::<Vec<_>>()
+                        .join(" or ")
+                ),
+            }),
+            Particle::Sequence(ref a, ref b) => {
+                self.expected(a, names);
+                if self.nullable(a) {
+                    self.expected(b, names);
+                }
+            }
+            Particle::Choice(ref a, ref b) | Particle::Interleave(ref a, ref b) => {
+                self.expected(a, names);
+                self.expected(b, names);
+            }
+            Particle::Repeat(ref p, _, max) => {
+                if max != Some(0) {
+                    self.expected(p, names);
+                }
+            }
+        }
+    }
+
+    /// Return whether a particle matches the empty sequence.
+    pub(crate) fn nullable(&self, particle: &Particle) -> bool {
+        match *particle {
+            Particle::Empty => true,
+            Particle::NotAllowed | Particle::Element(_) | Particle::Any(_) => false,
+            Particle::Sequence(ref a, ref b) | Particle::Interleave(ref a, ref b) => {
+                self.nullable(a) && self.nullable(b)
+            }
+            Particle::Choice(ref a, ref b) => self.nullable(a) || self.nullable(b),
+            Particle::Repeat(ref p, min, _) => min == 0 || self.nullable(p),
+        }
+    }
+}
+
+/// A schema compiled by `loader::Loader`, from which validators are created.
+///
+/// The schema is shared by the validators created from it, and is cheap to clone.
+#[derive(Clone, Debug)]
+pub struct SchemaSet {
+    components: Rc<Components>,
+}
+
+impl SchemaSet {
+    pub(crate) fn new(components: Components) -> Self {
+        SchemaSet {
+            components: Rc::new(components),
+        }
+    }
+
+    pub(crate) fn components(&self) -> &Rc<Components> {
+        &self.components
+    }
+
+    /// Return whether the schema declares a global element with the given name.
+    pub fn has_element(&self, uri: &str, local_name: &str) -> bool {
+        self.components
+            .global_elements
+            .contains_key(&QName::new(uri, local_name))
+    }
+
+    /// Return whether the schema defines a global type with the given name, including the
+    /// built-in types.
+    pub fn has_type(&self, uri: &str, local_name: &str) -> bool {
+        self.components
+            .global_types
+            .contains_key(&QName::new(uri, local_name))
+    }
+}
+
+impl Schema for SchemaSet {
+    type Handler<L: Locator, CH, EH> = Validator<L, CH, EH>;
+    fn new_validator_handler<L: Locator, CH, EH>(&self) -> Validator<L, CH, EH> {
+        Validator::new(self)
+    }
+}
+
+/// The type of an element or attribute, as assigned by a `validator::Validator`.
+#[derive(Clone)]
+pub struct TypeInfoImpl {
+    components: Rc<Components>,
+    id: TypeId,
+}
+
+impl TypeInfoImpl {
+    pub(crate) fn new(components: Rc<Components>, id: TypeId) -> Self {
+        TypeInfoImpl { components, id }
+    }
+
+    /// Return whether the type is a simple type.
+    pub fn is_simple(&self) -> bool {
+        self.components.is_simple(self.id)
+    }
+}
+
+impl fmt::Debug for TypeInfoImpl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TypeInfoImpl({})", self.components.describe(self.id))
+    }
+}
+
+impl PartialEq for TypeInfoImpl {
+    fn eq(&self, other: &TypeInfoImpl) -> bool {
+        Rc::ptr_eq(&self.components, &other.components) && self.id == other.id
+    }
+}
+
+impl TypeInfo for TypeInfoImpl {
+    fn get_type_name(&self) -> Option<String> {
+        let def = self.components.type_def(self.id);
+        def.name.as_ref().map(|name| name.local_name.clone())
+    }
+    fn get_type_namespace(&self) -> Option<String> {
+        let def = self.components.type_def(self.id);
+        def.name
+            .as_ref()
+            .filter(|name| !name.uri.is_empty())
+            .map(|name| name.uri.clone())
+    }
+    /// A type is not derived from itself. With a non-zero `derivation_method`, every step of
+    /// the derivation must use one of the given methods; `DERIVATION_UNION` allows the last
+    /// step to be from a member type to a union type.
+    fn is_derived_from(
+        &self,
+        type_namespace: &str,
+        type_name: &str,
+        derivation_method: u32,
+    ) -> bool {
+        let base = match self
+            .components
+            .global_types
+            .get(&QName::new(type_namespace, type_name))
+        {
+            Some(base) if *base != self.id => *base,
+            _ => return false,
+        };
+        match self.components.derivation(self.id, base) {
+            Some(methods) => derivation_method == 0 || methods & !derivation_method == 0,
+            None => false,
+        }
+    }
+}
+
+/// The derivation flags named in a `block`, `final`, `blockDefault` or `finalDefault`
+/// attribute. `#all` stands for all of `allowed`.
+pub(crate) fn derivation_set(value: &str, allowed: u32) -> Option<u32> {
+    let mut set = 0;
+    for token in value.split(names::is_whitespace).filter(|s| !s.is_empty()) {
+        set |= match token {
+            "#all" => allowed,
+            "extension" => DERIVATION_EXTENSION,
+            "restriction" => DERIVATION_RESTRICTION,
+            "list" => DERIVATION_LIST,
+            "union" => DERIVATION_UNION,
+            "substitution" => BLOCK_SUBSTITUTION,
+            _ => return None,
+        };
+    }
+    if set & !allowed == 0 {
+        Some(set)
+    } else {
+        None
+    }
+}
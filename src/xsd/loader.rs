@@ -0,0 +1,2414 @@
+//! Loading XML Schema documents into a `components::SchemaSet`.
+//!
+//! `Loader` locates each schema document through the registered `EntityResolver`, falling back
+//! to opening `file:` URIs itself, and parses it with a `xinclude::ResourceParser` into a
+//! `tree::Document`. The documents included, imported and redefined by a schema document are
+//! loaded in turn; an included document without a target Namespace takes the one of the
+//! including document. The XML Namespace is built in: importing it loads nothing, and
+//! `xml:lang`, `xml:space`, `xml:base`, `xml:id` and the `xml:specialAttrs` attribute group
+//! can always be referenced.
+//!
+//! Once every document is loaded, the global components and everything they contain are
+//! compiled. Errors in the schema, such as references to undefined components, circular
+//! definitions or invalid facets, are reported through the registered `ErrorHandler`, and
+//! `new_schema` then fails with the first of them. The schema component constraints that only
+//! restrict how a schema may be written, such as Unique Particle Attribution and the rules for
+//! restricting content models, are not checked.
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::Read;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use common::EntityResolver;
+use common::Locator;
+use helpers::ErrorKind;
+use helpers::ParseErrorImpl;
+use helpers::XMLNS;
+use names;
+use sax2::ErrorHandler;
+use sax2::Result;
+use tree::Document;
+use tree::NodeId;
+use tree::NodeKind;
+use tree::TreeBuilder;
+use uri;
+use xinclude::ResourceParser;
+use xsd::components::choice;
+use xsd::components::derivation_set;
+use xsd::components::empty;
+use xsd::components::interleave;
+use xsd::components::not_allowed;
+use xsd::components::repeat;
+use xsd::components::sequence;
+use xsd::components::AttributeUse;
+use xsd::components::ComplexType;
+use xsd::components::Components;
+use xsd::components::ConstraintId;
+use xsd::components::ConstraintKind;
+use xsd::components::Content;
+use xsd::components::ElementDecl;
+use xsd::components::ElementId;
+use xsd::components::IdentityConstraint;
+use xsd::components::NameTest;
+use xsd::components::NamespaceConstraint;
+use xsd::components::Particle;
+use xsd::components::Path;
+use xsd::components::ProcessContents;
+use xsd::components::QName;
+use xsd::components::SchemaSet;
+use xsd::components::TypeDef;
+use xsd::components::TypeId;
+use xsd::components::TypeKind;
+use xsd::components::ValueConstraint;
+use xsd::components::Variety;
+use xsd::components::Wildcard;
+use xsd::components::ANY_TYPE;
+use xsd::components::BLOCK_SUBSTITUTION;
+use xsd::regex::Regex;
+use xsd::BuiltinType;
+use xsd::Facets;
+use xsd::SchemaLoader;
+use xsd::SimpleType;
+use xsd::WhiteSpace;
+use xsd::DERIVATION_EXTENSION;
+use xsd::DERIVATION_LIST;
+use xsd::DERIVATION_RESTRICTION;
+use xsd::DERIVATION_UNION;
+use xsd::XSD_NS;
+
+/// The derivations that `block` and `final` may name for complex types.
+const COMPLEX_DERIVATIONS: u32 = DERIVATION_EXTENSION | DERIVATION_RESTRICTION;
+
+/// The derivations that `final` may name for simple types.
+const SIMPLE_DERIVATIONS: u32 = DERIVATION_RESTRICTION | DERIVATION_LIST | DERIVATION_UNION;
+
+/// Loads XML Schema documents and compiles them into a `SchemaSet`.
+///
+/// Schema documents are parsed with `parser`, which is given the system identifier of each
+/// document along with its content. For `xs:import`, the imported Namespace is passed to the
+/// entity resolver as the public identifier; an import without a `schemaLocation` is offered to
+/// the resolver with an empty system identifier, and skipped if the resolver declines it.
+pub struct Loader<L: Locator, P, ER, EH> {
+    parser: P,
+    resolver: RefCell<Option<ER>>,
+    error_handler: RefCell<Option<EH>>,
+    locator: PhantomData<L>,
+}
+
+impl<L, P, ER, EH> Loader<L, P, ER, EH>
+where
+    L: Locator,
+    P: ResourceParser<L>,
+    ER: EntityResolver,
+    EH: ErrorHandler<ParseErrorImpl>,
+{
+    /// Construct a loader parsing schema documents with `parser`.
+    pub fn new(parser: P) -> Self {
+        Loader {
+            parser,
+            resolver: RefCell::new(None),
+            error_handler: RefCell::new(None),
+            locator: PhantomData,
+        }
+    }
+
+    /// Open a schema document, or return `None` if there is nothing to open: an import without
+    /// a location that the resolver declined.
+    fn open(
+        &self,
+        public_id: Option<&str>,
+        system_id: &str,
+    ) -> std::result::Result<Option<Box<dyn Read>>, String> {
+        let resolved = match *self.resolver.borrow() {
+            Some(ref resolver) => match resolver.resolve_entity(public_id, system_id) {
+                Ok(resolved) => resolved,
+                // A resolver that refuses an unknown resource declines it.
+                Err(_) if system_id.is_empty() => None,
+                Err(e) => return Err(format!("cannot resolve {}: {}", system_id, e)),
+            },
+            None => None,
+        };
+        if let Some(input) = resolved {
+            return Ok(Some(input));
+        }
+        if system_id.is_empty() {
+            return Ok(None);
+        }
+        match uri::to_file_path(system_id).map(std::fs::File::open) {
+            Some(Ok(file)) => Ok(Some(Box::new(file))),
+            Some(Err(e)) => Err(format!("cannot open {}: {}", system_id, e)),
+            None => Err(format!("cannot retrieve {}", system_id)),
+        }
+    }
+
+    /// Load a schema document and the documents it refers to. `from` is the element referring
+    /// to the document, if any.
+    fn load(
+        &self,
+        state: &mut LoadState,
+        system_id: &str,
+        reference: Reference,
+        from: Option<Source>,
+    ) -> Result<()> {
+        let public_id = match reference {
+            Reference::Import(ref namespace) => Some(namespace.as_str()),
+            _ => None,
+        };
+        let mut input = match self.open(public_id, system_id) {
+            Ok(Some(input)) => input,
+            Ok(None) => return Ok(()),
+            Err(message) => {
+                state.error(from, ErrorKind::Resolution, message);
+                return Ok(());
+            }
+        };
+        let builder = TreeBuilder::<L>::new();
+        self.parser
+            .parse_resource(&mut *input, system_id, &builder)?;
+        let tree = builder.take_document();
+        let root = match tree.document_element() {
+            Some(root)
+                if tree.namespace_uri(root) == XSD_NS && tree.local_name(root) == "schema" =>
+            {
+                root
+            }
+            _ => {
+                let message = format!("{} is not a schema document", system_id);
+                state.error(from, ErrorKind::Parse, message);
+                return Ok(());
+            }
+        };
+        let declared = tree
+            .attribute_value(root, "", "targetNamespace")
+            .unwrap_or_default()
+            .to_owned();
+        let (target_namespace, chameleon) = match reference {
+            Reference::Include(ref including) if declared.is_empty() => {
+                (including.clone(), !including.is_empty())
+            }
+            Reference::Include(ref including) if *including != declared => {
+                let message = format!(
+                    "the target Namespace \"{}\" of {} is not the one of the including schema",
+                    declared, system_id
+                );
+                state.error(from, ErrorKind::Parse, message);
+                return Ok(());
+            }
+            Reference::Import(ref namespace) if *namespace != declared => {
+                let message = format!(
+                    "the target Namespace \"{}\" of {} is not the imported Namespace \"{}\"",
+                    declared, system_id, namespace
+                );
+                state.error(from, ErrorKind::Parse, message);
+                return Ok(());
+            }
+            _ => (declared, false),
+        };
+        let key = (system_id.to_owned(), target_namespace.clone());
+        if state.loaded.contains_key(&key) {
+            if let Some(from) = from {
+                state.referenced.insert(from, state.loaded[&key]);
+            }
+            return Ok(());
+        }
+        let index = state.documents.len();
+        state.loaded.insert(key, index);
+        if let Some(from) = from {
+            state.referenced.insert(from, index);
+        }
+        let flag = |name: &str| tree.attribute_value(root, "", name) == Some("qualified");
+        let document = SchemaDocument {
+            uri: system_id.to_owned(),
+            root,
+            target_namespace: target_namespace.clone(),
+            chameleon,
+            qualified_elements: flag("elementFormDefault"),
+            qualified_attributes: flag("attributeFormDefault"),
+            block_default: 0,
+            final_default: 0,
+            tree,
+        };
+        state.documents.push(document);
+        for (name, allowed) in &[
+            ("blockDefault", COMPLEX_DERIVATIONS | BLOCK_SUBSTITUTION),
+            ("finalDefault", COMPLEX_DERIVATIONS | SIMPLE_DERIVATIONS),
+        ] {
+            let document = &state.documents[index];
+            let value = match document.tree.attribute_value(root, "", name) {
+                Some(value) => value.to_owned(),
+                None => continue,
+            };
+            match derivation_set(&value, *allowed) {
+                Some(set) if *name == "blockDefault" => state.documents[index].block_default = set,
+                Some(set) => state.documents[index].final_default = set,
+                None => {
+                    let message = format!("invalid value \"{}\" for {}", value, name);
+                    state.error(Some((index, root)), ErrorKind::Parse, message);
+                }
+            }
+        }
+
+        let references: Vec<NodeId> = state.documents[index]
+            .tree
+            .children(root)
+            .iter()
+            .cloned()
+            .filter(|child| state.documents[index].tree.namespace_uri(*child) == XSD_NS)
+            .collect();
+        for child in references {
+            let tree = &state.documents[index].tree;
+            let location = tree
+                .attribute_value(child, "", "schemaLocation")
+                .map(|location| uri::resolve(system_id, location.trim()));
+            let reference = match tree.local_name(child) {
+                "include" | "redefine" => Reference::Include(target_namespace.clone()),
+                "import" => {
+                    let namespace = tree
+                        .attribute_value(child, "", "namespace")
+                        .unwrap_or_default()
+                        .to_owned();
+                    if namespace == target_namespace {
+                        let message = "a schema cannot import its own target Namespace";
+                        state.error(Some((index, child)), ErrorKind::Parse, message.to_owned());
+                        continue;
+                    }
+                    if namespace == XMLNS {
+                        continue;
+                    }
+                    Reference::Import(namespace)
+                }
+                _ => continue,
+            };
+            match location {
+                Some(location) => self.load(state, &location, reference, Some((index, child)))?,
+                None if matches!(reference, Reference::Import(_)) => {
+                    self.load(state, "", reference, Some((index, child)))?
+                }
+                None => {
+                    let message = "missing schemaLocation attribute".to_owned();
+                    state.error(Some((index, child)), ErrorKind::Parse, message);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<L, P, ER, EH> SchemaLoader<ER, EH, ParseErrorImpl> for Loader<L, P, ER, EH>
+where
+    L: Locator,
+    P: ResourceParser<L>,
+    ER: EntityResolver,
+    EH: ErrorHandler<ParseErrorImpl>,
+{
+    type Schema = SchemaSet;
+    fn set_entity_resolver(&self, resolver: ER) {
+        *self.resolver.borrow_mut() = Some(resolver);
+    }
+    fn set_error_handler(&self, handler: EH) {
+        *self.error_handler.borrow_mut() = Some(handler);
+    }
+    fn new_schema(&self, system_ids: &[&str]) -> Result<SchemaSet> {
+        let mut state = LoadState::default();
+        for system_id in system_ids {
+            self.load(&mut state, system_id, Reference::Initial, None)?;
+        }
+        let errors = std::mem::take(&mut state.errors);
+        let (components, compile_errors) = Compiler::new(&state).compile();
+        let errors: Vec<ParseErrorImpl> = errors.into_iter().chain(compile_errors).collect();
+        if let Some(ref handler) = *self.error_handler.borrow() {
+            for error in &errors {
+                handler.error(error)?;
+            }
+        }
+        match errors.into_iter().next() {
+            Some(error) => Err(error.into()),
+            None => Ok(SchemaSet::new(components)),
+        }
+    }
+}
+
+/// A schema document, and an element in it.
+type Source = (usize, NodeId);
+
+/// How a schema document is referred to.
+enum Reference {
+    /// One of the documents given to `new_schema`.
+    Initial,
+    /// By `xs:include` or `xs:redefine`, from a schema document with the given target
+    /// Namespace.
+    Include(String),
+    /// By `xs:import` of the given Namespace.
+    Import(String),
+}
+
+struct SchemaDocument {
+    uri: String,
+    tree: Document,
+    root: NodeId,
+    target_namespace: String,
+    /// Whether the document was included without a target Namespace of its own, so that its
+    /// references to components in no Namespace are to the target Namespace instead.
+    chameleon: bool,
+    qualified_elements: bool,
+    qualified_attributes: bool,
+    block_default: u32,
+    final_default: u32,
+}
+
+#[derive(Default)]
+struct LoadState {
+    documents: Vec<SchemaDocument>,
+    /// The documents loaded, by system identifier and target Namespace.
+    loaded: HashMap<(String, String), usize>,
+    /// The documents loaded for each `xs:include`, `xs:import` and `xs:redefine` element.
+    referenced: HashMap<Source, usize>,
+    errors: Vec<ParseErrorImpl>,
+}
+
+impl LoadState {
+    fn error(&mut self, at: Option<Source>, kind: ErrorKind, message: String) {
+        let error = match at {
+            Some(at) => self.documents[at.0].error(at.1, kind, message),
+            None => ParseErrorImpl::of_kind(kind, message),
+        };
+        self.errors.push(error);
+    }
+}
+
+impl SchemaDocument {
+    fn error(&self, node: NodeId, kind: ErrorKind, message: String) -> ParseErrorImpl {
+        let mut location = self.tree.location(node).clone();
+        if location.system_id.is_none() {
+            location.system_id = Some(self.uri.clone());
+        }
+        ParseErrorImpl::of_kind(kind, message).located(Some(&location))
+    }
+}
+
+/// The symbol spaces of the global components.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Symbol {
+    Type,
+    Element,
+    Attribute,
+    Group,
+    AttributeGroup,
+}
+
+impl Symbol {
+    fn from_name(local_name: &str) -> Option<Symbol> {
+        Some(match local_name {
+            "simpleType" | "complexType" => Symbol::Type,
+            "element" => Symbol::Element,
+            "attribute" => Symbol::Attribute,
+            "group" => Symbol::Group,
+            "attributeGroup" => Symbol::AttributeGroup,
+            _ => return None,
+        })
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Symbol::Type => "type",
+            Symbol::Element => "element",
+            Symbol::Attribute => "attribute",
+            Symbol::Group => "group",
+            Symbol::AttributeGroup => "attribute group",
+        }
+    }
+}
+
+/// The attribute uses, prohibitions and wildcard of a complex type or attribute group.
+#[derive(Clone, Debug, Default)]
+struct AttributeSet {
+    uses: Vec<AttributeUse>,
+    prohibited: Vec<QName>,
+    wildcard: Option<Wildcard>,
+}
+
+/// How far the compilation of a type definition has got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Status {
+    Queued,
+    InProgress,
+}
+
+struct Compiler<'s> {
+    documents: &'s [SchemaDocument],
+    referenced: &'s HashMap<Source, usize>,
+    components: Components,
+    errors: Vec<ParseErrorImpl>,
+    globals: HashMap<(Symbol, QName), Source>,
+    /// The original definitions of the components redefined by `xs:redefine`, by the source
+    /// of their redefinition.
+    originals: HashMap<Source, Source>,
+    types: HashMap<Source, TypeId>,
+    /// The definitions of the types that have not been compiled yet, or are being compiled.
+    pending: HashMap<TypeId, (Source, Option<QName>, Status)>,
+    elements: HashMap<Source, ElementId>,
+    element_sources: Vec<Source>,
+    /// The model groups and attribute groups compiled, or `None` while being compiled.
+    groups: HashMap<Source, Option<Rc<Particle>>>,
+    attribute_groups: HashMap<Source, Option<AttributeSet>>,
+    builtin_attribute_groups: HashMap<QName, AttributeSet>,
+    constraint_names: HashMap<QName, ConstraintId>,
+    /// The keyrefs, with the name of the key they refer to.
+    keyrefs: Vec<(ConstraintId, QName, Source)>,
+}
+
+impl<'s> Compiler<'s> {
+    fn new(state: &'s LoadState) -> Self {
+        let mut compiler = Compiler {
+            documents: &state.documents,
+            referenced: &state.referenced,
+            components: Components::new(),
+            errors: Vec::new(),
+            globals: HashMap::new(),
+            originals: HashMap::new(),
+            types: HashMap::new(),
+            pending: HashMap::new(),
+            elements: HashMap::new(),
+            element_sources: Vec::new(),
+            groups: HashMap::new(),
+            attribute_groups: HashMap::new(),
+            builtin_attribute_groups: HashMap::new(),
+            constraint_names: HashMap::new(),
+            keyrefs: Vec::new(),
+        };
+        compiler.declare_xml_attributes();
+        compiler
+    }
+
+    /// Declare the attributes of the XML Namespace, as `xml.xsd` does.
+    fn declare_xml_attributes(&mut self) {
+        let components = &mut self.components;
+        let string = components.builtin(BuiltinType::String);
+        let empty_string = components.push_type(TypeDef::simple(
+            None,
+            string,
+            DERIVATION_RESTRICTION,
+            Variety::Atomic(SimpleType::with_facets(
+                BuiltinType::String,
+                Facets {
+                    enumeration: vec![String::new()],
+                    ..Facets::default()
+                },
+            )),
+        ));
+        let language = components.builtin(BuiltinType::Language);
+        let any_simple_type = components.builtin(BuiltinType::AnySimpleType);
+        let lang = components.push_type(TypeDef::simple(
+            None,
+            any_simple_type,
+            DERIVATION_UNION,
+            Variety::Union {
+                members: vec![language, empty_string],
+                facets: Facets::default(),
+            },
+        ));
+        let space = components.push_type(TypeDef::simple(
+            None,
+            components.builtin(BuiltinType::NCName),
+            DERIVATION_RESTRICTION,
+            Variety::Atomic(SimpleType::with_facets(
+                BuiltinType::NCName,
+                Facets {
+                    enumeration: vec!["default".to_owned(), "preserve".to_owned()],
+                    ..Facets::default()
+                },
+            )),
+        ));
+        let mut group = AttributeSet::default();
+        for &(name, type_id) in &[
+            ("lang", lang),
+            ("space", space),
+            ("base", components.builtin(BuiltinType::AnyUri)),
+            ("id", components.builtin(BuiltinType::Id)),
+        ] {
+            let attribute = AttributeUse {
+                name: QName::new(XMLNS, name),
+                type_id,
+                required: false,
+                constraint: None,
+            };
+            components
+                .global_attributes
+                .insert(attribute.name.clone(), attribute.clone());
+            group.uses.push(attribute);
+        }
+        self.builtin_attribute_groups
+            .insert(QName::new(XMLNS, "specialAttrs"), group);
+    }
+
+    fn compile(mut self) -> (Components, Vec<ParseErrorImpl>) {
+        self.index();
+        let mut globals: Vec<((Symbol, QName), Source)> = self
+            .globals
+            .iter()
+            .map(|(key, source)| (key.clone(), *source))
+            .collect();
+        globals.sort_by_key(|entry| entry.1);
+        for ((symbol, name), source) in globals {
+            match symbol {
+                Symbol::Type => {
+                    self.type_by_name(source, &name);
+                }
+                Symbol::Element => {
+                    self.element_by_name(source, &name);
+                }
+                Symbol::Attribute => {
+                    self.attribute_by_name(source, &name);
+                }
+                Symbol::Group => {
+                    self.group_by_name(source, &name);
+                }
+                Symbol::AttributeGroup => {
+                    self.attribute_group_by_name(source, &name);
+                }
+            }
+        }
+        // Compiling a type may define more of them, which are compiled in turn.
+        let mut next = 0;
+        while next < self.components.types.len() {
+            self.complete(TypeId(next));
+            next += 1;
+        }
+        self.substitution_groups();
+        self.resolve_keyrefs();
+        (self.components, self.errors)
+    }
+
+    // Access to the schema documents.
+
+    fn tree(&self, at: Source) -> &'s Document {
+        &self.documents[at.0].tree
+    }
+
+    fn document(&self, at: Source) -> &'s SchemaDocument {
+        &self.documents[at.0]
+    }
+
+    fn local_name(&self, at: Source) -> &'s str {
+        self.tree(at).local_name(at.1)
+    }
+
+    fn attribute(&self, at: Source, name: &str) -> Option<&'s str> {
+        self.tree(at).attribute_value(at.1, "", name)
+    }
+
+    /// Return the child elements in the XML Schema Namespace, other than annotations.
+    fn children(&self, at: Source) -> Vec<Source> {
+        let tree = self.tree(at);
+        tree.children(at.1)
+            .iter()
+            .filter(|child| {
+                tree.is_element(**child)
+                    && tree.namespace_uri(**child) == XSD_NS
+                    && tree.local_name(**child) != "annotation"
+            })
+            .map(|child| (at.0, *child))
+            .collect()
+    }
+
+    fn child(&self, at: Source, names: &[&str]) -> Option<Source> {
+        self.children(at)
+            .into_iter()
+            .find(|child| names.contains(&self.local_name(*child)))
+    }
+
+    fn error<S: Into<String>>(&mut self, at: Source, message: S) {
+        let error = self.documents[at.0].error(at.1, ErrorKind::Parse, message.into());
+        self.errors.push(error);
+    }
+
+    fn flag(&mut self, at: Source, name: &str) -> bool {
+        match self.attribute(at, name).map(str::trim) {
+            None | Some("false") | Some("0") => false,
+            Some("true") | Some("1") => true,
+            Some(value) => {
+                self.error(at, format!("invalid value \"{}\" for {}", value, name));
+                false
+            }
+        }
+    }
+
+    /// Return the derivations named by an attribute, or else by the default of the document.
+    fn derivations(&mut self, at: Source, name: &str, default: u32, allowed: u32) -> u32 {
+        match self.attribute(at, name) {
+            Some(value) => match derivation_set(value, allowed) {
+                Some(set) => set,
+                None => {
+                    self.error(at, format!("invalid value \"{}\" for {}", value, name));
+                    0
+                }
+            },
+            None => default & allowed,
+        }
+    }
+
+    fn lookup_prefix(&self, at: Source, prefix: &str) -> Option<String> {
+        if prefix == "xml" {
+            return Some(XMLNS.to_owned());
+        }
+        let tree = self.tree(at);
+        tree.namespace_nodes(at.1)
+            .iter()
+            .find_map(|node| match *tree.kind(*node) {
+                NodeKind::Namespace {
+                    prefix: ref p,
+                    ref uri,
+                } if p == prefix => Some(uri.clone()),
+                _ => None,
+            })
+    }
+
+    /// Resolve a qualified name in an attribute value, with the Namespace declarations in
+    /// scope on the element.
+    fn resolve(&mut self, at: Source, value: &str) -> Option<QName> {
+        let value = value.trim();
+        let (prefix, local_name) = match value.find(':') {
+            Some(i) => (&value[..i], &value[i + 1..]),
+            None => ("", value),
+        };
+        if !names::is_ncname(local_name) || !(prefix.is_empty() || names::is_ncname(prefix)) {
+            self.error(at, format!("invalid qualified name \"{}\"", value));
+            return None;
+        }
+        let uri = match self.lookup_prefix(at, prefix) {
+            Some(uri) => uri,
+            None if prefix.is_empty() => String::new(),
+            None => {
+                self.error(at, format!("undeclared prefix in \"{}\"", value));
+                return None;
+            }
+        };
+        let document = self.document(at);
+        let uri = if uri.is_empty() && document.chameleon {
+            document.target_namespace.clone()
+        } else {
+            uri
+        };
+        Some(QName {
+            uri,
+            local_name: local_name.to_owned(),
+        })
+    }
+
+    /// Return the name of a component declared by an element with a `name` attribute, in the
+    /// target Namespace if `qualified`.
+    fn component_name(&mut self, at: Source, qualified: bool) -> Option<QName> {
+        let name = match self.attribute(at, "name") {
+            Some(name) => name.trim(),
+            None => {
+                self.error(at, format!("xs:{} has no name", self.local_name(at)));
+                return None;
+            }
+        };
+        if !names::is_ncname(name) {
+            self.error(at, format!("invalid name \"{}\"", name));
+            return None;
+        }
+        let uri = if qualified {
+            self.document(at).target_namespace.as_str()
+        } else {
+            ""
+        };
+        Some(QName::new(uri, name))
+    }
+
+    // The global components.
+
+    /// Collect the global components of all documents, then apply the redefinitions.
+    fn index(&mut self) {
+        let mut redefinitions = Vec::new();
+        let documents = self.documents;
+        for (index, document) in documents.iter().enumerate() {
+            for child in self.children((index, document.root)) {
+                match self.local_name(child) {
+                    "redefine" => redefinitions.push(child),
+                    local_name => {
+                        if let Some(symbol) = Symbol::from_name(local_name) {
+                            self.declare(symbol, child);
+                        }
+                    }
+                }
+            }
+        }
+        for redefine in redefinitions {
+            if !self.referenced.contains_key(&redefine) {
+                continue;
+            }
+            for child in self.children(redefine) {
+                let symbol = match Symbol::from_name(self.local_name(child)) {
+                    Some(symbol) if symbol != Symbol::Element && symbol != Symbol::Attribute => {
+                        symbol
+                    }
+                    _ => {
+                        let message = format!("xs:{} cannot be redefined", self.local_name(child));
+                        self.error(child, message);
+                        continue;
+                    }
+                };
+                let name = match self.component_name(child, true) {
+                    Some(name) => name,
+                    None => continue,
+                };
+                match self.globals.insert((symbol, name.clone()), child) {
+                    Some(original) => {
+                        self.originals.insert(child, original);
+                    }
+                    None => {
+                        let message = format!("no {} \"{}\" to redefine", symbol.describe(), name);
+                        self.error(child, message);
+                    }
+                }
+            }
+        }
+    }
+
+    fn declare(&mut self, symbol: Symbol, at: Source) {
+        let name = match self.component_name(at, true) {
+            Some(name) => name,
+            None => return,
+        };
+        match self.globals.entry((symbol, name)) {
+            Entry::Occupied(entry) => {
+                let message = format!("duplicate {} \"{}\"", symbol.describe(), entry.key().1);
+                self.error(at, message);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(at);
+            }
+        }
+    }
+
+    fn global(&mut self, at: Source, symbol: Symbol, name: &QName) -> Option<Source> {
+        let source = self.globals.get(&(symbol, name.clone())).cloned();
+        if source.is_none() {
+            let message = format!("{} \"{}\" is not defined", symbol.describe(), name);
+            self.error(at, message);
+        }
+        source
+    }
+
+    /// Return the original definition of a component, if `at` is within its redefinition.
+    fn original(&self, at: Source) -> Option<Source> {
+        let tree = self.tree(at);
+        let mut node = at.1;
+        while let Some(parent) = tree.parent(node) {
+            if tree.local_name(parent) == "redefine" {
+                return self.originals.get(&(at.0, node)).cloned();
+            }
+            node = parent;
+        }
+        None
+    }
+
+    fn type_by_name(&mut self, at: Source, name: &QName) -> Option<TypeId> {
+        if let Some(id) = self.components.global_types.get(name) {
+            return Some(*id);
+        }
+        let source = self.global(at, Symbol::Type, name)?;
+        let id = self.type_from(source, Some(name.clone()));
+        self.components.global_types.insert(name.clone(), id);
+        Some(id)
+    }
+
+    fn element_by_name(&mut self, at: Source, name: &QName) -> Option<ElementId> {
+        if let Some(id) = self.components.global_elements.get(name) {
+            return Some(*id);
+        }
+        let source = self.global(at, Symbol::Element, name)?;
+        let id = self.element_from(source, true);
+        self.components.global_elements.insert(name.clone(), id);
+        Some(id)
+    }
+
+    fn attribute_by_name(&mut self, at: Source, name: &QName) -> Option<AttributeUse> {
+        if let Some(attribute) = self.components.global_attributes.get(name) {
+            return Some(attribute.clone());
+        }
+        let source = self.global(at, Symbol::Attribute, name)?;
+        let attribute = self.attribute_declaration(source, true)?;
+        self.components
+            .global_attributes
+            .insert(name.clone(), attribute.clone());
+        Some(attribute)
+    }
+
+    fn group_by_name(&mut self, at: Source, name: &QName) -> Option<Rc<Particle>> {
+        let source = match self.original(at) {
+            Some(original) if self.component_name(original, true).as_ref() == Some(name) => {
+                original
+            }
+            _ => self.global(at, Symbol::Group, name)?,
+        };
+        match self.groups.get(&source) {
+            Some(Some(particle)) => return Some(particle.clone()),
+            Some(None) => {
+                self.error(at, format!("circular definition of group \"{}\"", name));
+                return None;
+            }
+            None => {}
+        }
+        self.groups.insert(source, None);
+        let particle = match self.child(source, &["all", "choice", "sequence"]) {
+            Some(model) => self.particle(model),
+            None => empty(),
+        };
+        self.groups.insert(source, Some(particle.clone()));
+        Some(particle)
+    }
+
+    fn attribute_group_by_name(&mut self, at: Source, name: &QName) -> Option<AttributeSet> {
+        if let Some(group) = self.builtin_attribute_groups.get(name) {
+            return Some(group.clone());
+        }
+        let source = match self.original(at) {
+            Some(original) if self.component_name(original, true).as_ref() == Some(name) => {
+                original
+            }
+            _ => self.global(at, Symbol::AttributeGroup, name)?,
+        };
+        match self.attribute_groups.get(&source) {
+            Some(Some(group)) => return Some(group.clone()),
+            Some(None) => {
+                let message = format!("circular definition of attribute group \"{}\"", name);
+                self.error(at, message);
+                return None;
+            }
+            None => {}
+        }
+        self.attribute_groups.insert(source, None);
+        let children = self.children(source);
+        let group = self.attribute_set(&children);
+        self.attribute_groups.insert(source, Some(group.clone()));
+        Some(group)
+    }
+
+    // Type definitions.
+
+    /// Return the type defined at `source`, which is compiled later.
+    fn type_from(&mut self, source: Source, name: Option<QName>) -> TypeId {
+        if let Some(id) = self.types.get(&source) {
+            return *id;
+        }
+        let mut placeholder = self.components.type_def(ANY_TYPE).clone();
+        placeholder.name = name.clone();
+        let id = self.components.push_type(placeholder);
+        self.types.insert(source, id);
+        self.pending.insert(id, (source, name, Status::Queued));
+        id
+    }
+
+    /// Compile a queued type definition.
+    fn complete(&mut self, id: TypeId) {
+        let (source, name) = match self.pending.get_mut(&id) {
+            Some(entry) if entry.2 == Status::Queued => {
+                entry.2 = Status::InProgress;
+                (entry.0, entry.1.clone())
+            }
+            _ => return,
+        };
+        let def = if self.local_name(source) == "simpleType" {
+            self.simple_type(source, name)
+        } else {
+            self.complex_type(source, name)
+        };
+        self.components.types[id.0] = def;
+        self.pending.remove(&id);
+    }
+
+    /// Compile a type definition that another one is derived from, and return whether it is
+    /// complete: it is not if the derivation is circular.
+    fn base_complete(&mut self, at: Source, id: TypeId) -> bool {
+        self.complete(id);
+        if self.pending.contains_key(&id) {
+            let message = format!(
+                "circular definition of type \"{}\"",
+                self.components.describe(id)
+            );
+            self.error(at, message);
+            return false;
+        }
+        true
+    }
+
+    /// Return the base type named by the `base` attribute of a derivation, compiled.
+    fn base_type(&mut self, at: Source, component: Option<&QName>) -> Option<TypeId> {
+        let reference = self.attribute(at, "base")?;
+        let name = self.resolve(at, reference)?;
+        let id = match self.original(at) {
+            Some(original) if Some(&name) == component => self.type_from(original, Some(name)),
+            _ => self.type_by_name(at, &name)?,
+        };
+        if self.base_complete(at, id) {
+            Some(id)
+        } else {
+            None
+        }
+    }
+
+    /// Check that a type may be derived from its base by the given method.
+    fn check_final(&mut self, at: Source, base: TypeId, derivation: u32) {
+        if self.components.type_def(base).final_derivations & derivation != 0 {
+            let message = format!(
+                "type \"{}\" cannot be derived from by {}",
+                self.components.describe(base),
+                describe_derivation(derivation)
+            );
+            self.error(at, message);
+        }
+    }
+
+    /// Return the type named by a `type` attribute or defined by a child of an element or
+    /// attribute declaration, or `None` if there is neither.
+    fn declared_type(&mut self, at: Source, simple: bool) -> Option<TypeId> {
+        if let Some(reference) = self.attribute(at, "type") {
+            let name = self.resolve(at, reference)?;
+            return self.type_by_name(at, &name);
+        }
+        let kinds: &[&str] = if simple {
+            &["simpleType"]
+        } else {
+            &["simpleType", "complexType"]
+        };
+        let child = self.child(at, kinds)?;
+        Some(self.type_from(child, None))
+    }
+
+    fn simple_type(&mut self, source: Source, name: Option<QName>) -> TypeDef {
+        let any_simple_type = self.components.builtin(BuiltinType::AnySimpleType);
+        let fallback = TypeDef::simple(
+            name.clone(),
+            any_simple_type,
+            DERIVATION_RESTRICTION,
+            Variety::Atomic(SimpleType::new(BuiltinType::AnySimpleType)),
+        );
+        let final_default = self.document(source).final_default;
+        let final_derivations =
+            self.derivations(source, "final", final_default, SIMPLE_DERIVATIONS);
+        let derivation = match self.child(source, &["restriction", "list", "union"]) {
+            Some(derivation) => derivation,
+            None => {
+                self.error(
+                    source,
+                    "xs:simpleType has no xs:restriction, xs:list or xs:union",
+                );
+                return fallback;
+            }
+        };
+        let mut def = match self.local_name(derivation) {
+            "restriction" => {
+                let base = if self.attribute(derivation, "base").is_some() {
+                    self.base_type(derivation, name.as_ref())
+                } else {
+                    match self.child(derivation, &["simpleType"]) {
+                        Some(child) => {
+                            let id = self.type_from(child, None);
+                            Some(id).filter(|id| self.base_complete(child, *id))
+                        }
+                        None => {
+                            self.error(derivation, "xs:restriction has no base type");
+                            None
+                        }
+                    }
+                };
+                let base = match base {
+                    Some(base) => base,
+                    None => return fallback,
+                };
+                if !self.components.is_simple(base) {
+                    let message = format!(
+                        "the base of a simple type must be a simple type, not \"{}\"",
+                        self.components.describe(base)
+                    );
+                    self.error(derivation, message);
+                    return fallback;
+                }
+                self.check_final(derivation, base, DERIVATION_RESTRICTION);
+                let facets = self.facets(derivation);
+                let variety = self.restricted_variety(base, facets);
+                TypeDef::simple(name, base, DERIVATION_RESTRICTION, variety)
+            }
+            "list" => {
+                let item = match self.attribute(derivation, "itemType") {
+                    Some(reference) => self
+                        .resolve(derivation, reference)
+                        .and_then(|item| self.type_by_name(derivation, &item)),
+                    None => match self.child(derivation, &["simpleType"]) {
+                        Some(child) => Some(self.type_from(child, None)),
+                        None => {
+                            self.error(derivation, "xs:list has no item type");
+                            None
+                        }
+                    },
+                };
+                let item = match item {
+                    Some(item) if self.base_complete(derivation, item) => item,
+                    _ => return fallback,
+                };
+                let is_list = matches!(
+                    self.components.type_def(item).kind,
+                    TypeKind::Simple(Variety::List { .. })
+                ) || matches!(
+                    self.components.type_def(item).kind,
+                    TypeKind::Simple(Variety::Atomic(ref simple)) if simple.base.is_list()
+                );
+                if !self.components.is_simple(item) || is_list {
+                    let message = format!(
+                        "the item type of a list must be an atomic or union type, not \"{}\"",
+                        self.components.describe(item)
+                    );
+                    self.error(derivation, message);
+                    return fallback;
+                }
+                self.check_final(derivation, item, DERIVATION_LIST);
+                TypeDef::simple(
+                    name,
+                    any_simple_type,
+                    DERIVATION_LIST,
+                    Variety::List {
+                        item,
+                        facets: Facets::default(),
+                    },
+                )
+            }
+            _ => {
+                let mut members = Vec::new();
+                let references = self
+                    .attribute(derivation, "memberTypes")
+                    .unwrap_or_default()
+                    .split(names::is_whitespace)
+                    .filter(|s| !s.is_empty());
+                for reference in references {
+                    if let Some(member) = self
+                        .resolve(derivation, reference)
+                        .and_then(|member| self.type_by_name(derivation, &member))
+                    {
+                        members.push(member);
+                    }
+                }
+                for child in self.children(derivation) {
+                    if self.local_name(child) == "simpleType" {
+                        members.push(self.type_from(child, None));
+                    }
+                }
+                if members.is_empty() {
+                    self.error(derivation, "xs:union has no member types");
+                    return fallback;
+                }
+                for member in &members {
+                    if !self.base_complete(derivation, *member) {
+                        return fallback;
+                    }
+                    if !self.components.is_simple(*member) {
+                        let message = format!(
+                            "the member types of a union must be simple types, not \"{}\"",
+                            self.components.describe(*member)
+                        );
+                        self.error(derivation, message);
+                        return fallback;
+                    }
+                    self.check_final(derivation, *member, DERIVATION_UNION);
+                }
+                TypeDef::simple(
+                    name,
+                    any_simple_type,
+                    DERIVATION_UNION,
+                    Variety::Union {
+                        members,
+                        facets: Facets::default(),
+                    },
+                )
+            }
+        };
+        def.final_derivations = final_derivations;
+        def
+    }
+
+    /// Return the variety of a restriction of a simple type by facets.
+    fn restricted_variety(&self, base: TypeId, facets: Facets) -> Variety {
+        match self.components.type_def(base).kind {
+            TypeKind::Simple(Variety::Atomic(ref simple)) => Variety::Atomic(
+                SimpleType::with_facets(simple.base, merge_facets(&simple.facets, facets)),
+            ),
+            TypeKind::Simple(Variety::List {
+                item,
+                facets: ref base_facets,
+            }) => Variety::List {
+                item,
+                facets: merge_facets(base_facets, facets),
+            },
+            TypeKind::Simple(Variety::Union {
+                ref members,
+                facets: ref base_facets,
+            }) => Variety::Union {
+                members: members.clone(),
+                facets: merge_facets(base_facets, facets),
+            },
+            TypeKind::Complex(_) => Variety::Atomic(SimpleType::new(BuiltinType::AnySimpleType)),
+        }
+    }
+
+    /// Collect the facets given by the children of an `xs:restriction` element.
+    fn facets(&mut self, at: Source) -> Facets {
+        let mut facets = Facets::default();
+        let mut patterns = Vec::new();
+        for child in self.children(at) {
+            let facet = self.local_name(child);
+            if ["simpleType", "attribute", "attributeGroup", "anyAttribute"].contains(&facet) {
+                continue;
+            }
+            let value = match self.attribute(child, "value") {
+                Some(value) => value,
+                None => {
+                    self.error(child, format!("xs:{} has no value", facet));
+                    continue;
+                }
+            };
+            match facet {
+                "length" | "minLength" | "maxLength" | "totalDigits" | "fractionDigits" => {
+                    let slot = match facet {
+                        "length" => &mut facets.length,
+                        "minLength" => &mut facets.min_length,
+                        "maxLength" => &mut facets.max_length,
+                        "totalDigits" => &mut facets.total_digits,
+                        _ => &mut facets.fraction_digits,
+                    };
+                    match value.trim().parse() {
+                        Ok(number) => *slot = Some(number),
+                        Err(_) => {
+                            let message = format!("invalid value \"{}\" for xs:{}", value, facet);
+                            self.error(child, message);
+                        }
+                    }
+                }
+                "minInclusive" | "maxInclusive" | "minExclusive" | "maxExclusive" => {
+                    let slot = match facet {
+                        "minInclusive" => &mut facets.min_inclusive,
+                        "maxInclusive" => &mut facets.max_inclusive,
+                        "minExclusive" => &mut facets.min_exclusive,
+                        _ => &mut facets.max_exclusive,
+                    };
+                    *slot = Some(value.trim().to_owned());
+                }
+                "enumeration" => facets.enumeration.push(value.to_owned()),
+                "pattern" => patterns.push(value),
+                "whiteSpace" => match value.trim() {
+                    "preserve" => facets.white_space = Some(WhiteSpace::Preserve),
+                    "replace" => facets.white_space = Some(WhiteSpace::Replace),
+                    "collapse" => facets.white_space = Some(WhiteSpace::Collapse),
+                    _ => {
+                        let message = format!("invalid value \"{}\" for xs:whiteSpace", value);
+                        self.error(child, message);
+                    }
+                },
+                _ => self.error(child, format!("unknown facet xs:{}", facet)),
+            }
+        }
+        if !patterns.is_empty() {
+            match Regex::new(&patterns.join("|")) {
+                Ok(pattern) => facets.patterns.push(pattern),
+                Err(e) => self.error(at, format!("invalid pattern: {}", e.message())),
+            }
+        }
+        facets
+    }
+
+    fn complex_type(&mut self, source: Source, name: Option<QName>) -> TypeDef {
+        let document = self.document(source);
+        let block = self.derivations(source, "block", document.block_default, COMPLEX_DERIVATIONS);
+        let final_derivations =
+            self.derivations(source, "final", document.final_default, COMPLEX_DERIVATIONS);
+        let is_abstract = self.flag(source, "abstract");
+        let mixed = self.flag(source, "mixed");
+        let children = self.children(source);
+        let (base, derivation, complex) = match children.first() {
+            Some(&content) if self.local_name(content) == "simpleContent" => {
+                self.simple_content(content, name.as_ref())
+            }
+            Some(&content) if self.local_name(content) == "complexContent" => {
+                let mixed = match self.attribute(content, "mixed") {
+                    Some(_) => self.flag(content, "mixed"),
+                    None => mixed,
+                };
+                self.complex_content(content, name.as_ref(), mixed)
+            }
+            _ => {
+                let particle = self.content_particle(&children);
+                let attributes = self.attribute_set(&children);
+                let complex = ComplexType {
+                    attributes: attributes.uses,
+                    wildcard: attributes.wildcard,
+                    content: element_content(particle, mixed),
+                };
+                (Some(ANY_TYPE), DERIVATION_RESTRICTION, complex)
+            }
+        };
+        TypeDef {
+            name,
+            base: Some(base.unwrap_or(ANY_TYPE)),
+            derivation,
+            is_abstract,
+            block,
+            final_derivations,
+            kind: TypeKind::Complex(complex),
+        }
+    }
+
+    fn content_particle(&mut self, children: &[Source]) -> Option<Rc<Particle>> {
+        let model = children.iter().find(|child| {
+            ["group", "all", "choice", "sequence"].contains(&self.local_name(**child))
+        })?;
+        Some(self.particle(*model))
+    }
+
+    /// Return the restriction or extension child of `xs:simpleContent` or
+    /// `xs:complexContent`, and the type it is derived from.
+    fn derivation(
+        &mut self,
+        content: Source,
+        component: Option<&QName>,
+    ) -> Option<(Source, u32, TypeId)> {
+        let derivation = match self.child(content, &["restriction", "extension"]) {
+            Some(derivation) => derivation,
+            None => {
+                let message = format!("xs:{} has no derivation", self.local_name(content));
+                self.error(content, message);
+                return None;
+            }
+        };
+        let method = if self.local_name(derivation) == "extension" {
+            DERIVATION_EXTENSION
+        } else {
+            DERIVATION_RESTRICTION
+        };
+        if self.attribute(derivation, "base").is_none() {
+            self.error(derivation, "missing base attribute");
+            return None;
+        }
+        let base = self.base_type(derivation, component)?;
+        self.check_final(derivation, base, method);
+        Some((derivation, method, base))
+    }
+
+    fn simple_content(
+        &mut self,
+        content: Source,
+        component: Option<&QName>,
+    ) -> (Option<TypeId>, u32, ComplexType) {
+        let mut complex = ComplexType {
+            attributes: Vec::new(),
+            wildcard: None,
+            content: Content::Simple(self.components.builtin(BuiltinType::AnySimpleType)),
+        };
+        let (derivation, method, base) = match self.derivation(content, component) {
+            Some(derivation) => derivation,
+            None => return (None, DERIVATION_RESTRICTION, complex),
+        };
+        let children = self.children(derivation);
+        let attributes = self.attribute_set(&children);
+        let base_def = self.components.type_def(base).clone();
+        match (method, base_def.kind) {
+            (DERIVATION_EXTENSION, TypeKind::Simple(_)) => {
+                complex.content = Content::Simple(base);
+                complex.attributes = attributes.uses;
+                complex.wildcard = attributes.wildcard;
+            }
+            (
+                DERIVATION_EXTENSION,
+                TypeKind::Complex(ComplexType {
+                    content: Content::Simple(simple),
+                    attributes: ref base_attributes,
+                    wildcard: ref base_wildcard,
+                }),
+            ) => {
+                complex.content = Content::Simple(simple);
+                self.extend_attributes(
+                    derivation,
+                    &mut complex,
+                    base_attributes,
+                    base_wildcard,
+                    attributes,
+                );
+            }
+            (
+                DERIVATION_RESTRICTION,
+                TypeKind::Complex(ComplexType {
+                    content: Content::Simple(simple),
+                    attributes: ref base_attributes,
+                    ..
+                }),
+            ) => {
+                let simple = match self.child(derivation, &["simpleType"]) {
+                    Some(child) => {
+                        let id = self.type_from(child, None);
+                        if self.base_complete(child, id) {
+                            id
+                        } else {
+                            simple
+                        }
+                    }
+                    None => simple,
+                };
+                let facets = self.facets(derivation);
+                let variety = self.restricted_variety(simple, facets);
+                let restricted = self.components.push_type(TypeDef::simple(
+                    None,
+                    simple,
+                    DERIVATION_RESTRICTION,
+                    variety,
+                ));
+                complex.content = Content::Simple(restricted);
+                complex.attributes =
+                    restrict_attributes(base_attributes, attributes.uses, &attributes.prohibited);
+                complex.wildcard = attributes.wildcard;
+            }
+            _ => {
+                let message = format!(
+                    "the base of simple content must be a simple type or a complex type with \
+                     simple content, not \"{}\"",
+                    self.components.describe(base)
+                );
+                self.error(derivation, message);
+            }
+        }
+        (Some(base), method, complex)
+    }
+
+    fn complex_content(
+        &mut self,
+        content: Source,
+        component: Option<&QName>,
+        mixed: bool,
+    ) -> (Option<TypeId>, u32, ComplexType) {
+        let mut complex = ComplexType {
+            attributes: Vec::new(),
+            wildcard: None,
+            content: Content::Empty,
+        };
+        let (derivation, method, base) = match self.derivation(content, component) {
+            Some(derivation) => derivation,
+            None => return (None, DERIVATION_RESTRICTION, complex),
+        };
+        let children = self.children(derivation);
+        let particle = self.content_particle(&children);
+        let attributes = self.attribute_set(&children);
+        let base_complex = match self.components.type_def(base).kind {
+            TypeKind::Complex(ref base_complex) => base_complex.clone(),
+            TypeKind::Simple(_) => {
+                let message = format!(
+                    "the base of complex content must be a complex type, not \"{}\"",
+                    self.components.describe(base)
+                );
+                self.error(derivation, message);
+                return (None, method, complex);
+            }
+        };
+        if method == DERIVATION_RESTRICTION {
+            complex.content = element_content(particle, mixed);
+            complex.attributes = restrict_attributes(
+                &base_complex.attributes,
+                attributes.uses,
+                &attributes.prohibited,
+            );
+            complex.wildcard = attributes.wildcard;
+            return (Some(base), method, complex);
+        }
+        complex.content = match (base_complex.content.clone(), particle) {
+            (Content::Empty, particle) => element_content(particle, mixed),
+            (
+                Content::Elements {
+                    particle: base_particle,
+                    mixed: base_mixed,
+                },
+                Some(particle),
+            ) if !matches!(*particle, Particle::Empty) => Content::Elements {
+                particle: sequence(base_particle, particle),
+                mixed: mixed || base_mixed,
+            },
+            (
+                Content::Elements {
+                    particle,
+                    mixed: base_mixed,
+                },
+                _,
+            ) => Content::Elements {
+                particle,
+                mixed: mixed || base_mixed,
+            },
+            (Content::Simple(_), _) => {
+                self.error(derivation, "complex content cannot extend simple content");
+                Content::Empty
+            }
+        };
+        self.extend_attributes(
+            derivation,
+            &mut complex,
+            &base_complex.attributes,
+            &base_complex.wildcard,
+            attributes,
+        );
+        (Some(base), method, complex)
+    }
+
+    /// Set the attribute uses and wildcard of a type derived by extension.
+    fn extend_attributes(
+        &mut self,
+        at: Source,
+        complex: &mut ComplexType,
+        base_attributes: &[AttributeUse],
+        base_wildcard: &Option<Wildcard>,
+        attributes: AttributeSet,
+    ) {
+        complex.attributes = base_attributes.to_vec();
+        for attribute in attributes.uses {
+            if base_attributes
+                .iter()
+                .any(|base| base.name == attribute.name)
+            {
+                let message = format!(
+                    "attribute \"{}\" is already declared by the base type",
+                    attribute.name
+                );
+                self.error(at, message);
+            } else {
+                complex.attributes.push(attribute);
+            }
+        }
+        complex.wildcard = match (base_wildcard, attributes.wildcard) {
+            (Some(base), Some(own)) => Some(Wildcard {
+                namespaces: base.namespaces.union(&own.namespaces),
+                process_contents: own.process_contents,
+            }),
+            (base, own) => own.or_else(|| base.clone()),
+        };
+    }
+
+    // Content models.
+
+    /// Return the `minOccurs` and `maxOccurs` of a particle.
+    fn occurs(&mut self, at: Source) -> (u64, Option<u64>) {
+        let min = match self.attribute(at, "minOccurs").map(str::trim) {
+            None => 1,
+            Some(value) => match value.parse::<u64>() {
+                Ok(min) => min,
+                Err(_) => {
+                    self.error(at, format!("invalid value \"{}\" for minOccurs", value));
+                    1
+                }
+            },
+        };
+        let max = match self.attribute(at, "maxOccurs").map(str::trim) {
+            None => Some(1),
+            Some("unbounded") => None,
+            Some(value) => match value.parse::<u64>() {
+                Ok(max) => Some(max),
+                Err(_) => {
+                    self.error(at, format!("invalid value \"{}\" for maxOccurs", value));
+                    Some(1)
+                }
+            },
+        };
+        if max.is_some_and(|max| max < min) {
+            self.error(at, "maxOccurs is less than minOccurs");
+            return (min, Some(min));
+        }
+        (min, max)
+    }
+
+    fn particle(&mut self, at: Source) -> Rc<Particle> {
+        let (min, max) = self.occurs(at);
+        let term = match self.local_name(at) {
+            "element" => match self.attribute(at, "ref") {
+                Some(reference) => match self
+                    .resolve(at, reference)
+                    .and_then(|name| self.element_by_name(at, &name))
+                {
+                    Some(id) => Rc::new(Particle::Element(id)),
+                    None => not_allowed(),
+                },
+                None => Rc::new(Particle::Element(self.element_from(at, false))),
+            },
+            "any" => Rc::new(Particle::Any(Rc::new(self.wildcard(at)))),
+            "sequence" => self.children(at).into_iter().fold(empty(), |model, child| {
+                sequence(model, self.particle(child))
+            }),
+            "choice" => self
+                .children(at)
+                .into_iter()
+                .fold(not_allowed(), |model, child| {
+                    choice(model, self.particle(child))
+                }),
+            "all" => {
+                let mut model = empty();
+                for child in self.children(at) {
+                    let (_, max) = self.occurs(child);
+                    if self.local_name(child) != "element" || max.is_none_or(|max| max > 1) {
+                        self.error(
+                            child,
+                            "xs:all may only contain elements that occur at most once",
+                        );
+                    }
+                    model = interleave(model, self.particle(child));
+                }
+                model
+            }
+            "group" => match self.attribute(at, "ref") {
+                Some(reference) => match self
+                    .resolve(at, reference)
+                    .and_then(|name| self.group_by_name(at, &name))
+                {
+                    Some(particle) => particle,
+                    None => not_allowed(),
+                },
+                None => {
+                    self.error(at, "missing ref attribute");
+                    not_allowed()
+                }
+            },
+            other => {
+                self.error(at, format!("unexpected xs:{} in a content model", other));
+                not_allowed()
+            }
+        };
+        repeat(term, min, max)
+    }
+
+    fn wildcard(&mut self, at: Source) -> Wildcard {
+        let target_namespace = self.document(at).target_namespace.clone();
+        let namespaces = match self.attribute(at, "namespace").map(str::trim) {
+            None | Some("##any") => NamespaceConstraint::Any,
+            Some("##other") => NamespaceConstraint::Not(target_namespace),
+            Some(list) => NamespaceConstraint::Set(
+                list.split(names::is_whitespace)
+                    .filter(|s| !s.is_empty())
+                    .map(|uri| match uri {
+                        "##targetNamespace" => target_namespace.clone(),
+                        "##local" => String::new(),
+                        uri => uri.to_owned(),
+                    })
+                    .collect(),
+            ),
+        };
+        let process_contents = match self.attribute(at, "processContents").map(str::trim) {
+            None | Some("strict") => ProcessContents::Strict,
+            Some("lax") => ProcessContents::Lax,
+            Some("skip") => ProcessContents::Skip,
+            Some(value) => {
+                self.error(
+                    at,
+                    format!("invalid value \"{}\" for processContents", value),
+                );
+                ProcessContents::Strict
+            }
+        };
+        Wildcard {
+            namespaces,
+            process_contents,
+        }
+    }
+
+    // Attributes.
+
+    /// Collect the attribute uses, prohibitions and wildcard given by `xs:attribute`,
+    /// `xs:attributeGroup` and `xs:anyAttribute` elements.
+    fn attribute_set(&mut self, children: &[Source]) -> AttributeSet {
+        let mut set = AttributeSet::default();
+        let mut wildcards = Vec::new();
+        for &child in children {
+            match self.local_name(child) {
+                "attribute" => {
+                    let prohibited =
+                        self.attribute(child, "use").map(str::trim) == Some("prohibited");
+                    match self.attribute_use(child) {
+                        Some(attribute) if prohibited => set.prohibited.push(attribute.name),
+                        Some(attribute) => self.add_attribute(child, &mut set, attribute),
+                        None => {}
+                    }
+                }
+                "attributeGroup" => {
+                    let group = match self.attribute(child, "ref") {
+                        Some(reference) => self
+                            .resolve(child, reference)
+                            .and_then(|name| self.attribute_group_by_name(child, &name)),
+                        None => {
+                            self.error(child, "missing ref attribute");
+                            None
+                        }
+                    };
+                    if let Some(group) = group {
+                        for attribute in group.uses {
+                            self.add_attribute(child, &mut set, attribute);
+                        }
+                        set.prohibited.extend(group.prohibited);
+                        wildcards.extend(group.wildcard);
+                    }
+                }
+                "anyAttribute" => {
+                    let wildcard = self.wildcard(child);
+                    wildcards.insert(0, wildcard);
+                }
+                _ => {}
+            }
+        }
+        let mut wildcards = wildcards.into_iter();
+        set.wildcard = wildcards.next().map(|first| Wildcard {
+            namespaces: wildcards.fold(first.namespaces, |namespaces, wildcard| {
+                namespaces.intersect(&wildcard.namespaces)
+            }),
+            process_contents: first.process_contents,
+        });
+        set
+    }
+
+    fn add_attribute(&mut self, at: Source, set: &mut AttributeSet, attribute: AttributeUse) {
+        if set.uses.iter().any(|other| other.name == attribute.name) {
+            self.error(at, format!("duplicate attribute \"{}\"", attribute.name));
+        } else {
+            set.uses.push(attribute);
+        }
+    }
+
+    /// Return the use of an attribute by a complex type or attribute group.
+    fn attribute_use(&mut self, at: Source) -> Option<AttributeUse> {
+        let required = match self.attribute(at, "use").map(str::trim) {
+            None | Some("optional") | Some("prohibited") => false,
+            Some("required") => true,
+            Some(value) => {
+                self.error(at, format!("invalid value \"{}\" for use", value));
+                false
+            }
+        };
+        let constraint = self.value_constraint(at);
+        if required
+            && constraint
+                .as_ref()
+                .is_some_and(|constraint| !constraint.fixed)
+        {
+            self.error(at, "a required attribute cannot have a default value");
+        }
+        let mut attribute = match self.attribute(at, "ref") {
+            Some(reference) => {
+                let name = self.resolve(at, reference)?;
+                self.attribute_by_name(at, &name)?
+            }
+            None => self.attribute_declaration(at, false)?,
+        };
+        attribute.required = required;
+        if constraint.is_some() {
+            attribute.constraint = constraint;
+        }
+        self.check_value_constraint(at, attribute.type_id, attribute.constraint.as_ref());
+        Some(attribute)
+    }
+
+    fn attribute_declaration(&mut self, at: Source, global: bool) -> Option<AttributeUse> {
+        let qualified = match self.attribute(at, "form").map(str::trim) {
+            _ if global => true,
+            Some("qualified") => true,
+            Some("unqualified") => false,
+            None => self.document(at).qualified_attributes,
+            Some(value) => {
+                self.error(at, format!("invalid value \"{}\" for form", value));
+                false
+            }
+        };
+        let name = self.component_name(at, qualified)?;
+        if name.local_name == "xmlns" {
+            self.error(at, "attributes cannot be named xmlns");
+        }
+        let type_id = self
+            .declared_type(at, true)
+            .unwrap_or_else(|| self.components.builtin(BuiltinType::AnySimpleType));
+        let constraint = if global {
+            self.value_constraint(at)
+        } else {
+            None
+        };
+        Some(AttributeUse {
+            name,
+            type_id,
+            required: false,
+            constraint,
+        })
+    }
+
+    fn value_constraint(&mut self, at: Source) -> Option<ValueConstraint> {
+        match (self.attribute(at, "default"), self.attribute(at, "fixed")) {
+            (Some(_), Some(_)) => {
+                self.error(at, "default and fixed cannot both be present");
+                None
+            }
+            (Some(value), None) => Some(ValueConstraint {
+                value: value.to_owned(),
+                fixed: false,
+            }),
+            (None, Some(value)) => Some(ValueConstraint {
+                value: value.to_owned(),
+                fixed: true,
+            }),
+            (None, None) => None,
+        }
+    }
+
+    /// Check that a default or fixed value is valid for its type, if the type can be compiled
+    /// at this point.
+    fn check_value_constraint(
+        &mut self,
+        at: Source,
+        type_id: TypeId,
+        constraint: Option<&ValueConstraint>,
+    ) {
+        let constraint = match constraint {
+            Some(constraint) => constraint,
+            None => return,
+        };
+        self.complete(type_id);
+        if self.pending.contains_key(&type_id) {
+            return;
+        }
+        match self.components.value_type(type_id) {
+            Some(simple) => {
+                if let Err(e) = self.components.validate(simple, &constraint.value) {
+                    self.error(at, format!("invalid value constraint: {}", e.message()));
+                }
+            }
+            None => {
+                let emptiable = match self.components.type_def(type_id).kind {
+                    TypeKind::Complex(ComplexType {
+                        content:
+                            Content::Elements {
+                                ref particle,
+                                mixed: true,
+                            },
+                        ..
+                    }) => self.components.nullable(particle),
+                    _ => false,
+                };
+                if !emptiable {
+                    let message = format!(
+                        "type \"{}\" has no simple content, and cannot have a value constraint",
+                        self.components.describe(type_id)
+                    );
+                    self.error(at, message);
+                }
+            }
+        }
+    }
+
+    // Elements.
+
+    fn element_from(&mut self, source: Source, global: bool) -> ElementId {
+        if let Some(id) = self.elements.get(&source) {
+            return *id;
+        }
+        let id = ElementId(self.components.elements.len());
+        self.components.elements.push(ElementDecl {
+            name: QName::new("", ""),
+            type_id: ANY_TYPE,
+            nillable: false,
+            constraint: None,
+            is_abstract: false,
+            block: 0,
+            final_derivations: 0,
+            head: None,
+            substitutes: Vec::new(),
+            constraints: Vec::new(),
+        });
+        self.element_sources.push(source);
+        self.elements.insert(source, id);
+
+        let qualified = match self.attribute(source, "form").map(str::trim) {
+            _ if global => true,
+            Some("qualified") => true,
+            Some("unqualified") => false,
+            None => self.document(source).qualified_elements,
+            Some(value) => {
+                self.error(source, format!("invalid value \"{}\" for form", value));
+                false
+            }
+        };
+        let name = self
+            .component_name(source, qualified)
+            .unwrap_or_else(|| QName::new("", ""));
+        let document = self.document(source);
+        let block = self.derivations(
+            source,
+            "block",
+            document.block_default,
+            COMPLEX_DERIVATIONS | BLOCK_SUBSTITUTION,
+        );
+        let final_derivations =
+            self.derivations(source, "final", document.final_default, COMPLEX_DERIVATIONS);
+        let nillable = self.flag(source, "nillable");
+        let is_abstract = global && self.flag(source, "abstract");
+        let head = match self.attribute(source, "substitutionGroup") {
+            Some(reference) if global => self
+                .resolve(source, reference)
+                .and_then(|head| self.element_by_name(source, &head)),
+            _ => None,
+        };
+        let type_id = match self.declared_type(source, false) {
+            Some(type_id) => type_id,
+            None => match head {
+                Some(head) => self.components.element(head).type_id,
+                None => ANY_TYPE,
+            },
+        };
+        let constraint = self.value_constraint(source);
+        self.check_value_constraint(source, type_id, constraint.as_ref());
+        let mut constraints = Vec::new();
+        for child in self.children(source) {
+            if ["unique", "key", "keyref"].contains(&self.local_name(child)) {
+                if let Some(constraint) = self.identity_constraint(child) {
+                    constraints.push(constraint);
+                }
+            }
+        }
+        self.components.elements[id.0] = ElementDecl {
+            name,
+            type_id,
+            nillable,
+            constraint,
+            is_abstract,
+            block,
+            final_derivations,
+            head,
+            substitutes: Vec::new(),
+            constraints,
+        };
+        id
+    }
+
+    /// Check the substitution groups, and list the members of each.
+    fn substitution_groups(&mut self) {
+        let count = self.components.elements.len();
+        for id in 0..count {
+            let head = match self.components.elements[id].head {
+                Some(head) => head,
+                None => continue,
+            };
+            let name = self.components.elements[id].name.clone();
+            let mut current = Some(head);
+            let mut steps = 0;
+            while let Some(element) = current {
+                if element.0 == id || steps > count {
+                    let message = format!("circular substitution group of element \"{}\"", name);
+                    self.error(self.element_sources[id], message);
+                    self.components.elements[id].head = None;
+                    break;
+                }
+                current = self.components.element(element).head;
+                steps += 1;
+            }
+            if self.components.elements[id].head.is_none() {
+                continue;
+            }
+            let member_type = self.components.elements[id].type_id;
+            let head_element = self.components.element(head);
+            let head_name = head_element.name.clone();
+            let head_final = head_element.final_derivations;
+            let message = match self
+                .components
+                .derivation(member_type, head_element.type_id)
+            {
+                Some(methods) if methods & head_final == 0 => continue,
+                Some(_) => format!(
+                    "element \"{}\" does not allow the type of \"{}\" in its substitution group",
+                    head_name, name
+                ),
+                None => {
+                    self.components.elements[id].head = None;
+                    format!(
+                        "the type of element \"{}\" is not derived from the type of \"{}\", \
+                         the head of its substitution group",
+                        name, head_name
+                    )
+                }
+            };
+            self.error(self.element_sources[id], message);
+        }
+        for id in 0..count {
+            if self.components.elements[id].is_abstract {
+                continue;
+            }
+            let member_type = self.components.elements[id].type_id;
+            let mut current = self.components.elements[id].head;
+            while let Some(head) = current {
+                let head_element = self.components.element(head);
+                let methods = self
+                    .components
+                    .derivation(member_type, head_element.type_id)
+                    .unwrap_or(0);
+                let type_block = self.components.type_def(head_element.type_id).block;
+                let blocked = head_element.block & BLOCK_SUBSTITUTION != 0
+                    || methods & (head_element.block | type_block) != 0;
+                current = head_element.head;
+                if !blocked {
+                    self.components.elements[head.0]
+                        .substitutes
+                        .push(ElementId(id));
+                }
+            }
+        }
+    }
+
+    // Identity constraints.
+
+    fn identity_constraint(&mut self, at: Source) -> Option<ConstraintId> {
+        let name = self.component_name(at, true)?;
+        if self.constraint_names.contains_key(&name) {
+            self.error(at, format!("duplicate identity constraint \"{}\"", name));
+            return None;
+        }
+        let id = ConstraintId(self.components.constraints.len());
+        let (kind, refer) = match self.local_name(at) {
+            "unique" => (ConstraintKind::Unique, None),
+            "key" => (ConstraintKind::Key, None),
+            _ => match self.attribute(at, "refer") {
+                // The key is resolved once all constraints are compiled.
+                Some(refer) => (ConstraintKind::Keyref(id), Some(self.resolve(at, refer)?)),
+                None => {
+                    self.error(at, "missing refer attribute");
+                    return None;
+                }
+            },
+        };
+        let selector = match self.child(at, &["selector"]) {
+            Some(selector) => self.xpath(selector, false)?,
+            None => {
+                self.error(at, format!("xs:{} has no xs:selector", self.local_name(at)));
+                return None;
+            }
+        };
+        let mut fields = Vec::new();
+        for child in self.children(at) {
+            if self.local_name(child) == "field" {
+                fields.push(self.xpath(child, true)?);
+            }
+        }
+        if fields.is_empty() {
+            self.error(at, format!("xs:{} has no xs:field", self.local_name(at)));
+            return None;
+        }
+        self.components.constraints.push(IdentityConstraint {
+            name: name.clone(),
+            kind,
+            selector,
+            fields,
+        });
+        self.constraint_names.insert(name, id);
+        if let Some(refer) = refer {
+            self.keyrefs.push((id, refer, at));
+        }
+        Some(id)
+    }
+
+    /// Parse the `xpath` attribute of a selector or field, in the XPath subset of XSD 1.0.
+    fn xpath(&mut self, at: Source, field: bool) -> Option<Vec<Path>> {
+        let xpath = match self.attribute(at, "xpath") {
+            Some(xpath) => xpath,
+            None => {
+                self.error(at, "missing xpath attribute");
+                return None;
+            }
+        };
+        let compact: String = xpath
+            .chars()
+            .filter(|c| !names::is_whitespace(*c))
+            .collect();
+        let invalid = |compiler: &mut Compiler| {
+            let message = format!(
+                "invalid {} \"{}\"",
+                if field { "field" } else { "selector" },
+                xpath
+            );
+            compiler.error(at, message);
+            None
+        };
+        let mut paths = Vec::new();
+        for alternative in compact.split('|') {
+            let (descendant, rest) = match alternative.strip_prefix(".//") {
+                Some(rest) => (true, rest),
+                None => (false, alternative),
+            };
+            let mut path = Path {
+                descendant,
+                steps: Vec::new(),
+                attribute: None,
+            };
+            for step in rest.split('/') {
+                if path.attribute.is_some() || step.is_empty() {
+                    return invalid(self);
+                }
+                if step == "." {
+                    continue;
+                }
+                let (is_attribute, test) = if let Some(test) = step.strip_prefix('@') {
+                    (true, test)
+                } else if let Some(test) = step.strip_prefix("attribute::") {
+                    (true, test)
+                } else {
+                    (false, step.strip_prefix("child::").unwrap_or(step))
+                };
+                if is_attribute && !field {
+                    return invalid(self);
+                }
+                let test = match self.name_test(at, test) {
+                    Some(test) => test,
+                    None => return invalid(self),
+                };
+                if is_attribute {
+                    path.attribute = Some(test);
+                } else {
+                    path.steps.push(test);
+                }
+            }
+            paths.push(path);
+        }
+        Some(paths)
+    }
+
+    fn name_test(&self, at: Source, test: &str) -> Option<NameTest> {
+        if test == "*" {
+            return Some(NameTest::Any);
+        }
+        let (prefix, local_name) = match test.find(':') {
+            Some(i) => (&test[..i], &test[i + 1..]),
+            None => ("", test),
+        };
+        let uri = if prefix.is_empty() {
+            String::new()
+        } else {
+            self.lookup_prefix(at, prefix)?
+        };
+        if local_name == "*" && !prefix.is_empty() {
+            Some(NameTest::Namespace(uri))
+        } else if names::is_ncname(local_name) {
+            Some(NameTest::Name(QName {
+                uri,
+                local_name: local_name.to_owned(),
+            }))
+        } else {
+            None
+        }
+    }
+
+    fn resolve_keyrefs(&mut self) {
+        for (id, refer, at) in std::mem::take(&mut self.keyrefs) {
+            let key = match self.constraint_names.get(&refer) {
+                Some(key) => *key,
+                None => {
+                    self.error(
+                        at,
+                        format!("identity constraint \"{}\" is not defined", refer),
+                    );
+                    continue;
+                }
+            };
+            let referred = self.components.constraint(key);
+            let message = if let ConstraintKind::Keyref(_) = referred.kind {
+                format!("\"{}\" is not a key or unique constraint", refer)
+            } else if referred.fields.len() != self.components.constraint(id).fields.len() {
+                format!("keyref does not have as many fields as \"{}\"", refer)
+            } else {
+                self.components.constraints[id.0].kind = ConstraintKind::Keyref(key);
+                continue;
+            };
+            self.error(at, message);
+        }
+    }
+}
+
+/// Return the content of a complex type with the given particle.
+fn element_content(particle: Option<Rc<Particle>>, mixed: bool) -> Content {
+    match particle {
+        Some(particle) if !matches!(*particle, Particle::Empty) => {
+            Content::Elements { particle, mixed }
+        }
+        _ if mixed => Content::Elements {
+            particle: empty(),
+            mixed,
+        },
+        _ => Content::Empty,
+    }
+}
+
+/// Return the attribute uses of a type derived by restriction: those of the base type, unless
+/// redeclared or prohibited.
+fn restrict_attributes(
+    base: &[AttributeUse],
+    uses: Vec<AttributeUse>,
+    prohibited: &[QName],
+) -> Vec<AttributeUse> {
+    let mut attributes: Vec<AttributeUse> = base
+        .iter()
+        .filter(|attribute| {
+            !prohibited.contains(&attribute.name)
+                && !uses.iter().any(|other| other.name == attribute.name)
+        })
+        .cloned()
+        .collect();
+    attributes.extend(uses);
+    attributes
+}
+
+/// Return the facets of a restriction: those given by the restriction, and those of the base
+/// type that it does not replace. Patterns accumulate, since a value must match the patterns
+/// of every derivation step.
+fn merge_facets(base: &Facets, facets: Facets) -> Facets {
+    let mut patterns = base.patterns.clone();
+    patterns.extend(facets.patterns);
+    Facets {
+        length: facets.length.or(base.length),
+        min_length: facets.min_length.or(base.min_length),
+        max_length: facets.max_length.or(base.max_length),
+        enumeration: if facets.enumeration.is_empty() {
+            base.enumeration.clone()
+        } else {
+            facets.enumeration
+        },
+        min_inclusive: facets.min_inclusive.or_else(|| base.min_inclusive.clone()),
+        max_inclusive: facets.max_inclusive.or_else(|| base.max_inclusive.clone()),
+        min_exclusive: facets.min_exclusive.or_else(|| base.min_exclusive.clone()),
+        max_exclusive: facets.max_exclusive.or_else(|| base.max_exclusive.clone()),
+        total_digits: facets.total_digits.or(base.total_digits),
+        fraction_digits: facets.fraction_digits.or(base.fraction_digits),
+        patterns,
+        white_space: facets.white_space.or(base.white_space),
+    }
+}
+
+fn describe_derivation(derivation: u32) -> &'static str {
+    match derivation {
+        DERIVATION_EXTENSION => "extension",
+        DERIVATION_LIST => "list",
+        DERIVATION_UNION => "union",
+        _ => "restriction",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event::markup_events;
+    use event::EventRecorder;
+    use helpers::AttributesImpl;
+    use helpers::LocatorImpl;
+    use resolver::SandboxResolver;
+    use sax2::ContentHandler;
+    use xsd::components::SchemaSet;
+    use xsd::components::TypeInfoImpl;
+    use xsd::Schema;
+    use xsd::SchemaLoader;
+    use xsd::ValidatorHandler;
+    use xsd::XSD_NS;
+
+    #[derive(Clone, Default)]
+    struct Errors(Rc<RefCell<Vec<String>>>);
+
+    impl ErrorHandler<ParseErrorImpl> for Errors {
+        fn error(&self, e: &ParseErrorImpl) -> Result<()> {
+            self.0.borrow_mut().push(e.to_string());
+            Ok(())
+        }
+    }
+
+    fn parse(
+        input: &mut dyn Read,
+        _system_id: &str,
+        handler: &dyn ContentHandler<LocatorImpl, AttributesImpl>,
+    ) -> Result<()> {
+        let mut markup = String::new();
+        input.read_to_string(&mut markup).unwrap();
+        for event in markup_events(&markup) {
+            event.dispatch(handler)?;
+        }
+        Ok(())
+    }
+
+    /// Wrap components in a schema document with the given attributes.
+    fn document(attributes: &str, components: &str) -> String {
+        format!(
+            "<xs:schema xmlns:xs=\"{}\" {}>{}</xs:schema>",
+            XSD_NS, attributes, components
+        )
+    }
+
+    /// Load the first of the given documents, and return the result with the errors reported.
+    fn load(documents: &[(&str, String)]) -> (Result<SchemaSet>, Vec<String>) {
+        let mut resolver = SandboxResolver::in_memory();
+        for &(uri, ref document) in documents {
+            resolver.insert(uri, document.clone());
+        }
+        let errors = Errors::default();
+        let loader = Loader::<LocatorImpl, _, _, _>::new(parse);
+        loader.set_entity_resolver(resolver);
+        loader.set_error_handler(errors.clone());
+        let schema = loader.new_schema(&[documents[0].0]);
+        let errors = errors.0.take();
+        (schema, errors)
+    }
+
+    /// Validate a document with a schema, and return the violations reported.
+    fn validate(schema: &SchemaSet, markup: &str) -> Vec<String> {
+        let errors = Errors::default();
+        let mut validator = schema.new_validator_handler();
+        let handler: &mut dyn ValidatorHandler<
+            LocatorImpl,
+            AttributesImpl,
+            EventRecorder<LocatorImpl>,
+            Errors,
+            ParseErrorImpl,
+            Type = TypeInfoImpl,
+        > = &mut validator;
+        handler.set_error_handler(errors.clone());
+        for event in markup_events(markup) {
+            event.dispatch(&validator).unwrap();
+        }
+        errors.0.take()
+    }
+
+    #[test]
+    fn includes_and_imports() {
+        let main = document(
+            "targetNamespace='urn:main' xmlns='urn:main' xmlns:o='urn:other'",
+            concat!(
+                "<xs:include schemaLocation='types.xsd'/>",
+                "<xs:include schemaLocation='common.xsd'/>",
+                "<xs:import namespace='urn:other' schemaLocation='http://example.com/o.xsd'/>",
+                "<xs:import namespace='urn:unlocated'/>",
+                "<xs:import namespace='http://www.w3.org/XML/1998/namespace'/>",
+                "<xs:element name='doc'><xs:complexType><xs:sequence>",
+                "<xs:element ref='title'/><xs:element ref='o:note'/>",
+                "</xs:sequence><xs:attribute ref='xml:lang'/></xs:complexType></xs:element>",
+            ),
+        );
+        let types = document(
+            "targetNamespace='urn:main' xmlns='urn:main'",
+            "<xs:include schemaLocation='main.xsd'/><xs:simpleType name='text'>\
+             <xs:restriction base='xs:string'/></xs:simpleType>",
+        );
+        let common = document("", "<xs:element name='title' type='text'/>");
+        let other = document(
+            "targetNamespace='urn:other' elementFormDefault='qualified'",
+            "<xs:element name='note' type='xs:string'/>",
+        );
+        let (schema, errors) = load(&[
+            ("http://example.com/main.xsd", main),
+            ("http://example.com/types.xsd", types),
+            ("http://example.com/common.xsd", common),
+            ("http://example.com/o.xsd", other),
+        ]);
+        assert_eq!(errors, Vec::<String>::new());
+        let schema = schema.unwrap();
+        assert!(schema.has_element("urn:main", "doc"));
+        assert!(schema.has_element("urn:main", "title"));
+        assert!(!schema.has_element("", "title"));
+        assert!(schema.has_element("urn:other", "note"));
+        assert!(schema.has_type("urn:main", "text"));
+        let valid = concat!(
+            "<doc xmlns='urn:main' xml:lang='en'><title>T</title>",
+            "<note xmlns='urn:other'>N</note></doc>",
+        );
+        assert_eq!(validate(&schema, valid), Vec::<String>::new());
+        let errors = validate(&schema, "<doc xmlns='urn:main' xml:lang='?'><title/></doc>");
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(errors[0].starts_with("invalid value of attribute \"xml:lang\""));
+    }
+
+    #[test]
+    fn redefinitions() {
+        let base = document(
+            "",
+            concat!(
+                "<xs:complexType name='person'><xs:sequence>",
+                "<xs:element name='name' type='xs:string'/>",
+                "</xs:sequence></xs:complexType>",
+                "<xs:group name='extra'><xs:sequence>",
+                "<xs:element name='age' type='xs:int'/>",
+                "</xs:sequence></xs:group>",
+            ),
+        );
+        let main = document(
+            "",
+            concat!(
+                "<xs:redefine schemaLocation='base.xsd'>",
+                "<xs:complexType name='person'><xs:complexContent>",
+                "<xs:extension base='person'><xs:group ref='extra'/></xs:extension>",
+                "</xs:complexContent></xs:complexType>",
+                "<xs:group name='extra'><xs:sequence><xs:group ref='extra'/>",
+                "<xs:element name='email' type='xs:string' minOccurs='0'/>",
+                "</xs:sequence></xs:group>",
+                "</xs:redefine>",
+                "<xs:element name='person' type='person'/>",
+            ),
+        );
+        let (schema, errors) = load(&[
+            ("http://example.com/main.xsd", main),
+            ("http://example.com/base.xsd", base),
+        ]);
+        assert_eq!(errors, Vec::<String>::new());
+        let schema = schema.unwrap();
+        let valid = "<person><name>N</name><age>3</age><email>e</email></person>";
+        assert_eq!(validate(&schema, valid), Vec::<String>::new());
+        assert_eq!(
+            validate(&schema, "<person><name>N</name></person>"),
+            ["element \"person\" incomplete; expected \"age\""]
+        );
+    }
+
+    #[test]
+    fn schema_errors() {
+        let main = document(
+            "",
+            concat!(
+                "<xs:element name='a' type='missing'/>",
+                "<xs:complexType name='loop'><xs:complexContent>",
+                "<xs:extension base='loop'/></xs:complexContent></xs:complexType>",
+                "<xs:element name='b' type='xs:string'/>",
+                "<xs:element name='b' type='xs:int'/>",
+                "<xs:simpleType name='bad'><xs:restriction base='xs:int'>",
+                "<xs:maxLength value='x'/></xs:restriction></xs:simpleType>",
+            ),
+        );
+        let (schema, errors) = load(&[("http://example.com/main.xsd", main)]);
+        assert_eq!(
+            errors,
+            [
+                "http://example.com/main.xsd: duplicate element \"b\"",
+                "http://example.com/main.xsd: type \"missing\" is not defined",
+                "http://example.com/main.xsd: circular definition of type \"loop\"",
+                "http://example.com/main.xsd: invalid value \"x\" for xs:maxLength",
+            ]
+        );
+        let error = schema.err().unwrap();
+        assert_eq!(error.to_string(), errors[0]);
+    }
+
+    #[test]
+    fn unresolvable_documents() {
+        let main = document("", "<xs:include schemaLocation='missing.xsd'/>");
+        let (schema, errors) = load(&[("http://example.com/main.xsd", main)]);
+        assert_eq!(
+            errors,
+            [concat!(
+                "http://example.com/main.xsd: cannot resolve http://example.com/missing.xsd: ",
+                "http://example.com/missing.xsd is not an available resource"
+            )]
+        );
+        assert!(schema.is_err());
+
+        let (schema, errors) = load(&[("http://example.com/main.xsd", "<schema/>".to_owned())]);
+        assert_eq!(
+            errors,
+            ["http://example.com/main.xsd is not a schema document"]
+        );
+        assert!(schema.is_err());
+    }
+}
@@ -0,0 +1,608 @@
+//! The regular expressions of XML Schema, as used by the `pattern` facet.
+//!
+//! The syntax is that of XML Schema Part 2, Appendix F: branches, groups, the `?`, `*`, `+`
+//! and `{n,m}` quantifiers, character class expressions with negation and subtraction, the
+//! wildcard, and the single-character, multi-character and category escapes. Block escapes
+//! such as `\p{IsBasicLatin}` are not supported, and are rejected when the expression is
+//! compiled. An expression always matches a whole string; there are no anchors.
+//!
+//! Expressions are compiled to an automaton that is simulated over the input, so matching
+//! takes time linear in the length of the input, whatever the expression.
+use std::fmt;
+
+use helpers::ErrorKind;
+use helpers::ParseErrorImpl;
+use names;
+use xsd::categories::general_category;
+
+/// The most instructions that an expression may compile to, which bounds the counts of the
+/// `{n,m}` quantifiers.
+const MAX_PROGRAM: usize = 100_000;
+
+/// The general categories that may be named in a category escape.
+const CATEGORIES: [&str; 37] = [
+    "L", "Lu", "Ll", "Lt", "Lm", "Lo", "M", "Mn", "Mc", "Me", "N", "Nd", "Nl", "No", "P", "Pc",
+    "Pd", "Ps", "Pe", "Pi", "Pf", "Po", "Z", "Zs", "Zl", "Zp", "S", "Sm", "Sc", "Sk", "So", "C",
+    "Cc", "Cf", "Co", "Cn", "Cs",
+];
+
+/// A set of characters, as matched by a character class.
+#[derive(Clone, Debug)]
+enum Class {
+    Char(char),
+    Range(char, char),
+    /// The wildcard `.`: anything but a line end.
+    Any,
+    /// `\s`
+    Space,
+    /// `\i`
+    NameStart,
+    /// `\c`
+    NameChar,
+    /// `\w`: anything but punctuation, separators and other characters.
+    Word,
+    /// A general category or, if the name is a single letter, a group of them.
+    Category(String),
+    Not(Box<Class>),
+    Union(Vec<Class>),
+    Subtract(Box<Class>, Box<Class>),
+}
+
+impl Class {
+    fn matches(&self, c: char) -> bool {
+        match *self {
+            Class::Char(expected) => c == expected,
+            Class::Range(first, last) => first <= c && c <= last,
+            Class::Any => c != '\n' && c != '\r',
+            Class::Space => matches!(c, ' ' | '\t' | '\n' | '\r'),
+            Class::NameStart => names::is_name_start_char(c),
+            Class::NameChar => names::is_name_char(c),
+            Class::Word => !matches!(general_category(c).as_bytes()[0], b'P' | b'Z' | b'C'),
+            Class::Category(ref name) => general_category(c).starts_with(name.as_str()),
+            Class::Not(ref class) => !class.matches(c),
+            Class::Union(ref classes) => classes.iter().any(|class| class.matches(c)),
+            Class::Subtract(ref class, ref excluded) => class.matches(c) && !excluded.matches(c),
+        }
+    }
+}
+
+/// A parsed expression.
+enum Node {
+    Class(Class),
+    Concat(Vec<Node>),
+    Alternation(Vec<Node>),
+    Repeat(Box<Node>, usize, Option<usize>),
+}
+
+/// An instruction of the compiled automaton.
+#[derive(Clone, Debug)]
+enum Inst {
+    /// Consume a character of the class.
+    Class(Class),
+    /// Continue at both instructions.
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+/// What a backslash escape stands for.
+enum Escape {
+    Char(char),
+    Class(Class),
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> ParseErrorImpl {
+        ParseErrorImpl::of_kind(
+            ErrorKind::Expression,
+            format!(
+                "invalid regular expression \"{}\": {}",
+                self.source, message
+            ),
+        )
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).cloned()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.position += 1;
+        }
+        c
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseErrorImpl> {
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", expected)))
+        }
+    }
+
+    /// `regExp ::= branch ( '|' branch )*`
+    fn reg_exp(&mut self) -> Result<Node, ParseErrorImpl> {
+        let mut branches = vec![self.branch()?];
+        while self.eat('|') {
+            branches.push(self.branch()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Node::Alternation(branches)
+        })
+    }
+
+    /// `branch ::= piece*`
+    fn branch(&mut self) -> Result<Node, ParseErrorImpl> {
+        let mut pieces = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            pieces.push(self.piece()?);
+        }
+        Ok(Node::Concat(pieces))
+    }
+
+    /// `piece ::= atom quantifier?`
+    fn piece(&mut self) -> Result<Node, ParseErrorImpl> {
+        let atom = self.atom()?;
+        let (min, max) = match self.peek() {
+            Some('?') => (0, Some(1)),
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('{') => {
+                self.position += 1;
+                return self.quantity(atom);
+            }
+            _ => return Ok(atom),
+        };
+        self.position += 1;
+        Ok(Node::Repeat(Box::new(atom), min, max))
+    }
+
+    /// `quantity ::= quantRange | quantMin | QuantExact`, after the `{`.
+    fn quantity(&mut self, atom: Node) -> Result<Node, ParseErrorImpl> {
+        let min = self.count()?;
+        let max = if self.eat(',') {
+            if self.peek() == Some('}') {
+                None
+            } else {
+                Some(self.count()?)
+            }
+        } else {
+            Some(min)
+        };
+        self.expect('}')?;
+        if max.is_some_and(|max| max < min) {
+            return Err(self.error("the maximum of a quantifier is less than its minimum"));
+        }
+        Ok(Node::Repeat(Box::new(atom), min, max))
+    }
+
+    fn count(&mut self) -> Result<usize, ParseErrorImpl> {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+        let digits: String = self.chars[start..self.position].iter().collect();
+        digits
+            .parse()
+            .map_err(|_| self.error("expected a number in a quantifier"))
+    }
+
+    /// `atom ::= NormalChar | charClass | ( '(' regExp ')' )`
+    fn atom(&mut self) -> Result<Node, ParseErrorImpl> {
+        let c = self.next().expect("atom at end of expression");
+        let class = match c {
+            '(' => {
+                let node = self.reg_exp()?;
+                self.expect(')')?;
+                return Ok(node);
+            }
+            '[' => self.char_group()?,
+            '.' => Class::Any,
+            '\\' => match self.escape()? {
+                Escape::Char(c) => Class::Char(c),
+                Escape::Class(class) => class,
+            },
+            '?' | '*' | '+' | '{' | '}' | ']' => {
+                return Err(self.error(&format!("'{}' must be escaped", c)))
+            }
+            c => Class::Char(c),
+        };
+        Ok(Node::Class(class))
+    }
+
+    /// `charClassEsc` or `SingleCharEsc`, after the backslash.
+    fn escape(&mut self) -> Result<Escape, ParseErrorImpl> {
+        let class = match self.next() {
+            Some('n') => return Ok(Escape::Char('\n')),
+            Some('r') => return Ok(Escape::Char('\r')),
+            Some('t') => return Ok(Escape::Char('\t')),
+            Some(c @ '\\') | Some(c @ '|') | Some(c @ '.') | Some(c @ '-') | Some(c @ '^')
+            | Some(c @ '?') | Some(c @ '*') | Some(c @ '+') | Some(c @ '{') | Some(c @ '}')
+            | Some(c @ '(') | Some(c @ ')') | Some(c @ '[') | Some(c @ ']') => {
+                return Ok(Escape::Char(c))
+            }
+            Some('s') => Class::Space,
+            Some('S') => Class::Not(Box::new(Class::Space)),
+            Some('i') => Class::NameStart,
+            Some('I') => Class::Not(Box::new(Class::NameStart)),
+            Some('c') => Class::NameChar,
+            Some('C') => Class::Not(Box::new(Class::NameChar)),
+            Some('d') => Class::Category("Nd".to_owned()),
+            Some('D') => Class::Not(Box::new(Class::Category("Nd".to_owned()))),
+            Some('w') => Class::Word,
+            Some('W') => Class::Not(Box::new(Class::Word)),
+            Some('p') => self.category()?,
+            Some('P') => Class::Not(Box::new(self.category()?)),
+            Some(c) => return Err(self.error(&format!("unknown escape '\\{}'", c))),
+            None => return Err(self.error("the expression ends with a backslash")),
+        };
+        Ok(Escape::Class(class))
+    }
+
+    /// `charProp`, between the braces of a category escape.
+    fn category(&mut self) -> Result<Class, ParseErrorImpl> {
+        self.expect('{')?;
+        let start = self.position;
+        while self.peek().is_some_and(|c| c != '}') {
+            self.position += 1;
+        }
+        let name: String = self.chars[start..self.position].iter().collect();
+        self.expect('}')?;
+        if name.starts_with("Is") {
+            Err(self.error(&format!("block escape \"{}\" is not supported", name)))
+        } else if CATEGORIES.contains(&name.as_str()) {
+            Ok(Class::Category(name))
+        } else {
+            Err(self.error(&format!("unknown category \"{}\"", name)))
+        }
+    }
+
+    /// `charGroup ']'`, after the `[`.
+    fn char_group(&mut self) -> Result<Class, ParseErrorImpl> {
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+        let mut excluded = None;
+        loop {
+            let c = match self.next() {
+                Some(c) => c,
+                None => return Err(self.error("missing ']'")),
+            };
+            let first = match c {
+                ']' if items.is_empty() => return Err(self.error("empty character class")),
+                ']' => break,
+                '-' if self.peek() == Some('[') && !items.is_empty() => {
+                    self.position += 1;
+                    excluded = Some(self.char_group()?);
+                    self.expect(']')?;
+                    break;
+                }
+                '-' if !items.is_empty() && self.peek() != Some(']') => {
+                    return Err(self.error("'-' must be escaped"))
+                }
+                '[' => return Err(self.error("'[' must be escaped")),
+                '\\' => match self.escape()? {
+                    Escape::Char(c) => c,
+                    Escape::Class(class) => {
+                        items.push(class);
+                        continue;
+                    }
+                },
+                c => c,
+            };
+            if self.peek() == Some('-') && !matches!(self.peek_at(1), Some(']') | Some('[')) {
+                self.position += 1;
+                let last = match self.next() {
+                    Some('\\') => match self.escape()? {
+                        Escape::Char(c) => c,
+                        Escape::Class(_) => {
+                            return Err(self.error("a range must end at a character"))
+                        }
+                    },
+                    Some('[') => return Err(self.error("'[' must be escaped")),
+                    Some(c) => c,
+                    None => return Err(self.error("missing ']'")),
+                };
+                if last < first {
+                    return Err(self.error(&format!("invalid range '{}-{}'", first, last)));
+                }
+                items.push(Class::Range(first, last));
+            } else {
+                items.push(Class::Char(first));
+            }
+        }
+        let mut class = Class::Union(items);
+        if negated {
+            class = Class::Not(Box::new(class));
+        }
+        Ok(match excluded {
+            Some(excluded) => Class::Subtract(Box::new(class), Box::new(excluded)),
+            None => class,
+        })
+    }
+}
+
+/// A compiled XML Schema regular expression.
+#[derive(Clone)]
+pub struct Regex {
+    source: String,
+    program: Vec<Inst>,
+}
+
+impl Regex {
+    /// Compile an expression.
+    pub fn new(source: &str) -> Result<Regex, ParseErrorImpl> {
+        let mut parser = Parser {
+            source,
+            chars: source.chars().collect(),
+            position: 0,
+        };
+        let node = parser.reg_exp()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("unbalanced ')'"));
+        }
+        let mut program = Vec::new();
+        compile(&node, &mut program).map_err(|()| parser.error("the expression is too large"))?;
+        program.push(Inst::Match);
+        Ok(Regex {
+            source: source.to_owned(),
+            program,
+        })
+    }
+
+    /// Return the expression as written.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Return whether the expression matches the whole of a string.
+    pub fn is_match(&self, text: &str) -> bool {
+        let mut marks = vec![0; self.program.len()];
+        let mut current = Vec::new();
+        let mut next = Vec::new();
+        self.add(&mut current, &mut marks, 0, 1);
+        for (step, c) in text.chars().enumerate() {
+            for &pc in &current {
+                if let Inst::Class(ref class) = self.program[pc] {
+                    if class.matches(c) {
+                        self.add(&mut next, &mut marks, pc + 1, step + 2);
+                    }
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+            if current.is_empty() {
+                return false;
+            }
+        }
+        current
+            .iter()
+            .any(|&pc| matches!(self.program[pc], Inst::Match))
+    }
+
+    /// Add the instructions reachable from `pc` without consuming a character to `list`,
+    /// unless they were already added in this step.
+    fn add(&self, list: &mut Vec<usize>, marks: &mut [usize], pc: usize, step: usize) {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if marks[pc] == step {
+                continue;
+            }
+            marks[pc] = step;
+            match self.program[pc] {
+                Inst::Jump(target) => stack.push(target),
+                Inst::Split(first, second) => {
+                    stack.push(second);
+                    stack.push(first);
+                }
+                _ => list.push(pc),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Regex").field(&self.source).finish()
+    }
+}
+
+impl fmt::Display for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl PartialEq for Regex {
+    fn eq(&self, other: &Regex) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for Regex {}
+
+fn emit(program: &mut Vec<Inst>, inst: Inst) -> Result<usize, ()> {
+    if program.len() >= MAX_PROGRAM {
+        return Err(());
+    }
+    program.push(inst);
+    Ok(program.len() - 1)
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) -> Result<(), ()> {
+    match *node {
+        Node::Class(ref class) => {
+            emit(program, Inst::Class(class.clone()))?;
+        }
+        Node::Concat(ref nodes) => {
+            for node in nodes {
+                compile(node, program)?;
+            }
+        }
+        Node::Alternation(ref branches) => {
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i + 1 == branches.len() {
+                    compile(branch, program)?;
+                } else {
+                    let split = emit(program, Inst::Split(0, 0))?;
+                    compile(branch, program)?;
+                    jumps.push(emit(program, Inst::Jump(0))?);
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+            }
+            for jump in jumps {
+                program[jump] = Inst::Jump(program.len());
+            }
+        }
+        Node::Repeat(ref node, min, max) => {
+            for _ in 0..min {
+                compile(node, program)?;
+            }
+            match max {
+                None => {
+                    let split = emit(program, Inst::Split(0, 0))?;
+                    compile(node, program)?;
+                    emit(program, Inst::Jump(split))?;
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in min..max {
+                        splits.push(emit(program, Inst::Split(0, 0))?);
+                        compile(node, program)?;
+                    }
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, program.len());
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn re(source: &str) -> Regex {
+        Regex::new(source).unwrap()
+    }
+
+    fn rejects(source: &str) -> String {
+        Regex::new(source).expect_err(source).message().to_owned()
+    }
+
+    #[test]
+    fn matches_the_whole_string() {
+        let abc = re("abc");
+        assert!(abc.is_match("abc"));
+        assert!(!abc.is_match("abcd"));
+        assert!(!abc.is_match("xabc"));
+        assert!(re("").is_match(""));
+        assert!(!re("").is_match("a"));
+    }
+
+    #[test]
+    fn branches_groups_and_quantifiers() {
+        let colour = re("colou?r|grey|gray");
+        for s in &["color", "colour", "grey", "gray"] {
+            assert!(colour.is_match(s), "{}", s);
+        }
+        assert!(!colour.is_match("colouur"));
+        let repeated = re("(ab)+c*");
+        assert!(repeated.is_match("ababcc"));
+        assert!(!repeated.is_match("c"));
+        let counted = re("a{2,3}b{2}c{1,}");
+        assert!(counted.is_match("aabbc"));
+        assert!(counted.is_match("aaabbccc"));
+        assert!(!counted.is_match("abbc"));
+        assert!(!counted.is_match("aaaabbc"));
+        assert!(!counted.is_match("aabbbc"));
+        assert!(re("(a|)*b").is_match("aab"));
+    }
+
+    #[test]
+    fn character_class_expressions() {
+        let hex = re("[0-9a-fA-F]+");
+        assert!(hex.is_match("09afAF"));
+        assert!(!hex.is_match("g"));
+        let negated = re("[^a-z]");
+        assert!(negated.is_match("A"));
+        assert!(!negated.is_match("q"));
+        let consonant = re("[a-z-[aeiou]]");
+        assert!(consonant.is_match("b"));
+        assert!(!consonant.is_match("e"));
+        let dashes = re("[-+]?[a-]");
+        assert!(dashes.is_match("-a"));
+        assert!(dashes.is_match("+-"));
+        assert!(re("[\\[\\]\\-]").is_match("]"));
+        assert!(re(".").is_match("x"));
+        assert!(!re(".").is_match("\n"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(re("\\d{3}-\\d{4}").is_match("555-1234"));
+        assert!(re("\\d").is_match("\u{0663}"));
+        assert!(!re("\\d").is_match("x"));
+        assert!(re("\\w+").is_match("caf\u{E9}1"));
+        assert!(!re("\\w").is_match("-"));
+        assert!(re("\\s\\S").is_match("\tx"));
+        assert!(re("\\i\\c*").is_match("_x-1.y"));
+        assert!(!re("\\i").is_match("1"));
+        assert!(re("\\p{Lu}\\p{Ll}+").is_match("Hello"));
+        assert!(re("\\P{L}").is_match("1"));
+        assert!(!re("\\P{L}").is_match("a"));
+        assert!(re("[\\p{N}\\s]+").is_match("1 2\u{2167}"));
+        assert!(re("\\.\\*\\\\").is_match(".*\\"));
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        assert!(rejects("(ab").contains("expected ')'"));
+        assert!(rejects("ab)").contains("unbalanced ')'"));
+        assert!(rejects("[z-a]").contains("invalid range"));
+        assert!(rejects("[a-c-x]").contains("'-' must be escaped"));
+        assert!(rejects("[]").contains("empty character class"));
+        assert!(rejects("a{3,2}").contains("less than its minimum"));
+        assert!(rejects("*a").contains("'*' must be escaped"));
+        assert!(rejects("\\q").contains("unknown escape"));
+        assert!(rejects("\\p{Xx}").contains("unknown category"));
+        assert!(rejects("\\p{IsBasicLatin}").contains("not supported"));
+        assert!(rejects("(a{1000}){1000}").contains("too large"));
+    }
+
+    #[test]
+    fn matching_is_linear() {
+        let nested = re("(a*)*b");
+        let text = "a".repeat(10_000);
+        assert!(!nested.is_match(&text));
+    }
+}
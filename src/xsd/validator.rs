@@ -0,0 +1,1714 @@
+//! Validation of the event stream against a `components::SchemaSet`.
+//!
+//! `Validator` checks each element against its declaration and type as the events pass
+//! through: the children of elements against the content models of their complex types, by
+//! computing the derivative of the content model with respect to each child in turn;
+//! substitution group members in place of their heads; `xsi:type` and `xsi:nil`; attributes
+//! against the attribute uses and wildcards of complex types; character content against simple
+//! types; default and fixed values; `xs:ID` and `xs:IDREF` values; and `xs:unique`, `xs:key`
+//! and `xs:keyref` identity constraints, whose node tables are built as the selected elements
+//! end.
+//!
+//! `xsi:schemaLocation` and `xsi:noNamespaceSchemaLocation` are ignored: the schema is the one
+//! the validator was created from.
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use common::Locator;
+use helpers::AttributesImpl;
+use helpers::ErrorKind;
+use helpers::LocatorImpl;
+use helpers::NamespaceSupport;
+use helpers::ParseErrorImpl;
+use helpers::NSDECL;
+use names;
+use sax2::Attributes;
+use sax2::ContentHandler;
+use sax2::ErrorHandler;
+use sax2::Result;
+use xsd::components::choice;
+use xsd::components::empty;
+use xsd::components::interleave;
+use xsd::components::not_allowed;
+use xsd::components::repeat;
+use xsd::components::sequence;
+use xsd::components::ComplexType;
+use xsd::components::Components;
+use xsd::components::ConstraintId;
+use xsd::components::ConstraintKind;
+use xsd::components::Content;
+use xsd::components::ElementId;
+use xsd::components::KeyValue;
+use xsd::components::Particle;
+use xsd::components::ProcessContents;
+use xsd::components::QName;
+use xsd::components::SchemaSet;
+use xsd::components::TypeId;
+use xsd::components::TypeInfoImpl;
+use xsd::components::TypeKind;
+use xsd::components::Wildcard;
+use xsd::components::ANY_TYPE;
+use xsd::BuiltinType;
+use xsd::ValidatorHandler;
+use xsd::XSI_NS;
+
+/// What matched an element in a content model.
+enum Term {
+    Element(ElementId),
+    Wildcard(Rc<Wildcard>),
+}
+
+/// An element being validated.
+struct Frame {
+    /// The name of the element, for messages.
+    name: String,
+    element: Option<ElementId>,
+    type_id: TypeId,
+    /// Whether the element was assigned a type: it was not if it has no declaration and is
+    /// assessed laxly, as `xs:anyType`.
+    typed: bool,
+    /// What remains of the content model after the children seen so far.
+    particle: Rc<Particle>,
+    nil: bool,
+    text: String,
+    had_child: bool,
+    attribute_types: Vec<Option<TypeId>>,
+    /// The node tables of the keys and unique constraints of this element and its
+    /// descendants.
+    tables: HashMap<ConstraintId, Vec<Vec<KeyValue>>>,
+}
+
+/// An identity constraint in scope, declared by an open element.
+struct Scope {
+    constraint: ConstraintId,
+    /// The depth of the element declaring the constraint.
+    depth: usize,
+    /// The key sequences of the elements selected so far.
+    table: Vec<Vec<KeyValue>>,
+}
+
+/// An element selected by the selector of a scope, whose fields are being evaluated.
+struct Selected {
+    scope: usize,
+    depth: usize,
+    fields: Vec<Option<KeyValue>>,
+}
+
+/// A field of a selected element whose value is the content of an open element.
+struct ElementField {
+    selected: usize,
+    field: usize,
+    depth: usize,
+}
+
+#[derive(Default)]
+struct ValidatorState {
+    frames: Vec<Frame>,
+    /// The depth of the subtree being skipped, or zero.
+    skip_depth: usize,
+    namespaces: NamespaceSupport,
+    mappings: Vec<(String, String)>,
+    /// The names of the open elements.
+    path: Vec<QName>,
+    scopes: Vec<Scope>,
+    selected: Vec<Selected>,
+    element_fields: Vec<ElementField>,
+    ids: HashSet<String>,
+    /// The IDREF values seen, with where they were found.
+    idrefs: Vec<(String, LocatorImpl)>,
+}
+
+/// Validates the events passing through it against a `SchemaSet`, and forwards them to a
+/// content handler.
+///
+/// The root element must have a global declaration. Elements matched by a wildcard are
+/// validated according to its `processContents`, and those of a lax wildcard without a
+/// declaration are assessed as `xs:anyType`. After a violation, validation continues: an
+/// element that is not allowed is skipped along with its content, without further checks.
+///
+/// Attributes defaulted by the schema are added to the attribute list forwarded downstream,
+/// and default element values are forwarded as character content. Values are forwarded as they
+/// are, without white space normalization.
+pub struct Validator<L: Locator, CH, EH> {
+    components: Rc<Components>,
+    handler: Option<CH>,
+    error_handler: Option<EH>,
+    locator: RefCell<Option<Rc<L>>>,
+    error_count: Cell<usize>,
+    state: RefCell<ValidatorState>,
+}
+
+impl<L: Locator, CH, EH> Validator<L, CH, EH> {
+    /// Construct a validator for a schema, without a content handler or error handler.
+    pub fn new(schema: &SchemaSet) -> Self {
+        Validator {
+            components: schema.components().clone(),
+            handler: None,
+            error_handler: None,
+            locator: RefCell::new(None),
+            error_count: Cell::new(0),
+            state: RefCell::new(ValidatorState::default()),
+        }
+    }
+
+    /// Return the number of violations found in the current document.
+    pub fn error_count(&self) -> usize {
+        self.error_count.get()
+    }
+
+    /// Return whether the document validated so far is free of violations.
+    pub fn is_valid(&self) -> bool {
+        self.error_count() == 0
+    }
+
+    fn location(&self) -> LocatorImpl {
+        self.locator
+            .borrow()
+            .as_ref()
+            .map(|locator| LocatorImpl::from_locator(&**locator))
+            .unwrap_or_default()
+    }
+
+    fn type_info(&self, id: Option<TypeId>) -> Option<TypeInfoImpl> {
+        id.map(|id| TypeInfoImpl::new(self.components.clone(), id))
+    }
+}
+
+impl<L, CH, EH> Validator<L, CH, EH>
+where
+    L: Locator,
+    CH: ContentHandler<L, AttributesImpl>,
+    EH: ErrorHandler<ParseErrorImpl>,
+{
+    fn report(&self, message: String) -> Result<()> {
+        self.error_count.set(self.error_count.get() + 1);
+        match self.error_handler {
+            Some(ref handler) => {
+                let locator = self.locator.borrow();
+                let error = ParseErrorImpl::of_kind(ErrorKind::Validity, message)
+                    .located(locator.as_ref().map(|locator| &**locator));
+                handler.error(&error)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn forward<F: FnOnce(&CH) -> Result<()>>(&self, event: F) -> Result<()> {
+        match self.handler {
+            Some(ref handler) => event(handler),
+            None => Ok(()),
+        }
+    }
+
+    /// Return the derivative of a content model with respect to a child element, and set
+    /// `term` to what the element matched.
+    fn deriv(
+        &self,
+        particle: &Rc<Particle>,
+        name: &QName,
+        term: &mut Option<Term>,
+    ) -> Rc<Particle> {
+        let components = &*self.components;
+        match **particle {
+            Particle::Empty | Particle::NotAllowed => not_allowed(),
+            Particle::Element(id) => {
+                let element = components.element(id);
+                let matched = if element.name == *name && !element.is_abstract {
+                    Some(id)
+                } else {
+                    element
+                        .substitutes
+                        .iter()
+                        .find(|substitute| components.element(**substitute).name == *name)
+                        .cloned()
+                };
+                match matched {
+                    Some(matched) => {
+                        if term.is_none() {
+                            *term = Some(Term::Element(matched));
+                        }
+                        empty()
+                    }
+                    None => not_allowed(),
+                }
+            }
+            Particle::Any(ref wildcard) => {
+                if wildcard.namespaces.allows(&name.uri) {
+                    if term.is_none() {
+                        *term = Some(Term::Wildcard(wildcard.clone()));
+                    }
+                    empty()
+                } else {
+                    not_allowed()
+                }
+            }
+            Particle::Sequence(ref a, ref b) => {
+                let derived = sequence(self.deriv(a, name, term), b.clone());
+                if components.nullable(a) {
+                    choice(derived, self.deriv(b, name, term))
+                } else {
+                    derived
+                }
+            }
+            Particle::Choice(ref a, ref b) => {
+                choice(self.deriv(a, name, term), self.deriv(b, name, term))
+            }
+            Particle::Interleave(ref a, ref b) => choice(
+                interleave(self.deriv(a, name, term), b.clone()),
+                interleave(a.clone(), self.deriv(b, name, term)),
+            ),
+            Particle::Repeat(ref p, min, max) => sequence(
+                self.deriv(p, name, term),
+                repeat(p.clone(), min.saturating_sub(1), max.map(|max| max - 1)),
+            ),
+        }
+    }
+
+    fn expected(&self, particle: &Particle) -> String {
+        let mut expected = Vec::new();
+        self.components.expected(particle, &mut expected);
+        match expected.len() {
+            0 => String::new(),
+            1 => format!("; expected \"{}\"", expected[0]),
+            _ => format!(
+                "; expected one of {}",
+                expected
+                    .iter()
+                    .map(|name| format!("\"{}\"", name))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    /// Check a value of an ID, IDREF or IDREFS type.
+    fn check_id(&self, state: &mut ValidatorState, type_id: TypeId, value: &str) -> Result<()> {
+        match self.components.id_kind(type_id) {
+            Some(BuiltinType::Id) if !state.ids.insert(value.to_owned()) => {
+                return self.report(format!("duplicate ID \"{}\"", value));
+            }
+            Some(BuiltinType::Idref) | Some(BuiltinType::Idrefs) => {
+                for reference in value.split(' ').filter(|s| !s.is_empty()) {
+                    state.idrefs.push((reference.to_owned(), self.location()));
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Validate a value against a simple type and its value constraint. Returns the value for
+    /// identity constraints, if it is valid.
+    fn check_value(
+        &self,
+        state: &mut ValidatorState,
+        subject: &str,
+        type_id: TypeId,
+        value: &str,
+        fixed: Option<&str>,
+    ) -> Result<Option<KeyValue>> {
+        let normalized = match self.components.validate(type_id, value) {
+            Ok(normalized) => normalized,
+            Err(e) => {
+                self.report(format!("invalid value of {}: {}", subject, e.message()))?;
+                return Ok(None);
+            }
+        };
+        let key = self.components.key_value(type_id, &normalized);
+        if let Some(fixed) = fixed {
+            let matches = self
+                .components
+                .validate(type_id, fixed)
+                .map(|fixed| self.components.key_value(type_id, &fixed) == key)
+                .unwrap_or(false);
+            if !matches {
+                let message = format!("{} must have the fixed value \"{}\"", subject, fixed);
+                self.report(message)?;
+            }
+        }
+        self.check_id(state, type_id, &normalized)?;
+        Ok(Some(key))
+    }
+
+    /// Resolve the `xsi:type` of an element, and check that it may be used in place of the
+    /// declared type.
+    fn xsi_type(
+        &self,
+        state: &ValidatorState,
+        name: &str,
+        value: &str,
+        declared: Option<(ElementId, TypeId)>,
+    ) -> Result<Option<TypeId>> {
+        let value = value.trim();
+        let (prefix, local_name) = match value.find(':') {
+            Some(i) => (&value[..i], &value[i + 1..]),
+            None => ("", value),
+        };
+        let uri = state.namespaces.get_uri(prefix).unwrap_or_default();
+        let type_id = match self
+            .components
+            .global_types
+            .get(&QName::new(uri, local_name))
+        {
+            Some(type_id) => *type_id,
+            None => {
+                let message = format!("type \"{}\" of xsi:type is not defined", value);
+                return self.report(message).map(|_| None);
+            }
+        };
+        if let Some((element, declared)) = declared {
+            let block =
+                self.components.element(element).block | self.components.type_def(declared).block;
+            let allowed = match self.components.derivation(type_id, declared) {
+                Some(methods) => methods & block == 0,
+                None => false,
+            };
+            if !allowed {
+                let message = format!(
+                    "type \"{}\" of xsi:type cannot be used in place of the type of element \
+                     \"{}\"",
+                    value, name
+                );
+                return self.report(message).map(|_| None);
+            }
+        }
+        Ok(Some(type_id))
+    }
+
+    /// Validate the attributes of an element, and return them with the defaulted attributes
+    /// added, along with their types.
+    fn check_attributes<A: Attributes>(
+        &self,
+        state: &mut ValidatorState,
+        name: &str,
+        type_id: TypeId,
+        attributes: &A,
+    ) -> Result<(AttributesImpl, Vec<Option<TypeId>>)> {
+        let mut forwarded = AttributesImpl::from_attributes(attributes);
+        let mut types = Vec::new();
+        let complex = match self.components.type_def(type_id).kind {
+            TypeKind::Complex(ref complex) => Some(complex),
+            TypeKind::Simple(_) => None,
+        };
+        let mut seen = Vec::new();
+        for index in 0..attributes.get_length() as u64 {
+            let uri = attributes.get_uri(index).unwrap_or_default();
+            let q_name = attributes.get_q_name(index).unwrap_or_default();
+            let local_name = match attributes.get_local_name(index) {
+                Some(ref local_name) if !local_name.is_empty() => local_name.clone(),
+                _ => q_name.clone(),
+            };
+            let value = attributes.get_value(index).unwrap_or_default();
+            if uri == NSDECL || q_name == "xmlns" || q_name.starts_with("xmlns:") {
+                types.push(None);
+                continue;
+            }
+            if uri == XSI_NS {
+                let builtin = match local_name.as_str() {
+                    "type" => Some(BuiltinType::QName),
+                    "nil" => Some(BuiltinType::Boolean),
+                    "schemaLocation" | "noNamespaceSchemaLocation" => Some(BuiltinType::AnyUri),
+                    _ => None,
+                };
+                types.push(builtin.map(|builtin| self.components.builtin(builtin)));
+                continue;
+            }
+            let attribute_name = QName::new(&uri, &local_name);
+            let subject = format!("attribute \"{}\"", q_name);
+            let declared = complex.and_then(|complex| {
+                complex
+                    .attributes
+                    .iter()
+                    .find(|attribute| attribute.name == attribute_name)
+            });
+            let (attribute_type, fixed) = match (declared, complex) {
+                (Some(attribute), _) => {
+                    seen.push(attribute.name.clone());
+                    let fixed = attribute
+                        .constraint
+                        .as_ref()
+                        .filter(|constraint| constraint.fixed)
+                        .map(|constraint| constraint.value.as_str());
+                    (Some(attribute.type_id), fixed)
+                }
+                (
+                    None,
+                    Some(&ComplexType {
+                        wildcard: Some(ref wildcard),
+                        ..
+                    }),
+                ) if wildcard.namespaces.allows(&uri) => {
+                    let global = self.components.global_attributes.get(&attribute_name);
+                    match (wildcard.process_contents, global) {
+                        (ProcessContents::Skip, _) | (ProcessContents::Lax, None) => (None, None),
+                        (_, Some(global)) => {
+                            let fixed = global
+                                .constraint
+                                .as_ref()
+                                .filter(|constraint| constraint.fixed)
+                                .map(|constraint| constraint.value.as_str());
+                            (Some(global.type_id), fixed)
+                        }
+                        (ProcessContents::Strict, None) => {
+                            self.report(format!("no declaration for {}", subject))?;
+                            (None, None)
+                        }
+                    }
+                }
+                _ => {
+                    let message = format!("{} not allowed on element \"{}\"", subject, name);
+                    self.report(message)?;
+                    (None, None)
+                }
+            };
+            if let Some(attribute_type) = attribute_type {
+                self.check_value(state, &subject, attribute_type, &value, fixed)?;
+            }
+            types.push(attribute_type);
+        }
+        for attribute in complex.iter().flat_map(|complex| &complex.attributes) {
+            if seen.contains(&attribute.name) {
+                continue;
+            }
+            if attribute.required {
+                let message = format!(
+                    "element \"{}\" missing required attribute \"{}\"",
+                    name, attribute.name
+                );
+                self.report(message)?;
+            } else if let Some(ref constraint) = attribute.constraint {
+                let uri = &attribute.name.uri;
+                let local_name = &attribute.name.local_name;
+                let q_name = match state.namespaces.get_prefix(uri) {
+                    Some(prefix) if !uri.is_empty() => format!("{}:{}", prefix, local_name),
+                    _ => local_name.clone(),
+                };
+                forwarded.add_attribute(uri, local_name, &q_name, "CDATA", &constraint.value);
+                types.push(Some(attribute.type_id));
+                let normalized = self
+                    .components
+                    .validate(attribute.type_id, &constraint.value);
+                if let Ok(normalized) = normalized {
+                    self.check_id(state, attribute.type_id, &normalized)?;
+                }
+            }
+        }
+        Ok((forwarded, types))
+    }
+
+    /// Check the character content of an element so far, when it is followed by a child
+    /// element or, if `end` is set, by the end of the element.
+    fn check_text(&self, frame: &mut Frame, end: bool) -> Result<()> {
+        let message = match self.components.type_def(frame.type_id).kind {
+            _ if frame.nil => {
+                if !end || frame.text.is_empty() && !frame.had_child {
+                    return Ok(());
+                }
+                format!("element \"{}\" is nil, and must be empty", frame.name)
+            }
+            // Child elements of elements with empty content are reported as they start.
+            TypeKind::Complex(ComplexType {
+                content: Content::Empty,
+                ..
+            }) => {
+                if !end || frame.text.is_empty() {
+                    return Ok(());
+                }
+                format!("element \"{}\" must be empty", frame.name)
+            }
+            TypeKind::Complex(ComplexType {
+                content: Content::Elements { mixed: false, .. },
+                ..
+            }) => {
+                let whitespace = frame.text.chars().all(names::is_whitespace);
+                frame.text.clear();
+                if whitespace {
+                    return Ok(());
+                }
+                format!(
+                    "character content not allowed in element \"{}\"",
+                    frame.name
+                )
+            }
+            _ => return Ok(()),
+        };
+        self.report(message)
+    }
+
+    /// Validate the start of an element, and return the frame for it, or `None` if it is to be
+    /// skipped.
+    fn start<A: Attributes>(
+        &self,
+        state: &mut ValidatorState,
+        name: &QName,
+        display: &str,
+        attributes: &A,
+    ) -> Result<Option<(Frame, AttributesImpl)>> {
+        let components = &*self.components;
+        let mut element = None;
+        let mut typed = true;
+        match state.frames.last_mut() {
+            None => match components.global_elements.get(name) {
+                Some(id) => element = Some(*id),
+                None => {
+                    let message = format!("no declaration for element \"{}\"", display);
+                    self.report(message)?;
+                    typed = false;
+                }
+            },
+            Some(parent) => {
+                parent.had_child = true;
+                self.check_text(parent, false)?;
+                if parent.nil {
+                    return Ok(None);
+                }
+                let parent_content = match components.type_def(parent.type_id).kind {
+                    TypeKind::Complex(ComplexType {
+                        content: Content::Elements { .. },
+                        ..
+                    }) => None,
+                    TypeKind::Complex(ComplexType {
+                        content: Content::Empty,
+                        ..
+                    }) => Some("empty"),
+                    _ => Some("simple"),
+                };
+                if let Some(content) = parent_content {
+                    let message = format!(
+                        "element \"{}\" not allowed in element \"{}\", which has {} content",
+                        display, parent.name, content
+                    );
+                    return self.report(message).map(|_| None);
+                }
+                let mut term = None;
+                let derived = self.deriv(&parent.particle, name, &mut term);
+                if let Particle::NotAllowed = *derived {
+                    let message = format!(
+                        "element \"{}\" not allowed here{}",
+                        display,
+                        self.expected(&parent.particle)
+                    );
+                    return self.report(message).map(|_| None);
+                }
+                parent.particle = derived;
+                match term {
+                    Some(Term::Element(id)) => element = Some(id),
+                    Some(Term::Wildcard(wildcard)) => {
+                        let global = components.global_elements.get(name).cloned();
+                        match (wildcard.process_contents, global) {
+                            (ProcessContents::Skip, _) => return Ok(None),
+                            (_, Some(id)) => element = Some(id),
+                            (ProcessContents::Lax, None) => typed = false,
+                            (ProcessContents::Strict, None) => {
+                                let message = format!("no declaration for element \"{}\"", display);
+                                self.report(message)?;
+                                typed = false;
+                            }
+                        }
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        let declared = element.map(|id| (id, components.element(id).type_id));
+        let mut type_id = declared.map_or(ANY_TYPE, |declared| declared.1);
+        if let Some(value) = attributes.get_ns_name_value(XSI_NS, "type") {
+            if let Some(xsi_type) = self.xsi_type(state, display, &value, declared)? {
+                type_id = xsi_type;
+                typed = true;
+            }
+        }
+        if let Some(id) = element {
+            if components.element(id).is_abstract {
+                let message = format!("element \"{}\" is abstract", display);
+                self.report(message)?;
+            }
+        }
+        if components.type_def(type_id).is_abstract {
+            let message = format!(
+                "element \"{}\" has the abstract type \"{}\"",
+                display,
+                components.describe(type_id)
+            );
+            self.report(message)?;
+        }
+        let mut nil = false;
+        if let Some(value) = attributes.get_ns_name_value(XSI_NS, "nil") {
+            let value = value.trim();
+            if value != "true" && value != "1" && value != "false" && value != "0" {
+                self.report(format!("invalid value \"{}\" for xsi:nil", value))?;
+            } else if value == "true" || value == "1" {
+                match element.map(|id| components.element(id)) {
+                    Some(declaration) if !declaration.nillable => {
+                        let message = format!("element \"{}\" is not nillable", display);
+                        self.report(message)?;
+                    }
+                    Some(declaration)
+                        if declaration
+                            .constraint
+                            .as_ref()
+                            .is_some_and(|constraint| constraint.fixed) =>
+                    {
+                        let message = format!(
+                            "element \"{}\" has a fixed value, and cannot be nil",
+                            display
+                        );
+                        self.report(message)?;
+                    }
+                    _ => nil = true,
+                }
+            }
+        }
+        let (forwarded, attribute_types) =
+            self.check_attributes(state, display, type_id, attributes)?;
+        let particle = match components.type_def(type_id).kind {
+            TypeKind::Complex(ComplexType {
+                content: Content::Elements { ref particle, .. },
+                ..
+            }) => particle.clone(),
+            _ => empty(),
+        };
+        let frame = Frame {
+            name: display.to_owned(),
+            element,
+            type_id,
+            typed,
+            particle,
+            nil,
+            text: String::new(),
+            had_child: false,
+            attribute_types,
+            tables: HashMap::new(),
+        };
+        Ok(Some((frame, forwarded)))
+    }
+
+    /// Start the identity constraints of an element, and evaluate the selectors and fields of
+    /// those in scope against it.
+    fn select(
+        &self,
+        state: &mut ValidatorState,
+        element: Option<ElementId>,
+        attributes: &AttributesImpl,
+    ) -> Result<()> {
+        let components = &*self.components;
+        let depth = state.path.len();
+        if let Some(element) = element {
+            for constraint in &components.element(element).constraints {
+                state.scopes.push(Scope {
+                    constraint: *constraint,
+                    depth,
+                    table: Vec::new(),
+                });
+            }
+        }
+        for index in 0..state.scopes.len() {
+            let scope = &state.scopes[index];
+            let constraint = components.constraint(scope.constraint);
+            let relative = &state.path[scope.depth..];
+            if constraint
+                .selector
+                .iter()
+                .any(|path| path.selects(relative))
+            {
+                state.selected.push(Selected {
+                    scope: index,
+                    depth,
+                    fields: vec![None; constraint.fields.len()],
+                });
+            }
+        }
+        let mut values = Vec::new();
+        for (index, selected) in state.selected.iter().enumerate() {
+            let constraint = components.constraint(state.scopes[selected.scope].constraint);
+            let relative = &state.path[selected.depth..];
+            for (field, paths) in constraint.fields.iter().enumerate() {
+                for path in paths.iter().filter(|path| path.selects(relative)) {
+                    let test = match path.attribute {
+                        Some(ref test) => test,
+                        None => {
+                            state.element_fields.push(ElementField {
+                                selected: index,
+                                field,
+                                depth,
+                            });
+                            continue;
+                        }
+                    };
+                    let frame = state.frames.last().expect("open element");
+                    for (position, attribute) in attributes.iter().enumerate() {
+                        if !test.matches(&attribute.uri, &attribute.local_name) {
+                            continue;
+                        }
+                        let type_id = match frame.attribute_types.get(position) {
+                            Some(&Some(type_id)) => type_id,
+                            _ => continue,
+                        };
+                        if let Ok(value) = components.validate(type_id, &attribute.value) {
+                            values.push((index, field, components.key_value(type_id, &value)));
+                        }
+                    }
+                }
+            }
+        }
+        for (selected, field, value) in values {
+            self.set_field(state, selected, field, value)?;
+        }
+        Ok(())
+    }
+
+    fn set_field(
+        &self,
+        state: &mut ValidatorState,
+        selected: usize,
+        field: usize,
+        value: KeyValue,
+    ) -> Result<()> {
+        let scope = &state.scopes[state.selected[selected].scope];
+        let constraint = &self.components.constraint(scope.constraint).name;
+        let slot = &mut state.selected[selected].fields[field];
+        if slot.is_some() {
+            let message = format!(
+                "a field of identity constraint \"{}\" selects more than one value",
+                constraint
+            );
+            return self.report(message);
+        }
+        *slot = Some(value);
+        Ok(())
+    }
+
+    /// Finish the identity constraints of the element ending at the current depth, given the
+    /// value of its content if it has a simple type.
+    fn end_constraints(&self, state: &mut ValidatorState, value: Option<KeyValue>) -> Result<()> {
+        let components = &*self.components;
+        let depth = state.path.len();
+        while state
+            .element_fields
+            .last()
+            .is_some_and(|field| field.depth == depth)
+        {
+            let field = state.element_fields.pop().expect("element field");
+            let scope = state.selected[field.selected].scope;
+            let constraint = &components.constraint(state.scopes[scope].constraint).name;
+            match value {
+                Some(ref value) => {
+                    self.set_field(state, field.selected, field.field, value.clone())?
+                }
+                None => {
+                    let message = format!(
+                        "a field of identity constraint \"{}\" selects an element without a \
+                         simple type",
+                        constraint
+                    );
+                    self.report(message)?;
+                }
+            }
+        }
+        while state
+            .selected
+            .last()
+            .is_some_and(|selected| selected.depth == depth)
+        {
+            let selected = state.selected.pop().expect("selected element");
+            let scope = &mut state.scopes[selected.scope];
+            let constraint = components.constraint(scope.constraint);
+            let key: Option<Vec<KeyValue>> = selected.fields.into_iter().collect();
+            let key = match (key, constraint.kind) {
+                (Some(key), _) => key,
+                (None, ConstraintKind::Key) => {
+                    let message = format!("missing field of key \"{}\"", constraint.name);
+                    self.report(message)?;
+                    continue;
+                }
+                (None, _) => continue,
+            };
+            if let ConstraintKind::Keyref(_) = constraint.kind {
+                scope.table.push(key);
+            } else if scope.table.contains(&key) {
+                let message = format!(
+                    "duplicate value \"{}\" for identity constraint \"{}\"",
+                    describe_key(&key),
+                    constraint.name
+                );
+                self.report(message)?;
+            } else {
+                scope.table.push(key);
+            }
+        }
+        let mut ended = Vec::new();
+        while state
+            .scopes
+            .last()
+            .is_some_and(|scope| scope.depth == depth)
+        {
+            ended.push(state.scopes.pop().expect("scope"));
+        }
+        let frame = state.frames.last_mut().expect("open element");
+        // Keys first, so that keyrefs on the same element can refer to them.
+        ended.sort_by_key(|scope| match components.constraint(scope.constraint).kind {
+            ConstraintKind::Keyref(_) => 1,
+            _ => 0,
+        });
+        for scope in ended {
+            let constraint = components.constraint(scope.constraint);
+            let key = match constraint.kind {
+                ConstraintKind::Keyref(key) => key,
+                _ => {
+                    frame
+                        .tables
+                        .entry(scope.constraint)
+                        .or_default()
+                        .extend(scope.table);
+                    continue;
+                }
+            };
+            let table = frame.tables.get(&key);
+            for reference in scope.table {
+                if !table.is_some_and(|table| table.contains(&reference)) {
+                    let message = format!(
+                        "no value \"{}\" for identity constraint \"{}\", referred to by \"{}\"",
+                        describe_key(&reference),
+                        components.constraint(key).name,
+                        constraint.name
+                    );
+                    self.report(message)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate the end of the current element, and return its default value if it is to be
+    /// forwarded as its content.
+    fn end(&self, state: &mut ValidatorState) -> Result<Option<String>> {
+        let components = &*self.components;
+        let mut frame = state.frames.pop().expect("open element");
+        self.check_text(&mut frame, true)?;
+        let constraint = frame
+            .element
+            .and_then(|id| components.element(id).constraint.clone());
+        let empty = !frame.had_child && frame.text.is_empty();
+        let default = constraint
+            .as_ref()
+            .filter(|_| empty && !frame.nil)
+            .map(|constraint| constraint.value.clone());
+        let text = default.clone().unwrap_or_else(|| frame.text.clone());
+        let fixed = constraint
+            .as_ref()
+            .filter(|constraint| constraint.fixed)
+            .map(|constraint| constraint.value.as_str());
+        let subject = format!("element \"{}\"", frame.name);
+        let mut value = None;
+        match components.type_def(frame.type_id).kind {
+            _ if frame.nil => {}
+            TypeKind::Complex(ComplexType {
+                content: Content::Elements { mixed, .. },
+                ..
+            }) => {
+                if !components.nullable(&frame.particle) {
+                    let message = format!(
+                        "element \"{}\" incomplete{}",
+                        frame.name,
+                        self.expected(&frame.particle)
+                    );
+                    self.report(message)?;
+                }
+                if let (Some(fixed), true) = (fixed, mixed) {
+                    if frame.had_child || text != fixed {
+                        let message =
+                            format!("{} must have the fixed value \"{}\"", subject, fixed);
+                        self.report(message)?;
+                    }
+                }
+            }
+            TypeKind::Complex(ComplexType {
+                content: Content::Empty,
+                ..
+            }) => {}
+            _ => {
+                if !frame.had_child {
+                    value = self.check_value(state, &subject, frame.type_id, &text, fixed)?;
+                }
+            }
+        }
+        state.frames.push(frame);
+        self.end_constraints(state, value)?;
+        let frame = state.frames.pop().expect("open element");
+        if let Some(parent) = state.frames.last_mut() {
+            for (constraint, table) in frame.tables {
+                parent.tables.entry(constraint).or_default().extend(table);
+            }
+        }
+        Ok(default)
+    }
+}
+
+/// Describe a key sequence for messages.
+fn describe_key(key: &[KeyValue]) -> String {
+    key.iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl<L, A, CH, EH> ContentHandler<L, A> for Validator<L, CH, EH>
+where
+    L: Locator,
+    A: Attributes,
+    CH: ContentHandler<L, AttributesImpl>,
+    EH: ErrorHandler<ParseErrorImpl>,
+{
+    fn characters(&self, content: &str) -> Result<()> {
+        {
+            let mut state = self.state.borrow_mut();
+            if state.skip_depth == 0 {
+                if let Some(frame) = state.frames.last_mut() {
+                    frame.text.push_str(content);
+                }
+            }
+        }
+        self.forward(|handler| handler.characters(content))
+    }
+    fn end_document(&self) -> Result<()> {
+        let idrefs = {
+            let mut state = self.state.borrow_mut();
+            let state = &mut *state;
+            let ids = &state.ids;
+            state
+                .idrefs
+                .drain(..)
+                .filter(|idref| !ids.contains(&idref.0))
+                .collect::<Vec<_>>()
+        };
+        for (idref, location) in idrefs {
+            let error = ParseErrorImpl::of_kind(
+                ErrorKind::Validity,
+                format!("no element has the ID \"{}\"", idref),
+            )
+            .located(Some(&location));
+            self.error_count.set(self.error_count.get() + 1);
+            if let Some(ref handler) = self.error_handler {
+                handler.error(&error)?;
+            }
+        }
+        self.forward(|handler| handler.end_document())
+    }
+    fn end_element(&self, uri: &str, local_name: &str, q_name: &str) -> Result<()> {
+        let skipped = self.state.borrow().skip_depth > 0;
+        if skipped {
+            self.forward(|handler| handler.end_element(uri, local_name, q_name))?;
+            self.state.borrow_mut().skip_depth -= 1;
+            return Ok(());
+        }
+        let default = {
+            let mut state = self.state.borrow_mut();
+            let state = &mut *state;
+            if state.frames.is_empty() {
+                None
+            } else {
+                self.end(state)?
+            }
+        };
+        if let Some(default) = default {
+            self.forward(|handler| handler.characters(&default))?;
+        }
+        self.forward(|handler| handler.end_element(uri, local_name, q_name))?;
+        let mut state = self.state.borrow_mut();
+        state.path.pop();
+        state.namespaces.pop_context();
+        Ok(())
+    }
+    fn end_prefix_mapping(&self, prefix: &str) -> Result<()> {
+        self.forward(|handler| handler.end_prefix_mapping(prefix))
+    }
+    fn ignorable_whitespace(&self, content: &str) -> Result<()> {
+        self.forward(|handler| handler.ignorable_whitespace(content))
+    }
+    fn processing_instruction(&self, target: &str, data: &str) -> Result<()> {
+        self.forward(|handler| handler.processing_instruction(target, data))
+    }
+    fn set_document_locator(&self, locator: Rc<L>) {
+        *self.locator.borrow_mut() = Some(locator.clone());
+        if let Some(ref handler) = self.handler {
+            handler.set_document_locator(locator);
+        }
+    }
+    fn skipped_entity(&self, name: &str) -> Result<()> {
+        self.forward(|handler| handler.skipped_entity(name))
+    }
+    fn start_document(&self) -> Result<()> {
+        *self.state.borrow_mut() = ValidatorState::default();
+        self.error_count.set(0);
+        self.forward(|handler| handler.start_document())
+    }
+    fn start_element(
+        &self,
+        uri: &str,
+        local_name: &str,
+        q_name: &str,
+        attributes: A,
+    ) -> Result<()> {
+        let forwarded = {
+            let mut state = self.state.borrow_mut();
+            let state = &mut *state;
+            if state.skip_depth > 0 {
+                state.skip_depth += 1;
+                None
+            } else {
+                state.namespaces.push_context();
+                for (prefix, uri) in std::mem::take(&mut state.mappings) {
+                    state.namespaces.declare_prefix(&prefix, &uri);
+                }
+                for index in 0..attributes.get_length() as u64 {
+                    let name = attributes.get_q_name(index).unwrap_or_default();
+                    let prefix = match name.strip_prefix("xmlns") {
+                        Some("") => "",
+                        Some(prefix) if prefix.starts_with(':') => &prefix[1..],
+                        _ => continue,
+                    };
+                    let value = attributes.get_value(index).unwrap_or_default();
+                    state.namespaces.declare_prefix(prefix, &value);
+                }
+                let local_name = if local_name.is_empty() {
+                    q_name
+                } else {
+                    local_name
+                };
+                let display = if q_name.is_empty() {
+                    local_name
+                } else {
+                    q_name
+                };
+                let name = QName::new(uri, local_name);
+                match self.start(state, &name, display, &attributes)? {
+                    Some((frame, forwarded)) => {
+                        let element = frame.element;
+                        state.path.push(name);
+                        state.frames.push(frame);
+                        self.select(state, element, &forwarded)?;
+                        Some(forwarded)
+                    }
+                    None => {
+                        state.namespaces.pop_context();
+                        state.skip_depth = 1;
+                        None
+                    }
+                }
+            }
+        };
+        let attributes = forwarded.unwrap_or_else(|| AttributesImpl::from_attributes(&attributes));
+        self.forward(|handler| handler.start_element(uri, local_name, q_name, attributes))
+    }
+    fn start_prefix_mapping(&self, prefix: &str, uri: &str) -> Result<()> {
+        self.state
+            .borrow_mut()
+            .mappings
+            .push((prefix.to_owned(), uri.to_owned()));
+        self.forward(|handler| handler.start_prefix_mapping(prefix, uri))
+    }
+}
+
+impl<L, A, CH, EH> ValidatorHandler<L, A, CH, EH, ParseErrorImpl> for Validator<L, CH, EH>
+where
+    L: Locator,
+    A: Attributes,
+    CH: ContentHandler<L, AttributesImpl>,
+    EH: ErrorHandler<ParseErrorImpl>,
+{
+    type Type = TypeInfoImpl;
+    fn get_content_handler(&self) -> Option<&CH> {
+        self.handler.as_ref()
+    }
+    fn set_content_handler(&mut self, handler: CH) {
+        self.handler = Some(handler);
+    }
+    fn get_error_handler(&self) -> Option<&EH> {
+        self.error_handler.as_ref()
+    }
+    fn set_error_handler(&mut self, handler: EH) {
+        self.error_handler = Some(handler);
+    }
+    fn get_element_type_info(&self) -> Option<TypeInfoImpl> {
+        let state = self.state.borrow();
+        if state.skip_depth > 0 {
+            return None;
+        }
+        let frame = state.frames.last()?;
+        self.type_info(Some(frame.type_id).filter(|_| frame.typed))
+    }
+    fn get_attribute_type_info(&self, index: u64) -> Option<TypeInfoImpl> {
+        let state = self.state.borrow();
+        if state.skip_depth > 0 {
+            return None;
+        }
+        let frame = state.frames.last()?;
+        self.type_info(frame.attribute_types.get(index as usize).cloned().flatten())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event::markup_events;
+    use event::Event;
+    use event::EventRecorder;
+    use resolver::SandboxResolver;
+    use std::io::Read;
+    use xsd::loader::Loader;
+    use xsd::Schema;
+    use xsd::SchemaLoader;
+    use xsd::TypeInfo;
+    use xsd::DERIVATION_EXTENSION;
+    use xsd::DERIVATION_RESTRICTION;
+    use xsd::XSD_NS;
+
+    #[derive(Clone, Default)]
+    struct Errors(Rc<RefCell<Vec<String>>>);
+
+    impl ErrorHandler<ParseErrorImpl> for Errors {
+        fn error(&self, e: &ParseErrorImpl) -> Result<()> {
+            self.0.borrow_mut().push(e.to_string());
+            Ok(())
+        }
+    }
+
+    type Checker = Validator<LocatorImpl, EventRecorder<LocatorImpl>, Errors>;
+    type Handler = dyn ValidatorHandler<
+        LocatorImpl,
+        AttributesImpl,
+        EventRecorder<LocatorImpl>,
+        Errors,
+        ParseErrorImpl,
+        Type = TypeInfoImpl,
+    >;
+
+    fn parse(
+        input: &mut dyn Read,
+        _system_id: &str,
+        handler: &dyn ContentHandler<LocatorImpl, AttributesImpl>,
+    ) -> Result<()> {
+        let mut markup = String::new();
+        input.read_to_string(&mut markup).unwrap();
+        for event in markup_events(&markup) {
+            event.dispatch(handler)?;
+        }
+        Ok(())
+    }
+
+    /// Compile a schema document without a target Namespace, with the given top-level
+    /// components.
+    fn schema(components: &str) -> SchemaSet {
+        let mut resolver = SandboxResolver::in_memory();
+        let document = format!(
+            "<xs:schema xmlns:xs=\"{}\">{}</xs:schema>",
+            XSD_NS, components
+        );
+        resolver.insert("http://example.com/schema.xsd", document);
+        let loader = Loader::<LocatorImpl, _, _, Errors>::new(parse);
+        loader.set_entity_resolver(resolver);
+        loader
+            .new_schema(&["http://example.com/schema.xsd"])
+            .unwrap()
+    }
+
+    fn validator(schema: &SchemaSet) -> Checker {
+        let mut validator: Checker = schema.new_validator_handler();
+        let handler: &mut Handler = &mut validator;
+        handler.set_content_handler(EventRecorder::new());
+        handler.set_error_handler(Errors::default());
+        validator
+    }
+
+    fn errors(validator: &Checker) -> Vec<String> {
+        let handler: &Handler = validator;
+        handler.get_error_handler().unwrap().0.take()
+    }
+
+    fn events(validator: &Checker) -> Vec<Event> {
+        let handler: &Handler = validator;
+        handler.get_content_handler().unwrap().take_events()
+    }
+
+    /// Validate a document, and return the violations reported.
+    fn validate(schema: &SchemaSet, markup: &str) -> Vec<String> {
+        let validator = validator(schema);
+        for event in markup_events(markup) {
+            event.dispatch(&validator).unwrap();
+        }
+        errors(&validator)
+    }
+
+    #[test]
+    fn content_models() {
+        let schema = schema(concat!(
+            "<xs:element name='doc'><xs:complexType><xs:sequence>",
+            "<xs:element name='title' type='xs:string'/>",
+            "<xs:choice minOccurs='0' maxOccurs='unbounded'>",
+            "<xs:element name='p' type='xs:string'/>",
+            "<xs:element name='list' type='listType'/>",
+            "</xs:choice></xs:sequence></xs:complexType></xs:element>",
+            "<xs:complexType name='listType'><xs:sequence>",
+            "<xs:element name='item' type='xs:string' maxOccurs='3'/>",
+            "</xs:sequence></xs:complexType>",
+        ));
+        let valid = concat!(
+            "<doc>\n  <title>T</title><p>a</p>\n",
+            "  <list><item>1</item><item/></list><p>b</p>\n</doc>",
+        );
+        assert_eq!(validate(&schema, valid), Vec::<String>::new());
+        assert_eq!(
+            validate(&schema, "<doc><p>a</p></doc>"),
+            [
+                "element \"p\" not allowed here; expected \"title\"",
+                "element \"doc\" incomplete; expected \"title\"",
+            ]
+        );
+        assert_eq!(
+            validate(
+                &schema,
+                "<doc><title/><list><item/><item/><item/><item/></list></doc>"
+            ),
+            ["element \"item\" not allowed here"]
+        );
+        assert_eq!(
+            validate(&schema, "<doc><title/><list/>text</doc>"),
+            [
+                "element \"list\" incomplete; expected \"item\"",
+                "character content not allowed in element \"doc\"",
+            ]
+        );
+        assert_eq!(
+            validate(&schema, "<doc><title/><p><b/></p><list/></doc>"),
+            [
+                "element \"b\" not allowed in element \"p\", which has simple content",
+                "element \"list\" incomplete; expected \"item\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn all_groups() {
+        let schema = schema(concat!(
+            "<xs:element name='point'><xs:complexType><xs:all>",
+            "<xs:element name='x' type='xs:int'/>",
+            "<xs:element name='y' type='xs:int' minOccurs='0'/>",
+            "</xs:all></xs:complexType></xs:element>",
+        ));
+        assert!(validate(&schema, "<point><y>1</y><x>2</x></point>").is_empty());
+        assert!(validate(&schema, "<point><x>2</x></point>").is_empty());
+        assert_eq!(
+            validate(&schema, "<point><x>1</x><x>2</x></point>"),
+            ["element \"x\" not allowed here; expected \"y\""]
+        );
+        assert_eq!(
+            validate(&schema, "<point><y>1</y></point>"),
+            ["element \"point\" incomplete; expected \"x\""]
+        );
+        let errors = validate(&schema, "<point><x>a</x></point>");
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].starts_with("invalid value of element \"x\": "),
+            "{}",
+            errors[0]
+        );
+    }
+
+    #[test]
+    fn simple_types() {
+        let schema = schema(concat!(
+            "<xs:simpleType name='size'><xs:restriction base='xs:token'>",
+            "<xs:enumeration value='small'/><xs:enumeration value='large'/>",
+            "</xs:restriction></xs:simpleType>",
+            "<xs:simpleType name='sizes'><xs:list itemType='size'/></xs:simpleType>",
+            "<xs:simpleType name='code'><xs:restriction base='xs:string'>",
+            "<xs:pattern value='[A-Z]{2}'/><xs:pattern value='[0-9]{3}'/>",
+            "</xs:restriction></xs:simpleType>",
+            "<xs:simpleType name='shortCode'><xs:restriction base='code'>",
+            "<xs:maxLength value='2'/></xs:restriction></xs:simpleType>",
+            "<xs:simpleType name='sizeOrNumber'>",
+            "<xs:union memberTypes='size xs:positiveInteger'/></xs:simpleType>",
+            "<xs:element name='v'><xs:complexType>",
+            "<xs:attribute name='size' type='size'/>",
+            "<xs:attribute name='sizes' type='sizes'/>",
+            "<xs:attribute name='code' type='shortCode'/>",
+            "<xs:attribute name='any' type='sizeOrNumber'/>",
+            "</xs:complexType></xs:element>",
+        ));
+        let valid = "<v size=' large ' sizes='small  large' code='AB' any='12'/>";
+        assert!(validate(&schema, valid).is_empty());
+        assert!(validate(&schema, "<v any='small'/>").is_empty());
+        let errors = validate(
+            &schema,
+            "<v size='medium' sizes='small huge' code='123' any='0'/>",
+        );
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        assert!(errors[0].starts_with("invalid value of attribute \"size\": 'medium'"));
+        assert!(errors[0].contains("enumeration"));
+        assert!(errors[1].starts_with("invalid value of attribute \"sizes\": 'huge'"));
+        assert!(errors[2].starts_with("invalid value of attribute \"code\": '123'"));
+        assert!(errors[2].contains("maxLength"));
+        assert_eq!(
+            errors[3],
+            concat!(
+                "invalid value of attribute \"any\": ",
+                "'0' is not a valid value of any member type of sizeOrNumber"
+            )
+        );
+    }
+
+    #[test]
+    fn substitution_groups() {
+        let schema = schema(concat!(
+            "<xs:complexType name='shapeType'>",
+            "<xs:attribute name='color' type='xs:string' default='black'/>",
+            "</xs:complexType>",
+            "<xs:element name='shape' type='shapeType' abstract='true'/>",
+            "<xs:element name='circle' substitutionGroup='shape'><xs:complexType>",
+            "<xs:complexContent><xs:extension base='shapeType'>",
+            "<xs:attribute name='r' type='xs:decimal' use='required'/>",
+            "</xs:extension></xs:complexContent></xs:complexType></xs:element>",
+            "<xs:element name='square' type='shapeType' substitutionGroup='shape'/>",
+            "<xs:element name='plain' type='shapeType' block='substitution'/>",
+            "<xs:element name='blocked' type='shapeType' substitutionGroup='plain'/>",
+            "<xs:element name='drawing'><xs:complexType><xs:sequence>",
+            "<xs:element ref='shape' maxOccurs='unbounded'/>",
+            "<xs:element ref='plain' minOccurs='0'/>",
+            "</xs:sequence></xs:complexType></xs:element>",
+        ));
+        let validator = validator(&schema);
+        let markup = "<drawing><circle r='1'/><square color='red'/><plain/></drawing>";
+        for event in markup_events(markup) {
+            event.dispatch(&validator).unwrap();
+        }
+        assert!(errors(&validator).is_empty());
+        let circle = events(&validator)
+            .into_iter()
+            .find_map(|event| match event {
+                Event::StartElement {
+                    ref local_name,
+                    ref attributes,
+                    ..
+                } if local_name == "circle" => Some(attributes.clone()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(circle.get_q_name_value("r").as_deref(), Some("1"));
+        assert_eq!(circle.get_q_name_value("color").as_deref(), Some("black"));
+
+        assert_eq!(
+            validate(&schema, "<drawing><shape/></drawing>"),
+            [
+                "element \"shape\" not allowed here; expected one of \"circle\", \"square\"",
+                "element \"drawing\" incomplete; expected one of \"circle\", \"square\"",
+            ]
+        );
+        assert_eq!(
+            validate(&schema, "<drawing><circle r='1'/><blocked/></drawing>"),
+            [concat!(
+                "element \"blocked\" not allowed here; ",
+                "expected one of \"circle\", \"square\", \"plain\""
+            )]
+        );
+        assert_eq!(
+            validate(&schema, "<drawing><circle/></drawing>"),
+            ["element \"circle\" missing required attribute \"r\""]
+        );
+        assert_eq!(
+            validate(&schema, "<shape/>"),
+            ["element \"shape\" is abstract"]
+        );
+    }
+
+    const XSI: &str = "xmlns:xsi='http://www.w3.org/2001/XMLSchema-instance'";
+
+    fn derivations() -> SchemaSet {
+        schema(concat!(
+            "<xs:complexType name='base'><xs:sequence>",
+            "<xs:element name='a' type='xs:string'/>",
+            "</xs:sequence></xs:complexType>",
+            "<xs:complexType name='derived'><xs:complexContent>",
+            "<xs:extension base='base'><xs:sequence>",
+            "<xs:element name='b' type='xs:string'/>",
+            "</xs:sequence></xs:extension></xs:complexContent></xs:complexType>",
+            "<xs:complexType name='restricted'><xs:complexContent>",
+            "<xs:restriction base='derived'><xs:sequence>",
+            "<xs:element name='a' type='xs:string'/><xs:element name='b' type='xs:string'/>",
+            "</xs:sequence></xs:restriction></xs:complexContent></xs:complexType>",
+            "<xs:element name='root'><xs:complexType><xs:sequence>",
+            "<xs:element name='item' type='base' maxOccurs='unbounded' nillable='true'/>",
+            "<xs:element name='n' type='xs:int' minOccurs='0'/>",
+            "</xs:sequence></xs:complexType></xs:element>",
+        ))
+    }
+
+    #[test]
+    fn xsi_type_and_nil() {
+        let schema = derivations();
+        let valid = format!(
+            concat!(
+                "<root {}><item><a/></item><item xsi:type='derived'><a/><b/></item>",
+                "<item xsi:type='restricted'><a/><b/></item><item xsi:nil='true'/></root>"
+            ),
+            XSI
+        );
+        assert!(validate(&schema, &valid).is_empty());
+        let invalid = format!(
+            concat!(
+                "<root {} xmlns:xs='http://www.w3.org/2001/XMLSchema'>",
+                "<item xsi:type='derived'><a/></item>",
+                "<item xsi:type='unknown'><a/></item>",
+                "<item xsi:type='xs:string'><a/></item>",
+                "<item xsi:nil='true'><a/></item>",
+                "<n xsi:nil='1'>1</n></root>"
+            ),
+            XSI
+        );
+        assert_eq!(
+            validate(&schema, &invalid),
+            [
+                "element \"item\" incomplete; expected \"b\"",
+                "type \"unknown\" of xsi:type is not defined",
+                concat!(
+                    "type \"xs:string\" of xsi:type cannot be used in place of the type of ",
+                    "element \"item\""
+                ),
+                "element \"item\" is nil, and must be empty",
+                "element \"n\" is not nillable",
+            ]
+        );
+    }
+
+    #[test]
+    fn type_info() {
+        let schema = derivations();
+        let validator = validator(&schema);
+        let handler: &Handler = &validator;
+        let markup = format!(
+            "<root {}><item xsi:type='derived'><a>x</a><b/></item></root>",
+            XSI
+        );
+        let mut events = markup_events(&markup).into_iter();
+        let mut next_element = || loop {
+            let event = events.next().unwrap();
+            let is_start = matches!(event, Event::StartElement { .. });
+            event.dispatch(&validator).unwrap();
+            if is_start {
+                break;
+            }
+        };
+
+        next_element();
+        let root = handler.get_element_type_info().unwrap();
+        assert_eq!(root.get_type_name(), None);
+        assert_eq!(root.get_type_namespace(), None);
+        assert!(root.is_derived_from(XSD_NS, "anyType", DERIVATION_RESTRICTION));
+
+        next_element();
+        let item = handler.get_element_type_info().unwrap();
+        assert_eq!(item.get_type_name().as_deref(), Some("derived"));
+        assert!(item.is_derived_from("", "base", DERIVATION_EXTENSION));
+        assert!(item.is_derived_from("", "base", 0));
+        assert!(!item.is_derived_from("", "base", DERIVATION_RESTRICTION));
+        assert!(!item.is_derived_from("", "derived", 0));
+        let xsi_type = handler.get_attribute_type_info(0);
+        assert_eq!(
+            xsi_type.and_then(|info| info.get_type_name()).as_deref(),
+            Some("QName")
+        );
+
+        next_element();
+        let a = handler.get_element_type_info().unwrap();
+        assert!(a.is_simple());
+        assert_eq!(a.get_type_name().as_deref(), Some("string"));
+        assert_eq!(a.get_type_namespace().as_deref(), Some(XSD_NS));
+        assert!(handler.get_attribute_type_info(0).is_none());
+        assert!(errors(&validator).is_empty());
+
+        let undeclared = validator_for_undeclared(&schema);
+        assert!(undeclared.is_none());
+    }
+
+    /// Return the type assigned to an undeclared root element.
+    fn validator_for_undeclared(schema: &SchemaSet) -> Option<TypeInfoImpl> {
+        let validator = validator(schema);
+        let mut events = markup_events("<other/>").into_iter();
+        events.next().unwrap().dispatch(&validator).unwrap();
+        events.next().unwrap().dispatch(&validator).unwrap();
+        assert_eq!(errors(&validator), ["no declaration for element \"other\""]);
+        let handler: &Handler = &validator;
+        handler.get_element_type_info()
+    }
+
+    #[test]
+    fn wildcards() {
+        let schema = schema(concat!(
+            "<xs:element name='known' type='xs:int'/>",
+            "<xs:attribute name='lang' type='xs:language'/>",
+            "<xs:element name='box'><xs:complexType><xs:sequence>",
+            "<xs:any namespace='##other' processContents='skip' minOccurs='0'/>",
+            "<xs:any namespace='##local' processContents='lax' minOccurs='0' ",
+            "maxOccurs='unbounded'/>",
+            "</xs:sequence><xs:anyAttribute namespace='##local' processContents='strict'/>",
+            "</xs:complexType></xs:element>",
+        ));
+        let valid = concat!(
+            "<box lang='en'><x:foo xmlns:x='urn:x'><anything/></x:foo>",
+            "<known>1</known><unknown><known>2</known></unknown></box>",
+        );
+        assert!(validate(&schema, valid).is_empty());
+        let errors = validate(
+            &schema,
+            concat!(
+                "<box lang='e n' other='1'><x:foo xmlns:x='urn:x'/><y:bar xmlns:y='urn:y'/>",
+                "<unknown><known>two</known></unknown></box>"
+            ),
+        );
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        assert!(errors[0].starts_with("invalid value of attribute \"lang\": 'e n'"));
+        assert_eq!(errors[1], "no declaration for attribute \"other\"");
+        assert_eq!(
+            errors[2],
+            "element \"y:bar\" not allowed here; expected \"any element in no Namespace\""
+        );
+        assert!(errors[3].starts_with("invalid value of element \"known\": 'two'"));
+    }
+
+    #[test]
+    fn default_and_fixed_values() {
+        let schema = schema(concat!(
+            "<xs:element name='m'><xs:complexType><xs:sequence>",
+            "<xs:element name='e' minOccurs='0'><xs:complexType><xs:simpleContent>",
+            "<xs:extension base='xs:decimal'>",
+            "<xs:attribute name='unit' type='xs:token' fixed='cm'/>",
+            "<xs:attribute name='scale' type='xs:int' default='1'/>",
+            "</xs:extension></xs:simpleContent></xs:complexType></xs:element>",
+            "<xs:element name='d' type='xs:int' default='7' minOccurs='0'/>",
+            "<xs:element name='f' type='xs:decimal' fixed='1.0' minOccurs='0'/>",
+            "</xs:sequence></xs:complexType></xs:element>",
+        ));
+        let validator = validator(&schema);
+        for event in markup_events("<m><e unit=' cm '>2</e><d/><f>1</f></m>") {
+            event.dispatch(&validator).unwrap();
+        }
+        assert!(errors(&validator).is_empty());
+        let events = events(&validator);
+        let e = events
+            .iter()
+            .find_map(|event| match *event {
+                Event::StartElement {
+                    ref local_name,
+                    ref attributes,
+                    ..
+                } if local_name == "e" => Some(attributes.clone()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(e.get_length(), 2);
+        assert_eq!(e.get_q_name_value("unit").as_deref(), Some(" cm "));
+        assert_eq!(e.get_q_name_value("scale").as_deref(), Some("1"));
+        let d = events
+            .iter()
+            .position(|event| match *event {
+                Event::StartElement { ref local_name, .. } => local_name == "d",
+                _ => false,
+            })
+            .unwrap();
+        assert_eq!(events[d + 1], Event::Characters("7".to_owned()));
+
+        let errors = validate(&schema, "<m><e unit='mm'>x</e><f>2</f></m>");
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert_eq!(
+            errors[0],
+            "attribute \"unit\" must have the fixed value \"cm\""
+        );
+        assert!(errors[1].starts_with("invalid value of element \"e\": 'x'"));
+        assert_eq!(errors[2], "element \"f\" must have the fixed value \"1.0\"");
+    }
+
+    #[test]
+    fn mixed_and_empty_content() {
+        let schema = schema(concat!(
+            "<xs:element name='para'><xs:complexType mixed='true'><xs:sequence>",
+            "<xs:element name='em' type='xs:string' minOccurs='0' maxOccurs='unbounded'/>",
+            "</xs:sequence></xs:complexType></xs:element>",
+            "<xs:element name='br'><xs:complexType/></xs:element>",
+        ));
+        assert!(validate(&schema, "<para>a <em>b</em> c</para>").is_empty());
+        assert!(validate(&schema, "<br/>").is_empty());
+        assert_eq!(
+            validate(&schema, "<br> </br>"),
+            ["element \"br\" must be empty"]
+        );
+        assert_eq!(
+            validate(&schema, "<br><em/></br>"),
+            ["element \"em\" not allowed in element \"br\", which has empty content"]
+        );
+        assert_eq!(
+            validate(&schema, "<para><br/></para>"),
+            ["element \"br\" not allowed here; expected \"em\""]
+        );
+    }
+
+    #[test]
+    fn identity_constraints() {
+        let schema = schema(concat!(
+            "<xs:element name='library'><xs:complexType><xs:sequence>",
+            "<xs:element name='book' maxOccurs='unbounded'><xs:complexType>",
+            "<xs:attribute name='isbn' type='xs:string'/>",
+            "<xs:attribute name='n' type='xs:int'/>",
+            "</xs:complexType></xs:element>",
+            "<xs:element name='loan' minOccurs='0' maxOccurs='unbounded'><xs:complexType>",
+            "<xs:sequence><xs:element name='ref' type='xs:string'/></xs:sequence>",
+            "</xs:complexType></xs:element>",
+            "</xs:sequence></xs:complexType>",
+            "<xs:key name='isbn'><xs:selector xpath='book'/>",
+            "<xs:field xpath='@isbn'/></xs:key>",
+            "<xs:unique name='number'><xs:selector xpath='./book'/>",
+            "<xs:field xpath='@n'/></xs:unique>",
+            "<xs:keyref name='loanRef' refer='isbn'><xs:selector xpath='.//loan'/>",
+            "<xs:field xpath='ref'/></xs:keyref>",
+            "</xs:element>",
+        ));
+        let valid = concat!(
+            "<library><book isbn='a' n='1'/><book isbn='b'/><book isbn='c' n='2'/>",
+            "<loan><ref>b</ref></loan></library>",
+        );
+        assert!(validate(&schema, valid).is_empty());
+        let invalid = concat!(
+            "<library><book isbn='a' n='1'/><book isbn='a' n='01'/><book n='3'/>",
+            "<loan><ref>c</ref></loan></library>",
+        );
+        assert_eq!(
+            validate(&schema, invalid),
+            [
+                "duplicate value \"1\" for identity constraint \"number\"",
+                "duplicate value \"a\" for identity constraint \"isbn\"",
+                "missing field of key \"isbn\"",
+                "no value \"c\" for identity constraint \"isbn\", referred to by \"loanRef\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn ids_and_references() {
+        let schema = schema(concat!(
+            "<xs:element name='nodes'><xs:complexType><xs:sequence>",
+            "<xs:element name='node' maxOccurs='unbounded'><xs:complexType>",
+            "<xs:attribute name='id' type='xs:ID'/>",
+            "<xs:attribute name='next' type='xs:IDREF'/>",
+            "<xs:attribute name='all' type='xs:IDREFS'/>",
+            "</xs:complexType></xs:element>",
+            "</xs:sequence></xs:complexType></xs:element>",
+        ));
+        let markup = "<nodes><node id='a' next='b'/><node id='b' all='a c'/><node id='a'/></nodes>";
+        assert_eq!(
+            validate(&schema, markup),
+            ["duplicate ID \"a\"", "no element has the ID \"c\""]
+        );
+    }
+}